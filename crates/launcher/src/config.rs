@@ -1,3 +1,4 @@
+use crate::game_options::GameProfile;
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
 use std::fs;
@@ -7,6 +8,18 @@ use std::path::PathBuf;
 pub struct Config {
     pub server: ServerConfig,
     pub game_path: String,
+
+    /// Saved game option profiles
+    #[serde(default = "default_profiles")]
+    pub profiles: Vec<GameProfile>,
+
+    /// Name of the profile applied on launch
+    #[serde(default)]
+    pub active_profile: String,
+
+    /// URL of the server news feed (JSON)
+    #[serde(default)]
+    pub news_url: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -23,10 +36,17 @@ impl Default for Config {
                 port: 7101,
             },
             game_path: String::new(),
+            profiles: default_profiles(),
+            active_profile: String::from("Default"),
+            news_url: String::new(),
         }
     }
 }
 
+fn default_profiles() -> Vec<GameProfile> {
+    vec![GameProfile::default()]
+}
+
 impl Config {
     /// Get the config file path
     pub fn config_path() -> Result<PathBuf> {
@@ -57,6 +77,36 @@ impl Config {
         Ok(config)
     }
 
+    /// Get the active profile, falling back to the first one
+    pub fn active_profile(&self) -> GameProfile {
+        self.profiles
+            .iter()
+            .find(|p| p.name == self.active_profile)
+            .or_else(|| self.profiles.first())
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Insert or replace a profile (matched by name) and make it active
+    pub fn upsert_profile(&mut self, profile: GameProfile) {
+        self.active_profile = profile.name.clone();
+        match self.profiles.iter_mut().find(|p| p.name == profile.name) {
+            Some(existing) => *existing = profile,
+            None => self.profiles.push(profile),
+        }
+    }
+
+    /// Remove a profile by name, keeping at least one profile around
+    pub fn remove_profile(&mut self, name: &str) {
+        self.profiles.retain(|p| p.name != name);
+        if self.profiles.is_empty() {
+            self.profiles = default_profiles();
+        }
+        if self.active_profile == name {
+            self.active_profile = self.profiles[0].name.clone();
+        }
+    }
+
     /// Save config to file
     pub fn save(&self) -> Result<()> {
         let path = Self::config_path()?;
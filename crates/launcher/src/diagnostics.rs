@@ -0,0 +1,173 @@
+//! Diagnostics bundle for bug reports
+//!
+//! Collects everything we usually ask for when someone reports that the
+//! client won't connect: launcher log, config, the state of the game
+//! executable (hash and whether the patcher's backup exists), and the result
+//! of a TCP connection test against the configured server. Everything is
+//! written into a single zip next to the launcher config.
+
+use crate::config::Config;
+use crate::logging;
+use crate::wine;
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+use std::fmt::Write as _;
+use std::fs::{self, File};
+use std::io::Write;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use zip::write::SimpleFileOptions;
+
+/// Timeout for the server connectivity test
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// State of the game executable on disk
+#[derive(Debug, Clone)]
+pub struct PatchStatus {
+    /// SHA-256 of Rag2.exe, if it could be read
+    pub exe_sha256: Option<String>,
+
+    /// Whether ro2-patcher's `Rag2.exe.bak` backup exists
+    pub backup_present: bool,
+}
+
+impl PatchStatus {
+    /// Inspect the executable at `game_path`
+    pub fn inspect(game_path: &Path) -> Self {
+        let exe_sha256 = fs::read(game_path).ok().map(|data| {
+            let mut hasher = Sha256::new();
+            hasher.update(&data);
+            format!("{:x}", hasher.finalize())
+        });
+
+        let mut backup = game_path.to_path_buf();
+        backup.set_extension("exe.bak");
+
+        Self {
+            exe_sha256,
+            backup_present: backup.exists(),
+        }
+    }
+}
+
+/// Try a TCP connection to the server and describe the outcome
+pub fn connectivity_test(ip: &str, port: u16) -> String {
+    let addr = match (ip, port).to_socket_addrs().map(|mut a| a.next()) {
+        Ok(Some(addr)) => addr,
+        Ok(None) => return format!("FAILED: {} did not resolve", ip),
+        Err(e) => return format!("FAILED: cannot resolve {}: {}", ip, e),
+    };
+
+    let start = Instant::now();
+    match TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT) {
+        Ok(_) => format!(
+            "OK: connected to {} in {} ms",
+            addr,
+            start.elapsed().as_millis()
+        ),
+        Err(e) => format!("FAILED: {}: {}", addr, e),
+    }
+}
+
+/// Build the plain-text summary included in the bundle
+pub fn report(config: &Config) -> String {
+    let game_path = PathBuf::from(&config.game_path);
+    let patch = PatchStatus::inspect(&game_path);
+
+    let mut out = String::new();
+    let _ = writeln!(out, "Ragnoria Launcher v{}", env!("CARGO_PKG_VERSION"));
+    let _ = writeln!(
+        out,
+        "OS: {} ({})",
+        std::env::consts::OS,
+        std::env::consts::ARCH
+    );
+    let _ = writeln!(out);
+    let _ = writeln!(out, "Server: {}:{}", config.server.ip, config.server.port);
+    let _ = writeln!(
+        out,
+        "Connectivity: {}",
+        connectivity_test(&config.server.ip, config.server.port)
+    );
+    let _ = writeln!(out);
+    let _ = writeln!(out, "Game path: {}", config.game_path);
+    let _ = writeln!(out, "Game exists: {}", game_path.exists());
+    let _ = writeln!(
+        out,
+        "Rag2.exe SHA-256: {}",
+        patch.exe_sha256.as_deref().unwrap_or("<unreadable>")
+    );
+    let _ = writeln!(out, "Patcher backup present: {}", patch.backup_present);
+    let _ = writeln!(out, "Active profile: {}", config.active_profile().name);
+
+    if !cfg!(target_os = "windows") {
+        let _ = writeln!(out);
+        let runners = wine::detect();
+        if runners.is_empty() {
+            let _ = writeln!(out, "Wine/Proton runners: none found");
+        } else {
+            let _ = writeln!(out, "Wine/Proton runners:");
+            for runner in runners {
+                let _ = writeln!(out, "  {}", runner);
+            }
+        }
+    }
+
+    out
+}
+
+/// Write the diagnostics zip and return its path
+pub fn collect(config: &Config) -> Result<PathBuf> {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+
+    let out_dir = logging::log_dir()?;
+    fs::create_dir_all(&out_dir)?;
+    let out_path = out_dir.join(format!("diagnostics-{}.zip", timestamp));
+
+    let file = File::create(&out_path)
+        .with_context(|| format!("Failed to create {}", out_path.display()))?;
+    let mut zip = zip::ZipWriter::new(file);
+    let options = SimpleFileOptions::default();
+
+    zip.start_file("report.txt", options)?;
+    zip.write_all(report(config).as_bytes())?;
+
+    zip.start_file("launcher.toml", options)?;
+    zip.write_all(toml::to_string_pretty(config)?.as_bytes())?;
+
+    if let Ok(log) = fs::read(logging::log_path()?) {
+        zip.start_file(logging::LOG_FILE, options)?;
+        zip.write_all(&log)?;
+    }
+
+    zip.finish()?;
+    Ok(out_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    #[test]
+    fn test_connectivity_test() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        assert!(connectivity_test("127.0.0.1", port).starts_with("OK"));
+
+        drop(listener);
+        assert!(connectivity_test("127.0.0.1", port).starts_with("FAILED"));
+    }
+
+    #[test]
+    fn test_patch_status_missing_exe() {
+        let status = PatchStatus::inspect(Path::new("/nonexistent/SHIPPING/Rag2.exe"));
+        assert!(status.exe_sha256.is_none());
+        assert!(!status.backup_present);
+    }
+}
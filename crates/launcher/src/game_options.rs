@@ -0,0 +1,304 @@
+//! Client-side game options
+//!
+//! Rag2.exe reads its display and language settings from files in the game
+//! root rather than from the command line, so the launcher writes them out
+//! right before spawning the client. Each launcher profile carries its own
+//! set of options.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+/// Options file written relative to the game root directory
+///
+/// Section and key names follow the layout of the stock options file
+/// shipped with the Jawaii client.
+pub const OPTIONS_FILE: &str = "Config/ClientOption.ini";
+
+/// Directory (relative to the game root) holding the active locale files
+pub const LOCALE_DIR: &str = "Locale";
+
+/// Client language
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Language {
+    #[default]
+    English,
+    Korean,
+    Japanese,
+    Portuguese,
+}
+
+impl Language {
+    /// All selectable languages
+    pub const ALL: [Language; 4] = [
+        Language::English,
+        Language::Korean,
+        Language::Japanese,
+        Language::Portuguese,
+    ];
+
+    /// Locale code used by the client for this language
+    pub fn code(self) -> &'static str {
+        match self {
+            Language::English => "en_US",
+            Language::Korean => "ko_KR",
+            Language::Japanese => "ja_JP",
+            Language::Portuguese => "pt_BR",
+        }
+    }
+}
+
+impl fmt::Display for Language {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Language::English => "English",
+            Language::Korean => "한국어",
+            Language::Japanese => "日本語",
+            Language::Portuguese => "Português",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Display and locale options for one launcher profile
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GameProfile {
+    /// Profile name shown in the launcher
+    pub name: String,
+
+    /// Horizontal resolution in pixels
+    pub width: u32,
+
+    /// Vertical resolution in pixels
+    pub height: u32,
+
+    /// Run in a window instead of fullscreen
+    pub windowed: bool,
+
+    /// Client language
+    #[serde(default)]
+    pub language: Language,
+
+    /// Wine/Proton runner path (non-Windows only, empty = auto-detect)
+    #[serde(default)]
+    pub wine_runner: String,
+
+    /// WINEPREFIX (or Proton compat data dir), empty = runner default
+    #[serde(default)]
+    pub wine_prefix: String,
+
+    /// WINEDLLOVERRIDES value passed to the runner
+    #[serde(default = "default_dll_overrides")]
+    pub dll_overrides: String,
+}
+
+fn default_dll_overrides() -> String {
+    String::from(crate::wine::DEFAULT_DLL_OVERRIDES)
+}
+
+impl Default for GameProfile {
+    fn default() -> Self {
+        Self {
+            name: String::from("Default"),
+            width: 1024,
+            height: 768,
+            windowed: true,
+            language: Language::default(),
+            wine_runner: String::new(),
+            wine_prefix: String::new(),
+            dll_overrides: default_dll_overrides(),
+        }
+    }
+}
+
+impl GameProfile {
+    /// Render the options file contents for this profile
+    pub fn to_ini(&self) -> String {
+        format!(
+            "[Display]\r\nWidth={}\r\nHeight={}\r\nWindowed={}\r\n\r\n[Locale]\r\nLanguage={}\r\n",
+            self.width,
+            self.height,
+            u8::from(self.windowed),
+            self.language.code()
+        )
+    }
+
+    /// Write the client options file and activate the locale files
+    ///
+    /// `game_root` is the working directory the client is started in (the
+    /// parent of `SHIPPING/`).
+    pub fn apply(&self, game_root: &Path) -> Result<()> {
+        let options_path = game_root.join(OPTIONS_FILE);
+        if let Some(parent) = options_path.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create {}", parent.display()))?;
+        }
+        fs::write(&options_path, self.to_ini())
+            .with_context(|| format!("Failed to write {}", options_path.display()))?;
+
+        self.install_locale(game_root)
+    }
+
+    /// Copy `Locale/<code>/*` over the active locale directory
+    ///
+    /// Installs that only ship one language have no per-language folders;
+    /// in that case nothing is copied. Whatever a previous language put in
+    /// place is undone first, so picking the shipped language again brings
+    /// back the original files.
+    fn install_locale(&self, game_root: &Path) -> Result<()> {
+        let locale_root = game_root.join(LOCALE_DIR);
+        restore_locale(&locale_root)?;
+
+        let source = locale_root.join(self.language.code());
+        if !source.is_dir() {
+            return Ok(());
+        }
+
+        let backup = locale_root.join(LOCALE_BACKUP_DIR);
+        fs::create_dir_all(&backup)
+            .with_context(|| format!("Failed to create {}", backup.display()))?;
+        let mut added = Vec::new();
+        for entry in fs::read_dir(&source)? {
+            let entry = entry?;
+            if !entry.file_type()?.is_file() {
+                continue;
+            }
+
+            let name = entry.file_name();
+            let target = locale_root.join(&name);
+            if target.exists() {
+                fs::rename(&target, backup.join(&name))
+                    .with_context(|| format!("Failed to back up {}", target.display()))?;
+            } else {
+                // Recorded before the copy, so an interrupted install
+                // still gets cleaned up
+                added.push(name.to_string_lossy().into_owned());
+                fs::write(backup.join(ADDED_FILE), added.join("\n"))
+                    .context("Failed to record installed locale files")?;
+            }
+            fs::copy(entry.path(), &target).with_context(|| {
+                format!("Failed to install locale file {}", entry.path().display())
+            })?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Directory under `Locale/` holding the files a language install replaced
+const LOCALE_BACKUP_DIR: &str = ".original";
+
+/// File in [`LOCALE_BACKUP_DIR`] listing installed files that replaced
+/// nothing, one name per line
+const ADDED_FILE: &str = ".added";
+
+/// Put back the files a language install replaced and remove the ones it
+/// added
+fn restore_locale(locale_root: &Path) -> Result<()> {
+    let backup = locale_root.join(LOCALE_BACKUP_DIR);
+    if !backup.is_dir() {
+        return Ok(());
+    }
+
+    let added = match fs::read_to_string(backup.join(ADDED_FILE)) {
+        Ok(added) => added,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e).context("Failed to read installed locale files"),
+    };
+    for name in added.lines().filter(|name| !name.is_empty()) {
+        match fs::remove_file(locale_root.join(name)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                return Err(e).with_context(|| format!("Failed to remove locale file {}", name));
+            }
+            _ => {}
+        }
+    }
+
+    for entry in fs::read_dir(&backup)? {
+        let entry = entry?;
+        if entry.file_type()?.is_file() && entry.file_name() != ADDED_FILE {
+            let target = locale_root.join(entry.file_name());
+            fs::rename(entry.path(), &target)
+                .with_context(|| format!("Failed to restore {}", target.display()))?;
+        }
+    }
+    fs::remove_dir_all(&backup).with_context(|| format!("Failed to remove {}", backup.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_profile_ini() {
+        let profile = GameProfile {
+            name: String::from("Laptop"),
+            width: 1280,
+            height: 720,
+            windowed: false,
+            language: Language::Korean,
+            ..GameProfile::default()
+        };
+
+        let ini = profile.to_ini();
+        assert!(ini.contains("Width=1280\r\n"));
+        assert!(ini.contains("Height=720\r\n"));
+        assert!(ini.contains("Windowed=0\r\n"));
+        assert!(ini.contains("Language=ko_KR\r\n"));
+    }
+
+    #[test]
+    fn test_apply_installs_locale() {
+        let root = std::env::temp_dir().join(format!("ragnoria-opts-{}", std::process::id()));
+        let locale_src = root.join(LOCALE_DIR).join("ja_JP");
+        fs::create_dir_all(&locale_src).unwrap();
+        fs::write(locale_src.join("strings.dat"), b"ja").unwrap();
+
+        let profile = GameProfile {
+            language: Language::Japanese,
+            ..GameProfile::default()
+        };
+        profile.apply(&root).unwrap();
+
+        let ini = fs::read_to_string(root.join(OPTIONS_FILE)).unwrap();
+        assert!(ini.contains("Language=ja_JP"));
+        assert_eq!(
+            fs::read(root.join(LOCALE_DIR).join("strings.dat")).unwrap(),
+            b"ja"
+        );
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_switching_back_restores_locale() {
+        let root = std::env::temp_dir().join(format!("ragnoria-locale-{}", std::process::id()));
+        let locale = root.join(LOCALE_DIR);
+        fs::create_dir_all(locale.join("ko_KR")).unwrap();
+        fs::write(locale.join("strings.dat"), b"en").unwrap();
+        fs::write(locale.join("ko_KR").join("strings.dat"), b"ko").unwrap();
+        fs::write(locale.join("ko_KR").join("fonts.dat"), b"ko").unwrap();
+
+        let mut profile = GameProfile {
+            language: Language::Korean,
+            ..GameProfile::default()
+        };
+        profile.apply(&root).unwrap();
+        // Applying twice must not back up the installed copy
+        profile.apply(&root).unwrap();
+        assert_eq!(fs::read(locale.join("strings.dat")).unwrap(), b"ko");
+        assert!(locale.join("fonts.dat").exists());
+
+        // English ships no folder of its own: the original files come back
+        profile.language = Language::English;
+        profile.apply(&root).unwrap();
+        assert_eq!(fs::read(locale.join("strings.dat")).unwrap(), b"en");
+        assert!(!locale.join("fonts.dat").exists());
+        assert!(!locale.join(LOCALE_BACKUP_DIR).exists());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,60 @@
+//! Launcher logging
+//!
+//! The launcher is a GUI app, so nothing written to stdout is visible on
+//! Windows. Logs go to `launcher.log` in the launcher's config directory
+//! instead, where the Logs panel and the diagnostics bundle can read them.
+
+use anyhow::Result;
+use std::fs;
+use std::path::PathBuf;
+use tracing_appender::non_blocking::WorkerGuard;
+
+/// Log file name inside the log directory
+pub const LOG_FILE: &str = "launcher.log";
+
+/// Get the log directory
+pub fn log_dir() -> Result<PathBuf> {
+    let config_dir =
+        dirs::config_dir().ok_or_else(|| anyhow::anyhow!("Could not find config directory"))?;
+
+    Ok(config_dir.join("ragnoria").join("logs"))
+}
+
+/// Get the log file path
+pub fn log_path() -> Result<PathBuf> {
+    Ok(log_dir()?.join(LOG_FILE))
+}
+
+/// Initialize the file logger
+///
+/// The returned guard flushes buffered log lines when dropped, so it must be
+/// kept alive for the lifetime of the application.
+pub fn init() -> Result<WorkerGuard> {
+    let dir = log_dir()?;
+    fs::create_dir_all(&dir)?;
+
+    let appender = tracing_appender::rolling::never(&dir, LOG_FILE);
+    let (writer, guard) = tracing_appender::non_blocking(appender);
+
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            tracing_subscriber::EnvFilter::from_default_env()
+                .add_directive(tracing::Level::INFO.into()),
+        )
+        .with_writer(writer)
+        .with_ansi(false)
+        .init();
+
+    Ok(guard)
+}
+
+/// Read the last `max_lines` lines of the log file
+pub fn tail(max_lines: usize) -> String {
+    let contents = log_path()
+        .and_then(|path| Ok(fs::read_to_string(path)?))
+        .unwrap_or_default();
+
+    let lines: Vec<&str> = contents.lines().collect();
+    let start = lines.len().saturating_sub(max_lines);
+    lines[start..].join("\n")
+}
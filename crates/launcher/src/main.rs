@@ -1,11 +1,29 @@
-use iced::widget::{button, column, container, row, text, text_input};
-use iced::{Center, Element, Fill, Task};
-use std::path::PathBuf;
+use iced::widget::{
+    button, checkbox, column, container, pick_list, row, scrollable, text, text_input,
+};
+use iced::{Center, Element, Fill, Font, Task};
+use std::path::{Path, PathBuf};
+use tracing::{error, info, warn};
 
 mod config;
+mod diagnostics;
+mod game_options;
+mod logging;
+mod news;
+#[cfg_attr(target_os = "windows", allow(dead_code))]
+mod wine;
 use config::Config;
+use game_options::{GameProfile, Language};
+use news::{NewsFeed, NewsSource};
+
+/// Number of log lines shown in the Logs panel
+const LOG_VIEW_LINES: usize = 500;
 
 fn main() -> iced::Result {
+    // Keep the guard alive so buffered log lines are flushed on exit
+    let _log_guard = logging::init().ok();
+    info!("Ragnoria Launcher v{} starting", env!("CARGO_PKG_VERSION"));
+
     iced::application("Ragnoria Launcher", Launcher::update, Launcher::view)
         .window_size((600.0, 400.0))
         .run_with(Launcher::new)
@@ -19,6 +37,33 @@ enum Message {
     LaunchGame,
     BrowseGamePath,
     GamePathSelected(Option<PathBuf>),
+    ShowPanel(Panel),
+    ProfileSelected(String),
+    ProfileNameChanged(String),
+    ResolutionWidthChanged(String),
+    ResolutionHeightChanged(String),
+    WindowedToggled(bool),
+    LanguageSelected(Language),
+    WineRunnerSelected(String),
+    WinePrefixChanged(String),
+    DllOverridesChanged(String),
+    SaveProfile,
+    DeleteProfile,
+    NewsUrlChanged(String),
+    RefreshNews,
+    NewsLoaded(NewsSource),
+    RefreshLogs,
+    CollectDiagnostics,
+    DiagnosticsCollected(Result<PathBuf, String>),
+}
+
+/// Which panel the launcher window is showing
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Panel {
+    Main,
+    Settings,
+    News,
+    Logs,
 }
 
 struct Launcher {
@@ -27,21 +72,52 @@ struct Launcher {
     game_path: String,
     status_message: String,
     config: Config,
+    panel: Panel,
+
+    /// Profile being edited in the settings panel
+    profile: GameProfile,
+    width_input: String,
+    height_input: String,
+
+    /// Last loaded news feed and a note about where it came from
+    news: NewsFeed,
+    news_status: String,
+
+    /// Log file contents shown in the Logs panel
+    log_text: String,
+
+    /// Wine/Proton runners found on this system
+    runners: Vec<wine::Runner>,
 }
 
 impl Launcher {
     fn new() -> (Self, Task<Message>) {
         let config = Config::load().unwrap_or_default();
 
+        let profile = config.active_profile();
+
         let launcher = Self {
             server_ip: config.server.ip.clone(),
             server_port: config.server.port.to_string(),
             game_path: config.game_path.clone(),
             status_message: String::from("Ready to launch"),
             config,
+            panel: Panel::Main,
+            width_input: profile.width.to_string(),
+            height_input: profile.height.to_string(),
+            profile,
+            news: NewsFeed::default(),
+            news_status: String::from("Loading news..."),
+            log_text: String::new(),
+            runners: if cfg!(target_os = "windows") {
+                Vec::new()
+            } else {
+                wine::detect()
+            },
         };
 
-        (launcher, Task::none())
+        let task = launcher.refresh_news();
+        (launcher, task)
     }
 
     fn update(&mut self, message: Message) -> Task<Message> {
@@ -82,10 +158,159 @@ impl Launcher {
                 }
                 Task::none()
             }
+            Message::ShowPanel(panel) => {
+                if panel == Panel::Logs {
+                    self.log_text = logging::tail(LOG_VIEW_LINES);
+                }
+                self.panel = panel;
+                Task::none()
+            }
+            Message::ProfileSelected(name) => {
+                self.config.active_profile = name;
+                self.edit_profile(self.config.active_profile());
+                let _ = self.config.save();
+                Task::none()
+            }
+            Message::ProfileNameChanged(name) => {
+                self.profile.name = name;
+                Task::none()
+            }
+            Message::ResolutionWidthChanged(width) => {
+                self.width_input = width;
+                Task::none()
+            }
+            Message::ResolutionHeightChanged(height) => {
+                self.height_input = height;
+                Task::none()
+            }
+            Message::WindowedToggled(windowed) => {
+                self.profile.windowed = windowed;
+                Task::none()
+            }
+            Message::LanguageSelected(language) => {
+                self.profile.language = language;
+                Task::none()
+            }
+            Message::WineRunnerSelected(runner) => {
+                self.profile.wine_runner = self
+                    .runners
+                    .iter()
+                    .find(|r| r.to_string() == runner)
+                    .map(|r| r.path.to_string_lossy().into_owned())
+                    .unwrap_or_default();
+                Task::none()
+            }
+            Message::WinePrefixChanged(prefix) => {
+                self.profile.wine_prefix = prefix;
+                Task::none()
+            }
+            Message::DllOverridesChanged(overrides) => {
+                self.profile.dll_overrides = overrides;
+                Task::none()
+            }
+            Message::SaveProfile => {
+                self.save_profile();
+                Task::none()
+            }
+            Message::DeleteProfile => {
+                let name = self.profile.name.clone();
+                self.config.remove_profile(&name);
+                self.edit_profile(self.config.active_profile());
+                let _ = self.config.save();
+                self.status_message = format!("Deleted profile '{}'", name);
+                Task::none()
+            }
+            Message::NewsUrlChanged(url) => {
+                self.config.news_url = url;
+                Task::none()
+            }
+            Message::RefreshNews => {
+                let _ = self.config.save();
+                self.news_status = String::from("Loading news...");
+                self.refresh_news()
+            }
+            Message::NewsLoaded(source) => {
+                match source {
+                    NewsSource::Live(feed) => {
+                        info!("Loaded {} news item(s)", feed.items.len());
+                        self.news_status = format!("{} announcement(s)", feed.items.len());
+                        self.news = feed;
+                    }
+                    NewsSource::Cached { feed, error } => {
+                        warn!("News fetch failed, using cache: {}", error);
+                        self.news_status = format!("Offline, showing cached news ({})", error);
+                        self.news = feed;
+                    }
+                    NewsSource::Unavailable(error) => {
+                        warn!("News unavailable: {}", error);
+                        self.news_status = format!("News unavailable: {}", error);
+                    }
+                }
+                Task::none()
+            }
+            Message::RefreshLogs => {
+                self.log_text = logging::tail(LOG_VIEW_LINES);
+                Task::none()
+            }
+            Message::CollectDiagnostics => {
+                self.status_message = String::from("Collecting diagnostics...");
+                let config = self.config.clone();
+                Task::perform(
+                    async move {
+                        tokio::task::spawn_blocking(move || {
+                            diagnostics::collect(&config).map_err(|e| e.to_string())
+                        })
+                        .await
+                        .unwrap_or_else(|e| Err(e.to_string()))
+                    },
+                    Message::DiagnosticsCollected,
+                )
+            }
+            Message::DiagnosticsCollected(result) => {
+                match result {
+                    Ok(path) => {
+                        info!("Diagnostics bundle written to {}", path.display());
+                        self.status_message = format!("Diagnostics saved: {}", path.display());
+                    }
+                    Err(e) => {
+                        error!("Failed to collect diagnostics: {}", e);
+                        self.status_message = format!("Error collecting diagnostics: {}", e);
+                    }
+                }
+                Task::none()
+            }
         }
     }
 
+    /// Fetch the news feed in the background
+    fn refresh_news(&self) -> Task<Message> {
+        let url = self.config.news_url.clone();
+        Task::perform(
+            async move {
+                tokio::task::spawn_blocking(move || news::load(&url))
+                    .await
+                    .unwrap_or_else(|e| NewsSource::Unavailable(e.to_string()))
+            },
+            Message::NewsLoaded,
+        )
+    }
+
     fn view(&self) -> Element<'_, Message> {
+        let content = match self.panel {
+            Panel::Main => self.view_main(),
+            Panel::Settings => self.view_settings(),
+            Panel::News => self.view_news(),
+            Panel::Logs => self.view_logs(),
+        };
+
+        container(content)
+            .width(Fill)
+            .height(Fill)
+            .padding(20)
+            .into()
+    }
+
+    fn view_main(&self) -> Element<'_, Message> {
         let title = text("Ragnoria Launcher").size(28).width(Fill);
         let subtitle = text("Connect to custom RO2 server").size(14).width(Fill);
 
@@ -127,28 +352,290 @@ impl Launcher {
             .padding(12)
             .width(200);
 
+        let settings_button = button(text("Settings"))
+            .on_press(Message::ShowPanel(Panel::Settings))
+            .padding(12);
+
+        let news_button = button(text("News"))
+            .on_press(Message::ShowPanel(Panel::News))
+            .padding(12);
+
+        let logs_button = button(text("Logs"))
+            .on_press(Message::ShowPanel(Panel::Logs))
+            .padding(12);
+
+        let banner = match self.news.banner() {
+            Some(item) => text(format!("[{}] {}", item.kind.label(), item.title)).size(14),
+            None => text(""),
+        };
+
         let status = text(&self.status_message).size(12).width(Fill);
 
-        let content = column![
+        column![
             title,
             subtitle,
+            banner,
             server_ip_row,
             server_port_row,
             game_path_row,
-            launch_button,
+            row![launch_button, settings_button, news_button, logs_button].spacing(10),
             status,
         ]
         .spacing(15)
         .padding(30)
+        .width(Fill)
+        .into()
+    }
+
+    fn view_logs(&self) -> Element<'_, Message> {
+        let title = text("Launcher Log").size(28).width(Fill);
+
+        let log = scrollable(text(&self.log_text).size(12).font(Font::MONOSPACE))
+            .height(Fill)
+            .width(Fill);
+
+        let buttons = row![
+            button(text("Refresh"))
+                .on_press(Message::RefreshLogs)
+                .padding(12),
+            button(text("Collect Diagnostics"))
+                .on_press(Message::CollectDiagnostics)
+                .padding(12),
+            button(text("Back"))
+                .on_press(Message::ShowPanel(Panel::Main))
+                .padding(12),
+        ]
+        .spacing(10);
+
+        let status = text(&self.status_message).size(12).width(Fill);
+
+        column![title, log, buttons, status]
+            .spacing(15)
+            .padding(30)
+            .width(Fill)
+            .into()
+    }
+
+    fn view_news(&self) -> Element<'_, Message> {
+        let title = text("Server News").size(28).width(Fill);
+
+        let url_row = row![
+            text("Feed URL:").width(120),
+            text_input("http://server/news", &self.config.news_url)
+                .on_input(Message::NewsUrlChanged)
+                .padding(8)
+                .width(Fill),
+            button(text("Refresh"))
+                .on_press(Message::RefreshNews)
+                .padding(8)
+        ]
+        .spacing(10)
         .width(Fill);
 
-        container(content)
+        let items = self
+            .news
+            .items
+            .iter()
+            .fold(column![].spacing(12), |col, item| {
+                let heading = match &item.published_at {
+                    Some(date) => format!("[{}] {} ({})", item.kind.label(), item.title, date),
+                    None => format!("[{}] {}", item.kind.label(), item.title),
+                };
+                col.push(column![text(heading).size(16), text(&item.body).size(13)].spacing(4))
+            });
+
+        let back = button(text("Back"))
+            .on_press(Message::ShowPanel(Panel::Main))
+            .padding(12);
+
+        let status = text(&self.news_status).size(12).width(Fill);
+
+        column![title, url_row, scrollable(items).height(Fill), status, back]
+            .spacing(15)
+            .padding(30)
             .width(Fill)
-            .height(Fill)
-            .padding(20)
             .into()
     }
 
+    fn view_settings(&self) -> Element<'_, Message> {
+        let title = text("Game Settings").size(28).width(Fill);
+
+        let profile_names: Vec<String> = self
+            .config
+            .profiles
+            .iter()
+            .map(|p| p.name.clone())
+            .collect();
+
+        let profile_row = row![
+            text("Profile:").width(120),
+            pick_list(
+                profile_names,
+                Some(self.config.active_profile.clone()),
+                Message::ProfileSelected
+            )
+            .width(Fill),
+            text_input("Profile name", &self.profile.name)
+                .on_input(Message::ProfileNameChanged)
+                .padding(8)
+                .width(Fill)
+        ]
+        .spacing(10)
+        .width(Fill);
+
+        let resolution_row = row![
+            text("Resolution:").width(120),
+            text_input("1024", &self.width_input)
+                .on_input(Message::ResolutionWidthChanged)
+                .padding(8)
+                .width(100),
+            text("x"),
+            text_input("768", &self.height_input)
+                .on_input(Message::ResolutionHeightChanged)
+                .padding(8)
+                .width(100),
+            checkbox("Windowed", self.profile.windowed).on_toggle(Message::WindowedToggled)
+        ]
+        .spacing(10)
+        .align_y(Center)
+        .width(Fill);
+
+        let language_row = row![
+            text("Language:").width(120),
+            pick_list(
+                Language::ALL,
+                Some(self.profile.language),
+                Message::LanguageSelected
+            )
+            .width(Fill)
+        ]
+        .spacing(10)
+        .width(Fill);
+
+        let wine_rows = self.view_wine_settings();
+
+        let buttons = row![
+            button(text("Save Profile"))
+                .on_press(Message::SaveProfile)
+                .padding(12),
+            button(text("Delete Profile"))
+                .on_press(Message::DeleteProfile)
+                .padding(12),
+            button(text("Back"))
+                .on_press(Message::ShowPanel(Panel::Main))
+                .padding(12),
+        ]
+        .spacing(10);
+
+        let status = text(&self.status_message).size(12).width(Fill);
+
+        column![
+            title,
+            profile_row,
+            resolution_row,
+            language_row,
+            wine_rows,
+            buttons,
+            status
+        ]
+        .spacing(15)
+        .padding(30)
+        .width(Fill)
+        .into()
+    }
+
+    /// Wine/Proton options (hidden on Windows)
+    fn view_wine_settings(&self) -> Element<'_, Message> {
+        if cfg!(target_os = "windows") {
+            return column![].into();
+        }
+
+        const AUTO: &str = "Auto-detect";
+        let mut runner_names = vec![String::from(AUTO)];
+        runner_names.extend(self.runners.iter().map(|r| r.to_string()));
+
+        let selected = self
+            .runners
+            .iter()
+            .find(|r| r.path.to_string_lossy() == self.profile.wine_runner)
+            .map(|r| r.to_string())
+            .unwrap_or_else(|| String::from(AUTO));
+
+        let runner_row = row![
+            text("Runner:").width(120),
+            pick_list(runner_names, Some(selected), Message::WineRunnerSelected).width(Fill)
+        ]
+        .spacing(10)
+        .width(Fill);
+
+        let prefix_row = row![
+            text("Wine prefix:").width(120),
+            text_input("~/.wine (runner default)", &self.profile.wine_prefix)
+                .on_input(Message::WinePrefixChanged)
+                .padding(8)
+                .width(Fill)
+        ]
+        .spacing(10)
+        .width(Fill);
+
+        let overrides_row = row![
+            text("DLL overrides:").width(120),
+            text_input(wine::DEFAULT_DLL_OVERRIDES, &self.profile.dll_overrides)
+                .on_input(Message::DllOverridesChanged)
+                .padding(8)
+                .width(Fill)
+        ]
+        .spacing(10)
+        .width(Fill);
+
+        let mut rows = column![runner_row, prefix_row, overrides_row].spacing(15);
+        if self.runners.is_empty() {
+            rows = rows.push(text(wine::missing_runner_help()).size(12));
+        }
+        rows.into()
+    }
+
+    /// Load a profile into the settings editor
+    fn edit_profile(&mut self, profile: GameProfile) {
+        self.width_input = profile.width.to_string();
+        self.height_input = profile.height.to_string();
+        self.profile = profile;
+    }
+
+    /// Validate the settings editor and store it as a profile
+    fn save_profile(&mut self) {
+        if self.profile.name.trim().is_empty() {
+            self.status_message = String::from("Error: Profile name is required");
+            return;
+        }
+
+        let (width, height) = match (
+            self.width_input.parse::<u32>(),
+            self.height_input.parse::<u32>(),
+        ) {
+            (Ok(w), Ok(h)) if w > 0 && h > 0 => (w, h),
+            _ => {
+                self.status_message = String::from("Error: Invalid resolution");
+                return;
+            }
+        };
+
+        self.profile.width = width;
+        self.profile.height = height;
+        self.config.upsert_profile(self.profile.clone());
+
+        match self.config.save() {
+            Ok(()) => {
+                info!("Saved profile '{}'", self.profile.name);
+                self.status_message = format!("Saved profile '{}'", self.profile.name);
+            }
+            Err(e) => {
+                error!("Failed to save config: {}", e);
+                self.status_message = format!("Error saving profile: {}", e);
+            }
+        }
+    }
+
     fn launch_game(&mut self) {
         // Validate inputs
         if self.server_ip.trim().is_empty() {
@@ -181,31 +668,44 @@ impl Launcher {
         self.config.game_path = self.game_path.clone();
         let _ = self.config.save();
 
+        // Write client options for the active profile
+        let profile = self.config.active_profile();
+        if let Err(e) = game_root(&game_path).and_then(|root| profile.apply(root)) {
+            error!("Failed to write game options: {}", e);
+            self.status_message = format!("Error writing game options: {}", e);
+            return;
+        }
+
         // Launch game
-        match self.launch_game_process(&game_path, &self.server_ip) {
+        match self.launch_game_process(&game_path, &self.server_ip, &profile) {
             Ok(_) => {
+                info!(
+                    "Launched {} (server {}:{}, profile '{}')",
+                    game_path.display(),
+                    self.server_ip,
+                    port,
+                    profile.name
+                );
                 self.status_message =
                     format!("Game launched! Connecting to {}:{}", self.server_ip, port);
             }
             Err(e) => {
+                error!("Failed to launch game: {}", e);
                 self.status_message = format!("Error launching game: {}", e);
             }
         }
     }
 
-    fn launch_game_process(&self, game_path: &PathBuf, server_ip: &str) -> anyhow::Result<()> {
-        use std::process::Command;
-
-        // Get directories
-        let shipping_dir = game_path
-            .parent()
-            .ok_or_else(|| anyhow::anyhow!("Invalid game path"))?;
-
-        let game_root_dir = shipping_dir
-            .parent()
-            .ok_or_else(|| anyhow::anyhow!("Invalid game directory structure"))?;
+    #[cfg_attr(target_os = "windows", allow(unused_variables))]
+    fn launch_game_process(
+        &self,
+        game_path: &Path,
+        server_ip: &str,
+        profile: &GameProfile,
+    ) -> anyhow::Result<()> {
+        let game_root_dir = game_root(game_path)?;
 
-        // Build command line arguments  
+        // Build command line arguments
         // Format: /FROM=-FromLauncher /IP=127.0.0.1
         // Normal mode: Shows login UI with username/password fields
         let args = vec![
@@ -216,7 +716,7 @@ impl Launcher {
         // Launch game
         #[cfg(target_os = "windows")]
         {
-            Command::new(game_path)
+            std::process::Command::new(game_path)
                 .args(&args)
                 .current_dir(game_root_dir)
                 .spawn()?;
@@ -224,8 +724,26 @@ impl Launcher {
 
         #[cfg(not(target_os = "windows"))]
         {
-            Command::new("wine")
-                .arg(game_path)
+            let runner = wine::resolve(&profile.wine_runner, &self.runners)?;
+
+            let prefix = if !profile.wine_prefix.trim().is_empty() {
+                Some(PathBuf::from(profile.wine_prefix.trim()))
+            } else if runner.kind == wine::RunnerKind::Proton {
+                wine::default_proton_prefix(&profile.name)
+            } else {
+                None
+            };
+            if let Some(prefix) = &prefix {
+                std::fs::create_dir_all(prefix)?;
+            }
+
+            info!(
+                "Launching through {} (prefix: {:?}, overrides: {})",
+                runner, prefix, profile.dll_overrides
+            );
+
+            runner
+                .command(game_path, prefix.as_deref(), &profile.dll_overrides)
                 .args(&args)
                 .current_dir(game_root_dir)
                 .spawn()?;
@@ -234,3 +752,14 @@ impl Launcher {
         Ok(())
     }
 }
+
+/// Get the game root directory (parent of `SHIPPING/`) from the Rag2.exe path
+fn game_root(game_path: &Path) -> anyhow::Result<&Path> {
+    let shipping_dir = game_path
+        .parent()
+        .ok_or_else(|| anyhow::anyhow!("Invalid game path"))?;
+
+    shipping_dir
+        .parent()
+        .ok_or_else(|| anyhow::anyhow!("Invalid game directory structure"))
+}
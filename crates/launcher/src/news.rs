@@ -0,0 +1,177 @@
+//! Server news and announcements
+//!
+//! The launcher fetches a JSON feed from the configured news URL (normally
+//! the admin API's `/news` endpoint) and shows it in the News panel. The
+//! last successful fetch is cached next to the launcher config so the panel
+//! still has content when the server is unreachable.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Timeout for fetching the news feed
+const FETCH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Kind of announcement, used for grouping and labels
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NewsKind {
+    #[default]
+    News,
+    PatchNotes,
+    Event,
+}
+
+impl NewsKind {
+    /// Short label shown next to the title
+    pub fn label(self) -> &'static str {
+        match self {
+            NewsKind::News => "NEWS",
+            NewsKind::PatchNotes => "PATCH",
+            NewsKind::Event => "EVENT",
+        }
+    }
+}
+
+/// A single announcement
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NewsItem {
+    pub title: String,
+
+    #[serde(default)]
+    pub body: String,
+
+    #[serde(default)]
+    pub kind: NewsKind,
+
+    /// Publication date as sent by the server (displayed verbatim)
+    #[serde(default)]
+    pub published_at: Option<String>,
+
+    /// Event banners are highlighted on the main panel
+    #[serde(default)]
+    pub banner: bool,
+}
+
+/// News feed as served by the admin API
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NewsFeed {
+    pub items: Vec<NewsItem>,
+}
+
+impl NewsFeed {
+    /// Parse a feed from JSON
+    ///
+    /// Accepts either `{"items": [...]}` or a bare array of items.
+    pub fn from_json(json: &str) -> Result<Self> {
+        if let Ok(feed) = serde_json::from_str::<NewsFeed>(json) {
+            return Ok(feed);
+        }
+        let items: Vec<NewsItem> = serde_json::from_str(json).context("Invalid news feed JSON")?;
+        Ok(Self { items })
+    }
+
+    /// First item flagged as a banner, if any
+    pub fn banner(&self) -> Option<&NewsItem> {
+        self.items.iter().find(|item| item.banner)
+    }
+
+    /// Get the cache file path
+    pub fn cache_path() -> Result<PathBuf> {
+        let config_dir =
+            dirs::config_dir().ok_or_else(|| anyhow::anyhow!("Could not find config directory"))?;
+
+        Ok(config_dir.join("ragnoria").join("news_cache.json"))
+    }
+
+    /// Load the cached feed from the last successful fetch
+    pub fn load_cached() -> Result<Self> {
+        let contents = fs::read_to_string(Self::cache_path()?)?;
+        Self::from_json(&contents)
+    }
+
+    /// Save this feed to the cache
+    pub fn save_cache(&self) -> Result<()> {
+        let path = Self::cache_path()?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+}
+
+/// Result of loading the news, tagged with where it came from
+#[derive(Debug, Clone)]
+pub enum NewsSource {
+    /// Freshly fetched from the server
+    Live(NewsFeed),
+
+    /// Fetch failed, showing the cached copy
+    Cached { feed: NewsFeed, error: String },
+
+    /// Fetch failed and nothing is cached
+    Unavailable(String),
+}
+
+/// Fetch the feed from `url`, falling back to the cache on failure
+///
+/// Blocking; call from a background task.
+pub fn load(url: &str) -> NewsSource {
+    match fetch(url) {
+        Ok(feed) => {
+            let _ = feed.save_cache();
+            NewsSource::Live(feed)
+        }
+        Err(e) => match NewsFeed::load_cached() {
+            Ok(feed) => NewsSource::Cached {
+                feed,
+                error: e.to_string(),
+            },
+            Err(_) => NewsSource::Unavailable(e.to_string()),
+        },
+    }
+}
+
+fn fetch(url: &str) -> Result<NewsFeed> {
+    if url.trim().is_empty() {
+        anyhow::bail!("No news URL configured");
+    }
+
+    let body = ureq::get(url)
+        .timeout(FETCH_TIMEOUT)
+        .call()
+        .with_context(|| format!("Failed to fetch news from {}", url))?
+        .into_string()?;
+
+    NewsFeed::from_json(&body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_feed() {
+        let json = r#"{"items": [
+            {"title": "Server open", "body": "Welcome!", "published_at": "2026-02-01"},
+            {"title": "Poring Festival", "kind": "event", "banner": true}
+        ]}"#;
+
+        let feed = NewsFeed::from_json(json).unwrap();
+        assert_eq!(feed.items.len(), 2);
+        assert_eq!(feed.items[0].kind, NewsKind::News);
+        assert_eq!(feed.banner().unwrap().title, "Poring Festival");
+    }
+
+    #[test]
+    fn test_parse_bare_array() {
+        let json = r#"[{"title": "v0.2 patch notes", "kind": "patch_notes"}]"#;
+
+        let feed = NewsFeed::from_json(json).unwrap();
+        assert_eq!(feed.items[0].kind, NewsKind::PatchNotes);
+        assert!(feed.banner().is_none());
+    }
+}
@@ -0,0 +1,243 @@
+//! Wine/Proton runner support for non-Windows hosts
+//!
+//! Rag2.exe is a 32-bit Windows binary, so on Linux it has to go through a
+//! compatibility layer. This module finds the runners installed on the
+//! system (system Wine and Steam's Proton builds) and builds the command
+//! line for whichever one the active profile selected.
+
+use anyhow::{Result, bail};
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Default DLL overrides
+///
+/// `ehsvc` is the HackShield service DLL; disabling it stops the client
+/// from trying to start the anti-cheat service, which doesn't work under
+/// Wine anyway. `d3dx9_43` must be native for the Gamebryo renderer.
+pub const DEFAULT_DLL_OVERRIDES: &str = "ehsvc=d;d3dx9_43=n,b";
+
+/// Kind of compatibility runner
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RunnerKind {
+    Wine,
+    Proton,
+}
+
+/// An installed Wine or Proton runner
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Runner {
+    pub kind: RunnerKind,
+
+    /// Path to the `wine` binary or Proton's `proton` script
+    pub path: PathBuf,
+
+    /// Display name (e.g. "Proton 9.0")
+    pub name: String,
+}
+
+impl fmt::Display for Runner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.name, self.path.display())
+    }
+}
+
+impl Runner {
+    /// Build the command that runs `game_path` through this runner
+    ///
+    /// `prefix` is the WINEPREFIX for Wine, or the compat data directory for
+    /// Proton (Proton creates `pfx/` inside it).
+    pub fn command(&self, game_path: &Path, prefix: Option<&Path>, dll_overrides: &str) -> Command {
+        let mut command = match self.kind {
+            RunnerKind::Wine => {
+                let mut command = Command::new(&self.path);
+                command.arg(game_path);
+                if let Some(prefix) = prefix {
+                    command.env("WINEPREFIX", prefix);
+                }
+                command
+            }
+            RunnerKind::Proton => {
+                let mut command = Command::new(&self.path);
+                command.arg("run").arg(game_path);
+                if let Some(prefix) = prefix {
+                    command.env("STEAM_COMPAT_DATA_PATH", prefix);
+                }
+                if let Some(steam) = steam_root() {
+                    command.env("STEAM_COMPAT_CLIENT_INSTALL_PATH", steam);
+                }
+                command
+            }
+        };
+
+        if !dll_overrides.trim().is_empty() {
+            command.env("WINEDLLOVERRIDES", dll_overrides.trim());
+        }
+
+        command
+    }
+}
+
+/// Find all Wine and Proton runners on this system
+pub fn detect() -> Vec<Runner> {
+    let mut runners = Vec::new();
+
+    for binary in ["wine", "wine64"] {
+        if let Some(path) = find_in_path(binary) {
+            runners.push(Runner {
+                kind: RunnerKind::Wine,
+                name: format!("System {}", binary),
+                path,
+            });
+        }
+    }
+
+    if let Some(steam) = steam_root() {
+        let search_dirs = [
+            steam.join("steamapps").join("common"),
+            steam.join("compatibilitytools.d"),
+        ];
+        for dir in search_dirs {
+            let Ok(entries) = fs::read_dir(&dir) else {
+                continue;
+            };
+            let mut found: Vec<Runner> = entries
+                .filter_map(|entry| entry.ok())
+                .filter_map(|entry| {
+                    let script = entry.path().join("proton");
+                    script.is_file().then(|| Runner {
+                        kind: RunnerKind::Proton,
+                        name: entry.file_name().to_string_lossy().into_owned(),
+                        path: script,
+                    })
+                })
+                .collect();
+            found.sort_by(|a, b| b.name.cmp(&a.name));
+            runners.extend(found);
+        }
+    }
+
+    runners
+}
+
+/// Resolve the runner selected in a profile
+///
+/// An empty selection picks the first detected runner. Returns an error
+/// with installation hints when nothing usable is found.
+pub fn resolve(selected: &str, detected: &[Runner]) -> Result<Runner> {
+    if !selected.trim().is_empty() {
+        if let Some(runner) = detected.iter().find(|r| r.path == Path::new(selected)) {
+            return Ok(runner.clone());
+        }
+
+        let path = PathBuf::from(selected);
+        if !path.is_file() {
+            bail!("Selected runner not found: {}", selected);
+        }
+        let kind = if path.file_name().is_some_and(|n| n == "proton") {
+            RunnerKind::Proton
+        } else {
+            RunnerKind::Wine
+        };
+        return Ok(Runner {
+            kind,
+            name: String::from("Custom"),
+            path,
+        });
+    }
+
+    match detected.first() {
+        Some(runner) => Ok(runner.clone()),
+        None => bail!("{}", missing_runner_help()),
+    }
+}
+
+/// Hint shown when no runner is installed
+pub fn missing_runner_help() -> &'static str {
+    "Wine was not found. Install it with your package manager \
+     (e.g. `sudo apt install wine32 wine64` or `sudo dnf install wine`), \
+     or install Proton through Steam and select it in Settings."
+}
+
+/// Default Proton compat data directory for a profile
+pub fn default_proton_prefix(profile_name: &str) -> Option<PathBuf> {
+    let safe: String = profile_name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    dirs::data_dir().map(|dir| dir.join("ragnoria").join(format!("proton-{}", safe)))
+}
+
+fn steam_root() -> Option<PathBuf> {
+    let home = dirs::home_dir()?;
+    [
+        home.join(".steam").join("steam"),
+        home.join(".local").join("share").join("Steam"),
+    ]
+    .into_iter()
+    .find(|dir| dir.is_dir())
+}
+
+fn find_in_path(binary: &str) -> Option<PathBuf> {
+    let paths = env::var_os("PATH")?;
+    env::split_paths(&paths)
+        .map(|dir| dir.join(binary))
+        .find(|path| path.is_file())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_without_runners() {
+        let err = resolve("", &[]).unwrap_err();
+        assert!(err.to_string().contains("Wine was not found"));
+    }
+
+    #[test]
+    fn test_resolve_prefers_selection() {
+        let detected = vec![
+            Runner {
+                kind: RunnerKind::Wine,
+                path: PathBuf::from("/usr/bin/wine"),
+                name: String::from("System wine"),
+            },
+            Runner {
+                kind: RunnerKind::Proton,
+                path: PathBuf::from("/steam/Proton 9.0/proton"),
+                name: String::from("Proton 9.0"),
+            },
+        ];
+
+        let runner = resolve("/steam/Proton 9.0/proton", &detected).unwrap();
+        assert_eq!(runner.kind, RunnerKind::Proton);
+
+        let runner = resolve("", &detected).unwrap();
+        assert_eq!(runner.kind, RunnerKind::Wine);
+    }
+
+    #[test]
+    fn test_proton_command_env() {
+        let runner = Runner {
+            kind: RunnerKind::Proton,
+            path: PathBuf::from("/steam/Proton 9.0/proton"),
+            name: String::from("Proton 9.0"),
+        };
+
+        let command = runner.command(
+            Path::new("/games/ro2/SHIPPING/Rag2.exe"),
+            Some(Path::new("/tmp/pfx")),
+            DEFAULT_DLL_OVERRIDES,
+        );
+
+        let args: Vec<_> = command.get_args().collect();
+        assert_eq!(args[0], "run");
+        let envs: Vec<_> = command.get_envs().collect();
+        assert!(envs.iter().any(|(k, v)| *k == "STEAM_COMPAT_DATA_PATH"
+            && *v == Some(Path::new("/tmp/pfx").as_os_str())));
+        assert!(envs.iter().any(|(k, _)| *k == "WINEDLLOVERRIDES"));
+    }
+}
@@ -0,0 +1,121 @@
+//! Opcode database
+//!
+//! Notes taken while browsing captures are stored per opcode in a JSON
+//! file (by default `docs/protocol/opcodes.json`) so they survive between
+//! sessions and can be reviewed alongside the protocol docs.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Default database location, relative to the repository root
+pub const DEFAULT_DB_PATH: &str = "docs/protocol/opcodes.json";
+
+/// A note attached to an opcode
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Note {
+    /// Capture frame the note was written against
+    pub frame: u32,
+    pub text: String,
+}
+
+/// Everything known about one opcode
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OpcodeEntry {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+
+    #[serde(default)]
+    pub notes: Vec<Note>,
+}
+
+/// Opcode database backed by a JSON file
+#[derive(Debug, Default)]
+pub struct OpcodeDatabase {
+    path: PathBuf,
+    opcodes: BTreeMap<String, OpcodeEntry>,
+}
+
+impl OpcodeDatabase {
+    /// Load the database, starting empty if the file doesn't exist
+    pub fn load(path: &Path) -> Result<Self> {
+        let opcodes = match fs::read_to_string(path) {
+            Ok(contents) => serde_json::from_str(&contents)
+                .with_context(|| format!("Invalid opcode database: {}", path.display()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(e).context(format!("Failed to read {}", path.display())),
+        };
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            opcodes,
+        })
+    }
+
+    /// Write the database back to disk
+    pub fn save(&self) -> Result<()> {
+        if let Some(parent) = self.path.parent()
+            && !parent.as_os_str().is_empty()
+        {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(
+            &self.path,
+            serde_json::to_string_pretty(&self.opcodes)? + "\n",
+        )
+        .with_context(|| format!("Failed to write {}", self.path.display()))
+    }
+
+    pub fn get(&self, key: &str) -> Option<&OpcodeEntry> {
+        self.opcodes.get(key)
+    }
+
+    /// Attach a note to an opcode
+    pub fn annotate(&mut self, key: &str, frame: u32, text: &str) {
+        self.opcodes
+            .entry(key.to_string())
+            .or_default()
+            .notes
+            .push(Note {
+                frame,
+                text: text.to_string(),
+            });
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+/// Database key for a frame: the game opcode if known, else the ProudNet one
+pub fn opcode_key(proudnet: Option<u8>, game: Option<u16>) -> Option<String> {
+    match (game, proudnet) {
+        (Some(game), _) => Some(format!("0x{:04X}", game)),
+        (None, Some(op)) => Some(format!("0x{:02X}", op)),
+        (None, None) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_annotate_roundtrip() {
+        let path = std::env::temp_dir().join(format!("ro2-opcodes-{}.json", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let mut db = OpcodeDatabase::load(&path).unwrap();
+        db.annotate("0x2EE2", 22, "username at offset 0");
+        db.save().unwrap();
+
+        let db = OpcodeDatabase::load(&path).unwrap();
+        assert_eq!(db.get("0x2EE2").unwrap().notes[0].frame, 22);
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(opcode_key(Some(0x25), Some(0x2EE2)).unwrap(), "0x2EE2");
+        assert_eq!(opcode_key(Some(0x25), None).unwrap(), "0x25");
+    }
+}
@@ -0,0 +1,248 @@
+//! Offline decryption of reassembled captures
+//!
+//! When we control the server we either have its RSA private key or the
+//! `AES_SESSION_KEY` line it logs after each handshake. With the private
+//! key, each connection's 0x05 is decrypted to recover that connection's
+//! session key; with an AES key, every connection uses that key. Either
+//! way, every 0x25/0x26 frame is then decrypted to the game message inside.
+
+use crate::stream::{Direction, TaggedFrame};
+use anyhow::{Context, Result, bail};
+use ro2_common::crypto::ProudNetCrypto;
+use rsa::RsaPrivateKey;
+use rsa::pkcs1::DecodeRsaPrivateKey;
+use rsa::pkcs8::DecodePrivateKey;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+/// Bytes before the ciphertext in 0x25/0x26 (opcode + 3 flag bytes)
+pub const ENCRYPTED_HEADER_LEN: usize = 4;
+
+/// Outcome of running a frame through the decryptor
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Decrypted {
+    /// 0x05 processed; the connection's session key is now known
+    SessionKey([u8; 16]),
+
+    /// Game message recovered from 0x25/0x26
+    Message { opcode: u16, data: Vec<u8> },
+
+    /// Frame should have been decryptable but wasn't
+    Failed(String),
+}
+
+/// Tracks session keys per connection and decrypts frames
+pub struct CaptureDecryptor {
+    rsa_private: Option<RsaPrivateKey>,
+    fixed_key: Option<[u8; 16]>,
+    /// Session keys by client port
+    sessions: HashMap<u16, ProudNetCrypto>,
+}
+
+impl CaptureDecryptor {
+    /// Build a decryptor from the `--rsa-key` / `--aes-key` options
+    ///
+    /// Returns `None` when neither was given.
+    pub fn from_options(rsa_key: Option<&Path>, aes_key: Option<&str>) -> Result<Option<Self>> {
+        let rsa_private = rsa_key.map(load_private_key).transpose()?;
+        let fixed_key = aes_key.map(parse_aes_key).transpose()?;
+
+        if rsa_private.is_none() && fixed_key.is_none() {
+            return Ok(None);
+        }
+
+        Ok(Some(Self {
+            rsa_private,
+            fixed_key,
+            sessions: HashMap::new(),
+        }))
+    }
+
+    /// Process one frame in capture order
+    ///
+    /// Returns `None` for frames that are neither 0x05 nor 0x25/0x26.
+    pub fn process(&mut self, frame: &TaggedFrame) -> Option<Decrypted> {
+        let payload = &frame.packet.payload;
+        let client_port = frame.stream.client_port;
+
+        match payload.first()? {
+            0x05 if frame.stream.direction == Direction::ClientToServer => {
+                let private_key = self.rsa_private.clone()?;
+                Some(self.handle_session_key(client_port, private_key, payload))
+            }
+            0x25 | 0x26 => Some(self.decrypt_message(client_port, payload)),
+            _ => None,
+        }
+    }
+
+    fn handle_session_key(
+        &mut self,
+        client_port: u16,
+        private_key: RsaPrivateKey,
+        payload: &[u8],
+    ) -> Decrypted {
+        let encrypted_key = match encrypted_session_key(payload) {
+            Ok(encrypted_key) => encrypted_key,
+            Err(e) => return Decrypted::Failed(e),
+        };
+
+        let mut crypto = ProudNetCrypto::new();
+        crypto.set_rsa_private_key(private_key);
+        if let Err(e) = crypto.decrypt_session_key_rsa(encrypted_key) {
+            return Decrypted::Failed(e.to_string());
+        }
+        let Some(key) = crypto.aes_session_key().copied() else {
+            return Decrypted::Failed(String::from("Decrypted session key shorter than 16 bytes"));
+        };
+
+        self.sessions.insert(client_port, crypto);
+        Decrypted::SessionKey(key)
+    }
+
+    fn decrypt_message(&mut self, client_port: u16, payload: &[u8]) -> Decrypted {
+        let crypto = match (self.sessions.get(&client_port), self.fixed_key) {
+            (Some(crypto), _) => crypto,
+            (None, Some(key)) => {
+                let mut crypto = ProudNetCrypto::new();
+                crypto.set_aes_session_key(key);
+                self.sessions.entry(client_port).or_insert(crypto)
+            }
+            (None, None) => {
+                return Decrypted::Failed(String::from("No session key for this connection"));
+            }
+        };
+
+        let Some(ciphertext) = payload.get(ENCRYPTED_HEADER_LEN..) else {
+            return Decrypted::Failed(String::from("Encrypted frame too short"));
+        };
+
+        match crypto.decrypt_aes_ecb(ciphertext) {
+            Ok(data) if data.len() >= 2 => Decrypted::Message {
+                opcode: u16::from_le_bytes([data[0], data[1]]),
+                data: data[2..].to_vec(),
+            },
+            Ok(data) => Decrypted::Failed(format!("Plaintext too short: {} bytes", data.len())),
+            Err(e) => Decrypted::Failed(e.to_string()),
+        }
+    }
+}
+
+/// The RSA-encrypted session key in a 0x05 payload
+pub fn encrypted_session_key(payload: &[u8]) -> Result<&[u8], String> {
+    // 05 <sub-opcode> <key_len u16> <encrypted key> ...
+    if payload.len() < 4 {
+        return Err(format!("0x05 too short: {} bytes", payload.len()));
+    }
+    let key_len = u16::from_le_bytes([payload[2], payload[3]]) as usize;
+    payload
+        .get(4..4 + key_len)
+        .ok_or_else(|| format!("0x05 truncated: key length {}", key_len))
+}
+
+/// Load an RSA private key from a PKCS#1 or PKCS#8 PEM file
+pub fn load_private_key(path: &Path) -> Result<RsaPrivateKey> {
+    let pem = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read RSA key: {}", path.display()))?;
+
+    RsaPrivateKey::from_pkcs1_pem(&pem)
+        .or_else(|_| RsaPrivateKey::from_pkcs8_pem(&pem))
+        .map_err(|e| anyhow::anyhow!("Invalid RSA private key in {}: {}", path.display(), e))
+}
+
+/// Parse a 16-byte AES key from hex (as logged by the login server)
+fn parse_aes_key(hex_key: &str) -> Result<[u8; 16]> {
+    let bytes = hex::decode(hex_key.trim()).context("AES key is not valid hex")?;
+    if bytes.len() != 16 {
+        bail!("AES key must be 16 bytes, got {}", bytes.len());
+    }
+
+    let mut key = [0u8; 16];
+    key.copy_from_slice(&bytes);
+    Ok(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stream::StreamId;
+    use ro2_common::packet::PacketFrame;
+    use ro2_common::testing::{self, Handshake};
+    use rsa::pkcs1::EncodeRsaPrivateKey;
+
+    fn tagged(direction: Direction, bytes: Vec<u8>) -> TaggedFrame {
+        TaggedFrame {
+            stream: StreamId {
+                client_port: 50123,
+                direction,
+            },
+            capture_frame: 1,
+            time: None,
+            packet: PacketFrame::from_bytes(&bytes).unwrap().0,
+        }
+    }
+
+    #[test]
+    fn test_decrypt_with_rsa_key() {
+        let handshake = Handshake::new();
+        let pem_path =
+            std::env::temp_dir().join(format!("ro2-analyzer-{}.pem", std::process::id()));
+        fs::write(
+            &pem_path,
+            handshake
+                .private_key()
+                .to_pkcs1_pem(Default::default())
+                .unwrap(),
+        )
+        .unwrap();
+        let mut decryptor = CaptureDecryptor::from_options(Some(&pem_path), None)
+            .unwrap()
+            .unwrap();
+        fs::remove_file(&pem_path).unwrap();
+
+        let result = decryptor.process(&tagged(
+            Direction::ClientToServer,
+            handshake.session_key_response(),
+        ));
+        assert_eq!(result, Some(Decrypted::SessionKey(handshake.session_key())));
+
+        let message = testing::encrypted_message(handshake.session_key(), 0x2EE2, b"user");
+        let result = decryptor.process(&tagged(Direction::ServerToClient, message));
+        assert_eq!(
+            result,
+            Some(Decrypted::Message {
+                opcode: 0x2EE2,
+                data: b"user".to_vec()
+            })
+        );
+    }
+
+    #[test]
+    fn test_decrypt_with_aes_key() {
+        let key = testing::TEST_AES_KEY;
+        let mut decryptor = CaptureDecryptor::from_options(None, Some(&hex::encode(key)))
+            .unwrap()
+            .unwrap();
+
+        let result = decryptor.process(&tagged(
+            Direction::ClientToServer,
+            testing::encrypted_message(key, 0x1001, &[1, 2, 3]),
+        ));
+        assert!(matches!(
+            result,
+            Some(Decrypted::Message { opcode: 0x1001, .. })
+        ));
+
+        assert!(
+            decryptor
+                .process(&tagged(Direction::ClientToServer, testing::heartbeat(1)))
+                .is_none()
+        );
+        assert!(CaptureDecryptor::from_options(None, Some("abcd")).is_err());
+        assert!(
+            CaptureDecryptor::from_options(None, None)
+                .unwrap()
+                .is_none()
+        );
+    }
+}
@@ -0,0 +1,195 @@
+//! Which key derivation the client uses, from a capture
+//!
+//! For each connection, decrypts the 0x05 with the server's RSA private
+//! key, picks up the client GUID from 0x07 and the server GUID from 0x0A,
+//! then runs [`derivation::identify`] on the client's first 0x25, whose
+//! opcode is known (ReqLogin on the login server). A scheme that matches
+//! on every connection is the one the client uses.
+
+use crate::decrypt::{ENCRYPTED_HEADER_LEN, encrypted_session_key};
+use crate::stream::{Direction, StreamReassembler};
+use ro2_common::crypto::ProudNetCrypto;
+use ro2_common::crypto::derivation::{self, KeyMaterial, Match};
+use rsa::RsaPrivateKey;
+use std::collections::BTreeMap;
+use zeroize::Zeroizing;
+
+/// What derive-key found on one connection
+#[derive(Debug)]
+pub struct ConnectionResult {
+    pub client_port: u16,
+    /// Capture frame of the 0x25 the schemes were tried on
+    pub capture_frame: Option<u32>,
+    /// Schemes that decrypted it, or why none could be tried
+    pub outcome: Result<Vec<Match>, String>,
+}
+
+/// What has been seen of a connection so far
+#[derive(Default)]
+struct Probe {
+    session: Option<Result<Zeroizing<Vec<u8>>, String>>,
+    client_guid: Option<[u8; 16]>,
+    server_guid: Option<[u8; 16]>,
+    result: Option<ConnectionResult>,
+}
+
+/// Try every scheme on each connection's first client 0x25, expecting
+/// its plaintext to start with `opcode`
+pub fn analyze(
+    reassembler: &StreamReassembler,
+    private_key: &RsaPrivateKey,
+    opcode: u16,
+) -> Vec<ConnectionResult> {
+    let mut probes: BTreeMap<u16, Probe> = BTreeMap::new();
+
+    for frame in reassembler.frames() {
+        let client_port = frame.stream.client_port;
+        let probe = probes.entry(client_port).or_default();
+        if probe.result.is_some() {
+            continue;
+        }
+
+        let payload = &frame.packet.payload;
+        match (frame.stream.direction, payload.first()) {
+            (Direction::ClientToServer, Some(0x05)) => {
+                probe.session = Some(decrypt_session(private_key, payload));
+            }
+            (Direction::ClientToServer, Some(0x07)) => {
+                probe.client_guid = guid(payload, 3);
+            }
+            (Direction::ServerToClient, Some(0x0A)) => {
+                probe.server_guid = guid(payload, 5);
+            }
+            (Direction::ClientToServer, Some(0x25)) => {
+                let outcome = match &probe.session {
+                    Some(Ok(session)) => {
+                        let material = KeyMaterial {
+                            session,
+                            client_guid: probe.client_guid,
+                            server_guid: probe.server_guid,
+                        };
+                        let ciphertext = payload.get(ENCRYPTED_HEADER_LEN..).unwrap_or_default();
+                        Ok(derivation::identify(
+                            &material,
+                            ciphertext,
+                            &opcode.to_le_bytes(),
+                        ))
+                    }
+                    Some(Err(e)) => Err(e.clone()),
+                    None => Err(String::from("No 0x05 before the first 0x25")),
+                };
+                probe.result = Some(ConnectionResult {
+                    client_port,
+                    capture_frame: Some(frame.capture_frame),
+                    outcome,
+                });
+            }
+            _ => {}
+        }
+    }
+
+    probes
+        .into_iter()
+        .map(|(client_port, probe)| {
+            probe.result.unwrap_or(ConnectionResult {
+                client_port,
+                capture_frame: None,
+                outcome: Err(String::from("No 0x25 from the client")),
+            })
+        })
+        .collect()
+}
+
+/// The whole RSA-decrypted 0x05 payload, not just the key the server takes
+fn decrypt_session(
+    private_key: &RsaPrivateKey,
+    payload: &[u8],
+) -> Result<Zeroizing<Vec<u8>>, String> {
+    let encrypted_key = encrypted_session_key(payload)?;
+    let mut crypto = ProudNetCrypto::new();
+    crypto.set_rsa_private_key(private_key.clone());
+    crypto
+        .decrypt_session_key_rsa(encrypted_key)
+        .map_err(|e| e.to_string())
+}
+
+fn guid(payload: &[u8], offset: usize) -> Option<[u8; 16]> {
+    payload.get(offset..offset + 16)?.try_into().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stream::reassemble;
+    use ro2_common::crypto::derivation::KeyDerivation;
+    use ro2_common::testing::{self, Handshake};
+
+    const SERVER: u16 = 7101;
+
+    /// A tshark export of `packets` on one connection
+    fn export(client_port: u16, packets: &[(Direction, Vec<u8>)]) -> String {
+        let mut seq = [1u32, 1u32];
+        packets
+            .iter()
+            .enumerate()
+            .map(|(i, (direction, bytes))| {
+                let (src, dst, seq) = match direction {
+                    Direction::ClientToServer => (client_port, SERVER, &mut seq[0]),
+                    Direction::ServerToClient => (SERVER, client_port, &mut seq[1]),
+                };
+                let line = format!(
+                    "{}\t{}\t{}\t{}\t{}",
+                    i + 1,
+                    src,
+                    dst,
+                    seq,
+                    hex::encode(bytes)
+                );
+                *seq += bytes.len() as u32;
+                line
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    #[test]
+    fn test_analyze() {
+        let handshake = Handshake::new();
+        let reassembler = reassemble(&export(50123, &handshake.sequence()), SERVER).unwrap();
+
+        let results = analyze(&reassembler, handshake.private_key(), 0x2EE2);
+        assert_eq!(results.len(), 1);
+        let matches = results[0].outcome.as_ref().unwrap();
+        // A 16-byte payload is its own first and last block
+        let derivations: Vec<_> = matches.iter().map(|m| m.derivation).collect();
+        assert_eq!(
+            derivations,
+            [
+                KeyDerivation::Raw,
+                KeyDerivation::FirstBlock,
+                KeyDerivation::LastBlock
+            ]
+        );
+        assert_eq!(matches[0].key, handshake.session_key());
+
+        let results = analyze(&reassembler, handshake.private_key(), 0x1001);
+        assert!(results[0].outcome.as_ref().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_analyze_without_session_key() {
+        let message = testing::encrypted_message(testing::TEST_AES_KEY, 0x2EE2, b"user");
+        let reassembler = reassemble(
+            &export(50124, &[(Direction::ClientToServer, message)]),
+            SERVER,
+        )
+        .unwrap();
+        let handshake = Handshake::new();
+
+        let results = analyze(&reassembler, handshake.private_key(), 0x2EE2);
+        assert_eq!(
+            results[0].outcome.as_ref().unwrap_err(),
+            "No 0x05 before the first 0x25"
+        );
+    }
+}
@@ -0,0 +1,104 @@
+//! Live `listen` mode
+//!
+//! Accepts real clients and serves them through the same
+//! [`ProudNetConnection`] loop as the login server, so there is one
+//! implementation of the handshake. Every frame is logged with its hex
+//! dump and decrypted game messages are decoded by name. With `--forward`,
+//! game messages are answered by the login server's handlers; otherwise
+//! they are only logged.
+
+use anyhow::{Context, Result};
+use ro2_common::crypto::ProudNetCrypto;
+use ro2_common::net::{Direction, FrameObserver, ProudNetConnection};
+use ro2_common::protocol::{MessageDispatcher, ProudNetHandler, ProudNetSettings};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use tokio::net::TcpListener;
+
+/// Accept clients on `bind:port` until interrupted
+pub async fn run(bind: IpAddr, port: u16, forward: bool) -> Result<()> {
+    println!("Generating RSA-1024 keypair...");
+    let mut crypto = ProudNetCrypto::new();
+    crypto.generate_rsa_keypair(1024)?;
+    let crypto = Arc::new(crypto);
+
+    let listener = TcpListener::bind((bind, port))
+        .await
+        .with_context(|| format!("Failed to bind {}:{}", bind, port))?;
+    println!(
+        "Listening on {} ({})",
+        listener.local_addr()?,
+        if forward {
+            "forwarding to login handlers"
+        } else {
+            "log only"
+        }
+    );
+
+    loop {
+        let (socket, addr) = listener.accept().await?;
+        println!("🔌 [{}] Connected", addr);
+
+        let handler =
+            ProudNetHandler::with_shared_crypto(addr, ProudNetSettings::default(), crypto.clone());
+        tokio::spawn(async move {
+            let mut dispatcher = if forward {
+                ro2_login::dispatcher()
+            } else {
+                MessageDispatcher::new()
+            };
+            let mut connection = ProudNetConnection::new(socket, addr, handler)
+                .with_observer(Logger { addr })
+                .keep_open_on_bad_key(true);
+
+            match connection.run(&mut dispatcher).await {
+                Ok(()) => println!("🔌 [{}] Disconnected", addr),
+                Err(e) => println!("❌ [{}] Connection error: {:#}", addr, e),
+            }
+        });
+    }
+}
+
+/// Prints all traffic of one connection
+struct Logger {
+    addr: SocketAddr,
+}
+
+impl FrameObserver for Logger {
+    fn on_frame(&mut self, direction: Direction, payload: &[u8]) {
+        if payload.starts_with(b"<") {
+            println!(
+                "\n[{}] {} policy XML ({} bytes)",
+                self.addr,
+                direction,
+                payload.len()
+            );
+            return;
+        }
+
+        let opcode = payload.first().copied().unwrap_or(0);
+        println!(
+            "\n[{}] {} 0x{:02X} ({} bytes)",
+            self.addr,
+            direction,
+            opcode,
+            payload.len()
+        );
+        // Encrypted frames are printed as plaintext by `on_message`
+        if !matches!(opcode, 0x25 | 0x26) {
+            crate::print_hex_dump(payload);
+        }
+    }
+
+    fn on_message(&mut self, direction: Direction, message: &[u8]) {
+        let opcode = u16::from_le_bytes([message[0], message[1]]);
+        println!(
+            "[{}] {} game message 0x{:04X} ({} bytes)",
+            self.addr,
+            direction,
+            opcode,
+            message.len() - 2
+        );
+        crate::print_game_message(opcode, &message[2..]);
+    }
+}
@@ -1,8 +1,25 @@
+mod annotations;
+mod decrypt;
+mod derive_key;
+mod listen;
+mod proxy;
+mod report;
+mod stream;
+mod tui;
+mod validate;
+
 use anyhow::{Context, Result};
-use clap::{Parser, Subcommand};
+use clap::{Args, Parser, Subcommand};
+use report::{CaptureReport, OutputFormat};
+use ro2_common::crypto::derivation::KeyDerivation;
+use ro2_common::protocol::{opcodes, schema};
 use std::fs;
+use std::net::{IpAddr, SocketAddr};
 use std::path::PathBuf;
 
+/// Login server port, used to tell client and server segments apart
+const DEFAULT_SERVER_PORT: u16 = 7101;
+
 #[derive(Parser)]
 #[command(name = "packet-analyzer")]
 #[command(about = "Analyze RO2 ProudNet packet captures", long_about = None)]
@@ -24,10 +41,131 @@ enum Commands {
         #[arg(short, long)]
         data: String,
     },
+    /// Decode a decrypted game message (u16 opcode + payload) by field name
+    Decode {
+        /// Hex string starting with the little-endian opcode
+        #[arg(short, long)]
+        data: String,
+    },
+    /// Reassemble TCP streams from a tshark segment export
+    ///
+    /// Expects tab-separated `frame.number tcp.srcport tcp.dstport tcp.seq data`
+    /// lines, see `src/stream.rs` for the tshark command.
+    Capture {
+        #[command(flatten)]
+        input: CaptureInput,
+
+        /// Output format
+        #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+        output: OutputFormat,
+    },
+    /// Browse a capture interactively and annotate opcodes
+    Tui {
+        #[command(flatten)]
+        input: CaptureInput,
+
+        /// Opcode database that annotations are saved to
+        #[arg(long, default_value = annotations::DEFAULT_DB_PATH)]
+        db: PathBuf,
+    },
+    /// Check each connection follows the expected handshake sequence
+    Validate {
+        #[command(flatten)]
+        input: CaptureInput,
+
+        /// Report gaps longer than this many seconds (needs timestamps)
+        #[arg(long, default_value_t = 5.0)]
+        gap: f64,
+    },
+    /// Find how the client derives its AES key from the 0x05 payload
+    ///
+    /// Tries each candidate scheme on every connection's first client
+    /// 0x25, whose opcode must be known.
+    DeriveKey {
+        /// Path to the segment export
+        path: PathBuf,
+
+        /// Server port (segments from this port are server→client)
+        #[arg(long, default_value_t = DEFAULT_SERVER_PORT)]
+        server_port: u16,
+
+        /// Server RSA private key (PEM) used to decrypt each 0x05
+        #[arg(long, value_name = "PEM")]
+        rsa_key: PathBuf,
+
+        /// Opcode the first client message starts with (hex; ReqLogin if
+        /// not given)
+        #[arg(long, value_parser = parse_opcode)]
+        opcode: Option<u16>,
+    },
+    /// Accept live clients, run the handshake and log all traffic
+    Listen {
+        /// Port to listen on
+        #[arg(long, default_value_t = DEFAULT_SERVER_PORT)]
+        port: u16,
+
+        /// Address to bind
+        #[arg(long, default_value = "0.0.0.0")]
+        bind: IpAddr,
+
+        /// Answer game messages with the login server's handlers
+        #[arg(long)]
+        forward: bool,
+    },
+    /// Sit between a client and another server, decrypting both directions
+    Proxy {
+        /// Port to accept the client on
+        #[arg(long, default_value_t = DEFAULT_SERVER_PORT)]
+        listen: u16,
+
+        /// Address to bind
+        #[arg(long, default_value = "0.0.0.0")]
+        bind: IpAddr,
+
+        /// Server to forward to, e.g. an official login server
+        #[arg(long, value_name = "HOST:PORT")]
+        upstream: String,
+
+        /// Replace a game message's body, e.g. `2EE2=0100ff` (repeatable)
+        #[arg(long, value_name = "OPCODE=HEX")]
+        rewrite: Vec<proxy::Rewrite>,
+    },
     /// Interactive mode - paste hex and analyze
     Interactive,
 }
 
+/// Options shared by the commands that read a tshark segment export
+#[derive(Args)]
+struct CaptureInput {
+    /// Path to the segment export
+    path: PathBuf,
+
+    /// Server port (segments from this port are server→client)
+    #[arg(long, default_value_t = DEFAULT_SERVER_PORT)]
+    server_port: u16,
+
+    /// Server RSA private key (PEM) used to decrypt each 0x05 session key
+    #[arg(long, value_name = "PEM")]
+    rsa_key: Option<PathBuf>,
+
+    /// AES session key (hex), e.g. from the server's AES_SESSION_KEY log line
+    #[arg(long, value_name = "HEX")]
+    aes_key: Option<String>,
+}
+
+impl CaptureInput {
+    /// Reassemble the export and set up decryption if a key was given
+    fn open(&self) -> Result<(stream::StreamReassembler, Option<decrypt::CaptureDecryptor>)> {
+        let content = fs::read_to_string(&self.path)
+            .with_context(|| format!("Failed to read file: {:?}", self.path))?;
+        let decryptor = decrypt::CaptureDecryptor::from_options(
+            self.rsa_key.as_deref(),
+            self.aes_key.as_deref(),
+        )?;
+        Ok((stream::reassemble(&content, self.server_port)?, decryptor))
+    }
+}
+
 fn main() -> Result<()> {
     let cli = Cli::parse();
 
@@ -41,6 +179,60 @@ fn main() -> Result<()> {
             let bytes = parse_hex_string(&data)?;
             analyze_packet(&bytes)?;
         }
+        Commands::Decode { data } => {
+            let bytes = parse_hex_string(&data)?;
+            if bytes.len() < 2 {
+                anyhow::bail!("Game message needs at least a 2-byte opcode");
+            }
+            let opcode = u16::from_le_bytes([bytes[0], bytes[1]]);
+            println!("Game message 0x{:04X} ({} bytes)", opcode, bytes.len() - 2);
+            print_game_message(opcode, &bytes[2..]);
+        }
+        Commands::Capture { input, output } => {
+            let (reassembler, decryptor) = input.open()?;
+            let report = CaptureReport::build(&reassembler, input.server_port, decryptor);
+            print_capture(&report, output)?;
+        }
+        Commands::Tui { input, db } => {
+            let (reassembler, decryptor) = input.open()?;
+            let report = CaptureReport::build(&reassembler, input.server_port, decryptor);
+            tui::run(report, annotations::OpcodeDatabase::load(&db)?)?;
+        }
+        Commands::Validate { input, gap } => {
+            let (reassembler, decryptor) = input.open()?;
+            let connections = validate::validate(&reassembler, decryptor, gap);
+            print_validation(&connections, input.server_port)?;
+        }
+        Commands::DeriveKey {
+            path,
+            server_port,
+            rsa_key,
+            opcode,
+        } => {
+            let content = fs::read_to_string(&path)
+                .with_context(|| format!("Failed to read file: {:?}", path))?;
+            let reassembler = stream::reassemble(&content, server_port)?;
+            let private_key = decrypt::load_private_key(&rsa_key)?;
+            let opcode = opcode.unwrap_or(opcodes::REQ_LOGIN);
+            let results = derive_key::analyze(&reassembler, &private_key, opcode);
+            print_derivations(&results, opcode)?;
+        }
+        Commands::Listen {
+            port,
+            bind,
+            forward,
+        } => {
+            tokio::runtime::Runtime::new()?.block_on(listen::run(bind, port, forward))?;
+        }
+        Commands::Proxy {
+            listen,
+            bind,
+            upstream,
+            rewrite,
+        } => {
+            let listen = SocketAddr::new(bind, listen);
+            tokio::runtime::Runtime::new()?.block_on(proxy::run(listen, upstream, rewrite))?;
+        }
         Commands::Interactive => {
             interactive_mode()?;
         }
@@ -74,6 +266,191 @@ fn analyze_hex_dump(content: &str) -> Result<()> {
     Ok(())
 }
 
+fn print_capture(report: &CaptureReport, output: OutputFormat) -> Result<()> {
+    match output {
+        OutputFormat::Text => print_capture_report(report),
+        OutputFormat::Json => println!("{}", report.to_json()?),
+        OutputFormat::Csv => print!("{}", report.to_csv()),
+        OutputFormat::Md => print!("{}", report.to_markdown()),
+    }
+
+    Ok(())
+}
+
+fn print_capture_report(report: &CaptureReport) {
+    println!(
+        "=== Reassembling TCP Streams (server port {}) ===\n",
+        report.server_port
+    );
+
+    for frame in &report.frames {
+        let opcode = frame
+            .opcode
+            .map(|op| format!("0x{:02X}", op))
+            .unwrap_or_else(|| String::from("--"));
+        println!(
+            "#{:<6} {} :{:<5} opcode {} ({} bytes)",
+            frame.frame, frame.direction, frame.client_port, opcode, frame.length
+        );
+        print_hex_dump(&frame.payload);
+
+        if let Some(opcode) = frame.game_opcode {
+            println!(
+                "  🔓 Game message 0x{:04X} ({} bytes)",
+                opcode,
+                frame.decrypted.len()
+            );
+            print_game_message(opcode, &frame.decrypted);
+        } else if let Some(note) = &frame.note {
+            println!("  {}", note);
+        }
+        println!();
+    }
+
+    let s = &report.summary;
+    println!("=== Summary ===\n");
+    println!("Segments:       {}", s.segments);
+    println!("Frames:         {}", s.frames);
+    println!("Retransmits:    {}", s.retransmits);
+    println!("Out of order:   {}", s.out_of_order);
+    println!("Skipped bytes:  {}", s.skipped_bytes);
+    if report.decryption {
+        println!("Session keys:   {}", s.session_keys);
+        println!("Decrypted:      {}", s.decrypted);
+        println!("Failed:         {}", s.decrypt_failures);
+    }
+    for (direction, client_port, bytes) in &s.leftover {
+        println!(
+            "⚠️  {} :{} ended with {} bytes of incomplete frame",
+            direction, client_port, bytes
+        );
+    }
+}
+
+fn print_derivations(results: &[derive_key::ConnectionResult], opcode: u16) -> Result<()> {
+    println!(
+        "=== Key Derivation (first client message 0x{:04X}) ===\n",
+        opcode
+    );
+
+    if results.is_empty() {
+        anyhow::bail!("No frames found in capture");
+    }
+
+    let mut tally: Vec<(KeyDerivation, usize)> =
+        KeyDerivation::ALL.iter().map(|&d| (d, 0)).collect();
+    for result in results {
+        let frame = result
+            .capture_frame
+            .map_or(String::new(), |frame| format!(" (#{})", frame));
+        println!("Connection :{}{}", result.client_port, frame);
+        match &result.outcome {
+            Ok(matches) if matches.is_empty() => println!("  ⚠️  No scheme decrypted it"),
+            Ok(matches) => {
+                for m in matches {
+                    println!("  ✓ {:<16} key {}", m.derivation, hex::encode(m.key));
+                    if let Some((_, count)) = tally.iter_mut().find(|(d, _)| *d == m.derivation) {
+                        *count += 1;
+                    }
+                }
+            }
+            Err(e) => println!("  ⚠️  {}", e),
+        }
+    }
+
+    println!();
+    for (derivation, count) in tally {
+        println!(
+            "{:<16} matched {} of {} connections",
+            derivation,
+            count,
+            results.len()
+        );
+    }
+    Ok(())
+}
+
+fn parse_opcode(s: &str) -> Result<u16> {
+    let hex = s.trim_start_matches("0x").trim_start_matches("0X");
+    u16::from_str_radix(hex, 16).with_context(|| format!("invalid opcode `{}`", s))
+}
+
+fn print_validation(connections: &[validate::ConnectionReport], server_port: u16) -> Result<()> {
+    println!(
+        "=== Validating Handshakes (server port {}) ===\n",
+        server_port
+    );
+
+    if connections.is_empty() {
+        anyhow::bail!("No frames found in capture");
+    }
+
+    let mut failed = 0;
+    for connection in connections {
+        println!("Connection :{}", connection.client_port);
+        let start = connection.events.iter().find_map(|e| e.time);
+        for event in &connection.events {
+            let time = match (event.time, start) {
+                (Some(t), Some(start)) => format!("{:>9.3}s", t - start),
+                _ => format!("{:>10}", ""),
+            };
+            println!("  {}  {}", time, event);
+        }
+
+        if connection.issues.is_empty() {
+            println!("  ✓ Handshake complete");
+        } else {
+            failed += 1;
+            for issue in &connection.issues {
+                println!("  ⚠️  {}", issue);
+            }
+            if let Some(last) = connection.events.last() {
+                println!("  Last frame: {}", last);
+            }
+        }
+        println!();
+    }
+
+    if failed > 0 {
+        anyhow::bail!(
+            "{} of {} connections failed validation",
+            failed,
+            connections.len()
+        );
+    }
+    Ok(())
+}
+
+/// Print a game message payload, decoded by name when its layout is known
+fn print_game_message(opcode: u16, data: &[u8]) {
+    let info = opcodes::info(opcode);
+    if let Some(size) = info.and_then(|info| info.size)
+        && size != data.len()
+    {
+        println!("  ⚠️  Expected {} payload bytes, got {}", size, data.len());
+    }
+    let Some(schema) = schema::lookup(opcode) else {
+        if let Some(info) = info {
+            println!("  {} ({}):", info.name, info.description);
+        }
+        print_hex_dump(data);
+        return;
+    };
+
+    let decoded = schema.decode(data);
+    println!("  {}:", schema.name);
+    for (name, value) in &decoded.fields {
+        println!("    {:<20} {}", name, value);
+    }
+    if let Some(field) = decoded.truncated_at {
+        println!("    ⚠️  Payload ends before field `{}`", field);
+    }
+    if !decoded.trailing.is_empty() {
+        println!("    ⚠️  {} unknown trailing bytes:", decoded.trailing.len());
+        print_hex_dump(&decoded.trailing);
+    }
+}
+
 fn extract_hex_from_line(line: &str) -> Option<String> {
     // Match Wireshark format: "0000  50 52 4f 55 ..."
     // Skip lines that don't start with hex offset
@@ -203,41 +580,48 @@ fn analyze_packet(bytes: &[u8]) -> Result<()> {
 }
 
 fn print_hex_dump(bytes: &[u8]) {
-    for (i, chunk) in bytes.chunks(16).enumerate() {
-        print!("{:04X}  ", i * 16);
-
-        // Hex bytes
-        for (j, byte) in chunk.iter().enumerate() {
-            print!("{:02X} ", byte);
-            if j == 7 {
-                print!(" ");
-            }
-        }
+    for line in hex_dump_lines(bytes) {
+        println!("{}", line);
+    }
+}
 
-        // Padding if last line is short
-        if chunk.len() < 16 {
-            for j in chunk.len()..16 {
-                print!("   ");
+/// Format bytes as offset / hex / ASCII lines, 16 bytes per line
+fn hex_dump_lines(bytes: &[u8]) -> Vec<String> {
+    use std::fmt::Write as _;
+
+    bytes
+        .chunks(16)
+        .enumerate()
+        .map(|(i, chunk)| {
+            let mut line = format!("{:04X}  ", i * 16);
+
+            // Hex bytes, padded if the last line is short
+            for j in 0..16 {
+                match chunk.get(j) {
+                    Some(byte) => {
+                        let _ = write!(line, "{:02X} ", byte);
+                    }
+                    None => line.push_str("   "),
+                }
                 if j == 7 {
-                    print!(" ");
+                    line.push(' ');
                 }
             }
-        }
 
-        print!(" ");
+            line.push(' ');
 
-        // ASCII representation
-        for byte in chunk {
-            let c = if byte.is_ascii_graphic() || *byte == b' ' {
-                *byte as char
-            } else {
-                '.'
-            };
-            print!("{}", c);
-        }
+            // ASCII representation
+            for byte in chunk {
+                line.push(if byte.is_ascii_graphic() || *byte == b' ' {
+                    *byte as char
+                } else {
+                    '.'
+                });
+            }
 
-        println!();
-    }
+            line
+        })
+        .collect()
 }
 
 fn analyze_payload(payload: &[u8], message_id: u16) {
@@ -250,11 +634,11 @@ fn analyze_payload(payload: &[u8], message_id: u16) {
     for &byte in payload {
         if byte == 0 {
             if !current_string.is_empty() {
-                if let Ok(s) = String::from_utf8(current_string.clone()) {
-                    if s.len() >= 3 && s.chars().all(|c| c.is_ascii_graphic() || c.is_whitespace())
-                    {
-                        potential_strings.push(s);
-                    }
+                if let Ok(s) = String::from_utf8(current_string.clone())
+                    && s.len() >= 3
+                    && s.chars().all(|c| c.is_ascii_graphic() || c.is_whitespace())
+                {
+                    potential_strings.push(s);
                 }
                 current_string.clear();
             }
@@ -276,15 +660,16 @@ fn analyze_payload(payload: &[u8], message_id: u16) {
     // Check for length-prefixed strings (common in ProudNet)
     if payload.len() >= 4 {
         let len1 = u32::from_le_bytes([payload[0], payload[1], payload[2], payload[3]]);
-        if len1 > 0 && len1 < 256 && (4 + len1 as usize) <= payload.len() {
-            if let Ok(s) = String::from_utf8(payload[4..(4 + len1 as usize)].to_vec()) {
-                if s.chars().all(|c| c.is_ascii_graphic() || c.is_whitespace()) {
-                    println!("Length-prefixed string detected at offset 0:");
-                    println!("  Length: {} bytes", len1);
-                    println!("  String: \"{}\"", s);
-                    println!();
-                }
-            }
+        if len1 > 0
+            && len1 < 256
+            && (4 + len1 as usize) <= payload.len()
+            && let Ok(s) = String::from_utf8(payload[4..(4 + len1 as usize)].to_vec())
+            && s.chars().all(|c| c.is_ascii_graphic() || c.is_whitespace())
+        {
+            println!("Length-prefixed string detected at offset 0:");
+            println!("  Length: {} bytes", len1);
+            println!("  String: \"{}\"", s);
+            println!();
         }
     }
 
@@ -0,0 +1,469 @@
+//! MITM `proxy` mode
+//!
+//! Sits between a client and another server (the official one, or ours).
+//! The client is shown our RSA key instead of the upstream's; its 0x05
+//! session key is decrypted and a second session key is sent upstream, so
+//! every 0x25/0x26 can be decrypted, logged, optionally rewritten and
+//! re-encrypted for the other side. All other frames, and the unframed
+//! policy XML, pass through untouched.
+
+use anyhow::{Context, Result, anyhow, bail};
+use ro2_common::crypto::ProudNetCrypto;
+use ro2_common::net::{Chunk, Direction, FrameBuffer};
+use ro2_common::packet::PacketFrame;
+use rsa::pkcs1::EncodeRsaPublicKey;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+/// Replace the body of every game message with this opcode
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rewrite {
+    pub opcode: u16,
+    pub body: Vec<u8>,
+}
+
+impl FromStr for Rewrite {
+    type Err = anyhow::Error;
+
+    /// Parses `OPCODE=HEX`, e.g. `2EE2=0100ff`
+    fn from_str(s: &str) -> Result<Self> {
+        let (opcode, body) = s
+            .split_once('=')
+            .ok_or_else(|| anyhow!("expected OPCODE=HEX, got `{}`", s))?;
+        let opcode = opcode.trim_start_matches("0x").trim_start_matches("0X");
+        Ok(Self {
+            opcode: u16::from_str_radix(opcode, 16)
+                .with_context(|| format!("invalid opcode `{}`", opcode))?,
+            body: hex::decode(body.replace(' ', "")).context("invalid body hex")?,
+        })
+    }
+}
+
+/// A frame after passing through the proxy
+#[derive(Debug)]
+pub struct Relayed {
+    /// Bytes to send to the other side
+    pub bytes: Vec<u8>,
+    /// Decrypted game message (u16 opcode + payload), after any rewrite
+    pub message: Option<Vec<u8>>,
+    /// What the proxy changed, if anything
+    pub note: Option<String>,
+}
+
+impl Relayed {
+    fn unchanged(raw: &[u8]) -> Self {
+        Self {
+            bytes: raw.to_vec(),
+            message: None,
+            note: None,
+        }
+    }
+}
+
+/// Key exchange and re-encryption state of one proxied connection
+pub struct Session {
+    /// Our keypair, then the client's session key
+    client_side: ProudNetCrypto,
+    /// The upstream's public key, then our session key
+    upstream_side: ProudNetCrypto,
+    rewrites: Vec<Rewrite>,
+}
+
+impl Session {
+    /// `keypair` is the RSA keypair shown to the client
+    pub fn new(keypair: &ProudNetCrypto, rewrites: Vec<Rewrite>) -> Self {
+        Self {
+            client_side: keypair.clone(),
+            upstream_side: ProudNetCrypto::new(),
+            rewrites,
+        }
+    }
+
+    /// Translate a frame from `direction`'s sender for the other side
+    pub fn relay(
+        &mut self,
+        direction: Direction,
+        frame: &PacketFrame,
+        raw: &[u8],
+    ) -> Result<Relayed> {
+        match (direction, frame.opcode()) {
+            (Direction::ServerToClient, Some(0x04)) => self.swap_public_key(frame, raw),
+            (Direction::ClientToServer, Some(0x05)) => self.swap_session_key(frame, raw),
+            (_, Some(0x25 | 0x26)) => self.reencrypt(direction, frame, raw),
+            _ => Ok(Relayed::unchanged(raw)),
+        }
+    }
+
+    /// 0x04: remember the upstream's key and show the client ours
+    fn swap_public_key(&mut self, frame: &PacketFrame, raw: &[u8]) -> Result<Relayed> {
+        // Opcode, 10 u32 settings, u16 DER length, DER, anything after
+        const DER_OFFSET: usize = 1 + 40 + 2;
+        let payload = &frame.payload;
+        if payload.len() < DER_OFFSET {
+            bail!("0x04 too short: {} bytes", payload.len());
+        }
+        let der_len = u16::from_le_bytes([payload[41], payload[42]]) as usize;
+        let der = payload
+            .get(DER_OFFSET..DER_OFFSET + der_len)
+            .ok_or_else(|| anyhow!("0x04 truncated: DER length {}", der_len))?;
+        self.upstream_side.set_rsa_public_key_from_der(der)?;
+
+        let ours = self
+            .client_side
+            .rsa_public_key()
+            .ok_or_else(|| anyhow!("proxy has no RSA key"))?
+            .to_pkcs1_der()?;
+        let ours = ours.as_bytes();
+
+        let mut rewritten = payload[..41].to_vec();
+        rewritten.extend_from_slice(&(ours.len() as u16).to_le_bytes());
+        rewritten.extend_from_slice(ours);
+        rewritten.extend_from_slice(&payload[DER_OFFSET + der_len..]);
+
+        Ok(Relayed {
+            bytes: reframe_like(raw, &rewritten),
+            message: None,
+            note: Some(format!(
+                "replaced upstream RSA key ({} byte DER) with ours",
+                der_len
+            )),
+        })
+    }
+
+    /// 0x05: take the client's session key and send our own upstream
+    fn swap_session_key(&mut self, frame: &PacketFrame, raw: &[u8]) -> Result<Relayed> {
+        let payload = &frame.payload;
+        if payload.len() < 4 {
+            bail!("0x05 too short: {} bytes", payload.len());
+        }
+        let key_len = u16::from_le_bytes([payload[2], payload[3]]) as usize;
+        let encrypted = payload
+            .get(4..4 + key_len)
+            .ok_or_else(|| anyhow!("0x05 truncated: key length {}", key_len))?;
+
+        let client_key = self.client_side.decrypt_session_key_rsa(encrypted)?;
+        let upstream_key = self.upstream_side.generate_aes_session_key();
+        let encrypted = self.upstream_side.encrypt_session_key_rsa(&upstream_key)?;
+
+        // Trailing bytes are passed on as-is, their meaning is unknown
+        let mut rewritten = payload[..2].to_vec();
+        rewritten.extend_from_slice(&(encrypted.len() as u16).to_le_bytes());
+        rewritten.extend_from_slice(&encrypted);
+        rewritten.extend_from_slice(&payload[4 + key_len..]);
+
+        Ok(Relayed {
+            bytes: reframe_like(raw, &rewritten),
+            message: None,
+            note: Some(format!(
+                "client key {}, upstream key {}",
+                hex::encode(&client_key[..client_key.len().min(16)]),
+                hex::encode(upstream_key)
+            )),
+        })
+    }
+
+    /// 0x25/0x26: decrypt with the sender's key, re-encrypt with the
+    /// receiver's
+    fn reencrypt(
+        &mut self,
+        direction: Direction,
+        frame: &PacketFrame,
+        raw: &[u8],
+    ) -> Result<Relayed> {
+        let (from, to) = match direction {
+            Direction::ClientToServer => (&self.client_side, &self.upstream_side),
+            Direction::ServerToClient => (&self.upstream_side, &self.client_side),
+        };
+        if from.aes_session_key().is_none() || to.aes_session_key().is_none() {
+            return Ok(Relayed {
+                note: Some("no session key yet, passed through".to_string()),
+                ..Relayed::unchanged(raw)
+            });
+        }
+
+        let mut message = from.decrypt_packet_0x25(&frame.payload)?;
+        if message.len() < 2 {
+            bail!("decrypted message too short: {} bytes", message.len());
+        }
+
+        let opcode = u16::from_le_bytes([message[0], message[1]]);
+        let mut note = None;
+        if let Some(rewrite) = self.rewrites.iter().find(|r| r.opcode == opcode) {
+            message.truncate(2);
+            message.extend_from_slice(&rewrite.body);
+            note = Some(format!("rewrote body to {} bytes", rewrite.body.len()));
+        }
+
+        // Keep the sender's flag bytes
+        let mut payload = frame.payload[..4].to_vec();
+        payload.extend(to.encrypt_aes_ecb(&message)?);
+
+        Ok(Relayed {
+            bytes: reframe_like(raw, &payload),
+            message: Some(message),
+            note,
+        })
+    }
+}
+
+/// Frame `payload` with the same size-field width as `raw`, since the
+/// captures show fixed widths (e.g. 2 bytes for 0x04) regardless of size
+fn reframe_like(raw: &[u8], payload: &[u8]) -> Vec<u8> {
+    let mut bytes = raw[..2].to_vec();
+    match raw[2] {
+        1 if payload.len() <= u8::MAX as usize => {
+            bytes.push(1);
+            bytes.push(payload.len() as u8);
+        }
+        1 | 2 if payload.len() <= u16::MAX as usize => {
+            bytes.push(2);
+            bytes.extend_from_slice(&(payload.len() as u16).to_le_bytes());
+        }
+        _ => {
+            bytes.push(4);
+            bytes.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        }
+    }
+    bytes.extend_from_slice(payload);
+    bytes
+}
+
+/// Accept clients on `listen` and proxy each one to `upstream`
+pub async fn run(listen: SocketAddr, upstream: String, rewrites: Vec<Rewrite>) -> Result<()> {
+    println!("Generating RSA-1024 keypair...");
+    let mut keypair = ProudNetCrypto::new();
+    keypair.generate_rsa_keypair(1024)?;
+    let keypair = Arc::new(keypair);
+
+    let listener = TcpListener::bind(listen)
+        .await
+        .with_context(|| format!("Failed to bind {}", listen))?;
+    println!("Proxying {} -> {}", listener.local_addr()?, upstream);
+    for rewrite in &rewrites {
+        println!(
+            "  rewriting 0x{:04X} bodies to {}",
+            rewrite.opcode,
+            hex::encode(&rewrite.body)
+        );
+    }
+
+    loop {
+        let (client, addr) = listener.accept().await?;
+        println!("🔌 [{}] Connected", addr);
+
+        let session = Session::new(&keypair, rewrites.clone());
+        let upstream = upstream.clone();
+        tokio::spawn(async move {
+            match proxy_connection(client, addr, &upstream, session).await {
+                Ok(()) => println!("🔌 [{}] Disconnected", addr),
+                Err(e) => println!("❌ [{}] Proxy error: {:#}", addr, e),
+            }
+        });
+    }
+}
+
+async fn proxy_connection(
+    client: TcpStream,
+    addr: SocketAddr,
+    upstream: &str,
+    mut session: Session,
+) -> Result<()> {
+    let server = TcpStream::connect(upstream)
+        .await
+        .with_context(|| format!("Failed to connect to {}", upstream))?;
+    let (mut client_read, mut client_write) = client.into_split();
+    let (mut server_read, mut server_write) = server.into_split();
+
+    let mut from_client = FrameBuffer::new();
+    let mut from_server = FrameBuffer::new();
+    let mut client_buf = vec![0u8; 4096];
+    let mut server_buf = vec![0u8; 4096];
+
+    loop {
+        tokio::select! {
+            n = client_read.read(&mut client_buf) => {
+                let n = n?;
+                if n == 0 {
+                    return Ok(());
+                }
+                from_client.extend(&client_buf[..n]);
+                forward(Direction::ClientToServer, addr, &mut session, &mut from_client, &mut server_write).await?;
+            }
+            n = server_read.read(&mut server_buf) => {
+                let n = n?;
+                if n == 0 {
+                    println!("🔌 [{}] Upstream closed the connection", addr);
+                    return Ok(());
+                }
+                from_server.extend(&server_buf[..n]);
+                forward(Direction::ServerToClient, addr, &mut session, &mut from_server, &mut client_write).await?;
+            }
+        }
+    }
+}
+
+/// Relay every complete chunk in `buffer` to `to`, logging as we go
+async fn forward<W: AsyncWrite + Unpin>(
+    direction: Direction,
+    addr: SocketAddr,
+    session: &mut Session,
+    buffer: &mut FrameBuffer,
+    to: &mut W,
+) -> Result<()> {
+    while let Some(chunk) = buffer.next_chunk() {
+        let bytes = match chunk {
+            Chunk::Unframed(data) => {
+                println!("\n[{}] {} unframed ({} bytes)", addr, direction, data.len());
+                if !data.starts_with(b"<") {
+                    crate::print_hex_dump(&data);
+                }
+                data
+            }
+            Chunk::Frame { frame, raw } => {
+                let opcode = frame.opcode().unwrap_or(0);
+                println!(
+                    "\n[{}] {} 0x{:02X} ({} bytes)",
+                    addr,
+                    direction,
+                    opcode,
+                    frame.payload.len()
+                );
+
+                let relayed = session.relay(direction, &frame, &raw).unwrap_or_else(|e| {
+                    println!("  ⚠️  {:#}, passed through", e);
+                    Relayed::unchanged(&raw)
+                });
+                if let Some(note) = &relayed.note {
+                    println!("  ✏️  {}", note);
+                }
+                match &relayed.message {
+                    Some(message) => {
+                        let game_opcode = u16::from_le_bytes([message[0], message[1]]);
+                        println!(
+                            "  game message 0x{:04X} ({} bytes)",
+                            game_opcode,
+                            message.len() - 2
+                        );
+                        crate::print_game_message(game_opcode, &message[2..]);
+                    }
+                    None if !matches!(opcode, 0x25 | 0x26) => crate::print_hex_dump(&frame.payload),
+                    None => {}
+                }
+                relayed.bytes
+            }
+        };
+
+        to.write_all(&bytes).await?;
+    }
+    to.flush().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ro2_common::protocol::{ProudNetHandler, ProudNetSettings, Reliability};
+    use ro2_common::testing::{self, Handshake};
+
+    fn parse(bytes: &[u8]) -> (PacketFrame, Vec<u8>) {
+        let (frame, size) = PacketFrame::from_bytes(bytes).unwrap();
+        (frame, bytes[..size].to_vec())
+    }
+
+    fn relay(session: &mut Session, direction: Direction, bytes: &[u8]) -> Relayed {
+        let (frame, raw) = parse(bytes);
+        session.relay(direction, &frame, &raw).unwrap()
+    }
+
+    #[test]
+    fn test_parse_rewrite() {
+        assert_eq!(
+            "0x2EE2=01 ff".parse::<Rewrite>().unwrap(),
+            Rewrite {
+                opcode: 0x2EE2,
+                body: vec![0x01, 0xFF]
+            }
+        );
+        assert!("2EE2".parse::<Rewrite>().is_err());
+    }
+
+    #[test]
+    fn test_handshake_and_reencryption() {
+        // Upstream server with its own keypair
+        let upstream = Handshake::new();
+        let mut server = ProudNetHandler::with_shared_crypto(
+            "127.0.0.1:50000".parse().unwrap(),
+            ProudNetSettings::default(),
+            upstream.server_crypto(),
+        );
+
+        let mut keypair = ProudNetCrypto::new();
+        keypair.generate_rsa_keypair(1024).unwrap();
+        let rewrite = Rewrite {
+            opcode: 0x1001,
+            body: vec![0xAB],
+        };
+        let mut session = Session::new(&keypair, vec![rewrite]);
+
+        // The client sees our key in the 0x04, with the framing kept
+        let handshake = upstream.encryption_handshake();
+        let relayed = relay(&mut session, Direction::ServerToClient, &handshake);
+        assert_eq!(&relayed.bytes[..3], &handshake[..3]);
+        let (frame, _) = parse(&relayed.bytes);
+        let ours = keypair.rsa_public_key().unwrap().to_pkcs1_der().unwrap();
+        assert_eq!(&frame.payload[43..], ours.as_bytes());
+
+        // The client encrypts its key for us, the upstream gets ours
+        let client_key = testing::TEST_AES_KEY;
+        let response = testing::session_key_response(&keypair, &client_key);
+        let relayed = relay(&mut session, Direction::ClientToServer, &response);
+        let (frame, _) = parse(&relayed.bytes);
+        let ready = server.handle(0x05, &frame.payload).unwrap().unwrap();
+        assert_eq!(ready, testing::encryption_ready());
+
+        // C->S is re-encrypted for the upstream
+        let message = testing::encrypted_message(client_key, 0x2EE2, b"login");
+        let relayed = relay(&mut session, Direction::ClientToServer, &message);
+        assert_eq!(
+            relayed.message,
+            Some(testing::game_message(0x2EE2, b"login"))
+        );
+        let (frame, _) = parse(&relayed.bytes);
+        assert_eq!(
+            server.decrypt_packet(&frame.payload).unwrap().1,
+            testing::game_message(0x2EE2, b"login")
+        );
+
+        // S->C is re-encrypted for the client, with rewrites applied
+        let message = server
+            .encrypt_packet(&testing::game_message(0x1001, b"hello"))
+            .unwrap();
+        let relayed = relay(&mut session, Direction::ServerToClient, &message);
+        assert!(relayed.note.is_some());
+        let (frame, _) = parse(&relayed.bytes);
+        let mut client = ProudNetCrypto::new();
+        client.set_aes_session_key(client_key);
+        assert_eq!(
+            client.decrypt_packet_0x25(&frame.payload).unwrap(),
+            testing::game_message(0x1001, &[0xAB])
+        );
+
+        // 0x26 stays 0x26
+        let message = server
+            .encrypt_packet_with(
+                Reliability::Unreliable,
+                &testing::game_message(0x1005, b"move"),
+            )
+            .unwrap();
+        let relayed = relay(&mut session, Direction::ServerToClient, &message);
+        let (frame, _) = parse(&relayed.bytes);
+        assert_eq!(frame.opcode(), Some(0x26));
+        assert_eq!(
+            client.decrypt_packet_0x25(&frame.payload).unwrap(),
+            testing::game_message(0x1005, b"move")
+        );
+    }
+}
@@ -0,0 +1,332 @@
+//! Machine-readable capture reports
+//!
+//! `capture --output json|csv|md` emits the same analysis as the console
+//! view as structured data: JSON for scripts, CSV for spreadsheets, and a
+//! Markdown table that can be pasted straight into the protocol docs.
+
+use crate::decrypt::{CaptureDecryptor, Decrypted};
+use crate::stream::{StreamReassembler, StreamStats};
+use clap::ValueEnum;
+use ro2_common::protocol::{opcodes, schema};
+use serde::{Serialize, Serializer};
+use std::fmt::Write as _;
+
+/// Output format for the `capture` command
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// Annotated console output
+    #[default]
+    Text,
+    Json,
+    Csv,
+    Md,
+}
+
+/// A named, decoded field
+#[derive(Debug, Clone, Serialize)]
+pub struct FieldRecord {
+    pub name: &'static str,
+    pub value: String,
+}
+
+/// Analysis of one reassembled frame
+#[derive(Debug, Clone, Serialize)]
+pub struct FrameRecord {
+    pub frame: u32,
+    pub time: Option<f64>,
+    pub direction: String,
+    pub client_port: u16,
+    pub opcode: Option<u8>,
+    pub length: usize,
+    pub entropy: f64,
+    #[serde(serialize_with = "as_hex")]
+    pub payload: Vec<u8>,
+
+    /// Game opcode inside 0x25/0x26, once decrypted
+    pub game_opcode: Option<u16>,
+    pub message: Option<&'static str>,
+    pub fields: Vec<FieldRecord>,
+    #[serde(serialize_with = "as_hex")]
+    pub decrypted: Vec<u8>,
+    pub trailing_bytes: usize,
+
+    /// Session key found, or why decryption failed
+    pub note: Option<String>,
+}
+
+/// Counters shown at the end of the analysis
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Summary {
+    pub segments: usize,
+    pub frames: usize,
+    pub retransmits: usize,
+    pub out_of_order: usize,
+    pub skipped_bytes: usize,
+    pub session_keys: usize,
+    pub decrypted: usize,
+    pub decrypt_failures: usize,
+    /// (direction, client port, bytes) of incomplete frames at stream end
+    pub leftover: Vec<(String, u16, usize)>,
+}
+
+/// Full result of analyzing a capture
+#[derive(Debug, Clone, Serialize)]
+pub struct CaptureReport {
+    pub server_port: u16,
+    pub decryption: bool,
+    pub summary: Summary,
+    pub frames: Vec<FrameRecord>,
+}
+
+impl CaptureReport {
+    /// Analyze reassembled frames, decrypting where possible
+    pub fn build(
+        reassembler: &StreamReassembler,
+        server_port: u16,
+        mut decryptor: Option<CaptureDecryptor>,
+    ) -> Self {
+        let StreamStats {
+            segments,
+            retransmits,
+            out_of_order,
+            skipped_bytes,
+        } = reassembler.stats();
+        let mut summary = Summary {
+            segments,
+            frames: reassembler.frames().len(),
+            retransmits,
+            out_of_order,
+            skipped_bytes,
+            leftover: reassembler
+                .leftover()
+                .into_iter()
+                .map(|(id, bytes)| (id.direction.to_string(), id.client_port, bytes))
+                .collect(),
+            ..Summary::default()
+        };
+
+        let mut frames = Vec::with_capacity(summary.frames);
+        for frame in reassembler.frames() {
+            let payload = &frame.packet.payload;
+            let mut record = FrameRecord {
+                frame: frame.capture_frame,
+                time: frame.time,
+                direction: frame.stream.direction.to_string(),
+                client_port: frame.stream.client_port,
+                opcode: frame.packet.opcode(),
+                length: payload.len(),
+                entropy: crate::calculate_entropy(payload),
+                payload: payload.clone(),
+                game_opcode: None,
+                message: None,
+                fields: Vec::new(),
+                decrypted: Vec::new(),
+                trailing_bytes: 0,
+                note: None,
+            };
+
+            match decryptor.as_mut().and_then(|d| d.process(frame)) {
+                Some(Decrypted::SessionKey(key)) => {
+                    record.note = Some(format!("session key {}", hex::encode(key)));
+                    summary.session_keys += 1;
+                }
+                Some(Decrypted::Message { opcode, data }) => {
+                    record.game_opcode = Some(opcode);
+                    record.message = opcodes::info(opcode).map(|info| info.name);
+                    if let Some(schema) = schema::lookup(opcode) {
+                        let decoded = schema.decode(&data);
+                        record.message = Some(schema.name);
+                        record.fields = decoded
+                            .fields
+                            .iter()
+                            .map(|(name, value)| FieldRecord {
+                                name,
+                                value: value.to_string(),
+                            })
+                            .collect();
+                        record.trailing_bytes = decoded.trailing.len();
+                    }
+                    record.decrypted = data;
+                    summary.decrypted += 1;
+                }
+                Some(Decrypted::Failed(error)) => {
+                    record.note = Some(format!("decryption failed: {}", error));
+                    summary.decrypt_failures += 1;
+                }
+                None => {}
+            }
+
+            frames.push(record);
+        }
+
+        Self {
+            server_port,
+            decryption: decryptor.is_some(),
+            summary,
+            frames,
+        }
+    }
+
+    /// Render as pretty-printed JSON
+    pub fn to_json(&self) -> anyhow::Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    /// Render one CSV row per frame
+    pub fn to_csv(&self) -> String {
+        let mut out = String::from(
+            "frame,time,direction,client_port,opcode,length,entropy,game_opcode,message,fields,trailing_bytes,note,payload\n",
+        );
+        for f in &self.frames {
+            let row = [
+                f.frame.to_string(),
+                f.time.map(|t| format!("{:.6}", t)).unwrap_or_default(),
+                f.direction.clone(),
+                f.client_port.to_string(),
+                f.opcode
+                    .map(|op| format!("0x{:02X}", op))
+                    .unwrap_or_default(),
+                f.length.to_string(),
+                format!("{:.2}", f.entropy),
+                f.game_opcode
+                    .map(|op| format!("0x{:04X}", op))
+                    .unwrap_or_default(),
+                f.message.unwrap_or_default().to_string(),
+                fields_inline(&f.fields),
+                f.trailing_bytes.to_string(),
+                f.note.clone().unwrap_or_default(),
+                hex::encode(&f.payload),
+            ];
+            let row: Vec<String> = row.iter().map(|v| csv_escape(v)).collect();
+            out.push_str(&row.join(","));
+            out.push('\n');
+        }
+        out
+    }
+
+    /// Render a Markdown summary and frame table
+    pub fn to_markdown(&self) -> String {
+        let s = &self.summary;
+        let mut out = String::new();
+
+        let _ = writeln!(
+            out,
+            "## Capture analysis (server port {})\n",
+            self.server_port
+        );
+        let _ = writeln!(out, "| Metric | Value |");
+        let _ = writeln!(out, "|--------|-------|");
+        let _ = writeln!(out, "| Segments | {} |", s.segments);
+        let _ = writeln!(out, "| Frames | {} |", s.frames);
+        let _ = writeln!(out, "| Retransmits | {} |", s.retransmits);
+        let _ = writeln!(out, "| Out of order | {} |", s.out_of_order);
+        let _ = writeln!(out, "| Skipped bytes | {} |", s.skipped_bytes);
+        if self.decryption {
+            let _ = writeln!(out, "| Session keys | {} |", s.session_keys);
+            let _ = writeln!(out, "| Decrypted | {} |", s.decrypted);
+            let _ = writeln!(out, "| Decryption failures | {} |", s.decrypt_failures);
+        }
+
+        let _ = writeln!(out, "\n### Frames\n");
+        let _ = writeln!(
+            out,
+            "| Frame | Time | Dir | Opcode | Len | Entropy | Message | Fields |"
+        );
+        let _ = writeln!(
+            out,
+            "|-------|------|-----|--------|-----|---------|---------|--------|"
+        );
+        for f in &self.frames {
+            let opcode = f
+                .opcode
+                .map(|op| format!("0x{:02X}", op))
+                .unwrap_or_default();
+            let message = match (f.game_opcode, f.message) {
+                (Some(op), Some(name)) => format!("{} (0x{:04X})", name, op),
+                (Some(op), None) => format!("0x{:04X}", op),
+                _ => f.note.clone().unwrap_or_default(),
+            };
+            let mut fields = fields_inline(&f.fields);
+            if f.trailing_bytes > 0 {
+                fields.push_str(&format!(" **+{} unknown bytes**", f.trailing_bytes));
+            }
+            let _ = writeln!(
+                out,
+                "| {} | {} | {} | {} | {} | {:.2} | {} | {} |",
+                f.frame,
+                f.time.map(|t| format!("{:.3}", t)).unwrap_or_default(),
+                f.direction,
+                opcode,
+                f.length,
+                f.entropy,
+                md_escape(&message),
+                md_escape(fields.trim())
+            );
+        }
+
+        out
+    }
+}
+
+fn fields_inline(fields: &[FieldRecord]) -> String {
+    fields
+        .iter()
+        .map(|f| format!("{}={}", f.name, f.value))
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+fn csv_escape(value: &str) -> String {
+    if value.contains([',', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn md_escape(value: &str) -> String {
+    value.replace('|', "\\|")
+}
+
+fn as_hex<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&hex::encode(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stream::reassemble;
+    use ro2_common::packet::PacketFrame;
+
+    fn sample_report() -> CaptureReport {
+        let frame = PacketFrame::new(vec![0x1B, 0x01]).to_bytes();
+        let line = format!("3\t50123\t7101\t1\t{}\t1.5", hex::encode(frame));
+        let reassembler = reassemble(&line, 7101).unwrap();
+        CaptureReport::build(&reassembler, 7101, None)
+    }
+
+    #[test]
+    fn test_json_and_csv() {
+        let report = sample_report();
+
+        let json: serde_json::Value = serde_json::from_str(&report.to_json().unwrap()).unwrap();
+        assert_eq!(json["frames"][0]["opcode"], 0x1B);
+        assert_eq!(json["frames"][0]["payload"], "1b01");
+        assert_eq!(json["summary"]["frames"], 1);
+
+        let csv = report.to_csv();
+        let lines: Vec<_> = csv.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[1].starts_with("3,1.500000,C->S,50123,0x1B,2,"));
+    }
+
+    #[test]
+    fn test_markdown_and_escaping() {
+        let md = sample_report().to_markdown();
+        assert!(md.contains("| Frames | 1 |"));
+        assert!(md.contains("| 3 | 1.500 | C->S | 0x1B | 2 |"));
+
+        assert_eq!(csv_escape("a,\"b\""), "\"a,\"\"b\"\"\"");
+        assert_eq!(md_escape("a|b"), "a\\|b");
+    }
+}
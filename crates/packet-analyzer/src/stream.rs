@@ -0,0 +1,414 @@
+//! TCP stream reassembly for captured RO2 traffic
+//!
+//! Captures are exported from Wireshark with one TCP segment per line:
+//!
+//! ```bash
+//! tshark -r capture.pcapng -Y 'tcp.port == 7101 && tcp.len > 0' \
+//!     -T fields -e frame.number -e tcp.srcport -e tcp.dstport -e tcp.seq -e data \
+//!     -e frame.time_relative > segments.txt
+//! ```
+//!
+//! The trailing timestamp column is optional.
+//!
+//! Segments are grouped into one stream per client connection and
+//! direction, reordered by sequence number (dropping retransmits), and the
+//! resulting byte stream is cut into [`PacketFrame`]s. A single ProudNet
+//! frame regularly spans several segments, and one segment can carry
+//! several frames, so per-segment parsing gives wrong results.
+
+use anyhow::{Context, Result};
+use ro2_common::packet::PacketFrame;
+use ro2_common::packet::framing::{MAX_PACKET_SIZE, PACKET_MAGIC_BYTES};
+use std::collections::{BTreeMap, HashMap};
+
+pub use ro2_common::net::Direction;
+
+/// One TCP segment with payload
+#[derive(Debug, Clone, PartialEq)]
+pub struct Segment {
+    /// Capture frame number
+    pub frame: u32,
+    pub src_port: u16,
+    pub dst_port: u16,
+    /// TCP sequence number (relative or absolute, as exported)
+    pub seq: u32,
+    pub data: Vec<u8>,
+    /// Seconds since the start of the capture, if exported
+    pub time: Option<f64>,
+}
+
+impl Segment {
+    /// Parse a tab-separated tshark line
+    /// (`frame.number`, `tcp.srcport`, `tcp.dstport`, `tcp.seq`, `data`,
+    /// optionally `frame.time_relative`)
+    pub fn parse_line(line: &str) -> Result<Self> {
+        let parts: Vec<&str> = line.trim_end().split('\t').collect();
+        if parts.len() < 5 {
+            anyhow::bail!("Expected 5 tab-separated fields, got {}", parts.len());
+        }
+
+        Ok(Self {
+            frame: parts[0].parse().context("Invalid frame number")?,
+            src_port: parts[1].parse().context("Invalid source port")?,
+            dst_port: parts[2].parse().context("Invalid destination port")?,
+            seq: parts[3].parse().context("Invalid sequence number")?,
+            data: hex::decode(parts[4].replace(':', "")).context("Invalid segment data")?,
+            time: match parts.get(5) {
+                Some(time) if !time.is_empty() => Some(time.parse().context("Invalid timestamp")?),
+                _ => None,
+            },
+        })
+    }
+}
+
+/// Identifies one half of a TCP connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct StreamId {
+    /// Ephemeral port on the client side
+    pub client_port: u16,
+    pub direction: Direction,
+}
+
+/// A frame extracted from a reassembled stream
+#[derive(Debug, Clone, PartialEq)]
+pub struct TaggedFrame {
+    pub stream: StreamId,
+    /// Capture frame number of the segment that completed this frame
+    pub capture_frame: u32,
+    /// Timestamp of that segment
+    pub time: Option<f64>,
+    pub packet: PacketFrame,
+}
+
+/// Bytes skipped while looking for the packet magic
+///
+/// Usually noise, except for the 0x2F policy response, which the server
+/// sends as raw XML without framing.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnframedData {
+    pub stream: StreamId,
+    pub capture_frame: u32,
+    pub time: Option<f64>,
+    pub data: Vec<u8>,
+}
+
+/// Counters for anomalies seen while reassembling
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StreamStats {
+    pub segments: usize,
+    pub retransmits: usize,
+    pub out_of_order: usize,
+    /// Bytes skipped while resynchronizing on the packet magic
+    pub skipped_bytes: usize,
+}
+
+#[derive(Debug, Default)]
+struct HalfStream {
+    next_seq: Option<u32>,
+    pending: BTreeMap<u32, Segment>,
+    buffer: Vec<u8>,
+}
+
+/// Reassembles segments into per-stream packet frames
+#[derive(Debug)]
+pub struct StreamReassembler {
+    server_port: u16,
+    streams: HashMap<StreamId, HalfStream>,
+    frames: Vec<TaggedFrame>,
+    unframed: Vec<UnframedData>,
+    stats: StreamStats,
+}
+
+impl StreamReassembler {
+    /// Create a reassembler; `server_port` decides segment direction
+    pub fn new(server_port: u16) -> Self {
+        Self {
+            server_port,
+            streams: HashMap::new(),
+            frames: Vec::new(),
+            unframed: Vec::new(),
+            stats: StreamStats::default(),
+        }
+    }
+
+    /// Feed one segment in capture order
+    pub fn push(&mut self, segment: Segment) {
+        self.stats.segments += 1;
+
+        let (direction, client_port) = if segment.src_port == self.server_port {
+            (Direction::ServerToClient, segment.dst_port)
+        } else {
+            (Direction::ClientToServer, segment.src_port)
+        };
+        let id = StreamId {
+            client_port,
+            direction,
+        };
+
+        let stream = self.streams.entry(id).or_default();
+        let next_seq = *stream.next_seq.get_or_insert(segment.seq);
+
+        let end = segment.seq.wrapping_add(segment.data.len() as u32);
+        if seq_le(end, next_seq) {
+            self.stats.retransmits += 1;
+            return;
+        }
+        if seq_lt(next_seq, segment.seq) {
+            self.stats.out_of_order += 1;
+            stream.pending.insert(segment.seq, segment);
+            return;
+        }
+
+        let (frame_number, time) = (segment.frame, segment.time);
+        Self::append(stream, segment);
+        Self::drain_pending(stream, &mut self.stats);
+        self.extract(id, frame_number, time);
+    }
+
+    /// Frames extracted so far, in completion order
+    pub fn frames(&self) -> &[TaggedFrame] {
+        &self.frames
+    }
+
+    /// Data skipped between frames, in capture order
+    pub fn unframed(&self) -> &[UnframedData] {
+        &self.unframed
+    }
+
+    pub fn stats(&self) -> StreamStats {
+        self.stats
+    }
+
+    /// Bytes left over at the end of each stream (incomplete frames)
+    pub fn leftover(&self) -> Vec<(StreamId, usize)> {
+        let mut leftover: Vec<_> = self
+            .streams
+            .iter()
+            .filter(|(_, s)| !s.buffer.is_empty())
+            .map(|(id, s)| (*id, s.buffer.len()))
+            .collect();
+        leftover.sort();
+        leftover
+    }
+
+    /// Append the part of `segment` at or after `next_seq`
+    fn append(stream: &mut HalfStream, segment: Segment) {
+        let next_seq = stream.next_seq.unwrap_or(segment.seq);
+        let overlap = next_seq.wrapping_sub(segment.seq) as usize;
+        stream.buffer.extend_from_slice(&segment.data[overlap..]);
+        stream.next_seq = Some(segment.seq.wrapping_add(segment.data.len() as u32));
+    }
+
+    fn drain_pending(stream: &mut HalfStream, stats: &mut StreamStats) {
+        while let Some(next_seq) = stream.next_seq {
+            let Some(seq) = stream.pending.keys().next().copied() else {
+                break;
+            };
+            if seq_lt(next_seq, seq) {
+                break;
+            }
+
+            let segment = stream.pending.remove(&seq).expect("key taken from map");
+            let end = segment.seq.wrapping_add(segment.data.len() as u32);
+            if seq_le(end, next_seq) {
+                stats.retransmits += 1;
+                continue;
+            }
+            Self::append(stream, segment);
+        }
+    }
+
+    fn extract(&mut self, id: StreamId, capture_frame: u32, time: Option<f64>) {
+        let stream = self.streams.get_mut(&id).expect("stream exists");
+
+        loop {
+            // Resynchronize on the magic if the stream starts mid-frame
+            let skip = match find_magic(&stream.buffer) {
+                Some(pos) => pos,
+                // Keep a possible partial magic byte
+                None => {
+                    stream.buffer.len()
+                        - usize::from(stream.buffer.last() == Some(&PACKET_MAGIC_BYTES[0]))
+                }
+            };
+            if skip > 0 {
+                self.stats.skipped_bytes += skip;
+                self.unframed.push(UnframedData {
+                    stream: id,
+                    capture_frame,
+                    time,
+                    data: stream.buffer.drain(..skip).collect(),
+                });
+            }
+            if stream.buffer.len() < 2 {
+                return;
+            }
+
+            match frame_len(&stream.buffer) {
+                FrameLen::NeedMore => return,
+                FrameLen::Invalid => {
+                    // Magic matched by chance; skip it and keep looking
+                    self.stats.skipped_bytes += 1;
+                    stream.buffer.drain(..1);
+                }
+                FrameLen::Complete(len) => {
+                    let (packet, _) = PacketFrame::from_bytes(&stream.buffer[..len])
+                        .expect("header validated by frame_len");
+                    stream.buffer.drain(..len);
+                    self.frames.push(TaggedFrame {
+                        stream: id,
+                        capture_frame,
+                        time,
+                        packet,
+                    });
+                }
+            }
+        }
+    }
+}
+
+/// Reassemble a tshark export into tagged frames
+pub fn reassemble(content: &str, server_port: u16) -> Result<StreamReassembler> {
+    let mut reassembler = StreamReassembler::new(server_port);
+
+    for (line_no, line) in content.lines().enumerate() {
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let segment = Segment::parse_line(line).with_context(|| format!("Line {}", line_no + 1))?;
+        if segment.data.is_empty() {
+            continue;
+        }
+        reassembler.push(segment);
+    }
+
+    Ok(reassembler)
+}
+
+enum FrameLen {
+    NeedMore,
+    Invalid,
+    Complete(usize),
+}
+
+/// Peek at the frame header at the start of `buffer`
+fn frame_len(buffer: &[u8]) -> FrameLen {
+    let Some(&size_byte) = buffer.get(2) else {
+        return FrameLen::NeedMore;
+    };
+    let width = match size_byte {
+        1 | 2 | 4 => size_byte as usize,
+        _ => return FrameLen::Invalid,
+    };
+
+    let header = 3 + width;
+    let Some(raw) = buffer.get(3..header) else {
+        return FrameLen::NeedMore;
+    };
+    let mut value = [0u8; 4];
+    value[..width].copy_from_slice(raw);
+    let payload = u32::from_le_bytes(value) as usize;
+
+    if payload > MAX_PACKET_SIZE {
+        FrameLen::Invalid
+    } else if buffer.len() < header + payload {
+        FrameLen::NeedMore
+    } else {
+        FrameLen::Complete(header + payload)
+    }
+}
+
+fn find_magic(buffer: &[u8]) -> Option<usize> {
+    buffer.windows(2).position(|w| w == PACKET_MAGIC_BYTES)
+}
+
+/// `a < b` in sequence-number space (RFC 1982)
+fn seq_lt(a: u32, b: u32) -> bool {
+    (b.wrapping_sub(a) as i32) > 0
+}
+
+fn seq_le(a: u32, b: u32) -> bool {
+    a == b || seq_lt(a, b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SERVER: u16 = 7101;
+    const CLIENT: u16 = 50123;
+
+    fn seg(frame: u32, from_client: bool, seq: u32, data: &[u8]) -> Segment {
+        let (src_port, dst_port) = if from_client {
+            (CLIENT, SERVER)
+        } else {
+            (SERVER, CLIENT)
+        };
+        Segment {
+            frame,
+            src_port,
+            dst_port,
+            seq,
+            data: data.to_vec(),
+            time: None,
+        }
+    }
+
+    #[test]
+    fn test_frame_split_across_segments() {
+        let bytes = PacketFrame::new(vec![0x04, 1, 2, 3, 4, 5]).to_bytes();
+        let mut r = StreamReassembler::new(SERVER);
+
+        r.push(seg(1, false, 1, &bytes[..3]));
+        assert!(r.frames().is_empty());
+        r.push(seg(2, false, 4, &bytes[3..]));
+
+        assert_eq!(r.frames().len(), 1);
+        assert_eq!(r.frames()[0].stream.direction, Direction::ServerToClient);
+        assert_eq!(r.frames()[0].capture_frame, 2);
+        assert_eq!(r.frames()[0].packet.opcode(), Some(0x04));
+    }
+
+    #[test]
+    fn test_out_of_order_and_retransmit() {
+        let mut bytes = PacketFrame::new(vec![0x05, 0xAA]).to_bytes();
+        bytes.extend(PacketFrame::new(vec![0x25, 0xBB, 0xCC]).to_bytes());
+        let mut r = StreamReassembler::new(SERVER);
+
+        r.push(seg(1, true, 100, &bytes[..4]));
+        r.push(seg(3, true, 106, &bytes[6..]));
+        r.push(seg(2, true, 104, &bytes[4..6]));
+        r.push(seg(4, true, 100, &bytes[..4]));
+
+        let stats = r.stats();
+        assert_eq!(stats.out_of_order, 1);
+        assert_eq!(stats.retransmits, 1);
+
+        let opcodes: Vec<_> = r.frames().iter().map(|f| f.packet.opcode()).collect();
+        assert_eq!(opcodes, vec![Some(0x05), Some(0x25)]);
+        assert!(
+            r.frames()
+                .iter()
+                .all(|f| f.stream.direction == Direction::ClientToServer)
+        );
+    }
+
+    #[test]
+    fn test_resync_and_parse_line() {
+        let mut bytes = vec![0xDE, 0xAD];
+        bytes.extend(PacketFrame::new(vec![0x1B]).to_bytes());
+        let line = format!(
+            "7\t{}\t{}\t1\t{}\t0.25",
+            CLIENT,
+            SERVER,
+            hex::encode(&bytes)
+        );
+
+        let r = reassemble(&line, SERVER).unwrap();
+        assert_eq!(r.frames().len(), 1);
+        assert_eq!(r.frames()[0].time, Some(0.25));
+        assert_eq!(r.stats().skipped_bytes, 2);
+        assert_eq!(r.unframed()[0].data, vec![0xDE, 0xAD]);
+        assert!(r.leftover().is_empty());
+    }
+}
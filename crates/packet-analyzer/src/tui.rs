@@ -0,0 +1,403 @@
+//! Interactive capture browser
+//!
+//! `packet-analyzer tui <capture>` shows the reassembled frames as a
+//! scrollable list with the selected frame's hex dump and decoded fields
+//! side by side. Frames can be filtered by opcode or message name, and
+//! notes typed with `a` are saved to the opcode database straight away.
+//!
+//! Keys: ↑/↓ or j/k scroll, PgUp/PgDn page, Home/End jump, `/` filter,
+//! `a` annotate, `q` quit. Enter confirms input, Esc cancels it.
+
+use crate::annotations::{OpcodeDatabase, opcode_key};
+use crate::report::{CaptureReport, FrameRecord};
+use anyhow::Result;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Borders, Paragraph, Row, Table, TableState};
+use ratatui::{DefaultTerminal, Frame};
+
+/// Rows moved by PgUp/PgDn
+const PAGE_SIZE: usize = 20;
+
+/// What keystrokes currently go to
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Mode {
+    Browse,
+    Filter(String),
+    Annotate(String),
+}
+
+/// Browser state, independent of the terminal
+pub struct App {
+    report: CaptureReport,
+    db: OpcodeDatabase,
+    filter: String,
+    /// Indices into `report.frames` that pass the filter
+    visible: Vec<usize>,
+    table: TableState,
+    mode: Mode,
+    status: String,
+}
+
+impl App {
+    pub fn new(report: CaptureReport, db: OpcodeDatabase) -> Self {
+        let mut app = Self {
+            report,
+            db,
+            filter: String::new(),
+            visible: Vec::new(),
+            table: TableState::default(),
+            mode: Mode::Browse,
+            status: String::from("/ filter  a annotate  q quit"),
+        };
+        app.apply_filter();
+        app
+    }
+
+    /// Run until the user quits
+    pub fn run(mut self, terminal: &mut DefaultTerminal) -> Result<()> {
+        loop {
+            terminal.draw(|frame| self.draw(frame))?;
+
+            if let Event::Key(key) = event::read()?
+                && key.kind == KeyEventKind::Press
+                && self.handle_key(key)?
+            {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Handle a key press, returning true to quit
+    fn handle_key(&mut self, key: KeyEvent) -> Result<bool> {
+        match &mut self.mode {
+            Mode::Browse => match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => return Ok(true),
+                KeyCode::Down | KeyCode::Char('j') => self.move_selection(1),
+                KeyCode::Up | KeyCode::Char('k') => self.move_selection(-1),
+                KeyCode::PageDown => self.move_selection(PAGE_SIZE as isize),
+                KeyCode::PageUp => self.move_selection(-(PAGE_SIZE as isize)),
+                KeyCode::Home => self.select(0),
+                KeyCode::End => self.select(self.visible.len().saturating_sub(1)),
+                KeyCode::Char('/') => self.mode = Mode::Filter(self.filter.clone()),
+                KeyCode::Char('a') => match self.selected_key() {
+                    Some(_) => self.mode = Mode::Annotate(String::new()),
+                    None => self.status = String::from("Nothing to annotate"),
+                },
+                _ => {}
+            },
+            Mode::Filter(input) | Mode::Annotate(input) => match key.code {
+                KeyCode::Char(c) => input.push(c),
+                KeyCode::Backspace => {
+                    input.pop();
+                }
+                KeyCode::Esc => self.mode = Mode::Browse,
+                KeyCode::Enter => {
+                    let mode = std::mem::replace(&mut self.mode, Mode::Browse);
+                    match mode {
+                        Mode::Filter(input) => {
+                            self.filter = input.trim().to_string();
+                            self.apply_filter();
+                        }
+                        Mode::Annotate(input) => self.annotate(input.trim())?,
+                        Mode::Browse => unreachable!(),
+                    }
+                }
+                _ => {}
+            },
+        }
+
+        Ok(false)
+    }
+
+    fn apply_filter(&mut self) {
+        self.visible = self
+            .report
+            .frames
+            .iter()
+            .enumerate()
+            .filter(|(_, frame)| matches_filter(frame, &self.filter))
+            .map(|(i, _)| i)
+            .collect();
+
+        self.status = format!(
+            "{} of {} frames{}",
+            self.visible.len(),
+            self.report.frames.len(),
+            if self.filter.is_empty() {
+                String::new()
+            } else {
+                format!(" matching {:?}", self.filter)
+            }
+        );
+        self.select(0);
+    }
+
+    fn annotate(&mut self, text: &str) -> Result<()> {
+        if text.is_empty() {
+            return Ok(());
+        }
+        let (Some(frame), Some(key)) = (self.selected(), self.selected_key()) else {
+            return Ok(());
+        };
+
+        let frame_number = frame.frame;
+        self.db.annotate(&key, frame_number, text);
+        self.db.save()?;
+        self.status = format!("Saved note for {} to {}", key, self.db.path().display());
+        Ok(())
+    }
+
+    fn select(&mut self, index: usize) {
+        self.table
+            .select((!self.visible.is_empty()).then_some(index));
+    }
+
+    fn move_selection(&mut self, delta: isize) {
+        if self.visible.is_empty() {
+            return;
+        }
+        let current = self.table.selected().unwrap_or(0) as isize;
+        let last = self.visible.len() as isize - 1;
+        self.select((current + delta).clamp(0, last) as usize);
+    }
+
+    fn selected(&self) -> Option<&FrameRecord> {
+        let index = *self.visible.get(self.table.selected()?)?;
+        self.report.frames.get(index)
+    }
+
+    fn selected_key(&self) -> Option<String> {
+        self.selected()
+            .and_then(|f| opcode_key(f.opcode, f.game_opcode))
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [list_area, detail_area, status_area] = Layout::vertical([
+            Constraint::Percentage(45),
+            Constraint::Min(0),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+        let [hex_area, fields_area] =
+            Layout::horizontal([Constraint::Length(76), Constraint::Min(0)]).areas(detail_area);
+
+        self.draw_list(frame, list_area);
+
+        let (hex, fields) = match self.selected() {
+            Some(record) => (hex_lines(record), field_lines(record, &self.db)),
+            None => (Vec::new(), Vec::new()),
+        };
+        frame.render_widget(
+            Paragraph::new(hex).block(Block::default().borders(Borders::ALL).title("Hex")),
+            hex_area,
+        );
+        frame.render_widget(
+            Paragraph::new(fields).block(Block::default().borders(Borders::ALL).title("Decoded")),
+            fields_area,
+        );
+
+        let status = match &self.mode {
+            Mode::Browse => self.status.clone(),
+            Mode::Filter(input) => format!("Filter (opcode or name): {}", input),
+            Mode::Annotate(input) => format!("Note: {}", input),
+        };
+        frame.render_widget(Paragraph::new(status), status_area);
+    }
+
+    fn draw_list(&mut self, frame: &mut Frame, area: Rect) {
+        let rows = self.visible.iter().map(|&i| {
+            let f = &self.report.frames[i];
+            let noted = opcode_key(f.opcode, f.game_opcode)
+                .and_then(|key| self.db.get(&key))
+                .is_some_and(|entry| !entry.notes.is_empty());
+            Row::new([
+                f.frame.to_string(),
+                f.time.map(|t| format!("{:.3}", t)).unwrap_or_default(),
+                f.direction.clone(),
+                format_opcode(f),
+                f.length.to_string(),
+                format!(
+                    "{}{}",
+                    if noted { "* " } else { "" },
+                    f.message.unwrap_or_default()
+                ),
+            ])
+        });
+
+        let table = Table::new(
+            rows,
+            [
+                Constraint::Length(7),
+                Constraint::Length(9),
+                Constraint::Length(5),
+                Constraint::Length(14),
+                Constraint::Length(6),
+                Constraint::Min(0),
+            ],
+        )
+        .header(
+            Row::new(["Frame", "Time", "Dir", "Opcode", "Len", "Message"])
+                .style(Style::default().add_modifier(Modifier::BOLD)),
+        )
+        .row_highlight_style(Style::default().bg(Color::DarkGray))
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title(format!("Frames (server port {})", self.report.server_port)),
+        );
+
+        frame.render_stateful_widget(table, area, &mut self.table);
+    }
+}
+
+/// Open the browser on a report, restoring the terminal on exit
+pub fn run(report: CaptureReport, db: OpcodeDatabase) -> Result<()> {
+    let mut terminal = ratatui::init();
+    let result = App::new(report, db).run(&mut terminal);
+    ratatui::restore();
+    result
+}
+
+/// Match a frame against a hex opcode (`25`, `0x2EE2`) or a message name
+fn matches_filter(frame: &FrameRecord, filter: &str) -> bool {
+    if filter.is_empty() {
+        return true;
+    }
+
+    let hex = filter
+        .strip_prefix("0x")
+        .or_else(|| filter.strip_prefix("0X"))
+        .unwrap_or(filter);
+    if let Ok(opcode) = u16::from_str_radix(hex, 16)
+        && (frame.opcode.map(u16::from) == Some(opcode) || frame.game_opcode == Some(opcode))
+    {
+        return true;
+    }
+
+    frame
+        .message
+        .is_some_and(|name| name.to_lowercase().contains(&filter.to_lowercase()))
+}
+
+fn format_opcode(frame: &FrameRecord) -> String {
+    match (frame.opcode, frame.game_opcode) {
+        (Some(op), Some(game)) => format!("0x{:02X} 0x{:04X}", op, game),
+        (Some(op), None) => format!("0x{:02X}", op),
+        (None, _) => String::from("--"),
+    }
+}
+
+fn hex_lines(record: &FrameRecord) -> Vec<Line<'static>> {
+    let mut lines: Vec<Line> = crate::hex_dump_lines(&record.payload)
+        .into_iter()
+        .map(Line::from)
+        .collect();
+
+    if record.game_opcode.is_some() {
+        lines.push(Line::from(""));
+        lines.push(Line::from("Decrypted:").style(Style::default().fg(Color::Green)));
+        lines.extend(
+            crate::hex_dump_lines(&record.decrypted)
+                .into_iter()
+                .map(Line::from),
+        );
+    }
+    lines
+}
+
+fn field_lines(record: &FrameRecord, db: &OpcodeDatabase) -> Vec<Line<'static>> {
+    let mut lines = vec![Line::from(format!(
+        "#{} {} :{}  {} bytes, entropy {:.2}",
+        record.frame, record.direction, record.client_port, record.length, record.entropy
+    ))];
+
+    if let Some(opcode) = record.game_opcode {
+        lines.push(Line::from(format!(
+            "{} (0x{:04X})",
+            record.message.unwrap_or("Unknown message"),
+            opcode
+        )));
+    }
+    for field in &record.fields {
+        lines.push(Line::from(format!("  {:<16} {}", field.name, field.value)));
+    }
+    if record.trailing_bytes > 0 {
+        lines.push(
+            Line::from(format!(
+                "  +{} unknown trailing bytes",
+                record.trailing_bytes
+            ))
+            .style(Style::default().fg(Color::Yellow)),
+        );
+    }
+    if let Some(note) = &record.note {
+        lines.push(Line::from(note.clone()));
+    }
+
+    let entry = opcode_key(record.opcode, record.game_opcode).and_then(|key| db.get(&key));
+    if let Some(entry) = entry {
+        lines.push(Line::from(""));
+        if let Some(name) = &entry.name {
+            lines.push(Line::from(format!("Known as {}", name)));
+        }
+        for note in &entry.notes {
+            lines.push(
+                Line::from(format!("[#{}] {}", note.frame, note.text))
+                    .style(Style::default().fg(Color::Cyan)),
+            );
+        }
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stream::reassemble;
+    use ratatui::crossterm::event::KeyModifiers;
+    use ro2_common::packet::PacketFrame;
+
+    fn press(app: &mut App, keys: &str) {
+        for c in keys.chars() {
+            let code = match c {
+                '\n' => KeyCode::Enter,
+                c => KeyCode::Char(c),
+            };
+            app.handle_key(KeyEvent::new(code, KeyModifiers::NONE))
+                .unwrap();
+        }
+    }
+
+    #[test]
+    fn test_filter_and_annotate() {
+        let lines: Vec<String> = [(1, 50123, 7101, vec![0x1B]), (2, 7101, 50123, vec![0x06])]
+            .into_iter()
+            .map(|(frame, src, dst, payload)| {
+                let bytes = PacketFrame::new(payload).to_bytes();
+                format!("{}\t{}\t{}\t1\t{}", frame, src, dst, hex::encode(bytes))
+            })
+            .collect();
+        let reassembler = reassemble(&lines.join("\n"), 7101).unwrap();
+        let report = CaptureReport::build(&reassembler, 7101, None);
+
+        let path = std::env::temp_dir().join(format!("ro2-tui-{}.json", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let mut app = App::new(report, OpcodeDatabase::load(&path).unwrap());
+        assert_eq!(app.visible, vec![0, 1]);
+
+        press(&mut app, "/0x06\n");
+        assert_eq!(app.visible, vec![1]);
+
+        press(&mut app, "aready ack\n");
+        let db = OpcodeDatabase::load(&path).unwrap();
+        assert_eq!(db.get("0x06").unwrap().notes[0].text, "ready ack");
+        std::fs::remove_file(&path).unwrap();
+
+        press(&mut app, "/zz\n");
+        assert!(app.visible.is_empty());
+        assert_eq!(app.table.selected(), None);
+    }
+}
@@ -0,0 +1,393 @@
+//! Handshake validation and per-connection timelines
+//!
+//! Every connection is expected to go through the same ProudNet sequence:
+//!
+//! ```text
+//! C->S 0x2F  policy request
+//! S->C       policy XML (unframed)
+//! S->C 0x04  RSA public key + settings
+//! C->S 0x05  encrypted session key
+//! S->C 0x06  encryption ready
+//! C->S 0x07  version check
+//! S->C 0x0A  connection success
+//! C->S 0x1B  heartbeats (answered with 0x1D)
+//!      0x25  encrypted game messages
+//! ```
+//!
+//! `validate` reports steps that are missing or out of order, and gaps
+//! where neither side sent anything for a while. The last event of each
+//! connection is what the client stalled on.
+
+use crate::decrypt::{CaptureDecryptor, Decrypted};
+use crate::stream::{Direction, StreamReassembler};
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// What an event in the timeline is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    /// Unframed `<?xml` cross-domain policy
+    PolicyXml,
+    Opcode(u8),
+}
+
+/// One expected handshake step
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Step {
+    pub kind: Kind,
+    pub direction: Direction,
+    pub name: &'static str,
+}
+
+const fn step(kind: Kind, direction: Direction, name: &'static str) -> Step {
+    Step {
+        kind,
+        direction,
+        name,
+    }
+}
+
+/// Handshake steps in the order the client expects them
+pub const HANDSHAKE: &[Step] = &[
+    step(
+        Kind::Opcode(0x2F),
+        Direction::ClientToServer,
+        "0x2F policy request",
+    ),
+    step(Kind::PolicyXml, Direction::ServerToClient, "policy XML"),
+    step(
+        Kind::Opcode(0x04),
+        Direction::ServerToClient,
+        "0x04 RSA public key",
+    ),
+    step(
+        Kind::Opcode(0x05),
+        Direction::ClientToServer,
+        "0x05 session key",
+    ),
+    step(
+        Kind::Opcode(0x06),
+        Direction::ServerToClient,
+        "0x06 encryption ready",
+    ),
+    step(
+        Kind::Opcode(0x07),
+        Direction::ClientToServer,
+        "0x07 version check",
+    ),
+    step(
+        Kind::Opcode(0x0A),
+        Direction::ServerToClient,
+        "0x0A connection success",
+    ),
+];
+
+/// A frame (or policy XML) on one connection
+#[derive(Debug, Clone, PartialEq)]
+pub struct Event {
+    pub capture_frame: u32,
+    pub time: Option<f64>,
+    pub direction: Direction,
+    pub kind: Kind,
+    /// Game opcode inside 0x25/0x26, when decrypted
+    pub game_opcode: Option<u16>,
+}
+
+impl Event {
+    fn is(&self, kind: Kind, direction: Direction) -> bool {
+        self.kind == kind && self.direction == direction
+    }
+
+    fn is_encrypted(&self) -> bool {
+        matches!(self.kind, Kind::Opcode(0x25 | 0x26))
+    }
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{} {} ", self.capture_frame, self.direction)?;
+        match self.kind {
+            Kind::PolicyXml => f.write_str("policy XML")?,
+            Kind::Opcode(op) => write!(f, "0x{:02X}", op)?,
+        }
+        if let Some(game) = self.game_opcode {
+            write!(f, " [0x{:04X}]", game)?;
+        }
+        Ok(())
+    }
+}
+
+/// Something wrong with a connection's sequence
+#[derive(Debug, Clone, PartialEq)]
+pub enum Issue {
+    Missing(&'static str),
+    OutOfOrder {
+        step: &'static str,
+        frame: u32,
+        expected_after: &'static str,
+    },
+    EncryptedBeforeReady(u32),
+    NoHeartbeats,
+    UnansweredHeartbeats(usize),
+    NoGameMessages,
+    Gap {
+        after: String,
+        seconds: f64,
+    },
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Issue::Missing(step) => write!(f, "Missing {}", step),
+            Issue::OutOfOrder {
+                step,
+                frame,
+                expected_after,
+            } => write!(f, "{} (#{}) arrived before {}", step, frame, expected_after),
+            Issue::EncryptedBeforeReady(frame) => {
+                write!(f, "Encrypted frame #{} before 0x06 encryption ready", frame)
+            }
+            Issue::NoHeartbeats => f.write_str("No 0x1B/0x1C heartbeats after handshake"),
+            Issue::UnansweredHeartbeats(n) => write!(f, "{} heartbeats without a 0x1D ack", n),
+            Issue::NoGameMessages => f.write_str("No 0x25/0x26 game messages"),
+            Issue::Gap { after, seconds } => write!(f, "{:.2}s gap after {}", seconds, after),
+        }
+    }
+}
+
+/// Timeline and findings for one client connection
+#[derive(Debug, Clone)]
+pub struct ConnectionReport {
+    pub client_port: u16,
+    pub events: Vec<Event>,
+    pub issues: Vec<Issue>,
+}
+
+/// Build a timeline per connection and check it against [`HANDSHAKE`]
+///
+/// Gaps longer than `gap_threshold` seconds are reported; they need the
+/// optional timestamp column in the export.
+pub fn validate(
+    reassembler: &StreamReassembler,
+    mut decryptor: Option<CaptureDecryptor>,
+    gap_threshold: f64,
+) -> Vec<ConnectionReport> {
+    let mut connections: BTreeMap<u16, Vec<Event>> = BTreeMap::new();
+
+    for frame in reassembler.frames() {
+        let Some(opcode) = frame.packet.opcode() else {
+            continue;
+        };
+        let game_opcode = match decryptor.as_mut().and_then(|d| d.process(frame)) {
+            Some(Decrypted::Message { opcode, .. }) => Some(opcode),
+            _ => None,
+        };
+        connections
+            .entry(frame.stream.client_port)
+            .or_default()
+            .push(Event {
+                capture_frame: frame.capture_frame,
+                time: frame.time,
+                direction: frame.stream.direction,
+                kind: Kind::Opcode(opcode),
+                game_opcode,
+            });
+    }
+
+    for chunk in reassembler.unframed() {
+        if chunk.stream.direction == Direction::ServerToClient && chunk.data.starts_with(b"<?xml") {
+            connections
+                .entry(chunk.stream.client_port)
+                .or_default()
+                .push(Event {
+                    capture_frame: chunk.capture_frame,
+                    time: chunk.time,
+                    direction: chunk.stream.direction,
+                    kind: Kind::PolicyXml,
+                    game_opcode: None,
+                });
+        }
+    }
+
+    connections
+        .into_iter()
+        .map(|(client_port, mut events)| {
+            // Stable, so frames completed by the same segment keep their
+            // order; policy XML sharing a segment with a frame precedes it
+            events.sort_by_key(|e| (e.capture_frame, e.kind != Kind::PolicyXml));
+            let issues = check(&events, gap_threshold);
+            ConnectionReport {
+                client_port,
+                events,
+                issues,
+            }
+        })
+        .collect()
+}
+
+fn check(events: &[Event], gap_threshold: f64) -> Vec<Issue> {
+    let mut issues = Vec::new();
+    let position =
+        |kind: Kind, direction: Direction| events.iter().position(|e| e.is(kind, direction));
+
+    // Handshake steps: each present, each after the previous ones
+    let mut latest: Option<(usize, &Step)> = None;
+    for step in HANDSHAKE {
+        let Some(index) = position(step.kind, step.direction) else {
+            issues.push(Issue::Missing(step.name));
+            continue;
+        };
+        match latest {
+            Some((prev, prev_step)) if index < prev => issues.push(Issue::OutOfOrder {
+                step: step.name,
+                frame: events[index].capture_frame,
+                expected_after: prev_step.name,
+            }),
+            _ => latest = Some((index, step)),
+        }
+    }
+
+    let ready = position(Kind::Opcode(0x06), Direction::ServerToClient);
+    if let Some(first) = events.iter().position(Event::is_encrypted)
+        && ready.is_none_or(|ready| first < ready)
+    {
+        issues.push(Issue::EncryptedBeforeReady(events[first].capture_frame));
+    }
+
+    // Heartbeats and game traffic only start once the handshake is done
+    if let Some(success) = position(Kind::Opcode(0x0A), Direction::ServerToClient) {
+        let after = &events[success..];
+        let count = |op: u8, direction: Direction| {
+            after
+                .iter()
+                .filter(|e| e.is(Kind::Opcode(op), direction))
+                .count()
+        };
+
+        let heartbeats = count(0x1B, Direction::ClientToServer);
+        if heartbeats + count(0x1C, Direction::ClientToServer) == 0 {
+            issues.push(Issue::NoHeartbeats);
+        }
+        let acks = count(0x1D, Direction::ServerToClient);
+        if heartbeats > acks {
+            issues.push(Issue::UnansweredHeartbeats(heartbeats - acks));
+        }
+        if !after.iter().any(Event::is_encrypted) {
+            issues.push(Issue::NoGameMessages);
+        }
+    }
+
+    for pair in events.windows(2) {
+        if let (Some(a), Some(b)) = (pair[0].time, pair[1].time)
+            && b - a > gap_threshold
+        {
+            issues.push(Issue::Gap {
+                after: pair[0].to_string(),
+                seconds: b - a,
+            });
+        }
+    }
+
+    issues
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stream::reassemble;
+    use ro2_common::packet::PacketFrame;
+    use ro2_common::testing::Handshake;
+
+    const SERVER: u16 = 7101;
+    const CLIENT: u16 = 50123;
+
+    /// Build a tshark export; each entry is (from client, time, bytes)
+    fn export(steps: &[(bool, f64, Vec<u8>)]) -> String {
+        let mut seq = [1u32, 1u32];
+        steps
+            .iter()
+            .enumerate()
+            .map(|(i, (from_client, time, bytes))| {
+                let (src, dst, seq) = if *from_client {
+                    (CLIENT, SERVER, &mut seq[0])
+                } else {
+                    (SERVER, CLIENT, &mut seq[1])
+                };
+                let line = format!(
+                    "{}\t{}\t{}\t{}\t{}\t{}",
+                    i + 1,
+                    src,
+                    dst,
+                    seq,
+                    hex::encode(bytes),
+                    time
+                );
+                *seq += bytes.len() as u32;
+                line
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    fn frame(opcode: u8) -> Vec<u8> {
+        PacketFrame::new(vec![opcode, 0]).to_bytes()
+    }
+
+    fn run(steps: &[(bool, f64, Vec<u8>)]) -> ConnectionReport {
+        let reassembler = reassemble(&export(steps), SERVER).unwrap();
+        let mut reports = validate(&reassembler, None, 5.0);
+        assert_eq!(reports.len(), 1);
+        reports.remove(0)
+    }
+
+    #[test]
+    fn test_complete_handshake() {
+        let steps: Vec<_> = Handshake::new()
+            .sequence()
+            .into_iter()
+            .enumerate()
+            .map(|(i, (direction, bytes))| {
+                let from_client = direction == Direction::ClientToServer;
+                (from_client, i as f64 * 0.1, bytes)
+            })
+            .collect();
+        let report = run(&steps);
+
+        assert_eq!(report.client_port, CLIENT);
+        assert_eq!(report.events.len(), 11);
+        assert_eq!(report.events[1].kind, Kind::PolicyXml);
+        assert!(report.issues.is_empty(), "{:?}", report.issues);
+    }
+
+    #[test]
+    fn test_missing_out_of_order_and_gap() {
+        let report = run(&[
+            (true, 0.0, frame(0x2F)),
+            (true, 0.1, frame(0x05)),
+            (false, 0.2, frame(0x04)),
+            (false, 0.3, frame(0x06)),
+            (true, 0.4, frame(0x07)),
+            (false, 0.5, frame(0x0A)),
+            (true, 9.0, frame(0x1B)),
+        ]);
+
+        assert_eq!(
+            report.issues,
+            vec![
+                Issue::Missing("policy XML"),
+                Issue::OutOfOrder {
+                    step: "0x05 session key",
+                    frame: 2,
+                    expected_after: "0x04 RSA public key",
+                },
+                Issue::UnansweredHeartbeats(1),
+                Issue::NoGameMessages,
+                Issue::Gap {
+                    after: String::from("#6 S->C 0x0A"),
+                    seconds: 8.5,
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,84 @@
+//! AES-128 throughput of 0x25 payloads at typical packet sizes, raw and
+//! as whole framed packets
+//!
+//! Run with `cargo bench -p ro2-common --bench aes`.
+
+use criterion::{BenchmarkId, Criterion, Throughput, black_box, criterion_group, criterion_main};
+use ro2_common::crypto::{ProudNetCrypto, SharedRng};
+use ro2_common::packet::PacketFrame;
+
+/// Small notifications, a chat line, a zone delta and a near-MTU packet
+const SIZES: [usize; 4] = [32, 128, 512, 1400];
+
+fn crypto() -> ProudNetCrypto {
+    let mut crypto = ProudNetCrypto::new().with_rng(SharedRng::seeded(1));
+    crypto.generate_aes_session_key();
+    crypto
+}
+
+fn encrypt(c: &mut Criterion) {
+    let crypto = crypto();
+    let mut group = c.benchmark_group("aes_encrypt");
+    for size in SIZES {
+        let payload = vec![0x5A; size];
+        group.throughput(Throughput::Bytes(size as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(size), &payload, |b, payload| {
+            b.iter(|| crypto.encrypt_aes_ecb(black_box(payload)).unwrap())
+        });
+    }
+    group.finish();
+}
+
+fn decrypt(c: &mut Criterion) {
+    let crypto = crypto();
+    let mut group = c.benchmark_group("aes_decrypt");
+    for size in SIZES {
+        let encrypted = crypto.encrypt_aes_ecb(&vec![0x5A; size]).unwrap();
+        group.throughput(Throughput::Bytes(size as u64));
+        group.bench_with_input(
+            BenchmarkId::from_parameter(size),
+            &encrypted,
+            |b, encrypted| b.iter(|| crypto.decrypt_aes_ecb(black_box(encrypted)).unwrap()),
+        );
+    }
+    group.finish();
+}
+
+/// A framed 0x25 packet, as the server sends one
+fn packet(crypto: &ProudNetCrypto, message: &[u8]) -> Vec<u8> {
+    let mut payload = vec![0x25, 0x01, 0x01, 0x20];
+    payload.extend(crypto.encrypt_aes_ecb(message).unwrap());
+    PacketFrame::new(payload).to_bytes()
+}
+
+fn encrypt_packet(c: &mut Criterion) {
+    let crypto = crypto();
+    let mut group = c.benchmark_group("packet_encrypt");
+    for size in SIZES {
+        let message = vec![0x5A; size];
+        group.throughput(Throughput::Bytes(size as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(size), &message, |b, message| {
+            b.iter(|| packet(&crypto, black_box(message)))
+        });
+    }
+    group.finish();
+}
+
+fn decrypt_packet(c: &mut Criterion) {
+    let crypto = crypto();
+    let mut group = c.benchmark_group("packet_decrypt");
+    for size in SIZES {
+        let bytes = packet(&crypto, &vec![0x5A; size]);
+        group.throughput(Throughput::Bytes(size as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(size), &bytes, |b, bytes| {
+            b.iter(|| {
+                let (frame, _) = PacketFrame::from_bytes(black_box(bytes)).unwrap();
+                crypto.decrypt_packet_0x25(&frame.payload).unwrap()
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, encrypt, decrypt, encrypt_packet, decrypt_packet);
+criterion_main!(benches);
@@ -0,0 +1,79 @@
+//! PacketFrame encode/decode and varint read/write at typical packet sizes
+//!
+//! Run with `cargo bench -p ro2-common --bench framing`.
+
+use criterion::{BenchmarkId, Criterion, Throughput, black_box, criterion_group, criterion_main};
+use ro2_common::packet::{PacketFrame, read_varint, write_varint};
+use std::io::Cursor;
+
+/// A heartbeat, a chat line, a zone delta and a near-MTU packet
+const SIZES: [usize; 4] = [13, 128, 512, 1400];
+
+/// Packets in a batch read off the socket at once
+const BATCH: usize = 16;
+
+fn encode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("frame_encode");
+    for size in SIZES {
+        let frame = PacketFrame::new(vec![0x25; size]);
+        group.throughput(Throughput::Bytes(size as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(size), &frame, |b, frame| {
+            b.iter(|| black_box(frame).to_bytes())
+        });
+    }
+    group.finish();
+}
+
+fn decode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("frame_decode");
+    for size in SIZES {
+        let bytes = PacketFrame::new(vec![0x25; size]).to_bytes();
+        group.throughput(Throughput::Bytes(bytes.len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(size), &bytes, |b, bytes| {
+            b.iter(|| PacketFrame::from_bytes(black_box(bytes)).unwrap())
+        });
+    }
+    group.finish();
+}
+
+fn decode_batch(c: &mut Criterion) {
+    let mut group = c.benchmark_group("frame_parse_multiple");
+    for size in SIZES {
+        let bytes = PacketFrame::new(vec![0x25; size]).to_bytes().repeat(BATCH);
+        group.throughput(Throughput::Elements(BATCH as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(size), &bytes, |b, bytes| {
+            b.iter(|| PacketFrame::parse_multiple(black_box(bytes)).unwrap())
+        });
+    }
+    group.finish();
+}
+
+/// One value for each width: 1, 2 and 4 bytes
+const VARINTS: [u32; 3] = [0x7F, 0x05DC, 0x0001_0000];
+
+fn varint(c: &mut Criterion) {
+    let mut group = c.benchmark_group("varint_write");
+    for value in VARINTS {
+        group.bench_with_input(BenchmarkId::from_parameter(value), &value, |b, &value| {
+            let mut buf = Vec::with_capacity(5);
+            b.iter(|| {
+                buf.clear();
+                write_varint(&mut buf, black_box(value));
+            })
+        });
+    }
+    group.finish();
+
+    let mut group = c.benchmark_group("varint_read");
+    for value in VARINTS {
+        let mut bytes = Vec::new();
+        write_varint(&mut bytes, value);
+        group.bench_with_input(BenchmarkId::from_parameter(value), &bytes, |b, bytes| {
+            b.iter(|| read_varint(&mut Cursor::new(black_box(bytes.as_slice()))).unwrap())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, encode, decode, decode_batch, varint);
+criterion_main!(benches);
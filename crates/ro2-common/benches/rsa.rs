@@ -0,0 +1,34 @@
+//! RSA session key decryption (0x05) at the key sizes the presets use
+//!
+//! Run with `cargo bench -p ro2-common --bench rsa`.
+
+use criterion::{BenchmarkId, Criterion, black_box, criterion_group, criterion_main};
+use ro2_common::crypto::{ProudNetCrypto, SharedRng};
+
+/// `ro2` and `ro2-strict` use 1024 bits, `rsa2048` 2048
+const KEY_BITS: [usize; 2] = [1024, 2048];
+
+fn decrypt_session_key(c: &mut Criterion) {
+    let mut group = c.benchmark_group("rsa_decrypt_session_key");
+    group.sample_size(20);
+    for bits in KEY_BITS {
+        let mut crypto = ProudNetCrypto::new().with_rng(SharedRng::seeded(1));
+        crypto.generate_rsa_keypair(bits).unwrap();
+        let encrypted = crypto.encrypt_session_key_rsa(&[0x5A; 16]).unwrap();
+        group.bench_with_input(
+            BenchmarkId::from_parameter(bits),
+            &encrypted,
+            |b, encrypted| {
+                b.iter(|| {
+                    crypto
+                        .decrypt_session_key_rsa(black_box(encrypted))
+                        .unwrap()
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, decrypt_session_key);
+criterion_main!(benches);
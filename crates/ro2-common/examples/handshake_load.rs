@@ -0,0 +1,80 @@
+//! Load test: many clients handshaking and sending messages at once
+//!
+//! Opens `clients` concurrent connections to a running server, runs the
+//! ProudNet handshake on each, then sends `messages` encrypted messages
+//! per connection (a blank 0x2EE2 ReqLogin unless `opcode` says
+//! otherwise), and reports the rate. Pair it with a server built with
+//! `--features profiling` to record flame graphs (see `docs/PROFILING.md`):
+//!
+//! ```text
+//! cargo run --release -p ro2-common --features client --example handshake_load -- \
+//!     127.0.0.1:7101 [clients=200] [messages=50] [opcode=0x2EE2]
+//! ```
+
+use anyhow::{Context, Result, anyhow};
+use ro2_common::net::ProudNetClient;
+use ro2_common::protocol::opcodes;
+use std::time::Instant;
+use tokio::net::TcpStream;
+
+/// Payload size of ReqLogin, sent whatever the opcode
+const REQ_LOGIN_LEN: usize = 209;
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let addr = args
+        .first()
+        .cloned()
+        .ok_or_else(|| anyhow!("usage: handshake_load <addr> [clients] [messages] [opcode]"))?;
+    let clients: usize = arg(&args, 1, "clients")?.unwrap_or(200);
+    let messages: usize = arg(&args, 2, "messages")?.unwrap_or(50);
+    let opcode = match args.get(3) {
+        Some(opcode) => u16::from_str_radix(opcode.trim_start_matches("0x"), 16)
+            .context("bad opcode, expected hex")?,
+        None => opcodes::REQ_LOGIN,
+    };
+
+    let started = Instant::now();
+    let tasks: Vec<_> = (0..clients)
+        .map(|_| tokio::spawn(client(addr.clone(), messages, opcode)))
+        .collect();
+    let mut failed = 0;
+    for task in tasks {
+        if let Err(e) = task.await? {
+            failed += 1;
+            eprintln!("client failed: {:#}", e);
+        }
+    }
+
+    let elapsed = started.elapsed().as_secs_f64();
+    let succeeded = clients - failed;
+    println!(
+        "{} of {} clients in {:.2}s: {:.0} handshakes/s, {:.0} messages/s",
+        succeeded,
+        clients,
+        elapsed,
+        succeeded as f64 / elapsed,
+        (succeeded * messages) as f64 / elapsed
+    );
+    Ok(())
+}
+
+fn arg(args: &[String], index: usize, name: &str) -> Result<Option<usize>> {
+    args.get(index)
+        .map(|value| value.parse().with_context(|| format!("bad {}", name)))
+        .transpose()
+}
+
+async fn client(addr: String, messages: usize, opcode: u16) -> Result<()> {
+    let stream = TcpStream::connect(&addr)
+        .await
+        .with_context(|| format!("connecting to {}", addr))?;
+    let mut client = ProudNetClient::connect(stream).await?;
+    let mut message = opcode.to_le_bytes().to_vec();
+    message.extend_from_slice(&[0u8; REQ_LOGIN_LEN]);
+    for _ in 0..messages {
+        client.send_message(&message).await?;
+    }
+    Ok(())
+}
@@ -0,0 +1,114 @@
+//! Where the time comes from
+//!
+//! Expiry, cooldowns and timeouts read the time from a [`Clock`] instead
+//! of calling `Utc::now()` or `Instant::now()` themselves. Servers use the
+//! system clock; tests use a [`manual`](Clock::manual) one that stands
+//! still until [`advance`](Clock::advance)d, so a thirty-second timeout
+//! can be checked without waiting thirty seconds.
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Cloneable handle to a clock; clones of a manual clock move together
+#[derive(Clone, Default)]
+pub struct Clock(Source);
+
+#[derive(Clone, Default)]
+enum Source {
+    #[default]
+    System,
+    Manual(Arc<Mutex<ManualTime>>),
+}
+
+struct ManualTime {
+    wall: DateTime<Utc>,
+    instant: Instant,
+}
+
+impl Clock {
+    /// The operating system's clock
+    pub fn system() -> Self {
+        Self(Source::System)
+    }
+
+    /// A clock stopped at `start` until advanced, for tests. Its local
+    /// time is UTC, so tests don't depend on the machine's time zone.
+    pub fn manual(start: DateTime<Utc>) -> Self {
+        Self(Source::Manual(Arc::new(Mutex::new(ManualTime {
+            wall: start,
+            instant: Instant::now(),
+        }))))
+    }
+
+    /// A manual clock at `secs` seconds since the Unix epoch
+    pub fn at_unix(secs: i64) -> Self {
+        Self::manual(DateTime::from_timestamp(secs, 0).expect("timestamp in range"))
+    }
+
+    /// Move a manual clock forward by `by`
+    ///
+    /// # Panics
+    ///
+    /// On the system clock, which can't be moved
+    pub fn advance(&self, by: Duration) {
+        let Source::Manual(time) = &self.0 else {
+            panic!("only a manual clock can be advanced");
+        };
+        let mut time = time.lock().unwrap();
+        time.wall += by;
+        time.instant += by;
+    }
+
+    /// Wall-clock time
+    pub fn now(&self) -> DateTime<Utc> {
+        match &self.0 {
+            Source::System => Utc::now(),
+            Source::Manual(time) => time.lock().unwrap().wall,
+        }
+    }
+
+    /// Monotonic time, for measuring intervals
+    pub fn instant(&self) -> Instant {
+        match &self.0 {
+            Source::System => Instant::now(),
+            Source::Manual(time) => time.lock().unwrap().instant,
+        }
+    }
+
+    /// Seconds since the Unix epoch
+    pub fn unix(&self) -> i64 {
+        self.now().timestamp()
+    }
+
+    /// Local time, for limits that follow the calendar day
+    pub fn local(&self) -> NaiveDateTime {
+        match &self.0 {
+            Source::System => chrono::Local::now().naive_local(),
+            Source::Manual(time) => time.lock().unwrap().wall.naive_utc(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_manual_clock() {
+        let clock = Clock::at_unix(1_000);
+        let instant = clock.instant();
+        let copy = clock.clone();
+
+        copy.advance(Duration::from_secs(90));
+        assert_eq!(clock.unix(), 1_090);
+        assert_eq!(clock.instant() - instant, Duration::from_secs(90));
+        assert_eq!(clock.local().and_utc().timestamp(), 1_090);
+    }
+
+    #[test]
+    #[should_panic(expected = "only a manual clock")]
+    fn test_system_clock_cannot_advance() {
+        Clock::system().advance(Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,488 @@
+//! Server configuration
+//!
+//! Each server binary reads `config/<name>.toml` when it exists; every key
+//! is optional:
+//!
+//! ```toml
+//! # Address to listen on. An IPv6 address such as "[::]:7101" accepts
+//! # IPv4 clients too unless `ipv6_only` is set.
+//! bind = "0.0.0.0:7101"
+//! ipv6_only = false
+//!
+//! # Hex-encoded secret, at least 32 bytes, signing the tokens the lobby
+//! # hands clients for the world server (see `session`). The lobby and
+//! # world servers need the same one.
+//! transfer_secret = "<64+ hex digits>"
+//!
+//! # ProudNet handshake preset (see `ProudNetSettings::preset`): "ro2"
+//! # (default), "ro2-strict" or "rsa2048". The RSA key size and accepted
+//! # session key paddings can be overridden on top of it.
+//! proudnet_preset = "ro2"
+//! rsa_key_bits = 1024
+//! rsa_paddings = ["oaep-sha1", "pkcs1v15", "oaep-sha256"]
+//!
+//! # More listeners, e.g. an alternate port or an IPv6 address.
+//! # Connections are tagged with the name of the listener they came in on.
+//! [[listeners]]
+//! name = "alt"
+//! bind = "0.0.0.0:7102"
+//!
+//! [[listeners]]
+//! name = "ipv6"
+//! bind = "[::]:7102"
+//! ipv6_only = true
+//! ```
+//!
+//! ## Environment variables
+//!
+//! Any key of a server's config, in any section, can be set with an
+//! `RO2_` environment variable instead, which wins over the file, so
+//! containers can run without a config file baked into the image. The
+//! name is the key in capitals with `__` between sections:
+//!
+//! ```text
+//! RO2_BIND=0.0.0.0:7101
+//! RO2_TRANSFER_SECRET=<64+ hex digits>
+//! RO2_AUTH__BACKEND=http
+//! RO2_LOGGING__FORMAT=json
+//! ```
+//!
+//! With `_FILE` on the end the value is read from that file instead, for
+//! secrets mounted by the orchestrator (`RO2_TRANSFER_SECRET_FILE=/run/secrets/transfer`).
+//! Lists (`listeners`, `rsa_paddings`, webhooks) can only be set in the
+//! file. The database is given by `DATABASE_URL` or `DATABASE_URL_FILE`
+//! (see [`database_url`]).
+
+use crate::Result;
+use crate::crypto::RsaPadding;
+use crate::protocol::ProudNetSettings;
+use anyhow::{Context, anyhow};
+use config::{Config, ConfigError, File, FileFormat, Map, Source, Value, ValueKind};
+use serde::Deserialize;
+use serde::de::DeserializeOwned;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::path::Path;
+
+/// Prefix of environment variables setting config keys
+pub const ENV_PREFIX: &str = "RO2_";
+
+/// Config keys from `RO2_` environment variables
+#[derive(Debug, Clone, Default)]
+pub struct EnvOverrides {
+    vars: Vec<(String, String)>,
+}
+
+impl EnvOverrides {
+    /// The process's environment
+    pub fn from_env() -> Self {
+        Self::from_vars(std::env::vars())
+    }
+
+    /// Variables given by name and value, e.g. in tests
+    pub fn from_vars(vars: impl IntoIterator<Item = (String, String)>) -> Self {
+        Self {
+            vars: vars
+                .into_iter()
+                .filter(|(name, _)| name.starts_with(ENV_PREFIX))
+                .collect(),
+        }
+    }
+}
+
+impl Source for EnvOverrides {
+    fn clone_into_box(&self) -> Box<dyn Source + Send + Sync> {
+        Box::new(self.clone())
+    }
+
+    fn collect(&self) -> std::result::Result<Map<String, Value>, ConfigError> {
+        let mut keys = Map::new();
+        for (name, value) in &self.vars {
+            let key = &name[ENV_PREFIX.len()..];
+            let (key, value) = match key.strip_suffix("_FILE") {
+                Some(key) => {
+                    let contents = std::fs::read_to_string(value).map_err(|e| {
+                        ConfigError::Message(format!("{}: reading {}: {}", name, value, e))
+                    })?;
+                    (key, contents.trim_end().to_string())
+                }
+                None => (key, value.clone()),
+            };
+            let key = key.to_lowercase().replace("__", ".");
+            keys.insert(key, Value::new(Some(name), ValueKind::String(value)));
+        }
+        Ok(keys)
+    }
+}
+
+/// The TOML file at `path` if it exists, with `RO2_` environment
+/// variables over it
+pub fn file_and_env(path: &Path) -> Vec<Box<dyn Source + Send + Sync>> {
+    vec![
+        Box::new(File::from(path).format(FileFormat::Toml).required(false)),
+        Box::new(EnvOverrides::from_env()),
+    ]
+}
+
+/// Config read by [`section`] or [`load`], checked once it's parsed
+pub trait Validate: DeserializeOwned {
+    /// Refuse values that parse but can't be used; anything goes unless
+    /// overridden
+    fn validate(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Read the `[name]` section of the TOML file at `path`, with `RO2_`
+/// environment variables over it; a missing file, section or key keeps
+/// the defaults
+pub fn section<T: Validate + Default>(path: impl AsRef<Path>, name: &str) -> Result<T> {
+    let path = path.as_ref();
+    read_section(file_and_env(path), name)
+        .with_context(|| format!("loading [{}] from {}", name, path.display()))
+}
+
+/// Parse the `[name]` section of TOML text
+pub fn section_from_toml<T: Validate + Default>(text: &str, name: &str) -> Result<T> {
+    read_section(File::from_str(text, FileFormat::Toml), name)
+}
+
+fn read_section<T: Validate + Default>(
+    source: impl Source + Send + Sync + 'static,
+    name: &str,
+) -> Result<T> {
+    let config = Config::builder().add_source(source).build()?;
+    let section = match config.get::<T>(name) {
+        Ok(section) => section,
+        Err(ConfigError::NotFound(_)) => T::default(),
+        Err(e) => return Err(e.into()),
+    };
+    section.validate()?;
+    Ok(section)
+}
+
+/// Read the whole TOML file at `path`, such as a data file; a missing
+/// file reads as an empty one
+pub fn load<T: Validate>(path: impl AsRef<Path>) -> Result<T> {
+    let path = path.as_ref();
+    read(File::from(path).format(FileFormat::Toml).required(false))
+        .with_context(|| format!("loading {}", path.display()))
+}
+
+/// Parse a whole TOML document
+pub fn from_toml<T: Validate>(text: &str) -> Result<T> {
+    read(File::from_str(text, FileFormat::Toml))
+}
+
+fn read<T: Validate>(source: impl Source + Send + Sync + 'static) -> Result<T> {
+    let value: T = Config::builder()
+        .add_source(source)
+        .build()?
+        .try_deserialize()?;
+    value.validate()?;
+    Ok(value)
+}
+
+/// The database to use: `DATABASE_URL`, or the contents of the file
+/// named by `DATABASE_URL_FILE`
+pub fn database_url() -> Result<Option<String>> {
+    if let Ok(url) = std::env::var("DATABASE_URL") {
+        return Ok(Some(url));
+    }
+    match std::env::var("DATABASE_URL_FILE") {
+        Ok(path) => std::fs::read_to_string(&path)
+            .map(|url| Some(url.trim().to_string()))
+            .with_context(|| format!("reading DATABASE_URL_FILE {}", path)),
+        Err(_) => Ok(None),
+    }
+}
+
+/// Settings shared by every server binary
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct ServerConfig {
+    /// Address to listen on
+    pub bind: SocketAddr,
+
+    /// Refuse IPv4 clients when `bind` is an IPv6 address
+    #[serde(default)]
+    pub ipv6_only: bool,
+
+    /// Listeners besides `bind`
+    #[serde(default)]
+    pub listeners: Vec<ListenerConfig>,
+
+    /// Hex-encoded key signing lobby-to-world transfer tokens
+    #[serde(default)]
+    pub transfer_secret: Option<String>,
+
+    /// Name of the [`ProudNetSettings`] preset to start from
+    #[serde(default)]
+    pub proudnet_preset: Option<String>,
+
+    /// RSA key size, instead of the preset's
+    #[serde(default)]
+    pub rsa_key_bits: Option<usize>,
+
+    /// Session key paddings to accept, instead of the preset's
+    #[serde(default)]
+    pub rsa_paddings: Option<Vec<RsaPadding>>,
+}
+
+/// One address a server accepts clients on
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct ListenerConfig {
+    /// Tag for connections accepted here, used in logs
+    pub name: String,
+
+    /// Address to listen on
+    pub bind: SocketAddr,
+
+    /// Refuse IPv4 clients when `bind` is an IPv6 address; otherwise the
+    /// socket is dual-stack on every platform
+    #[serde(default)]
+    pub ipv6_only: bool,
+}
+
+impl ServerConfig {
+    /// Defaults for a server listening on `port`
+    pub fn new(port: u16) -> Self {
+        Self {
+            bind: SocketAddr::from((Ipv4Addr::UNSPECIFIED, port)),
+            ipv6_only: false,
+            listeners: Vec::new(),
+            transfer_secret: None,
+            proudnet_preset: None,
+            rsa_key_bits: None,
+            rsa_paddings: None,
+        }
+    }
+
+    /// The decoded transfer secret, if one is set
+    pub fn transfer_secret(&self) -> Result<Option<Vec<u8>>> {
+        self.transfer_secret
+            .as_deref()
+            .map(|secret| hex::decode(secret.trim()).context("transfer_secret is not hex"))
+            .transpose()
+    }
+
+    /// ProudNet settings of the configured preset and overrides
+    pub fn proudnet_settings(&self) -> Result<ProudNetSettings> {
+        let preset = self.proudnet_preset.as_deref().unwrap_or("ro2");
+        let mut settings = ProudNetSettings::preset(preset).ok_or_else(|| {
+            anyhow!(
+                "unknown proudnet_preset {:?}, expected one of {:?}",
+                preset,
+                ProudNetSettings::PRESETS
+            )
+        })?;
+        if let Some(bits) = self.rsa_key_bits {
+            if !(1024..=4096).contains(&bits) {
+                return Err(anyhow!("rsa_key_bits must be 1024 to 4096"));
+            }
+            settings.rsa_key_bits = bits;
+        }
+        if let Some(paddings) = &self.rsa_paddings {
+            if paddings.is_empty() {
+                return Err(anyhow!("rsa_paddings can't be empty"));
+            }
+            settings.rsa_paddings = paddings.clone();
+        }
+        Ok(settings)
+    }
+
+    /// Every listener, starting with `bind` as `"default"`
+    pub fn all_listeners(&self) -> Vec<ListenerConfig> {
+        let default = ListenerConfig {
+            name: "default".to_string(),
+            bind: self.bind,
+            ipv6_only: self.ipv6_only,
+        };
+        std::iter::once(default)
+            .chain(self.listeners.iter().cloned())
+            .collect()
+    }
+
+    /// Read `path` and the environment over the defaults for `port`; a
+    /// missing file is fine
+    pub fn load(path: impl AsRef<Path>, port: u16) -> Result<Self> {
+        let path = path.as_ref();
+        Self::build(file_and_env(path), port).with_context(|| format!("loading {}", path.display()))
+    }
+
+    /// Parse TOML text over the defaults for `port`
+    pub fn from_toml(text: &str, port: u16) -> Result<Self> {
+        Self::build(File::from_str(text, FileFormat::Toml), port)
+    }
+
+    fn build(source: impl Source + Send + Sync + 'static, port: u16) -> Result<Self> {
+        Ok(Config::builder()
+            .set_default("bind", Self::new(port).bind.to_string())?
+            .add_source(source)
+            .build()?
+            .try_deserialize()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Default, PartialEq, Deserialize)]
+    #[serde(default)]
+    struct Limits {
+        max: u32,
+        name: String,
+    }
+
+    impl Validate for Limits {
+        fn validate(&self) -> Result<()> {
+            if self.max > 10 {
+                return Err(anyhow!("max over 10"));
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_section() {
+        let limits: Limits = section_from_toml("[limits]\nmax = 3", "limits").unwrap();
+        assert_eq!((limits.max, limits.name.as_str()), (3, ""));
+        // A missing section or file keeps the defaults
+        assert_eq!(
+            section_from_toml::<Limits>("[other]\nmax = 3", "limits").unwrap(),
+            Limits::default()
+        );
+        assert_eq!(
+            section::<Limits>("does/not/exist.toml", "limits").unwrap(),
+            Limits::default()
+        );
+        assert!(section_from_toml::<Limits>("[limits]\nmax = 11", "limits").is_err());
+        assert!(section_from_toml::<Limits>("[limits]\nmax = \"x\"", "limits").is_err());
+
+        let limits: Limits = from_toml("max = 4\nname = \"a\"").unwrap();
+        assert_eq!((limits.max, limits.name.as_str()), (4, "a"));
+        assert_eq!(
+            load::<Limits>("does/not/exist.toml").unwrap(),
+            Limits::default()
+        );
+        assert!(from_toml::<Limits>("max = 11").is_err());
+    }
+
+    #[test]
+    fn test_defaults_and_overrides() {
+        assert_eq!(
+            ServerConfig::load("does/not/exist.toml", 7101).unwrap(),
+            ServerConfig::new(7101)
+        );
+
+        let config = ServerConfig::from_toml(r#"bind = "127.0.0.1:7201""#, 7101).unwrap();
+        assert_eq!(config.bind, "127.0.0.1:7201".parse().unwrap());
+
+        assert!(ServerConfig::from_toml(r#"bind = "nonsense""#, 7101).is_err());
+    }
+
+    #[test]
+    fn test_transfer_secret() {
+        assert_eq!(ServerConfig::new(7201).transfer_secret().unwrap(), None);
+        let config = ServerConfig::from_toml(r#"transfer_secret = "00ff10""#, 7201).unwrap();
+        assert_eq!(
+            config.transfer_secret().unwrap(),
+            Some(vec![0x00, 0xFF, 0x10])
+        );
+        let config = ServerConfig::from_toml(r#"transfer_secret = "xyz""#, 7201).unwrap();
+        assert!(config.transfer_secret().is_err());
+    }
+
+    #[test]
+    fn test_proudnet_settings() {
+        let settings = ServerConfig::new(7101).proudnet_settings().unwrap();
+        assert_eq!(settings.rsa_key_bits, 1024);
+        assert_eq!(settings.rsa_paddings, RsaPadding::ALL);
+
+        let config = ServerConfig::from_toml(
+            r#"
+            proudnet_preset = "ro2-strict"
+            rsa_paddings = ["oaep-sha256", "oaep-sha1"]
+            "#,
+            7101,
+        )
+        .unwrap();
+        let settings = config.proudnet_settings().unwrap();
+        assert_eq!(
+            settings.rsa_paddings,
+            [RsaPadding::OaepSha256, RsaPadding::OaepSha1]
+        );
+
+        for bad in [
+            r#"proudnet_preset = "ro3""#,
+            "rsa_key_bits = 512",
+            "rsa_paddings = []",
+        ] {
+            let config = ServerConfig::from_toml(bad, 7101).unwrap();
+            assert!(config.proudnet_settings().is_err(), "{}", bad);
+        }
+        assert!(ServerConfig::from_toml(r#"rsa_paddings = ["rot13"]"#, 7101).is_err());
+    }
+
+    #[test]
+    fn test_env_overrides() {
+        let secret = std::env::temp_dir().join(format!("ro2-secret-{}", std::process::id()));
+        std::fs::write(&secret, "00ff10\n").unwrap();
+        let env = EnvOverrides::from_vars([
+            ("RO2_BIND".to_string(), "127.0.0.1:9000".to_string()),
+            ("RO2_IPV6_ONLY".to_string(), "true".to_string()),
+            (
+                "RO2_TRANSFER_SECRET_FILE".to_string(),
+                secret.display().to_string(),
+            ),
+            ("RO2_AUTH__API_KEY".to_string(), "key".to_string()),
+            ("HOME".to_string(), "/root".to_string()),
+        ]);
+        let sources: Vec<Box<dyn Source + Send + Sync>> = vec![
+            Box::new(File::from_str(
+                "bind = \"0.0.0.0:7101\"\nrsa_key_bits = 2048",
+                FileFormat::Toml,
+            )),
+            Box::new(env.clone()),
+        ];
+        let config = ServerConfig::build(sources, 7101).unwrap();
+        std::fs::remove_file(&secret).unwrap();
+
+        assert_eq!(config.bind, "127.0.0.1:9000".parse().unwrap());
+        assert!(config.ipv6_only);
+        assert_eq!(config.rsa_key_bits, Some(2048));
+        assert_eq!(
+            config.transfer_secret().unwrap(),
+            Some(vec![0x00, 0xFF, 0x10])
+        );
+
+        // Sections nest on `__`; the secret file is gone now
+        assert!(env.collect().is_err());
+        let keys = EnvOverrides::from_vars([("RO2_AUTH__API_KEY".to_string(), "key".to_string())])
+            .collect()
+            .unwrap();
+        assert_eq!(keys["auth.api_key"].clone().into_string().unwrap(), "key");
+    }
+
+    #[test]
+    fn test_extra_listeners() {
+        let config = ServerConfig::from_toml(
+            r#"
+            [[listeners]]
+            name = "ipv6"
+            bind = "[::]:7101"
+            ipv6_only = true
+            "#,
+            7101,
+        )
+        .unwrap();
+
+        let listeners = config.all_listeners();
+        assert_eq!(listeners.len(), 2);
+        assert_eq!(listeners[0].name, "default");
+        assert_eq!(listeners[0].bind, "0.0.0.0:7101".parse().unwrap());
+        assert_eq!(listeners[1].name, "ipv6");
+        assert_eq!(listeners[1].bind, "[::]:7101".parse().unwrap());
+        assert!(!listeners[0].ipv6_only);
+        assert!(listeners[1].ipv6_only);
+    }
+}
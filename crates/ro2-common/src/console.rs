@@ -0,0 +1,425 @@
+//! Local admin console
+//!
+//! A [`Console`] takes one command per line from an operator and prints
+//! the answer, for poking at a running server without a client. It
+//! listens where the `[console]` section of the server's config says, and
+//! nowhere without it:
+//!
+//! ```toml
+//! [console]
+//! socket = "run/world.sock"   # a Unix socket, readable by its owner only
+//! bind = "127.0.0.1:7499"     # telnet-style; needs a password
+//! password = "change me"
+//! dashboard = "127.0.0.1:7498" # live event feed over WebSocket; needs a password
+//! ```
+//!
+//! Every console knows `help`, `sessions`, `kick <session> [reason]`,
+//! `trace on|off` (log every frame in hex) and `quit`; servers add their
+//! own with [`Console::command`]. The dashboard feed is described in
+//! [`crate::dashboard`].
+
+use crate::Result;
+use crate::config::{self, Validate};
+use crate::crypto::constant_time_eq;
+use crate::dashboard;
+use crate::events::EventBus;
+use crate::net::ConnectionRegistry;
+use anyhow::{Context, anyhow};
+use async_trait::async_trait;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpListener;
+use tracing::{error, info, warn};
+
+/// Commands every console knows
+const BUILT_IN: [&str; 6] = ["help", "sessions", "kick", "trace", "quit", "exit"];
+
+/// Reason given to kicked players when the operator doesn't give one
+const DEFAULT_KICK_REASON: &str = "kicked by an operator";
+
+/// Where the console listens
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct ConsoleConfig {
+    /// Unix socket path
+    pub socket: Option<PathBuf>,
+    /// TCP address, for telnet or netcat
+    pub bind: Option<SocketAddr>,
+    /// Asked for before anything else; required with `bind` and
+    /// `dashboard`
+    pub password: Option<String>,
+    /// TCP address of the WebSocket event feed
+    pub dashboard: Option<SocketAddr>,
+}
+
+impl ConsoleConfig {
+    /// Read the `[console]` section of `path`
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        config::section(path, "console")
+    }
+
+    /// Parse the `[console]` section of TOML text
+    pub fn from_toml(text: &str) -> Result<Self> {
+        config::section_from_toml(text, "console")
+    }
+
+    /// Whether the console listens anywhere
+    pub fn is_enabled(&self) -> bool {
+        self.socket.is_some() || self.bind.is_some() || self.dashboard.is_some()
+    }
+}
+
+impl Validate for ConsoleConfig {
+    fn validate(&self) -> Result<()> {
+        if (self.bind.is_some() || self.dashboard.is_some())
+            && self.password.as_deref().is_none_or(str::is_empty)
+        {
+            return Err(anyhow!("console bind and dashboard need a password"));
+        }
+        Ok(())
+    }
+}
+
+/// A server-specific console command
+#[async_trait]
+pub trait ConsoleCommand: Send + Sync {
+    /// Its arguments, shown by `help`
+    fn usage(&self) -> &'static str {
+        ""
+    }
+
+    /// Run with the words after the command's name; returns what to print
+    async fn run(&self, args: &[&str]) -> Result<String>;
+}
+
+/// Answers operator commands
+pub struct Console {
+    connections: Arc<ConnectionRegistry>,
+    commands: BTreeMap<String, Arc<dyn ConsoleCommand>>,
+    /// What the dashboard feed sends
+    events: Option<EventBus>,
+}
+
+impl Console {
+    /// A console with the built-in commands, acting on `connections`
+    pub fn new(connections: Arc<ConnectionRegistry>) -> Self {
+        Self {
+            connections,
+            commands: BTreeMap::new(),
+            events: None,
+        }
+    }
+
+    /// Send what's published on `events` to dashboard clients
+    pub fn with_events(mut self, events: EventBus) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    /// Add `name`, replacing any earlier command by that name
+    pub fn command(mut self, name: &str, command: impl ConsoleCommand + 'static) -> Self {
+        self.commands.insert(name.to_string(), Arc::new(command));
+        self
+    }
+
+    /// Whether `name` is taken, by a built-in command or an added one
+    pub fn has_command(&self, name: &str) -> bool {
+        BUILT_IN.contains(&name) || self.commands.contains_key(name)
+    }
+
+    /// Run one command line; returns what to print
+    pub async fn execute(&self, line: &str) -> String {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let Some((&name, args)) = words.split_first() else {
+            return String::new();
+        };
+
+        let result = match (name, self.commands.get(name)) {
+            (_, Some(command)) => command.run(args).await,
+            ("help", None) => Ok(self.help()),
+            ("sessions", None) => Ok(self.sessions()),
+            ("kick", None) => self.kick(args),
+            ("trace", None) => self.trace(args),
+            _ => Err(anyhow!("unknown command {:?}, try help", name)),
+        };
+        result.unwrap_or_else(|e| format!("error: {:#}", e))
+    }
+
+    fn help(&self) -> String {
+        let mut help =
+            String::from("help\nsessions\nkick <session> [reason]\ntrace [on|off]\nquit\n");
+        for (name, command) in &self.commands {
+            let _ = writeln!(help, "{} {}", name, command.usage());
+        }
+        help.lines()
+            .map(str::trim_end)
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    fn sessions(&self) -> String {
+        let sessions = self.connections.sessions();
+        let mut list = format!("{} sessions", sessions.len());
+        for session in sessions {
+            let _ = write!(
+                list,
+                "\n{} {} account {}",
+                session.session_id,
+                session.remote_addr.as_deref().unwrap_or("-"),
+                session
+                    .account_id
+                    .map_or_else(|| "-".to_string(), |id| id.to_string())
+            );
+        }
+        list
+    }
+
+    fn kick(&self, args: &[&str]) -> Result<String> {
+        let (session, reason) = args
+            .split_first()
+            .ok_or_else(|| anyhow!("usage: kick <session> [reason]"))?;
+        let session_id: u64 = session.parse().context("bad session id")?;
+        let reason = match reason.join(" ") {
+            reason if reason.is_empty() => DEFAULT_KICK_REASON.to_string(),
+            reason => reason,
+        };
+        if !self.connections.kick(session_id, &reason) {
+            return Err(anyhow!("no session {} to kick", session_id));
+        }
+        info!("Console kicked session {}: {}", session_id, reason);
+        Ok(format!("kicked {}", session_id))
+    }
+
+    fn trace(&self, args: &[&str]) -> Result<String> {
+        match args {
+            [] => {}
+            ["on"] => self.connections.set_wire_trace(true),
+            ["off"] => self.connections.set_wire_trace(false),
+            _ => return Err(anyhow!("usage: trace [on|off]")),
+        }
+        let state = if self.connections.wire_trace() {
+            "on"
+        } else {
+            "off"
+        };
+        Ok(format!("wire trace {}", state))
+    }
+
+    /// Talk to one operator until they quit or hang up, asking for
+    /// `password` first if there is one
+    pub async fn serve<S: AsyncRead + AsyncWrite + Unpin>(
+        &self,
+        stream: S,
+        password: Option<&str>,
+    ) -> Result<()> {
+        let (reader, mut writer) = tokio::io::split(stream);
+        let mut lines = BufReader::new(reader).lines();
+
+        if let Some(password) = password {
+            writer.write_all(b"Password: ").await?;
+            let given = lines.next_line().await?.unwrap_or_default();
+            if !constant_time_eq(given.trim().as_bytes(), password.as_bytes()) {
+                warn!("Console login with a wrong password");
+                writer.write_all(b"Wrong password\n").await?;
+                return Ok(());
+            }
+        }
+
+        loop {
+            writer.write_all(b"> ").await?;
+            let Some(line) = lines.next_line().await? else {
+                return Ok(());
+            };
+            let line = line.trim();
+            if matches!(line, "quit" | "exit") {
+                return Ok(());
+            }
+            if !line.is_empty() {
+                info!("Console: {}", line);
+            }
+            let mut output = self.execute(line).await;
+            if !output.is_empty() {
+                output.push('\n');
+            }
+            writer.write_all(output.as_bytes()).await?;
+        }
+    }
+
+    /// Listen where `config` says, serving each operator in its own task
+    pub async fn start(mut self, config: &ConsoleConfig) -> Result<()> {
+        // Only a weak handle stays, so the bus still closes on shutdown
+        let events = self.events.take();
+        if let Some(addr) = config.dashboard {
+            match events {
+                Some(events) => {
+                    let password = config.password.clone().unwrap_or_default();
+                    dashboard::start(addr, password, events.downgrade()).await?;
+                }
+                None => warn!("This server has no dashboard feed, ignoring console.dashboard"),
+            }
+        }
+        let console = Arc::new(self);
+
+        #[cfg(unix)]
+        if let Some(path) = &config.socket {
+            let listener = bind_socket(path)?;
+            info!("Console listening on {}", path.display());
+            let console = Arc::clone(&console);
+            tokio::spawn(async move {
+                loop {
+                    match listener.accept().await {
+                        Ok((stream, _)) => {
+                            let console = Arc::clone(&console);
+                            tokio::spawn(async move {
+                                if let Err(e) = console.serve(stream, None).await {
+                                    warn!("Console session failed: {}", e);
+                                }
+                            });
+                        }
+                        Err(e) => error!("Console accept failed: {}", e),
+                    }
+                }
+            });
+        }
+        #[cfg(not(unix))]
+        if config.socket.is_some() {
+            warn!("Console sockets need Unix, ignoring console.socket");
+        }
+
+        if let Some(addr) = config.bind {
+            let listener = TcpListener::bind(addr)
+                .await
+                .with_context(|| format!("binding console to {}", addr))?;
+            info!("Console listening on {}", addr);
+            let password = config.password.clone().unwrap_or_default();
+            tokio::spawn(async move {
+                loop {
+                    match listener.accept().await {
+                        Ok((stream, peer)) => {
+                            info!("Console connection from {}", peer);
+                            let console = Arc::clone(&console);
+                            let password = password.clone();
+                            tokio::spawn(async move {
+                                if let Err(e) = console.serve(stream, Some(&password)).await {
+                                    warn!("Console session failed: {}", e);
+                                }
+                            });
+                        }
+                        Err(e) => error!("Console accept failed: {}", e),
+                    }
+                }
+            });
+        }
+
+        Ok(())
+    }
+}
+
+/// Bind a Unix socket at `path` that only its owner can connect to,
+/// replacing one a previous run left behind
+#[cfg(unix)]
+fn bind_socket(path: &Path) -> Result<tokio::net::UnixListener> {
+    use std::os::unix::fs::PermissionsExt;
+
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        std::fs::create_dir_all(dir)?;
+    }
+    match std::fs::remove_file(path) {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e).with_context(|| format!("removing {}", path.display())),
+    }
+    let listener = tokio::net::UnixListener::bind(path)
+        .with_context(|| format!("binding console to {}", path.display()))?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+    Ok(listener)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncReadExt;
+    use tokio::sync::mpsc;
+
+    struct Echo;
+
+    #[async_trait]
+    impl ConsoleCommand for Echo {
+        fn usage(&self) -> &'static str {
+            "<text>"
+        }
+
+        async fn run(&self, args: &[&str]) -> Result<String> {
+            Ok(args.join(" "))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_builtin_and_server_commands() {
+        let connections = Arc::new(ConnectionRegistry::new());
+        let (outbox, _outbox_rx) = mpsc::channel(1);
+        let (kick, mut kick_rx) = mpsc::channel(1);
+        connections.register_connection(5, outbox, "127.0.0.1:40000".to_string(), kick);
+        let console = Console::new(Arc::clone(&connections)).command("echo", Echo);
+
+        assert_eq!(
+            console.execute("sessions").await,
+            "1 sessions\n5 127.0.0.1:40000 account -"
+        );
+        assert_eq!(console.execute("kick 5 too loud").await, "kicked 5");
+        assert_eq!(kick_rx.try_recv().unwrap(), "too loud");
+        assert_eq!(
+            console.execute("kick 6").await,
+            "error: no session 6 to kick"
+        );
+
+        assert_eq!(console.execute("trace on").await, "wire trace on");
+        assert!(connections.wire_trace());
+        assert_eq!(console.execute("trace off").await, "wire trace off");
+
+        assert_eq!(console.execute("echo hello  there").await, "hello there");
+        assert!(console.execute("help").await.ends_with("echo <text>"));
+        assert!(console.execute("reboot").await.starts_with("error: "));
+        assert!(console.has_command("echo") && console.has_command("kick"));
+        assert!(!console.has_command("reboot"));
+    }
+
+    #[tokio::test]
+    async fn test_serve_asks_for_the_password() {
+        let console = Console::new(Arc::new(ConnectionRegistry::new()));
+
+        let (mut client, server) = tokio::io::duplex(1024);
+        client.write_all(b"nope\n").await.unwrap();
+        console.serve(server, Some("secret")).await.unwrap();
+        let mut output = String::new();
+        client.read_to_string(&mut output).await.unwrap();
+        assert_eq!(output, "Password: Wrong password\n");
+
+        let (mut client, server) = tokio::io::duplex(1024);
+        client.write_all(b"secret\ntrace\nquit\n").await.unwrap();
+        console.serve(server, Some("secret")).await.unwrap();
+        drop(console);
+        let mut output = String::new();
+        client.read_to_string(&mut output).await.unwrap();
+        assert_eq!(output, "Password: > wire trace off\n> ");
+    }
+
+    #[test]
+    fn test_console_config() {
+        assert!(!ConsoleConfig::from_toml("").unwrap().is_enabled());
+        let config = ConsoleConfig::from_toml("[console]\nsocket = \"run/login.sock\"").unwrap();
+        assert_eq!(config.socket, Some(PathBuf::from("run/login.sock")));
+        assert!(ConsoleConfig::from_toml("[console]\nbind = \"127.0.0.1:7499\"").is_err());
+        assert!(ConsoleConfig::from_toml("[console]\ndashboard = \"127.0.0.1:7498\"").is_err());
+        assert!(
+            ConsoleConfig::from_toml("[console]\nbind = \"127.0.0.1:7499\"\npassword = \"x\"")
+                .unwrap()
+                .is_enabled()
+        );
+    }
+}
@@ -0,0 +1,26 @@
+//! Comparing secrets
+//!
+//! Comparing tokens, keys or MACs with `==` stops at the first byte that
+//! differs, so how long a wrong guess takes to reject tells an attacker how
+//! much of it was right. Compare them with [`constant_time_eq`] instead.
+
+use subtle::ConstantTimeEq;
+
+/// Whether `a` and `b` hold the same bytes, taking the same time wherever
+/// they differ; only their lengths are compared early
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.ct_eq(b).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_constant_time_eq() {
+        assert!(constant_time_eq(b"session", b"session"));
+        assert!(constant_time_eq(b"", b""));
+        assert!(!constant_time_eq(b"session", b"sessiom"));
+        assert!(!constant_time_eq(b"session", b"sessio"));
+    }
+}
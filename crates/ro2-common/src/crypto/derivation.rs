@@ -0,0 +1,190 @@
+//! Candidate AES key derivations, for pinning down what the client does
+//!
+//! The server takes the first 16 bytes of the RSA-decrypted 0x05 payload
+//! as the AES key. Our own client does the same, but nothing confirms the
+//! official client does: it could hash the payload, use another block of
+//! it, or mix in one of the GUIDs exchanged during the handshake.
+//! [`identify`] tries every [`KeyDerivation`] on a captured 0x25 whose
+//! plaintext starts with something known (usually the opcode) and reports
+//! the ones whose key decrypts it. `packet-analyzer derive-key` runs it
+//! over a capture.
+//!
+//! This is research tooling; the server never derives keys this way.
+
+use super::ProudNetCrypto;
+use sha1::{Digest, Sha1};
+use std::fmt;
+
+/// What a key could be derived from, gathered from one handshake
+#[derive(Debug, Clone, Copy, Default)]
+pub struct KeyMaterial<'a> {
+    /// RSA-decrypted 0x05 payload
+    pub session: &'a [u8],
+    /// GUID the client sent in 0x07
+    pub client_guid: Option<[u8; 16]>,
+    /// GUID the server sent in 0x0A
+    pub server_guid: Option<[u8; 16]>,
+}
+
+/// One way the client might turn the session payload into its AES key
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyDerivation {
+    /// The payload itself, when it is exactly 16 bytes
+    Raw,
+    /// The first 16 bytes of the payload (what the server does)
+    FirstBlock,
+    /// The last 16 bytes of the payload
+    LastBlock,
+    /// The first 16 bytes of SHA-1 over the payload
+    Sha1,
+    /// The first block XORed with the client's GUID
+    XorClientGuid,
+    /// The first block XORed with the server's GUID
+    XorServerGuid,
+}
+
+impl KeyDerivation {
+    pub const ALL: [Self; 6] = [
+        Self::Raw,
+        Self::FirstBlock,
+        Self::LastBlock,
+        Self::Sha1,
+        Self::XorClientGuid,
+        Self::XorServerGuid,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Raw => "raw",
+            Self::FirstBlock => "first-block",
+            Self::LastBlock => "last-block",
+            Self::Sha1 => "sha1",
+            Self::XorClientGuid => "xor-client-guid",
+            Self::XorServerGuid => "xor-server-guid",
+        }
+    }
+
+    /// The key this scheme gives, or `None` if `material` lacks what it needs
+    pub fn derive(self, material: &KeyMaterial) -> Option<[u8; 16]> {
+        let session = material.session;
+        match self {
+            Self::Raw => session.try_into().ok(),
+            Self::FirstBlock => session.get(..16)?.try_into().ok(),
+            Self::LastBlock => session
+                .get(session.len().checked_sub(16)?..)?
+                .try_into()
+                .ok(),
+            Self::Sha1 => Sha1::digest(session)[..16].try_into().ok(),
+            Self::XorClientGuid => Some(xor(
+                Self::FirstBlock.derive(material)?,
+                material.client_guid?,
+            )),
+            Self::XorServerGuid => Some(xor(
+                Self::FirstBlock.derive(material)?,
+                material.server_guid?,
+            )),
+        }
+    }
+}
+
+impl fmt::Display for KeyDerivation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+fn xor(mut key: [u8; 16], guid: [u8; 16]) -> [u8; 16] {
+    key.iter_mut().zip(guid).for_each(|(k, g)| *k ^= g);
+    key
+}
+
+/// A scheme whose key decrypted the known plaintext
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Match {
+    pub derivation: KeyDerivation,
+    pub key: [u8; 16],
+    pub plaintext: Vec<u8>,
+}
+
+/// Try every scheme on `ciphertext` (a 0x25 payload past its 4-byte
+/// header), keeping those whose plaintext starts with `known`
+///
+/// Schemes that happen to give the same key (`raw`, `first-block` and
+/// `last-block` on a 16-byte payload) all match together; a longer
+/// payload in the capture tells them apart.
+pub fn identify(material: &KeyMaterial, ciphertext: &[u8], known: &[u8]) -> Vec<Match> {
+    KeyDerivation::ALL
+        .into_iter()
+        .filter_map(|derivation| {
+            let key = derivation.derive(material)?;
+            let mut crypto = ProudNetCrypto::new();
+            crypto.set_aes_session_key(key);
+            let plaintext = crypto.decrypt_aes_ecb(ciphertext).ok()?;
+            plaintext.starts_with(known).then_some(Match {
+                derivation,
+                key,
+                plaintext,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CLIENT_GUID: [u8; 16] = [0xC1; 16];
+    const SERVER_GUID: [u8; 16] = [0x5E; 16];
+
+    fn encrypt(key: [u8; 16], plaintext: &[u8]) -> Vec<u8> {
+        let mut crypto = ProudNetCrypto::new();
+        crypto.set_aes_session_key(key);
+        crypto.encrypt_aes_ecb(plaintext).unwrap()
+    }
+
+    #[test]
+    fn test_derive() {
+        let session: Vec<u8> = (0..32).collect();
+        let material = KeyMaterial {
+            session: &session,
+            client_guid: Some(CLIENT_GUID),
+            server_guid: None,
+        };
+
+        assert_eq!(KeyDerivation::Raw.derive(&material), None);
+        assert_eq!(KeyDerivation::LastBlock.derive(&material).unwrap()[0], 16);
+        assert_eq!(
+            KeyDerivation::XorClientGuid.derive(&material).unwrap()[1],
+            1 ^ 0xC1
+        );
+        assert_eq!(KeyDerivation::XorServerGuid.derive(&material), None);
+        assert_eq!(
+            KeyDerivation::Sha1.derive(&material).unwrap()[..],
+            Sha1::digest(&session)[..16]
+        );
+    }
+
+    #[test]
+    fn test_identify() {
+        let session: Vec<u8> = (0..24).collect();
+        let material = KeyMaterial {
+            session: &session,
+            client_guid: Some(CLIENT_GUID),
+            server_guid: Some(SERVER_GUID),
+        };
+        let message = [0xE2, 0x2E, b'u', b's', b'e', b'r'];
+
+        for derivation in KeyDerivation::ALL {
+            let Some(key) = derivation.derive(&material) else {
+                continue;
+            };
+            let matches = identify(&material, &encrypt(key, &message), &message[..2]);
+            assert_eq!(matches.len(), 1, "{}", derivation);
+            assert_eq!(matches[0].derivation, derivation);
+            assert_eq!(matches[0].plaintext, message);
+        }
+
+        let unrelated = encrypt([0xAA; 16], &message);
+        assert!(identify(&material, &unrelated, &message[..2]).is_empty());
+    }
+}
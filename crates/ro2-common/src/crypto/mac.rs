@@ -0,0 +1,54 @@
+//! HMAC-SHA256 for data the servers pass to each other through clients
+//!
+//! Verification compares in constant time, so a forger learns nothing from
+//! how long a wrong MAC takes to reject.
+
+use super::constant_time_eq;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+/// Length of a MAC
+pub const MAC_LEN: usize = 32;
+
+/// Shortest key accepted for signing
+pub const MIN_KEY_LEN: usize = 32;
+
+type HmacSha256 = Hmac<Sha256>;
+
+fn hmac(key: &[u8], data: &[u8]) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC takes keys of any length");
+    mac.update(data);
+    mac
+}
+
+/// MAC of `data` under `key`
+pub fn sign(key: &[u8], data: &[u8]) -> [u8; MAC_LEN] {
+    hmac(key, data).finalize().into_bytes().into()
+}
+
+/// Whether `mac` is the MAC of `data` under `key`, in constant time
+pub fn verify(key: &[u8], data: &[u8], mac: &[u8]) -> bool {
+    constant_time_eq(&sign(key, data), mac)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sign_and_verify() {
+        // RFC 4231 test case 2
+        let mac = sign(b"Jefe", b"what do ya want for nothing?");
+        assert_eq!(
+            hex::encode(mac),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+
+        let key = [7u8; MIN_KEY_LEN];
+        let mac = sign(&key, b"token");
+        assert!(verify(&key, b"token", &mac));
+        assert!(!verify(&key, b"tokem", &mac));
+        assert!(!verify(&[8u8; MIN_KEY_LEN], b"token", &mac));
+        assert!(!verify(&key, b"token", &mac[..31]));
+    }
+}
@@ -1,5 +1,15 @@
 //! Cryptography utilities for AES/RSA encryption
 
+pub mod compare;
+pub mod derivation;
+pub mod mac;
 pub mod proudnet;
+pub mod replay;
+pub mod rng;
+pub mod stream;
 
-pub use proudnet::ProudNetCrypto;
+pub use compare::constant_time_eq;
+pub use proudnet::{DEFAULT_RSA_KEY_BITS, ProudNetCrypto, RsaPadding};
+pub use replay::{ReplayGuard, Replayed};
+pub use rng::SharedRng;
+pub use stream::StreamDecryptor;
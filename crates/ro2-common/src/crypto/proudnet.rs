@@ -10,16 +10,94 @@
 //! 3. Client encrypts session key with RSA and sends it (0x05)
 //! 4. Server decrypts session key with RSA private key
 //! 5. All subsequent game messages encrypted with AES in 0x25 packets
+//!
+//! Which RSA paddings are accepted in step 4 is configurable (see
+//! [`RsaPadding`]); the RO2 client uses OAEP-SHA1.
+//!
+//! Session keys are wiped from memory when replaced or dropped, as is the
+//! decrypted 0x05 payload. RSA private keys wipe themselves.
 
+use super::rng::SharedRng;
+use super::stream::StreamDecryptor;
 use crate::Result;
+use crate::wire::WireReader;
 use aes::Aes128;
+use aes::cipher::consts::U16;
+use aes::cipher::inout::InOutBuf;
 use aes::cipher::{BlockDecrypt, BlockEncrypt, KeyInit, generic_array::GenericArray};
-use rand::{Rng, rngs::OsRng};
 use rsa::pkcs1::DecodeRsaPublicKey;
 use rsa::{Oaep, Pkcs1v15Encrypt, RsaPrivateKey, RsaPublicKey};
+use serde::Deserialize;
 use sha1::Sha1;
 use sha2::Sha256;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
 use tracing::{debug, warn};
+use zeroize::{Zeroize, Zeroizing};
+
+/// RSA key size the RO2 client is known to accept
+pub const DEFAULT_RSA_KEY_BITS: usize = 1024;
+
+/// Padding a client may encrypt its session key with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RsaPadding {
+    /// What the RO2 client uses (found via Ghidra analysis of
+    /// RSA_ApplyPadding)
+    OaepSha1,
+    Pkcs1v15,
+    OaepSha256,
+}
+
+impl RsaPadding {
+    /// Every padding, in the order they're tried by default
+    pub const ALL: [Self; 3] = [Self::OaepSha1, Self::Pkcs1v15, Self::OaepSha256];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::OaepSha1 => "oaep-sha1",
+            Self::Pkcs1v15 => "pkcs1v15",
+            Self::OaepSha256 => "oaep-sha256",
+        }
+    }
+
+    /// How many session keys have been decrypted with this padding since
+    /// startup
+    pub fn decrypted_count(self) -> u64 {
+        DECRYPTED[self as usize].load(Ordering::Relaxed)
+    }
+
+    fn encrypt(
+        self,
+        crypto: &ProudNetCrypto,
+        key: &RsaPublicKey,
+        data: &[u8],
+    ) -> rsa::Result<Vec<u8>> {
+        crypto.rng.with(|mut rng| match self {
+            Self::OaepSha1 => key.encrypt(&mut rng, Oaep::new::<Sha1>(), data),
+            Self::Pkcs1v15 => key.encrypt(&mut rng, Pkcs1v15Encrypt, data),
+            Self::OaepSha256 => key.encrypt(&mut rng, Oaep::new::<Sha256>(), data),
+        })
+    }
+
+    #[cfg(feature = "server")]
+    fn decrypt(self, key: &RsaPrivateKey, data: &[u8]) -> rsa::Result<Vec<u8>> {
+        match self {
+            Self::OaepSha1 => key.decrypt(Oaep::new::<Sha1>(), data),
+            Self::Pkcs1v15 => key.decrypt(Pkcs1v15Encrypt, data),
+            Self::OaepSha256 => key.decrypt(Oaep::new::<Sha256>(), data),
+        }
+    }
+}
+
+impl fmt::Display for RsaPadding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Session keys decrypted with each [`RsaPadding`], by discriminant
+static DECRYPTED: [AtomicU64; 3] = [AtomicU64::new(0), AtomicU64::new(0), AtomicU64::new(0)];
 
 /// ProudNet encryption handler
 ///
@@ -37,6 +115,16 @@ pub struct ProudNetCrypto {
 
     /// AES IV (initialization vector, if using CBC mode)
     aes_iv: Option<[u8; 16]>,
+
+    /// Paddings accepted for the session key, tried in order; a client
+    /// encrypts with the first
+    rsa_paddings: Vec<RsaPadding>,
+
+    /// The padding the last session key was decrypted with
+    rsa_padding: Option<RsaPadding>,
+
+    /// Source of keys and padding, also used for session IDs and GUIDs
+    rng: SharedRng,
 }
 
 impl ProudNetCrypto {
@@ -47,9 +135,38 @@ impl ProudNetCrypto {
             rsa_private: None,
             aes_key: None,
             aes_iv: None,
+            rsa_paddings: RsaPadding::ALL.to_vec(),
+            rsa_padding: None,
+            rng: SharedRng::os(),
         }
     }
 
+    /// Draw all randomness from `rng` instead of the OS generator
+    pub fn with_rng(mut self, rng: SharedRng) -> Self {
+        self.rng = rng;
+        self
+    }
+
+    /// Accept (or as a client, encrypt with the first of) `paddings`
+    /// instead of all of them
+    pub fn with_rsa_paddings(mut self, paddings: Vec<RsaPadding>) -> Self {
+        self.rsa_paddings = paddings;
+        self
+    }
+
+    pub fn rsa_paddings(&self) -> &[RsaPadding] {
+        &self.rsa_paddings
+    }
+
+    /// The padding the last session key was decrypted with
+    pub fn rsa_padding(&self) -> Option<RsaPadding> {
+        self.rsa_padding
+    }
+
+    pub fn rng(&self) -> &SharedRng {
+        &self.rng
+    }
+
     /// Parse RSA public key from DER-encoded data
     ///
     /// The server sends an ASN.1 DER encoded RSA public key in the 0x04 packet.
@@ -70,9 +187,11 @@ impl ProudNetCrypto {
 
     #[cfg(feature = "server")]
     /// Generate a new RSA keypair (server-side)
+    #[cfg_attr(feature = "profiling", tracing::instrument(level = "trace", skip_all))]
     pub fn generate_rsa_keypair(&mut self, bits: usize) -> Result<()> {
-        let mut rng = OsRng;
-        let private_key = RsaPrivateKey::new(&mut rng, bits)
+        let private_key = self
+            .rng
+            .with(|mut rng| RsaPrivateKey::new(&mut rng, bits))
             .map_err(|e| anyhow::anyhow!("Failed to generate RSA keypair: {}", e))?;
         let public_key = RsaPublicKey::from(&private_key);
 
@@ -88,15 +207,14 @@ impl ProudNetCrypto {
 
     /// Generate AES session key
     pub fn generate_aes_session_key(&mut self) -> [u8; 16] {
-        let mut rng = OsRng;
-        let mut key = [0u8; 16];
-        rng.fill(&mut key);
-        self.aes_key = Some(key);
+        let key = self.rng.bytes::<16>();
+        self.set_aes_session_key(key);
         key
     }
 
-    /// Set AES session key
+    /// Set AES session key, wiping the old one
     pub fn set_aes_session_key(&mut self, key: [u8; 16]) {
+        self.aes_key.zeroize();
         self.aes_key = Some(key);
     }
 
@@ -107,22 +225,29 @@ impl ProudNetCrypto {
 
     /// Set AES IV (for CBC mode)
     pub fn set_aes_iv(&mut self, iv: [u8; 16]) {
+        self.aes_iv.zeroize();
         self.aes_iv = Some(iv);
     }
 
     /// Encrypt session key with RSA (client-side, opcode 0x05)
     ///
     /// The client encrypts the AES session key with the server's RSA public key
-    /// and sends it in a 0x05 packet.
+    /// and sends it in a 0x05 packet. Uses the first of
+    /// [`rsa_paddings`](Self::rsa_paddings), by default OAEP-SHA1 like the
+    /// RO2 client, so the official server accepts it.
+    #[cfg_attr(feature = "profiling", tracing::instrument(level = "trace", skip_all))]
     pub fn encrypt_session_key_rsa(&self, session_key: &[u8]) -> Result<Vec<u8>> {
         let public_key = self
             .rsa_public
             .as_ref()
             .ok_or_else(|| anyhow::anyhow!("No RSA public key set"))?;
+        let padding = *self
+            .rsa_paddings
+            .first()
+            .ok_or_else(|| anyhow::anyhow!("No RSA padding set"))?;
 
-        let mut rng = OsRng;
-        let encrypted = public_key
-            .encrypt(&mut rng, Pkcs1v15Encrypt, session_key)
+        let encrypted = padding
+            .encrypt(self, public_key, session_key)
             .map_err(|e| anyhow::anyhow!("Failed to encrypt with RSA: {}", e))?;
 
         Ok(encrypted)
@@ -133,7 +258,12 @@ impl ProudNetCrypto {
     ///
     /// RO2 client uses OAEP-SHA1 padding (circa 2011), not PKCS#1 v1.5.
     /// This was discovered through Ghidra analysis of RSA_ApplyPadding function.
-    pub fn decrypt_session_key_rsa(&mut self, encrypted_key: &[u8]) -> Result<Vec<u8>> {
+    /// Each of [`rsa_paddings`](Self::rsa_paddings) is tried in turn; the one
+    /// that worked is kept in [`rsa_padding`](Self::rsa_padding) and counted
+    /// in [`RsaPadding::decrypted_count`]. The returned plaintext is wiped
+    /// when dropped.
+    #[cfg_attr(feature = "profiling", tracing::instrument(level = "trace", skip_all))]
+    pub fn decrypt_session_key_rsa(&mut self, encrypted_key: &[u8]) -> Result<Zeroizing<Vec<u8>>> {
         use rsa::traits::PublicKeyParts;
 
         let private_key = self
@@ -147,35 +277,44 @@ impl ProudNetCrypto {
             "Attempting RSA decryption"
         );
 
-        // RO2 uses OAEP-SHA1 (discovered via Ghidra analysis of RSA_ApplyPadding)
-        // Try fallback schemes in case other clients behave differently
-        let decrypted = private_key
-            .decrypt(Oaep::new::<Sha1>(), encrypted_key)
-            .or_else(|e1| {
-                debug!(error = %e1, "OAEP-SHA1 failed, trying PKCS#1 v1.5");
-                private_key.decrypt(Pkcs1v15Encrypt, encrypted_key)
-            })
-            .or_else(|e2| {
-                debug!(error = %e2, "PKCS#1 v1.5 failed, trying OAEP-SHA256");
-                private_key.decrypt(Oaep::new::<Sha256>(), encrypted_key)
-            })
-            .map_err(|e| {
-                warn!(
-                    encrypted_len = encrypted_key.len(),
-                    key_size = private_key.size() * 8,
-                    error = %e,
-                    "All RSA decryption methods failed"
-                );
-                anyhow::anyhow!("Failed to decrypt session key with RSA: {}", e)
-            })?;
-
-        debug!(decrypted_len = decrypted.len(), "RSA decryption successful");
+        let mut error = None;
+        let mut decrypted = None;
+        for &padding in &self.rsa_paddings {
+            match padding.decrypt(private_key, encrypted_key) {
+                Ok(plaintext) => {
+                    decrypted = Some((padding, Zeroizing::new(plaintext)));
+                    break;
+                }
+                Err(e) => {
+                    debug!(%padding, error = %e, "RSA padding didn't match");
+                    error = Some(e);
+                }
+            }
+        }
+        let Some((padding, decrypted)) = decrypted else {
+            let error = error.map_or("no paddings to try".to_string(), |e| e.to_string());
+            warn!(
+                encrypted_len = encrypted_key.len(),
+                key_size = private_key.size() * 8,
+                error = %error,
+                "All RSA decryption methods failed"
+            );
+            return Err(anyhow::anyhow!(
+                "Failed to decrypt session key with RSA: {}",
+                error
+            ));
+        };
+        self.rsa_padding = Some(padding);
+        DECRYPTED[padding as usize].fetch_add(1, Ordering::Relaxed);
+
+        debug!(decrypted_len = decrypted.len(), %padding, "RSA decryption successful");
 
         // Extract the 16-byte AES key
         if decrypted.len() >= 16 {
             let mut key = [0u8; 16];
             key.copy_from_slice(&decrypted[0..16]);
-            self.aes_key = Some(key);
+            self.set_aes_session_key(key);
+            key.zeroize();
             debug!("AES session key extracted");
         } else {
             warn!(
@@ -192,36 +331,28 @@ impl ProudNetCrypto {
     /// Note: We need to determine the actual AES mode used by inspecting
     /// encrypted packets. ECB is the simplest (each block encrypted independently).
     /// ProudNet might use CBC, CTR, or another mode.
+    #[cfg_attr(feature = "profiling", tracing::instrument(level = "trace", skip_all))]
     pub fn encrypt_aes_ecb(&self, data: &[u8]) -> Result<Vec<u8>> {
-        let key = self
-            .aes_key
-            .as_ref()
-            .ok_or_else(|| anyhow::anyhow!("No AES session key set"))?;
-
-        let cipher = Aes128::new(GenericArray::from_slice(key));
+        let cipher = self.aes_cipher()?;
 
         // Pad to 16-byte blocks (PKCS#7 padding)
-        let mut padded = data.to_vec();
         let padding_len = 16 - (data.len() % 16);
-        padded.extend(vec![padding_len as u8; padding_len]);
-
-        // Encrypt each block
-        let mut encrypted = Vec::with_capacity(padded.len());
-        for chunk in padded.chunks(16) {
-            let mut block = GenericArray::clone_from_slice(chunk);
-            cipher.encrypt_block(&mut block);
-            encrypted.extend_from_slice(&block);
-        }
+        let mut encrypted = Vec::with_capacity(data.len() + padding_len);
+        encrypted.extend_from_slice(data);
+        encrypted.resize(data.len() + padding_len, padding_len as u8);
+
+        // Encrypt the whole buffer in place at once, so the cipher can work
+        // on several blocks in parallel (AES-NI where the CPU has it)
+        let (blocks, _) = InOutBuf::from(&mut encrypted[..]).into_chunks::<U16>();
+        cipher.encrypt_blocks_inout(blocks);
 
         Ok(encrypted)
     }
 
     /// Decrypt data with AES-128 ECB
+    #[cfg_attr(feature = "profiling", tracing::instrument(level = "trace", skip_all))]
     pub fn decrypt_aes_ecb(&self, data: &[u8]) -> Result<Vec<u8>> {
-        let key = self
-            .aes_key
-            .as_ref()
-            .ok_or_else(|| anyhow::anyhow!("No AES session key set"))?;
+        let cipher = self.aes_cipher()?;
 
         if !data.len().is_multiple_of(16) {
             return Err(anyhow::anyhow!(
@@ -230,47 +361,56 @@ impl ProudNetCrypto {
             ));
         }
 
-        let cipher = Aes128::new(GenericArray::from_slice(key));
-
-        // Decrypt each block
-        let mut decrypted = Vec::with_capacity(data.len());
-        for chunk in data.chunks(16) {
-            let mut block = GenericArray::clone_from_slice(chunk);
-            cipher.decrypt_block(&mut block);
-            decrypted.extend_from_slice(&block);
-        }
+        let mut decrypted = data.to_vec();
+        let (blocks, _) = InOutBuf::from(&mut decrypted[..]).into_chunks::<U16>();
+        cipher.decrypt_blocks_inout(blocks);
 
         // Remove PKCS#7 padding
         if let Some(&padding_len) = decrypted.last()
-            && padding_len > 0 && padding_len <= 16 {
-                let len = decrypted.len();
-                decrypted.truncate(len - padding_len as usize);
-            }
+            && padding_len > 0
+            && padding_len <= 16
+        {
+            let len = decrypted.len();
+            decrypted.truncate(len - padding_len as usize);
+        }
 
         Ok(decrypted)
     }
 
-    /// Decrypt a 0x25 encrypted packet
+    /// Decrypt an AES-128 ECB message chunk by chunk as it arrives
+    pub fn stream_decryptor(&self) -> Result<StreamDecryptor> {
+        Ok(StreamDecryptor::new(self.aes_cipher()?))
+    }
+
+    /// AES-128 keyed with the session key
+    fn aes_cipher(&self) -> Result<Aes128> {
+        let key = self
+            .aes_key
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("No AES session key set"))?;
+        Ok(Aes128::new(GenericArray::from_slice(key)))
+    }
+
+    /// Decrypt a 0x25 encrypted packet, or its unreliable twin 0x26
     ///
     /// Packet structure:
-    /// - Byte 0: 0x25 (opcode)
+    /// - Byte 0: 0x25 or 0x26 (opcode, see [`Reliability`])
     /// - Byte 1: Sub-opcode (0x01 or 0x02)
     /// - Byte 2-3: Possible length field?
     /// - Byte 4+: Encrypted data
+    ///
+    /// [`Reliability`]: crate::protocol::proudnet::Reliability
     pub fn decrypt_packet_0x25(&self, payload: &[u8]) -> Result<Vec<u8>> {
-        if payload.is_empty() || payload[0] != 0x25 {
-            return Err(anyhow::anyhow!("Not a 0x25 packet"));
+        let mut r = WireReader::new(payload);
+        if !matches!(r.u8("opcode"), Ok(0x25 | 0x26)) {
+            return Err(anyhow::anyhow!("Not a 0x25/0x26 packet"));
         }
 
-        if payload.len() < 4 {
-            return Err(anyhow::anyhow!("0x25 packet too short"));
-        }
-
-        // Extract encrypted data (skip opcode, sub-opcode, and length field)
-        let encrypted_data = &payload[4..];
+        // Skip the sub-opcode and length field to the encrypted data
+        r.bytes("header", 3)?;
 
         // Try to decrypt with AES ECB
-        self.decrypt_aes_ecb(encrypted_data)
+        self.decrypt_aes_ecb(r.remaining())
     }
 
     // ===== Client-side Convenience Methods =====
@@ -312,10 +452,18 @@ impl Default for ProudNetCrypto {
     }
 }
 
+impl Drop for ProudNetCrypto {
+    fn drop(&mut self) {
+        self.aes_key.zeroize();
+        self.aes_iv.zeroize();
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use rand::Rng;
+    #[cfg(feature = "server")]
+    use rsa::pkcs1::EncodeRsaPublicKey;
 
     #[test]
     fn test_aes_encryption_roundtrip() {
@@ -394,6 +542,22 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_aes_known_answer() {
+        // FIPS-197 appendix C.1, twice over, then a block of padding
+        let mut crypto = ProudNetCrypto::new();
+        crypto.set_aes_session_key(core::array::from_fn(|i| i as u8));
+        let plaintext = hex::decode("00112233445566778899aabbccddeeff")
+            .unwrap()
+            .repeat(2);
+
+        let encrypted = crypto.encrypt_aes_ecb(&plaintext).unwrap();
+        assert_eq!(encrypted.len(), 48);
+        let expected = "69c4e0d86a7b0430d8cdb78070b4c55a";
+        assert_eq!(hex::encode(&encrypted[..32]), expected.repeat(2));
+        assert_eq!(crypto.decrypt_aes_ecb(&encrypted).unwrap(), plaintext);
+    }
+
     #[test]
     #[cfg(feature = "server")]
     fn test_rsa_decrypt_raw_data() {
@@ -0,0 +1,231 @@
+//! Spotting replayed handshakes
+//!
+//! The client's 0x05 carries no nonce or timestamp of its own: it's the
+//! session key it drew, encrypted to the server's RSA key. A real client
+//! draws a new key and random padding every time it connects, so the same
+//! ciphertext, or the same key under a different padding, turning up again
+//! for the same server key means someone is replaying captured traffic.
+//! [`ReplayGuard`] remembers a fingerprint of both for `window`, along with
+//! the connection (remote and local address) the key was exchanged on, so
+//! a session key stays bound to the connection that first presented it.
+//!
+//! One guard goes with one server key; a new key (e.g. after a restart)
+//! makes every old capture useless anyway. Past `capacity` fingerprints
+//! the oldest are forgotten first.
+
+use crate::clock::Clock;
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// The connection a session key was exchanged on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Peer {
+    pub remote: SocketAddr,
+    /// The server's end, when the stream has one
+    pub local: Option<SocketAddr>,
+}
+
+impl fmt::Display for Peer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.local {
+            Some(local) => write!(f, "{} -> {}", self.remote, local),
+            None => write!(f, "{}", self.remote),
+        }
+    }
+}
+
+/// What was seen twice
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fingerprinted {
+    /// The RSA ciphertext in 0x05, byte for byte
+    Ciphertext,
+    /// The session key inside it
+    SessionKey,
+}
+
+/// A handshake that was seen before
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("replayed {what:?}, first seen from {first} {secs_ago} s ago")]
+pub struct Replayed {
+    pub what: Fingerprinted,
+    /// The connection it was first seen on
+    pub first: Peer,
+    pub secs_ago: u64,
+}
+
+type Fingerprint = [u8; 32];
+
+struct Seen {
+    first: HashMap<Fingerprint, (Peer, Instant)>,
+    /// Fingerprints oldest first, to forget them in order
+    order: VecDeque<(Fingerprint, Instant)>,
+    replays: u64,
+}
+
+/// Handshakes seen for one server key
+///
+/// Cheap to clone; every clone remembers the same handshakes.
+#[derive(Clone)]
+pub struct ReplayGuard {
+    window: Duration,
+    capacity: usize,
+    clock: Clock,
+    seen: Arc<Mutex<Seen>>,
+}
+
+impl ReplayGuard {
+    /// Remember handshakes for `window`, at most `capacity` fingerprints
+    pub fn new(window: Duration, capacity: usize) -> Self {
+        Self {
+            window,
+            capacity,
+            clock: Clock::system(),
+            seen: Arc::new(Mutex::new(Seen {
+                first: HashMap::new(),
+                order: VecDeque::new(),
+                replays: 0,
+            })),
+        }
+    }
+
+    /// Tell the time by `clock`
+    pub fn with_clock(mut self, clock: Clock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Note `bytes` presented by `peer`, unless they've been seen before
+    pub fn check(&self, what: Fingerprinted, bytes: &[u8], peer: Peer) -> Result<(), Replayed> {
+        let fingerprint = fingerprint(what, bytes);
+        let now = self.clock.instant();
+        let mut seen = self.seen.lock().unwrap();
+        self.forget_expired(&mut seen, now);
+        if let Some(&(first, at)) = seen.first.get(&fingerprint) {
+            seen.replays += 1;
+            return Err(Replayed {
+                what,
+                first,
+                secs_ago: now.saturating_duration_since(at).as_secs(),
+            });
+        }
+        seen.first.insert(fingerprint, (peer, now));
+        seen.order.push_back((fingerprint, now));
+        while seen.order.len() > self.capacity {
+            let (oldest, _) = seen.order.pop_front().expect("over capacity");
+            seen.first.remove(&oldest);
+        }
+        Ok(())
+    }
+
+    /// Replays caught so far
+    pub fn replays(&self) -> u64 {
+        self.seen.lock().unwrap().replays
+    }
+
+    /// Fingerprints remembered
+    pub fn len(&self) -> usize {
+        self.seen.lock().unwrap().order.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn forget_expired(&self, seen: &mut Seen, now: Instant) {
+        while let Some(&(oldest, at)) = seen.order.front() {
+            if now.saturating_duration_since(at) < self.window {
+                break;
+            }
+            seen.order.pop_front();
+            seen.first.remove(&oldest);
+        }
+    }
+}
+
+/// SHA-256 of `bytes`, separated by what they are
+fn fingerprint(what: Fingerprinted, bytes: &[u8]) -> Fingerprint {
+    let mut hasher = Sha256::new();
+    hasher.update([what as u8]);
+    hasher.update(bytes);
+    hasher.finalize().into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn peer(port: u16) -> Peer {
+        Peer {
+            remote: SocketAddr::from(([10, 0, 0, 1], port)),
+            local: Some(SocketAddr::from(([10, 0, 0, 2], 7101))),
+        }
+    }
+
+    #[test]
+    fn test_replays() {
+        let clock = Clock::at_unix(1_700_000_000);
+        let guard = ReplayGuard::new(Duration::from_secs(60), 10).with_clock(clock.clone());
+        assert!(
+            guard
+                .check(Fingerprinted::Ciphertext, b"abc", peer(1))
+                .is_ok()
+        );
+        assert!(
+            guard
+                .check(Fingerprinted::SessionKey, b"abc", peer(1))
+                .is_ok()
+        );
+
+        clock.advance(Duration::from_secs(5));
+        let replayed = guard
+            .check(Fingerprinted::Ciphertext, b"abc", peer(2))
+            .unwrap_err();
+        assert_eq!(
+            replayed,
+            Replayed {
+                what: Fingerprinted::Ciphertext,
+                first: peer(1),
+                secs_ago: 5,
+            }
+        );
+        assert_eq!(
+            replayed.to_string(),
+            "replayed Ciphertext, first seen from 10.0.0.1:1 -> 10.0.0.2:7101 5 s ago"
+        );
+        assert_eq!(guard.replays(), 1);
+
+        // Forgotten after the window
+        clock.advance(Duration::from_secs(55));
+        assert!(
+            guard
+                .check(Fingerprinted::Ciphertext, b"abc", peer(3))
+                .is_ok()
+        );
+        assert_eq!(guard.len(), 1);
+    }
+
+    #[test]
+    fn test_capacity() {
+        let guard = ReplayGuard::new(Duration::from_secs(60), 2);
+        for bytes in [b"a", b"b", b"c"] {
+            guard
+                .check(Fingerprinted::Ciphertext, bytes, peer(1))
+                .unwrap();
+        }
+        assert_eq!(guard.len(), 2);
+        assert!(
+            guard
+                .check(Fingerprinted::Ciphertext, b"a", peer(1))
+                .is_ok()
+        );
+        assert!(
+            guard
+                .check(Fingerprinted::Ciphertext, b"c", peer(1))
+                .is_err()
+        );
+    }
+}
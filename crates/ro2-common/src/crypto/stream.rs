@@ -0,0 +1,199 @@
+//! Incremental AES decryption
+//!
+//! [`ProudNetCrypto::decrypt_aes_ecb`](super::ProudNetCrypto::decrypt_aes_ecb)
+//! needs the whole ciphertext at once. A [`StreamDecryptor`] takes it in
+//! chunks as they arrive and hands back plaintext as soon as whole blocks
+//! are in, holding at most two blocks itself, so a large fragmented or
+//! compressed message can be fed straight on (e.g. to a decompressor)
+//! without buffering it. A message may be at most
+//! [`MAX_PACKET_SIZE`] bytes of ciphertext.
+
+use crate::Result;
+use crate::packet::framing::MAX_PACKET_SIZE;
+use aes::Aes128;
+use aes::cipher::consts::U16;
+use aes::cipher::inout::InOutBuf;
+use aes::cipher::{BlockDecrypt, generic_array::GenericArray};
+use anyhow::anyhow;
+use zeroize::Zeroize;
+
+const BLOCK: usize = 16;
+
+/// Decrypts one AES-128 ECB message chunk by chunk
+///
+/// Get one from
+/// [`ProudNetCrypto::stream_decryptor`](super::ProudNetCrypto::stream_decryptor).
+pub struct StreamDecryptor {
+    cipher: Aes128,
+    /// Ciphertext short of a whole block
+    partial: [u8; BLOCK],
+    partial_len: usize,
+    /// The latest decrypted block, held back until we know whether it's
+    /// the last one and carries padding
+    held: Option<[u8; BLOCK]>,
+    /// Ciphertext taken so far
+    total: usize,
+    limit: usize,
+}
+
+impl StreamDecryptor {
+    pub(crate) fn new(cipher: Aes128) -> Self {
+        Self {
+            cipher,
+            partial: [0; BLOCK],
+            partial_len: 0,
+            held: None,
+            total: 0,
+            limit: MAX_PACKET_SIZE,
+        }
+    }
+
+    /// Accept at most `limit` bytes of ciphertext instead of
+    /// [`MAX_PACKET_SIZE`]
+    pub fn with_limit(mut self, limit: usize) -> Self {
+        self.limit = limit;
+        self
+    }
+
+    /// Ciphertext taken so far
+    pub fn total(&self) -> usize {
+        self.total
+    }
+
+    /// Take the next `chunk` of ciphertext, appending whatever plaintext it
+    /// completes to `out`
+    pub fn update(&mut self, mut chunk: &[u8], out: &mut Vec<u8>) -> Result<()> {
+        if self.total + chunk.len() > self.limit {
+            return Err(anyhow!("encrypted message over {} bytes", self.limit));
+        }
+        self.total += chunk.len();
+
+        // Finish the block left over from the last chunk
+        if self.partial_len > 0 {
+            let take = (BLOCK - self.partial_len).min(chunk.len());
+            self.partial[self.partial_len..self.partial_len + take].copy_from_slice(&chunk[..take]);
+            self.partial_len += take;
+            chunk = &chunk[take..];
+            if self.partial_len < BLOCK {
+                return Ok(());
+            }
+            let mut block = self.partial;
+            self.partial.zeroize();
+            self.partial_len = 0;
+            self.cipher
+                .decrypt_block(GenericArray::from_mut_slice(&mut block));
+            self.push(block, out);
+        }
+
+        // Decrypt the whole blocks in one go, keeping back the last
+        let whole = chunk.len() / BLOCK * BLOCK;
+        if whole > 0 {
+            if let Some(mut held) = self.held.take() {
+                out.extend_from_slice(&held);
+                held.zeroize();
+            }
+            let blocks_at = out.len();
+            out.extend_from_slice(&chunk[..whole]);
+            let (blocks, _) = InOutBuf::from(&mut out[blocks_at..]).into_chunks::<U16>();
+            self.cipher.decrypt_blocks_inout(blocks);
+
+            let last = out.len() - BLOCK;
+            let mut held = [0; BLOCK];
+            held.copy_from_slice(&out[last..]);
+            out[last..].zeroize();
+            out.truncate(last);
+            self.held = Some(held);
+        }
+
+        let rest = &chunk[whole..];
+        self.partial[..rest.len()].copy_from_slice(rest);
+        self.partial_len = rest.len();
+        Ok(())
+    }
+
+    /// End of the message: append the last block less its PKCS#7 padding
+    pub fn finish(self, out: &mut Vec<u8>) -> Result<()> {
+        if self.partial_len > 0 {
+            return Err(anyhow!(
+                "encrypted message of {} bytes isn't whole blocks",
+                self.total
+            ));
+        }
+        if let Some(held) = &self.held {
+            // Same rule as decrypt_aes_ecb: a last byte of 1 to 16 is padding
+            let padding = match held[BLOCK - 1] {
+                len @ 1..=16 => len as usize,
+                _ => 0,
+            };
+            out.extend_from_slice(&held[..BLOCK - padding]);
+        }
+        Ok(())
+    }
+
+    fn push(&mut self, block: [u8; BLOCK], out: &mut Vec<u8>) {
+        if let Some(mut held) = self.held.replace(block) {
+            out.extend_from_slice(&held);
+            held.zeroize();
+        }
+    }
+}
+
+impl Drop for StreamDecryptor {
+    fn drop(&mut self) {
+        self.partial.zeroize();
+        if let Some(held) = &mut self.held {
+            held.zeroize();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::crypto::{ProudNetCrypto, SharedRng};
+
+    fn crypto() -> ProudNetCrypto {
+        let mut crypto = ProudNetCrypto::new().with_rng(SharedRng::seeded(3));
+        crypto.generate_aes_session_key();
+        crypto
+    }
+
+    #[test]
+    fn test_chunked_matches_whole() {
+        let crypto = crypto();
+        for size in [0, 1, 15, 16, 17, 100, 4000] {
+            let plaintext: Vec<u8> = (0..size).map(|i| (i * 7) as u8).collect();
+            let encrypted = crypto.encrypt_aes_ecb(&plaintext).unwrap();
+            let whole = crypto.decrypt_aes_ecb(&encrypted).unwrap();
+
+            for chunk_size in [1, 5, 16, 33, 4096] {
+                let mut stream = crypto.stream_decryptor().unwrap();
+                let mut out = Vec::new();
+                for chunk in encrypted.chunks(chunk_size) {
+                    stream.update(chunk, &mut out).unwrap();
+                    // Never more than what whole blocks so far give
+                    assert!(out.len() <= stream.total());
+                }
+                stream.finish(&mut out).unwrap();
+                assert_eq!(out, whole, "{} bytes in chunks of {}", size, chunk_size);
+                assert_eq!(out, plaintext);
+            }
+        }
+    }
+
+    #[test]
+    fn test_limits() {
+        let crypto = crypto();
+        let encrypted = crypto.encrypt_aes_ecb(&[7; 100]).unwrap();
+
+        let mut stream = crypto.stream_decryptor().unwrap().with_limit(64);
+        let mut out = Vec::new();
+        stream.update(&encrypted[..64], &mut out).unwrap();
+        assert!(stream.update(&encrypted[64..65], &mut out).is_err());
+
+        let mut stream = crypto.stream_decryptor().unwrap();
+        stream.update(&encrypted[..20], &mut out).unwrap();
+        assert!(stream.finish(&mut out).is_err());
+
+        assert!(ProudNetCrypto::new().stream_decryptor().is_err());
+    }
+}
@@ -0,0 +1,602 @@
+//! Live activity feed for GM dashboards
+//!
+//! With `dashboard` set in the `[console]` section, the console also
+//! accepts WebSocket clients there and pushes them what happens on the
+//! server's [`EventBus`] as it happens: logins, chat, zone populations and
+//! anti-cheat violations. It's authenticated with the console password:
+//!
+//! ```toml
+//! [console]
+//! dashboard = "127.0.0.1:7498"
+//! password = "change me"
+//! ```
+//!
+//! Clients choose what they get in the query string; everything is sent
+//! when it's left out:
+//!
+//! ```text
+//! ws://127.0.0.1:7498/?token=change%20me&events=login,chat&chat_channel=shout&chat_zone=1&chat_contains=gm
+//! ```
+//!
+//! Each event is one JSON text message, e.g.
+//! `{"type":"population","zone":1,"players":12,"monsters":40,"messages_per_sec":95}`. Messages from the client
+//! are ignored apart from pings and close.
+
+use crate::Result;
+use crate::crypto::constant_time_eq;
+use crate::events::{
+    ChatMessage, EventBus, PlayerLoggedIn, Violation, WeakEventBus, ZonePopulation,
+};
+use crate::net::{HTTP_REQUEST_TIMEOUT, read_http_request};
+use anyhow::{Context, anyhow};
+use base64::Engine;
+use serde::Serialize;
+use sha1::{Digest, Sha1};
+use std::net::SocketAddr;
+use tokio::io::{AsyncBufRead, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+use tracing::{error, info, warn};
+
+/// Appended to the client's key before hashing it (RFC 6455)
+const WEBSOCKET_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// Largest frame accepted from a client; it has nothing to send but
+/// control frames
+const MAX_CLIENT_FRAME: u64 = 4096;
+
+const OP_TEXT: u8 = 0x1;
+const OP_CLOSE: u8 = 0x8;
+const OP_PING: u8 = 0x9;
+const OP_PONG: u8 = 0xA;
+
+/// Kinds of event a client can ask for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FeedKind {
+    Login,
+    Chat,
+    Population,
+    Violation,
+}
+
+impl FeedKind {
+    fn parse(name: &str) -> Result<Self> {
+        match name {
+            "login" => Ok(Self::Login),
+            "chat" => Ok(Self::Chat),
+            "population" => Ok(Self::Population),
+            "violation" => Ok(Self::Violation),
+            other => Err(anyhow!("unknown event {:?}", other)),
+        }
+    }
+}
+
+/// One message of the feed
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum FeedEvent {
+    Login {
+        account_id: u32,
+        session_id: u64,
+    },
+    Chat {
+        session_id: u64,
+        zone: u32,
+        channel: String,
+        from: String,
+        text: String,
+    },
+    Population {
+        zone: u32,
+        players: usize,
+        monsters: usize,
+        messages_per_sec: u32,
+    },
+    Violation {
+        session_id: u64,
+        account_id: Option<u32>,
+        kind: String,
+        detail: String,
+    },
+}
+
+impl FeedEvent {
+    pub fn kind(&self) -> FeedKind {
+        match self {
+            Self::Login { .. } => FeedKind::Login,
+            Self::Chat { .. } => FeedKind::Chat,
+            Self::Population { .. } => FeedKind::Population,
+            Self::Violation { .. } => FeedKind::Violation,
+        }
+    }
+}
+
+impl From<PlayerLoggedIn> for FeedEvent {
+    fn from(event: PlayerLoggedIn) -> Self {
+        Self::Login {
+            account_id: event.account_id,
+            session_id: event.session_id,
+        }
+    }
+}
+
+impl From<ChatMessage> for FeedEvent {
+    fn from(event: ChatMessage) -> Self {
+        Self::Chat {
+            session_id: event.session_id,
+            zone: event.zone,
+            channel: event.channel,
+            from: event.from,
+            text: event.text,
+        }
+    }
+}
+
+impl From<ZonePopulation> for FeedEvent {
+    fn from(event: ZonePopulation) -> Self {
+        Self::Population {
+            zone: event.zone,
+            players: event.players,
+            monsters: event.monsters,
+            messages_per_sec: event.messages_per_sec,
+        }
+    }
+}
+
+impl From<Violation> for FeedEvent {
+    fn from(event: Violation) -> Self {
+        Self::Violation {
+            session_id: event.session_id,
+            account_id: event.account_id,
+            kind: event.kind,
+            detail: event.detail,
+        }
+    }
+}
+
+/// What one client asked to be sent
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Filter {
+    /// Every kind when empty
+    pub kinds: Vec<FeedKind>,
+    pub chat_channel: Option<String>,
+    pub chat_zone: Option<u32>,
+    /// Lowercase; chat whose text contains it, ignoring case
+    pub chat_contains: Option<String>,
+}
+
+impl Filter {
+    /// Read the filter parameters of a query string, ignoring the others
+    pub fn from_query(query: &str) -> Result<Self> {
+        let mut filter = Self::default();
+        for (name, value) in query_pairs(query) {
+            match name.as_str() {
+                "events" => {
+                    filter.kinds = value
+                        .split(',')
+                        .filter(|name| !name.is_empty())
+                        .map(FeedKind::parse)
+                        .collect::<Result<_>>()?;
+                }
+                "chat_channel" => filter.chat_channel = Some(value),
+                "chat_zone" => filter.chat_zone = Some(value.parse().context("bad chat_zone")?),
+                "chat_contains" => filter.chat_contains = Some(value.to_lowercase()),
+                _ => {}
+            }
+        }
+        Ok(filter)
+    }
+
+    pub fn allows(&self, event: &FeedEvent) -> bool {
+        if !self.kinds.is_empty() && !self.kinds.contains(&event.kind()) {
+            return false;
+        }
+        let FeedEvent::Chat {
+            zone,
+            channel,
+            text,
+            ..
+        } = event
+        else {
+            return true;
+        };
+        self.chat_channel.as_ref().is_none_or(|c| c == channel)
+            && self.chat_zone.is_none_or(|z| z == *zone)
+            && self
+                .chat_contains
+                .as_ref()
+                .is_none_or(|word| text.to_lowercase().contains(word))
+    }
+}
+
+/// Decoded `name=value` pairs of a query string
+fn query_pairs(query: &str) -> impl Iterator<Item = (String, String)> + '_ {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (percent_decode(name), percent_decode(value))
+        })
+}
+
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => out.push(b' '),
+            b'%' => {
+                match text
+                    .get(i + 1..i + 3)
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                {
+                    Some(byte) => {
+                        out.push(byte);
+                        i += 2;
+                    }
+                    None => out.push(b'%'),
+                }
+            }
+            byte => out.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// `Sec-WebSocket-Accept` for a client's `Sec-WebSocket-Key`
+pub fn accept_key(key: &str) -> String {
+    let digest = Sha1::digest(format!("{}{}", key.trim(), WEBSOCKET_GUID));
+    base64::engine::general_purpose::STANDARD.encode(digest)
+}
+
+/// Accept dashboard clients on `addr`, each in its own task, until the
+/// bus closes
+pub async fn start(addr: SocketAddr, password: String, events: WeakEventBus) -> Result<()> {
+    let listener = TcpListener::bind(addr)
+        .await
+        .with_context(|| format!("binding dashboard to {}", addr))?;
+    info!("Dashboard feed listening on {}", addr);
+    tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, peer)) => {
+                    let password = password.clone();
+                    let Some(events) = events.upgrade() else {
+                        return;
+                    };
+                    tokio::spawn(async move {
+                        if let Err(e) = serve(stream, &password, events).await {
+                            warn!("Dashboard client {} failed: {}", peer, e);
+                        }
+                    });
+                }
+                Err(e) => error!("Dashboard accept failed: {}", e),
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Run the WebSocket handshake with one client, then send it events until
+/// it closes the connection
+pub async fn serve<S: AsyncRead + AsyncWrite + Unpin + Send + 'static>(
+    stream: S,
+    password: &str,
+    events: EventBus,
+) -> Result<()> {
+    let (reader, mut writer) = tokio::io::split(stream);
+    let mut reader = BufReader::new(reader);
+
+    let request = match read_request(&mut reader).await {
+        Ok(request) => request,
+        Err(e) => {
+            writer
+                .write_all(b"HTTP/1.1 400 Bad Request\r\n\r\n")
+                .await?;
+            return Err(e);
+        }
+    };
+    let token = query_pairs(&request.query)
+        .find(|(name, _)| name == "token")
+        .map(|(_, token)| token)
+        .unwrap_or_default();
+    if !constant_time_eq(token.as_bytes(), password.as_bytes()) {
+        warn!("Dashboard client with a wrong token");
+        writer
+            .write_all(b"HTTP/1.1 401 Unauthorized\r\n\r\n")
+            .await?;
+        return Ok(());
+    }
+    let filter = match Filter::from_query(&request.query) {
+        Ok(filter) => filter,
+        Err(e) => {
+            writer
+                .write_all(b"HTTP/1.1 400 Bad Request\r\n\r\n")
+                .await?;
+            return Err(e);
+        }
+    };
+
+    let mut logins = events.subscribe::<PlayerLoggedIn>();
+    let mut chat = events.subscribe::<ChatMessage>();
+    let mut populations = events.subscribe::<ZonePopulation>();
+    let mut violations = events.subscribe::<Violation>();
+    drop(events);
+
+    writer
+        .write_all(
+            format!(
+                "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+                accept_key(&request.key)
+            )
+            .as_bytes(),
+        )
+        .await?;
+
+    // Frames are read in their own task, so waiting for events never
+    // leaves one half read
+    let (control_tx, mut control) = mpsc::channel(8);
+    let read_task = tokio::spawn(async move {
+        loop {
+            let frame = read_frame(&mut reader).await;
+            let done = !matches!(frame, Ok((op, _)) if op != OP_CLOSE);
+            if control_tx.send(frame).await.is_err() || done {
+                return;
+            }
+        }
+    });
+
+    let result = async {
+        loop {
+            let event: FeedEvent = tokio::select! {
+                frame = control.recv() => match frame {
+                    Some(Ok((OP_PING, payload))) => {
+                        write_frame(&mut writer, OP_PONG, &payload).await?;
+                        continue;
+                    }
+                    Some(Ok((OP_CLOSE, _))) | None => {
+                        write_frame(&mut writer, OP_CLOSE, &[]).await?;
+                        return Ok(());
+                    }
+                    Some(Ok(_)) => continue,
+                    Some(Err(e)) => return Err(e),
+                },
+                Some(event) = logins.recv() => event.into(),
+                Some(event) = chat.recv() => event.into(),
+                Some(event) = populations.recv() => event.into(),
+                Some(event) = violations.recv() => event.into(),
+                else => return Ok(()),
+            };
+            if filter.allows(&event) {
+                let json = serde_json::to_string(&event)?;
+                write_frame(&mut writer, OP_TEXT, json.as_bytes()).await?;
+            }
+        }
+    }
+    .await;
+    read_task.abort();
+    result
+}
+
+/// The parts of the handshake request that matter
+struct Request {
+    query: String,
+    key: String,
+}
+
+async fn read_request<R: AsyncBufRead + Unpin>(reader: &mut R) -> Result<Request> {
+    let request = read_http_request(reader, HTTP_REQUEST_TIMEOUT).await?;
+    if request.method != "GET" {
+        return Err(anyhow!("not a WebSocket request: {}", request.method));
+    }
+    if !request
+        .header("upgrade")
+        .is_some_and(|upgrade| upgrade.eq_ignore_ascii_case("websocket"))
+    {
+        return Err(anyhow!("not a WebSocket upgrade"));
+    }
+    let key = request
+        .header("sec-websocket-key")
+        .ok_or_else(|| anyhow!("no Sec-WebSocket-Key"))?;
+    Ok(Request {
+        query: request.query().to_string(),
+        key: key.to_string(),
+    })
+}
+
+/// Read one client frame: its opcode and unmasked payload
+async fn read_frame<R: AsyncRead + Unpin>(reader: &mut R) -> Result<(u8, Vec<u8>)> {
+    let mut header = [0u8; 2];
+    reader.read_exact(&mut header).await?;
+    let opcode = header[0] & 0x0F;
+    let len = match header[1] & 0x7F {
+        126 => reader.read_u16().await? as u64,
+        127 => reader.read_u64().await?,
+        len => len as u64,
+    };
+    if len > MAX_CLIENT_FRAME {
+        return Err(anyhow!("{}-byte frame from a dashboard client", len));
+    }
+    let mut mask = [0u8; 4];
+    if header[1] & 0x80 != 0 {
+        reader.read_exact(&mut mask).await?;
+    }
+    let mut payload = vec![0u8; len as usize];
+    reader.read_exact(&mut payload).await?;
+    for (i, byte) in payload.iter_mut().enumerate() {
+        *byte ^= mask[i % 4];
+    }
+    Ok((opcode, payload))
+}
+
+/// Write one unmasked, unfragmented frame
+async fn write_frame<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    opcode: u8,
+    payload: &[u8],
+) -> Result<()> {
+    let mut frame = vec![0x80 | opcode];
+    match payload.len() {
+        len @ 0..=125 => frame.push(len as u8),
+        len @ 126..=0xFFFF => {
+            frame.push(126);
+            frame.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            frame.push(127);
+            frame.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    frame.extend_from_slice(payload);
+    writer.write_all(&frame).await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::DuplexStream;
+
+    const REQUEST: &str = "GET /?token=s%3Dcret&events=login,chat&chat_channel=shout HTTP/1.1\r\n\
+        Host: localhost\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+        Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n";
+
+    fn chat(channel: &str, text: &str) -> ChatMessage {
+        ChatMessage {
+            session_id: 3,
+            zone: 1,
+            channel: channel.to_string(),
+            from: "Alice".to_string(),
+            text: text.to_string(),
+        }
+    }
+
+    /// Everything up to the end of the response headers
+    async fn read_response(client: &mut DuplexStream) -> String {
+        let mut response = Vec::new();
+        while !response.ends_with(b"\r\n\r\n") {
+            response.push(client.read_u8().await.unwrap());
+        }
+        String::from_utf8(response).unwrap()
+    }
+
+    async fn read_text(client: &mut DuplexStream) -> serde_json::Value {
+        let (opcode, payload) = read_frame(client).await.unwrap();
+        assert_eq!(opcode, OP_TEXT);
+        serde_json::from_slice(&payload).unwrap()
+    }
+
+    #[test]
+    fn test_accept_key() {
+        // The example from RFC 6455
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+    }
+
+    #[test]
+    fn test_filter() {
+        assert_eq!(Filter::from_query("token=x").unwrap(), Filter::default());
+        assert!(Filter::from_query("events=logins").is_err());
+
+        let filter =
+            Filter::from_query("events=chat,violation&chat_zone=1&chat_contains=Buy%20ZENY")
+                .unwrap();
+        assert!(filter.allows(&chat("say", "WTS cheap, buy zeny here").into()));
+        assert!(!filter.allows(&chat("say", "hello").into()));
+        assert!(
+            !filter.allows(
+                &ZonePopulation {
+                    zone: 1,
+                    players: 2,
+                    monsters: 0,
+                    messages_per_sec: 0,
+                }
+                .into()
+            )
+        );
+        assert!(
+            filter.allows(
+                &Violation {
+                    session_id: 3,
+                    account_id: None,
+                    kind: "speed".to_string(),
+                    detail: String::new(),
+                }
+                .into()
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn test_serve_pushes_filtered_events() {
+        let events = EventBus::new();
+        let (mut client, server) = tokio::io::duplex(4096);
+        let task = tokio::spawn({
+            let events = events.clone();
+            async move { serve(server, "s=cret", events).await }
+        });
+
+        client.write_all(REQUEST.as_bytes()).await.unwrap();
+        let response = read_response(&mut client).await;
+        assert!(response.starts_with("HTTP/1.1 101 "));
+        assert!(response.contains("Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"));
+
+        events.publish(ZonePopulation {
+            zone: 1,
+            players: 5,
+            monsters: 3,
+            messages_per_sec: 10,
+        });
+        events.publish(chat("say", "not this one"));
+        events.publish(PlayerLoggedIn {
+            account_id: 7,
+            session_id: 3,
+        });
+        assert_eq!(
+            read_text(&mut client).await,
+            serde_json::json!({"type": "login", "account_id": 7, "session_id": 3})
+        );
+        events.publish(chat("shout", "hello"));
+        assert_eq!(read_text(&mut client).await["text"], "hello");
+
+        // A masked close, as a browser would send it
+        client.write_all(&[0x88, 0x80, 1, 2, 3, 4]).await.unwrap();
+        assert_eq!(read_frame(&mut client).await.unwrap(), (OP_CLOSE, vec![]));
+        task.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_serve_wants_the_token() {
+        let (mut client, server) = tokio::io::duplex(4096);
+        client
+            .write_all(REQUEST.replace("s%3Dcret", "guess").as_bytes())
+            .await
+            .unwrap();
+        serve(server, "s=cret", EventBus::new()).await.unwrap();
+        assert!(
+            read_response(&mut client)
+                .await
+                .starts_with("HTTP/1.1 401 ")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_serve_refuses_endless_requests() {
+        let (mut client, server) = tokio::io::duplex(64 * 1024);
+        let task = tokio::spawn(serve(server, "s=cret", EventBus::new()));
+        let endless = format!("GET /?token={} HTTP/1.1\r\n", "a".repeat(20 * 1024));
+        client.write_all(endless.as_bytes()).await.unwrap();
+        assert!(task.await.unwrap().is_err());
+        assert!(
+            read_response(&mut client)
+                .await
+                .starts_with("HTTP/1.1 400 ")
+        );
+    }
+}
@@ -0,0 +1,470 @@
+//! Database backups
+//!
+//! [`backup`] writes a consistent snapshot of the database in
+//! `DATABASE_URL` to a directory, named after the time it was taken, and
+//! [`restore`] puts one back. Operators run them from the lobby's admin
+//! command line (`ro2-lobby admin db backup|restore`), e.g. before a
+//! migration; the lobby can also take them on a schedule, pruning the
+//! oldest, when the `[backup]` section of `config/lobby.toml` says so:
+//!
+//! ```toml
+//! [backup]
+//! dir = "backups"
+//! interval_hours = 24   # 0 for no scheduled backups
+//! keep = 14             # Newest backups kept; 0 keeps them all
+//! ```
+//!
+//! SQLite databases are checkpointed, so nothing is left in the WAL, and
+//! copied with `VACUUM INTO`, which reads the whole database in one
+//! transaction; the servers can keep writing while it runs. MySQL ones are
+//! dumped with `mysqldump --single-transaction` and restored with `mysql`,
+//! both of which must be on the `PATH`.
+//!
+//! Restoring replaces the whole database. Stop the servers first.
+
+use crate::Result;
+use crate::clock::Clock;
+use crate::config::{self, Validate};
+use anyhow::{Context, anyhow};
+use serde::Deserialize;
+use sqlx::Connection;
+use sqlx::sqlite::SqliteConnection;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::process::Command;
+use tokio::task::JoinHandle;
+use tracing::{error, info};
+
+/// Start of every backup's file name
+const PREFIX: &str = "backup-";
+
+/// Scheduled backup settings
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct BackupConfig {
+    pub dir: PathBuf,
+    /// 0 for no scheduled backups
+    pub interval_hours: u64,
+    /// 0 keeps every backup
+    pub keep: usize,
+}
+
+impl Default for BackupConfig {
+    fn default() -> Self {
+        Self {
+            dir: PathBuf::from("backups"),
+            interval_hours: 0,
+            keep: 14,
+        }
+    }
+}
+
+impl BackupConfig {
+    /// Read the `[backup]` section of `path`; missing keys keep their
+    /// defaults
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        config::section(path, "backup")
+    }
+
+    /// Parse the `[backup]` section of TOML text
+    pub fn from_toml(text: &str) -> Result<Self> {
+        config::section_from_toml(text, "backup")
+    }
+
+    /// Time between scheduled backups, if there are any
+    pub fn interval(&self) -> Option<Duration> {
+        match self.interval_hours {
+            0 => None,
+            hours => Some(Duration::from_secs(hours * 3600)),
+        }
+    }
+}
+
+impl Validate for BackupConfig {}
+
+/// A database, as named by a `DATABASE_URL`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Database {
+    Sqlite(PathBuf),
+    Mysql {
+        host: String,
+        port: u16,
+        user: String,
+        password: Option<String>,
+        database: String,
+    },
+}
+
+impl Database {
+    pub fn parse(url: &str) -> Result<Self> {
+        if let Some(path) = url
+            .strip_prefix("sqlite://")
+            .or_else(|| url.strip_prefix("sqlite:"))
+        {
+            let path = path.split('?').next().unwrap_or_default();
+            if path.is_empty() || path == ":memory:" {
+                return Err(anyhow!("can't back up an in-memory database"));
+            }
+            return Ok(Self::Sqlite(PathBuf::from(path)));
+        }
+
+        let rest = url
+            .strip_prefix("mysql://")
+            .or_else(|| url.strip_prefix("mariadb://"))
+            .ok_or_else(|| anyhow!("unsupported database URL {:?}", url))?;
+        let rest = rest.split('?').next().unwrap_or_default();
+        let (credentials, rest) = rest.rsplit_once('@').unwrap_or(("", rest));
+        let (user, password) = match credentials.split_once(':') {
+            Some((user, password)) => (user, Some(password.to_string())),
+            None => (credentials, None),
+        };
+        let (address, database) = rest
+            .split_once('/')
+            .filter(|(_, database)| !database.is_empty())
+            .ok_or_else(|| anyhow!("no database named in the MySQL URL"))?;
+        let (host, port) = match address.rsplit_once(':') {
+            Some((host, port)) => (host, port.parse().context("bad MySQL port")?),
+            None => (address, 3306),
+        };
+        Ok(Self::Mysql {
+            host: host.to_string(),
+            port,
+            user: user.to_string(),
+            password,
+            database: database.to_string(),
+        })
+    }
+
+    fn extension(&self) -> &'static str {
+        match self {
+            Self::Sqlite(_) => "db",
+            Self::Mysql { .. } => "sql",
+        }
+    }
+
+    /// A MySQL client command for this database, logged in
+    fn mysql_command(&self, program: &str) -> Command {
+        let Self::Mysql {
+            host,
+            port,
+            user,
+            password,
+            ..
+        } = self
+        else {
+            unreachable!("only MySQL databases run MySQL clients");
+        };
+        let mut command = Command::new(program);
+        command
+            .arg(format!("--host={}", host))
+            .arg(format!("--port={}", port));
+        if !user.is_empty() {
+            command.arg(format!("--user={}", user));
+        }
+        // Kept off the command line, where other users could see it
+        if let Some(password) = password {
+            command.env("MYSQL_PWD", password);
+        }
+        command
+    }
+}
+
+/// Write a snapshot of the database at `url` into `dir`; returns its path
+pub async fn backup(url: &str, dir: impl AsRef<Path>, clock: &Clock) -> Result<PathBuf> {
+    let database = Database::parse(url)?;
+    let dir = dir.as_ref();
+    std::fs::create_dir_all(dir).with_context(|| format!("creating {}", dir.display()))?;
+    let path = dir.join(format!(
+        "{}{}.{}",
+        PREFIX,
+        clock.now().format("%Y%m%d-%H%M%S"),
+        database.extension()
+    ));
+    if path.exists() {
+        return Err(anyhow!("{} already exists", path.display()));
+    }
+
+    match &database {
+        Database::Sqlite(source) => {
+            let mut conn = SqliteConnection::connect(&format!("sqlite:{}", source.display()))
+                .await
+                .with_context(|| format!("opening {}", source.display()))?;
+            sqlx::query("PRAGMA wal_checkpoint(TRUNCATE)")
+                .execute(&mut conn)
+                .await?;
+            sqlx::query("VACUUM INTO ?")
+                .bind(path.to_string_lossy().into_owned())
+                .execute(&mut conn)
+                .await?;
+            conn.close().await?;
+        }
+        Database::Mysql { database: name, .. } => {
+            let status = database
+                .mysql_command("mysqldump")
+                .arg("--single-transaction")
+                .arg("--routines")
+                .arg(format!("--result-file={}", path.display()))
+                .arg(name)
+                .status()
+                .await
+                .context("running mysqldump")?;
+            if !status.success() {
+                let _ = std::fs::remove_file(&path);
+                return Err(anyhow!("mysqldump failed: {}", status));
+            }
+        }
+    }
+    info!("Backed up the database to {}", path.display());
+    Ok(path)
+}
+
+/// Replace the database at `url` with the backup at `file`
+pub async fn restore(url: &str, file: impl AsRef<Path>) -> Result<()> {
+    let file = file.as_ref();
+    if !file.is_file() {
+        return Err(anyhow!("no backup at {}", file.display()));
+    }
+
+    match &Database::parse(url)? {
+        Database::Sqlite(target) => {
+            // Don't replace a database with something that isn't one
+            let mut conn = SqliteConnection::connect(&format!("sqlite:{}?mode=ro", file.display()))
+                .await
+                .with_context(|| format!("opening {}", file.display()))?;
+            let (check,): (String,) = sqlx::query_as("PRAGMA integrity_check")
+                .fetch_one(&mut conn)
+                .await?;
+            conn.close().await?;
+            if check != "ok" {
+                return Err(anyhow!("{} is damaged: {}", file.display(), check));
+            }
+
+            // Copied next to the target first, so a failed copy leaves the
+            // database as it was. The old write-ahead log holds committed
+            // transactions until the replacement is in place, so it goes
+            // only after that.
+            let staged = target.with_extension("restoring");
+            std::fs::copy(file, &staged).with_context(|| format!("copying {}", file.display()))?;
+            if let Err(e) = std::fs::rename(&staged, target) {
+                let _ = std::fs::remove_file(&staged);
+                return Err(e).with_context(|| format!("replacing {}", target.display()));
+            }
+            for suffix in ["-wal", "-shm"] {
+                let mut leftover = target.clone().into_os_string();
+                leftover.push(suffix);
+                let _ = std::fs::remove_file(leftover);
+            }
+        }
+        database @ Database::Mysql { database: name, .. } => {
+            let dump = std::fs::File::open(file)?;
+            let status = database
+                .mysql_command("mysql")
+                .arg(name)
+                .stdin(Stdio::from(dump))
+                .status()
+                .await
+                .context("running mysql")?;
+            if !status.success() {
+                return Err(anyhow!("mysql failed: {}", status));
+            }
+        }
+    }
+    info!("Restored the database from {}", file.display());
+    Ok(())
+}
+
+/// Delete all but the newest `keep` backups in `dir`; returns the ones
+/// deleted
+pub fn prune(dir: impl AsRef<Path>, keep: usize) -> Result<Vec<PathBuf>> {
+    let dir = dir.as_ref();
+    let mut backups = Vec::new();
+    for entry in std::fs::read_dir(dir).with_context(|| format!("reading {}", dir.display()))? {
+        let path = entry?.path();
+        let is_backup = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| name.starts_with(PREFIX))
+            && path
+                .extension()
+                .is_some_and(|ext| ext == "db" || ext == "sql");
+        if is_backup {
+            backups.push(path);
+        }
+    }
+    if keep == 0 || backups.len() <= keep {
+        return Ok(Vec::new());
+    }
+
+    // Names hold the time they were taken, so they sort oldest first
+    backups.sort();
+    backups.truncate(backups.len() - keep);
+    for path in &backups {
+        std::fs::remove_file(path).with_context(|| format!("deleting {}", path.display()))?;
+    }
+    Ok(backups)
+}
+
+/// Back up the database at `url` every `config.interval()`, first after one
+/// interval, pruning old backups each time; nothing with no interval
+pub fn spawn_backups(url: String, config: BackupConfig, clock: Clock) -> Option<JoinHandle<()>> {
+    let every = config.interval()?;
+    info!(
+        "Backing up the database to {} every {} hours, keeping {}",
+        config.dir.display(),
+        config.interval_hours,
+        config.keep
+    );
+    Some(tokio::spawn(async move {
+        let mut interval = tokio::time::interval_at(tokio::time::Instant::now() + every, every);
+        loop {
+            interval.tick().await;
+            if let Err(e) = backup(&url, &config.dir, &clock).await {
+                error!("Scheduled backup failed: {:#}", e);
+                continue;
+            }
+            match prune(&config.dir, config.keep) {
+                Ok(pruned) if !pruned.is_empty() => {
+                    info!("Deleted {} old backups", pruned.len())
+                }
+                Ok(_) => {}
+                Err(e) => error!("Pruning old backups failed: {:#}", e),
+            }
+        }
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("ro2-backup-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    async fn names(url: &str) -> Vec<String> {
+        let mut conn = SqliteConnection::connect(url).await.unwrap();
+        sqlx::query_scalar("SELECT name FROM characters ORDER BY name")
+            .fetch_all(&mut conn)
+            .await
+            .unwrap()
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            Database::parse("sqlite://data/ragnoria.db?mode=rwc").unwrap(),
+            Database::Sqlite(PathBuf::from("data/ragnoria.db"))
+        );
+        assert_eq!(
+            Database::parse("sqlite:ragnoria.db").unwrap(),
+            Database::Sqlite(PathBuf::from("ragnoria.db"))
+        );
+        assert!(Database::parse("sqlite::memory:").is_err());
+        assert_eq!(
+            Database::parse("mysql://ro2:p@ss@db.local:3307/ragnoria").unwrap(),
+            Database::Mysql {
+                host: "db.local".to_string(),
+                port: 3307,
+                user: "ro2".to_string(),
+                password: Some("p@ss".to_string()),
+                database: "ragnoria".to_string(),
+            }
+        );
+        assert!(matches!(
+            Database::parse("mysql://localhost/ragnoria").unwrap(),
+            Database::Mysql {
+                port: 3306,
+                password: None,
+                ..
+            }
+        ));
+        assert!(Database::parse("mysql://localhost/").is_err());
+        assert!(Database::parse("postgres://localhost/ragnoria").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_backup_and_restore_sqlite() {
+        let dir = temp_dir("sqlite");
+        let url = format!("sqlite://{}?mode=rwc", dir.join("game.db").display());
+        {
+            let mut conn = SqliteConnection::connect(&url).await.unwrap();
+            sqlx::raw_sql(
+                "PRAGMA journal_mode = WAL;
+                 CREATE TABLE characters (name TEXT);
+                 INSERT INTO characters VALUES ('Alice'), ('Bob');",
+            )
+            .execute(&mut conn)
+            .await
+            .unwrap();
+
+            // Taken while the connection still has the database open
+            let clock = Clock::at_unix(1_700_000_000);
+            let path = backup(&url, dir.join("backups"), &clock).await.unwrap();
+            assert_eq!(path.file_name().unwrap(), "backup-20231114-221320.db");
+            assert!(backup(&url, dir.join("backups"), &clock).await.is_err());
+
+            // A bad migration
+            sqlx::query("DELETE FROM characters")
+                .execute(&mut conn)
+                .await
+                .unwrap();
+        }
+        assert!(names(&url).await.is_empty());
+
+        let file = dir.join("backups/backup-20231114-221320.db");
+        restore(&url, &file).await.unwrap();
+        assert_eq!(names(&url).await, ["Alice", "Bob"]);
+
+        std::fs::write(dir.join("junk.db"), "not a database").unwrap();
+        assert!(restore(&url, dir.join("junk.db")).await.is_err());
+        assert!(restore(&url, dir.join("missing.db")).await.is_err());
+        assert_eq!(names(&url).await, ["Alice", "Bob"]);
+
+        // A replacement that fails keeps the target's log
+        let busy = dir.join("busy.db");
+        std::fs::create_dir_all(busy.join("in-use")).unwrap();
+        std::fs::write(dir.join("busy.db-wal"), "committed").unwrap();
+        let busy_url = format!("sqlite://{}", busy.display());
+        assert!(restore(&busy_url, &file).await.is_err());
+        assert!(dir.join("busy.db-wal").exists());
+        assert!(!dir.join("busy.restoring").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_prune() {
+        let dir = temp_dir("prune");
+        for name in [
+            "backup-20240103-000000.db",
+            "backup-20240101-000000.db",
+            "backup-20240102-000000.sql",
+            "notes.txt",
+        ] {
+            std::fs::write(dir.join(name), "").unwrap();
+        }
+        assert!(prune(&dir, 0).unwrap().is_empty());
+        assert_eq!(
+            prune(&dir, 2).unwrap(),
+            [dir.join("backup-20240101-000000.db")]
+        );
+        assert!(prune(&dir, 2).unwrap().is_empty());
+        assert!(dir.join("notes.txt").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_config() {
+        assert_eq!(
+            BackupConfig::load("does/not/exist.toml").unwrap(),
+            BackupConfig::default()
+        );
+        assert_eq!(BackupConfig::default().interval(), None);
+        let config = BackupConfig::from_toml("[backup]\ninterval_hours = 6\nkeep = 3").unwrap();
+        assert_eq!(config.interval(), Some(Duration::from_secs(6 * 3600)));
+        assert_eq!(config.dir, PathBuf::from("backups"));
+    }
+}
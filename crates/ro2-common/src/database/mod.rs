@@ -14,24 +14,77 @@ pub struct Account {
     pub last_login: Option<i64>,
     pub is_banned: bool,
     pub ban_reason: Option<String>,
+    /// When the account was deactivated; such accounts can't log in
+    pub deactivated_at: Option<i64>,
 }
 
 /// Character model
-#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, FromRow)]
 pub struct Character {
     pub id: i64,
     pub account_id: i64,
+    /// Position in the character select screen
+    pub slot_index: i32,
     pub name: String,
-    pub level: i32,
+    #[sqlx(rename = "class_id")]
     pub job_class: i32,
+    pub level: i32,
+    pub experience: i64,
+    pub job_level: i32,
+    pub job_experience: i64,
+    /// 0 = male, 1 = female
+    pub gender: i32,
+    pub hair_style: i32,
+    pub hair_color: i32,
+    pub face: i32,
     pub map_id: i32,
+    #[sqlx(rename = "position_x")]
     pub x: f32,
+    #[sqlx(rename = "position_y")]
     pub y: f32,
+    #[sqlx(rename = "position_z")]
     pub z: f32,
+    pub hp: i32,
+    pub max_hp: i32,
+    pub mp: i32,
+    pub max_mp: i32,
+    pub gold: i64,
     pub created_at: i64,
+    pub last_played: Option<i64>,
     pub deleted_at: Option<i64>,
 }
 
+/// A character about to be created
+#[derive(Debug, Clone, PartialEq)]
+pub struct NewCharacter {
+    pub account_id: i64,
+    pub slot_index: i32,
+    pub name: String,
+    pub job_class: i32,
+    pub gender: i32,
+    pub hair_style: i32,
+    pub hair_color: i32,
+    pub face: i32,
+    pub map_id: i32,
+    pub position: (f32, f32, f32),
+    pub hp: i32,
+    pub mp: i32,
+    pub gold: i64,
+}
+
+/// Character attributes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, FromRow)]
+pub struct CharacterStats {
+    pub character_id: i64,
+    pub strength: i32,
+    pub dexterity: i32,
+    pub intelligence: i32,
+    pub vitality: i32,
+    pub luck: i32,
+    /// Unallocated points
+    pub stat_points: i32,
+}
+
 /// Session model (for session key management)
 #[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
 pub struct Session {
@@ -43,4 +96,196 @@ pub struct Session {
     pub is_active: bool,
 }
 
+/// A name a character used to have
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, FromRow)]
+pub struct NameChange {
+    pub character_id: i64,
+    pub old_name: String,
+    pub new_name: String,
+    pub changed_at: i64,
+    /// Admin who did the rename; `None` when the player paid for it
+    pub changed_by: Option<String>,
+}
+
+/// Seconds a deactivated account can still be restored in before it's
+/// anonymized
+pub const ACCOUNT_RESTORE_GRACE_SECS: i64 = 30 * 24 * 60 * 60;
+
+/// A deactivation, restore or anonymization of an account
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, FromRow)]
+pub struct AccountAuditEntry {
+    pub account_id: i64,
+    /// `deactivate`, `restore` or `anonymize`
+    pub action: String,
+    /// Admin who did it, or `player` / `system`
+    pub actor: String,
+    pub at: i64,
+}
+
+/// Playtime restrictions of an account (see [`crate::playtime`])
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct AccountPlaytimeLimits {
+    pub account_id: i64,
+    pub daily_cap_minutes: Option<i64>,
+    pub allowed_start_minute: Option<i64>,
+    pub allowed_end_minute: Option<i64>,
+}
+
+/// A message to every world server's players (see
+/// [`queries::GlobalMessageQueries`])
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, FromRow)]
+pub struct GlobalMessage {
+    /// Set by the database; ignored when posting
+    pub id: i64,
+    /// `megaphone` or `announcement`
+    pub kind: String,
+    /// Character name, or the GM's
+    pub sender: String,
+    /// Who used the megaphone
+    pub character_id: Option<i64>,
+    /// The megaphone item used
+    pub item_id: Option<i32>,
+    pub text: String,
+    pub sent_at: i64,
+}
+
+/// An anti-bot challenge and how it went (see
+/// [`queries::BotChallengeQueries`])
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, FromRow)]
+pub struct BotChallengeEntry {
+    /// Set by the database; ignored when recording
+    pub id: i64,
+    pub account_id: Option<i64>,
+    pub character_id: i64,
+    /// What set it off: a violation kind, or `gm:<name>`
+    pub reason: String,
+    /// Which challenge was asked
+    pub kind: String,
+    /// `passed`, `wrong`, `timed_out` or `left`
+    pub result: String,
+    /// Whether the player was kicked for it
+    pub kicked: bool,
+    pub issued_at: i64,
+    pub resolved_at: i64,
+}
+
+/// A channel's latest population report (see
+/// [`queries::ChannelQueries`])
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, FromRow)]
+pub struct ChannelStatus {
+    pub id: i64,
+    pub name: String,
+    /// Where players connect to it
+    pub address: String,
+    pub players: i64,
+    pub reported_at: i64,
+}
+
+/// What a guild rank may do with the guild's storage
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, FromRow)]
+pub struct GuildRank {
+    pub guild_id: i64,
+    /// 0 is the guild master
+    pub rank: i32,
+    pub name: String,
+    pub can_view: bool,
+    pub can_deposit: bool,
+    /// Items a member may withdraw a day; `None` for no limit
+    pub items_per_day: Option<i64>,
+    /// Zeny a member may withdraw a day; `None` for no limit
+    pub zeny_per_day: Option<i64>,
+}
+
+/// A stack in guild storage
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, FromRow)]
+pub struct GuildStorageItem {
+    pub slot_index: i32,
+    pub item_id: i32,
+    pub quantity: i32,
+}
+
+/// A guild storage deposit or withdrawal
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, FromRow)]
+pub struct GuildStorageLogEntry {
+    pub id: i64,
+    pub guild_id: i64,
+    pub character_id: i64,
+    /// `deposit_item`, `withdraw_item`, `deposit_zeny` or `withdraw_zeny`
+    pub action: String,
+    /// `None` for zeny
+    pub item_id: Option<i32>,
+    pub amount: i64,
+    pub at: i64,
+}
+
+/// An open vending stall (see [`queries::VendingQueries`])
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, FromRow)]
+pub struct VendingStall {
+    pub character_id: i64,
+    /// The channel it's open on
+    pub channel_id: i64,
+    pub title: String,
+    pub map_id: i32,
+    #[sqlx(rename = "position_x")]
+    pub x: f32,
+    #[sqlx(rename = "position_y")]
+    pub y: f32,
+    #[sqlx(rename = "position_z")]
+    pub z: f32,
+    pub opened_at: i64,
+}
+
+/// Items a stall sells from one of its owner's inventory slots
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, FromRow)]
+pub struct VendingItem {
+    pub character_id: i64,
+    pub stall_slot: i32,
+    pub inventory_slot: i32,
+    pub item_id: i32,
+    /// Left for sale
+    pub quantity: i32,
+    /// Zeny each
+    pub price: i64,
+}
+
+/// Who a profile value belongs to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ProfileOwner {
+    Character(i64),
+    Account(i64),
+}
+
+/// One value a script or system keeps for a character or account
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, FromRow)]
+pub struct ProfileValue {
+    /// The system or script that owns it
+    pub namespace: String,
+    pub key: String,
+    /// JSON
+    pub value: String,
+    pub updated_at: i64,
+}
+
+/// A stat or skill reset a character had
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, FromRow)]
+pub struct CharacterReset {
+    pub character_id: i64,
+    /// `stats` or `skills`
+    pub kind: String,
+    /// Zeny paid; 0 with an item
+    pub cost: i64,
+    /// The reset item used up, `None` at an NPC
+    pub item_id: Option<i32>,
+    /// Points given back
+    pub refunded: i32,
+    pub reset_at: i64,
+}
+
+#[cfg(feature = "server")]
+pub mod backup;
 pub mod queries;
+pub mod transaction;
+
+pub use transaction::{
+    GuildStorageAction, GuildStorageUse, ItemTransaction, Outcome, Refusal, TransactionKind,
+};
@@ -1,7 +1,13 @@
 //! Database query functions
 
-use super::{Account, Session};
-use sqlx::{Pool, Sqlite};
+use super::{
+    Account, AccountAuditEntry, AccountPlaytimeLimits, BotChallengeEntry, ChannelStatus, Character,
+    CharacterReset, CharacterStats, GlobalMessage, GuildRank, GuildStorageItem,
+    GuildStorageLogEntry, NameChange, NewCharacter, ProfileOwner, ProfileValue, Session,
+    VendingItem, VendingStall,
+};
+use crate::crypto::constant_time_eq;
+use sqlx::{Pool, Sqlite, SqliteConnection};
 
 /// Account queries
 pub struct AccountQueries;
@@ -37,6 +43,267 @@ impl AccountQueries {
 
         Ok(result.last_insert_rowid())
     }
+
+    /// Insert `account` as it is, apart from its ID, e.g. one imported
+    /// from another server; returns its new ID
+    pub async fn insert(conn: &mut SqliteConnection, account: &Account) -> crate::Result<i64> {
+        let result = sqlx::query(
+            "INSERT INTO accounts (username, password_hash, email, created_at, last_login, is_banned, ban_reason, deactivated_at)
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+        )
+        .bind(&account.username)
+        .bind(&account.password_hash)
+        .bind(&account.email)
+        .bind(account.created_at)
+        .bind(account.last_login)
+        .bind(account.is_banned)
+        .bind(&account.ban_reason)
+        .bind(account.deactivated_at)
+        .execute(conn)
+        .await?;
+
+        Ok(result.last_insert_rowid())
+    }
+
+    /// Language the account reads server text in, if it has picked one
+    pub async fn language(pool: &Pool<Sqlite>, account_id: i64) -> crate::Result<Option<String>> {
+        let language: Option<(Option<String>,)> =
+            sqlx::query_as("SELECT language FROM accounts WHERE id = ?")
+                .bind(account_id)
+                .fetch_optional(pool)
+                .await?;
+
+        Ok(language.and_then(|(language,)| language))
+    }
+
+    /// Set the account's language; `None` goes back to the server default
+    pub async fn set_language(
+        pool: &Pool<Sqlite>,
+        account_id: i64,
+        language: Option<&str>,
+    ) -> crate::Result<()> {
+        sqlx::query("UPDATE accounts SET language = ? WHERE id = ?")
+            .bind(language)
+            .bind(account_id)
+            .execute(pool)
+            .await?;
+
+        Ok(())
+    }
+}
+
+/// Queries mapping accounts on an external account API to local ones
+///
+/// Local accounts made this way have no password hash, so they can only
+/// log in through the API.
+pub struct ExternalAccountQueries;
+
+impl ExternalAccountQueries {
+    /// The local account of `external_id`, created as `username` the first
+    /// time it logs in; that fails if a local account already has the name
+    pub async fn provision(
+        pool: &Pool<Sqlite>,
+        external_id: i64,
+        username: &str,
+        now: i64,
+    ) -> crate::Result<Account> {
+        let mut tx = pool.begin().await?;
+        let found: Option<(i64,)> =
+            sqlx::query_as("SELECT account_id FROM external_accounts WHERE external_id = ?")
+                .bind(external_id)
+                .fetch_optional(&mut *tx)
+                .await?;
+        let account_id = match found {
+            Some((account_id,)) => account_id,
+            None => {
+                let account_id = sqlx::query(
+                    "INSERT INTO accounts (username, password_hash, created_at, is_banned) VALUES (?, '', ?, 0)",
+                )
+                .bind(username)
+                .bind(now)
+                .execute(&mut *tx)
+                .await?
+                .last_insert_rowid();
+                sqlx::query(
+                    "INSERT INTO external_accounts (external_id, account_id, created_at) VALUES (?, ?, ?)",
+                )
+                .bind(external_id)
+                .bind(account_id)
+                .bind(now)
+                .execute(&mut *tx)
+                .await?;
+                account_id
+            }
+        };
+        let account = sqlx::query_as::<_, Account>("SELECT * FROM accounts WHERE id = ?")
+            .bind(account_id)
+            .fetch_one(&mut *tx)
+            .await?;
+        tx.commit().await?;
+
+        Ok(account)
+    }
+}
+
+/// Account deactivation queries
+///
+/// Deactivating an account logs it out and keeps it from logging in, but
+/// keeps everything so [`restore`](Self::restore) can undo it within the
+/// grace period (see [`super::ACCOUNT_RESTORE_GRACE_SECS`]). After that it
+/// should be [`anonymize`](Self::anonymize)d, which scrubs the personal data
+/// for good but keeps the account and character rows, so IDs in logs and
+/// [`audit_log`](Self::audit_log) still resolve.
+pub struct AccountDeactivationQueries;
+
+impl AccountDeactivationQueries {
+    /// Deactivate an active account; returns whether it was
+    pub async fn deactivate(
+        pool: &Pool<Sqlite>,
+        account_id: i64,
+        actor: &str,
+        now: i64,
+    ) -> crate::Result<bool> {
+        let mut tx = pool.begin().await?;
+        let result = sqlx::query(
+            "UPDATE accounts SET deactivated_at = ? WHERE id = ? AND deactivated_at IS NULL",
+        )
+        .bind(now)
+        .bind(account_id)
+        .execute(&mut *tx)
+        .await?;
+        if result.rows_affected() == 0 {
+            return Ok(false);
+        }
+
+        sqlx::query("DELETE FROM sessions WHERE account_id = ?")
+            .bind(account_id)
+            .execute(&mut *tx)
+            .await?;
+        Self::log(&mut tx, account_id, "deactivate", actor, now).await?;
+        tx.commit().await?;
+
+        Ok(true)
+    }
+
+    /// Reactivate an account deactivated less than `grace_secs` ago that
+    /// hasn't been anonymized; returns whether it was
+    pub async fn restore(
+        pool: &Pool<Sqlite>,
+        account_id: i64,
+        actor: &str,
+        now: i64,
+        grace_secs: i64,
+    ) -> crate::Result<bool> {
+        let mut tx = pool.begin().await?;
+        let result = sqlx::query(
+            "UPDATE accounts SET deactivated_at = NULL
+             WHERE id = ? AND deactivated_at > ? AND anonymized_at IS NULL",
+        )
+        .bind(account_id)
+        .bind(now - grace_secs)
+        .execute(&mut *tx)
+        .await?;
+        if result.rows_affected() == 0 {
+            return Ok(false);
+        }
+
+        Self::log(&mut tx, account_id, "restore", actor, now).await?;
+        tx.commit().await?;
+
+        Ok(true)
+    }
+
+    /// Scrub a deactivated account's username, password, email and
+    /// language and its characters' names, and delete its characters;
+    /// returns whether there was anything to scrub
+    ///
+    /// Scrubbed names are `deleted-<id>`, which no player can pick. Name
+    /// history is kept for moderation.
+    pub async fn anonymize(
+        pool: &Pool<Sqlite>,
+        account_id: i64,
+        actor: &str,
+        now: i64,
+    ) -> crate::Result<bool> {
+        let mut tx = pool.begin().await?;
+        let result = sqlx::query(
+            "UPDATE accounts SET username = 'deleted-' || id, password_hash = '', email = NULL,
+             last_login = NULL, language = NULL, anonymized_at = ?
+             WHERE id = ? AND deactivated_at IS NOT NULL AND anonymized_at IS NULL",
+        )
+        .bind(now)
+        .bind(account_id)
+        .execute(&mut *tx)
+        .await?;
+        if result.rows_affected() == 0 {
+            return Ok(false);
+        }
+
+        sqlx::query(
+            "UPDATE characters SET name = 'deleted-' || id, deleted_at = COALESCE(deleted_at, ?)
+             WHERE account_id = ?",
+        )
+        .bind(now)
+        .bind(account_id)
+        .execute(&mut *tx)
+        .await?;
+        Self::log(&mut tx, account_id, "anonymize", actor, now).await?;
+        tx.commit().await?;
+
+        Ok(true)
+    }
+
+    /// Accounts deactivated at least `grace_secs` ago and not yet
+    /// anonymized
+    pub async fn past_grace(
+        pool: &Pool<Sqlite>,
+        now: i64,
+        grace_secs: i64,
+    ) -> crate::Result<Vec<i64>> {
+        let accounts: Vec<(i64,)> = sqlx::query_as(
+            "SELECT id FROM accounts WHERE deactivated_at <= ? AND anonymized_at IS NULL ORDER BY id",
+        )
+        .bind(now - grace_secs)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(accounts.into_iter().map(|(id,)| id).collect())
+    }
+
+    /// Everything done to an account, oldest first
+    pub async fn audit_log(
+        pool: &Pool<Sqlite>,
+        account_id: i64,
+    ) -> crate::Result<Vec<AccountAuditEntry>> {
+        let log = sqlx::query_as::<_, AccountAuditEntry>(
+            "SELECT account_id, action, actor, at FROM account_audit_log WHERE account_id = ? ORDER BY id",
+        )
+        .bind(account_id)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(log)
+    }
+
+    async fn log(
+        conn: &mut SqliteConnection,
+        account_id: i64,
+        action: &str,
+        actor: &str,
+        at: i64,
+    ) -> crate::Result<()> {
+        sqlx::query(
+            "INSERT INTO account_audit_log (account_id, action, actor, at) VALUES (?, ?, ?, ?)",
+        )
+        .bind(account_id)
+        .bind(action)
+        .bind(actor)
+        .bind(at)
+        .execute(&mut *conn)
+        .await?;
+
+        Ok(())
+    }
 }
 
 /// Session queries
@@ -66,23 +333,2259 @@ impl SessionQueries {
         Ok(result.last_insert_rowid())
     }
 
-    /// Validate session key
+    /// Validate an account's session key
+    ///
+    /// The key is compared in constant time here rather than by the
+    /// database, so how long a guess takes doesn't hint at how close it was.
     pub async fn validate(
         pool: &Pool<Sqlite>,
+        account_id: i64,
         session_key: &str,
     ) -> crate::Result<Option<Session>> {
         let now = chrono::Utc::now().timestamp();
 
-        let session = sqlx::query_as::<_, Session>(
-            "SELECT * FROM sessions WHERE session_key = ? AND is_active = 1 AND expires_at > ?",
+        let sessions = sqlx::query_as::<_, Session>(
+            "SELECT * FROM sessions WHERE account_id = ? AND is_active = 1 AND expires_at > ?",
         )
-        .bind(session_key)
+        .bind(account_id)
         .bind(now)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(sessions.into_iter().find(|session| {
+            constant_time_eq(session.session_key.as_bytes(), session_key.as_bytes())
+        }))
+    }
+}
+
+/// Character queries
+pub struct CharacterQueries;
+
+impl CharacterQueries {
+    /// An account's characters that haven't been deleted, by slot
+    pub async fn list_for_account(
+        pool: &Pool<Sqlite>,
+        account_id: i64,
+    ) -> crate::Result<Vec<Character>> {
+        let characters = sqlx::query_as::<_, Character>(
+            "SELECT * FROM characters WHERE account_id = ? AND deleted_at IS NULL ORDER BY slot_index",
+        )
+        .bind(account_id)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(characters)
+    }
+
+    /// A character's attributes, if it has a stats row
+    pub async fn stats(
+        pool: &Pool<Sqlite>,
+        character_id: i64,
+    ) -> crate::Result<Option<CharacterStats>> {
+        let stats = sqlx::query_as::<_, CharacterStats>(
+            "SELECT * FROM character_stats WHERE character_id = ?",
+        )
+        .bind(character_id)
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(stats)
+    }
+
+    /// Insert a character with full HP/MP and default attributes; returns
+    /// its ID
+    ///
+    /// Takes a connection so creation can share a transaction with the
+    /// character's starting items and skills.
+    pub async fn create(conn: &mut SqliteConnection, new: &NewCharacter) -> crate::Result<i64> {
+        let (x, y, z) = new.position;
+        let result = sqlx::query(
+            "INSERT INTO characters (account_id, slot_index, name, class_id, gender, hair_style, hair_color, face, map_id, position_x, position_y, position_z, hp, max_hp, mp, max_mp, gold, created_at)
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+        )
+        .bind(new.account_id)
+        .bind(new.slot_index)
+        .bind(&new.name)
+        .bind(new.job_class)
+        .bind(new.gender)
+        .bind(new.hair_style)
+        .bind(new.hair_color)
+        .bind(new.face)
+        .bind(new.map_id)
+        .bind(x)
+        .bind(y)
+        .bind(z)
+        .bind(new.hp)
+        .bind(new.hp)
+        .bind(new.mp)
+        .bind(new.mp)
+        .bind(new.gold)
+        .bind(chrono::Utc::now().timestamp())
+        .execute(&mut *conn)
+        .await?;
+        let character_id = result.last_insert_rowid();
+
+        sqlx::query("INSERT INTO character_stats (character_id) VALUES (?)")
+            .bind(character_id)
+            .execute(&mut *conn)
+            .await?;
+
+        Ok(character_id)
+    }
+
+    /// Insert `character` as it is, apart from its ID, with default
+    /// stats; returns its new ID
+    pub async fn insert(conn: &mut SqliteConnection, character: &Character) -> crate::Result<i64> {
+        let c = character;
+        let result = sqlx::query(
+            "INSERT INTO characters (account_id, slot_index, name, class_id, level, experience, job_level, job_experience, gender, hair_style, hair_color, face, map_id, position_x, position_y, position_z, hp, max_hp, mp, max_mp, gold, created_at, last_played, deleted_at)
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+        )
+        .bind(c.account_id)
+        .bind(c.slot_index)
+        .bind(&c.name)
+        .bind(c.job_class)
+        .bind(c.level)
+        .bind(c.experience)
+        .bind(c.job_level)
+        .bind(c.job_experience)
+        .bind(c.gender)
+        .bind(c.hair_style)
+        .bind(c.hair_color)
+        .bind(c.face)
+        .bind(c.map_id)
+        .bind(c.x)
+        .bind(c.y)
+        .bind(c.z)
+        .bind(c.hp)
+        .bind(c.max_hp)
+        .bind(c.mp)
+        .bind(c.max_mp)
+        .bind(c.gold)
+        .bind(c.created_at)
+        .bind(c.last_played)
+        .bind(c.deleted_at)
+        .execute(&mut *conn)
+        .await?;
+        let character_id = result.last_insert_rowid();
+
+        sqlx::query("INSERT INTO character_stats (character_id) VALUES (?)")
+            .bind(character_id)
+            .execute(&mut *conn)
+            .await?;
+
+        Ok(character_id)
+    }
+
+    /// A character that hasn't been deleted
+    pub async fn find(pool: &Pool<Sqlite>, character_id: i64) -> crate::Result<Option<Character>> {
+        let character = sqlx::query_as::<_, Character>(
+            "SELECT * FROM characters WHERE id = ? AND deleted_at IS NULL",
+        )
+        .bind(character_id)
         .fetch_optional(pool)
         .await?;
 
-        Ok(session)
+        Ok(character)
+    }
+
+    /// Whether any character, deleted or not, has `name` (ignoring case)
+    pub async fn name_taken(conn: &mut SqliteConnection, name: &str) -> crate::Result<bool> {
+        let taken: Option<(i64,)> = sqlx::query_as("SELECT id FROM characters WHERE name = ?")
+            .bind(name)
+            .fetch_optional(&mut *conn)
+            .await?;
+
+        Ok(taken.is_some())
+    }
+
+    /// Take `amount` zeny from a character if they have it; returns
+    /// whether they did
+    pub async fn spend_gold(
+        conn: &mut SqliteConnection,
+        character_id: i64,
+        amount: i64,
+    ) -> crate::Result<bool> {
+        let result =
+            sqlx::query("UPDATE characters SET gold = gold - ? WHERE id = ? AND gold >= ?")
+                .bind(amount)
+                .bind(character_id)
+                .bind(amount)
+                .execute(&mut *conn)
+                .await?;
+
+        Ok(result.rows_affected() == 1)
+    }
+
+    /// Set a character's zeny (stored as `gold`)
+    pub async fn set_gold(pool: &Pool<Sqlite>, character_id: i64, gold: i64) -> crate::Result<()> {
+        sqlx::query("UPDATE characters SET gold = ? WHERE id = ?")
+            .bind(gold)
+            .bind(character_id)
+            .execute(pool)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Set a character's level and experience
+    pub async fn set_experience(
+        pool: &Pool<Sqlite>,
+        character_id: i64,
+        level: i32,
+        experience: i64,
+    ) -> crate::Result<()> {
+        sqlx::query("UPDATE characters SET level = ?, experience = ? WHERE id = ?")
+            .bind(level)
+            .bind(experience)
+            .bind(character_id)
+            .execute(pool)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Set a character's map and position
+    pub async fn set_position(
+        pool: &Pool<Sqlite>,
+        character_id: i64,
+        map_id: i32,
+        (x, y, z): (f32, f32, f32),
+    ) -> crate::Result<()> {
+        sqlx::query(
+            "UPDATE characters SET map_id = ?, position_x = ?, position_y = ?, position_z = ? WHERE id = ?",
+        )
+        .bind(map_id)
+        .bind(x)
+        .bind(y)
+        .bind(z)
+        .bind(character_id)
+        .execute(pool)
+        .await?;
+
+        Ok(())
     }
 }
 
-// Note: Add chrono dependency when implementing these queries
+/// Inventory queries
+pub struct InventoryQueries;
+
+impl InventoryQueries {
+    /// Put `quantity` of `item_id` in an inventory slot, replacing what was
+    /// there; a quantity of 0 empties the slot
+    pub async fn set_slot(
+        pool: &Pool<Sqlite>,
+        character_id: i64,
+        slot_index: i32,
+        item_id: i32,
+        quantity: i32,
+    ) -> crate::Result<()> {
+        let mut tx = pool.begin().await?;
+
+        sqlx::query("DELETE FROM inventory WHERE character_id = ? AND slot_index = ?")
+            .bind(character_id)
+            .bind(slot_index)
+            .execute(&mut *tx)
+            .await?;
+
+        if quantity > 0 {
+            sqlx::query(
+                "INSERT INTO inventory (character_id, item_id, quantity, slot_index) VALUES (?, ?, ?, ?)",
+            )
+            .bind(character_id)
+            .bind(item_id)
+            .bind(quantity)
+            .bind(slot_index)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Add an item to an inventory slot
+    pub async fn add_item(
+        conn: &mut SqliteConnection,
+        character_id: i64,
+        slot_index: i32,
+        item_id: i32,
+        quantity: i32,
+    ) -> crate::Result<()> {
+        sqlx::query(
+            "INSERT INTO inventory (character_id, item_id, quantity, slot_index) VALUES (?, ?, ?, ?)",
+        )
+        .bind(character_id)
+        .bind(item_id)
+        .bind(quantity)
+        .bind(slot_index)
+        .execute(conn)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Add an item to an inventory slot, equipped or not
+    pub async fn insert(
+        conn: &mut SqliteConnection,
+        character_id: i64,
+        slot_index: i32,
+        item_id: i32,
+        quantity: i32,
+        equipped: bool,
+    ) -> crate::Result<()> {
+        sqlx::query(
+            "INSERT INTO inventory (character_id, item_id, quantity, slot_index, is_equipped) VALUES (?, ?, ?, ?, ?)",
+        )
+        .bind(character_id)
+        .bind(item_id)
+        .bind(quantity)
+        .bind(slot_index)
+        .bind(equipped)
+        .execute(conn)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Set whether the item in an inventory slot is bound and when it
+    /// expires (`None` = never)
+    pub async fn set_attributes(
+        pool: &Pool<Sqlite>,
+        character_id: i64,
+        slot_index: i32,
+        bound: bool,
+        expires_at: Option<i64>,
+    ) -> crate::Result<()> {
+        sqlx::query(
+            "UPDATE inventory SET is_bound = ?, expires_at = ? WHERE character_id = ? AND slot_index = ?",
+        )
+        .bind(bound)
+        .bind(expires_at)
+        .bind(character_id)
+        .bind(slot_index)
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Delete every rental item expired by `now`, equipped or not; returns
+    /// the character ID, slot and item ID of each
+    pub async fn purge_expired(
+        pool: &Pool<Sqlite>,
+        now: i64,
+    ) -> crate::Result<Vec<(i64, i32, i32)>> {
+        let purged = sqlx::query_as(
+            "DELETE FROM inventory WHERE expires_at IS NOT NULL AND expires_at <= ?
+             RETURNING character_id, slot_index, item_id",
+        )
+        .bind(now)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(purged)
+    }
+}
+
+/// Skill queries
+pub struct SkillQueries;
+
+impl SkillQueries {
+    /// A character's skills and their levels, by skill ID
+    pub async fn list(pool: &Pool<Sqlite>, character_id: i64) -> crate::Result<Vec<(i32, i32)>> {
+        let skills = sqlx::query_as(
+            "SELECT skill_id, level FROM character_skills WHERE character_id = ? ORDER BY skill_id",
+        )
+        .bind(character_id)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(skills)
+    }
+
+    /// Teach a character a skill it doesn't have yet
+    pub async fn learn(
+        conn: &mut SqliteConnection,
+        character_id: i64,
+        skill_id: i32,
+        level: i32,
+    ) -> crate::Result<()> {
+        sqlx::query(
+            "INSERT INTO character_skills (character_id, skill_id, level) VALUES (?, ?, ?)",
+        )
+        .bind(character_id)
+        .bind(skill_id)
+        .bind(level)
+        .execute(conn)
+        .await?;
+
+        Ok(())
+    }
+}
+
+/// Profession (life skill) queries
+pub struct ProfessionQueries;
+
+impl ProfessionQueries {
+    /// A character's professions as (profession ID, level, experience)
+    pub async fn list(
+        pool: &Pool<Sqlite>,
+        character_id: i64,
+    ) -> crate::Result<Vec<(i32, i32, i64)>> {
+        let professions = sqlx::query_as(
+            "SELECT profession_id, level, experience FROM character_professions WHERE character_id = ? ORDER BY profession_id",
+        )
+        .bind(character_id)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(professions)
+    }
+
+    /// Save a character's level and experience in a profession
+    pub async fn set(
+        pool: &Pool<Sqlite>,
+        character_id: i64,
+        profession_id: i32,
+        level: i32,
+        experience: i64,
+    ) -> crate::Result<()> {
+        sqlx::query(
+            "INSERT INTO character_professions (character_id, profession_id, level, experience) VALUES (?, ?, ?, ?)
+             ON CONFLICT(character_id, profession_id) DO UPDATE SET level = excluded.level, experience = excluded.experience",
+        )
+        .bind(character_id)
+        .bind(profession_id)
+        .bind(level)
+        .bind(experience)
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+}
+
+/// Khara challenge and title queries
+pub struct KharaQueries;
+
+impl KharaQueries {
+    /// A character's challenge progress as (challenge ID, progress)
+    pub async fn progress(
+        pool: &Pool<Sqlite>,
+        character_id: i64,
+    ) -> crate::Result<Vec<(i32, i64)>> {
+        let progress = sqlx::query_as(
+            "SELECT challenge_id, progress FROM character_khara WHERE character_id = ? ORDER BY challenge_id",
+        )
+        .bind(character_id)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(progress)
+    }
+
+    /// Save a character's progress in a challenge
+    pub async fn set_progress(
+        pool: &Pool<Sqlite>,
+        character_id: i64,
+        challenge_id: i32,
+        progress: i64,
+    ) -> crate::Result<()> {
+        sqlx::query(
+            "INSERT INTO character_khara (character_id, challenge_id, progress) VALUES (?, ?, ?)
+             ON CONFLICT(character_id, challenge_id) DO UPDATE SET progress = excluded.progress",
+        )
+        .bind(character_id)
+        .bind(challenge_id)
+        .bind(progress)
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// The titles a character has unlocked, by title ID
+    pub async fn titles(pool: &Pool<Sqlite>, character_id: i64) -> crate::Result<Vec<i32>> {
+        let titles: Vec<(i32,)> = sqlx::query_as(
+            "SELECT title_id FROM character_titles WHERE character_id = ? ORDER BY title_id",
+        )
+        .bind(character_id)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(titles.into_iter().map(|(title_id,)| title_id).collect())
+    }
+
+    /// Record a title as unlocked; unlocking it again does nothing
+    pub async fn unlock_title(
+        pool: &Pool<Sqlite>,
+        character_id: i64,
+        title_id: i32,
+        unlocked_at: i64,
+    ) -> crate::Result<()> {
+        sqlx::query(
+            "INSERT OR IGNORE INTO character_titles (character_id, title_id, unlocked_at) VALUES (?, ?, ?)",
+        )
+        .bind(character_id)
+        .bind(title_id)
+        .bind(unlocked_at)
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// The title a character has equipped
+    pub async fn equipped_title(
+        pool: &Pool<Sqlite>,
+        character_id: i64,
+    ) -> crate::Result<Option<i32>> {
+        let title: Option<(Option<i32>,)> =
+            sqlx::query_as("SELECT title_id FROM characters WHERE id = ?")
+                .bind(character_id)
+                .fetch_optional(pool)
+                .await?;
+
+        Ok(title.and_then(|(title_id,)| title_id))
+    }
+
+    /// Equip a title, or take it off with `None`
+    pub async fn equip_title(
+        pool: &Pool<Sqlite>,
+        character_id: i64,
+        title_id: Option<i32>,
+    ) -> crate::Result<()> {
+        sqlx::query("UPDATE characters SET title_id = ? WHERE id = ?")
+            .bind(title_id)
+            .bind(character_id)
+            .execute(pool)
+            .await?;
+
+        Ok(())
+    }
+}
+
+/// First-login tutorial queries
+pub struct TutorialQueries;
+
+impl TutorialQueries {
+    /// A character's tutorial step and when they finished it, or `None` if
+    /// they never started it
+    pub async fn state(
+        pool: &Pool<Sqlite>,
+        character_id: i64,
+    ) -> crate::Result<Option<(i32, Option<i64>)>> {
+        let state = sqlx::query_as(
+            "SELECT step, completed_at FROM character_tutorial WHERE character_id = ?",
+        )
+        .bind(character_id)
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(state)
+    }
+
+    /// Save the step a character is on
+    pub async fn set_step(pool: &Pool<Sqlite>, character_id: i64, step: i32) -> crate::Result<()> {
+        sqlx::query(
+            "INSERT INTO character_tutorial (character_id, step) VALUES (?, ?)
+             ON CONFLICT(character_id) DO UPDATE SET step = excluded.step",
+        )
+        .bind(character_id)
+        .bind(step)
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Record a character as done with the tutorial, finished or skipped
+    pub async fn complete(
+        pool: &Pool<Sqlite>,
+        character_id: i64,
+        completed_at: i64,
+    ) -> crate::Result<()> {
+        sqlx::query(
+            "INSERT INTO character_tutorial (character_id, completed_at) VALUES (?, ?)
+             ON CONFLICT(character_id) DO UPDATE SET completed_at = excluded.completed_at",
+        )
+        .bind(character_id)
+        .bind(completed_at)
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+}
+
+/// Character slot queries
+pub struct CharacterSlotQueries;
+
+impl CharacterSlotQueries {
+    /// Extra slots an account was granted or bought, on top of the base ones
+    pub async fn extra_slots(conn: &mut SqliteConnection, account_id: i64) -> crate::Result<i32> {
+        let (slots,): (i64,) = sqlx::query_as(
+            "SELECT COALESCE(SUM(slots), 0) FROM account_slot_grants WHERE account_id = ?",
+        )
+        .bind(account_id)
+        .fetch_one(&mut *conn)
+        .await?;
+
+        Ok(slots as i32)
+    }
+
+    /// Give an account `slots` more character slots; `granted_by` is the
+    /// admin, or what sold them
+    pub async fn grant(
+        conn: &mut SqliteConnection,
+        account_id: i64,
+        slots: i32,
+        granted_at: i64,
+        granted_by: &str,
+    ) -> crate::Result<()> {
+        sqlx::query(
+            "INSERT INTO account_slot_grants (account_id, slots, granted_at, granted_by) VALUES (?, ?, ?, ?)",
+        )
+        .bind(account_id)
+        .bind(slots)
+        .bind(granted_at)
+        .bind(granted_by)
+        .execute(&mut *conn)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Whether one of an account's characters that hasn't been deleted is
+    /// in `slot_index`
+    pub async fn slot_taken(
+        conn: &mut SqliteConnection,
+        account_id: i64,
+        slot_index: i32,
+    ) -> crate::Result<bool> {
+        let taken: Option<(i64,)> = sqlx::query_as(
+            "SELECT id FROM characters WHERE account_id = ? AND slot_index = ? AND deleted_at IS NULL",
+        )
+        .bind(account_id)
+        .bind(slot_index)
+        .fetch_optional(&mut *conn)
+        .await?;
+
+        Ok(taken.is_some())
+    }
+}
+
+/// Global message queries
+///
+/// World servers don't talk to each other, so a message for all of them is
+/// posted here and each relays the rows newer than the last it saw. The
+/// rows stay as the record of who sent what.
+pub struct GlobalMessageQueries;
+
+impl GlobalMessageQueries {
+    /// Post a message for every world server; returns its ID
+    pub async fn post(pool: &Pool<Sqlite>, message: &GlobalMessage) -> crate::Result<i64> {
+        let result = sqlx::query(
+            "INSERT INTO global_messages (kind, sender, character_id, item_id, text, sent_at) VALUES (?, ?, ?, ?, ?, ?)",
+        )
+        .bind(&message.kind)
+        .bind(&message.sender)
+        .bind(message.character_id)
+        .bind(message.item_id)
+        .bind(&message.text)
+        .bind(message.sent_at)
+        .execute(pool)
+        .await?;
+
+        Ok(result.last_insert_rowid())
+    }
+
+    /// ID of the newest message, 0 if there are none
+    pub async fn latest_id(pool: &Pool<Sqlite>) -> crate::Result<i64> {
+        let (id,): (i64,) = sqlx::query_as("SELECT COALESCE(MAX(id), 0) FROM global_messages")
+            .fetch_one(pool)
+            .await?;
+
+        Ok(id)
+    }
+
+    /// Up to `limit` messages posted after `after_id`, oldest first
+    pub async fn since(
+        pool: &Pool<Sqlite>,
+        after_id: i64,
+        limit: i64,
+    ) -> crate::Result<Vec<GlobalMessage>> {
+        let messages = sqlx::query_as::<_, GlobalMessage>(
+            "SELECT * FROM global_messages WHERE id > ? ORDER BY id LIMIT ?",
+        )
+        .bind(after_id)
+        .bind(limit)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(messages)
+    }
+
+    /// The last `limit` messages, newest first
+    pub async fn recent(pool: &Pool<Sqlite>, limit: i64) -> crate::Result<Vec<GlobalMessage>> {
+        let messages = sqlx::query_as::<_, GlobalMessage>(
+            "SELECT * FROM global_messages ORDER BY id DESC LIMIT ?",
+        )
+        .bind(limit)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(messages)
+    }
+
+    /// When a character last used a megaphone, on any server
+    pub async fn last_megaphone(
+        pool: &Pool<Sqlite>,
+        character_id: i64,
+    ) -> crate::Result<Option<i64>> {
+        let (sent_at,): (Option<i64>,) = sqlx::query_as(
+            "SELECT MAX(sent_at) FROM global_messages WHERE character_id = ? AND kind = 'megaphone'",
+        )
+        .bind(character_id)
+        .fetch_one(pool)
+        .await?;
+
+        Ok(sent_at)
+    }
+}
+
+/// Guild and rank queries
+pub struct GuildQueries;
+
+impl GuildQueries {
+    /// Found a guild with `founder_id` as its master; returns its ID
+    ///
+    /// The master's rank may do anything with the storage, without limits.
+    pub async fn create(
+        pool: &Pool<Sqlite>,
+        name: &str,
+        founder_id: i64,
+        now: i64,
+    ) -> crate::Result<i64> {
+        let mut tx = pool.begin().await?;
+        let guild_id = sqlx::query("INSERT INTO guilds (name, created_at) VALUES (?, ?)")
+            .bind(name)
+            .bind(now)
+            .execute(&mut *tx)
+            .await?
+            .last_insert_rowid();
+        sqlx::query(
+            "INSERT INTO guild_ranks (guild_id, rank, name, can_view, can_deposit, items_per_day, zeny_per_day)
+             VALUES (?, 0, 'Master', 1, 1, NULL, NULL)",
+        )
+        .bind(guild_id)
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query(
+            "INSERT INTO guild_members (character_id, guild_id, rank, joined_at) VALUES (?, ?, 0, ?)",
+        )
+        .bind(founder_id)
+        .bind(guild_id)
+        .bind(now)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok(guild_id)
+    }
+
+    /// Add a character to a guild, or move them to `rank` if already in it
+    pub async fn add_member(
+        pool: &Pool<Sqlite>,
+        guild_id: i64,
+        character_id: i64,
+        rank: i32,
+        now: i64,
+    ) -> crate::Result<()> {
+        sqlx::query(
+            "INSERT INTO guild_members (character_id, guild_id, rank, joined_at) VALUES (?, ?, ?, ?)
+             ON CONFLICT(character_id) DO UPDATE SET rank = excluded.rank WHERE guild_id = excluded.guild_id",
+        )
+        .bind(character_id)
+        .bind(guild_id)
+        .bind(rank)
+        .bind(now)
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// A character's guild and rank
+    pub async fn membership(
+        pool: &Pool<Sqlite>,
+        character_id: i64,
+    ) -> crate::Result<Option<(i64, i32)>> {
+        let membership =
+            sqlx::query_as("SELECT guild_id, rank FROM guild_members WHERE character_id = ?")
+                .bind(character_id)
+                .fetch_optional(pool)
+                .await?;
+
+        Ok(membership)
+    }
+
+    /// Set what a rank may do, adding it if it's new
+    pub async fn set_rank(pool: &Pool<Sqlite>, rank: &GuildRank) -> crate::Result<()> {
+        sqlx::query(
+            "INSERT INTO guild_ranks (guild_id, rank, name, can_view, can_deposit, items_per_day, zeny_per_day)
+             VALUES (?, ?, ?, ?, ?, ?, ?)
+             ON CONFLICT(guild_id, rank) DO UPDATE SET name = excluded.name, can_view = excluded.can_view,
+                 can_deposit = excluded.can_deposit, items_per_day = excluded.items_per_day,
+                 zeny_per_day = excluded.zeny_per_day",
+        )
+        .bind(rank.guild_id)
+        .bind(rank.rank)
+        .bind(&rank.name)
+        .bind(rank.can_view)
+        .bind(rank.can_deposit)
+        .bind(rank.items_per_day)
+        .bind(rank.zeny_per_day)
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn rank(
+        pool: &Pool<Sqlite>,
+        guild_id: i64,
+        rank: i32,
+    ) -> crate::Result<Option<GuildRank>> {
+        let rank = sqlx::query_as::<_, GuildRank>(
+            "SELECT * FROM guild_ranks WHERE guild_id = ? AND rank = ?",
+        )
+        .bind(guild_id)
+        .bind(rank)
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(rank)
+    }
+}
+
+/// Guild storage queries
+///
+/// Moves in and out of the storage go through
+/// [`ItemTransaction`](super::ItemTransaction), which also writes the log.
+pub struct GuildStorageQueries;
+
+impl GuildStorageQueries {
+    /// The guild's zeny and stacks, by slot
+    pub async fn contents(
+        pool: &Pool<Sqlite>,
+        guild_id: i64,
+    ) -> crate::Result<(i64, Vec<GuildStorageItem>)> {
+        let (zeny,): (i64,) = sqlx::query_as("SELECT zeny FROM guilds WHERE id = ?")
+            .bind(guild_id)
+            .fetch_one(pool)
+            .await?;
+        let items = sqlx::query_as::<_, GuildStorageItem>(
+            "SELECT slot_index, item_id, quantity FROM guild_storage WHERE guild_id = ? ORDER BY slot_index",
+        )
+        .bind(guild_id)
+        .fetch_all(pool)
+        .await?;
+
+        Ok((zeny, items))
+    }
+
+    /// The last `limit` deposits and withdrawals, newest first
+    pub async fn log(
+        pool: &Pool<Sqlite>,
+        guild_id: i64,
+        limit: i64,
+    ) -> crate::Result<Vec<GuildStorageLogEntry>> {
+        let entries = sqlx::query_as::<_, GuildStorageLogEntry>(
+            "SELECT * FROM guild_storage_log WHERE guild_id = ? ORDER BY id DESC LIMIT ?",
+        )
+        .bind(guild_id)
+        .bind(limit)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(entries)
+    }
+}
+
+/// Event currency queries
+///
+/// Spending goes through [`ItemTransaction`](super::ItemTransaction), so
+/// the reward bought is given in the same transaction.
+pub struct EventCurrencyQueries;
+
+impl EventCurrencyQueries {
+    /// Credit `amount` of a currency that expires at `expires_at`; a
+    /// balance that had already expired starts again from nothing
+    pub async fn add(
+        pool: &Pool<Sqlite>,
+        character_id: i64,
+        currency_id: i32,
+        amount: i64,
+        expires_at: i64,
+        now: i64,
+    ) -> crate::Result<()> {
+        sqlx::query(
+            "INSERT INTO event_currency (character_id, currency_id, amount, expires_at) VALUES (?, ?, ?, ?)
+             ON CONFLICT(character_id, currency_id) DO UPDATE SET
+                 amount = CASE WHEN expires_at > ? THEN amount + excluded.amount ELSE excluded.amount END,
+                 expires_at = excluded.expires_at",
+        )
+        .bind(character_id)
+        .bind(currency_id)
+        .bind(amount)
+        .bind(expires_at)
+        .bind(now)
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// How much of a currency a character has; 0 once it has expired
+    pub async fn balance(
+        pool: &Pool<Sqlite>,
+        character_id: i64,
+        currency_id: i32,
+        now: i64,
+    ) -> crate::Result<i64> {
+        let (amount,): (i64,) = sqlx::query_as(
+            "SELECT COALESCE(MAX(amount), 0) FROM event_currency
+             WHERE character_id = ? AND currency_id = ? AND expires_at > ?",
+        )
+        .bind(character_id)
+        .bind(currency_id)
+        .bind(now)
+        .fetch_one(pool)
+        .await?;
+
+        Ok(amount)
+    }
+
+    /// Delete expired balances; returns how many
+    pub async fn purge_expired(pool: &Pool<Sqlite>, now: i64) -> crate::Result<u64> {
+        let result = sqlx::query("DELETE FROM event_currency WHERE expires_at <= ?")
+            .bind(now)
+            .execute(pool)
+            .await?;
+
+        Ok(result.rows_affected())
+    }
+}
+
+/// Anti-bot challenge log queries
+pub struct BotChallengeQueries;
+
+impl BotChallengeQueries {
+    pub async fn record(pool: &Pool<Sqlite>, entry: &BotChallengeEntry) -> crate::Result<()> {
+        sqlx::query(
+            "INSERT INTO bot_challenges (account_id, character_id, reason, kind, result, kicked, issued_at, resolved_at)
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+        )
+        .bind(entry.account_id)
+        .bind(entry.character_id)
+        .bind(&entry.reason)
+        .bind(&entry.kind)
+        .bind(&entry.result)
+        .bind(entry.kicked)
+        .bind(entry.issued_at)
+        .bind(entry.resolved_at)
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// The last `limit` challenges, or the last of one account's, newest
+    /// first
+    pub async fn recent(
+        pool: &Pool<Sqlite>,
+        account_id: Option<i64>,
+        limit: i64,
+    ) -> crate::Result<Vec<BotChallengeEntry>> {
+        let entries = sqlx::query_as::<_, BotChallengeEntry>(
+            "SELECT * FROM bot_challenges WHERE ? IS NULL OR account_id = ? ORDER BY id DESC LIMIT ?",
+        )
+        .bind(account_id)
+        .bind(account_id)
+        .bind(limit)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(entries)
+    }
+}
+
+/// Channel population queries
+pub struct ChannelQueries;
+
+impl ChannelQueries {
+    /// Record a channel's population, replacing its last report
+    pub async fn report(pool: &Pool<Sqlite>, status: &ChannelStatus) -> crate::Result<()> {
+        sqlx::query(
+            "INSERT INTO channel_status (id, name, address, players, reported_at) VALUES (?, ?, ?, ?, ?)
+             ON CONFLICT(id) DO UPDATE SET name = excluded.name, address = excluded.address,
+                 players = excluded.players, reported_at = excluded.reported_at",
+        )
+        .bind(status.id)
+        .bind(&status.name)
+        .bind(&status.address)
+        .bind(status.players)
+        .bind(status.reported_at)
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Every channel that has ever reported, by ID
+    pub async fn list(pool: &Pool<Sqlite>) -> crate::Result<Vec<ChannelStatus>> {
+        let channels =
+            sqlx::query_as::<_, ChannelStatus>("SELECT * FROM channel_status ORDER BY id")
+                .fetch_all(pool)
+                .await?;
+
+        Ok(channels)
+    }
+}
+
+/// Vending stall queries
+///
+/// Purchases take from `vending_items` through
+/// [`ItemTransaction`](super::ItemTransaction), with the items and zeny.
+pub struct VendingQueries;
+
+impl VendingQueries {
+    /// Save a stall being opened with its items, replacing any the
+    /// character had
+    pub async fn open(
+        pool: &Pool<Sqlite>,
+        stall: &VendingStall,
+        items: &[VendingItem],
+    ) -> crate::Result<()> {
+        let mut tx = pool.begin().await?;
+        Self::delete(&mut tx, stall.character_id).await?;
+        sqlx::query(
+            "INSERT INTO vending_stalls (character_id, channel_id, title, map_id, position_x, position_y, position_z, opened_at)
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+        )
+        .bind(stall.character_id)
+        .bind(stall.channel_id)
+        .bind(&stall.title)
+        .bind(stall.map_id)
+        .bind(stall.x)
+        .bind(stall.y)
+        .bind(stall.z)
+        .bind(stall.opened_at)
+        .execute(&mut *tx)
+        .await?;
+        for item in items {
+            sqlx::query(
+                "INSERT INTO vending_items (character_id, stall_slot, inventory_slot, item_id, quantity, price)
+                 VALUES (?, ?, ?, ?, ?, ?)",
+            )
+            .bind(stall.character_id)
+            .bind(item.stall_slot)
+            .bind(item.inventory_slot)
+            .bind(item.item_id)
+            .bind(item.quantity)
+            .bind(item.price)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// Remove a character's stall; whether they had one
+    pub async fn close(pool: &Pool<Sqlite>, character_id: i64) -> crate::Result<bool> {
+        let mut tx = pool.begin().await?;
+        let closed = Self::delete(&mut tx, character_id).await?;
+        tx.commit().await?;
+
+        Ok(closed)
+    }
+
+    /// Remove every stall left open on a channel, e.g. by a crash; how many
+    /// there were
+    pub async fn close_channel(pool: &Pool<Sqlite>, channel_id: i64) -> crate::Result<u64> {
+        let mut tx = pool.begin().await?;
+        sqlx::query(
+            "DELETE FROM vending_items WHERE character_id IN
+                 (SELECT character_id FROM vending_stalls WHERE channel_id = ?)",
+        )
+        .bind(channel_id)
+        .execute(&mut *tx)
+        .await?;
+        let closed = sqlx::query("DELETE FROM vending_stalls WHERE channel_id = ?")
+            .bind(channel_id)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+
+        Ok(closed.rows_affected())
+    }
+
+    /// Every open stall, oldest first
+    pub async fn stalls(pool: &Pool<Sqlite>) -> crate::Result<Vec<VendingStall>> {
+        let stalls = sqlx::query_as::<_, VendingStall>(
+            "SELECT * FROM vending_stalls ORDER BY opened_at, character_id",
+        )
+        .fetch_all(pool)
+        .await?;
+
+        Ok(stalls)
+    }
+
+    /// What a character's stall has left, by stall slot
+    pub async fn items(pool: &Pool<Sqlite>, character_id: i64) -> crate::Result<Vec<VendingItem>> {
+        let items = sqlx::query_as::<_, VendingItem>(
+            "SELECT * FROM vending_items WHERE character_id = ? ORDER BY stall_slot",
+        )
+        .bind(character_id)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(items)
+    }
+
+    async fn delete(conn: &mut SqliteConnection, character_id: i64) -> crate::Result<bool> {
+        sqlx::query("DELETE FROM vending_items WHERE character_id = ?")
+            .bind(character_id)
+            .execute(&mut *conn)
+            .await?;
+        let deleted = sqlx::query("DELETE FROM vending_stalls WHERE character_id = ?")
+            .bind(character_id)
+            .execute(&mut *conn)
+            .await?;
+        Ok(deleted.rows_affected() > 0)
+    }
+}
+
+/// Rename and appearance change queries
+pub struct CharacterChangeQueries;
+
+impl CharacterChangeQueries {
+    /// When a character was last renamed and last changed appearance by
+    /// paying for it
+    pub async fn last_changes(
+        pool: &Pool<Sqlite>,
+        character_id: i64,
+    ) -> crate::Result<(Option<i64>, Option<i64>)> {
+        let changes: Option<(Option<i64>, Option<i64>)> =
+            sqlx::query_as("SELECT renamed_at, appearance_changed_at FROM characters WHERE id = ?")
+                .bind(character_id)
+                .fetch_optional(pool)
+                .await?;
+
+        Ok(changes.unwrap_or_default())
+    }
+
+    /// Rename a character and record its old name; `changed_by` is the
+    /// admin doing it, or `None` for a paid rename, which starts the
+    /// cooldown
+    pub async fn rename(
+        conn: &mut SqliteConnection,
+        character_id: i64,
+        old_name: &str,
+        new_name: &str,
+        changed_at: i64,
+        changed_by: Option<&str>,
+    ) -> crate::Result<()> {
+        sqlx::query(
+            "UPDATE characters SET name = ?, renamed_at = CASE WHEN ? THEN ? ELSE renamed_at END WHERE id = ?",
+        )
+        .bind(new_name)
+        .bind(changed_by.is_none())
+        .bind(changed_at)
+        .bind(character_id)
+        .execute(&mut *conn)
+        .await?;
+
+        sqlx::query(
+            "INSERT INTO character_name_history (character_id, old_name, new_name, changed_at, changed_by) VALUES (?, ?, ?, ?, ?)",
+        )
+        .bind(character_id)
+        .bind(old_name)
+        .bind(new_name)
+        .bind(changed_at)
+        .bind(changed_by)
+        .execute(&mut *conn)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Change a character's look; `changed_at` starts the cooldown, `None`
+    /// for admin changes that don't
+    pub async fn set_appearance(
+        conn: &mut SqliteConnection,
+        character_id: i64,
+        (hair_style, hair_color, face): (i32, i32, i32),
+        changed_at: Option<i64>,
+    ) -> crate::Result<()> {
+        sqlx::query(
+            "UPDATE characters SET hair_style = ?, hair_color = ?, face = ?,
+             appearance_changed_at = COALESCE(?, appearance_changed_at) WHERE id = ?",
+        )
+        .bind(hair_style)
+        .bind(hair_color)
+        .bind(face)
+        .bind(changed_at)
+        .bind(character_id)
+        .execute(&mut *conn)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Every rename of a character, oldest first
+    pub async fn name_history(
+        pool: &Pool<Sqlite>,
+        character_id: i64,
+    ) -> crate::Result<Vec<NameChange>> {
+        let history = sqlx::query_as::<_, NameChange>(
+            "SELECT character_id, old_name, new_name, changed_at, changed_by FROM character_name_history
+             WHERE character_id = ? ORDER BY id",
+        )
+        .bind(character_id)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(history)
+    }
+
+    /// Renames from or to `name`, for finding who used to go by it
+    pub async fn find_name(pool: &Pool<Sqlite>, name: &str) -> crate::Result<Vec<NameChange>> {
+        let history = sqlx::query_as::<_, NameChange>(
+            "SELECT character_id, old_name, new_name, changed_at, changed_by FROM character_name_history
+             WHERE old_name = ? OR new_name = ? ORDER BY id",
+        )
+        .bind(name)
+        .bind(name)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(history)
+    }
+}
+
+/// Stat and skill reset queries
+///
+/// A reset is made of these in one transaction: give the points back, check
+/// how many resets came before and pay for this one, then [`record`] it.
+///
+/// [`record`]: CharacterResetQueries::record
+pub struct CharacterResetQueries;
+
+impl CharacterResetQueries {
+    /// How many resets of `kind` a character has had
+    pub async fn count(
+        conn: &mut SqliteConnection,
+        character_id: i64,
+        kind: &str,
+    ) -> crate::Result<i64> {
+        let (count,): (i64,) = sqlx::query_as(
+            "SELECT COUNT(*) FROM character_resets WHERE character_id = ? AND kind = ?",
+        )
+        .bind(character_id)
+        .bind(kind)
+        .fetch_one(&mut *conn)
+        .await?;
+
+        Ok(count)
+    }
+
+    /// Put a character's attributes back to 1 and add what was spent on
+    /// them to its stat points; returns how many that was
+    pub async fn reset_stats(conn: &mut SqliteConnection, character_id: i64) -> crate::Result<i32> {
+        let spent: Option<(i32,)> = sqlx::query_as(
+            "SELECT MAX(strength - 1, 0) + MAX(dexterity - 1, 0) + MAX(intelligence - 1, 0)
+             + MAX(vitality - 1, 0) + MAX(luck - 1, 0) FROM character_stats WHERE character_id = ?",
+        )
+        .bind(character_id)
+        .fetch_optional(&mut *conn)
+        .await?;
+        let spent = spent.map_or(0, |(spent,)| spent);
+        if spent == 0 {
+            return Ok(0);
+        }
+
+        sqlx::query(
+            "UPDATE character_stats SET strength = 1, dexterity = 1, intelligence = 1, vitality = 1,
+             luck = 1, stat_points = stat_points + ? WHERE character_id = ?",
+        )
+        .bind(spent)
+        .bind(character_id)
+        .execute(&mut *conn)
+        .await?;
+
+        Ok(spent)
+    }
+
+    /// Put a character's skills back to level 1 and add a skill point for
+    /// every level above; returns how many that was
+    pub async fn reset_skills(
+        conn: &mut SqliteConnection,
+        character_id: i64,
+    ) -> crate::Result<i32> {
+        let (spent,): (i32,) = sqlx::query_as(
+            "SELECT COALESCE(SUM(level - 1), 0) FROM character_skills WHERE character_id = ? AND level > 1",
+        )
+        .bind(character_id)
+        .fetch_one(&mut *conn)
+        .await?;
+        if spent == 0 {
+            return Ok(0);
+        }
+
+        sqlx::query("UPDATE character_skills SET level = 1 WHERE character_id = ?")
+            .bind(character_id)
+            .execute(&mut *conn)
+            .await?;
+        sqlx::query("INSERT OR IGNORE INTO character_stats (character_id) VALUES (?)")
+            .bind(character_id)
+            .execute(&mut *conn)
+            .await?;
+        sqlx::query(
+            "UPDATE character_stats SET skill_points = skill_points + ? WHERE character_id = ?",
+        )
+        .bind(spent)
+        .bind(character_id)
+        .execute(&mut *conn)
+        .await?;
+
+        Ok(spent)
+    }
+
+    /// Record a reset, and add it to the owner's account audit log as
+    /// `reset_stats` or `reset_skills` by `player`
+    pub async fn record(conn: &mut SqliteConnection, reset: &CharacterReset) -> crate::Result<()> {
+        sqlx::query(
+            "INSERT INTO character_resets (character_id, kind, cost, item_id, refunded, reset_at)
+             VALUES (?, ?, ?, ?, ?, ?)",
+        )
+        .bind(reset.character_id)
+        .bind(&reset.kind)
+        .bind(reset.cost)
+        .bind(reset.item_id)
+        .bind(reset.refunded)
+        .bind(reset.reset_at)
+        .execute(&mut *conn)
+        .await?;
+
+        sqlx::query(
+            "INSERT INTO account_audit_log (account_id, action, actor, at)
+             SELECT account_id, ?, 'player', ? FROM characters WHERE id = ?",
+        )
+        .bind(format!("reset_{}", reset.kind))
+        .bind(reset.reset_at)
+        .bind(reset.character_id)
+        .execute(&mut *conn)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Every reset a character has had, oldest first
+    pub async fn history(
+        pool: &Pool<Sqlite>,
+        character_id: i64,
+    ) -> crate::Result<Vec<CharacterReset>> {
+        let history = sqlx::query_as::<_, CharacterReset>(
+            "SELECT character_id, kind, cost, item_id, refunded, reset_at FROM character_resets
+             WHERE character_id = ? ORDER BY id",
+        )
+        .bind(character_id)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(history)
+    }
+
+    /// A character's unspent skill points
+    pub async fn skill_points(pool: &Pool<Sqlite>, character_id: i64) -> crate::Result<i32> {
+        let points: Option<(i32,)> =
+            sqlx::query_as("SELECT skill_points FROM character_stats WHERE character_id = ?")
+                .bind(character_id)
+                .fetch_optional(pool)
+                .await?;
+
+        Ok(points.map_or(0, |(points,)| points))
+    }
+}
+
+/// Profile value queries
+///
+/// Character and account values are kept in tables of their own, so each
+/// goes with its owner when that's deleted.
+pub struct ProfileQueries;
+
+impl ProfileOwner {
+    /// The owner's table and its ID column
+    fn table(self) -> (&'static str, &'static str, i64) {
+        match self {
+            Self::Character(id) => ("character_profile_values", "character_id", id),
+            Self::Account(id) => ("account_profile_values", "account_id", id),
+        }
+    }
+}
+
+impl ProfileQueries {
+    /// Every value of an owner, by namespace and key
+    pub async fn all(pool: &Pool<Sqlite>, owner: ProfileOwner) -> crate::Result<Vec<ProfileValue>> {
+        let (table, column, id) = owner.table();
+        let values = sqlx::query_as::<_, ProfileValue>(&format!(
+            "SELECT namespace, key, value, updated_at FROM {} WHERE {} = ? ORDER BY namespace, key",
+            table, column
+        ))
+        .bind(id)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(values)
+    }
+
+    /// One value, if it's set
+    pub async fn get(
+        pool: &Pool<Sqlite>,
+        owner: ProfileOwner,
+        namespace: &str,
+        key: &str,
+    ) -> crate::Result<Option<String>> {
+        let (table, column, id) = owner.table();
+        let value: Option<(String,)> = sqlx::query_as(&format!(
+            "SELECT value FROM {} WHERE {} = ? AND namespace = ? AND key = ?",
+            table, column
+        ))
+        .bind(id)
+        .bind(namespace)
+        .bind(key)
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(value.map(|(value,)| value))
+    }
+
+    /// Set a value, replacing any already there
+    pub async fn set(
+        conn: &mut SqliteConnection,
+        owner: ProfileOwner,
+        value: &ProfileValue,
+    ) -> crate::Result<()> {
+        let (table, column, id) = owner.table();
+        sqlx::query(&format!(
+            "INSERT INTO {} ({}, namespace, key, value, updated_at) VALUES (?, ?, ?, ?, ?)
+             ON CONFLICT ({}, namespace, key) DO UPDATE SET value = excluded.value, updated_at = excluded.updated_at",
+            table, column, column
+        ))
+        .bind(id)
+        .bind(&value.namespace)
+        .bind(&value.key)
+        .bind(&value.value)
+        .bind(value.updated_at)
+        .execute(&mut *conn)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Remove a value; returns whether it was set
+    pub async fn remove(
+        conn: &mut SqliteConnection,
+        owner: ProfileOwner,
+        namespace: &str,
+        key: &str,
+    ) -> crate::Result<bool> {
+        let (table, column, id) = owner.table();
+        let result = sqlx::query(&format!(
+            "DELETE FROM {} WHERE {} = ? AND namespace = ? AND key = ?",
+            table, column
+        ))
+        .bind(id)
+        .bind(namespace)
+        .bind(key)
+        .execute(&mut *conn)
+        .await?;
+
+        Ok(result.rows_affected() == 1)
+    }
+}
+
+/// Playtime queries
+pub struct PlaytimeQueries;
+
+impl PlaytimeQueries {
+    /// Find an account's playtime limits
+    pub async fn limits(
+        pool: &Pool<Sqlite>,
+        account_id: i64,
+    ) -> crate::Result<Option<AccountPlaytimeLimits>> {
+        let limits = sqlx::query_as::<_, AccountPlaytimeLimits>(
+            "SELECT * FROM account_playtime_limits WHERE account_id = ?",
+        )
+        .bind(account_id)
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(limits)
+    }
+
+    /// Seconds played on `day` (YYYY-MM-DD)
+    pub async fn seconds_played(
+        pool: &Pool<Sqlite>,
+        account_id: i64,
+        day: &str,
+    ) -> crate::Result<i64> {
+        let seconds: Option<(i64,)> = sqlx::query_as(
+            "SELECT seconds_played FROM account_playtime WHERE account_id = ? AND day = ?",
+        )
+        .bind(account_id)
+        .bind(day)
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(seconds.map_or(0, |(seconds,)| seconds))
+    }
+
+    /// Add to the seconds played on `day` (YYYY-MM-DD)
+    pub async fn add_seconds_played(
+        pool: &Pool<Sqlite>,
+        account_id: i64,
+        day: &str,
+        seconds: i64,
+    ) -> crate::Result<()> {
+        sqlx::query(
+            "INSERT INTO account_playtime (account_id, day, seconds_played) VALUES (?, ?, ?)
+             ON CONFLICT(account_id, day) DO UPDATE SET seconds_played = seconds_played + excluded.seconds_played",
+        )
+        .bind(account_id)
+        .bind(day)
+        .bind(seconds)
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+}
+
+// Note: Add chrono dependency when implementing these queries
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::ACCOUNT_RESTORE_GRACE_SECS;
+    use crate::testing;
+
+    async fn pool() -> Pool<Sqlite> {
+        let pool = testing::database().await;
+        testing::character(&pool, 1, "Alice", 0).await;
+        sqlx::query(
+            "INSERT INTO sessions (account_id, session_key, created_at, expires_at, ip_address, last_activity)
+             VALUES (2, 'key', 0, 100, '127.0.0.1', 0)",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        pool
+    }
+
+    #[tokio::test]
+    async fn test_deactivate_and_restore() {
+        let pool = pool().await;
+        let grace = ACCOUNT_RESTORE_GRACE_SECS;
+
+        assert!(
+            !AccountDeactivationQueries::restore(&pool, 2, "gm", 0, grace)
+                .await
+                .unwrap()
+        );
+        assert!(
+            AccountDeactivationQueries::deactivate(&pool, 2, "player", 1_000)
+                .await
+                .unwrap()
+        );
+        assert!(
+            !AccountDeactivationQueries::deactivate(&pool, 2, "player", 1_000)
+                .await
+                .unwrap()
+        );
+
+        let account = AccountQueries::find_by_username(&pool, "player")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(account.deactivated_at, Some(1_000));
+        let sessions: (i64,) = sqlx::query_as("SELECT COUNT(*) FROM sessions")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(sessions.0, 0);
+
+        assert!(
+            AccountDeactivationQueries::restore(&pool, 2, "gm_jane", 2_000, grace)
+                .await
+                .unwrap()
+        );
+        let account = AccountQueries::find_by_username(&pool, "player")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(account.deactivated_at, None);
+
+        // Too late to restore once the grace period is over
+        AccountDeactivationQueries::deactivate(&pool, 2, "player", 3_000)
+            .await
+            .unwrap();
+        assert!(
+            AccountDeactivationQueries::past_grace(&pool, 3_000 + grace - 1, grace)
+                .await
+                .unwrap()
+                .is_empty()
+        );
+        assert!(
+            !AccountDeactivationQueries::restore(&pool, 2, "gm_jane", 3_000 + grace, grace)
+                .await
+                .unwrap()
+        );
+        assert_eq!(
+            AccountDeactivationQueries::past_grace(&pool, 3_000 + grace, grace)
+                .await
+                .unwrap(),
+            [2]
+        );
+
+        let actions: Vec<_> = AccountDeactivationQueries::audit_log(&pool, 2)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|entry| (entry.action, entry.actor, entry.at))
+            .collect();
+        assert_eq!(
+            actions,
+            [
+                ("deactivate".into(), "player".into(), 1_000),
+                ("restore".into(), "gm_jane".into(), 2_000),
+                ("deactivate".into(), "player".into(), 3_000),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_purge_expired() {
+        let pool = pool().await;
+        for (slot, item_id) in [(0, 501), (1, 1101), (2, 1102)] {
+            InventoryQueries::set_slot(&pool, 1, slot, item_id, 1)
+                .await
+                .unwrap();
+        }
+        InventoryQueries::set_attributes(&pool, 1, 1, true, Some(100))
+            .await
+            .unwrap();
+        InventoryQueries::set_attributes(&pool, 1, 2, false, Some(200))
+            .await
+            .unwrap();
+
+        assert!(
+            InventoryQueries::purge_expired(&pool, 99)
+                .await
+                .unwrap()
+                .is_empty()
+        );
+        assert_eq!(
+            InventoryQueries::purge_expired(&pool, 100).await.unwrap(),
+            [(1, 1, 1101)]
+        );
+        let left: Vec<(i32,)> = sqlx::query_as(
+            "SELECT item_id FROM inventory WHERE character_id = 1 ORDER BY slot_index",
+        )
+        .fetch_all(&pool)
+        .await
+        .unwrap();
+        assert_eq!(left, [(501,), (1102,)]);
+    }
+
+    #[tokio::test]
+    async fn test_anonymize() {
+        let pool = pool().await;
+        AccountQueries::set_language(&pool, 2, Some("ko"))
+            .await
+            .unwrap();
+
+        // Only deactivated accounts
+        assert!(
+            !AccountDeactivationQueries::anonymize(&pool, 2, "system", 0)
+                .await
+                .unwrap()
+        );
+        AccountDeactivationQueries::deactivate(&pool, 2, "player", 0)
+            .await
+            .unwrap();
+        assert!(
+            AccountDeactivationQueries::anonymize(&pool, 2, "system", 100)
+                .await
+                .unwrap()
+        );
+        assert!(
+            !AccountDeactivationQueries::anonymize(&pool, 2, "system", 100)
+                .await
+                .unwrap()
+        );
+
+        assert!(
+            AccountQueries::find_by_username(&pool, "player")
+                .await
+                .unwrap()
+                .is_none()
+        );
+        let account = AccountQueries::find_by_username(&pool, "deleted-2")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!((account.password_hash.as_str(), account.email), ("", None));
+        assert_eq!(AccountQueries::language(&pool, 2).await.unwrap(), None);
+
+        let character: (String, Option<i64>) =
+            sqlx::query_as("SELECT name, deleted_at FROM characters WHERE id = 1")
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+        assert_eq!(character, ("deleted-1".to_string(), Some(100)));
+
+        // Anonymized accounts stay deactivated
+        assert!(
+            !AccountDeactivationQueries::restore(&pool, 2, "gm", 200, ACCOUNT_RESTORE_GRACE_SECS)
+                .await
+                .unwrap()
+        );
+        assert_eq!(
+            AccountDeactivationQueries::audit_log(&pool, 2)
+                .await
+                .unwrap()
+                .last()
+                .map(|entry| entry.action.as_str()),
+            Some("anonymize")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_tutorial() {
+        let pool = pool().await;
+        assert_eq!(TutorialQueries::state(&pool, 1).await.unwrap(), None);
+        TutorialQueries::set_step(&pool, 1, 0).await.unwrap();
+        TutorialQueries::set_step(&pool, 1, 2).await.unwrap();
+        assert_eq!(
+            TutorialQueries::state(&pool, 1).await.unwrap(),
+            Some((2, None))
+        );
+        TutorialQueries::complete(&pool, 1, 100).await.unwrap();
+        assert_eq!(
+            TutorialQueries::state(&pool, 1).await.unwrap(),
+            Some((2, Some(100)))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_character_slots() {
+        let pool = pool().await;
+        let mut conn = pool.acquire().await.unwrap();
+        assert_eq!(
+            CharacterSlotQueries::extra_slots(&mut conn, 2)
+                .await
+                .unwrap(),
+            0
+        );
+        CharacterSlotQueries::grant(&mut conn, 2, 1, 100, "gm")
+            .await
+            .unwrap();
+        CharacterSlotQueries::grant(&mut conn, 2, 2, 200, "cash shop")
+            .await
+            .unwrap();
+        assert_eq!(
+            CharacterSlotQueries::extra_slots(&mut conn, 2)
+                .await
+                .unwrap(),
+            3
+        );
+        assert_eq!(
+            CharacterSlotQueries::extra_slots(&mut conn, 1)
+                .await
+                .unwrap(),
+            0
+        );
+
+        // Alice is in slot 0 until she's deleted
+        assert!(
+            CharacterSlotQueries::slot_taken(&mut conn, 2, 0)
+                .await
+                .unwrap()
+        );
+        assert!(
+            !CharacterSlotQueries::slot_taken(&mut conn, 2, 1)
+                .await
+                .unwrap()
+        );
+        sqlx::query("UPDATE characters SET deleted_at = 1 WHERE id = 1")
+            .execute(&mut *conn)
+            .await
+            .unwrap();
+        assert!(
+            !CharacterSlotQueries::slot_taken(&mut conn, 2, 0)
+                .await
+                .unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_global_messages() {
+        let pool = pool().await;
+        assert_eq!(GlobalMessageQueries::latest_id(&pool).await.unwrap(), 0);
+        let megaphone = GlobalMessage {
+            id: 0,
+            kind: "megaphone".to_string(),
+            sender: "Alice".to_string(),
+            character_id: Some(1),
+            item_id: Some(12221),
+            text: "WTS Poring card".to_string(),
+            sent_at: 100,
+        };
+        let announcement = GlobalMessage {
+            kind: "announcement".to_string(),
+            sender: "gm_jane".to_string(),
+            character_id: None,
+            item_id: None,
+            text: "Double EXP tonight".to_string(),
+            sent_at: 200,
+            ..megaphone.clone()
+        };
+        let first = GlobalMessageQueries::post(&pool, &megaphone).await.unwrap();
+        let second = GlobalMessageQueries::post(&pool, &announcement)
+            .await
+            .unwrap();
+        assert_eq!(
+            GlobalMessageQueries::latest_id(&pool).await.unwrap(),
+            second
+        );
+
+        let since = GlobalMessageQueries::since(&pool, 0, 10).await.unwrap();
+        assert_eq!(since.len(), 2);
+        assert_eq!(
+            since[0],
+            GlobalMessage {
+                id: first,
+                ..megaphone
+            }
+        );
+        assert_eq!(
+            GlobalMessageQueries::since(&pool, first, 10).await.unwrap()[0].sender,
+            "gm_jane"
+        );
+        assert_eq!(
+            GlobalMessageQueries::since(&pool, 0, 1)
+                .await
+                .unwrap()
+                .len(),
+            1
+        );
+        assert_eq!(
+            GlobalMessageQueries::recent(&pool, 1).await.unwrap()[0].id,
+            second
+        );
+
+        assert_eq!(
+            GlobalMessageQueries::last_megaphone(&pool, 1)
+                .await
+                .unwrap(),
+            Some(100)
+        );
+        assert_eq!(
+            GlobalMessageQueries::last_megaphone(&pool, 2)
+                .await
+                .unwrap(),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn test_guilds() {
+        let pool = pool().await;
+        let guild = GuildQueries::create(&pool, "Knights", 1, 100)
+            .await
+            .unwrap();
+        assert!(
+            GuildQueries::create(&pool, "knights", 1, 100)
+                .await
+                .is_err()
+        );
+        assert_eq!(
+            GuildQueries::membership(&pool, 1).await.unwrap(),
+            Some((guild, 0))
+        );
+        let master = GuildQueries::rank(&pool, guild, 0).await.unwrap().unwrap();
+        assert!(master.can_view && master.can_deposit);
+        assert_eq!((master.items_per_day, master.zeny_per_day), (None, None));
+        assert_eq!(GuildQueries::rank(&pool, guild, 1).await.unwrap(), None);
+
+        let member = GuildRank {
+            rank: 1,
+            name: "Member".to_string(),
+            can_deposit: false,
+            items_per_day: Some(5),
+            zeny_per_day: Some(0),
+            ..master
+        };
+        GuildQueries::set_rank(&pool, &member).await.unwrap();
+        GuildQueries::add_member(&pool, guild, 1, 1, 200)
+            .await
+            .unwrap();
+        assert_eq!(
+            GuildQueries::membership(&pool, 1).await.unwrap(),
+            Some((guild, 1))
+        );
+        assert_eq!(
+            GuildQueries::rank(&pool, guild, 1).await.unwrap(),
+            Some(member)
+        );
+
+        sqlx::raw_sql(
+            "UPDATE guilds SET zeny = 500;
+             INSERT INTO guild_storage (guild_id, slot_index, item_id, quantity) VALUES (1, 4, 501, 3), (1, 2, 1101, 1);
+             INSERT INTO guild_storage_log (guild_id, character_id, action, item_id, amount, at)
+                 VALUES (1, 1, 'deposit_zeny', NULL, 500, 10), (1, 1, 'withdraw_item', 501, 2, 20);",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        let (zeny, items) = GuildStorageQueries::contents(&pool, guild).await.unwrap();
+        assert_eq!(zeny, 500);
+        assert_eq!(
+            items.iter().map(|i| i.slot_index).collect::<Vec<_>>(),
+            [2, 4]
+        );
+        let log = GuildStorageQueries::log(&pool, guild, 10).await.unwrap();
+        assert_eq!(log.len(), 2);
+        assert_eq!(
+            (log[0].action.as_str(), log[0].item_id),
+            ("withdraw_item", Some(501))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_event_currency() {
+        let pool = pool().await;
+        assert_eq!(
+            EventCurrencyQueries::balance(&pool, 1, 7, 0).await.unwrap(),
+            0
+        );
+        EventCurrencyQueries::add(&pool, 1, 7, 3, 100, 0)
+            .await
+            .unwrap();
+        EventCurrencyQueries::add(&pool, 1, 7, 2, 100, 50)
+            .await
+            .unwrap();
+        assert_eq!(
+            EventCurrencyQueries::balance(&pool, 1, 7, 99)
+                .await
+                .unwrap(),
+            5
+        );
+        assert_eq!(
+            EventCurrencyQueries::balance(&pool, 1, 7, 100)
+                .await
+                .unwrap(),
+            0
+        );
+
+        // The next event's drops don't add to what expired
+        EventCurrencyQueries::add(&pool, 1, 7, 4, 300, 200)
+            .await
+            .unwrap();
+        assert_eq!(
+            EventCurrencyQueries::balance(&pool, 1, 7, 200)
+                .await
+                .unwrap(),
+            4
+        );
+
+        EventCurrencyQueries::add(&pool, 1, 8, 1, 250, 200)
+            .await
+            .unwrap();
+        assert_eq!(
+            EventCurrencyQueries::purge_expired(&pool, 250)
+                .await
+                .unwrap(),
+            1
+        );
+        assert_eq!(
+            EventCurrencyQueries::balance(&pool, 1, 7, 250)
+                .await
+                .unwrap(),
+            4
+        );
+    }
+
+    #[tokio::test]
+    async fn test_bot_challenges() {
+        let pool = pool().await;
+        let entry = BotChallengeEntry {
+            id: 0,
+            account_id: Some(2),
+            character_id: 1,
+            reason: "cooldown".to_string(),
+            kind: "arithmetic".to_string(),
+            result: "wrong".to_string(),
+            kicked: false,
+            issued_at: 100,
+            resolved_at: 110,
+        };
+        BotChallengeQueries::record(&pool, &entry).await.unwrap();
+        let kicked = BotChallengeEntry {
+            result: "timed_out".to_string(),
+            kicked: true,
+            issued_at: 120,
+            resolved_at: 180,
+            ..entry.clone()
+        };
+        BotChallengeQueries::record(&pool, &kicked).await.unwrap();
+        let other = BotChallengeEntry {
+            account_id: None,
+            ..entry.clone()
+        };
+        BotChallengeQueries::record(&pool, &other).await.unwrap();
+
+        let recent = BotChallengeQueries::recent(&pool, None, 10).await.unwrap();
+        assert_eq!(recent.len(), 3);
+        let account = BotChallengeQueries::recent(&pool, Some(2), 10)
+            .await
+            .unwrap();
+        assert_eq!(account.len(), 2);
+        assert_eq!(account[0], BotChallengeEntry { id: 2, ..kicked });
+    }
+
+    #[tokio::test]
+    async fn test_channel_status() {
+        let pool = pool().await;
+        let second = ChannelStatus {
+            id: 2,
+            name: "Channel 2".to_string(),
+            address: "127.0.0.1:7202".to_string(),
+            players: 40,
+            reported_at: 100,
+        };
+        ChannelQueries::report(&pool, &second).await.unwrap();
+        let first = ChannelStatus {
+            id: 1,
+            name: "Channel 1".to_string(),
+            address: "127.0.0.1:7201".to_string(),
+            players: 10,
+            reported_at: 100,
+        };
+        ChannelQueries::report(&pool, &first).await.unwrap();
+        let first = ChannelStatus {
+            players: 12,
+            reported_at: 110,
+            ..first
+        };
+        ChannelQueries::report(&pool, &first).await.unwrap();
+
+        assert_eq!(ChannelQueries::list(&pool).await.unwrap(), [first, second]);
+    }
+
+    #[tokio::test]
+    async fn test_vending_stalls() {
+        let pool = pool().await;
+        let stall = VendingStall {
+            character_id: 1,
+            channel_id: 1,
+            title: "Cheap potions".to_string(),
+            map_id: 1,
+            x: 10.0,
+            y: 0.0,
+            z: 20.0,
+            opened_at: 100,
+        };
+        let item = VendingItem {
+            character_id: 1,
+            stall_slot: 0,
+            inventory_slot: 3,
+            item_id: 501,
+            quantity: 10,
+            price: 50,
+        };
+        VendingQueries::open(&pool, &stall, std::slice::from_ref(&item))
+            .await
+            .unwrap();
+        assert_eq!(
+            VendingQueries::stalls(&pool).await.unwrap(),
+            std::slice::from_ref(&stall)
+        );
+        assert_eq!(VendingQueries::items(&pool, 1).await.unwrap(), [item]);
+
+        assert!(VendingQueries::close(&pool, 1).await.unwrap());
+        assert!(!VendingQueries::close(&pool, 1).await.unwrap());
+        assert!(VendingQueries::items(&pool, 1).await.unwrap().is_empty());
+
+        // Left open by a crash on channel 1, but not on channel 2
+        VendingQueries::open(&pool, &stall, &[]).await.unwrap();
+        assert_eq!(VendingQueries::close_channel(&pool, 2).await.unwrap(), 0);
+        assert_eq!(VendingQueries::close_channel(&pool, 1).await.unwrap(), 1);
+        assert!(VendingQueries::stalls(&pool).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_character_resets() {
+        let pool = pool().await;
+        let reset = CharacterReset {
+            character_id: 1,
+            kind: "skills".to_string(),
+            cost: 10_000,
+            item_id: None,
+            refunded: 3,
+            reset_at: 100,
+        };
+        {
+            let mut conn = pool.acquire().await.unwrap();
+            // Nothing spent yet, and no stats row
+            assert_eq!(
+                CharacterResetQueries::reset_stats(&mut conn, 1)
+                    .await
+                    .unwrap(),
+                0
+            );
+            assert_eq!(
+                CharacterResetQueries::reset_skills(&mut conn, 1)
+                    .await
+                    .unwrap(),
+                0
+            );
+
+            sqlx::query("INSERT INTO character_stats (character_id, strength, dexterity, vitality, stat_points) VALUES (1, 5, 3, 1, 2)")
+                .execute(&mut *conn)
+                .await
+                .unwrap();
+            sqlx::query("INSERT INTO character_skills (character_id, skill_id, level) VALUES (1, 10, 3), (1, 11, 1), (1, 12, 2)")
+                .execute(&mut *conn)
+                .await
+                .unwrap();
+            assert_eq!(
+                CharacterResetQueries::reset_stats(&mut conn, 1)
+                    .await
+                    .unwrap(),
+                6
+            );
+            assert_eq!(
+                CharacterResetQueries::reset_skills(&mut conn, 1)
+                    .await
+                    .unwrap(),
+                3
+            );
+
+            CharacterResetQueries::record(&mut conn, &reset)
+                .await
+                .unwrap();
+            assert_eq!(
+                CharacterResetQueries::count(&mut conn, 1, "skills")
+                    .await
+                    .unwrap(),
+                1
+            );
+            assert_eq!(
+                CharacterResetQueries::count(&mut conn, 1, "stats")
+                    .await
+                    .unwrap(),
+                0
+            );
+        }
+
+        let stats = CharacterQueries::stats(&pool, 1).await.unwrap().unwrap();
+        assert_eq!(
+            (stats.strength, stats.dexterity, stats.stat_points),
+            (1, 1, 8)
+        );
+        assert_eq!(
+            CharacterResetQueries::skill_points(&pool, 1).await.unwrap(),
+            3
+        );
+        assert_eq!(
+            SkillQueries::list(&pool, 1).await.unwrap(),
+            [(10, 1), (11, 1), (12, 1)]
+        );
+        assert_eq!(
+            CharacterResetQueries::history(&pool, 1).await.unwrap(),
+            [reset]
+        );
+        let log = AccountDeactivationQueries::audit_log(&pool, 2)
+            .await
+            .unwrap();
+        assert_eq!(
+            (log[0].action.as_str(), log[0].actor.as_str(), log[0].at),
+            ("reset_skills", "player", 100)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_profile_values() {
+        let pool = pool().await;
+        let value = |key: &str, value: &str, updated_at| ProfileValue {
+            namespace: "quest".to_string(),
+            key: key.to_string(),
+            value: value.to_string(),
+            updated_at,
+        };
+        let alice = ProfileOwner::Character(1);
+        let player = ProfileOwner::Account(2);
+        {
+            let mut conn = pool.acquire().await.unwrap();
+            ProfileQueries::set(&mut conn, alice, &value("stage", "1", 100))
+                .await
+                .unwrap();
+            ProfileQueries::set(&mut conn, alice, &value("stage", "2", 110))
+                .await
+                .unwrap();
+            ProfileQueries::set(&mut conn, alice, &value("done", "false", 110))
+                .await
+                .unwrap();
+            ProfileQueries::set(&mut conn, player, &value("stage", "\"account\"", 120))
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(
+            ProfileQueries::all(&pool, alice).await.unwrap(),
+            [value("done", "false", 110), value("stage", "2", 110)]
+        );
+        // Owners don't share values
+        assert_eq!(
+            ProfileQueries::get(&pool, player, "quest", "stage")
+                .await
+                .unwrap()
+                .as_deref(),
+            Some("\"account\"")
+        );
+        assert_eq!(
+            ProfileQueries::get(&pool, alice, "event", "stage")
+                .await
+                .unwrap(),
+            None
+        );
+
+        let mut conn = pool.acquire().await.unwrap();
+        assert!(
+            ProfileQueries::remove(&mut conn, alice, "quest", "stage")
+                .await
+                .unwrap()
+        );
+        assert!(
+            !ProfileQueries::remove(&mut conn, alice, "quest", "stage")
+                .await
+                .unwrap()
+        );
+    }
+}
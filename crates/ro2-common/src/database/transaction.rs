@@ -0,0 +1,1188 @@
+//! Moving items and zeny between characters without duplicating them
+//!
+//! Anything that takes and gives several rows at once (trades, mail
+//! claims, auction settlements, shop purchases) is described as an
+//! [`ItemTransaction`] and applied in one go. Applying it:
+//!
+//! - runs every step in one database transaction, so either all of them
+//!   happen or none do;
+//! - starts with `BEGIN IMMEDIATE`, which takes SQLite's write lock before
+//!   anything is read, so two transactions touching the same rows run one
+//!   after the other instead of both seeing the items still there;
+//! - takes items and zeny with guarded updates (`quantity >= ?`), never by
+//!   reading a value and writing it back;
+//! - records its idempotency key with the changes. A request that is
+//!   retried, replayed or sent twice by a client finds its key and is
+//!   reported as [`Outcome::AlreadyDone`] without touching anything.
+//!
+//! Bound items only ever leave their owner by being sold to a shop, so
+//! other kinds of transaction refuse to take them. Rentals past their
+//! expiry can't be taken at all, even before the purge removes them, and
+//! nothing given is stacked onto a bound or rented stack. Guild storage
+//! keeps no expiry, so rentals can't be put in it.
+//!
+//! Guild storage deposits and withdrawals move items and zeny between a
+//! character and their guild's storage, and
+//! [`log_guild_use`](ItemTransaction::log_guild_use) writes them to the
+//! guild's storage log with the rest. A withdrawal that would take a
+//! member past their rank's daily limit is refused there; since the log is
+//! read after the write lock is taken, two withdrawals at once can't both
+//! slip under it.
+//!
+//! Event currency is spent the same way, so an exchange shop's reward is
+//! only given if the currency could be taken.
+//!
+//! A purchase from a vending stall also takes from the stall's listing
+//! ([`take_stall_item`](ItemTransaction::take_stall_item)), at the price
+//! the buyer saw, so two buyers can't both get the last of it and a stall
+//! reopened with new prices refuses a purchase made at the old ones.
+//! Stalls can't sell rentals, which would lose their expiry on the way.
+//!
+//! Keys should name what is being settled, e.g. `trade:<id>` or
+//! `mail:<id>`, so any number of requests for the same thing share one.
+
+use sqlx::{Pool, Sqlite, SqliteConnection};
+
+/// What a transaction is for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransactionKind {
+    Trade,
+    MailClaim,
+    AuctionSettle,
+    ShopPurchase,
+    GuildStorage,
+    EventExchange,
+    Vending,
+}
+
+impl TransactionKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Trade => "trade",
+            Self::MailClaim => "mail_claim",
+            Self::AuctionSettle => "auction_settle",
+            Self::ShopPurchase => "shop_purchase",
+            Self::GuildStorage => "guild_storage",
+            Self::EventExchange => "event_exchange",
+            Self::Vending => "vending",
+        }
+    }
+
+    /// Whether it may take bound items
+    pub fn takes_bound(self) -> bool {
+        self == Self::ShopPurchase
+    }
+
+    /// Whether it may take rentals that haven't expired
+    pub fn takes_rentals(self) -> bool {
+        !matches!(self, Self::GuildStorage | Self::Vending)
+    }
+}
+
+/// What a member did with their guild's storage
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GuildStorageAction {
+    DepositItem,
+    WithdrawItem,
+    DepositZeny,
+    WithdrawZeny,
+}
+
+impl GuildStorageAction {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::DepositItem => "deposit_item",
+            Self::WithdrawItem => "withdraw_item",
+            Self::DepositZeny => "deposit_zeny",
+            Self::WithdrawZeny => "withdraw_zeny",
+        }
+    }
+}
+
+/// A deposit or withdrawal for the guild storage log
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GuildStorageUse {
+    pub guild_id: i64,
+    pub character_id: i64,
+    pub action: GuildStorageAction,
+    /// `None` for zeny
+    pub item_id: Option<i32>,
+    /// Items or zeny
+    pub amount: i64,
+}
+
+/// Window the guild storage daily limits count over
+pub const GUILD_LIMIT_WINDOW_SECS: i64 = 24 * 60 * 60;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Step {
+    TakeItem {
+        character_id: i64,
+        slot: i32,
+        item_id: i32,
+        quantity: i32,
+    },
+    GiveItem {
+        character_id: i64,
+        slot: i32,
+        item_id: i32,
+        quantity: i32,
+    },
+    TakeZeny {
+        character_id: i64,
+        amount: i64,
+    },
+    GiveZeny {
+        character_id: i64,
+        amount: i64,
+    },
+    TakeGuildItem {
+        guild_id: i64,
+        slot: i32,
+        item_id: i32,
+        quantity: i32,
+    },
+    GiveGuildItem {
+        guild_id: i64,
+        slot: i32,
+        item_id: i32,
+        quantity: i32,
+    },
+    TakeGuildZeny {
+        guild_id: i64,
+        amount: i64,
+    },
+    GiveGuildZeny {
+        guild_id: i64,
+        amount: i64,
+    },
+    LogGuildUse {
+        used: GuildStorageUse,
+        daily_limit: Option<i64>,
+    },
+    TakeEventCurrency {
+        character_id: i64,
+        currency_id: i32,
+        amount: i64,
+    },
+    TakeStallItem {
+        character_id: i64,
+        stall_slot: i32,
+        item_id: i32,
+        quantity: i32,
+        price: i64,
+    },
+}
+
+/// Why a transaction was rolled back
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Refusal {
+    /// The slot doesn't hold that many of the item, or it's equipped or
+    /// expired
+    MissingItem {
+        character_id: i64,
+        slot: i32,
+    },
+    /// The item is bound to its owner
+    BoundItem {
+        character_id: i64,
+        slot: i32,
+    },
+    /// The item is a rental, which can't go where it's being moved
+    RentalItem {
+        character_id: i64,
+        slot: i32,
+    },
+    /// The slot holds a different item
+    SlotTaken {
+        character_id: i64,
+        slot: i32,
+    },
+    NotEnoughZeny {
+        character_id: i64,
+    },
+    NoSuchCharacter {
+        character_id: i64,
+    },
+    /// The guild storage slot doesn't hold that many of the item
+    MissingGuildItem {
+        guild_id: i64,
+        slot: i32,
+    },
+    /// The guild storage slot holds a different item
+    GuildSlotTaken {
+        guild_id: i64,
+        slot: i32,
+    },
+    NotEnoughGuildZeny {
+        guild_id: i64,
+    },
+    /// The member would withdraw more than their rank allows in a day
+    DailyLimit {
+        character_id: i64,
+    },
+    /// Not enough of the event currency, or it has expired
+    NotEnoughCurrency {
+        character_id: i64,
+        currency_id: i32,
+    },
+    /// The stall no longer sells that many of the item at that price
+    StallItemGone {
+        character_id: i64,
+        stall_slot: i32,
+    },
+}
+
+/// What applying a transaction did
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Done,
+    /// Its key was already committed; nothing changed
+    AlreadyDone,
+    /// A step couldn't be done; nothing changed
+    Refused(Refusal),
+}
+
+/// Items and zeny to move, all or nothing
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ItemTransaction {
+    kind: TransactionKind,
+    key: String,
+    steps: Vec<Step>,
+}
+
+impl ItemTransaction {
+    pub fn new(kind: TransactionKind, key: impl Into<String>) -> Self {
+        Self {
+            kind,
+            key: key.into(),
+            steps: Vec::new(),
+        }
+    }
+
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// Take `quantity` of `item_id` out of an unequipped, unexpired stack
+    /// in `slot`
+    pub fn take_item(mut self, character_id: i64, slot: i32, item_id: i32, quantity: i32) -> Self {
+        self.steps.push(Step::TakeItem {
+            character_id,
+            slot,
+            item_id,
+            quantity,
+        });
+        self
+    }
+
+    /// Put `quantity` of `item_id` in `slot`, which has to be empty or
+    /// hold the same item, neither bound nor rented
+    pub fn give_item(mut self, character_id: i64, slot: i32, item_id: i32, quantity: i32) -> Self {
+        self.steps.push(Step::GiveItem {
+            character_id,
+            slot,
+            item_id,
+            quantity,
+        });
+        self
+    }
+
+    pub fn take_zeny(mut self, character_id: i64, amount: i64) -> Self {
+        self.steps.push(Step::TakeZeny {
+            character_id,
+            amount,
+        });
+        self
+    }
+
+    pub fn give_zeny(mut self, character_id: i64, amount: i64) -> Self {
+        self.steps.push(Step::GiveZeny {
+            character_id,
+            amount,
+        });
+        self
+    }
+
+    /// Take `quantity` of `item_id` out of guild storage `slot`
+    pub fn take_guild_item(
+        mut self,
+        guild_id: i64,
+        slot: i32,
+        item_id: i32,
+        quantity: i32,
+    ) -> Self {
+        self.steps.push(Step::TakeGuildItem {
+            guild_id,
+            slot,
+            item_id,
+            quantity,
+        });
+        self
+    }
+
+    /// Put `quantity` of `item_id` in guild storage `slot`, which has to be
+    /// empty or hold the same item
+    pub fn give_guild_item(
+        mut self,
+        guild_id: i64,
+        slot: i32,
+        item_id: i32,
+        quantity: i32,
+    ) -> Self {
+        self.steps.push(Step::GiveGuildItem {
+            guild_id,
+            slot,
+            item_id,
+            quantity,
+        });
+        self
+    }
+
+    pub fn take_guild_zeny(mut self, guild_id: i64, amount: i64) -> Self {
+        self.steps.push(Step::TakeGuildZeny { guild_id, amount });
+        self
+    }
+
+    pub fn give_guild_zeny(mut self, guild_id: i64, amount: i64) -> Self {
+        self.steps.push(Step::GiveGuildZeny { guild_id, amount });
+        self
+    }
+
+    /// Write `used` to the guild storage log, refusing if the member's
+    /// total of that action over the last [`GUILD_LIMIT_WINDOW_SECS`]
+    /// would go past `daily_limit`
+    pub fn log_guild_use(mut self, used: GuildStorageUse, daily_limit: Option<i64>) -> Self {
+        self.steps.push(Step::LogGuildUse { used, daily_limit });
+        self
+    }
+
+    /// Spend `amount` of an event currency that hasn't expired
+    pub fn take_event_currency(mut self, character_id: i64, currency_id: i32, amount: i64) -> Self {
+        self.steps.push(Step::TakeEventCurrency {
+            character_id,
+            currency_id,
+            amount,
+        });
+        self
+    }
+
+    /// Take `quantity` of `item_id` off the listing in `character_id`'s
+    /// vending stall `stall_slot`, which has to still sell them for
+    /// `price` each
+    pub fn take_stall_item(
+        mut self,
+        character_id: i64,
+        stall_slot: i32,
+        item_id: i32,
+        quantity: i32,
+        price: i64,
+    ) -> Self {
+        self.steps.push(Step::TakeStallItem {
+            character_id,
+            stall_slot,
+            item_id,
+            quantity,
+            price,
+        });
+        self
+    }
+
+    /// Whether a transaction with this key has already been applied, for
+    /// callers that check more than the steps do before applying
+    pub async fn is_done(&self, pool: &Pool<Sqlite>) -> crate::Result<bool> {
+        let done = sqlx::query("SELECT 1 FROM item_transactions WHERE idempotency_key = ?")
+            .bind(&self.key)
+            .fetch_optional(pool)
+            .await?;
+        Ok(done.is_some())
+    }
+
+    /// Apply every step, or none of them if one is refused
+    pub async fn apply(&self, pool: &Pool<Sqlite>, now: i64) -> crate::Result<Outcome> {
+        if self.steps.iter().any(|step| !step.positive()) {
+            return Err(anyhow::anyhow!(
+                "transaction {}: quantities and amounts must be positive",
+                self.key
+            ));
+        }
+
+        let mut tx = pool.begin_with("BEGIN IMMEDIATE").await?;
+        let recorded = sqlx::query(
+            "INSERT OR IGNORE INTO item_transactions (idempotency_key, kind, committed_at) VALUES (?, ?, ?)",
+        )
+        .bind(&self.key)
+        .bind(self.kind.as_str())
+        .bind(now)
+        .execute(&mut *tx)
+        .await?;
+        if recorded.rows_affected() == 0 {
+            return Ok(Outcome::AlreadyDone);
+        }
+
+        for step in &self.steps {
+            if let Some(refusal) = step.apply(&mut tx, self.kind, now).await? {
+                // Dropping the transaction rolls back the steps before it
+                // and the key, so a corrected request can still go through
+                return Ok(Outcome::Refused(refusal));
+            }
+        }
+        tx.commit().await?;
+        Ok(Outcome::Done)
+    }
+}
+
+impl Step {
+    fn positive(&self) -> bool {
+        match *self {
+            Self::TakeItem { quantity, .. }
+            | Self::GiveItem { quantity, .. }
+            | Self::TakeStallItem { quantity, .. }
+            | Self::TakeGuildItem { quantity, .. }
+            | Self::GiveGuildItem { quantity, .. } => quantity > 0,
+            Self::TakeZeny { amount, .. }
+            | Self::GiveZeny { amount, .. }
+            | Self::TakeGuildZeny { amount, .. }
+            | Self::GiveGuildZeny { amount, .. } => amount > 0,
+            Self::LogGuildUse { used, .. } => used.amount > 0,
+            Self::TakeEventCurrency { amount, .. } => amount > 0,
+        }
+    }
+
+    async fn apply(
+        &self,
+        conn: &mut SqliteConnection,
+        kind: TransactionKind,
+        now: i64,
+    ) -> crate::Result<Option<Refusal>> {
+        match *self {
+            Self::TakeItem {
+                character_id,
+                slot,
+                item_id,
+                quantity,
+            } => {
+                let taken = sqlx::query(
+                    "UPDATE inventory SET quantity = quantity - ?
+                     WHERE character_id = ? AND slot_index = ? AND item_id = ? AND quantity >= ? AND is_equipped = 0
+                         AND (is_bound = 0 OR ?) AND (expires_at IS NULL OR (? AND expires_at > ?))",
+                )
+                .bind(quantity)
+                .bind(character_id)
+                .bind(slot)
+                .bind(item_id)
+                .bind(quantity)
+                .bind(kind.takes_bound())
+                .bind(kind.takes_rentals())
+                .bind(now)
+                .execute(&mut *conn)
+                .await?;
+                if taken.rows_affected() == 0 {
+                    let held: Option<(bool, bool)> = sqlx::query_as(
+                        "SELECT is_bound = 1, COALESCE(expires_at > ?, 0) FROM inventory WHERE character_id = ? AND slot_index = ? AND item_id = ?",
+                    )
+                    .bind(now)
+                    .bind(character_id)
+                    .bind(slot)
+                    .bind(item_id)
+                    .fetch_optional(&mut *conn)
+                    .await?;
+                    return Ok(Some(match held {
+                        Some((true, _)) if !kind.takes_bound() => {
+                            Refusal::BoundItem { character_id, slot }
+                        }
+                        Some((_, true)) if !kind.takes_rentals() => {
+                            Refusal::RentalItem { character_id, slot }
+                        }
+                        _ => Refusal::MissingItem { character_id, slot },
+                    }));
+                }
+                sqlx::query(
+                    "DELETE FROM inventory WHERE character_id = ? AND slot_index = ? AND quantity <= 0",
+                )
+                .bind(character_id)
+                .bind(slot)
+                .execute(&mut *conn)
+                .await?;
+            }
+            Self::GiveItem {
+                character_id,
+                slot,
+                item_id,
+                quantity,
+            } => {
+                let stacked = sqlx::query(
+                    "UPDATE inventory SET quantity = quantity + ?
+                     WHERE character_id = ? AND slot_index = ? AND item_id = ? AND is_bound = 0 AND expires_at IS NULL",
+                )
+                .bind(quantity)
+                .bind(character_id)
+                .bind(slot)
+                .bind(item_id)
+                .execute(&mut *conn)
+                .await?;
+                if stacked.rows_affected() == 0 {
+                    let inserted = sqlx::query(
+                        "INSERT INTO inventory (character_id, item_id, quantity, slot_index)
+                         SELECT ?, ?, ?, ? WHERE NOT EXISTS
+                             (SELECT 1 FROM inventory WHERE character_id = ? AND slot_index = ?)",
+                    )
+                    .bind(character_id)
+                    .bind(item_id)
+                    .bind(quantity)
+                    .bind(slot)
+                    .bind(character_id)
+                    .bind(slot)
+                    .execute(&mut *conn)
+                    .await?;
+                    if inserted.rows_affected() == 0 {
+                        return Ok(Some(Refusal::SlotTaken { character_id, slot }));
+                    }
+                }
+            }
+            Self::TakeZeny {
+                character_id,
+                amount,
+            } => {
+                let taken =
+                    sqlx::query("UPDATE characters SET gold = gold - ? WHERE id = ? AND gold >= ?")
+                        .bind(amount)
+                        .bind(character_id)
+                        .bind(amount)
+                        .execute(&mut *conn)
+                        .await?;
+                if taken.rows_affected() == 0 {
+                    return Ok(Some(Refusal::NotEnoughZeny { character_id }));
+                }
+            }
+            Self::GiveZeny {
+                character_id,
+                amount,
+            } => {
+                let given = sqlx::query("UPDATE characters SET gold = gold + ? WHERE id = ?")
+                    .bind(amount)
+                    .bind(character_id)
+                    .execute(&mut *conn)
+                    .await?;
+                if given.rows_affected() == 0 {
+                    return Ok(Some(Refusal::NoSuchCharacter { character_id }));
+                }
+            }
+            Self::TakeGuildItem {
+                guild_id,
+                slot,
+                item_id,
+                quantity,
+            } => {
+                let taken = sqlx::query(
+                    "UPDATE guild_storage SET quantity = quantity - ?
+                     WHERE guild_id = ? AND slot_index = ? AND item_id = ? AND quantity >= ?",
+                )
+                .bind(quantity)
+                .bind(guild_id)
+                .bind(slot)
+                .bind(item_id)
+                .bind(quantity)
+                .execute(&mut *conn)
+                .await?;
+                if taken.rows_affected() == 0 {
+                    return Ok(Some(Refusal::MissingGuildItem { guild_id, slot }));
+                }
+                sqlx::query(
+                    "DELETE FROM guild_storage WHERE guild_id = ? AND slot_index = ? AND quantity <= 0",
+                )
+                .bind(guild_id)
+                .bind(slot)
+                .execute(&mut *conn)
+                .await?;
+            }
+            Self::GiveGuildItem {
+                guild_id,
+                slot,
+                item_id,
+                quantity,
+            } => {
+                let given = sqlx::query(
+                    "INSERT INTO guild_storage (guild_id, slot_index, item_id, quantity) VALUES (?, ?, ?, ?)
+                     ON CONFLICT(guild_id, slot_index) DO UPDATE SET quantity = quantity + excluded.quantity
+                         WHERE item_id = excluded.item_id",
+                )
+                .bind(guild_id)
+                .bind(slot)
+                .bind(item_id)
+                .bind(quantity)
+                .execute(&mut *conn)
+                .await?;
+                if given.rows_affected() == 0 {
+                    return Ok(Some(Refusal::GuildSlotTaken { guild_id, slot }));
+                }
+            }
+            Self::TakeGuildZeny { guild_id, amount } => {
+                let taken =
+                    sqlx::query("UPDATE guilds SET zeny = zeny - ? WHERE id = ? AND zeny >= ?")
+                        .bind(amount)
+                        .bind(guild_id)
+                        .bind(amount)
+                        .execute(&mut *conn)
+                        .await?;
+                if taken.rows_affected() == 0 {
+                    return Ok(Some(Refusal::NotEnoughGuildZeny { guild_id }));
+                }
+            }
+            Self::GiveGuildZeny { guild_id, amount } => {
+                let given = sqlx::query("UPDATE guilds SET zeny = zeny + ? WHERE id = ?")
+                    .bind(amount)
+                    .bind(guild_id)
+                    .execute(&mut *conn)
+                    .await?;
+                if given.rows_affected() == 0 {
+                    return Ok(Some(Refusal::NotEnoughGuildZeny { guild_id }));
+                }
+            }
+            Self::LogGuildUse { used, daily_limit } => {
+                if let Some(limit) = daily_limit {
+                    let (so_far,): (i64,) = sqlx::query_as(
+                        "SELECT COALESCE(SUM(amount), 0) FROM guild_storage_log
+                         WHERE guild_id = ? AND character_id = ? AND action = ? AND at > ?",
+                    )
+                    .bind(used.guild_id)
+                    .bind(used.character_id)
+                    .bind(used.action.as_str())
+                    .bind(now - GUILD_LIMIT_WINDOW_SECS)
+                    .fetch_one(&mut *conn)
+                    .await?;
+                    if so_far + used.amount > limit {
+                        return Ok(Some(Refusal::DailyLimit {
+                            character_id: used.character_id,
+                        }));
+                    }
+                }
+                sqlx::query(
+                    "INSERT INTO guild_storage_log (guild_id, character_id, action, item_id, amount, at) VALUES (?, ?, ?, ?, ?, ?)",
+                )
+                .bind(used.guild_id)
+                .bind(used.character_id)
+                .bind(used.action.as_str())
+                .bind(used.item_id)
+                .bind(used.amount)
+                .bind(now)
+                .execute(&mut *conn)
+                .await?;
+            }
+            Self::TakeEventCurrency {
+                character_id,
+                currency_id,
+                amount,
+            } => {
+                let taken = sqlx::query(
+                    "UPDATE event_currency SET amount = amount - ?
+                     WHERE character_id = ? AND currency_id = ? AND amount >= ? AND expires_at > ?",
+                )
+                .bind(amount)
+                .bind(character_id)
+                .bind(currency_id)
+                .bind(amount)
+                .bind(now)
+                .execute(&mut *conn)
+                .await?;
+                if taken.rows_affected() == 0 {
+                    return Ok(Some(Refusal::NotEnoughCurrency {
+                        character_id,
+                        currency_id,
+                    }));
+                }
+            }
+            Self::TakeStallItem {
+                character_id,
+                stall_slot,
+                item_id,
+                quantity,
+                price,
+            } => {
+                let taken = sqlx::query(
+                    "UPDATE vending_items SET quantity = quantity - ?
+                     WHERE character_id = ? AND stall_slot = ? AND item_id = ? AND quantity >= ? AND price = ?",
+                )
+                .bind(quantity)
+                .bind(character_id)
+                .bind(stall_slot)
+                .bind(item_id)
+                .bind(quantity)
+                .bind(price)
+                .execute(&mut *conn)
+                .await?;
+                if taken.rows_affected() == 0 {
+                    return Ok(Some(Refusal::StallItemGone {
+                        character_id,
+                        stall_slot,
+                    }));
+                }
+            }
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+    use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
+    use std::path::PathBuf;
+
+    const POTION: i32 = 501;
+    const SWORD: i32 = 1101;
+
+    /// A file database, so several connections really do race
+    struct TestDb {
+        pool: Pool<Sqlite>,
+        path: PathBuf,
+    }
+
+    impl Drop for TestDb {
+        fn drop(&mut self) {
+            for suffix in ["", "-wal", "-shm"] {
+                let _ = std::fs::remove_file(format!("{}{}", self.path.display(), suffix));
+            }
+        }
+    }
+
+    /// Alice (1) with 10 potions in slot 0, a sword in slot 1 and 1000
+    /// zeny; Bob (2) with nothing
+    async fn db(name: &str) -> TestDb {
+        let path =
+            std::env::temp_dir().join(format!("ro2-item-tx-{}-{}.db", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let options = SqliteConnectOptions::new()
+            .filename(&path)
+            .create_if_missing(true);
+        let pool = SqlitePoolOptions::new()
+            .max_connections(8)
+            .connect_with(options)
+            .await
+            .unwrap();
+        testing::database::migrate(&pool).await;
+        testing::character(&pool, 1, "Alice", 1000).await;
+        testing::character(&pool, 2, "Bob", 0).await;
+        sqlx::query(
+            "INSERT INTO inventory (character_id, item_id, quantity, slot_index) VALUES (1, 501, 10, 0), (1, 1101, 1, 1)",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        TestDb { pool, path }
+    }
+
+    async fn count(pool: &Pool<Sqlite>, character_id: i64, item_id: i32) -> i64 {
+        sqlx::query_as::<_, (i64,)>(
+            "SELECT COALESCE(SUM(quantity), 0) FROM inventory WHERE character_id = ? AND item_id = ?",
+        )
+        .bind(character_id)
+        .bind(item_id)
+        .fetch_one(pool)
+        .await
+        .unwrap()
+        .0
+    }
+
+    async fn zeny(pool: &Pool<Sqlite>, character_id: i64) -> i64 {
+        sqlx::query_as::<_, (i64,)>("SELECT gold FROM characters WHERE id = ?")
+            .bind(character_id)
+            .fetch_one(pool)
+            .await
+            .unwrap()
+            .0
+    }
+
+    /// Alice sells Bob her sword for 300 zeny she doesn't pay, under `key`
+    fn trade(key: &str) -> ItemTransaction {
+        ItemTransaction::new(TransactionKind::Trade, key)
+            .take_item(1, 1, SWORD, 1)
+            .give_item(2, 0, SWORD, 1)
+            .take_zeny(1, 300)
+            .give_zeny(2, 300)
+    }
+
+    #[tokio::test]
+    async fn test_apply_and_refuse() {
+        let db = db("apply").await;
+        let pool = &db.pool;
+
+        let purchase = ItemTransaction::new(TransactionKind::ShopPurchase, "shop:1")
+            .take_zeny(1, 200)
+            .give_item(1, 0, POTION, 5);
+        assert_eq!(purchase.apply(pool, 0).await.unwrap(), Outcome::Done);
+        assert_eq!(count(pool, 1, POTION).await, 15);
+        assert_eq!(zeny(pool, 1).await, 800);
+
+        // Everything is rolled back, including the steps that went through
+        let too_expensive = ItemTransaction::new(TransactionKind::ShopPurchase, "shop:2")
+            .give_item(1, 0, POTION, 5)
+            .take_zeny(1, 5_000);
+        assert_eq!(
+            too_expensive.apply(pool, 0).await.unwrap(),
+            Outcome::Refused(Refusal::NotEnoughZeny { character_id: 1 })
+        );
+        assert_eq!(count(pool, 1, POTION).await, 15);
+
+        let wrong_slot =
+            ItemTransaction::new(TransactionKind::MailClaim, "mail:1").give_item(1, 1, POTION, 1);
+        assert_eq!(
+            wrong_slot.apply(pool, 0).await.unwrap(),
+            Outcome::Refused(Refusal::SlotTaken {
+                character_id: 1,
+                slot: 1
+            })
+        );
+
+        // Taking a whole stack empties the slot
+        let sell = ItemTransaction::new(TransactionKind::ShopPurchase, "shop:3")
+            .take_item(1, 0, POTION, 15)
+            .give_zeny(1, 15);
+        assert_eq!(sell.apply(pool, 0).await.unwrap(), Outcome::Done);
+        assert_eq!(count(pool, 1, POTION).await, 0);
+
+        let negative = ItemTransaction::new(TransactionKind::Trade, "trade:x").give_zeny(1, -5);
+        assert!(negative.apply(pool, 0).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_bound_and_expired_items() {
+        let db = db("bound").await;
+        let pool = &db.pool;
+        sqlx::raw_sql(
+            "UPDATE inventory SET is_bound = 1 WHERE character_id = 1 AND slot_index = 1;
+             UPDATE inventory SET expires_at = 100 WHERE character_id = 1 AND slot_index = 0;",
+        )
+        .execute(pool)
+        .await
+        .unwrap();
+
+        let bound = Outcome::Refused(Refusal::BoundItem {
+            character_id: 1,
+            slot: 1,
+        });
+        assert_eq!(trade("trade:1").apply(pool, 0).await.unwrap(), bound);
+        let auction = ItemTransaction::new(TransactionKind::AuctionSettle, "auction:1")
+            .take_item(1, 1, SWORD, 1)
+            .give_item(2, 0, SWORD, 1);
+        assert_eq!(auction.apply(pool, 0).await.unwrap(), bound);
+
+        // A rental can be traded until it expires
+        let rental = |key: &str| {
+            ItemTransaction::new(TransactionKind::MailClaim, key)
+                .take_item(1, 0, POTION, 1)
+                .give_item(2, 0, POTION, 1)
+        };
+        assert_eq!(
+            rental("mail:1").apply(pool, 100).await.unwrap(),
+            Outcome::Refused(Refusal::MissingItem {
+                character_id: 1,
+                slot: 0
+            })
+        );
+        assert_eq!(
+            rental("mail:2").apply(pool, 99).await.unwrap(),
+            Outcome::Done
+        );
+        assert_eq!(count(pool, 2, POTION).await, 1);
+
+        // Nothing stacks onto the rented stack
+        let restock = ItemTransaction::new(TransactionKind::ShopPurchase, "shop:1")
+            .take_zeny(1, 10)
+            .give_item(1, 0, POTION, 1);
+        assert_eq!(
+            restock.apply(pool, 0).await.unwrap(),
+            Outcome::Refused(Refusal::SlotTaken {
+                character_id: 1,
+                slot: 0
+            })
+        );
+
+        // Shops still buy bound items
+        let sell = ItemTransaction::new(TransactionKind::ShopPurchase, "shop:2")
+            .take_item(1, 1, SWORD, 1)
+            .give_zeny(1, 50);
+        assert_eq!(sell.apply(pool, 0).await.unwrap(), Outcome::Done);
+        assert_eq!(count(pool, 1, SWORD).await, 0);
+    }
+
+    #[tokio::test]
+    async fn test_guild_storage() {
+        let db = db("guild").await;
+        let pool = &db.pool;
+        sqlx::raw_sql(
+            "INSERT INTO guilds (id, name, zeny, created_at) VALUES (7, 'Knights', 0, 0)",
+        )
+        .execute(pool)
+        .await
+        .unwrap();
+        let used = |action, item_id, amount| GuildStorageUse {
+            guild_id: 7,
+            character_id: 1,
+            action,
+            item_id,
+            amount,
+        };
+        let deposit = ItemTransaction::new(TransactionKind::GuildStorage, "guild:1")
+            .take_item(1, 0, POTION, 6)
+            .give_guild_item(7, 3, POTION, 6)
+            .take_zeny(1, 500)
+            .give_guild_zeny(7, 500)
+            .log_guild_use(used(GuildStorageAction::DepositItem, Some(POTION), 6), None);
+        assert!(!deposit.is_done(pool).await.unwrap());
+        assert_eq!(deposit.apply(pool, 0).await.unwrap(), Outcome::Done);
+        assert!(deposit.is_done(pool).await.unwrap());
+        assert_eq!(count(pool, 1, POTION).await, 4);
+
+        // Only the same item stacks in a guild slot
+        let wrong_slot = ItemTransaction::new(TransactionKind::GuildStorage, "guild:2")
+            .take_item(1, 1, SWORD, 1)
+            .give_guild_item(7, 3, SWORD, 1);
+        assert_eq!(
+            wrong_slot.apply(pool, 0).await.unwrap(),
+            Outcome::Refused(Refusal::GuildSlotTaken {
+                guild_id: 7,
+                slot: 3
+            })
+        );
+
+        // Four potions a day: the fifth is refused until a day has passed
+        let withdraw = |key: &str, quantity| {
+            ItemTransaction::new(TransactionKind::GuildStorage, key)
+                .take_guild_item(7, 3, POTION, quantity)
+                .give_item(1, 0, POTION, quantity)
+                .log_guild_use(
+                    used(
+                        GuildStorageAction::WithdrawItem,
+                        Some(POTION),
+                        quantity as i64,
+                    ),
+                    Some(4),
+                )
+        };
+        assert_eq!(
+            withdraw("guild:3", 3).apply(pool, 100).await.unwrap(),
+            Outcome::Done
+        );
+        assert_eq!(
+            withdraw("guild:4", 2).apply(pool, 200).await.unwrap(),
+            Outcome::Refused(Refusal::DailyLimit { character_id: 1 })
+        );
+        assert_eq!(
+            withdraw("guild:5", 1).apply(pool, 200).await.unwrap(),
+            Outcome::Done
+        );
+        assert_eq!(
+            withdraw("guild:6", 3)
+                .apply(pool, 100 + GUILD_LIMIT_WINDOW_SECS)
+                .await
+                .unwrap(),
+            Outcome::Refused(Refusal::MissingGuildItem {
+                guild_id: 7,
+                slot: 3
+            })
+        );
+        assert_eq!(
+            withdraw("guild:7", 2)
+                .apply(pool, 100 + GUILD_LIMIT_WINDOW_SECS)
+                .await
+                .unwrap(),
+            Outcome::Done
+        );
+        assert_eq!(count(pool, 1, POTION).await, 10);
+        let left: Option<(i32,)> =
+            sqlx::query_as("SELECT quantity FROM guild_storage WHERE guild_id = 7")
+                .fetch_optional(pool)
+                .await
+                .unwrap();
+        assert_eq!(left, None);
+
+        let too_much = ItemTransaction::new(TransactionKind::GuildStorage, "guild:8")
+            .take_guild_zeny(7, 501)
+            .give_zeny(1, 501);
+        assert_eq!(
+            too_much.apply(pool, 0).await.unwrap(),
+            Outcome::Refused(Refusal::NotEnoughGuildZeny { guild_id: 7 })
+        );
+        let logged: (i64,) =
+            sqlx::query_as("SELECT COUNT(*) FROM guild_storage_log WHERE guild_id = 7")
+                .fetch_one(pool)
+                .await
+                .unwrap();
+        assert_eq!(logged.0, 4);
+
+        // Rentals stay with their renter
+        sqlx::raw_sql(
+            "UPDATE inventory SET expires_at = 1000 WHERE character_id = 1 AND slot_index = 1",
+        )
+        .execute(pool)
+        .await
+        .unwrap();
+        let rental = ItemTransaction::new(TransactionKind::GuildStorage, "guild:9")
+            .take_item(1, 1, SWORD, 1)
+            .give_guild_item(7, 0, SWORD, 1);
+        assert_eq!(
+            rental.apply(pool, 0).await.unwrap(),
+            Outcome::Refused(Refusal::RentalItem {
+                character_id: 1,
+                slot: 1
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_event_currency() {
+        let db = db("event").await;
+        let pool = &db.pool;
+        sqlx::raw_sql(
+            "INSERT INTO event_currency (character_id, currency_id, amount, expires_at) VALUES (1, 3, 10, 100)",
+        )
+        .execute(pool)
+        .await
+        .unwrap();
+        let exchange = |key: &str, price| {
+            ItemTransaction::new(TransactionKind::EventExchange, key)
+                .take_event_currency(1, 3, price)
+                .give_item(1, 2, SWORD, 1)
+        };
+        let short = Outcome::Refused(Refusal::NotEnoughCurrency {
+            character_id: 1,
+            currency_id: 3,
+        });
+        assert_eq!(exchange("event:1", 11).apply(pool, 0).await.unwrap(), short);
+        assert_eq!(
+            exchange("event:2", 10).apply(pool, 100).await.unwrap(),
+            short
+        );
+        assert_eq!(
+            exchange("event:3", 10).apply(pool, 99).await.unwrap(),
+            Outcome::Done
+        );
+        assert_eq!(count(pool, 1, SWORD).await, 2);
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_stall_purchases() {
+        let db = db("vending").await;
+        let pool = &db.pool;
+        sqlx::raw_sql(
+            "UPDATE characters SET gold = 1000 WHERE id = 2;
+             INSERT INTO vending_stalls (character_id, channel_id, title, map_id, position_x, position_y, position_z, opened_at)
+                 VALUES (1, 1, 'Potions', 1, 0, 0, 0, 0);
+             INSERT INTO vending_items (character_id, stall_slot, inventory_slot, item_id, quantity, price)
+                 VALUES (1, 0, 0, 501, 10, 50);",
+        )
+        .execute(pool)
+        .await
+        .unwrap();
+        let purchase = |key: String, price| {
+            ItemTransaction::new(TransactionKind::Vending, key)
+                .take_stall_item(1, 0, POTION, 3, price)
+                .take_item(1, 0, POTION, 3)
+                .give_item(2, 0, POTION, 3)
+                .take_zeny(2, 3 * price)
+                .give_zeny(1, 3 * price)
+        };
+        let gone = Outcome::Refused(Refusal::StallItemGone {
+            character_id: 1,
+            stall_slot: 0,
+        });
+        // Not at a price the stall doesn't ask
+        assert_eq!(
+            purchase("vending:cheap".to_string(), 40)
+                .apply(pool, 0)
+                .await
+                .unwrap(),
+            gone
+        );
+
+        // Eight buyers, enough for three of them
+        let tasks: Vec<_> = (0..8)
+            .map(|i| {
+                let purchase = purchase(format!("vending:{}", i), 50);
+                let pool = pool.clone();
+                tokio::spawn(async move { purchase.apply(&pool, 0).await.unwrap() })
+            })
+            .collect();
+        let mut done = 0;
+        for task in tasks {
+            match task.await.unwrap() {
+                Outcome::Done => done += 1,
+                outcome => assert_eq!(outcome, gone),
+            }
+        }
+
+        assert_eq!(done, 3);
+        assert_eq!(
+            (count(pool, 1, POTION).await, count(pool, 2, POTION).await),
+            (1, 9)
+        );
+        assert_eq!((zeny(pool, 1).await, zeny(pool, 2).await), (1450, 550));
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_duplicates_apply_once() {
+        let db = db("duplicates").await;
+        let pool = &db.pool;
+
+        let tasks: Vec<_> = (0..8)
+            .map(|_| {
+                let pool = pool.clone();
+                tokio::spawn(async move { trade("trade:7").apply(&pool, 0).await.unwrap() })
+            })
+            .collect();
+        let mut outcomes = Vec::new();
+        for task in tasks {
+            outcomes.push(task.await.unwrap());
+        }
+
+        assert_eq!(outcomes.iter().filter(|o| **o == Outcome::Done).count(), 1);
+        assert_eq!(
+            outcomes
+                .iter()
+                .filter(|o| **o == Outcome::AlreadyDone)
+                .count(),
+            7
+        );
+        assert_eq!(count(pool, 1, SWORD).await, 0);
+        assert_eq!(count(pool, 2, SWORD).await, 1);
+        assert_eq!((zeny(pool, 1).await, zeny(pool, 2).await), (700, 300));
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_claims_on_one_item() {
+        let db = db("one-item").await;
+        let pool = &db.pool;
+
+        // Different keys, same sword: only one can have it
+        let tasks: Vec<_> = (0..8)
+            .map(|i| {
+                let pool = pool.clone();
+                tokio::spawn(async move {
+                    trade(&format!("trade:{}", i))
+                        .apply(&pool, 0)
+                        .await
+                        .unwrap()
+                })
+            })
+            .collect();
+        let mut done = 0;
+        for task in tasks {
+            match task.await.unwrap() {
+                Outcome::Done => done += 1,
+                outcome => assert_eq!(
+                    outcome,
+                    Outcome::Refused(Refusal::MissingItem {
+                        character_id: 1,
+                        slot: 1
+                    })
+                ),
+            }
+        }
+
+        assert_eq!(done, 1);
+        assert_eq!(count(pool, 2, SWORD).await, 1);
+        assert_eq!((zeny(pool, 1).await, zeny(pool, 2).await), (700, 300));
+    }
+}
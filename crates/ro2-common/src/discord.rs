@@ -0,0 +1,571 @@
+//! Discord integration
+//!
+//! [`Discord`] posts the [`ServerEvent`]s published on an [`EventBus`] to
+//! webhooks and, given a bot token, answers a few commands typed in one
+//! Discord channel:
+//!
+//! - `!online`: how many players are online
+//! - `!broadcast <text>`: a system message to everyone, for the Discord
+//!   users listed in `admins`
+//!
+//! Everything is set in the `[discord]` section of a server's config and
+//! nothing is sent without it:
+//!
+//! ```toml
+//! [[discord.webhooks]]
+//! url = "https://discord.com/api/webhooks/<id>/<token>"
+//! events = ["server_start", "server_stop", "boss_killed"]  # all if omitted
+//!
+//! [discord.bot]
+//! token = "<bot token>"
+//! channel_id = "123456789012345678"
+//! admins = ["234567890123456789"]  # Discord user IDs
+//! poll_secs = 5
+//! ```
+//!
+//! The bot reads the channel over the REST API every `poll_secs` instead
+//! of holding a gateway connection, so it needs the Message Content
+//! intent but nothing else.
+
+use crate::Result;
+use crate::config::{self, Validate};
+use crate::events::{EventBus, ServerEvent};
+use anyhow::anyhow;
+use serde::Deserialize;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+/// Discord's REST API
+pub const DISCORD_API: &str = "https://discord.com/api/v10";
+
+/// How long a webhook or API call may take
+const HTTP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// [`ServerEvent`]s a webhook can subscribe to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    ServerStart,
+    ServerStop,
+    BossKilled,
+    PlayerBanned,
+    Registration,
+}
+
+impl ServerEvent {
+    pub fn kind(&self) -> EventKind {
+        match self {
+            ServerEvent::ServerStarted { .. } => EventKind::ServerStart,
+            ServerEvent::ServerStopped { .. } => EventKind::ServerStop,
+            ServerEvent::BossKilled { .. } => EventKind::BossKilled,
+            ServerEvent::PlayerBanned { .. } => EventKind::PlayerBanned,
+            ServerEvent::AccountRegistered { .. } => EventKind::Registration,
+        }
+    }
+
+    /// The message posted for the event
+    pub fn message(&self) -> String {
+        match self {
+            ServerEvent::ServerStarted { server } => format!(":green_circle: {} is up", server),
+            ServerEvent::ServerStopped { server } => format!(":red_circle: {} is down", server),
+            ServerEvent::BossKilled { boss, killers } if killers.is_empty() => {
+                format!(":crossed_swords: {} was defeated", boss)
+            }
+            ServerEvent::BossKilled { boss, killers } => {
+                format!(
+                    ":crossed_swords: {} was defeated by {}",
+                    boss,
+                    killers.join(", ")
+                )
+            }
+            ServerEvent::PlayerBanned { name, by } => {
+                format!(":hammer: {} was banned by {}", name, by)
+            }
+            ServerEvent::AccountRegistered { username } => {
+                format!(":wave: Welcome, {}!", username)
+            }
+        }
+    }
+}
+
+/// A webhook and the events posted to it
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct WebhookConfig {
+    pub url: String,
+    /// Every event if `None`
+    #[serde(default)]
+    pub events: Option<Vec<EventKind>>,
+}
+
+impl WebhookConfig {
+    pub fn wants(&self, kind: EventKind) -> bool {
+        self.events
+            .as_ref()
+            .is_none_or(|events| events.contains(&kind))
+    }
+}
+
+/// The bot answering commands in a channel
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct BotConfig {
+    pub token: String,
+    pub channel_id: String,
+    /// Discord user IDs allowed to broadcast
+    #[serde(default)]
+    pub admins: Vec<String>,
+    #[serde(default = "default_poll_secs")]
+    pub poll_secs: u64,
+}
+
+fn default_poll_secs() -> u64 {
+    5
+}
+
+/// Discord settings
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct DiscordConfig {
+    pub webhooks: Vec<WebhookConfig>,
+    pub bot: Option<BotConfig>,
+    /// Discord's REST API, for the bot
+    pub api_base: String,
+}
+
+impl Default for DiscordConfig {
+    fn default() -> Self {
+        Self {
+            webhooks: Vec::new(),
+            bot: None,
+            api_base: DISCORD_API.to_string(),
+        }
+    }
+}
+
+impl DiscordConfig {
+    /// Read the `[discord]` section of `path`
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        config::section(path, "discord")
+    }
+
+    /// Parse the `[discord]` section of TOML text
+    pub fn from_toml(text: &str) -> Result<Self> {
+        config::section_from_toml(text, "discord")
+    }
+
+    /// Whether anything is configured
+    pub fn is_enabled(&self) -> bool {
+        !self.webhooks.is_empty() || self.bot.is_some()
+    }
+}
+
+impl Validate for DiscordConfig {
+    fn validate(&self) -> Result<()> {
+        if self.bot.as_ref().is_some_and(|bot| bot.poll_secs == 0) {
+            return Err(anyhow!("discord bot poll_secs must be at least 1"));
+        }
+        Ok(())
+    }
+}
+
+/// A command typed in the bot's channel
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BotCommand {
+    Online,
+    Broadcast(String),
+}
+
+impl BotCommand {
+    /// Parse a channel message; `None` if it isn't a command
+    pub fn parse(content: &str) -> Option<Self> {
+        let content = content.trim().strip_prefix('!')?;
+        let (command, rest) = content.split_once(' ').unwrap_or((content, ""));
+        match (command, rest.trim()) {
+            ("online", _) => Some(BotCommand::Online),
+            ("broadcast", text) if !text.is_empty() => {
+                Some(BotCommand::Broadcast(text.to_string()))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// What the bot's commands do on the server
+pub trait BotActions: Send + Sync {
+    /// Players online
+    fn online(&self) -> usize;
+
+    /// Send `text` to every player; returns how many it was sent to
+    fn broadcast(&self, text: &str) -> usize;
+}
+
+#[derive(Debug, Deserialize)]
+struct ChannelMessage {
+    id: String,
+    content: String,
+    author: Author,
+}
+
+#[derive(Debug, Deserialize)]
+struct Author {
+    id: String,
+    #[serde(default)]
+    bot: bool,
+}
+
+/// Posts events to webhooks and runs the bot
+pub struct Discord {
+    config: DiscordConfig,
+}
+
+impl Discord {
+    pub fn new(config: DiscordConfig) -> Arc<Self> {
+        Arc::new(Self { config })
+    }
+
+    /// Post every [`ServerEvent`] published on `events` until the last
+    /// clone of the bus is dropped; await the task to let the last ones
+    /// (e.g. the server stopping) go out
+    pub fn subscribe(self: &Arc<Self>, events: &EventBus) -> JoinHandle<()> {
+        let discord = Arc::clone(self);
+        let mut events = events.subscribe::<ServerEvent>();
+        tokio::spawn(async move {
+            while let Some(event) = events.recv().await {
+                discord.notify(&event).await;
+            }
+        })
+    }
+
+    /// Post `event` to every webhook that wants it; returns how many
+    /// accepted it. Failures are logged, not returned, so a Discord outage
+    /// never holds up the server.
+    pub async fn notify(self: &Arc<Self>, event: &ServerEvent) -> usize {
+        let urls: Vec<String> = self
+            .config
+            .webhooks
+            .iter()
+            .filter(|webhook| webhook.wants(event.kind()))
+            .map(|webhook| webhook.url.clone())
+            .collect();
+        if urls.is_empty() {
+            return 0;
+        }
+
+        let body = serde_json::json!({ "content": event.message() });
+        let posted = tokio::task::spawn_blocking(move || {
+            urls.iter()
+                .filter(|url| match post_json(url, None, &body) {
+                    Ok(_) => true,
+                    Err(e) => {
+                        warn!("Discord webhook failed: {:#}", e);
+                        false
+                    }
+                })
+                .count()
+        })
+        .await;
+        posted.unwrap_or(0)
+    }
+
+    /// Start answering bot commands with `actions`, if a bot is configured
+    pub fn spawn_bot(self: &Arc<Self>, actions: Arc<dyn BotActions>) -> Option<JoinHandle<()>> {
+        let bot = self.config.bot.clone()?;
+        let discord = Arc::clone(self);
+        info!("Discord bot reading channel {}", bot.channel_id);
+        Some(tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(bot.poll_secs));
+            let mut last_seen = None;
+            loop {
+                interval.tick().await;
+                let poll = {
+                    let (discord, actions, after) = (
+                        Arc::clone(&discord),
+                        Arc::clone(&actions),
+                        last_seen.clone(),
+                    );
+                    tokio::task::spawn_blocking(move || discord.poll(after, actions.as_ref())).await
+                };
+                match poll {
+                    Ok(Ok(Some(id))) => last_seen = Some(id),
+                    Ok(Ok(None)) => {}
+                    Ok(Err(e)) => warn!("Discord bot poll failed: {:#}", e),
+                    Err(e) => warn!("Discord bot poll panicked: {}", e),
+                }
+            }
+        }))
+    }
+
+    /// Answer the commands posted after `after`; returns the newest
+    /// message ID seen. The first poll only finds where the channel is, so
+    /// old commands aren't replayed on start.
+    fn poll(&self, after: Option<String>, actions: &dyn BotActions) -> Result<Option<String>> {
+        let bot = self.config.bot.as_ref().ok_or_else(|| anyhow!("no bot"))?;
+        let url = format!(
+            "{}/channels/{}/messages",
+            self.config.api_base, bot.channel_id
+        );
+        let request = ureq::get(&url)
+            .timeout(HTTP_TIMEOUT)
+            .set("Authorization", &format!("Bot {}", bot.token));
+        let request = match &after {
+            Some(after) => request.query("after", after).query("limit", "50"),
+            None => request.query("limit", "1"),
+        };
+        // Newest first
+        let mut messages: Vec<ChannelMessage> = request.call()?.into_json()?;
+        messages.reverse();
+        let newest = messages.last().map(|message| message.id.clone());
+        if after.is_none() {
+            return Ok(newest);
+        }
+
+        for message in &messages {
+            if let Some(reply) = self.answer(message, actions) {
+                post_json(
+                    &url,
+                    Some(&bot.token),
+                    &serde_json::json!({ "content": reply }),
+                )?;
+            }
+        }
+        Ok(newest.or(after))
+    }
+
+    /// The reply to `message`, if it's a command
+    fn answer(&self, message: &ChannelMessage, actions: &dyn BotActions) -> Option<String> {
+        if message.author.bot {
+            return None;
+        }
+        let bot = self.config.bot.as_ref()?;
+        Some(match BotCommand::parse(&message.content)? {
+            BotCommand::Online => format!("{} players online", actions.online()),
+            BotCommand::Broadcast(_) if !bot.admins.contains(&message.author.id) => {
+                "Only admins can broadcast".to_string()
+            }
+            BotCommand::Broadcast(text) => {
+                info!("Discord user {} broadcast: {}", message.author.id, text);
+                format!("Sent to {} players", actions.broadcast(&text))
+            }
+        })
+    }
+}
+
+fn post_json(url: &str, bot_token: Option<&str>, body: &serde_json::Value) -> Result<()> {
+    let mut request = ureq::post(url).timeout(HTTP_TIMEOUT);
+    if let Some(token) = bot_token {
+        request = request.set("Authorization", &format!("Bot {}", token));
+    }
+    request
+        .send_json(body)
+        .map_err(|e| anyhow!("POST {}: {}", url.split('?').next().unwrap_or(url), e))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Answer `count` requests with 200 and `body`; returns the server's
+    /// address and the requests it got
+    fn serve(count: usize, body: &'static str) -> (String, std::thread::JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = format!("http://{}", listener.local_addr().unwrap());
+        let handle = std::thread::spawn(move || {
+            (0..count)
+                .map(|_| {
+                    let (stream, _) = listener.accept().unwrap();
+                    let mut reader = BufReader::new(stream);
+                    let mut request = String::new();
+                    let mut length = 0;
+                    loop {
+                        let mut line = String::new();
+                        reader.read_line(&mut line).unwrap();
+                        if let Some(value) =
+                            line.to_ascii_lowercase().strip_prefix("content-length:")
+                        {
+                            length = value.trim().parse().unwrap();
+                        }
+                        request.push_str(&line);
+                        if line == "\r\n" {
+                            break;
+                        }
+                    }
+                    let mut content = vec![0; length];
+                    reader.read_exact(&mut content).unwrap();
+                    request.push_str(&String::from_utf8(content).unwrap());
+                    let response = format!(
+                        "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                        body.len(),
+                        body
+                    );
+                    reader.get_mut().write_all(response.as_bytes()).unwrap();
+                    request
+                })
+                .collect()
+        });
+        (addr, handle)
+    }
+
+    struct Server {
+        broadcasts: AtomicUsize,
+    }
+
+    impl BotActions for Server {
+        fn online(&self) -> usize {
+            12
+        }
+
+        fn broadcast(&self, _text: &str) -> usize {
+            self.broadcasts.fetch_add(1, Ordering::Relaxed);
+            12
+        }
+    }
+
+    #[test]
+    fn test_config() {
+        assert!(!DiscordConfig::from_toml("").unwrap().is_enabled());
+
+        let config = DiscordConfig::from_toml(
+            r#"
+            [[discord.webhooks]]
+            url = "https://example.org/a"
+            events = ["server_start", "boss_killed"]
+
+            [[discord.webhooks]]
+            url = "https://example.org/b"
+
+            [discord.bot]
+            token = "t"
+            channel_id = "1"
+            "#,
+        )
+        .unwrap();
+        assert!(config.is_enabled());
+        assert!(config.webhooks[0].wants(EventKind::BossKilled));
+        assert!(!config.webhooks[0].wants(EventKind::Registration));
+        assert!(config.webhooks[1].wants(EventKind::Registration));
+        assert_eq!(config.bot.unwrap().poll_secs, 5);
+
+        assert!(
+            DiscordConfig::from_toml("[[discord.webhooks]]\nurl = \"u\"\nevents = [\"x\"]")
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_parse_commands() {
+        assert_eq!(BotCommand::parse("!online"), Some(BotCommand::Online));
+        assert_eq!(
+            BotCommand::parse(" !broadcast  Maintenance at 5 "),
+            Some(BotCommand::Broadcast("Maintenance at 5".to_string()))
+        );
+        assert_eq!(BotCommand::parse("!broadcast"), None);
+        assert_eq!(BotCommand::parse("online"), None);
+        assert_eq!(BotCommand::parse("!kick someone"), None);
+    }
+
+    #[tokio::test]
+    async fn test_notify_posts_to_subscribed_webhooks() {
+        let (addr, requests) = serve(1, "");
+        let discord = Discord::new(DiscordConfig {
+            webhooks: vec![
+                WebhookConfig {
+                    url: format!("{}/hook", addr),
+                    events: Some(vec![EventKind::BossKilled]),
+                },
+                WebhookConfig {
+                    url: "http://127.0.0.1:1/unused".to_string(),
+                    events: Some(vec![EventKind::ServerStart]),
+                },
+            ],
+            ..DiscordConfig::default()
+        });
+
+        let event = ServerEvent::BossKilled {
+            boss: "Baphomet".to_string(),
+            killers: vec!["alice".to_string(), "bob".to_string()],
+        };
+        assert_eq!(discord.notify(&event).await, 1);
+        let requests = requests.join().unwrap();
+        assert!(requests[0].starts_with("POST /hook"));
+        assert!(requests[0].contains("Baphomet was defeated by alice, bob"));
+    }
+
+    #[tokio::test]
+    async fn test_bot_answers_new_commands() {
+        let (addr, requests) = serve(
+            3,
+            r#"[{"id": "3", "content": "!broadcast hi", "author": {"id": "9"}},
+                {"id": "2", "content": "!online", "author": {"id": "8"}}]"#,
+        );
+        let discord = Discord::new(DiscordConfig {
+            bot: Some(BotConfig {
+                token: "token".to_string(),
+                channel_id: "100".to_string(),
+                admins: vec!["9".to_string()],
+                poll_secs: 1,
+            }),
+            api_base: addr,
+            ..DiscordConfig::default()
+        });
+        let server = Arc::new(Server {
+            broadcasts: AtomicUsize::new(0),
+        });
+
+        let newest = tokio::task::spawn_blocking({
+            let (discord, server) = (Arc::clone(&discord), Arc::clone(&server));
+            move || {
+                discord
+                    .poll(Some("1".to_string()), server.as_ref())
+                    .unwrap()
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(newest.as_deref(), Some("3"));
+        assert_eq!(server.broadcasts.load(Ordering::Relaxed), 1);
+
+        let requests = requests.join().unwrap();
+        assert!(requests[0].starts_with("GET /channels/100/messages?after=1"));
+        assert!(requests[0].contains("Bot token"));
+        // Oldest first
+        assert!(requests[1].contains("12 players online"));
+        assert!(requests[2].contains("Sent to 12 players"));
+    }
+
+    #[test]
+    fn test_only_admins_broadcast() {
+        let discord = Discord::new(DiscordConfig {
+            bot: Some(BotConfig {
+                token: "token".to_string(),
+                channel_id: "100".to_string(),
+                admins: vec!["9".to_string()],
+                poll_secs: 1,
+            }),
+            ..DiscordConfig::default()
+        });
+        let server = Server {
+            broadcasts: AtomicUsize::new(0),
+        };
+        let message = |author: &str, bot| ChannelMessage {
+            id: "1".to_string(),
+            content: "!broadcast hi".to_string(),
+            author: Author {
+                id: author.to_string(),
+                bot,
+            },
+        };
+
+        assert_eq!(
+            discord.answer(&message("8", false), &server).as_deref(),
+            Some("Only admins can broadcast")
+        );
+        assert_eq!(discord.answer(&message("9", true), &server), None);
+        assert_eq!(server.broadcasts.load(Ordering::Relaxed), 0);
+    }
+}
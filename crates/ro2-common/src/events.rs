@@ -0,0 +1,288 @@
+//! Gameplay and system events
+//!
+//! Subsystems [`publish`](EventBus::publish) what happened on an
+//! [`EventBus`] and anything interested (quests, Khara challenges, the
+//! audit log, Discord, the GM dashboard) [`subscribe`](EventBus::subscribe)s
+//! to the event types it cares about, so the two never need to know about
+//! each other. Each event type is its own channel; publishing one nobody
+//! listens to costs a map lookup.
+//!
+//! Subscribers that fall more than [`EVENT_CAPACITY`] events behind miss
+//! the oldest ones rather than hold up publishers.
+
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock, Weak};
+use tokio::sync::broadcast;
+use tracing::warn;
+
+/// Events a subscriber can fall behind by before missing some
+pub const EVENT_CAPACITY: usize = 256;
+
+/// Something that can be sent over an [`EventBus`]
+pub trait Event: Clone + Send + Sync + 'static {}
+
+/// An account finished logging in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PlayerLoggedIn {
+    pub account_id: u32,
+    pub session_id: u64,
+}
+
+/// A character killed a monster
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MonsterKilled {
+    pub character_id: u32,
+    pub monster_id: u32,
+    pub zone: u32,
+}
+
+/// Items changed hands in a trade
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ItemTraded {
+    pub from_character: u32,
+    pub to_character: u32,
+    pub item_id: u32,
+    pub quantity: u32,
+}
+
+/// A character reached a new level
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LevelUp {
+    pub character_id: u32,
+    pub level: u32,
+}
+
+/// Something a player said in chat
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChatMessage {
+    pub session_id: u64,
+    pub zone: u32,
+    /// `say`, `shout`, `party`, `guild` or `whisper`
+    pub channel: String,
+    pub from: String,
+    pub text: String,
+}
+
+/// How many players and monsters a zone had at its last tick
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ZonePopulation {
+    pub zone: u32,
+    pub players: usize,
+    pub monsters: usize,
+    /// Messages the zone sent its clients per second, lately
+    pub messages_per_sec: u32,
+}
+
+/// A client did something the server refused as cheating, e.g. moving
+/// faster than it can
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Violation {
+    pub session_id: u64,
+    pub account_id: Option<u32>,
+    /// Short machine-readable name, e.g. `speed`
+    pub kind: String,
+    pub detail: String,
+}
+
+/// A step towards a scheduled restart (see [`crate::maintenance`])
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Maintenance {
+    /// The server restarts in `secs`
+    Warning { secs: u64 },
+    /// New players are turned away from here on
+    LoginsClosed,
+    /// Save everything and exit
+    Restart,
+}
+
+/// A character's way through the first-login tutorial
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tutorial {
+    /// Spawned into the tutorial map for the first time
+    Started { character_id: u32 },
+    /// Did what step `step` (its ID) asked
+    StepCompleted { character_id: u32, step: u32 },
+    /// Left the tutorial, having finished it or not
+    Finished { character_id: u32, skipped: bool },
+}
+
+/// Server-wide happenings worth announcing outside the game
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ServerEvent {
+    ServerStarted { server: String },
+    ServerStopped { server: String },
+    BossKilled { boss: String, killers: Vec<String> },
+    PlayerBanned { name: String, by: String },
+    AccountRegistered { username: String },
+}
+
+impl Event for PlayerLoggedIn {}
+impl Event for MonsterKilled {}
+impl Event for ItemTraded {}
+impl Event for LevelUp {}
+impl Event for ServerEvent {}
+impl Event for ChatMessage {}
+impl Event for ZonePopulation {}
+impl Event for Violation {}
+impl Event for Maintenance {}
+impl Event for Tutorial {}
+
+/// Typed publish/subscribe between subsystems
+///
+/// Cheap to clone; every clone publishes to the same subscribers.
+#[derive(Clone, Default)]
+pub struct EventBus {
+    /// `broadcast::Sender<E>` by `TypeId` of `E`
+    channels: Arc<RwLock<HashMap<TypeId, Box<dyn Any + Send + Sync>>>>,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Send `event` to every subscriber of its type; returns how many
+    /// there were
+    pub fn publish<E: Event>(&self, event: E) -> usize {
+        let channels = self.channels.read().unwrap_or_else(|e| e.into_inner());
+        channels
+            .get(&TypeId::of::<E>())
+            .and_then(|sender| sender.downcast_ref::<broadcast::Sender<E>>())
+            .and_then(|sender| sender.send(event).ok())
+            .unwrap_or(0)
+    }
+
+    /// Receive every `E` published from now on
+    pub fn subscribe<E: Event>(&self) -> Subscription<E> {
+        let mut channels = self.channels.write().unwrap_or_else(|e| e.into_inner());
+        let sender = channels
+            .entry(TypeId::of::<E>())
+            .or_insert_with(|| Box::new(broadcast::Sender::<E>::new(EVENT_CAPACITY)))
+            .downcast_ref::<broadcast::Sender<E>>()
+            .expect("channels are keyed by their event type");
+        Subscription {
+            receiver: sender.subscribe(),
+        }
+    }
+
+    /// A handle that doesn't keep the bus open, for tasks that run until
+    /// the server stops
+    pub fn downgrade(&self) -> WeakEventBus {
+        WeakEventBus {
+            channels: Arc::downgrade(&self.channels),
+        }
+    }
+
+    /// Subscribers to `E`
+    pub fn subscribers<E: Event>(&self) -> usize {
+        let channels = self.channels.read().unwrap_or_else(|e| e.into_inner());
+        channels
+            .get(&TypeId::of::<E>())
+            .and_then(|sender| sender.downcast_ref::<broadcast::Sender<E>>())
+            .map_or(0, broadcast::Sender::receiver_count)
+    }
+}
+
+/// An [`EventBus`] that closes once every strong clone is dropped
+#[derive(Clone, Default)]
+pub struct WeakEventBus {
+    channels: Weak<RwLock<HashMap<TypeId, Box<dyn Any + Send + Sync>>>>,
+}
+
+impl WeakEventBus {
+    /// The bus, unless it has closed
+    pub fn upgrade(&self) -> Option<EventBus> {
+        Some(EventBus {
+            channels: self.channels.upgrade()?,
+        })
+    }
+}
+
+/// Events of one type from an [`EventBus`]
+pub struct Subscription<E> {
+    receiver: broadcast::Receiver<E>,
+}
+
+impl<E: Event> Subscription<E> {
+    /// The next event; `None` once every clone of the bus is gone
+    pub async fn recv(&mut self) -> Option<E> {
+        loop {
+            match self.receiver.recv().await {
+                Ok(event) => return Some(event),
+                Err(broadcast::error::RecvError::Lagged(missed)) => {
+                    warn!(
+                        "{} subscriber fell behind, missed {} events",
+                        std::any::type_name::<E>(),
+                        missed
+                    );
+                }
+                Err(broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    }
+
+    /// The next event if one is waiting
+    pub fn try_recv(&mut self) -> Option<E> {
+        loop {
+            match self.receiver.try_recv() {
+                Ok(event) => return Some(event),
+                Err(broadcast::error::TryRecvError::Lagged(_)) => continue,
+                Err(_) => return None,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_subscribers_get_their_event_types() {
+        let bus = EventBus::new();
+        assert_eq!(
+            bus.publish(LevelUp {
+                character_id: 1,
+                level: 2
+            }),
+            0
+        );
+
+        let mut levels = bus.subscribe::<LevelUp>();
+        let mut kills = bus.subscribe::<MonsterKilled>();
+        let mut more_levels = bus.clone().subscribe::<LevelUp>();
+        assert_eq!(bus.subscribers::<LevelUp>(), 2);
+
+        let level_up = LevelUp {
+            character_id: 1,
+            level: 3,
+        };
+        assert_eq!(bus.publish(level_up), 2);
+        assert_eq!(levels.recv().await, Some(level_up));
+        assert_eq!(more_levels.try_recv(), Some(level_up));
+        assert_eq!(kills.try_recv(), None);
+
+        drop(more_levels);
+        assert_eq!(bus.subscribers::<LevelUp>(), 1);
+        let weak = bus.downgrade();
+        assert!(weak.upgrade().is_some());
+        drop(bus);
+        assert_eq!(levels.recv().await, None);
+        assert!(weak.upgrade().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_lagging_subscriber_skips_ahead() {
+        let bus = EventBus::new();
+        let mut levels = bus.subscribe::<LevelUp>();
+        for level in 0..EVENT_CAPACITY as u32 + 10 {
+            bus.publish(LevelUp {
+                character_id: 1,
+                level,
+            });
+        }
+
+        assert_eq!(levels.recv().await.unwrap().level, 10);
+    }
+}
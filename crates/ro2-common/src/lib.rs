@@ -6,10 +6,35 @@
 //! - Cryptography (AES/RSA)
 //! - Database models
 
+pub mod clock;
+#[cfg(feature = "server")]
+pub mod config;
+#[cfg(feature = "server")]
+pub mod console;
 pub mod crypto;
+#[cfg(feature = "server")]
+pub mod dashboard;
 pub mod database;
+#[cfg(feature = "discord")]
+pub mod discord;
+pub mod events;
+pub mod localization;
+#[cfg(feature = "server")]
+pub mod logging;
+#[cfg(feature = "server")]
+pub mod maintenance;
+pub mod net;
 pub mod packet;
+pub mod playtime;
+#[cfg(feature = "server")]
+pub mod plugin;
 pub mod protocol;
+#[cfg(all(feature = "server", feature = "client"))]
+pub mod selftest;
+pub mod session;
+#[cfg(any(test, feature = "test-support"))]
+pub mod testing;
+pub mod wire;
 
 pub use packet::{NetworkPacket, PacketBuffer, PacketHeader};
 pub use protocol::MessageType;
@@ -0,0 +1,231 @@
+//! Localized text sent by the servers
+//!
+//! The server side of the client's `LocalizationManager`: display text is
+//! looked up by key in a string table per language instead of being
+//! hardcoded. Tables are `locale/<language>.toml` files whose sections
+//! are flattened into dotted keys:
+//!
+//! ```toml
+//! [playtime]
+//! ends_in_minutes = "Your playtime ends in {minutes} minutes."
+//! ```
+//!
+//! is the key `playtime.ends_in_minutes`. A key missing from a language
+//! falls back to the default language, then to the key itself, so a gap
+//! in a translation shows up in game rather than as an error.
+//!
+//! Each account picks its language (`accounts.language`); accounts that
+//! haven't get the default.
+
+use crate::Result;
+use anyhow::{Context, anyhow};
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::path::Path;
+
+/// Where the servers look for string tables
+pub const DEFAULT_LOCALE_DIR: &str = "locale";
+
+/// Language used when an account hasn't picked one, and for missing keys
+pub const DEFAULT_LANGUAGE: &str = "en";
+
+/// The English table, built in so a server without `locale/` still has text
+const BUILTIN_ENGLISH: &str = include_str!("../../../locale/en.toml");
+
+/// String tables for every loaded language
+#[derive(Debug, Clone)]
+pub struct Localization {
+    default_language: String,
+    tables: HashMap<String, HashMap<String, String>>,
+}
+
+impl Default for Localization {
+    fn default() -> Self {
+        Self::builtin()
+    }
+}
+
+impl Localization {
+    /// No tables at all; every lookup returns its key
+    pub fn new(default_language: impl Into<String>) -> Self {
+        Self {
+            default_language: default_language.into(),
+            tables: HashMap::new(),
+        }
+    }
+
+    /// Just the built-in English table
+    pub fn builtin() -> Self {
+        let mut localization = Self::new(DEFAULT_LANGUAGE);
+        localization
+            .add_table(DEFAULT_LANGUAGE, BUILTIN_ENGLISH)
+            .expect("built-in string table is valid");
+        localization
+    }
+
+    /// The built-in table overlaid with every `<language>.toml` in `dir`;
+    /// a missing directory is fine
+    pub fn load_dir(dir: impl AsRef<Path>) -> Result<Self> {
+        let dir = dir.as_ref();
+        let mut localization = Self::builtin();
+        if !dir.is_dir() {
+            return Ok(localization);
+        }
+
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().is_none_or(|ext| ext != "toml") {
+                continue;
+            }
+            let Some(language) = path.file_stem().and_then(|stem| stem.to_str()) else {
+                continue;
+            };
+            let text = std::fs::read_to_string(&path)?;
+            localization
+                .add_table(language, &text)
+                .with_context(|| format!("loading {}", path.display()))?;
+        }
+        Ok(localization)
+    }
+
+    /// Add the strings in TOML `text` to `language`, replacing any with
+    /// the same key
+    pub fn add_table(&mut self, language: &str, text: &str) -> Result<()> {
+        let table: toml::Table = text.parse()?;
+        let strings = self.tables.entry(language.to_string()).or_default();
+        flatten("", &table, strings)
+    }
+
+    pub fn default_language(&self) -> &str {
+        &self.default_language
+    }
+
+    /// Languages with a string table, sorted
+    pub fn languages(&self) -> Vec<&str> {
+        let mut languages: Vec<_> = self.tables.keys().map(String::as_str).collect();
+        languages.sort_unstable();
+        languages
+    }
+
+    /// `language` if it has a table, otherwise the default; `None` (no
+    /// preference) is the default too
+    pub fn resolve<'a>(&'a self, language: Option<&'a str>) -> &'a str {
+        match language {
+            Some(language) if self.tables.contains_key(language) => language,
+            _ => &self.default_language,
+        }
+    }
+
+    /// The text for `key` in `language`
+    pub fn get<'a>(&'a self, language: &str, key: &'a str) -> &'a str {
+        [language, self.default_language.as_str()]
+            .into_iter()
+            .find_map(|language| self.tables.get(language)?.get(key))
+            .map_or(key, String::as_str)
+    }
+
+    /// The text for `key` in `language` with each `{name}` replaced by its
+    /// argument
+    pub fn format(&self, language: &str, key: &str, args: &[(&str, &dyn Display)]) -> String {
+        let mut text = self.get(language, key).to_string();
+        for (name, value) in args {
+            text = text.replace(&format!("{{{}}}", name), &value.to_string());
+        }
+        text
+    }
+}
+
+/// Add every string in `table` to `strings`, keyed by its dotted path
+fn flatten(prefix: &str, table: &toml::Table, strings: &mut HashMap<String, String>) -> Result<()> {
+    for (name, value) in table {
+        let key = if prefix.is_empty() {
+            name.clone()
+        } else {
+            format!("{}.{}", prefix, name)
+        };
+        match value {
+            toml::Value::String(text) => {
+                strings.insert(key, text.clone());
+            }
+            toml::Value::Table(table) => flatten(&key, table, strings)?,
+            _ => return Err(anyhow!("{}: expected a string or a table", key)),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lookup_and_fallback() {
+        let mut localization = Localization::builtin();
+        localization
+            .add_table(
+                "ko",
+                r#"
+                [playtime]
+                ends_in_minutes = "플레이 시간이 {minutes}분 남았습니다."
+                "#,
+            )
+            .unwrap();
+
+        assert_eq!(localization.languages(), ["en", "ko"]);
+        assert_eq!(
+            localization.format("ko", "playtime.ends_in_minutes", &[("minutes", &5)]),
+            "플레이 시간이 5분 남았습니다."
+        );
+        // Untranslated keys come from English, unknown ones are the key
+        assert_eq!(
+            localization.get("ko", "playtime.daily_cap_reached"),
+            "You have reached your playtime limit for today."
+        );
+        assert_eq!(localization.get("ko", "no.such.key"), "no.such.key");
+
+        assert_eq!(localization.resolve(Some("ko")), "ko");
+        assert_eq!(localization.resolve(Some("fr")), "en");
+        assert_eq!(localization.resolve(None), "en");
+    }
+
+    #[test]
+    fn test_load_dir() {
+        let dir = std::env::temp_dir().join(format!("ro2-locale-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("de.toml"), "greeting = \"Hallo\"").unwrap();
+        std::fs::write(dir.join("README.txt"), "not a table").unwrap();
+
+        let localization = Localization::load_dir(&dir).unwrap();
+        assert_eq!(localization.languages(), ["de", "en"]);
+        assert_eq!(localization.get("de", "greeting"), "Hallo");
+
+        std::fs::write(dir.join("bad.toml"), "count = 3").unwrap();
+        assert!(Localization::load_dir(&dir).is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(
+            Localization::load_dir(&dir).unwrap().languages(),
+            [DEFAULT_LANGUAGE]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_account_language() {
+        use crate::database::queries::AccountQueries;
+
+        let pool = crate::testing::database().await;
+
+        let localization = Localization::builtin();
+        let language = AccountQueries::language(&pool, 2).await.unwrap();
+        assert_eq!(language, None);
+        assert_eq!(localization.resolve(language.as_deref()), "en");
+
+        AccountQueries::set_language(&pool, 2, Some("ko"))
+            .await
+            .unwrap();
+        assert_eq!(
+            AccountQueries::language(&pool, 2).await.unwrap().as_deref(),
+            Some("ko")
+        );
+    }
+}
@@ -0,0 +1,435 @@
+//! Log outputs
+//!
+//! Each server binary sets up logging from the `[logging]` section of its
+//! config. By default it logs text to stdout like before; production
+//! deployments can switch to JSON, add daily rolling files, and send to
+//! syslog or journald:
+//!
+//! ```toml
+//! [logging]
+//! level = "info"     # filter when RUST_LOG isn't set
+//! format = "json"    # "text" (default) or "json", for stdout and files
+//! stdout = true
+//! syslog = false     # /dev/log, facility daemon
+//! journald = false
+//!
+//! [logging.file]
+//! dir = "logs"
+//! prefix = "login"   # defaults to the server's name
+//! rotation = "daily" # "hourly", "daily" or "never"
+//! ```
+//!
+//! Builds with the `profiling` feature can also record span timings as
+//! folded stacks for flame graphs, whatever `level` says (see
+//! `docs/PROFILING.md`):
+//!
+//! ```toml
+//! [logging]
+//! flame = "profile/login.folded"
+//! ```
+
+use crate::Result;
+use crate::config::{self, Validate};
+use anyhow::Context;
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{EnvFilter, Layer, Registry};
+
+/// How log lines are written
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Text,
+    /// One JSON object per event, with its spans' fields
+    Json,
+}
+
+/// When a log file is closed and a new one started
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogRotation {
+    Hourly,
+    #[default]
+    Daily,
+    Never,
+}
+
+/// Rolling log files
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct FileLogConfig {
+    pub dir: PathBuf,
+    /// File name prefix; the server's name if unset
+    #[serde(default)]
+    pub prefix: Option<String>,
+    #[serde(default)]
+    pub rotation: LogRotation,
+}
+
+/// Logging settings
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct LoggingConfig {
+    /// Filter directives, used when `RUST_LOG` isn't set
+    pub level: String,
+    pub format: LogFormat,
+    pub stdout: bool,
+    pub file: Option<FileLogConfig>,
+    pub syslog: bool,
+    pub journald: bool,
+    /// Folded span timings, with the `profiling` feature
+    pub flame: Option<PathBuf>,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            level: "info".to_string(),
+            format: LogFormat::Text,
+            stdout: true,
+            file: None,
+            syslog: false,
+            journald: false,
+            flame: None,
+        }
+    }
+}
+
+/// Keeps background log writers flushing; drop it last
+#[must_use = "logs written in the background are lost once this is dropped"]
+pub struct LogGuard {
+    _workers: Vec<WorkerGuard>,
+    #[cfg(feature = "profiling")]
+    _flame: Option<tracing_flame::FlushGuard<std::io::BufWriter<std::fs::File>>>,
+}
+
+type BoxedLayer = Box<dyn Layer<Registry> + Send + Sync>;
+
+impl LoggingConfig {
+    /// Read the `[logging]` section of `path`
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        config::section(path, "logging")
+    }
+
+    /// Parse the `[logging]` section of TOML text
+    pub fn from_toml(text: &str) -> Result<Self> {
+        config::section_from_toml(text, "logging")
+    }
+
+    /// Install these outputs as the global subscriber for `server`
+    pub fn init(&self, server: &str) -> Result<LogGuard> {
+        let filter = match EnvFilter::try_from_default_env() {
+            Ok(filter) => filter,
+            Err(_) => EnvFilter::try_new(&self.level)
+                .with_context(|| format!("bad logging level {:?}", self.level))?,
+        };
+        let (layers, guard) = self.layers(server)?;
+        let (flame, guard) = self.flame_layer(guard)?;
+        let mut layers = vec![layers.with_filter(filter).boxed()];
+        layers.extend(flame);
+        tracing_subscriber::registry().with(layers).try_init()?;
+        #[cfg(not(feature = "profiling"))]
+        if let Some(path) = &self.flame {
+            tracing::warn!(
+                "Not writing span timings to {}: built without the profiling feature",
+                path.display()
+            );
+        }
+        Ok(guard)
+    }
+
+    /// The configured outputs, unfiltered
+    fn layers(&self, server: &str) -> Result<(Vec<BoxedLayer>, LogGuard)> {
+        let mut layers = Vec::new();
+        let mut workers = Vec::new();
+
+        if self.stdout {
+            let (writer, guard) = tracing_appender::non_blocking(std::io::stdout());
+            workers.push(guard);
+            layers.push(self.fmt_layer(writer, true));
+        }
+        if let Some(file) = &self.file {
+            let rotation = match file.rotation {
+                LogRotation::Hourly => Rotation::HOURLY,
+                LogRotation::Daily => Rotation::DAILY,
+                LogRotation::Never => Rotation::NEVER,
+            };
+            let appender = RollingFileAppender::builder()
+                .rotation(rotation)
+                .filename_prefix(file.prefix.as_deref().unwrap_or(server))
+                .filename_suffix("log")
+                .build(&file.dir)
+                .with_context(|| format!("opening log files in {}", file.dir.display()))?;
+            let (writer, guard) = tracing_appender::non_blocking(appender);
+            workers.push(guard);
+            layers.push(self.fmt_layer(writer, false));
+        }
+        if self.syslog {
+            layers.push(syslog::layer(server)?);
+        }
+        if self.journald {
+            layers.push(journald_layer(server)?);
+        }
+
+        let guard = LogGuard {
+            _workers: workers,
+            #[cfg(feature = "profiling")]
+            _flame: None,
+        };
+        Ok((layers, guard))
+    }
+
+    /// Every span in our crates, down to trace, timed into `flame`;
+    /// unfiltered by `level`
+    #[cfg(feature = "profiling")]
+    fn flame_layer(&self, mut guard: LogGuard) -> Result<(Option<BoxedLayer>, LogGuard)> {
+        let Some(path) = &self.flame else {
+            return Ok((None, guard));
+        };
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let (layer, flush) = tracing_flame::FlameLayer::with_file(path)
+            .with_context(|| format!("opening {} for span timings", path.display()))?;
+        guard._flame = Some(flush);
+        let layer = layer
+            .with_threads_collapsed(true)
+            .with_filter(EnvFilter::new("ro2=trace"))
+            .boxed();
+        Ok((Some(layer), guard))
+    }
+
+    #[cfg(not(feature = "profiling"))]
+    fn flame_layer(&self, guard: LogGuard) -> Result<(Option<BoxedLayer>, LogGuard)> {
+        Ok((None, guard))
+    }
+
+    fn fmt_layer<W>(&self, writer: W, ansi: bool) -> BoxedLayer
+    where
+        W: for<'w> tracing_subscriber::fmt::MakeWriter<'w> + Send + Sync + 'static,
+    {
+        let layer = tracing_subscriber::fmt::layer().with_writer(writer);
+        match self.format {
+            LogFormat::Text => layer.with_ansi(ansi).boxed(),
+            LogFormat::Json => layer
+                .json()
+                .with_current_span(true)
+                .with_span_list(true)
+                .boxed(),
+        }
+    }
+}
+
+impl Validate for LoggingConfig {}
+
+#[cfg(target_os = "linux")]
+fn journald_layer(server: &str) -> Result<BoxedLayer> {
+    Ok(tracing_journald::layer()
+        .context("connecting to journald")?
+        .with_syslog_identifier(server.to_string())
+        .boxed())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn journald_layer(_server: &str) -> Result<BoxedLayer> {
+    Err(anyhow::anyhow!(
+        "journald logging is only available on Linux"
+    ))
+}
+
+/// Syslog over the local socket, one datagram per event
+#[cfg(unix)]
+mod syslog {
+    use super::BoxedLayer;
+    use crate::Result;
+    use anyhow::Context;
+    use std::io;
+    use std::os::unix::net::UnixDatagram;
+    use std::path::Path;
+    use std::sync::Arc;
+    use tracing::{Level, Metadata};
+    use tracing_subscriber::Layer;
+    use tracing_subscriber::fmt::MakeWriter;
+
+    const SYSLOG_SOCKET: &str = "/dev/log";
+
+    /// Facility "daemon"
+    const FACILITY: u8 = 3;
+
+    pub(super) fn layer(server: &str) -> Result<BoxedLayer> {
+        Ok(fmt_layer(SyslogWriter::connect(SYSLOG_SOCKET, server)?))
+    }
+
+    /// Syslog stamps the time and carries the level in the priority
+    pub(super) fn fmt_layer(writer: SyslogWriter) -> BoxedLayer {
+        tracing_subscriber::fmt::layer()
+            .with_writer(writer)
+            .with_ansi(false)
+            .without_time()
+            .with_level(false)
+            .boxed()
+    }
+
+    pub(super) struct SyslogWriter {
+        socket: Arc<UnixDatagram>,
+        ident: String,
+    }
+
+    impl SyslogWriter {
+        pub(super) fn connect(path: impl AsRef<Path>, server: &str) -> Result<Self> {
+            let path = path.as_ref();
+            let socket = UnixDatagram::unbound()?;
+            socket
+                .connect(path)
+                .with_context(|| format!("connecting to syslog at {}", path.display()))?;
+            Ok(Self {
+                socket: Arc::new(socket),
+                ident: format!("{}[{}]", server, std::process::id()),
+            })
+        }
+
+        fn line(&self, level: Level) -> SyslogLine {
+            let severity = match level {
+                Level::ERROR => 3,
+                Level::WARN => 4,
+                Level::INFO => 6,
+                _ => 7,
+            };
+            let header = format!("<{}>{}: ", FACILITY * 8 + severity, self.ident);
+            SyslogLine {
+                socket: Arc::clone(&self.socket),
+                buffer: header.into_bytes(),
+            }
+        }
+    }
+
+    impl<'a> MakeWriter<'a> for SyslogWriter {
+        type Writer = SyslogLine;
+
+        fn make_writer(&'a self) -> SyslogLine {
+            self.line(Level::INFO)
+        }
+
+        fn make_writer_for(&'a self, meta: &Metadata<'_>) -> SyslogLine {
+            self.line(*meta.level())
+        }
+    }
+
+    /// One event, sent when dropped
+    pub(super) struct SyslogLine {
+        socket: Arc<UnixDatagram>,
+        buffer: Vec<u8>,
+    }
+
+    impl io::Write for SyslogLine {
+        fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+            self.buffer.extend_from_slice(data);
+            Ok(data.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Drop for SyslogLine {
+        fn drop(&mut self) {
+            while self.buffer.last() == Some(&b'\n') {
+                self.buffer.pop();
+            }
+            // Nowhere to report a logging failure
+            let _ = self.socket.send(&self.buffer);
+        }
+    }
+}
+
+#[cfg(not(unix))]
+mod syslog {
+    use super::BoxedLayer;
+    use crate::Result;
+    use anyhow::anyhow;
+
+    pub(super) fn layer(_server: &str) -> Result<BoxedLayer> {
+        Err(anyhow!("syslog logging is only available on Unix"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tracing::info;
+
+    #[test]
+    fn test_config() {
+        assert_eq!(
+            LoggingConfig::from_toml("").unwrap(),
+            LoggingConfig::default()
+        );
+
+        let config = LoggingConfig::from_toml(
+            "[logging]\nformat = \"json\"\nstdout = false\n[logging.file]\ndir = \"logs\"",
+        )
+        .unwrap();
+        assert_eq!(config.format, LogFormat::Json);
+        assert!(!config.stdout);
+        let file = config.file.unwrap();
+        assert_eq!((file.prefix, file.rotation), (None, LogRotation::Daily));
+
+        assert!(LoggingConfig::from_toml("[logging]\nformat = \"xml\"").is_err());
+    }
+
+    #[test]
+    fn test_json_file_output() {
+        let dir = std::env::temp_dir().join(format!("ro2-logging-{}", std::process::id()));
+        let config = LoggingConfig {
+            format: LogFormat::Json,
+            stdout: false,
+            file: Some(FileLogConfig {
+                dir: dir.clone(),
+                prefix: None,
+                rotation: LogRotation::Never,
+            }),
+            ..LoggingConfig::default()
+        };
+
+        let (layers, guard) = config.layers("test").unwrap();
+        let subscriber = tracing_subscriber::registry().with(layers);
+        tracing::subscriber::with_default(subscriber, || {
+            let _span = tracing::info_span!("connection", session_id = 7).entered();
+            info!(account_id = 3, "Logged in");
+        });
+        drop(guard);
+
+        let text = std::fs::read_to_string(dir.join("test.log")).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        let line: serde_json::Value = serde_json::from_str(text.trim()).unwrap();
+        assert_eq!(line["fields"]["message"], "Logged in");
+        assert_eq!(line["fields"]["account_id"], 3);
+        assert_eq!(line["span"]["session_id"], 7);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_syslog_lines() {
+        use std::os::unix::net::UnixDatagram;
+
+        let path = std::env::temp_dir().join(format!("ro2-syslog-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let receiver = UnixDatagram::bind(&path).unwrap();
+        let writer = syslog::SyslogWriter::connect(&path, "world").unwrap();
+
+        let subscriber = tracing_subscriber::registry().with(syslog::fmt_layer(writer));
+        tracing::subscriber::with_default(subscriber, || tracing::warn!("Zone tick overran"));
+
+        let mut buffer = [0u8; 256];
+        let len = receiver.recv(&mut buffer).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let line = std::str::from_utf8(&buffer[..len]).unwrap();
+        assert!(line.starts_with(&format!("<28>world[{}]: ", std::process::id())));
+        assert!(line.ends_with("Zone tick overran"));
+    }
+}
@@ -0,0 +1,306 @@
+//! Scheduled maintenance restarts
+//!
+//! At the times set in `config/maintenance.toml` the servers warn players,
+//! stop letting new ones in, save what they keep and exit, for their
+//! supervisor (systemd, a container restart policy) to start them again:
+//!
+//! ```toml
+//! restarts = ["05:00", "17:00"]          # Local times, every day; off when empty
+//! warnings = [1800, 600, 300, 60, 10]    # Seconds before a restart to warn players
+//! close_logins_secs = 300                # Refuse new logins this long before
+//! ```
+//!
+//! The login, lobby and world servers don't talk to each other, so each
+//! reads the same file and runs its own [`MaintenanceScheduler`]; with
+//! their clocks in step they go down together. Inside a server the
+//! scheduler publishes each step on the [`EventBus`] as a [`Maintenance`]
+//! event, for whatever has to act on it:
+//!
+//! - [`Maintenance::Warning`]: the world server tells everyone in it
+//! - [`Maintenance::LoginsClosed`]: [`MaintenanceGate::admit`] starts
+//!   refusing, so the login server turns away ReqLogin and the world new
+//!   connections
+//! - [`Maintenance::Restart`]: each server saves its state and exits
+//!
+//! A server started after some of the warnings were due skips them, but
+//! still closes logins straight away if the restart is that close.
+
+use crate::Result;
+use crate::clock::Clock;
+use crate::config::{self, Validate};
+use crate::events::{EventBus, Maintenance};
+use crate::protocol::{ClientError, ErrorCode};
+use anyhow::anyhow;
+use chrono::{Days, NaiveDateTime, NaiveTime};
+use serde::Deserialize;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+/// Where every server reads the restart schedule
+pub const MAINTENANCE_PATH: &str = "config/maintenance.toml";
+
+/// Restart schedule
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct MaintenanceConfig {
+    /// Local times of day, `HH:MM`
+    pub restarts: Vec<String>,
+    /// Seconds before a restart
+    pub warnings: Vec<u64>,
+    pub close_logins_secs: u64,
+}
+
+impl Default for MaintenanceConfig {
+    fn default() -> Self {
+        Self {
+            restarts: Vec::new(),
+            warnings: vec![1800, 600, 300, 60, 10],
+            close_logins_secs: 300,
+        }
+    }
+}
+
+impl MaintenanceConfig {
+    /// Read `path`; a missing file means no scheduled restarts
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        config::load(path)
+    }
+
+    /// Parse TOML text
+    pub fn from_toml(text: &str) -> Result<Self> {
+        config::from_toml(text)
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.restarts.is_empty()
+    }
+
+    fn times(&self) -> Result<Vec<NaiveTime>> {
+        self.restarts
+            .iter()
+            .map(|time| {
+                NaiveTime::parse_from_str(time, "%H:%M")
+                    .map_err(|_| anyhow!("maintenance restart {:?} isn't HH:MM", time))
+            })
+            .collect()
+    }
+
+    /// The first scheduled restart after `now`
+    pub fn next_restart(&self, now: NaiveDateTime) -> Option<NaiveDateTime> {
+        let times = self.times().ok()?;
+        [now.date(), now.date() + Days::new(1)]
+            .into_iter()
+            .flat_map(|day| times.iter().map(move |&time| day.and_time(time)))
+            .filter(|&restart| restart > now)
+            .min()
+    }
+
+    /// What happens before and at `restart`, in order, leaving out
+    /// warnings already past at `now`
+    pub fn steps(
+        &self,
+        restart: NaiveDateTime,
+        now: NaiveDateTime,
+    ) -> Vec<(NaiveDateTime, Maintenance)> {
+        let before = |secs: u64| restart - chrono::Duration::seconds(secs as i64);
+        let mut steps: Vec<_> = self
+            .warnings
+            .iter()
+            .map(|&secs| (before(secs), Maintenance::Warning { secs }))
+            .filter(|&(at, _)| at >= now)
+            .collect();
+        steps.push((
+            before(self.close_logins_secs).max(now),
+            Maintenance::LoginsClosed,
+        ));
+        steps.push((restart, Maintenance::Restart));
+        // Logins close before a warning due at the same time
+        steps.sort_by_key(|&(at, step)| (at, step != Maintenance::LoginsClosed));
+        steps
+    }
+}
+
+impl Validate for MaintenanceConfig {
+    fn validate(&self) -> Result<()> {
+        self.times()?;
+        Ok(())
+    }
+}
+
+/// Whether new players are let in, shared by a server's connections
+#[derive(Debug, Clone, Default)]
+pub struct MaintenanceGate {
+    closed: Arc<AtomicBool>,
+}
+
+impl MaintenanceGate {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::Relaxed)
+    }
+
+    pub fn close(&self) {
+        self.closed.store(true, Ordering::Relaxed);
+    }
+
+    /// Let a player in, unless a restart is coming; the error is a
+    /// [`ClientError`] telling them so
+    pub fn admit(&self) -> Result<()> {
+        if self.is_closed() {
+            return Err(ClientError::new(ErrorCode::Busy, "maintenance.closed").into());
+        }
+        Ok(())
+    }
+}
+
+/// Runs one server's side of the restart schedule
+pub struct MaintenanceScheduler {
+    config: MaintenanceConfig,
+    clock: Clock,
+    gate: MaintenanceGate,
+}
+
+impl MaintenanceScheduler {
+    pub fn new(config: MaintenanceConfig) -> Self {
+        Self {
+            config,
+            clock: Clock::system(),
+            gate: MaintenanceGate::new(),
+        }
+    }
+
+    pub fn with_clock(mut self, clock: Clock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Closed once logins close before the restart
+    pub fn gate(&self) -> MaintenanceGate {
+        self.gate.clone()
+    }
+
+    /// Publish each step of the next restart on `events` when it's due,
+    /// closing the gate along the way; the task ends after
+    /// [`Maintenance::Restart`], or straight away with nothing scheduled
+    pub fn spawn(self, events: &EventBus) -> JoinHandle<()> {
+        // Only a weak handle stays, so the bus still closes on shutdown
+        let events = events.downgrade();
+        tokio::spawn(async move {
+            let now = self.clock.local();
+            let Some(restart) = self.config.next_restart(now) else {
+                return;
+            };
+            info!("Next maintenance restart at {}", restart);
+            for (at, step) in self.config.steps(restart, now) {
+                if let Ok(wait) = (at - self.clock.local()).to_std() {
+                    tokio::time::sleep(wait).await;
+                }
+                if step == Maintenance::LoginsClosed {
+                    self.gate.close();
+                }
+                warn!("Maintenance: {:?}", step);
+                let Some(events) = events.upgrade() else {
+                    return;
+                };
+                events.publish(step);
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn at(hour: u32, minute: u32, second: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2024, 3, 1)
+            .unwrap()
+            .and_hms_opt(hour, minute, second)
+            .unwrap()
+    }
+
+    fn config() -> MaintenanceConfig {
+        MaintenanceConfig {
+            restarts: vec!["17:00".to_string(), "05:00".to_string()],
+            warnings: vec![600, 60],
+            close_logins_secs: 300,
+        }
+    }
+
+    #[test]
+    fn test_next_restart() {
+        let config = config();
+        assert_eq!(config.next_restart(at(4, 0, 0)), Some(at(5, 0, 0)));
+        assert_eq!(config.next_restart(at(5, 0, 0)), Some(at(17, 0, 0)));
+        assert_eq!(
+            config.next_restart(at(18, 0, 0)),
+            Some(at(5, 0, 0) + Days::new(1))
+        );
+        assert_eq!(MaintenanceConfig::default().next_restart(at(4, 0, 0)), None);
+    }
+
+    #[test]
+    fn test_steps() {
+        let config = config();
+        assert_eq!(
+            config.steps(at(5, 0, 0), at(4, 0, 0)),
+            [
+                (at(4, 50, 0), Maintenance::Warning { secs: 600 }),
+                (at(4, 55, 0), Maintenance::LoginsClosed),
+                (at(4, 59, 0), Maintenance::Warning { secs: 60 }),
+                (at(5, 0, 0), Maintenance::Restart),
+            ]
+        );
+
+        // Started two minutes before: no ten-minute warning, and logins
+        // close at once
+        assert_eq!(
+            config.steps(at(5, 0, 0), at(4, 58, 0)),
+            [
+                (at(4, 58, 0), Maintenance::LoginsClosed),
+                (at(4, 59, 0), Maintenance::Warning { secs: 60 }),
+                (at(5, 0, 0), Maintenance::Restart),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_scheduler_publishes_and_closes_the_gate() {
+        // Just before the restart, so the steps are all due at once
+        let clock = Clock::manual((at(4, 59, 59) + chrono::Duration::milliseconds(950)).and_utc());
+        let events = EventBus::new();
+        let mut steps = events.subscribe::<Maintenance>();
+        let scheduler = MaintenanceScheduler::new(config()).with_clock(clock);
+        let gate = scheduler.gate();
+        assert!(gate.admit().is_ok());
+
+        scheduler.spawn(&events).await.unwrap();
+        assert_eq!(steps.try_recv(), Some(Maintenance::LoginsClosed));
+        assert_eq!(steps.try_recv(), Some(Maintenance::Restart));
+        let error = gate.admit().unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<ClientError>().unwrap().code,
+            ErrorCode::Busy
+        );
+    }
+
+    #[test]
+    fn test_config() {
+        assert_eq!(
+            MaintenanceConfig::load("does/not/exist.toml").unwrap(),
+            MaintenanceConfig::default()
+        );
+        assert!(!MaintenanceConfig::default().is_enabled());
+        let config = MaintenanceConfig::from_toml("restarts = [\"05:30\"]").unwrap();
+        assert!(config.is_enabled());
+        assert_eq!(config.close_logins_secs, 300);
+        assert!(MaintenanceConfig::from_toml("restarts = [\"5 o'clock\"]").is_err());
+    }
+}
@@ -0,0 +1,247 @@
+//! Scripted clients for end-to-end and load tests
+//!
+//! A [`Script`] is what a bot does once connected: send game messages,
+//! wait for the server's answers, pause. [`Script::run`] plays it over a
+//! [`ProudNetClient`] and times each step, so a test can check a server
+//! answers a whole exchange and a load test can see where it slows down:
+//!
+//! ```ignore
+//! let script = Script::login("bot01", "secret").pause(Duration::from_secs(1));
+//! let mut client = ProudNetClient::connect(TcpStream::connect(addr).await?).await?;
+//! let run = script.run(&mut client).await?;
+//! ```
+//!
+//! Only login has a message to script so far. Character creation,
+//! entering the world, moving and attacking have no messages defined yet
+//! and the lobby and world servers don't answer any; scripts for them are
+//! built from [`Script::send`] and [`Script::expect`] once they do.
+
+use super::ProudNetClient;
+use crate::Result;
+use crate::protocol::opcodes::{ACK_LOGIN, REQ_LOGIN};
+use crate::wire::WireWriter;
+use anyhow::{Context, anyhow};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncWrite};
+
+/// How long a step waits for an answer unless told otherwise
+pub const DEFAULT_EXPECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Bytes of ReqLogin's payload; username and password are the first 64,
+/// as the login server reads them
+const REQ_LOGIN_LEN: usize = 209;
+const USERNAME_LEN: usize = 32;
+const PASSWORD_LEN: usize = 32;
+
+/// One thing a bot does
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Step {
+    /// Send a game message (opcode and payload)
+    Send(Vec<u8>),
+    /// Wait for a message with this opcode, skipping any other
+    Expect {
+        opcode: u16,
+        within: Duration,
+    },
+    Pause(Duration),
+}
+
+impl Step {
+    fn describe(&self) -> String {
+        match self {
+            Step::Send(message) if message.len() >= 2 => {
+                format!(
+                    "send 0x{:04x}",
+                    u16::from_le_bytes([message[0], message[1]])
+                )
+            }
+            Step::Send(_) => "send".to_string(),
+            Step::Expect { opcode, .. } => format!("expect 0x{:04x}", opcode),
+            Step::Pause(duration) => format!("pause {:?}", duration),
+        }
+    }
+}
+
+/// How one step went
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StepReport {
+    pub step: String,
+    pub elapsed: Duration,
+    /// The message an expect step got
+    pub reply: Option<Vec<u8>>,
+}
+
+/// A bot's steps, in order
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Script {
+    steps: Vec<Step>,
+}
+
+impl Script {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Log in with `username` and `password` and wait for the answer
+    pub fn login(username: &str, password: &str) -> Self {
+        let mut payload = WireWriter::new();
+        payload
+            .fixed_str(username, USERNAME_LEN)
+            .fixed_str(password, PASSWORD_LEN)
+            .bytes(&[0; REQ_LOGIN_LEN - USERNAME_LEN - PASSWORD_LEN]);
+        Self::new()
+            .send(REQ_LOGIN, &payload.into_bytes())
+            .expect(ACK_LOGIN)
+    }
+
+    pub fn send(mut self, opcode: u16, payload: &[u8]) -> Self {
+        let mut message = opcode.to_le_bytes().to_vec();
+        message.extend_from_slice(payload);
+        self.steps.push(Step::Send(message));
+        self
+    }
+
+    /// Wait up to [`DEFAULT_EXPECT_TIMEOUT`] for a message with `opcode`
+    pub fn expect(self, opcode: u16) -> Self {
+        self.expect_within(opcode, DEFAULT_EXPECT_TIMEOUT)
+    }
+
+    pub fn expect_within(mut self, opcode: u16, within: Duration) -> Self {
+        self.steps.push(Step::Expect { opcode, within });
+        self
+    }
+
+    pub fn pause(mut self, duration: Duration) -> Self {
+        self.steps.push(Step::Pause(duration));
+        self
+    }
+
+    /// This script, then `next`
+    pub fn then(mut self, next: Script) -> Self {
+        self.steps.extend(next.steps);
+        self
+    }
+
+    pub fn steps(&self) -> &[Step] {
+        &self.steps
+    }
+
+    /// Play the script over `client`, stopping at the first step that fails
+    pub async fn run<S: AsyncRead + AsyncWrite + Unpin>(
+        &self,
+        client: &mut ProudNetClient<S>,
+    ) -> Result<Vec<StepReport>> {
+        let mut reports = Vec::with_capacity(self.steps.len());
+        for (index, step) in self.steps.iter().enumerate() {
+            let started = Instant::now();
+            let reply = match step {
+                Step::Send(message) => client.send_message(message).await.map(|()| None),
+                Step::Expect { opcode, within } => {
+                    let wanted = opcode.to_le_bytes();
+                    let expected = async {
+                        loop {
+                            let message = client.recv_message().await?;
+                            if message.starts_with(&wanted) {
+                                return Ok(Some(message));
+                            }
+                        }
+                    };
+                    tokio::time::timeout(*within, expected)
+                        .await
+                        .unwrap_or_else(|_| Err(anyhow!("no answer within {:?}", within)))
+                }
+                Step::Pause(duration) => {
+                    tokio::time::sleep(*duration).await;
+                    Ok(None)
+                }
+            }
+            .with_context(|| format!("step {} ({})", index + 1, step.describe()))?;
+            reports.push(StepReport {
+                step: step.describe(),
+                elapsed: started.elapsed(),
+                reply,
+            });
+        }
+        Ok(reports)
+    }
+}
+
+#[cfg(all(test, feature = "server"))]
+mod tests {
+    use super::*;
+    use crate::net::ProudNetConnection;
+    use crate::protocol::{GameContext, GameMessageHandler, MessageDispatcher, ProudNetHandler};
+    use crate::testing::Handshake;
+    use async_trait::async_trait;
+    use std::net::SocketAddr;
+    use std::sync::Arc;
+
+    /// Answers ReqLogin with the username it was sent
+    struct Login;
+
+    #[async_trait]
+    impl GameMessageHandler for Login {
+        async fn handle(
+            &self,
+            _packet_id: u32,
+            data: &[u8],
+            _context: &mut GameContext,
+        ) -> Result<Option<Vec<u8>>> {
+            let mut answer = WireWriter::message(ACK_LOGIN);
+            answer.bytes(&data[..USERNAME_LEN]);
+            Ok(Some(answer.into_bytes()))
+        }
+
+        fn opcode(&self) -> u32 {
+            REQ_LOGIN.into()
+        }
+
+        fn name(&self) -> &'static str {
+            "Login"
+        }
+    }
+
+    async fn connect() -> ProudNetClient<tokio::io::DuplexStream> {
+        let handshake = Handshake::new();
+        let addr: SocketAddr = "127.0.0.1:50124".parse().unwrap();
+        let handler = ProudNetHandler::with_shared_crypto(
+            addr,
+            Default::default(),
+            handshake.server_crypto(),
+        );
+        let (client, server) = tokio::io::duplex(8192);
+        let mut connection = ProudNetConnection::new(server, addr, handler);
+        tokio::spawn(async move {
+            let mut dispatcher = MessageDispatcher::with_handlers(vec![Arc::new(Login)]);
+            let _ = connection.run(&mut dispatcher).await;
+        });
+        ProudNetClient::connect(client).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_login_script() {
+        let mut client = connect().await;
+        let script = Script::login("bot01", "secret").pause(Duration::from_millis(1));
+        assert_eq!(script.steps().len(), 3);
+        let reports = script.run(&mut client).await.unwrap();
+        assert_eq!(
+            reports.iter().map(|r| r.step.as_str()).collect::<Vec<_>>(),
+            ["send 0x2ee2", "expect 0x30d5", "pause 1ms"]
+        );
+        let reply = reports[1].reply.as_ref().unwrap();
+        assert!(reply[2..].starts_with(b"bot01\0"));
+    }
+
+    #[tokio::test]
+    async fn test_unanswered_step_fails() {
+        let mut client = connect().await;
+        let script = Script::new()
+            .send(0x3F30, &[1])
+            .expect_within(0x3F31, Duration::from_millis(50));
+        let error = script.run(&mut client).await.unwrap_err();
+        assert_eq!(
+            format!("{:#}", error),
+            "step 2 (expect 0x3f31): no answer within 50ms"
+        );
+    }
+}
@@ -0,0 +1,345 @@
+//! What a server spends on handshakes
+//!
+//! Decrypting a client's session key (0x05) is an RSA private key
+//! operation, by far the most expensive thing a connection asks of the
+//! server, and any client can ask for it before logging in. Left
+//! unchecked a handful of machines sending nothing but 0x05 can keep the
+//! CPU pinned. [`HandshakeBudget`] caps the cost:
+//!
+//! - each address can have `max_per_ip` handshakes going at once; a
+//!   handshake lasts from the connection being accepted until its session
+//!   key is in;
+//! - the whole server decrypts at most `decrypts_per_sec` session keys a
+//!   second.
+//!
+//! Over either limit, a new connection is refused as soon as it's
+//! accepted ([`HandshakeBudget::admit`]) and a connection already in its
+//! handshake is closed before its 0x05 is decrypted
+//! ([`HandshakePermit::try_decrypt`]), both with a busy notice
+//! ([`build_busy_notice`](crate::protocol::build_busy_notice)) rather
+//! than silence, so real players know to retry.
+//!
+//! Set in the `[handshake]` section of the server's config file; 0 lifts
+//! a limit. The same section says how long handshakes are remembered to
+//! spot replays (see [`replay`](crate::crypto::replay)):
+//!
+//! ```toml
+//! [handshake]
+//! max_per_ip = 4
+//! decrypts_per_sec = 200
+//! replay_window_secs = 86400  # 0 to not look for replays
+//! replay_memory = 100000      # Handshakes remembered at most
+//! ```
+
+use crate::Result;
+use crate::clock::Clock;
+use crate::config::{self, Validate};
+use crate::crypto::ReplayGuard;
+use crate::protocol::build_busy_notice;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+/// What the refused are told
+pub const BUSY_MESSAGE: &str = "The server is busy. Please try again in a moment.";
+
+/// Handshake limits
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct HandshakeConfig {
+    /// Handshakes one address can have going at once; 0 for no limit
+    pub max_per_ip: u32,
+    /// Session keys decrypted a second, server-wide; 0 for no limit
+    pub decrypts_per_sec: u32,
+    /// How long a handshake is remembered; 0 to not look for replays
+    pub replay_window_secs: u64,
+    /// Most handshakes remembered; the oldest are forgotten first
+    pub replay_memory: usize,
+}
+
+impl Default for HandshakeConfig {
+    fn default() -> Self {
+        Self {
+            max_per_ip: 4,
+            decrypts_per_sec: 200,
+            replay_window_secs: 86_400,
+            replay_memory: 100_000,
+        }
+    }
+}
+
+impl HandshakeConfig {
+    /// Read the `[handshake]` section of `path`; missing keys keep their
+    /// defaults
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        config::section(path, "handshake")
+    }
+
+    /// Parse the `[handshake]` section of TOML text
+    pub fn from_toml(text: &str) -> Result<Self> {
+        config::section_from_toml(text, "handshake")
+    }
+
+    /// A guard against replayed handshakes for one server key, unless
+    /// that's off
+    pub fn replay_guard(&self) -> Option<ReplayGuard> {
+        (self.replay_window_secs > 0 && self.replay_memory > 0).then(|| {
+            ReplayGuard::new(
+                Duration::from_secs(self.replay_window_secs),
+                self.replay_memory,
+            )
+        })
+    }
+}
+
+impl Validate for HandshakeConfig {}
+
+/// Why a connection was refused
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shed {
+    /// Its address already has `max_per_ip` handshakes going
+    TooManyFromAddress,
+    /// This second's session key decryptions are used up
+    DecryptBudget,
+    /// The connection already had its one session key decrypted
+    AlreadyDecrypted,
+}
+
+impl fmt::Display for Shed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::TooManyFromAddress => "too many handshakes from its address",
+            Self::DecryptBudget => "session key decryptions used up for this second",
+            Self::AlreadyDecrypted => "session key already decrypted once",
+        })
+    }
+}
+
+struct Spent {
+    per_ip: HashMap<IpAddr, u32>,
+    /// Start of the second decryptions are counted in
+    second: Instant,
+    decrypts: u32,
+    shed: u64,
+}
+
+/// Handshakes going on and session keys decrypted, shared by every
+/// connection of a server
+///
+/// Cheap to clone; every clone counts against the same limits.
+#[derive(Clone)]
+pub struct HandshakeBudget {
+    config: HandshakeConfig,
+    clock: Clock,
+    spent: Arc<Mutex<Spent>>,
+}
+
+impl HandshakeBudget {
+    pub fn new(config: HandshakeConfig) -> Self {
+        Self::with_clock(config, Clock::system())
+    }
+
+    /// Count seconds by `clock`
+    pub fn with_clock(config: HandshakeConfig, clock: Clock) -> Self {
+        let spent = Spent {
+            per_ip: HashMap::new(),
+            second: clock.instant(),
+            decrypts: 0,
+            shed: 0,
+        };
+        Self {
+            config,
+            clock,
+            spent: Arc::new(Mutex::new(spent)),
+        }
+    }
+
+    pub fn config(&self) -> &HandshakeConfig {
+        &self.config
+    }
+
+    /// Let a connection from `ip` start its handshake, unless that's over
+    /// a limit; the permit holds its place until it's dropped
+    pub fn admit(&self, ip: IpAddr) -> std::result::Result<HandshakePermit, Shed> {
+        let mut spent = self.spent.lock().unwrap();
+        let refused = if self.decrypts_used_up(&mut spent) {
+            Some(Shed::DecryptBudget)
+        } else if self.config.max_per_ip > 0
+            && spent.per_ip.get(&ip).copied().unwrap_or(0) >= self.config.max_per_ip
+        {
+            Some(Shed::TooManyFromAddress)
+        } else {
+            None
+        };
+        if let Some(shed) = refused {
+            spent.shed += 1;
+            return Err(shed);
+        }
+        *spent.per_ip.entry(ip).or_default() += 1;
+        Ok(HandshakePermit {
+            budget: self.clone(),
+            ip,
+            decrypted: false,
+        })
+    }
+
+    /// Handshakes going on from `ip`
+    pub fn in_progress(&self, ip: IpAddr) -> u32 {
+        let spent = self.spent.lock().unwrap();
+        spent.per_ip.get(&ip).copied().unwrap_or(0)
+    }
+
+    /// Connections refused so far
+    pub fn shed(&self) -> u64 {
+        self.spent.lock().unwrap().shed
+    }
+
+    /// Whether this second's decryptions are used up, starting a new
+    /// second if the last one is over
+    fn decrypts_used_up(&self, spent: &mut Spent) -> bool {
+        let now = self.clock.instant();
+        if now.saturating_duration_since(spent.second) >= Duration::from_secs(1) {
+            spent.second = now;
+            spent.decrypts = 0;
+        }
+        self.config.decrypts_per_sec > 0 && spent.decrypts >= self.config.decrypts_per_sec
+    }
+}
+
+/// One connection's place in the [`HandshakeBudget`]
+///
+/// Drop it once the handshake is over, so its address can start another.
+pub struct HandshakePermit {
+    budget: HandshakeBudget,
+    ip: IpAddr,
+    /// Whether its one decryption is spent
+    decrypted: bool,
+}
+
+impl HandshakePermit {
+    /// Spend one session key decryption, unless this second's are used up
+    /// or the permit's one decryption already is; a client resending 0x05
+    /// isn't let at the RSA key again
+    pub fn try_decrypt(&mut self) -> std::result::Result<(), Shed> {
+        let mut spent = self.budget.spent.lock().unwrap();
+        if self.decrypted {
+            spent.shed += 1;
+            return Err(Shed::AlreadyDecrypted);
+        }
+        if self.budget.decrypts_used_up(&mut spent) {
+            spent.shed += 1;
+            return Err(Shed::DecryptBudget);
+        }
+        spent.decrypts += 1;
+        self.decrypted = true;
+        Ok(())
+    }
+}
+
+impl Drop for HandshakePermit {
+    fn drop(&mut self) {
+        let mut spent = self.budget.spent.lock().unwrap();
+        if let Some(count) = spent.per_ip.get_mut(&self.ip) {
+            *count -= 1;
+            if *count == 0 {
+                spent.per_ip.remove(&self.ip);
+            }
+        }
+    }
+}
+
+/// Tell a refused client the server is busy, then hang up
+pub async fn refuse<S: AsyncWrite + Unpin>(stream: &mut S) -> Result<()> {
+    stream.write_all(&build_busy_notice(BUSY_MESSAGE)).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn budget(max_per_ip: u32, decrypts_per_sec: u32) -> (HandshakeBudget, Clock) {
+        let clock = Clock::at_unix(1_700_000_000);
+        let config = HandshakeConfig {
+            max_per_ip,
+            decrypts_per_sec,
+            ..HandshakeConfig::default()
+        };
+        (HandshakeBudget::with_clock(config, clock.clone()), clock)
+    }
+
+    #[test]
+    fn test_config() {
+        assert_eq!(
+            HandshakeConfig::load("does/not/exist.toml").unwrap(),
+            HandshakeConfig::default()
+        );
+        let config = HandshakeConfig::from_toml("[handshake]\nmax_per_ip = 0").unwrap();
+        assert_eq!((config.max_per_ip, config.decrypts_per_sec), (0, 200));
+        assert!(config.replay_guard().is_some());
+        let config = HandshakeConfig::from_toml("[handshake]\nreplay_window_secs = 0").unwrap();
+        assert!(config.replay_guard().is_none());
+    }
+
+    #[test]
+    fn test_per_ip() {
+        let (budget, _) = budget(2, 0);
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+        let first = budget.admit(ip).unwrap();
+        let _second = budget.admit(ip).unwrap();
+        assert_eq!(budget.admit(ip).err(), Some(Shed::TooManyFromAddress));
+        assert!(budget.admit("10.0.0.2".parse().unwrap()).is_ok());
+
+        // A finished handshake frees its place
+        drop(first);
+        assert_eq!(budget.in_progress(ip), 1);
+        assert!(budget.admit(ip).is_ok());
+        assert_eq!(budget.shed(), 1);
+    }
+
+    #[test]
+    fn test_decrypts_per_second() {
+        let (budget, clock) = budget(0, 2);
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+        let mut permits: Vec<_> = (0..3).map(|_| budget.admit(ip).unwrap()).collect();
+        assert!(permits[0].try_decrypt().is_ok());
+        assert!(permits[1].try_decrypt().is_ok());
+        assert_eq!(permits[2].try_decrypt().unwrap_err(), Shed::DecryptBudget);
+
+        // New connections aren't started while it's used up
+        assert_eq!(budget.admit(ip).err(), Some(Shed::DecryptBudget));
+
+        clock.advance(Duration::from_secs(1));
+        assert!(permits[2].try_decrypt().is_ok());
+        assert!(budget.admit(ip).is_ok());
+        assert_eq!(budget.shed(), 2);
+    }
+
+    #[test]
+    fn test_one_decrypt_per_permit() {
+        let (budget, clock) = budget(0, 0);
+        let mut permit = budget.admit("10.0.0.1".parse().unwrap()).unwrap();
+        assert!(permit.try_decrypt().is_ok());
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(permit.try_decrypt().unwrap_err(), Shed::AlreadyDecrypted);
+        assert_eq!(budget.shed(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_refuse() {
+        let (mut client, mut server) = tokio::io::duplex(4096);
+        refuse(&mut server).await.unwrap();
+        drop(server);
+        let mut sent = Vec::new();
+        tokio::io::AsyncReadExt::read_to_end(&mut client, &mut sent)
+            .await
+            .unwrap();
+        assert_eq!(sent, build_busy_notice(BUSY_MESSAGE));
+    }
+}
@@ -0,0 +1,107 @@
+//! Cutting a TCP byte stream into ProudNet frames
+
+use crate::packet::framing::{PACKET_MAGIC_BYTES, PacketFrame};
+use crate::wire::WireError;
+
+/// One piece of a ProudNet byte stream
+#[derive(Debug, Clone, PartialEq)]
+pub enum Chunk {
+    /// A complete frame, with its bytes as they were received
+    Frame { frame: PacketFrame, raw: Vec<u8> },
+    /// Bytes before the next frame magic: the unframed policy XML, or garbage
+    Unframed(Vec<u8>),
+}
+
+/// Buffers received bytes until whole frames are available
+#[derive(Debug, Default)]
+pub struct FrameBuffer {
+    buffer: Vec<u8>,
+}
+
+impl FrameBuffer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append received bytes
+    pub fn extend(&mut self, data: &[u8]) {
+        self.buffer.extend_from_slice(data);
+    }
+
+    /// Number of buffered bytes
+    pub fn len(&self) -> usize {
+        self.buffer.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buffer.is_empty()
+    }
+
+    /// Take the next complete frame or unframed run off the buffer, or
+    /// `None` if more data is needed
+    pub fn next_chunk(&mut self) -> Option<Chunk> {
+        if self.buffer.is_empty() {
+            return None;
+        }
+
+        if !self.buffer.starts_with(&PACKET_MAGIC_BYTES) {
+            // Keep a trailing first magic byte, its partner may be in flight
+            let end = self
+                .buffer
+                .windows(2)
+                .position(|w| w == PACKET_MAGIC_BYTES)
+                .unwrap_or(if self.buffer.ends_with(&PACKET_MAGIC_BYTES[..1]) {
+                    self.buffer.len() - 1
+                } else {
+                    self.buffer.len()
+                });
+            if end == 0 {
+                return None;
+            }
+            return Some(Chunk::Unframed(self.buffer.drain(..end).collect()));
+        }
+
+        match PacketFrame::from_bytes(&self.buffer) {
+            Ok((frame, size)) => Some(Chunk::Frame {
+                frame,
+                raw: self.buffer.drain(..size).collect(),
+            }),
+            Err(e) if matches!(e.downcast_ref(), Some(WireError::Truncated { .. })) => None,
+            // A corrupt header can't be skipped reliably, drop everything
+            Err(_) => Some(Chunk::Unframed(std::mem::take(&mut self.buffer))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frames_split_and_unframed() {
+        let mut buffer = FrameBuffer::new();
+        let first = PacketFrame::new(vec![0x06]).to_bytes();
+        let second = PacketFrame::new(vec![0x1C]).to_bytes();
+
+        buffer.extend(b"<xml/>\0");
+        buffer.extend(&first);
+        buffer.extend(&second[..2]);
+
+        assert_eq!(
+            buffer.next_chunk(),
+            Some(Chunk::Unframed(b"<xml/>\0".to_vec()))
+        );
+        assert!(matches!(
+            buffer.next_chunk(),
+            Some(Chunk::Frame { raw, .. }) if raw == first
+        ));
+        assert_eq!(buffer.next_chunk(), None);
+
+        buffer.extend(&second[2..]);
+        assert!(matches!(
+            buffer.next_chunk(),
+            Some(Chunk::Frame { frame, .. }) if frame.opcode() == Some(0x1C)
+        ));
+        assert!(buffer.is_empty());
+    }
+}
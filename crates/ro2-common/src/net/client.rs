@@ -0,0 +1,151 @@
+//! Client side of the ProudNet handshake
+//!
+//! [`ProudNetClient`] connects the way the game client does: policy
+//! request, session key under the server's RSA key, version check, then
+//! AES-encrypted game messages. Servers use it to check themselves (see
+//! [`selftest`](crate::selftest)); it is not a game client.
+
+use super::{Chunk, FrameBuffer};
+use crate::Result;
+use crate::crypto::ProudNetCrypto;
+use crate::packet::framing::PacketFrame;
+use crate::protocol::Reliability;
+use crate::wire::WireReader;
+use anyhow::{Context, anyhow};
+use rand::RngCore;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// 0x07 version check, as captured from the official client
+const VERSION_CHECK: [u8; 23] = [
+    0x07, 0x01, 0x00, 0x76, 0x7A, 0xF2, 0x16, 0xCC, 0xC2, 0x83, 0x43, 0xA0, 0xE6, 0x49, 0x86, 0x24,
+    0x35, 0x56, 0x80, 0x82, 0x01, 0x03, 0x00,
+];
+
+/// Bytes of 0x04 before the public key: opcode and ten u32 settings
+const HANDSHAKE_HEADER_LEN: usize = 1 + 40;
+
+/// A connection to a ProudNet server
+pub struct ProudNetClient<S> {
+    stream: S,
+    buffer: FrameBuffer,
+    crypto: ProudNetCrypto,
+    session_id: u32,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> ProudNetClient<S> {
+    /// Run the handshake over `stream` up to 0x0A connection success,
+    /// with a random session key
+    pub async fn connect(stream: S) -> Result<Self> {
+        let mut client = Self {
+            stream,
+            buffer: FrameBuffer::new(),
+            crypto: ProudNetCrypto::new(),
+            session_id: 0,
+        };
+
+        client.send(vec![0x2F, 0x0F, 0x00, 0x00, 0x40]).await?;
+        // The XML is NUL-terminated and may arrive in pieces
+        let mut xml = Vec::new();
+        while !xml.ends_with(b"\0") {
+            match client.next_chunk().await? {
+                Chunk::Unframed(data) => xml.extend(data),
+                Chunk::Frame { .. } => return Err(anyhow!("expected the policy XML")),
+            }
+        }
+
+        let handshake = client.expect(0x04).await?;
+        let mut r = WireReader::new(&handshake);
+        r.bytes("0x04 settings", HANDSHAKE_HEADER_LEN)?;
+        client
+            .crypto
+            .set_server_public_key(r.blob("0x04 public key")?)?;
+
+        let mut session_key = [0u8; 16];
+        rand::rngs::OsRng.fill_bytes(&mut session_key);
+        let encrypted = client.crypto.encrypt_session_key(&session_key)?;
+        client.crypto.set_session_key(session_key)?;
+        let mut response = vec![0x05, 0x02];
+        response.extend_from_slice(&(encrypted.len() as u16).to_le_bytes());
+        response.extend_from_slice(&encrypted);
+        client.send(response).await?;
+        client.expect(0x06).await?;
+
+        client.send(VERSION_CHECK.to_vec()).await?;
+        let success = client.expect(0x0A).await?;
+        let mut r = WireReader::new(&success);
+        r.u8("opcode")?;
+        client.session_id = r.u32("0x0A session ID")?;
+        Ok(client)
+    }
+
+    /// Session ID the server gave us
+    pub fn session_id(&self) -> u32 {
+        self.session_id
+    }
+
+    /// Encrypt a game message (u16 opcode + payload) and send it
+    pub async fn send_message(&mut self, message: &[u8]) -> Result<()> {
+        self.send_message_with(Reliability::Reliable, message).await
+    }
+
+    /// [`send_message`](Self::send_message) in 0x25 or 0x26
+    pub async fn send_message_with(
+        &mut self,
+        reliability: Reliability,
+        message: &[u8],
+    ) -> Result<()> {
+        let mut payload = reliability.header().to_vec();
+        payload.extend(self.crypto.encrypt_data(message)?);
+        self.send(payload).await
+    }
+
+    /// The next encrypted game message, skipping anything else
+    pub async fn recv_message(&mut self) -> Result<Vec<u8>> {
+        loop {
+            match self.next_chunk().await? {
+                Chunk::Frame { frame, .. } if matches!(frame.opcode(), Some(0x25 | 0x26)) => {
+                    return self.crypto.decrypt_packet_0x25(&frame.payload);
+                }
+                _ => continue,
+            }
+        }
+    }
+
+    async fn send(&mut self, payload: Vec<u8>) -> Result<()> {
+        self.stream
+            .write_all(&PacketFrame::new(payload).to_bytes())
+            .await?;
+        Ok(())
+    }
+
+    /// The next frame's payload, which must have `opcode`
+    async fn expect(&mut self, opcode: u8) -> Result<Vec<u8>> {
+        match self.next_chunk().await? {
+            Chunk::Frame { frame, .. } if frame.opcode() == Some(opcode) => Ok(frame.payload),
+            Chunk::Frame { frame, .. } => Err(anyhow!(
+                "expected 0x{:02x}, got 0x{:02x}",
+                opcode,
+                frame.opcode().unwrap_or(0)
+            )),
+            Chunk::Unframed(_) => Err(anyhow!("expected 0x{:02x}, got unframed bytes", opcode)),
+        }
+    }
+
+    async fn next_chunk(&mut self) -> Result<Chunk> {
+        let mut read_buf = [0u8; 4096];
+        loop {
+            if let Some(chunk) = self.buffer.next_chunk() {
+                return Ok(chunk);
+            }
+            let n = self
+                .stream
+                .read(&mut read_buf)
+                .await
+                .context("reading from the server")?;
+            if n == 0 {
+                return Err(anyhow!("server closed the connection"));
+            }
+            self.buffer.extend(&read_buf[..n]);
+        }
+    }
+}
@@ -0,0 +1,1001 @@
+//! ProudNet connection loop
+//!
+//! [`ProudNetConnection`] owns one client socket and runs the ProudNet
+//! layer through [`ProudNetHandler`]: policy, key exchange, version check
+//! and heartbeats. Decrypted game messages are routed through a
+//! [`MessageDispatcher`], and handler responses are encrypted and sent
+//! back. Every server and tool that accepts clients goes through this loop,
+//! so the handshake has a single implementation.
+//!
+//! Heartbeat latency is copied into the [`GameContext`] as it's measured,
+//! and a client whose heartbeats stop for longer than the heartbeat timeout
+//! is disconnected. Messages the server sends unprompted (world updates,
+//! broadcasts) are queued on the connection's outbox and sent between
+//! reads; once the client has a session ID the outbox is registered in the
+//! [`SharedState`]'s [`ConnectionRegistry`](super::ConnectionRegistry), so
+//! other connections' handlers can reach it too. The server can also close
+//! the connection itself (a kick, or a playtime limit running out), after
+//! flushing the outbox. With wire tracing switched on in the registry,
+//! every frame and game message is logged in hex. A connection holding a
+//! [`HandshakePermit`] asks it before decrypting the session key, and is
+//! told the server is busy and closed if this second's decryptions are
+//! used up. A session key that doesn't decrypt closes the connection too,
+//! unless [`keep_open_on_bad_key`](ProudNetConnection::keep_open_on_bad_key)
+//! is set.
+//!
+//! Everything logged while serving a client is in a `connection` span with
+//! its address, listener, ProudNet session ID, account ID once a handler
+//! sets it, and [`CorrelationId`](crate::session::CorrelationId), and each
+//! game message in a `message` span inside it (see
+//! [`MessageDispatcher::dispatch`]).
+
+use super::{Chunk, Direction, FrameBuffer, HandshakePermit, budget};
+use crate::Result;
+use crate::crypto::Replayed;
+use crate::packet::framing::PacketFrame;
+use crate::protocol::{GameContext, MessageDispatcher, ProudNetHandler, Reliability, SharedState};
+use crate::wire::WireReader;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::{mpsc, oneshot};
+use tracing::{Instrument, Span, debug, error, field, info, info_span, warn};
+
+/// Messages that can wait in a connection's outbox
+const OUTBOX_CAPACITY: usize = 64;
+
+/// Sees all traffic on a connection, e.g. for logging
+pub trait FrameObserver: Send {
+    /// A frame payload (or the unframed policy XML) was received or sent
+    fn on_frame(&mut self, _direction: Direction, _payload: &[u8]) {}
+
+    /// A game message (u16 opcode + payload) was decrypted, or is about to
+    /// be encrypted
+    fn on_message(&mut self, _direction: Direction, _message: &[u8]) {}
+}
+
+/// One client connection speaking ProudNet
+pub struct ProudNetConnection<S> {
+    stream: S,
+    addr: SocketAddr,
+    handler: ProudNetHandler,
+    context: GameContext,
+    buffer: FrameBuffer,
+    observer: Option<Box<dyn FrameObserver>>,
+    heartbeat_timeout: Option<Duration>,
+    outbox_tx: mpsc::Sender<Vec<u8>>,
+    outbox: Option<mpsc::Receiver<Vec<u8>>>,
+    disconnect: Option<oneshot::Receiver<String>>,
+    /// Reasons to close the connection, from the registry's kicks
+    kick_tx: mpsc::Sender<String>,
+    kick: mpsc::Receiver<String>,
+    /// Place in the handshake budget, until the session key is in
+    permit: Option<HandshakePermit>,
+    /// Set to close the connection once the frames read so far are handled
+    closing: Option<String>,
+    /// Keep serving a client whose session key didn't decrypt
+    keep_open_on_bad_key: bool,
+    span: Span,
+}
+
+/// What woke the connection loop
+enum Event {
+    Read(usize),
+    Outgoing(Option<Vec<u8>>),
+    /// The server closed the connection, with a reason for the logs
+    Disconnect(Option<String>),
+    HeartbeatTimeout,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> ProudNetConnection<S> {
+    /// Wrap an accepted stream
+    pub fn new(stream: S, addr: SocketAddr, handler: ProudNetHandler) -> Self {
+        let heartbeat_timeout = Duration::from_secs(handler.settings().timeout_secs as u64);
+        let context = GameContext::new(0, addr.to_string());
+        let (outbox_tx, outbox) = mpsc::channel(OUTBOX_CAPACITY);
+        let (kick_tx, kick) = mpsc::channel(1);
+        let span = info_span!(
+            "connection",
+            remote_addr = %addr,
+            listener = field::Empty,
+            session_id = field::Empty,
+            account_id = field::Empty,
+            correlation_id = %context.correlation_id
+        );
+        Self {
+            stream,
+            addr,
+            handler,
+            context,
+            buffer: FrameBuffer::new(),
+            observer: None,
+            heartbeat_timeout: Some(heartbeat_timeout),
+            outbox_tx,
+            outbox: Some(outbox),
+            disconnect: None,
+            kick_tx,
+            kick,
+            permit: None,
+            closing: None,
+            keep_open_on_bad_key: false,
+            span,
+        }
+    }
+
+    /// Report every frame and game message to `observer`
+    pub fn with_observer(mut self, observer: impl FrameObserver + 'static) -> Self {
+        self.observer = Some(Box::new(observer));
+        self
+    }
+
+    /// Disconnect the client after `timeout` without a heartbeat, or never
+    /// with `None`. Defaults to the timeout sent to the client in 0x04.
+    pub fn with_heartbeat_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.heartbeat_timeout = timeout;
+        self
+    }
+
+    /// Log a session key that fails to decrypt instead of closing the
+    /// connection, so the rest of the client's traffic can still be
+    /// inspected. Off by default: a client resending 0x05 would otherwise
+    /// spend the server's decryption budget. Replays still close it.
+    pub fn keep_open_on_bad_key(mut self, keep_open: bool) -> Self {
+        self.keep_open_on_bad_key = keep_open;
+        self
+    }
+
+    /// Tag the connection with the listener it was accepted on
+    pub fn with_listener(mut self, name: impl Into<String>) -> Self {
+        let name = name.into();
+        self.span.record("listener", name.as_str());
+        self.context.connection_info.listener = Some(name);
+        self
+    }
+
+    /// Give handlers access to `shared`, register the connection's
+    /// outbox there once the client has a session ID, and time heartbeats
+    /// by its clock
+    pub fn with_shared(mut self, shared: SharedState) -> Self {
+        self.handler = self.handler.with_clock(shared.clock.clone());
+        self.context = self.context.with_shared(shared);
+        self
+    }
+
+    /// Queues game messages (u16 opcode + payload) to send to the client
+    pub fn outbox(&self) -> mpsc::Sender<Vec<u8>> {
+        self.outbox_tx.clone()
+    }
+
+    /// Close the connection when a reason is sent on `disconnect`, once
+    /// everything already in the outbox has been sent
+    pub fn with_disconnect(mut self, disconnect: oneshot::Receiver<String>) -> Self {
+        self.disconnect = Some(disconnect);
+        self
+    }
+
+    /// Spend from `permit`'s budget to decrypt the session key, and give
+    /// it back once it's in
+    pub fn with_handshake_permit(mut self, permit: HandshakePermit) -> Self {
+        self.permit = Some(permit);
+        self
+    }
+
+    pub fn handler(&self) -> &ProudNetHandler {
+        &self.handler
+    }
+
+    pub fn context(&self) -> &GameContext {
+        &self.context
+    }
+
+    /// The span everything about this connection is logged in
+    pub fn span(&self) -> &Span {
+        &self.span
+    }
+
+    /// Serve the client until it disconnects
+    pub async fn run(&mut self, dispatcher: &mut MessageDispatcher) -> Result<()> {
+        let span = self.span.clone();
+        let result = self.serve(dispatcher).instrument(span).await;
+        if self.context.session_id != 0 {
+            self.context
+                .shared
+                .connections
+                .unregister(self.context.session_id);
+        }
+        result
+    }
+
+    async fn serve(&mut self, dispatcher: &mut MessageDispatcher) -> Result<()> {
+        let mut read_buf = vec![0u8; 4096];
+        let connected_at = self.handler.clock().instant();
+
+        loop {
+            // Clients heartbeat from the start, so the clock runs before the
+            // first one too
+            let time_left = self.heartbeat_timeout.map(|timeout| {
+                let last = self.handler.last_heartbeat().unwrap_or(connected_at);
+                (last + timeout).saturating_duration_since(self.handler.clock().instant())
+            });
+
+            let event = {
+                let outbox = &mut self.outbox;
+                let disconnect = &mut self.disconnect;
+                let kick = &mut self.kick;
+                tokio::select! {
+                    n = self.stream.read(&mut read_buf) => Event::Read(n?),
+                    message = async { outbox.as_mut()?.recv().await }, if outbox.is_some() => {
+                        Event::Outgoing(message)
+                    }
+                    reason = async { disconnect.as_mut()?.await.ok() }, if disconnect.is_some() => {
+                        Event::Disconnect(reason)
+                    }
+                    Some(reason) = kick.recv() => Event::Disconnect(Some(reason)),
+                    _ = tokio::time::sleep(time_left.unwrap_or_default()),
+                        if time_left.is_some() => Event::HeartbeatTimeout,
+                }
+            };
+
+            let n = match event {
+                Event::Read(n) => n,
+                Event::Outgoing(Some(message)) => {
+                    if self.handler.is_encryption_ready() {
+                        self.send_message(&message).await?;
+                    } else {
+                        debug!("[{}] Dropping message before key exchange", self.addr);
+                    }
+                    continue;
+                }
+                Event::Outgoing(None) => {
+                    // Every sender is gone
+                    self.outbox = None;
+                    continue;
+                }
+                Event::Disconnect(None) => {
+                    // Dropped without a reason: nobody can disconnect us now
+                    self.disconnect = None;
+                    continue;
+                }
+                Event::Disconnect(Some(reason)) => {
+                    self.flush_outbox().await?;
+                    info!("[{}] Disconnecting: {}", self.addr, reason);
+                    self.log_latency();
+                    return Ok(());
+                }
+                Event::HeartbeatTimeout => {
+                    warn!(
+                        "[{}] No heartbeat for {}s, disconnecting",
+                        self.addr,
+                        self.heartbeat_timeout.unwrap_or_default().as_secs()
+                    );
+                    self.log_latency();
+                    return Ok(());
+                }
+            };
+            if n == 0 {
+                info!("[{}] Client disconnected", self.addr);
+                self.log_latency();
+                return Ok(());
+            }
+
+            self.buffer.extend(&read_buf[..n]);
+            debug!(
+                "[{}] Received {} bytes (buffer: {})",
+                self.addr,
+                n,
+                self.buffer.len()
+            );
+
+            while let Some(packet) = self.next_frame() {
+                self.handle_packet(packet, dispatcher).await?;
+                if let Some(reason) = self.closing.take() {
+                    info!("[{}] Disconnecting: {}", self.addr, reason);
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    /// Send whatever is already queued on the outbox
+    async fn flush_outbox(&mut self) -> Result<()> {
+        let Some(mut outbox) = self.outbox.take() else {
+            return Ok(());
+        };
+        while let Ok(message) = outbox.try_recv() {
+            if self.handler.is_encryption_ready() {
+                self.send_message(&message).await?;
+            }
+        }
+        self.outbox = Some(outbox);
+        Ok(())
+    }
+
+    /// Encrypt a game message (u16 opcode + payload) and send it
+    pub async fn send_message(&mut self, message: &[u8]) -> Result<()> {
+        self.send_message_with(Reliability::Reliable, message).await
+    }
+
+    /// [`send_message`](Self::send_message) in 0x25 or 0x26
+    #[cfg_attr(feature = "profiling", tracing::instrument(level = "trace", skip_all))]
+    pub async fn send_message_with(
+        &mut self,
+        reliability: Reliability,
+        message: &[u8],
+    ) -> Result<()> {
+        self.trace_wire("message", Direction::ServerToClient, message);
+        if let Some(observer) = &mut self.observer {
+            observer.on_message(Direction::ServerToClient, message);
+        }
+        let encrypted = self.handler.encrypt_packet_with(reliability, message)?;
+        self.send(&encrypted).await
+    }
+
+    /// Take the next complete frame off the buffer, discarding garbage
+    fn next_frame(&mut self) -> Option<PacketFrame> {
+        loop {
+            match self.buffer.next_chunk()? {
+                Chunk::Frame { frame, .. } => return Some(frame),
+                Chunk::Unframed(data) => error!(
+                    "[{}] Invalid packet magic, discarding {} bytes: {}",
+                    self.addr,
+                    data.len(),
+                    hex::encode(&data[..data.len().min(64)])
+                ),
+            }
+        }
+    }
+
+    #[cfg_attr(feature = "profiling", tracing::instrument(level = "trace", skip_all))]
+    async fn handle_packet(
+        &mut self,
+        packet: PacketFrame,
+        dispatcher: &mut MessageDispatcher,
+    ) -> Result<()> {
+        let opcode = packet.opcode().unwrap_or(0);
+        self.trace_wire("frame", Direction::ClientToServer, &packet.payload);
+        if let Some(observer) = &mut self.observer {
+            observer.on_frame(Direction::ClientToServer, &packet.payload);
+        }
+
+        match opcode {
+            0x25 | 0x26 => return self.handle_encrypted(&packet.payload, dispatcher).await,
+            0x05 => {
+                info!("[{}] 0x05: Encryption response", self.addr);
+                if let Some(Err(shed)) = self.permit.as_mut().map(HandshakePermit::try_decrypt) {
+                    budget::refuse(&mut self.stream).await?;
+                    self.closing = Some(format!("busy, {}", shed));
+                    return Ok(());
+                }
+                match self.handler.handle(0x05, &packet.payload) {
+                    Ok(Some(response)) => {
+                        info!("[{}] 0x06: Sending encryption ready", self.addr);
+                        self.send(&response).await?;
+                    }
+                    Ok(None) => warn!("[{}] 0x05: No response generated", self.addr),
+                    Err(e) if e.is::<Replayed>() => {
+                        self.closing = Some(format!("{:#}", e));
+                    }
+                    Err(e) => {
+                        error!("[{}] 0x05: Failed to decrypt session key: {}", self.addr, e);
+                        if !self.keep_open_on_bad_key {
+                            self.closing = Some(format!("bad session key: {:#}", e));
+                        }
+                    }
+                }
+                self.context.connection_info.encrypted = self.handler.is_encryption_ready();
+                if self.handler.is_encryption_ready() {
+                    self.permit = None;
+                }
+                return Ok(());
+            }
+            0x01 => info!("[{}] 0x01: Disconnect notification", self.addr),
+            0x2F => info!("[{}] 0x2F: Policy request", self.addr),
+            0x07 => info!("[{}] 0x07: Version check", self.addr),
+            0x1B => debug!("[{}] 0x1B: Heartbeat", self.addr),
+            0x1C => debug!("[{}] 0x1C: Keep-alive ping", self.addr),
+            _ => warn!("[{}] Unhandled opcode: 0x{:02x}", self.addr, opcode),
+        }
+
+        if let Some(response) = self.handler.handle(opcode, &packet.payload)? {
+            self.send(&response).await?;
+        }
+
+        match opcode {
+            // The RSA key follows the policy XML unprompted
+            0x2F => {
+                info!("[{}] 0x04: Sending encryption handshake", self.addr);
+                let handshake = self.handler.build_encryption_handshake()?;
+                self.send(&handshake).await?;
+            }
+            0x07 => {
+                let session_id = self.handler.session_id().unwrap_or(0);
+                info!(
+                    "[{}] 0x0A: Sent connection success (session: {})",
+                    self.addr, session_id
+                );
+                self.context.session_id = session_id as u64;
+                self.span.record("session_id", session_id);
+                self.context.shared.connections.register_connection(
+                    session_id as u64,
+                    self.outbox_tx.clone(),
+                    self.addr.to_string(),
+                    self.kick_tx.clone(),
+                );
+            }
+            0x1B => self.context.connection_info.latency = self.handler.latency(),
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    /// Log `bytes` in hex if wire tracing is on
+    fn trace_wire(&self, what: &str, direction: Direction, bytes: &[u8]) {
+        if self.context.shared.connections.wire_trace() {
+            info!(
+                "[{}] {} {} ({} bytes): {}",
+                self.addr,
+                direction,
+                what,
+                bytes.len(),
+                hex::encode(bytes)
+            );
+        }
+    }
+
+    fn log_latency(&self) {
+        let latency = self.handler.latency();
+        info!(
+            remote_addr = %self.addr,
+            heartbeats = latency.heartbeats,
+            rtt_ms = latency.rtt.map(|rtt| rtt.as_millis() as u64),
+            jitter_ms = latency.jitter.as_millis() as u64,
+            "Connection latency"
+        );
+    }
+
+    #[cfg_attr(feature = "profiling", tracing::instrument(level = "trace", skip_all))]
+    async fn handle_encrypted(
+        &mut self,
+        payload: &[u8],
+        dispatcher: &mut MessageDispatcher,
+    ) -> Result<()> {
+        if !self.handler.is_encryption_ready() {
+            warn!(
+                "[{}] Encrypted packet before key exchange, cannot decrypt",
+                self.addr
+            );
+            return Ok(());
+        }
+
+        let (reliability, message) = match self.handler.decrypt_packet(payload) {
+            Ok(decrypted) => decrypted,
+            Err(e) => {
+                error!("[{}] Decryption failed: {}", self.addr, e);
+                return Ok(());
+            }
+        };
+
+        let mut r = WireReader::new(&message);
+        let game_opcode = match r.u16("game opcode") {
+            Ok(opcode) => opcode,
+            Err(e) => {
+                warn!("[{}] Decrypted message too short: {}", self.addr, e);
+                return Ok(());
+            }
+        };
+
+        self.trace_wire("message", Direction::ClientToServer, &message);
+        if let Some(observer) = &mut self.observer {
+            observer.on_message(Direction::ClientToServer, &message);
+        }
+
+        info!(
+            "[{}] Game message 0x{:04x} ({} bytes, {})",
+            self.addr,
+            game_opcode,
+            message.len(),
+            reliability
+        );
+        self.context.update_activity();
+        self.context.reliability = reliability;
+
+        // Handler failures are logged by the dispatcher; keep serving
+        let response = dispatcher
+            .dispatch(game_opcode as u32, r.remaining(), &mut self.context)
+            .await;
+        // Handlers log players in and hand them over
+        if let Some(account_id) = self.context.account_id {
+            self.span.record("account_id", account_id);
+            self.context
+                .shared
+                .connections
+                .set_account(self.context.session_id, account_id);
+        }
+        self.span.record(
+            "correlation_id",
+            field::display(self.context.correlation_id),
+        );
+        if let Ok(Some(response)) = response {
+            self.send_message(&response).await?;
+        }
+
+        Ok(())
+    }
+
+    #[cfg_attr(feature = "profiling", tracing::instrument(level = "trace", skip_all))]
+    async fn send(&mut self, bytes: &[u8]) -> Result<()> {
+        self.trace_wire("frame", Direction::ServerToClient, bytes);
+        if let Some(observer) = &mut self.observer {
+            match PacketFrame::from_bytes(bytes) {
+                Ok((frame, _)) => observer.on_frame(Direction::ServerToClient, &frame.payload),
+                Err(_) => observer.on_frame(Direction::ServerToClient, bytes),
+            }
+        }
+
+        self.stream.write_all(bytes).await?;
+        self.stream.flush().await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::Clock;
+    use crate::net::{HandshakeBudget, HandshakeConfig};
+    use crate::protocol::{BoxedHandler, FLASH_POLICY_XML, GameMessageHandler, ProudNetSettings};
+    use crate::testing::{self, Handshake};
+    use async_trait::async_trait;
+    use std::sync::{Arc, Mutex};
+    use tokio::io::DuplexStream;
+
+    /// Answers 0x1001 with 0x1002 carrying the same payload
+    struct Echo;
+
+    #[async_trait]
+    impl GameMessageHandler for Echo {
+        async fn handle(
+            &self,
+            _packet_id: u32,
+            data: &[u8],
+            _context: &mut GameContext,
+        ) -> Result<Option<Vec<u8>>> {
+            Ok(Some(testing::game_message(0x1002, data)))
+        }
+
+        fn opcode(&self) -> u32 {
+            0x1001
+        }
+
+        fn name(&self) -> &'static str {
+            "Echo"
+        }
+    }
+
+    /// Notes the reliability 0x1005 messages come in
+    #[derive(Clone, Default)]
+    struct Mover(Arc<Mutex<Vec<Reliability>>>);
+
+    #[async_trait]
+    impl GameMessageHandler for Mover {
+        async fn handle(
+            &self,
+            _packet_id: u32,
+            _data: &[u8],
+            context: &mut GameContext,
+        ) -> Result<Option<Vec<u8>>> {
+            self.0.lock().unwrap().push(context.reliability);
+            Ok(None)
+        }
+
+        fn opcode(&self) -> u32 {
+            0x1005
+        }
+
+        fn name(&self) -> &'static str {
+            "Mover"
+        }
+    }
+
+    #[derive(Clone, Default)]
+    struct Recorder(Arc<Mutex<Vec<(Direction, u8)>>>);
+
+    impl FrameObserver for Recorder {
+        fn on_frame(&mut self, direction: Direction, payload: &[u8]) {
+            self.0.lock().unwrap().push((direction, payload[0]));
+        }
+    }
+
+    async fn read_frame(client: &mut DuplexStream) -> PacketFrame {
+        let mut data = Vec::new();
+        loop {
+            if let Ok((frame, _)) = PacketFrame::from_bytes(&data) {
+                return frame;
+            }
+            let mut byte = [0u8; 1];
+            client.read_exact(&mut byte).await.unwrap();
+            data.push(byte[0]);
+        }
+    }
+
+    /// Run the client side of the handshake up to 0x0A
+    async fn complete_handshake(client: &mut DuplexStream, handshake: &Handshake) {
+        client.write_all(&testing::policy_request()).await.unwrap();
+        let mut xml = vec![0u8; FLASH_POLICY_XML.len()];
+        client.read_exact(&mut xml).await.unwrap();
+        assert_eq!(xml, FLASH_POLICY_XML);
+        assert_eq!(read_frame(client).await.opcode(), Some(0x04));
+
+        client
+            .write_all(&handshake.session_key_response())
+            .await
+            .unwrap();
+        assert_eq!(read_frame(client).await.opcode(), Some(0x06));
+
+        client.write_all(&testing::version_check()).await.unwrap();
+        assert_eq!(read_frame(client).await.opcode(), Some(0x0A));
+    }
+
+    #[tokio::test]
+    async fn test_handshake_and_dispatch() {
+        let handshake = Handshake::new();
+        let addr: SocketAddr = "127.0.0.1:50123".parse().unwrap();
+        let handler = ProudNetHandler::with_shared_crypto(
+            addr,
+            ProudNetSettings::default(),
+            handshake.server_crypto(),
+        );
+
+        let (mut client, server) = tokio::io::duplex(4096);
+        let recorder = Recorder::default();
+        let mover = Mover::default();
+        let shared = SharedState::new();
+        let mut connection = ProudNetConnection::new(server, addr, handler)
+            .with_observer(recorder.clone())
+            .with_shared(shared.clone());
+        let outbox = connection.outbox();
+        let handlers: Vec<BoxedHandler> = vec![Arc::new(Echo), Arc::new(mover.clone())];
+        let server_task = tokio::spawn(async move {
+            let mut dispatcher = MessageDispatcher::with_handlers(handlers);
+            connection.run(&mut dispatcher).await.unwrap();
+            connection.context().session_id
+        });
+
+        complete_handshake(&mut client, &handshake).await;
+        assert_eq!(shared.connections.len(), 1);
+
+        let key = handshake.session_key();
+        client
+            .write_all(&testing::encrypted_message(key, 0x1001, b"ping"))
+            .await
+            .unwrap();
+        let response = read_frame(&mut client).await;
+        let mut crypto = crate::crypto::ProudNetCrypto::new();
+        crypto.set_aes_session_key(key);
+        assert_eq!(
+            crypto.decrypt_packet_0x25(&response.payload).unwrap(),
+            testing::game_message(0x1002, b"ping")
+        );
+
+        // Handlers are told which wrapper a message came in
+        let mut unreliable = testing::encrypted_payload(key, 0x1005, &[1, 2]);
+        unreliable[0] = 0x26;
+        client.write_all(&testing::frame(unreliable)).await.unwrap();
+        client
+            .write_all(&testing::encrypted_message(key, 0x1005, &[3, 4]))
+            .await
+            .unwrap();
+        client
+            .write_all(&testing::encrypted_message(key, 0x1001, b"sync"))
+            .await
+            .unwrap();
+        read_frame(&mut client).await;
+        assert_eq!(
+            *mover.0.lock().unwrap(),
+            [Reliability::Unreliable, Reliability::Reliable]
+        );
+
+        // Server-initiated messages go out without a request
+        outbox
+            .send(testing::game_message(0x1003, b"push"))
+            .await
+            .unwrap();
+        let pushed = read_frame(&mut client).await;
+        assert_eq!(
+            crypto.decrypt_packet_0x25(&pushed.payload).unwrap(),
+            testing::game_message(0x1003, b"push")
+        );
+
+        // And so do messages from other connections
+        assert_eq!(
+            shared
+                .connections
+                .broadcast(&testing::game_message(0x1003, b"hi")),
+            1
+        );
+        let pushed = read_frame(&mut client).await;
+        assert_eq!(
+            crypto.decrypt_packet_0x25(&pushed.payload).unwrap(),
+            testing::game_message(0x1003, b"hi")
+        );
+
+        drop(client);
+        assert_ne!(server_task.await.unwrap(), 0);
+        assert!(shared.connections.is_empty());
+
+        let frames = recorder.0.lock().unwrap().clone();
+        let outgoing: Vec<_> = frames
+            .iter()
+            .filter(|(d, _)| *d == Direction::ServerToClient)
+            .map(|(_, op)| *op)
+            .collect();
+        assert_eq!(
+            outgoing,
+            vec![b'<', 0x04, 0x06, 0x0A, 0x25, 0x25, 0x25, 0x25]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_server_disconnect_flushes_outbox() {
+        let handshake = Handshake::new();
+        let addr: SocketAddr = "127.0.0.1:50125".parse().unwrap();
+        let handler = ProudNetHandler::with_shared_crypto(
+            addr,
+            ProudNetSettings::default(),
+            handshake.server_crypto(),
+        );
+
+        let (mut client, server) = tokio::io::duplex(4096);
+        let (disconnect, disconnect_rx) = oneshot::channel();
+        let mut connection =
+            ProudNetConnection::new(server, addr, handler).with_disconnect(disconnect_rx);
+        let outbox = connection.outbox();
+        let server_task =
+            tokio::spawn(async move { connection.run(&mut MessageDispatcher::new()).await });
+        complete_handshake(&mut client, &handshake).await;
+
+        // The warning queued just before the kick still reaches the client
+        outbox
+            .send(testing::game_message(0x1001, b"bye"))
+            .await
+            .unwrap();
+        disconnect.send("playtime limit".to_string()).unwrap();
+        server_task.await.unwrap().unwrap();
+
+        let mut crypto = crate::crypto::ProudNetCrypto::new();
+        crypto.set_aes_session_key(handshake.session_key());
+        let warning = read_frame(&mut client).await;
+        assert_eq!(
+            crypto.decrypt_packet_0x25(&warning.payload).unwrap(),
+            testing::game_message(0x1001, b"bye")
+        );
+        let mut rest = Vec::new();
+        client.read_to_end(&mut rest).await.unwrap();
+        assert!(rest.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_kick_through_registry() {
+        let handshake = Handshake::new();
+        let addr: SocketAddr = "127.0.0.1:50126".parse().unwrap();
+        let handler = ProudNetHandler::with_shared_crypto(
+            addr,
+            ProudNetSettings::default(),
+            handshake.server_crypto(),
+        );
+
+        let (mut client, server) = tokio::io::duplex(4096);
+        let shared = SharedState::new();
+        let mut connection =
+            ProudNetConnection::new(server, addr, handler).with_shared(shared.clone());
+        let server_task =
+            tokio::spawn(async move { connection.run(&mut MessageDispatcher::new()).await });
+        complete_handshake(&mut client, &handshake).await;
+
+        let sessions = shared.connections.sessions();
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].remote_addr.as_deref(), Some("127.0.0.1:50126"));
+        assert!(shared.connections.kick(sessions[0].session_id, "kicked"));
+        server_task.await.unwrap().unwrap();
+        assert!(shared.connections.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_handshake_budget() {
+        let handshake = Handshake::new();
+        let addr: SocketAddr = "127.0.0.1:50127".parse().unwrap();
+        let config = HandshakeConfig {
+            max_per_ip: 0,
+            decrypts_per_sec: 1,
+            ..HandshakeConfig::default()
+        };
+        let budget = HandshakeBudget::with_clock(config, Clock::at_unix(1_700_000_000));
+        let connect = || {
+            let handler = ProudNetHandler::with_shared_crypto(
+                addr,
+                ProudNetSettings::default(),
+                handshake.server_crypto(),
+            );
+            let (client, server) = tokio::io::duplex(4096);
+            let mut connection = ProudNetConnection::new(server, addr, handler)
+                .with_handshake_permit(budget.admit(addr.ip()).unwrap());
+            let server_task =
+                tokio::spawn(async move { connection.run(&mut MessageDispatcher::new()).await });
+            (client, server_task)
+        };
+        let (mut first, first_task) = connect();
+        let (mut second, second_task) = connect();
+
+        // The first key in uses up the second's decryptions, and its
+        // connection gives back its place
+        complete_handshake(&mut first, &handshake).await;
+        assert_eq!(budget.in_progress(addr.ip()), 1);
+
+        second.write_all(&testing::policy_request()).await.unwrap();
+        let mut xml = vec![0u8; FLASH_POLICY_XML.len()];
+        second.read_exact(&mut xml).await.unwrap();
+        assert_eq!(read_frame(&mut second).await.opcode(), Some(0x04));
+        second
+            .write_all(&handshake.session_key_response())
+            .await
+            .unwrap();
+        let mut rest = Vec::new();
+        second.read_to_end(&mut rest).await.unwrap();
+        assert_eq!(
+            rest,
+            crate::protocol::build_busy_notice(budget::BUSY_MESSAGE)
+        );
+        second_task.await.unwrap().unwrap();
+        assert_eq!(budget.in_progress(addr.ip()), 0);
+        assert_eq!(budget.shed(), 1);
+
+        drop(first);
+        first_task.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_replayed_handshake_closes() {
+        let handshake = Handshake::new();
+        let addr: SocketAddr = "127.0.0.1:50128".parse().unwrap();
+        let guard = crate::crypto::ReplayGuard::new(Duration::from_secs(60), 100);
+        let connect = || {
+            let handler = ProudNetHandler::with_shared_crypto(
+                addr,
+                ProudNetSettings::default(),
+                handshake.server_crypto(),
+            )
+            .with_replay_guard(guard.clone());
+            let (client, server) = tokio::io::duplex(4096);
+            let mut connection = ProudNetConnection::new(server, addr, handler);
+            let server_task =
+                tokio::spawn(async move { connection.run(&mut MessageDispatcher::new()).await });
+            (client, server_task)
+        };
+        let (mut first, first_task) = connect();
+        complete_handshake(&mut first, &handshake).await;
+
+        // The same 0x05 again is hung up on without an answer
+        let (mut replay, replay_task) = connect();
+        replay
+            .write_all(&handshake.session_key_response())
+            .await
+            .unwrap();
+        let mut rest = Vec::new();
+        replay.read_to_end(&mut rest).await.unwrap();
+        assert!(rest.is_empty());
+        replay_task.await.unwrap().unwrap();
+        assert_eq!(guard.replays(), 1);
+
+        drop(first);
+        first_task.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_bad_session_key_closes() {
+        let handshake = Handshake::new();
+        let addr: SocketAddr = "127.0.0.1:50129".parse().unwrap();
+        let connect = |keep_open| {
+            let handler = ProudNetHandler::with_shared_crypto(
+                addr,
+                ProudNetSettings::default(),
+                handshake.server_crypto(),
+            );
+            let (client, server) = tokio::io::duplex(4096);
+            let mut connection =
+                ProudNetConnection::new(server, addr, handler).keep_open_on_bad_key(keep_open);
+            let server_task =
+                tokio::spawn(async move { connection.run(&mut MessageDispatcher::new()).await });
+            (client, server_task)
+        };
+        let mut bad_key = handshake.session_key_response();
+        bad_key[20] ^= 0xFF;
+
+        let (mut client, server_task) = connect(false);
+        client.write_all(&bad_key).await.unwrap();
+        let mut rest = Vec::new();
+        client.read_to_end(&mut rest).await.unwrap();
+        assert!(rest.is_empty());
+        server_task.await.unwrap().unwrap();
+
+        // The analyzer keeps listening
+        let (mut client, server_task) = connect(true);
+        client.write_all(&bad_key).await.unwrap();
+        let mut byte = [0u8; 1];
+        let read = tokio::time::timeout(Duration::from_millis(50), client.read(&mut byte)).await;
+        assert!(read.is_err());
+        assert!(!server_task.is_finished());
+        drop(client);
+        server_task.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_heartbeat_latency_and_timeout() {
+        let addr: SocketAddr = "127.0.0.1:50124".parse().unwrap();
+        let handler = ProudNetHandler::with_shared_crypto(
+            addr,
+            ProudNetSettings::default(),
+            Handshake::new().server_crypto(),
+        );
+
+        let (mut client, server) = tokio::io::duplex(4096);
+        let mut connection = ProudNetConnection::new(server, addr, handler)
+            .with_heartbeat_timeout(Some(Duration::from_millis(100)));
+        let server_task = tokio::spawn(async move {
+            connection.run(&mut MessageDispatcher::new()).await.unwrap();
+            connection.context().connection_info.latency
+        });
+
+        client.write_all(&testing::heartbeat(0x1D8)).await.unwrap();
+        let ack = read_frame(&mut client).await;
+        assert_eq!(
+            &ack.payload[..9],
+            &hex::decode("1dd801000000000000").unwrap()[..]
+        );
+
+        // The client goes quiet but keeps the socket open
+        let latency = tokio::time::timeout(Duration::from_secs(5), server_task)
+            .await
+            .expect("connection should time out")
+            .unwrap();
+        assert_eq!(latency.heartbeats, 1);
+        drop(client);
+    }
+
+    #[tokio::test]
+    async fn test_heartbeat_timeout_follows_clock() {
+        let addr: SocketAddr = "127.0.0.1:50127".parse().unwrap();
+        let clock = Clock::at_unix(1_000);
+        let handler = ProudNetHandler::with_shared_crypto(
+            addr,
+            ProudNetSettings::default(),
+            Handshake::new().server_crypto(),
+        );
+
+        let (mut client, server) = tokio::io::duplex(4096);
+        let mut connection = ProudNetConnection::new(server, addr, handler)
+            .with_shared(SharedState::new().with_clock(clock.clone()))
+            .with_heartbeat_timeout(Some(Duration::from_secs(30)));
+        let server_task =
+            tokio::spawn(async move { connection.run(&mut MessageDispatcher::new()).await });
+
+        // The server time in 0x1D is the clock's too
+        clock.advance(Duration::from_secs(29));
+        client.write_all(&testing::heartbeat(0x1D8)).await.unwrap();
+        let ack = read_frame(&mut client).await;
+        assert_eq!(ack.payload[9..17], 29_000i64.to_le_bytes());
+
+        clock.advance(Duration::from_secs(29));
+        client.write_all(&testing::heartbeat(0x1D9)).await.unwrap();
+        read_frame(&mut client).await;
+
+        // Anything from the client wakes the loop to notice the time
+        clock.advance(Duration::from_secs(30));
+        client.write_all(&testing::frame(vec![0x1C])).await.unwrap();
+        tokio::time::timeout(Duration::from_secs(5), server_task)
+            .await
+            .expect("connection should time out")
+            .unwrap()
+            .unwrap();
+    }
+}
@@ -0,0 +1,161 @@
+//! HTTP requests to the built-in endpoints
+//!
+//! The GM dashboard and the world's debug pages speak just enough HTTP/1.1
+//! to read one request. [`read_http_request`] caps the request at
+//! [`MAX_REQUEST_BYTES`] and [`MAX_HEADERS`] header lines and gives up after
+//! a timeout, so a client that never finishes its request costs at most that
+//! much before anyone checks who it is.
+
+use crate::Result;
+use anyhow::anyhow;
+use std::time::Duration;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt};
+
+/// Most bytes a request line and its headers may take together
+pub const MAX_REQUEST_BYTES: u64 = 16 * 1024;
+
+/// Most header lines a request may have
+pub const MAX_HEADERS: usize = 64;
+
+/// How long a client has to send its whole request
+pub const HTTP_REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A request line and its headers; bodies are never read
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HttpRequest {
+    pub method: String,
+    /// Path and query string, as sent
+    pub target: String,
+    /// Header names and values in order, values trimmed
+    pub headers: Vec<(String, String)>,
+}
+
+impl HttpRequest {
+    /// The first header called `name`, ignoring case
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// The target without its query string
+    pub fn path(&self) -> &str {
+        self.target
+            .split_once('?')
+            .map_or(&self.target, |(path, _)| path)
+    }
+
+    /// The query string, empty if there's none
+    pub fn query(&self) -> &str {
+        self.target.split_once('?').map_or("", |(_, query)| query)
+    }
+}
+
+/// Read one request's line and headers from `reader`, failing if it's too
+/// large or isn't all there within `timeout`
+pub async fn read_http_request<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+    timeout: Duration,
+) -> Result<HttpRequest> {
+    let mut limited = reader.take(MAX_REQUEST_BYTES);
+    tokio::time::timeout(timeout, read(&mut limited))
+        .await
+        .map_err(|_| anyhow!("no complete request within {:?}", timeout))?
+}
+
+async fn read<R: AsyncBufRead + Unpin>(reader: &mut tokio::io::Take<R>) -> Result<HttpRequest> {
+    let mut line = String::new();
+    read_line(reader, &mut line).await?;
+    let (method, target) = match line.split_whitespace().collect::<Vec<_>>()[..] {
+        [method, target, version] if version.starts_with("HTTP/1.") => {
+            (method.to_string(), target.to_string())
+        }
+        _ => return Err(anyhow!("not an HTTP request: {:?}", line.trim_end())),
+    };
+
+    let mut headers = Vec::new();
+    for _ in 0..MAX_HEADERS {
+        line.clear();
+        read_line(reader, &mut line).await?;
+        let header = line.trim_end();
+        if header.is_empty() {
+            return Ok(HttpRequest {
+                method,
+                target,
+                headers,
+            });
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            headers.push((name.to_string(), value.trim().to_string()));
+        }
+    }
+    Err(anyhow!("too many headers"))
+}
+
+/// Read one whole line, telling a request over the cap from one cut short
+async fn read_line<R: AsyncBufRead + Unpin>(
+    reader: &mut tokio::io::Take<R>,
+    line: &mut String,
+) -> Result<()> {
+    reader.read_line(line).await?;
+    if line.ends_with('\n') {
+        Ok(())
+    } else if reader.limit() == 0 {
+        Err(anyhow!("request over {} bytes", MAX_REQUEST_BYTES))
+    } else {
+        Err(anyhow!("connection closed during the request"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncWriteExt, BufReader};
+
+    async fn read_bytes(bytes: &[u8]) -> Result<HttpRequest> {
+        read_http_request(&mut BufReader::new(bytes), HTTP_REQUEST_TIMEOUT).await
+    }
+
+    #[tokio::test]
+    async fn test_read() {
+        let request = read_bytes(
+            b"GET /zones/1?pretty HTTP/1.1\r\nHost: localhost\r\nUpgrade:  websocket\r\n\r\nbody",
+        )
+        .await
+        .unwrap();
+        assert_eq!(request.method, "GET");
+        assert_eq!((request.path(), request.query()), ("/zones/1", "pretty"));
+        assert_eq!(request.header("upgrade"), Some("websocket"));
+        assert_eq!(request.header("Sec-WebSocket-Key"), None);
+
+        for bad in [
+            &b"hello\r\n\r\n"[..],
+            b"GET / HTTP/1.1\r\nHost: localhost\r\n",
+            b"GET / HTTP/1.1\r\nHost: localhost",
+        ] {
+            assert!(read_bytes(bad).await.is_err(), "{:?}", bad);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_limits() {
+        let endless = format!(
+            "GET /{} HTTP/1.1\r\n\r\n",
+            "a".repeat(MAX_REQUEST_BYTES as usize)
+        );
+        let error = read_bytes(endless.as_bytes()).await.unwrap_err();
+        assert!(error.to_string().contains("bytes"), "{}", error);
+
+        let many = format!("GET / HTTP/1.1\r\n{}\r\n", "X: y\r\n".repeat(MAX_HEADERS));
+        assert!(read_bytes(many.as_bytes()).await.is_err());
+
+        // A client that stops partway through is dropped
+        let (mut client, server) = tokio::io::duplex(1024);
+        client.write_all(b"GET / HTTP/1.1\r\n").await.unwrap();
+        let error = read_http_request(&mut BufReader::new(server), Duration::from_millis(50))
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("within"), "{}", error);
+    }
+}
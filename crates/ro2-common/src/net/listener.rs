@@ -0,0 +1,158 @@
+//! Accepting clients on several addresses at once
+//!
+//! A server can listen on more than one address (an alternate port, an
+//! IPv6 socket next to the IPv4 one, ...). [`Listeners`] binds all of them
+//! and hands out accepted connections from a single queue, each tagged with
+//! the [`ListenerConfig`] it came in on.
+//!
+//! IPv6 listeners are dual-stack unless `ipv6_only` is set. IPv4 clients of
+//! a dual-stack listener are reported with their plain IPv4 address rather
+//! than an IPv4-mapped IPv6 one, so logs, sessions and 0x0A look the same
+//! whichever listener a client used.
+
+use crate::Result;
+use crate::config::ListenerConfig;
+use anyhow::Context;
+use socket2::{Domain, Protocol, Socket, Type};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tracing::error;
+
+/// A connection accepted by [`Listeners`]
+#[derive(Debug)]
+pub struct Accepted {
+    pub stream: TcpStream,
+    pub addr: SocketAddr,
+    /// Listener the client connected to
+    pub listener: Arc<ListenerConfig>,
+}
+
+/// Every listener of a server
+pub struct Listeners {
+    local_addrs: Vec<(Arc<ListenerConfig>, SocketAddr)>,
+    accepted: mpsc::Receiver<Accepted>,
+}
+
+impl Listeners {
+    /// Bind every listener; fails if any address can't be bound
+    pub async fn bind(configs: &[ListenerConfig]) -> Result<Self> {
+        let mut bound = Vec::new();
+        for config in configs {
+            let listener = bind(config)
+                .with_context(|| format!("binding listener {} on {}", config.name, config.bind))?;
+            bound.push((Arc::new(config.clone()), listener));
+        }
+
+        let (tx, accepted) = mpsc::channel(64);
+        let mut local_addrs = Vec::new();
+        for (config, listener) in bound {
+            local_addrs.push((Arc::clone(&config), listener.local_addr()?));
+            tokio::spawn(accept_loop(listener, config, tx.clone()));
+        }
+
+        Ok(Self {
+            local_addrs,
+            accepted,
+        })
+    }
+
+    /// Each listener with the address it's bound to
+    pub fn local_addrs(&self) -> &[(Arc<ListenerConfig>, SocketAddr)] {
+        &self.local_addrs
+    }
+
+    /// Wait for the next client on any listener
+    pub async fn accept(&mut self) -> Option<Accepted> {
+        self.accepted.recv().await
+    }
+}
+
+fn bind(config: &ListenerConfig) -> Result<TcpListener> {
+    let socket = Socket::new(
+        Domain::for_address(config.bind),
+        Type::STREAM,
+        Some(Protocol::TCP),
+    )?;
+    // The OS default differs (dual-stack on Linux, IPv6-only on Windows)
+    if config.bind.is_ipv6() {
+        socket.set_only_v6(config.ipv6_only)?;
+    }
+    #[cfg(unix)]
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&config.bind.into())?;
+    socket.listen(1024)?;
+
+    Ok(TcpListener::from_std(socket.into())?)
+}
+
+async fn accept_loop(
+    listener: TcpListener,
+    config: Arc<ListenerConfig>,
+    tx: mpsc::Sender<Accepted>,
+) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, addr)) => {
+                let accepted = Accepted {
+                    stream,
+                    addr: SocketAddr::new(addr.ip().to_canonical(), addr.port()),
+                    listener: Arc::clone(&config),
+                };
+                if tx.send(accepted).await.is_err() {
+                    // Listeners was dropped
+                    return;
+                }
+            }
+            Err(e) => error!("[{}] Failed to accept connection: {}", config.name, e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn listener(name: &str) -> ListenerConfig {
+        ListenerConfig {
+            name: name.to_string(),
+            bind: "127.0.0.1:0".parse().unwrap(),
+            ipv6_only: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_connections_are_tagged() {
+        let mut listeners = Listeners::bind(&[listener("main"), listener("alt")])
+            .await
+            .unwrap();
+        let alt = listeners.local_addrs()[1].1;
+
+        let _client = TcpStream::connect(alt).await.unwrap();
+        let accepted = listeners.accept().await.unwrap();
+        assert_eq!(accepted.listener.name, "alt");
+        assert_eq!(accepted.stream.local_addr().unwrap(), alt);
+    }
+
+    #[tokio::test]
+    async fn test_dual_stack_reports_ipv4() {
+        let config = ListenerConfig {
+            bind: "[::]:0".parse().unwrap(),
+            ..listener("dual")
+        };
+        let Ok(mut listeners) = Listeners::bind(&[config]).await else {
+            // No IPv6 on this host
+            return;
+        };
+        let port = listeners.local_addrs()[0].1.port();
+
+        let _client = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        let accepted = listeners.accept().await.unwrap();
+        assert_eq!(
+            accepted.addr.ip(),
+            "127.0.0.1".parse::<std::net::IpAddr>().unwrap()
+        );
+    }
+}
@@ -0,0 +1,60 @@
+//! Networking shared by the servers and tools
+//!
+//! [`Direction`] labels traffic everywhere (fixtures, captures, live
+//! connections) and [`FrameBuffer`] cuts a byte stream into frames. With the
+//! `server` feature, [`Listeners`] accepts clients on every configured
+//! address, [`ProudNetConnection`] runs the ProudNet layer of a client
+//! connection, [`HandshakeBudget`] caps what handshakes cost the server and
+//! [`read_http_request`] reads requests to the built-in HTTP endpoints.
+//! [`ConnectionRegistry`] holds every connected session's outbox so
+//! handlers can message other players. With the `client` feature,
+//! `ProudNetClient` connects to a server the way the game does and `bot`
+//! scripts what it sends once connected.
+
+#[cfg(feature = "client")]
+pub mod bot;
+#[cfg(feature = "server")]
+mod budget;
+mod buffer;
+#[cfg(feature = "client")]
+mod client;
+#[cfg(feature = "server")]
+mod connection;
+#[cfg(feature = "server")]
+mod http;
+#[cfg(feature = "server")]
+mod listener;
+mod registry;
+
+#[cfg(feature = "server")]
+pub use budget::{BUSY_MESSAGE, HandshakeBudget, HandshakeConfig, HandshakePermit, Shed, refuse};
+pub use buffer::{Chunk, FrameBuffer};
+#[cfg(feature = "client")]
+pub use client::ProudNetClient;
+#[cfg(feature = "server")]
+pub use connection::{FrameObserver, ProudNetConnection};
+#[cfg(feature = "server")]
+pub use http::{
+    HTTP_REQUEST_TIMEOUT, HttpRequest, MAX_HEADERS, MAX_REQUEST_BYTES, read_http_request,
+};
+#[cfg(feature = "server")]
+pub use listener::{Accepted, Listeners};
+pub use registry::{ConnectionRegistry, SessionSummary};
+
+use std::fmt;
+
+/// Direction of traffic relative to the server
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Direction {
+    ClientToServer,
+    ServerToClient,
+}
+
+impl fmt::Display for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Direction::ClientToServer => f.write_str("C->S"),
+            Direction::ServerToClient => f.write_str("S->C"),
+        }
+    }
+}
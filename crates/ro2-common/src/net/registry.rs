@@ -0,0 +1,237 @@
+//! Every connected session's outbox
+//!
+//! [`ConnectionRegistry`] lets a handler send game messages to players
+//! other than the one it's answering. `ProudNetConnection` registers its
+//! outbox once the client has a session ID and removes it on disconnect.
+//! It also registers where the client connected from and a way to close
+//! the connection, so operators can list and [`kick`](ConnectionRegistry::kick)
+//! sessions, and checks [`wire_trace`](ConnectionRegistry::wire_trace) to
+//! decide whether to log every frame.
+
+use std::collections::HashMap;
+use std::sync::RwLock;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tokio::sync::mpsc;
+
+/// A connected session, as listed to operators
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionSummary {
+    pub session_id: u64,
+    pub remote_addr: Option<String>,
+    pub account_id: Option<u32>,
+}
+
+struct Entry {
+    outbox: mpsc::Sender<Vec<u8>>,
+    remote_addr: Option<String>,
+    account_id: Option<u32>,
+    /// Closes the connection with a reason
+    kick: Option<mpsc::Sender<String>>,
+}
+
+/// Outboxes of the connected sessions, by session ID
+#[derive(Default)]
+pub struct ConnectionRegistry {
+    outboxes: RwLock<HashMap<u64, Entry>>,
+    wire_trace: AtomicBool,
+}
+
+impl ConnectionRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Send game messages (u16 opcode + payload) for `session_id` to
+    /// `outbox`, replacing any earlier one
+    pub fn register(&self, session_id: u64, outbox: mpsc::Sender<Vec<u8>>) {
+        self.insert(
+            session_id,
+            Entry {
+                outbox,
+                remote_addr: None,
+                account_id: None,
+                kick: None,
+            },
+        );
+    }
+
+    /// [`register`](Self::register) a connection from `remote_addr` that
+    /// closes when a reason is sent on `kick`
+    pub fn register_connection(
+        &self,
+        session_id: u64,
+        outbox: mpsc::Sender<Vec<u8>>,
+        remote_addr: String,
+        kick: mpsc::Sender<String>,
+    ) {
+        self.insert(
+            session_id,
+            Entry {
+                outbox,
+                remote_addr: Some(remote_addr),
+                account_id: None,
+                kick: Some(kick),
+            },
+        );
+    }
+
+    fn insert(&self, session_id: u64, entry: Entry) {
+        self.outboxes
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(session_id, entry);
+    }
+
+    pub fn unregister(&self, session_id: u64) {
+        self.outboxes
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&session_id);
+    }
+
+    /// Note the account `session_id` logged in as
+    pub fn set_account(&self, session_id: u64, account_id: u32) {
+        if let Some(entry) = self
+            .outboxes
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .get_mut(&session_id)
+        {
+            entry.account_id = Some(account_id);
+        }
+    }
+
+    pub fn is_connected(&self, session_id: u64) -> bool {
+        self.outboxes
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .contains_key(&session_id)
+    }
+
+    /// Queue `message` for `session_id`; returns whether it was queued. A
+    /// full outbox drops the message rather than stall the sender.
+    pub fn send(&self, session_id: u64, message: Vec<u8>) -> bool {
+        let outboxes = self.outboxes.read().unwrap_or_else(|e| e.into_inner());
+        outboxes
+            .get(&session_id)
+            .is_some_and(|entry| entry.outbox.try_send(message).is_ok())
+    }
+
+    /// Queue `message` for every session; returns how many it was queued
+    /// for
+    pub fn broadcast(&self, message: &[u8]) -> usize {
+        let outboxes = self.outboxes.read().unwrap_or_else(|e| e.into_inner());
+        outboxes
+            .values()
+            .filter(|entry| entry.outbox.try_send(message.to_vec()).is_ok())
+            .count()
+    }
+
+    /// Close `session_id`'s connection once its outbox is flushed; returns
+    /// whether it could be asked to
+    pub fn kick(&self, session_id: u64, reason: &str) -> bool {
+        let outboxes = self.outboxes.read().unwrap_or_else(|e| e.into_inner());
+        outboxes
+            .get(&session_id)
+            .and_then(|entry| entry.kick.as_ref())
+            .is_some_and(|kick| kick.try_send(reason.to_string()).is_ok())
+    }
+
+    /// Every registered session, by session ID
+    pub fn sessions(&self) -> Vec<SessionSummary> {
+        let outboxes = self.outboxes.read().unwrap_or_else(|e| e.into_inner());
+        let mut sessions: Vec<_> = outboxes
+            .iter()
+            .map(|(&session_id, entry)| SessionSummary {
+                session_id,
+                remote_addr: entry.remote_addr.clone(),
+                account_id: entry.account_id,
+            })
+            .collect();
+        sessions.sort_by_key(|session| session.session_id);
+        sessions
+    }
+
+    /// Whether connections log every frame they send and receive
+    pub fn wire_trace(&self) -> bool {
+        self.wire_trace.load(Ordering::Relaxed)
+    }
+
+    pub fn set_wire_trace(&self, on: bool) {
+        self.wire_trace.store(on, Ordering::Relaxed);
+    }
+
+    /// Sessions registered
+    pub fn len(&self) -> usize {
+        self.outboxes
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_send_and_broadcast() {
+        let registry = ConnectionRegistry::new();
+        let (one, mut one_rx) = mpsc::channel(1);
+        let (two, mut two_rx) = mpsc::channel(4);
+        registry.register(1, one);
+        registry.register(2, two);
+        assert_eq!(registry.len(), 2);
+
+        assert!(registry.send(1, vec![1]));
+        assert!(!registry.send(3, vec![3]));
+        assert_eq!(one_rx.try_recv().unwrap(), [1]);
+
+        // Session 1's outbox is full after this; it misses the second
+        assert_eq!(registry.broadcast(&[2]), 2);
+        assert_eq!(registry.broadcast(&[4]), 1);
+        assert_eq!(one_rx.try_recv().unwrap(), [2]);
+        assert_eq!(two_rx.try_recv().unwrap(), [2]);
+        assert_eq!(two_rx.try_recv().unwrap(), [4]);
+
+        registry.unregister(1);
+        assert!(!registry.is_connected(1));
+        assert!(registry.is_connected(2));
+    }
+
+    #[test]
+    fn test_list_and_kick_sessions() {
+        let registry = ConnectionRegistry::new();
+        let (outbox, _outbox_rx) = mpsc::channel(1);
+        let (kick, mut kick_rx) = mpsc::channel(1);
+        registry.register_connection(7, outbox.clone(), "127.0.0.1:50000".to_string(), kick);
+        registry.register(3, outbox);
+        registry.set_account(7, 42);
+
+        assert_eq!(
+            registry.sessions(),
+            [
+                SessionSummary {
+                    session_id: 3,
+                    remote_addr: None,
+                    account_id: None,
+                },
+                SessionSummary {
+                    session_id: 7,
+                    remote_addr: Some("127.0.0.1:50000".to_string()),
+                    account_id: Some(42),
+                },
+            ]
+        );
+
+        assert!(registry.kick(7, "spamming"));
+        assert_eq!(kick_rx.try_recv().unwrap(), "spamming");
+        // Nothing to close without a kick channel
+        assert!(!registry.kick(3, "spamming"));
+        assert!(!registry.kick(8, "spamming"));
+    }
+}
@@ -12,6 +12,7 @@
 //! ```
 
 use crate::Result;
+use crate::wire::WireReader;
 use bytes::{Buf, BufMut};
 use std::io::Cursor;
 
@@ -82,20 +83,15 @@ impl PacketFrame {
 
     /// Deserialize a packet frame from bytes
     ///
-    /// Returns the packet frame and the number of bytes consumed.
+    /// Returns the packet frame and the number of bytes consumed. If `data`
+    /// is only the start of a frame the error is a
+    /// [`WireError::Truncated`](crate::wire::WireError::Truncated), so a
+    /// caller can wait for the rest.
     pub fn from_bytes(data: &[u8]) -> Result<(Self, usize)> {
-        if data.len() < MIN_PACKET_SIZE {
-            return Err(anyhow::anyhow!(
-                "Packet too short: {} bytes (need at least {})",
-                data.len(),
-                MIN_PACKET_SIZE
-            ));
-        }
-
-        let mut cursor = Cursor::new(data);
+        let mut r = WireReader::new(data);
 
         // Read magic
-        let magic = cursor.get_u16_le();
+        let magic = r.u16("magic")?;
         if magic != PACKET_MAGIC {
             return Err(anyhow::anyhow!(
                 "Invalid packet magic: 0x{:04x} (expected 0x{:04x})",
@@ -105,7 +101,7 @@ impl PacketFrame {
         }
 
         // Read payload size (varint)
-        let payload_size = read_varint(&mut cursor)? as usize;
+        let payload_size = varint(&mut r)? as usize;
 
         // Validate payload size
         if payload_size > MAX_PACKET_SIZE {
@@ -116,24 +112,8 @@ impl PacketFrame {
             ));
         }
 
-        let offset = cursor.position() as usize;
-
-        // Check if we have enough data for payload
-        if data.len() < offset + payload_size {
-            return Err(anyhow::anyhow!(
-                "Incomplete packet: need {} bytes, have {}",
-                offset + payload_size,
-                data.len()
-            ));
-        }
-
-        // Extract payload
-        let payload = data[offset..offset + payload_size].to_vec();
-
-        let packet = Self { magic, payload };
-        let total_size = offset + payload_size;
-
-        Ok((packet, total_size))
+        let payload = r.bytes("payload", payload_size)?.to_vec();
+        Ok((Self { magic, payload }, r.position()))
     }
 
     /// Try to parse multiple packets from a buffer
@@ -185,38 +165,27 @@ pub fn write_varint(buf: &mut Vec<u8>, value: u32) {
 /// - 1 byte: size_byte (1, 2, or 4)
 /// - N bytes: value (little endian)
 pub fn read_varint(cursor: &mut Cursor<&[u8]>) -> Result<u32> {
-    if !cursor.has_remaining() {
-        return Err(anyhow::anyhow!("No data for varint size byte"));
-    }
-
-    let size_byte = cursor.get_u8();
+    let mut r = WireReader::new(cursor.chunk());
+    let value = varint(&mut r)?;
+    let size = r.position();
+    cursor.advance(size);
+    Ok(value)
+}
 
-    match size_byte {
-        1 => {
-            if !cursor.has_remaining() {
-                return Err(anyhow::anyhow!("Not enough data for 1-byte varint"));
-            }
-            Ok(cursor.get_u8() as u32)
-        }
-        2 => {
-            if cursor.remaining() < 2 {
-                return Err(anyhow::anyhow!("Not enough data for 2-byte varint"));
-            }
-            Ok(cursor.get_u16_le() as u32)
-        }
-        4 => {
-            if cursor.remaining() < 4 {
-                return Err(anyhow::anyhow!("Not enough data for 4-byte varint"));
-            }
-            Ok(cursor.get_u32_le())
-        }
-        _ => Err(anyhow::anyhow!("Invalid varint size byte: {}", size_byte)),
+fn varint(r: &mut WireReader) -> Result<u32> {
+    match r.u8("varint size")? {
+        1 => Ok(r.u8("varint")? as u32),
+        2 => Ok(r.u16("varint")? as u32),
+        4 => Ok(r.u32("varint")?),
+        size => Err(anyhow::anyhow!("Invalid varint size byte: {}", size)),
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::wire::WireError;
+    use proptest::prelude::*;
 
     #[test]
     fn test_varint_roundtrip() {
@@ -280,12 +249,14 @@ mod tests {
         let data = hex::decode("13570164").unwrap(); // Claims 100 bytes but none present
         let result = PacketFrame::from_bytes(&data);
 
-        assert!(result.is_err());
-        assert!(
-            result
-                .unwrap_err()
-                .to_string()
-                .contains("Incomplete packet")
+        assert_eq!(
+            result.unwrap_err().downcast_ref::<WireError>(),
+            Some(&WireError::Truncated {
+                field: "payload",
+                offset: 4,
+                wanted: 100,
+                left: 0
+            })
         );
     }
 
@@ -309,4 +280,79 @@ mod tests {
         assert_eq!(packet.opcode(), Some(0x25));
         assert_eq!(packet.opcode_u16(), Some(0x0125));
     }
+
+    /// Payload sizes, weighted to either side of the 1/2/4-byte varint
+    /// widths where length bugs tend to be
+    fn payload_len() -> impl Strategy<Value = usize> {
+        prop_oneof![0..64usize, 0xF0..0x110usize, 0xFFF0..=MAX_PACKET_SIZE,]
+    }
+
+    fn payload() -> impl Strategy<Value = Vec<u8>> {
+        (payload_len(), any::<u8>())
+            .prop_map(|(len, seed)| (0..len).map(|i| seed.wrapping_add(i as u8)).collect())
+    }
+
+    proptest! {
+        #[test]
+        fn prop_varint_roundtrip(value in prop_oneof![
+            any::<u32>(),
+            0xF0..0x110u32,
+            0xFFF0..0x10010u32,
+        ]) {
+            let mut buf = Vec::new();
+            write_varint(&mut buf, value);
+            let width = match value {
+                0..=0xFF => 1,
+                0x100..=0xFFFF => 2,
+                _ => 4,
+            };
+            prop_assert_eq!(buf.len(), 1 + width);
+
+            let mut cursor = Cursor::new(buf.as_slice());
+            prop_assert_eq!(read_varint(&mut cursor).unwrap(), value);
+            prop_assert_eq!(cursor.position() as usize, buf.len());
+        }
+
+        #[test]
+        fn prop_frame_roundtrip(payload in payload(), cut in any::<prop::sample::Index>()) {
+            let frame = PacketFrame::new(payload);
+            let bytes = frame.to_bytes();
+            let (parsed, size) = PacketFrame::from_bytes(&bytes).unwrap();
+            prop_assert_eq!(size, bytes.len());
+            prop_assert_eq!(parsed, frame);
+
+            // Any shorter prefix is incomplete, never a different frame
+            let error = PacketFrame::from_bytes(&bytes[..cut.index(bytes.len())]).unwrap_err();
+            prop_assert!(
+                matches!(error.downcast_ref(), Some(WireError::Truncated { .. })),
+                "{}",
+                error
+            );
+        }
+
+        #[test]
+        fn prop_garbage_never_panics(data in prop::collection::vec(any::<u8>(), 0..64)) {
+            let mut framed = PACKET_MAGIC_BYTES.to_vec();
+            framed.extend_from_slice(&data);
+            let _ = PacketFrame::from_bytes(&data);
+            let _ = PacketFrame::parse_multiple(&framed);
+            let _ = read_varint(&mut Cursor::new(data.as_slice()));
+        }
+
+        #[test]
+        fn prop_parse_multiple_roundtrip(
+            payloads in prop::collection::vec(prop::collection::vec(any::<u8>(), 0..0x110), 0..8),
+            cut in 0..4usize,
+        ) {
+            let frames: Vec<_> = payloads.into_iter().map(PacketFrame::new).collect();
+            let mut bytes: Vec<u8> = frames.iter().flat_map(|f| f.to_bytes()).collect();
+            let whole = bytes.len();
+            // A partial frame at the end is left for the next read
+            bytes.extend_from_slice(&PacketFrame::new(vec![0x25; 300]).to_bytes()[..cut]);
+
+            let (parsed, consumed) = PacketFrame::parse_multiple(&bytes).unwrap();
+            prop_assert_eq!(parsed, frames);
+            prop_assert_eq!(consumed, whole);
+        }
+    }
 }
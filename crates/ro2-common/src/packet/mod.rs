@@ -8,9 +8,10 @@ pub mod parser;
 
 pub use framing::{PACKET_MAGIC, PacketFrame, read_varint, write_varint};
 
-use bytes::{Buf, BufMut, BytesMut};
+use crate::wire::WireReader;
+use bytes::{BufMut, BytesMut};
 use serde::{Deserialize, Serialize};
-use std::net::Ipv4Addr;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 
 /// PacketHeader (16 bytes)
 ///
@@ -22,6 +23,9 @@ pub struct PacketHeader {
     pub vtable: u32,
 
     /// Source IPv4 address
+    ///
+    /// The client's layout only has room for IPv4; see [`Self::from_addr`]
+    /// for IPv6 peers.
     pub source_ip: Ipv4Addr,
 
     /// Source TCP/UDP port
@@ -53,6 +57,22 @@ impl PacketHeader {
         }
     }
 
+    /// Create a PacketHeader for a peer address
+    ///
+    /// IPv4-mapped IPv6 addresses (IPv4 clients on a dual-stack listener)
+    /// are stored as IPv4. Other IPv6 addresses don't fit in the header.
+    pub fn from_addr(addr: SocketAddr, host_id: u32) -> crate::Result<Self> {
+        match addr.ip().to_canonical() {
+            IpAddr::V4(ip) => Ok(Self::new(ip, addr.port(), host_id)),
+            IpAddr::V6(ip) => anyhow::bail!("PacketHeader can't hold IPv6 address {}", ip),
+        }
+    }
+
+    /// Source address and port
+    pub fn source_addr(&self) -> SocketAddr {
+        SocketAddr::from((self.source_ip, self.source_port))
+    }
+
     /// Serialize to bytes (little-endian)
     pub fn to_bytes(&self) -> Vec<u8> {
         let mut buf = BytesMut::with_capacity(Self::SIZE);
@@ -66,26 +86,15 @@ impl PacketHeader {
     }
 
     /// Deserialize from bytes (little-endian)
-    pub fn from_bytes(mut data: &[u8]) -> crate::Result<Self> {
-        if data.len() < Self::SIZE {
-            anyhow::bail!("Insufficient data for PacketHeader");
-        }
-
-        let vtable = data.get_u32_le();
-        let ip_bytes = [data.get_u8(), data.get_u8(), data.get_u8(), data.get_u8()];
-        let source_ip = Ipv4Addr::from(ip_bytes);
-        let source_port = data.get_u16_le();
-        let address_flags = data.get_u8();
-        let reserved = data.get_u8();
-        let host_id = data.get_u32_le();
-
+    pub fn from_bytes(data: &[u8]) -> crate::Result<Self> {
+        let mut r = WireReader::new(data);
         Ok(Self {
-            vtable,
-            source_ip,
-            source_port,
-            address_flags,
-            reserved,
-            host_id,
+            vtable: r.u32("vtable")?,
+            source_ip: Ipv4Addr::from(r.array::<4>("source IP")?),
+            source_port: r.u16("source port")?,
+            address_flags: r.u8("address flags")?,
+            reserved: r.u8("reserved")?,
+            host_id: r.u32("host ID")?,
         })
     }
 }
@@ -184,6 +193,7 @@ impl CompletePacket {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use proptest::prelude::*;
 
     #[test]
     fn test_packet_header_size() {
@@ -205,4 +215,29 @@ mod tests {
         assert_eq!(deserialized.source_port, header.source_port);
         assert_eq!(deserialized.host_id, header.host_id);
     }
+
+    #[test]
+    fn test_packet_header_from_addr() {
+        let header =
+            PacketHeader::from_addr("[::ffff:10.0.0.7]:50123".parse().unwrap(), 1).unwrap();
+        assert_eq!(header.source_addr(), "10.0.0.7:50123".parse().unwrap());
+
+        assert!(PacketHeader::from_addr("[2001:db8::1]:50123".parse().unwrap(), 1).is_err());
+    }
+
+    proptest! {
+        #[test]
+        fn prop_packet_header_roundtrip(bytes in prop::array::uniform16(any::<u8>())) {
+            let header = PacketHeader::from_bytes(&bytes).unwrap();
+            prop_assert_eq!(header.to_bytes(), bytes.to_vec());
+        }
+
+        #[test]
+        fn prop_truncated_packet_header_fails(
+            bytes in prop::array::uniform16(any::<u8>()),
+            cut in 0..PacketHeader::SIZE,
+        ) {
+            prop_assert!(PacketHeader::from_bytes(&bytes[..cut]).is_err());
+        }
+    }
 }
@@ -4,7 +4,8 @@
 //! ProudNet protocol structure discovered through Ghidra analysis.
 
 use crate::protocol::MessageType;
-use bytes::{Buf, Bytes};
+use crate::wire::WireReader;
+use bytes::Bytes;
 
 /// Parsed ProudNet RMI message
 #[derive(Debug, Clone)]
@@ -46,34 +47,13 @@ impl RmiMessage {
     /// 0x10   | N    | payload
     /// ```
     pub fn parse(data: &[u8]) -> crate::Result<Self> {
-        if data.len() < Self::HEADER_SIZE {
-            anyhow::bail!(
-                "Packet too short: expected at least {} bytes, got {}",
-                Self::HEADER_SIZE,
-                data.len()
-            );
-        }
-
-        let mut buf = Bytes::copy_from_slice(data);
-
-        let magic = buf.get_u32_le();
-        let length = buf.get_u32_le();
-        let message_id = buf.get_u16_le();
-        let flags = buf.get_u16_le();
-        let sequence = buf.get_u32_le();
-
-        // Validate length
-        let expected_total = Self::HEADER_SIZE as u32 + length;
-        if data.len() < expected_total as usize {
-            anyhow::bail!(
-                "Incomplete packet: header claims {} bytes, but only {} available",
-                expected_total,
-                data.len()
-            );
-        }
-
-        // Extract payload
-        let payload = buf.slice(..length as usize);
+        let mut r = WireReader::new(data);
+        let magic = r.u32("magic")?;
+        let length = r.u32("length")?;
+        let message_id = r.u16("message ID")?;
+        let flags = r.u16("flags")?;
+        let sequence = r.u32("sequence")?;
+        let payload = r.bytes("payload", length as usize)?;
 
         Ok(Self {
             magic,
@@ -81,7 +61,7 @@ impl RmiMessage {
             message_id,
             flags,
             sequence,
-            payload,
+            payload: Bytes::copy_from_slice(payload),
         })
     }
 
@@ -177,6 +157,7 @@ impl RmiMessageBuilder {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use proptest::prelude::*;
 
     #[test]
     fn test_parse_rmi_message() {
@@ -222,5 +203,46 @@ mod tests {
         let data = vec![0u8; 8]; // Less than HEADER_SIZE
         let result = RmiMessage::parse(&data);
         assert!(result.is_err());
+
+        // A length too big to add to the header size
+        let mut data = vec![0u8; RmiMessage::HEADER_SIZE];
+        data[4..8].copy_from_slice(&u32::MAX.to_le_bytes());
+        let error = RmiMessage::parse(&data).unwrap_err();
+        assert!(
+            error.to_string().starts_with("payload truncated"),
+            "{}",
+            error
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn prop_rmi_roundtrip(
+            message_id in any::<u16>(),
+            sequence in any::<u32>(),
+            payload in prop::collection::vec(any::<u8>(), 0..0x110),
+        ) {
+            let message = RmiMessageBuilder::new(message_id, sequence)
+                .payload(&payload)
+                .build();
+            let bytes = message.to_bytes();
+            prop_assert_eq!(bytes.len(), RmiMessage::HEADER_SIZE + payload.len());
+
+            let parsed = RmiMessage::parse(&bytes).unwrap();
+            prop_assert_eq!(parsed.message_id, message_id);
+            prop_assert_eq!(parsed.sequence, sequence);
+            prop_assert_eq!(parsed.flags, message.flags);
+            prop_assert_eq!(&parsed.payload[..], &payload[..]);
+            prop_assert_eq!(&parsed.to_bytes(), &bytes);
+
+            for cut in 0..bytes.len() {
+                prop_assert!(RmiMessage::parse(&bytes[..cut]).is_err());
+            }
+        }
+
+        #[test]
+        fn prop_garbage_never_panics(data in prop::collection::vec(any::<u8>(), 0..64)) {
+            let _ = RmiMessage::parse(&data);
+        }
     }
 }
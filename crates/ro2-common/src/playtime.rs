@@ -0,0 +1,355 @@
+//! Per-account playtime limits
+//!
+//! An account can have a daily playtime cap and a window of hours it may
+//! play in (server local time). Accounts without a row in
+//! `account_playtime_limits` are unrestricted and their playtime isn't
+//! tracked. The login server refuses restricted accounts outright; the
+//! world server re-checks online ones periodically, warns them as the end
+//! approaches and logs them out when it's reached.
+
+use crate::Result;
+use crate::database::queries::PlaytimeQueries;
+use async_trait::async_trait;
+use chrono::{NaiveDate, NaiveDateTime, NaiveTime};
+use sqlx::{Pool, Sqlite};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Hours an account may play in; `end` before `start` wraps past midnight
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AllowedHours {
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+}
+
+impl AllowedHours {
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        if self.start <= self.end {
+            (self.start..self.end).contains(&time)
+        } else {
+            time >= self.start || time < self.end
+        }
+    }
+
+    /// Time left in the window at `time`, which must be inside it
+    fn remaining(&self, time: NaiveTime) -> Duration {
+        let to_end = (self.end - time).num_seconds();
+        let to_end = if to_end > 0 { to_end } else { to_end + 86_400 };
+        Duration::from_secs(to_end as u64)
+    }
+}
+
+/// Restrictions on one account
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PlaytimeLimits {
+    pub daily_cap: Option<Duration>,
+    pub allowed_hours: Option<AllowedHours>,
+}
+
+/// Why an account can't play
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Restriction {
+    DailyCapReached,
+    OutsideAllowedHours,
+}
+
+impl Restriction {
+    /// Localization key of the system message sent before logging the
+    /// player out
+    pub fn message_key(&self) -> &'static str {
+        match self {
+            Self::DailyCapReached => "playtime.daily_cap_reached",
+            Self::OutsideAllowedHours => "playtime.outside_allowed_hours",
+        }
+    }
+}
+
+/// Whether an account may play
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    /// Allowed for `remaining` more, or indefinitely with `None`
+    Allowed {
+        remaining: Option<Duration>,
+    },
+    Denied(Restriction),
+}
+
+impl PlaytimeLimits {
+    /// Check the limits at `now`, with `played_today` already played
+    pub fn check(&self, now: NaiveDateTime, played_today: Duration) -> Verdict {
+        let mut remaining = None;
+
+        if let Some(cap) = self.daily_cap {
+            match cap.checked_sub(played_today) {
+                Some(left) if !left.is_zero() => remaining = Some(left),
+                _ => return Verdict::Denied(Restriction::DailyCapReached),
+            }
+        }
+        if let Some(hours) = self.allowed_hours
+            && hours.start != hours.end
+        {
+            if !hours.contains(now.time()) {
+                return Verdict::Denied(Restriction::OutsideAllowedHours);
+            }
+            let left = hours.remaining(now.time());
+            remaining = Some(remaining.map_or(left, |r: Duration| r.min(left)));
+        }
+
+        Verdict::Allowed { remaining }
+    }
+}
+
+/// Where limits and playtime are kept
+#[async_trait]
+pub trait PlaytimeStore: Send + Sync {
+    /// The account's limits, or `None` if it's unrestricted
+    async fn limits(&self, account_id: i64) -> Result<Option<PlaytimeLimits>>;
+
+    /// Time played on `day`
+    async fn played(&self, account_id: i64, day: NaiveDate) -> Result<Duration>;
+
+    async fn add_played(&self, account_id: i64, day: NaiveDate, played: Duration) -> Result<()>;
+}
+
+/// Check whether `account_id` may log in at `now`
+pub async fn check_login(
+    store: &dyn PlaytimeStore,
+    account_id: i64,
+    now: NaiveDateTime,
+) -> Result<Verdict> {
+    let Some(limits) = store.limits(account_id).await? else {
+        return Ok(Verdict::Allowed { remaining: None });
+    };
+    let played = store.played(account_id, now.date()).await?;
+    Ok(limits.check(now, played))
+}
+
+/// Minutes after midnight as a time of day
+fn minute_of_day(minute: i64) -> Option<NaiveTime> {
+    let minute = u32::try_from(minute).ok()?;
+    NaiveTime::from_hms_opt(minute / 60, minute % 60, 0)
+}
+
+#[async_trait]
+impl PlaytimeStore for Pool<Sqlite> {
+    async fn limits(&self, account_id: i64) -> Result<Option<PlaytimeLimits>> {
+        let Some(row) = PlaytimeQueries::limits(self, account_id).await? else {
+            return Ok(None);
+        };
+        let allowed_hours = match (row.allowed_start_minute, row.allowed_end_minute) {
+            (Some(start), Some(end)) => Some(AllowedHours {
+                start: minute_of_day(start)
+                    .ok_or_else(|| anyhow::anyhow!("Bad allowed_start_minute {}", start))?,
+                end: minute_of_day(end)
+                    .ok_or_else(|| anyhow::anyhow!("Bad allowed_end_minute {}", end))?,
+            }),
+            _ => None,
+        };
+        Ok(Some(PlaytimeLimits {
+            daily_cap: row
+                .daily_cap_minutes
+                .map(|minutes| Duration::from_secs(minutes.max(0) as u64 * 60)),
+            allowed_hours,
+        }))
+    }
+
+    async fn played(&self, account_id: i64, day: NaiveDate) -> Result<Duration> {
+        let seconds = PlaytimeQueries::seconds_played(self, account_id, &day.to_string()).await?;
+        Ok(Duration::from_secs(seconds.max(0) as u64))
+    }
+
+    async fn add_played(&self, account_id: i64, day: NaiveDate, played: Duration) -> Result<()> {
+        PlaytimeQueries::add_seconds_played(
+            self,
+            account_id,
+            &day.to_string(),
+            played.as_secs() as i64,
+        )
+        .await
+    }
+}
+
+/// Keeps everything in memory, for tests and servers without a database
+#[derive(Debug, Default)]
+pub struct MemoryPlaytimeStore {
+    limits: Mutex<HashMap<i64, PlaytimeLimits>>,
+    played: Mutex<HashMap<(i64, NaiveDate), Duration>>,
+}
+
+impl MemoryPlaytimeStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_limits(&self, account_id: i64, limits: PlaytimeLimits) {
+        self.limits.lock().unwrap().insert(account_id, limits);
+    }
+}
+
+#[async_trait]
+impl PlaytimeStore for MemoryPlaytimeStore {
+    async fn limits(&self, account_id: i64) -> Result<Option<PlaytimeLimits>> {
+        Ok(self.limits.lock().unwrap().get(&account_id).copied())
+    }
+
+    async fn played(&self, account_id: i64, day: NaiveDate) -> Result<Duration> {
+        let played = self.played.lock().unwrap();
+        Ok(played.get(&(account_id, day)).copied().unwrap_or_default())
+    }
+
+    async fn add_played(&self, account_id: i64, day: NaiveDate, played: Duration) -> Result<()> {
+        *self
+            .played
+            .lock()
+            .unwrap()
+            .entry((account_id, day))
+            .or_default() += played;
+        Ok(())
+    }
+}
+
+/// Split the time from `since` to `now` by calendar day
+pub fn played_by_day(since: NaiveDateTime, now: NaiveDateTime) -> Vec<(NaiveDate, Duration)> {
+    let mut days = Vec::new();
+    let mut from = since;
+    while from < now {
+        let midnight = from.date().and_time(NaiveTime::MIN) + DAY;
+        let to = midnight.min(now);
+        days.push((from.date(), (to - from).to_std().unwrap_or_default()));
+        from = to;
+    }
+    days
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(hour: u32, minute: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2025, 3, 1)
+            .unwrap()
+            .and_hms_opt(hour, minute, 0)
+            .unwrap()
+    }
+
+    fn hours(start: u32, end: u32) -> Option<AllowedHours> {
+        Some(AllowedHours {
+            start: NaiveTime::from_hms_opt(start, 0, 0).unwrap(),
+            end: NaiveTime::from_hms_opt(end, 0, 0).unwrap(),
+        })
+    }
+
+    #[test]
+    fn test_daily_cap() {
+        let limits = PlaytimeLimits {
+            daily_cap: Some(Duration::from_secs(2 * 3600)),
+            allowed_hours: None,
+        };
+        assert_eq!(
+            limits.check(at(12, 0), Duration::from_secs(3600)),
+            Verdict::Allowed {
+                remaining: Some(Duration::from_secs(3600))
+            }
+        );
+        assert_eq!(
+            limits.check(at(12, 0), Duration::from_secs(2 * 3600)),
+            Verdict::Denied(Restriction::DailyCapReached)
+        );
+        assert_eq!(
+            PlaytimeLimits::default().check(at(3, 0), Duration::from_secs(99 * 3600)),
+            Verdict::Allowed { remaining: None }
+        );
+    }
+
+    #[test]
+    fn test_allowed_hours() {
+        let evenings = PlaytimeLimits {
+            daily_cap: Some(Duration::from_secs(3 * 3600)),
+            allowed_hours: hours(18, 21),
+        };
+        assert_eq!(
+            evenings.check(at(17, 59), Duration::ZERO),
+            Verdict::Denied(Restriction::OutsideAllowedHours)
+        );
+        // The window ends before the cap does
+        assert_eq!(
+            evenings.check(at(20, 30), Duration::ZERO),
+            Verdict::Allowed {
+                remaining: Some(Duration::from_secs(30 * 60))
+            }
+        );
+
+        let nights = PlaytimeLimits {
+            daily_cap: None,
+            allowed_hours: hours(22, 2),
+        };
+        assert_eq!(
+            nights.check(at(23, 0), Duration::ZERO),
+            Verdict::Allowed {
+                remaining: Some(Duration::from_secs(3 * 3600))
+            }
+        );
+        assert_eq!(
+            nights.check(at(1, 0), Duration::ZERO),
+            Verdict::Allowed {
+                remaining: Some(Duration::from_secs(3600))
+            }
+        );
+        assert_eq!(
+            nights.check(at(12, 0), Duration::ZERO),
+            Verdict::Denied(Restriction::OutsideAllowedHours)
+        );
+    }
+
+    #[test]
+    fn test_played_by_day() {
+        let days = played_by_day(at(23, 30), at(23, 30) + Duration::from_secs(3600));
+        assert_eq!(
+            days,
+            [
+                (at(0, 0).date(), Duration::from_secs(30 * 60)),
+                (
+                    at(0, 0).date().succ_opt().unwrap(),
+                    Duration::from_secs(30 * 60)
+                ),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_sqlite_store() {
+        let pool = crate::testing::database().await;
+        // The default "player" account, allowed 1h a day from 18:00 to 21:00
+        sqlx::query("INSERT INTO account_playtime_limits VALUES (2, 60, 1080, 1260)")
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        assert_eq!(pool.limits(1).await.unwrap(), None);
+        assert_eq!(
+            pool.limits(2).await.unwrap(),
+            Some(PlaytimeLimits {
+                daily_cap: Some(Duration::from_secs(3600)),
+                allowed_hours: hours(18, 21),
+            })
+        );
+
+        let day = at(0, 0).date();
+        for _ in 0..2 {
+            pool.add_played(2, day, Duration::from_secs(1800))
+                .await
+                .unwrap();
+        }
+        assert_eq!(
+            pool.played(2, day).await.unwrap(),
+            Duration::from_secs(3600)
+        );
+        assert_eq!(
+            check_login(&pool, 2, at(19, 0)).await.unwrap(),
+            Verdict::Denied(Restriction::DailyCapReached)
+        );
+    }
+}
@@ -0,0 +1,803 @@
+//! Server plugins
+//!
+//! A plugin extends a server without changes to the core crates: it can
+//! answer game messages, add console commands and in-game GM commands,
+//! subscribe to the [`EventBus`] and run tasks on a timer. Plugins are
+//! compiled in: each implements [`Plugin`] and the server hands it to its
+//! [`PluginHost`] at startup (the world server lists its own in
+//! `ro2_world::plugins`). [`Plugin::register`] says what the plugin adds
+//! through a [`Registrar`]; [`Plugin::on_start`] runs once the server is
+//! up and [`Plugin::on_stop`] as it shuts down.
+//!
+//! A plugin can't take the server down with it. A panic anywhere in its
+//! code (a handler, a command, a subscriber, a task or a hook) is caught,
+//! logged and counted, and after `max_panics` of them the plugin is
+//! disabled: its handlers drop their messages, its commands refuse to run
+//! and its tasks stop. A plugin whose `register` or `on_start` fails or
+//! panics is disabled from the start. Plugins never take over what's
+//! already claimed: the server's own handlers and commands, and those of
+//! plugins loaded earlier, win.
+//!
+//! Set in the `[plugins]` section of the server's config:
+//!
+//! ```toml
+//! [plugins]
+//! disabled = ["motd"]   # Compiled in, but not loaded
+//! max_panics = 3        # 0 to never disable a plugin
+//! ```
+
+use crate::Result;
+use crate::clock::Clock;
+use crate::config::{self, Validate};
+use crate::console::{Console, ConsoleCommand};
+use crate::events::{Event, EventBus};
+use crate::protocol::{
+    BoxedHandler, GameContext, GameMessageHandler, MessageDispatcher, Requirements,
+};
+use anyhow::anyhow;
+use async_trait::async_trait;
+use serde::Deserialize;
+use sqlx::{Pool, Sqlite};
+use std::any::Any;
+use std::collections::{BTreeMap, HashMap};
+use std::future::Future;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tracing::{error, info, warn};
+
+/// Which plugins not to load and how many panics they get
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct PluginConfig {
+    /// Names of plugins to leave out
+    pub disabled: Vec<String>,
+    /// Panics before a plugin is disabled; 0 for never
+    pub max_panics: u32,
+}
+
+impl Default for PluginConfig {
+    fn default() -> Self {
+        Self {
+            disabled: Vec::new(),
+            max_panics: 3,
+        }
+    }
+}
+
+impl PluginConfig {
+    /// Read the `[plugins]` section of `path`; missing keys keep their
+    /// defaults
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        config::section(path, "plugins")
+    }
+
+    /// Parse the `[plugins]` section of TOML text
+    pub fn from_toml(text: &str) -> Result<Self> {
+        config::section_from_toml(text, "plugins")
+    }
+}
+
+impl Validate for PluginConfig {}
+
+/// What a started plugin gets to work with
+#[derive(Clone)]
+pub struct PluginContext {
+    /// Which server it runs in, e.g. `world`
+    pub server: &'static str,
+    pub events: EventBus,
+    pub clock: Clock,
+    /// The shared database, if the server has one
+    pub pool: Option<Pool<Sqlite>>,
+}
+
+/// A server extension
+#[async_trait]
+pub trait Plugin: Send + Sync {
+    /// Unique among the server's plugins; what `disabled` lists
+    fn name(&self) -> &'static str;
+
+    /// Say what the plugin adds; called once, before the server starts
+    fn register(&self, registrar: &mut Registrar) -> Result<()>;
+
+    /// The server is up; an error disables the plugin
+    async fn on_start(&self, _context: &PluginContext) -> Result<()> {
+        Ok(())
+    }
+
+    /// The server is shutting down; the plugin's tasks have stopped
+    async fn on_stop(&self) {}
+}
+
+/// Polls a future, turning a panic into an `Err`
+struct CatchUnwind<F>(F);
+
+impl<F: Future + Unpin> Future for CatchUnwind<F> {
+    type Output = std::thread::Result<F::Output>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let inner = &mut self.0;
+        match panic::catch_unwind(AssertUnwindSafe(|| Pin::new(inner).poll(cx))) {
+            Ok(Poll::Pending) => Poll::Pending,
+            Ok(Poll::Ready(output)) => Poll::Ready(Ok(output)),
+            Err(payload) => Poll::Ready(Err(payload)),
+        }
+    }
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("non-string panic")
+}
+
+/// Counts one plugin's panics and disables it after too many
+struct Guard {
+    plugin: &'static str,
+    max_panics: u32,
+    panics: AtomicU32,
+    disabled: AtomicBool,
+}
+
+impl Guard {
+    fn is_enabled(&self) -> bool {
+        !self.disabled.load(Ordering::Relaxed)
+    }
+
+    fn disable(&self) {
+        self.disabled.store(true, Ordering::Relaxed);
+    }
+
+    fn panicked(&self, what: &str, payload: &(dyn Any + Send)) {
+        let panics = self.panics.fetch_add(1, Ordering::Relaxed) + 1;
+        error!(
+            "Plugin {} panicked in {}: {}",
+            self.plugin,
+            what,
+            panic_message(payload)
+        );
+        if self.max_panics > 0 && panics >= self.max_panics && self.is_enabled() {
+            self.disable();
+            error!("Disabled plugin {} after {} panics", self.plugin, panics);
+        }
+    }
+
+    /// Run `work`; `None` if it panicked
+    async fn run<'a, T>(&self, what: &str, work: impl Future<Output = T> + Send + 'a) -> Option<T> {
+        match CatchUnwind(Box::pin(work)).await {
+            Ok(output) => Some(output),
+            Err(payload) => {
+                self.panicked(what, &*payload);
+                None
+            }
+        }
+    }
+}
+
+/// Starts a subscriber or a timed task once the server is up
+type Spawner = Box<dyn FnOnce(&EventBus, Arc<Guard>) -> JoinHandle<()> + Send>;
+
+/// Collects what a plugin adds in [`Plugin::register`]
+#[derive(Default)]
+pub struct Registrar {
+    handlers: Vec<BoxedHandler>,
+    console_commands: Vec<(String, Arc<dyn ConsoleCommand>)>,
+    gm_commands: Vec<(String, Arc<dyn ConsoleCommand>)>,
+    spawners: Vec<Spawner>,
+}
+
+impl Registrar {
+    /// Answer the messages `handler` handles
+    pub fn handler(&mut self, handler: impl GameMessageHandler + 'static) -> &mut Self {
+        self.handlers.push(Arc::new(handler));
+        self
+    }
+
+    /// Add `name` to the operator console
+    pub fn console_command(
+        &mut self,
+        name: &str,
+        command: impl ConsoleCommand + 'static,
+    ) -> &mut Self {
+        self.console_commands
+            .push((name.to_string(), Arc::new(command)));
+        self
+    }
+
+    /// Add `/name` for GMs in game; `command` runs with the words after it
+    /// and its answer goes back to the GM
+    pub fn gm_command(&mut self, name: &str, command: impl ConsoleCommand + 'static) -> &mut Self {
+        self.gm_commands.push((name.to_string(), Arc::new(command)));
+        self
+    }
+
+    /// Call `on_event` with every `E` published once the server is up
+    pub fn subscribe<E, F, Fut>(&mut self, on_event: F) -> &mut Self
+    where
+        E: Event,
+        F: Fn(E) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.spawners.push(Box::new(move |events, guard| {
+            let mut events = events.subscribe::<E>();
+            tokio::spawn(async move {
+                while let Some(event) = events.recv().await {
+                    if !guard.is_enabled() {
+                        return;
+                    }
+                    guard
+                        .run(std::any::type_name::<E>(), async { on_event(event).await })
+                        .await;
+                }
+            })
+        }));
+        self
+    }
+
+    /// Run `task` every `interval` once the server is up, the first time
+    /// after one interval
+    pub fn every<F, Fut>(&mut self, name: &str, interval: Duration, task: F) -> &mut Self
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<()>> + Send + 'static,
+    {
+        let name = name.to_string();
+        self.spawners.push(Box::new(move |_, guard| {
+            tokio::spawn(async move {
+                let mut ticks = tokio::time::interval(interval);
+                ticks.tick().await;
+                loop {
+                    ticks.tick().await;
+                    if !guard.is_enabled() {
+                        return;
+                    }
+                    if let Some(Err(e)) = guard.run(&name, async { task().await }).await {
+                        warn!("Plugin {} task {} failed: {:#}", guard.plugin, name, e);
+                    }
+                }
+            })
+        }));
+        self
+    }
+}
+
+/// A plugin's handler, dropping messages once the plugin is disabled
+struct GuardedHandler {
+    handler: BoxedHandler,
+    guard: Arc<Guard>,
+}
+
+#[async_trait]
+impl GameMessageHandler for GuardedHandler {
+    async fn handle(
+        &self,
+        packet_id: u32,
+        data: &[u8],
+        context: &mut GameContext,
+    ) -> Result<Option<Vec<u8>>> {
+        if !self.guard.is_enabled() {
+            return Ok(None);
+        }
+        let name = self.handler.name();
+        self.guard
+            .run(name, self.handler.handle(packet_id, data, context))
+            .await
+            .unwrap_or_else(|| {
+                Err(anyhow!(
+                    "plugin {} handler {} panicked",
+                    self.guard.plugin,
+                    name
+                ))
+            })
+    }
+
+    fn opcode(&self) -> u32 {
+        self.handler.opcode()
+    }
+
+    fn name(&self) -> &'static str {
+        self.handler.name()
+    }
+
+    fn requirements(&self) -> Requirements {
+        self.handler.requirements()
+    }
+}
+
+/// A plugin's command, refusing to run once the plugin is disabled
+struct GuardedCommand {
+    command: Arc<dyn ConsoleCommand>,
+    guard: Arc<Guard>,
+}
+
+#[async_trait]
+impl ConsoleCommand for GuardedCommand {
+    fn usage(&self) -> &'static str {
+        self.command.usage()
+    }
+
+    async fn run(&self, args: &[&str]) -> Result<String> {
+        if !self.guard.is_enabled() {
+            return Err(anyhow!("plugin {} is disabled", self.guard.plugin));
+        }
+        self.guard
+            .run("a command", self.command.run(args))
+            .await
+            .unwrap_or_else(|| Err(anyhow!("plugin {} panicked", self.guard.plugin)))
+    }
+}
+
+struct Loaded {
+    plugin: Arc<dyn Plugin>,
+    guard: Arc<Guard>,
+    /// Taken when the plugin starts
+    spawners: Vec<Spawner>,
+    started: bool,
+}
+
+/// The plugins of one server
+pub struct PluginHost {
+    config: PluginConfig,
+    plugins: Vec<Loaded>,
+    handlers: HashMap<u32, BoxedHandler>,
+    console_commands: BTreeMap<String, Arc<dyn ConsoleCommand>>,
+    gm_commands: BTreeMap<String, Arc<dyn ConsoleCommand>>,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl PluginHost {
+    pub fn new(config: PluginConfig) -> Self {
+        Self {
+            config,
+            plugins: Vec::new(),
+            handlers: HashMap::new(),
+            console_commands: BTreeMap::new(),
+            gm_commands: BTreeMap::new(),
+            tasks: Vec::new(),
+        }
+    }
+
+    /// Register `plugin`, unless it's disabled in the config, failed to
+    /// register or has the name of one already loaded; returns whether it
+    /// was loaded
+    pub fn load(&mut self, plugin: Arc<dyn Plugin>) -> bool {
+        let name = plugin.name();
+        if self.config.disabled.iter().any(|disabled| disabled == name) {
+            info!("Plugin {} is disabled", name);
+            return false;
+        }
+        if self
+            .plugins
+            .iter()
+            .any(|loaded| loaded.plugin.name() == name)
+        {
+            warn!("Plugin {} is loaded already", name);
+            return false;
+        }
+        let mut registrar = Registrar::default();
+        match panic::catch_unwind(AssertUnwindSafe(|| plugin.register(&mut registrar))) {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => {
+                error!("Plugin {} failed to register: {:#}", name, e);
+                return false;
+            }
+            Err(payload) => {
+                error!(
+                    "Plugin {} panicked registering: {}",
+                    name,
+                    panic_message(&*payload)
+                );
+                return false;
+            }
+        }
+
+        let guard = Arc::new(Guard {
+            plugin: name,
+            max_panics: self.config.max_panics,
+            panics: AtomicU32::new(0),
+            disabled: AtomicBool::new(false),
+        });
+        for handler in registrar.handlers {
+            let opcode = handler.opcode();
+            if self.handlers.contains_key(&opcode) {
+                warn!(
+                    "Plugin {}: opcode 0x{:04x} is taken by another plugin",
+                    name, opcode
+                );
+                continue;
+            }
+            let guard = Arc::clone(&guard);
+            self.handlers
+                .insert(opcode, Arc::new(GuardedHandler { handler, guard }));
+        }
+        for (commands, added, kind) in [
+            (
+                &mut self.console_commands,
+                registrar.console_commands,
+                "console",
+            ),
+            (&mut self.gm_commands, registrar.gm_commands, "GM"),
+        ] {
+            for (command_name, command) in added {
+                if commands.contains_key(&command_name) {
+                    warn!(
+                        "Plugin {}: {} command {} is taken by another plugin",
+                        name, kind, command_name
+                    );
+                    continue;
+                }
+                let guard = Arc::clone(&guard);
+                commands.insert(command_name, Arc::new(GuardedCommand { command, guard }));
+            }
+        }
+        self.plugins.push(Loaded {
+            plugin,
+            guard,
+            spawners: registrar.spawners,
+            started: false,
+        });
+        info!("Loaded plugin {}", name);
+        true
+    }
+
+    /// Names of the loaded plugins, in load order
+    pub fn names(&self) -> Vec<&'static str> {
+        self.plugins
+            .iter()
+            .map(|loaded| loaded.plugin.name())
+            .collect()
+    }
+
+    /// Whether `name` is loaded and hasn't been disabled
+    pub fn is_enabled(&self, name: &str) -> bool {
+        self.plugins
+            .iter()
+            .any(|loaded| loaded.plugin.name() == name && loaded.guard.is_enabled())
+    }
+
+    /// Add the plugins' handlers to `dispatcher`, for opcodes it doesn't
+    /// handle already
+    pub fn install(&self, dispatcher: &mut MessageDispatcher) {
+        for (opcode, handler) in &self.handlers {
+            if !dispatcher.has_handler(*opcode) {
+                dispatcher.register_handler(Arc::clone(handler));
+            }
+        }
+    }
+
+    /// `console` with the plugins' console commands whose names it
+    /// doesn't know yet
+    pub fn console(&self, mut console: Console) -> Console {
+        for (name, command) in &self.console_commands {
+            if console.has_command(name) {
+                warn!("Console command {} is taken, a plugin's is left out", name);
+                continue;
+            }
+            console = console.command(name, SharedCommand(Arc::clone(command)));
+        }
+        console
+    }
+
+    /// Run a GM's `/command args` if a plugin added it; `None` if none did
+    pub async fn gm_command(&self, line: &str) -> Option<Result<String>> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let (name, args) = words.split_first()?;
+        let command = self.gm_commands.get(name.strip_prefix('/')?)?;
+        Some(command.run(args).await)
+    }
+
+    /// Call every plugin's [`Plugin::on_start`], then start the
+    /// subscribers and tasks of those that started
+    pub async fn start(&mut self, context: &PluginContext) {
+        for loaded in &mut self.plugins {
+            let name = loaded.plugin.name();
+            let started = loaded
+                .guard
+                .run("on_start", loaded.plugin.on_start(context))
+                .await;
+            match started {
+                Some(Ok(())) => {}
+                Some(Err(e)) => {
+                    error!("Plugin {} failed to start: {:#}", name, e);
+                    loaded.guard.disable();
+                }
+                None => loaded.guard.disable(),
+            }
+            loaded.started = true;
+            if !loaded.guard.is_enabled() {
+                continue;
+            }
+            for spawn in loaded.spawners.drain(..) {
+                self.tasks
+                    .push(spawn(&context.events, Arc::clone(&loaded.guard)));
+            }
+        }
+    }
+
+    /// Stop the plugins' subscribers and tasks, then call every started
+    /// plugin's [`Plugin::on_stop`], last loaded first
+    pub async fn stop(&mut self) {
+        for task in self.tasks.drain(..) {
+            task.abort();
+        }
+        for loaded in self.plugins.iter_mut().rev() {
+            if std::mem::take(&mut loaded.started) {
+                loaded.guard.run("on_stop", loaded.plugin.on_stop()).await;
+            }
+        }
+    }
+}
+
+/// A command shared between the host and a console
+struct SharedCommand(Arc<dyn ConsoleCommand>);
+
+#[async_trait]
+impl ConsoleCommand for SharedCommand {
+    fn usage(&self) -> &'static str {
+        self.0.usage()
+    }
+
+    async fn run(&self, args: &[&str]) -> Result<String> {
+        self.0.run(args).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::ConnectionRegistry;
+    use std::sync::Mutex;
+    use std::sync::atomic::AtomicUsize;
+
+    const OPCODE: u32 = 0x3D00;
+
+    /// Answers with its data, or panics on `panic`
+    struct Echo;
+
+    #[async_trait]
+    impl GameMessageHandler for Echo {
+        async fn handle(
+            &self,
+            _packet_id: u32,
+            data: &[u8],
+            _context: &mut GameContext,
+        ) -> Result<Option<Vec<u8>>> {
+            if data == b"panic" {
+                panic!("echo panicked");
+            }
+            Ok(Some(data.to_vec()))
+        }
+
+        fn opcode(&self) -> u32 {
+            OPCODE
+        }
+
+        fn name(&self) -> &'static str {
+            "Echo"
+        }
+    }
+
+    struct Hello;
+
+    #[async_trait]
+    impl ConsoleCommand for Hello {
+        async fn run(&self, args: &[&str]) -> Result<String> {
+            Ok(format!("hello {}", args.join(" ")))
+        }
+    }
+
+    #[derive(Default)]
+    struct Recorder {
+        levels: Arc<Mutex<Vec<u32>>>,
+        ticks: Arc<AtomicUsize>,
+        stopped: Arc<AtomicBool>,
+    }
+
+    #[async_trait]
+    impl Plugin for Recorder {
+        fn name(&self) -> &'static str {
+            "recorder"
+        }
+
+        fn register(&self, registrar: &mut Registrar) -> Result<()> {
+            let levels = Arc::clone(&self.levels);
+            let ticks = Arc::clone(&self.ticks);
+            registrar
+                .handler(Echo)
+                .console_command("hello", Hello)
+                .console_command("help", Hello)
+                .gm_command("hello", Hello)
+                .subscribe(move |level: crate::events::LevelUp| {
+                    let levels = Arc::clone(&levels);
+                    async move {
+                        if level.level == 13 {
+                            panic!("unlucky");
+                        }
+                        levels.lock().unwrap().push(level.level);
+                    }
+                })
+                .every("tick", Duration::from_millis(10), move || {
+                    let ticks = Arc::clone(&ticks);
+                    async move {
+                        ticks.fetch_add(1, Ordering::Relaxed);
+                        Ok(())
+                    }
+                });
+            Ok(())
+        }
+
+        async fn on_stop(&self) {
+            self.stopped.store(true, Ordering::Relaxed);
+        }
+    }
+
+    struct Broken;
+
+    #[async_trait]
+    impl Plugin for Broken {
+        fn name(&self) -> &'static str {
+            "broken"
+        }
+
+        fn register(&self, _registrar: &mut Registrar) -> Result<()> {
+            panic!("can't register");
+        }
+    }
+
+    struct FailsToStart;
+
+    #[async_trait]
+    impl Plugin for FailsToStart {
+        fn name(&self) -> &'static str {
+            "fails_to_start"
+        }
+
+        fn register(&self, registrar: &mut Registrar) -> Result<()> {
+            registrar.gm_command("fail", Hello);
+            Ok(())
+        }
+
+        async fn on_start(&self, _context: &PluginContext) -> Result<()> {
+            Err(anyhow!("no database"))
+        }
+    }
+
+    fn context(events: &EventBus) -> PluginContext {
+        PluginContext {
+            server: "test",
+            events: events.clone(),
+            clock: Clock::system(),
+            pool: None,
+        }
+    }
+
+    fn level(level: u32) -> crate::events::LevelUp {
+        crate::events::LevelUp {
+            character_id: 1,
+            level,
+        }
+    }
+
+    #[test]
+    fn test_config() {
+        assert_eq!(
+            PluginConfig::load("does/not/exist.toml").unwrap(),
+            PluginConfig::default()
+        );
+        let config =
+            PluginConfig::from_toml("[plugins]\ndisabled = [\"motd\"]\nmax_panics = 0").unwrap();
+        assert_eq!(
+            (config.disabled, config.max_panics),
+            (vec!["motd".to_string()], 0)
+        );
+    }
+
+    #[test]
+    fn test_load() {
+        let mut host = PluginHost::new(PluginConfig {
+            disabled: vec!["fails_to_start".to_string()],
+            ..PluginConfig::default()
+        });
+        assert!(host.load(Arc::new(Recorder::default())));
+        assert!(!host.load(Arc::new(Recorder::default())));
+        assert!(!host.load(Arc::new(Broken)));
+        assert!(!host.load(Arc::new(FailsToStart)));
+        assert_eq!(host.names(), ["recorder"]);
+        assert!(host.is_enabled("recorder"));
+        assert!(!host.is_enabled("broken"));
+    }
+
+    #[tokio::test]
+    async fn test_handlers_and_commands() {
+        let mut host = PluginHost::new(PluginConfig {
+            max_panics: 2,
+            ..PluginConfig::default()
+        });
+        host.load(Arc::new(Recorder::default()));
+
+        let console = host.console(Console::new(Arc::new(ConnectionRegistry::new())));
+        assert_eq!(console.execute("hello there").await, "hello there");
+        // Built-in commands stay
+        assert!(console.execute("help").await.starts_with("help\n"));
+        assert_eq!(
+            host.gm_command("/hello gm").await.unwrap().unwrap(),
+            "hello gm"
+        );
+        assert!(host.gm_command("/goto 5").await.is_none());
+        assert!(host.gm_command("hello").await.is_none());
+
+        let mut dispatcher = MessageDispatcher::new();
+        host.install(&mut dispatcher);
+        let mut context = GameContext::new(1, "127.0.0.1:50000".to_string());
+        assert_eq!(
+            dispatcher
+                .dispatch(OPCODE, b"hi", &mut context)
+                .await
+                .unwrap(),
+            Some(b"hi".to_vec())
+        );
+
+        // Panics are caught, and the second disables the plugin
+        for _ in 0..2 {
+            assert!(
+                dispatcher
+                    .dispatch(OPCODE, b"panic", &mut context)
+                    .await
+                    .is_err()
+            );
+        }
+        assert!(!host.is_enabled("recorder"));
+        assert_eq!(
+            dispatcher
+                .dispatch(OPCODE, b"hi", &mut context)
+                .await
+                .unwrap(),
+            None
+        );
+        assert!(
+            console
+                .execute("hello")
+                .await
+                .starts_with("error: plugin recorder is disabled")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_lifecycle() {
+        let recorder = Recorder::default();
+        let (levels, ticks, stopped) = (
+            Arc::clone(&recorder.levels),
+            Arc::clone(&recorder.ticks),
+            Arc::clone(&recorder.stopped),
+        );
+        let mut host = PluginHost::new(PluginConfig::default());
+        host.load(Arc::new(recorder));
+        host.load(Arc::new(FailsToStart));
+        let events = EventBus::new();
+        host.start(&context(&events)).await;
+        assert!(host.is_enabled("recorder"));
+        assert!(!host.is_enabled("fails_to_start"));
+        assert!(host.gm_command("/fail").await.unwrap().is_err());
+
+        // A panicking subscriber carries on with the next event
+        for reached in [12, 13, 14] {
+            events.publish(level(reached));
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(*levels.lock().unwrap(), [12, 14]);
+        assert!(ticks.load(Ordering::Relaxed) > 0);
+
+        host.stop().await;
+        assert!(stopped.load(Ordering::Relaxed));
+        let after = ticks.load(Ordering::Relaxed);
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        assert_eq!(ticks.load(Ordering::Relaxed), after);
+    }
+}
@@ -4,21 +4,29 @@
 //! The client uses function pointers to dispatch messages to handlers,
 //! we use a HashMap-based registry for flexibility.
 
+use super::error::{ClientError, ErrorResponse};
 use super::handler::{BoxedHandler, GameContext, HandlerRegistry};
 use crate::Result;
-use tracing::{debug, error, warn};
+use crate::database::queries::AccountQueries;
+use std::sync::Arc;
+use tracing::{Instrument, debug, error, info_span, warn};
 
 /// Message dispatcher routes incoming packets to registered handlers
 ///
 /// Architecture mirrors the client's dispatch system:
 /// - Packet arrives with opcode (u32)
 /// - Dispatcher looks up handler by opcode
+/// - Dispatcher checks the handler's [`Requirements`] against the context
 /// - Handler processes packet and optionally returns response
+/// - A handler failing with a [`ClientError`] is answered with an
+///   [`ErrorResponse`], its text in the account's language
 ///
 /// Key differences from client:
 /// - Client uses function pointers in a switch/table
 /// - Server uses HashMap for dynamic handler registration
 /// - Server handlers are async (client handlers are synchronous)
+///
+/// [`Requirements`]: super::Requirements
 pub struct MessageDispatcher {
     /// Handler registry (opcode -> handler)
     registry: HandlerRegistry,
@@ -41,6 +49,10 @@ pub struct DispatcherStats {
 
     /// Messages with no registered handler
     pub messages_unhandled: u64,
+
+    /// Messages dropped because the connection didn't meet their
+    /// handler's requirements
+    pub messages_rejected: u64,
 }
 
 impl MessageDispatcher {
@@ -74,6 +86,13 @@ impl MessageDispatcher {
 
     /// Dispatch a message to its handler
     ///
+    /// The handler runs in a `message` span carrying the opcode, the
+    /// handler's name and the context's [`reliability`], nested in
+    /// whatever span the caller is in (the connection's, for
+    /// `ProudNetConnection`).
+    ///
+    /// [`reliability`]: GameContext::reliability
+    ///
     /// # Parameters
     /// - `packet_id`: Message opcode (e.g., 0x1001)
     /// - `data`: Serialized message payload
@@ -81,8 +100,13 @@ impl MessageDispatcher {
     ///
     /// # Returns
     /// - `Ok(Some(response))`: Handler processed message and has response
-    /// - `Ok(None)`: Handler processed message but no response needed
-    /// - `Err(e)`: Handler failed or no handler registered
+    /// - `Ok(None)`: Handler processed message but no response needed, or
+    ///   the message was dropped for want of a handler or of the
+    ///   handler's requirements
+    /// - `Ok(Some(error))`: Handler failed with a [`ClientError`], turned
+    ///   into an [`ErrorResponse`] for the client
+    /// - `Err(e)`: Handler failed otherwise
+    #[cfg_attr(feature = "profiling", tracing::instrument(level = "trace", skip_all))]
     pub async fn dispatch(
         &mut self,
         packet_id: u32,
@@ -93,7 +117,7 @@ impl MessageDispatcher {
 
         // Look up handler
         let handler = match self.registry.get(packet_id) {
-            Some(h) => h,
+            Some(h) => Arc::clone(h),
             None => {
                 self.stats.messages_unhandled += 1;
                 warn!(
@@ -104,6 +128,18 @@ impl MessageDispatcher {
             }
         };
 
+        if let Some(unmet) = handler.requirements().unmet(context) {
+            self.stats.messages_rejected += 1;
+            warn!(
+                "Dropping opcode 0x{:04x} for {}: requires {} (session: {})",
+                packet_id,
+                handler.name(),
+                unmet,
+                context.session_id
+            );
+            return Ok(None);
+        }
+
         // Dispatch to handler
         debug!(
             "Dispatching opcode 0x{:04x} to {} (session: {})",
@@ -112,27 +148,51 @@ impl MessageDispatcher {
             context.session_id
         );
 
-        match handler.handle(packet_id, data, context).await {
-            Ok(response) => {
-                self.stats.messages_success += 1;
-                debug!(
-                    "Handler {} completed successfully (session: {})",
-                    handler.name(),
-                    context.session_id
-                );
-                Ok(response)
-            }
-            Err(e) => {
-                self.stats.messages_failed += 1;
-                error!(
-                    "Handler {} failed: {} (session: {})",
-                    handler.name(),
-                    e,
-                    context.session_id
-                );
-                Err(e)
+        let span = info_span!(
+            "message",
+            opcode = %format_args!("0x{:04x}", packet_id),
+            handler = handler.name(),
+            reliability = %context.reliability
+        );
+        let stats = &mut self.stats;
+        async move {
+            match handler.handle(packet_id, data, context).await {
+                Ok(response) => {
+                    stats.messages_success += 1;
+                    debug!(
+                        "Handler {} completed successfully (session: {})",
+                        handler.name(),
+                        context.session_id
+                    );
+                    Ok(response)
+                }
+                Err(e) => {
+                    stats.messages_failed += 1;
+                    if let Some(client_error) = ClientError::find(&e) {
+                        warn!(
+                            "Handler {} refused: {} (session: {})",
+                            handler.name(),
+                            e,
+                            context.session_id
+                        );
+                        let language = account_language(context).await;
+                        let localization = &context.shared.localization;
+                        let message = client_error
+                            .message(localization, localization.resolve(language.as_deref()));
+                        return Ok(Some(ErrorResponse::build(client_error.code, &message)));
+                    }
+                    error!(
+                        "Handler {} failed: {} (session: {})",
+                        handler.name(),
+                        e,
+                        context.session_id
+                    );
+                    Err(e)
+                }
             }
         }
+        .instrument(span)
+        .await
     }
 
     /// Check if handler is registered for opcode
@@ -162,16 +222,66 @@ impl Default for MessageDispatcher {
     }
 }
 
+/// The language the logged-in account picked, if the server has a
+/// database to ask
+async fn account_language(context: &GameContext) -> Option<String> {
+    let (db, account_id) = (context.shared.db.as_ref()?, context.account_id?);
+    AccountQueries::language(db, account_id.into())
+        .await
+        .unwrap_or_else(|e| {
+            warn!(
+                "Couldn't look up account {}'s language: {:#}",
+                account_id, e
+            );
+            None
+        })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::protocol::handler::GameMessageHandler;
+    use crate::localization::Localization;
+    use crate::protocol::error::ErrorCode;
+    use crate::protocol::handler::{GameMessageHandler, Requirements, SharedState};
+    use crate::testing;
+    use anyhow::anyhow;
     use async_trait::async_trait;
-    use std::sync::Arc;
 
     struct TestHandler {
         opcode: u32,
         name: &'static str,
+        requirements: Requirements,
+    }
+
+    /// Fails every message, for the client to see if `client` is set
+    struct FailingHandler {
+        client: bool,
+    }
+
+    #[async_trait]
+    impl GameMessageHandler for FailingHandler {
+        async fn handle(
+            &self,
+            _packet_id: u32,
+            _data: &[u8],
+            _context: &mut GameContext,
+        ) -> Result<Option<Vec<u8>>> {
+            if self.client {
+                Err(ClientError::new(ErrorCode::NotFound, "character.not_found")
+                    .with_arg("name", "Alice")
+                    .into())
+            } else {
+                Err(anyhow!("database is locked"))
+            }
+        }
+
+        fn opcode(&self) -> u32 {
+            if self.client { 0x2001 } else { 0x2002 }
+        }
+
+        fn name(&self) -> &'static str {
+            "FailingHandler"
+        }
     }
 
     #[async_trait]
@@ -192,6 +302,10 @@ mod tests {
         fn name(&self) -> &'static str {
             self.name
         }
+
+        fn requirements(&self) -> Requirements {
+            self.requirements
+        }
     }
 
     #[tokio::test]
@@ -199,6 +313,7 @@ mod tests {
         let handler = Arc::new(TestHandler {
             opcode: 0x1001,
             name: "TestHandler",
+            requirements: Requirements::NONE,
         });
 
         let mut dispatcher = MessageDispatcher::new();
@@ -226,11 +341,81 @@ mod tests {
         assert_eq!(dispatcher.stats().messages_unhandled, 1);
     }
 
+    #[tokio::test]
+    async fn test_dispatcher_enforces_requirements() {
+        let mut dispatcher = MessageDispatcher::with_handlers(vec![Arc::new(TestHandler {
+            opcode: 0x1001,
+            name: "TestHandler",
+            requirements: Requirements::AUTHENTICATED,
+        })]);
+        let mut ctx = GameContext::new(123, "127.0.0.1:8080".to_string());
+        ctx.connection_info.encrypted = true;
+
+        let response = dispatcher.dispatch(0x1001, &[], &mut ctx).await.unwrap();
+        assert_eq!(response, None);
+        assert_eq!(dispatcher.stats().messages_rejected, 1);
+
+        ctx.account_id = Some(7);
+        let response = dispatcher.dispatch(0x1001, &[], &mut ctx).await.unwrap();
+        assert_eq!(response, Some(vec![1, 2, 3, 4]));
+        assert_eq!(dispatcher.stats().messages_success, 1);
+    }
+
+    #[tokio::test]
+    async fn test_dispatcher_answers_client_errors() {
+        let mut dispatcher = MessageDispatcher::with_handlers(vec![
+            Arc::new(FailingHandler { client: true }),
+            Arc::new(FailingHandler { client: false }),
+        ]);
+        let mut localization = Localization::new("en");
+        localization
+            .add_table(
+                "en",
+                "[character]\nnot_found = \"No character named {name}.\"",
+            )
+            .unwrap();
+        localization
+            .add_table(
+                "de",
+                "[character]\nnot_found = \"Kein Charakter namens {name}.\"",
+            )
+            .unwrap();
+        let pool = testing::database().await;
+        let account_id = AccountQueries::create(&pool, "alice", "hash")
+            .await
+            .unwrap();
+        AccountQueries::set_language(&pool, account_id, Some("de"))
+            .await
+            .unwrap();
+        let shared = SharedState::new()
+            .with_localization(Arc::new(localization))
+            .with_db(pool);
+        let mut ctx = GameContext::new(123, "127.0.0.1:8080".to_string()).with_shared(shared);
+
+        let text = |response: Option<Vec<u8>>| {
+            let response = ErrorResponse::parse(&response.unwrap()[2..]).unwrap();
+            assert_eq!(response.code, ErrorCode::NotFound);
+            response.message
+        };
+        let response = dispatcher.dispatch(0x2001, &[], &mut ctx).await.unwrap();
+        assert_eq!(text(response), "No character named Alice.");
+
+        // In the language the account picked, once it's logged in
+        ctx.account_id = Some(account_id as u32);
+        let response = dispatcher.dispatch(0x2001, &[], &mut ctx).await.unwrap();
+        assert_eq!(text(response), "Kein Charakter namens Alice.");
+
+        // Other failures aren't the client's business
+        assert!(dispatcher.dispatch(0x2002, &[], &mut ctx).await.is_err());
+        assert_eq!(dispatcher.stats().messages_failed, 3);
+    }
+
     #[test]
     fn test_dispatcher_has_handler() {
         let handler = Arc::new(TestHandler {
             opcode: 0x1001,
             name: "TestHandler",
+            requirements: Requirements::NONE,
         });
 
         let mut dispatcher = MessageDispatcher::new();
@@ -0,0 +1,193 @@
+//! Telling the client a request failed
+//!
+//! Failures the player should see go out as an [`NFY_ERROR`] message: an
+//! [`ErrorCode`] and a text the client shows in its chat window. Handlers
+//! don't build these themselves; they fail with a [`ClientError`] naming
+//! the text's [`Localization`] key, and the
+//! [`MessageDispatcher`](super::MessageDispatcher) answers with the
+//! matching [`ErrorResponse`], in the language the account picked. Any
+//! other error stays in the server's logs.
+//!
+//! The opcode and codes are placeholders until the client's own are found
+//! in captures.
+
+use super::opcodes::NFY_ERROR;
+use crate::Result;
+use crate::localization::Localization;
+use crate::wire::{WireReader, WireWriter};
+use std::fmt::{self, Display};
+
+/// Why a request failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(u32)]
+pub enum ErrorCode {
+    Unknown = 0,
+    /// The message didn't parse or made no sense
+    InvalidRequest = 1,
+    /// Not allowed now, or not for this player
+    NotAllowed = 2,
+    /// What it refers to doesn't exist
+    NotFound = 3,
+    /// The server can't take it right now
+    Busy = 4,
+    /// Something went wrong on the server
+    Internal = 5,
+}
+
+impl ErrorCode {
+    pub fn from_u32(value: u32) -> Self {
+        match value {
+            1 => Self::InvalidRequest,
+            2 => Self::NotAllowed,
+            3 => Self::NotFound,
+            4 => Self::Busy,
+            5 => Self::Internal,
+            _ => Self::Unknown,
+        }
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Unknown => "unknown",
+            Self::InvalidRequest => "invalid request",
+            Self::NotAllowed => "not allowed",
+            Self::NotFound => "not found",
+            Self::Busy => "busy",
+            Self::Internal => "internal error",
+        };
+        f.write_str(name)
+    }
+}
+
+/// A handler failure the client is told about
+///
+/// Return it from a handler, e.g.
+/// `return Err(ClientError::new(ErrorCode::NotFound, "character.not_found").into())`.
+/// The dispatcher finds it anywhere in the error's chain, so context can
+/// still be added on top.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("{code}: {key}")]
+pub struct ClientError {
+    pub code: ErrorCode,
+    /// Localization key of the text shown to the player
+    pub key: String,
+    /// Values of the text's `{name}` placeholders
+    pub args: Vec<(String, String)>,
+}
+
+impl ClientError {
+    pub fn new(code: ErrorCode, key: impl Into<String>) -> Self {
+        Self {
+            code,
+            key: key.into(),
+            args: Vec::new(),
+        }
+    }
+
+    /// Fill the text's `{name}` placeholder with `value`
+    pub fn with_arg(mut self, name: &str, value: impl Display) -> Self {
+        self.args.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// The text in `language`
+    pub fn message(&self, localization: &Localization, language: &str) -> String {
+        let args: Vec<(&str, &dyn Display)> = self
+            .args
+            .iter()
+            .map(|(name, value)| (name.as_str(), value as &dyn Display))
+            .collect();
+        localization.format(language, &self.key, &args)
+    }
+
+    /// The one `error` carries, if any
+    pub fn find(error: &anyhow::Error) -> Option<&Self> {
+        error.chain().find_map(|cause| cause.downcast_ref())
+    }
+}
+
+/// An [`NFY_ERROR`] message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ErrorResponse {
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+impl ErrorResponse {
+    /// Opcode and payload of an error message: u32 code, then the text as
+    /// a u16 byte length and UTF-8
+    pub fn build(code: ErrorCode, message: &str) -> Vec<u8> {
+        let mut out = WireWriter::message(NFY_ERROR);
+        out.u32(code as u32).utf8(message);
+        out.into_bytes()
+    }
+
+    /// Read an error message's payload (after the opcode)
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        let mut r = WireReader::new(data);
+        Ok(Self {
+            code: ErrorCode::from_u32(r.u32("error code")?),
+            message: r.utf8("message")?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::{Context, anyhow};
+
+    #[test]
+    fn test_build_and_parse() {
+        let message = ErrorResponse::build(ErrorCode::NotFound, "No such character");
+        assert_eq!(&message[..2], &NFY_ERROR.to_le_bytes());
+        assert_eq!(&message[2..8], &[3, 0, 0, 0, 17, 0]);
+        assert_eq!(
+            ErrorResponse::parse(&message[2..]).unwrap(),
+            ErrorResponse {
+                code: ErrorCode::NotFound,
+                message: "No such character".to_string(),
+            }
+        );
+        for cut in 2..message.len() {
+            assert!(ErrorResponse::parse(&message[2..cut]).is_err());
+        }
+
+        let long = "é".repeat(40_000);
+        let message = ErrorResponse::build(ErrorCode::Busy, &long);
+        assert_eq!(
+            ErrorResponse::parse(&message[2..]).unwrap().message.len(),
+            65_534
+        );
+    }
+
+    #[test]
+    fn test_message() {
+        let mut localization = Localization::new("en");
+        localization
+            .add_table("en", "[trade]\ntoo_far = \"{name} is too far away.\"")
+            .unwrap();
+        localization
+            .add_table("de", "[trade]\ntoo_far = \"{name} ist zu weit weg.\"")
+            .unwrap();
+        let error =
+            ClientError::new(ErrorCode::NotAllowed, "trade.too_far").with_arg("name", "Alice");
+
+        assert_eq!(error.to_string(), "not allowed: trade.too_far");
+        assert_eq!(error.message(&localization, "en"), "Alice is too far away.");
+        assert_eq!(error.message(&localization, "de"), "Alice ist zu weit weg.");
+    }
+
+    #[test]
+    fn test_find_client_error() {
+        let error: anyhow::Error = ClientError::new(ErrorCode::NotAllowed, "Not here").into();
+        let error = Err::<(), _>(error).context("renaming").unwrap_err();
+        assert_eq!(
+            ClientError::find(&error),
+            Some(&ClientError::new(ErrorCode::NotAllowed, "Not here"))
+        );
+        assert_eq!(ClientError::find(&anyhow!("database is locked")), None);
+    }
+}
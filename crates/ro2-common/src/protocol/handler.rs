@@ -5,9 +5,22 @@
 //! - packet_id: Message opcode (0x1001+)
 //! - data: Serialized message payload
 //! - context: Game state and session context
+//!
+//! The context also carries [`SharedState`], handles to what every
+//! connection shares (other connections, the database, the world, the
+//! event bus, the clock, the string tables), so handlers can reach them
+//! without globals.
 
 use crate::Result;
+use crate::clock::Clock;
+use crate::events::EventBus;
+use crate::localization::Localization;
+use crate::net::ConnectionRegistry;
+use crate::protocol::{LatencyStats, Reliability};
+use crate::session::{CorrelationId, SessionManager, TransferToken};
 use async_trait::async_trait;
+use sqlx::{Pool, Sqlite};
+use std::any::Any;
 use std::sync::Arc;
 
 /// Game context passed to all message handlers
@@ -28,8 +41,91 @@ pub struct GameContext {
     /// Account ID
     pub account_id: Option<u32>,
 
+    /// Follows the player across connections and servers in the logs
+    pub correlation_id: CorrelationId,
+
     /// Connection metadata
     pub connection_info: ConnectionInfo,
+
+    /// Whether the message being handled came reliably (0x25) or not
+    /// (0x26); movement-style handlers can ignore stale unreliable ones
+    pub reliability: Reliability,
+
+    /// Server-wide state
+    pub shared: SharedState,
+}
+
+/// Handles to the state every connection on a server shares
+///
+/// Cheap to clone; every clone refers to the same state. The world is
+/// whatever type the server keeps it in, fetched back with
+/// [`world`](Self::world).
+#[derive(Clone, Default)]
+pub struct SharedState {
+    /// Transfer tokens, on servers that hand players over
+    pub sessions: Option<Arc<SessionManager>>,
+
+    /// Every connected session's outbox
+    pub connections: Arc<ConnectionRegistry>,
+
+    /// Database, on servers that have one
+    pub db: Option<Pool<Sqlite>>,
+
+    /// Gameplay and system events
+    pub events: EventBus,
+
+    /// What handlers tell the time by
+    pub clock: Clock,
+
+    /// Text sent to players, by key and language
+    pub localization: Arc<Localization>,
+
+    world: Option<Arc<dyn Any + Send + Sync>>,
+}
+
+impl SharedState {
+    /// Nothing but an empty connection registry and event bus
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_sessions(mut self, sessions: Arc<SessionManager>) -> Self {
+        self.sessions = Some(sessions);
+        self
+    }
+
+    pub fn with_clock(mut self, clock: Clock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn with_db(mut self, db: Pool<Sqlite>) -> Self {
+        self.db = Some(db);
+        self
+    }
+
+    /// Send players text from `localization` instead of the built-in
+    /// English
+    pub fn with_localization(mut self, localization: Arc<Localization>) -> Self {
+        self.localization = localization;
+        self
+    }
+
+    /// Publish on `events` instead of a bus of its own
+    pub fn with_events(mut self, events: EventBus) -> Self {
+        self.events = events;
+        self
+    }
+
+    pub fn with_world<T: Any + Send + Sync>(mut self, world: Arc<T>) -> Self {
+        self.world = Some(world);
+        self
+    }
+
+    /// The world, if one of type `T` was set
+    pub fn world<T: Any + Send + Sync>(&self) -> Option<Arc<T>> {
+        Arc::clone(self.world.as_ref()?).downcast().ok()
+    }
 }
 
 /// Connection metadata
@@ -38,30 +134,58 @@ pub struct ConnectionInfo {
     /// Remote IP address
     pub remote_addr: String,
 
+    /// Name of the listener the client connected to, if the server has
+    /// several
+    pub listener: Option<String>,
+
+    /// Whether the session key has been exchanged, so messages arrive
+    /// encrypted
+    pub encrypted: bool,
+
     /// Connection timestamp
     pub connected_at: chrono::DateTime<chrono::Utc>,
 
     /// Last activity timestamp
     pub last_activity: chrono::DateTime<chrono::Utc>,
+
+    /// Round trip and jitter from ProudNet heartbeats
+    pub latency: LatencyStats,
 }
 
 impl GameContext {
     /// Create a new game context for a connection
     pub fn new(session_id: u64, remote_addr: String) -> Self {
-        let now = chrono::Utc::now();
+        let shared = SharedState::default();
+        let now = shared.clock.now();
         Self {
             session_id,
             game_state: 0, // Disconnected
             character_id: None,
             account_id: None,
+            correlation_id: CorrelationId::random(),
             connection_info: ConnectionInfo {
                 remote_addr,
+                listener: None,
+                encrypted: false,
                 connected_at: now,
                 last_activity: now,
+                latency: LatencyStats::default(),
             },
+            reliability: Reliability::Reliable,
+            shared,
         }
     }
 
+    /// Give handlers access to `shared`, and time the connection by its
+    /// clock
+    pub fn with_shared(mut self, shared: SharedState) -> Self {
+        let now = shared.clock.now();
+        self.connection_info.connected_at = now;
+        self.connection_info.last_activity = now;
+        self.shared = shared;
+        self
+    }
+
     /// Check if game state is active (lobby or in-game)
     ///
     /// Mirrors IsGameStateActive check from 0x006a60a0
@@ -69,9 +193,72 @@ impl GameContext {
         self.game_state == 1 || self.game_state == 2
     }
 
+    /// Take over the account, character and correlation ID of a player
+    /// handed over with `token`
+    pub fn adopt_transfer(&mut self, token: &TransferToken) {
+        self.account_id = Some(token.account_id as u32);
+        self.character_id = Some(token.character_id as u32);
+        self.correlation_id = token.correlation_id;
+    }
+
     /// Update last activity timestamp
     pub fn update_activity(&mut self) {
-        self.connection_info.last_activity = chrono::Utc::now();
+        self.connection_info.last_activity = self.shared.clock.now();
+    }
+}
+
+/// What a connection must have done before the dispatcher runs a handler
+///
+/// Handlers declare theirs with [`GameMessageHandler::requirements`] and
+/// the [`MessageDispatcher`](super::MessageDispatcher) checks them against
+/// the [`GameContext`], dropping messages that come too early.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Requirements {
+    /// The session key has been exchanged
+    pub encryption: bool,
+    /// An account has logged in
+    pub auth: bool,
+    /// A character is in the world (game state 2)
+    pub in_game: bool,
+}
+
+impl Requirements {
+    /// Any message, any time
+    pub const NONE: Self = Self {
+        encryption: false,
+        auth: false,
+        in_game: false,
+    };
+
+    /// Only over an encrypted connection
+    pub const ENCRYPTED: Self = Self {
+        encryption: true,
+        ..Self::NONE
+    };
+
+    /// Only once logged in
+    pub const AUTHENTICATED: Self = Self {
+        auth: true,
+        ..Self::ENCRYPTED
+    };
+
+    /// Only with a character in the world
+    pub const IN_GAME: Self = Self {
+        in_game: true,
+        ..Self::AUTHENTICATED
+    };
+
+    /// The first requirement `context` doesn't meet, if any
+    pub fn unmet(&self, context: &GameContext) -> Option<&'static str> {
+        if self.encryption && !context.connection_info.encrypted {
+            Some("encryption")
+        } else if self.auth && context.account_id.is_none() {
+            Some("login")
+        } else if self.in_game && context.game_state != 2 {
+            Some("a character in game")
+        } else {
+            None
+        }
     }
 }
 
@@ -106,6 +293,12 @@ pub trait GameMessageHandler: Send + Sync {
 
     /// Get handler name for logging
     fn name(&self) -> &'static str;
+
+    /// What the connection must have done before this handler runs;
+    /// nothing by default
+    fn requirements(&self) -> Requirements {
+        Requirements::NONE
+    }
 }
 
 /// Type alias for boxed handler
@@ -205,4 +398,60 @@ mod tests {
         ctx.game_state = 2;
         assert!(ctx.is_game_state_active());
     }
+
+    #[test]
+    fn test_requirements() {
+        let mut ctx = GameContext::new(123, "127.0.0.1:8080".to_string());
+        assert_eq!(Requirements::NONE.unmet(&ctx), None);
+        assert_eq!(Requirements::IN_GAME.unmet(&ctx), Some("encryption"));
+
+        ctx.connection_info.encrypted = true;
+        assert_eq!(Requirements::ENCRYPTED.unmet(&ctx), None);
+        assert_eq!(Requirements::IN_GAME.unmet(&ctx), Some("login"));
+
+        ctx.account_id = Some(7);
+        ctx.game_state = 1;
+        assert_eq!(Requirements::AUTHENTICATED.unmet(&ctx), None);
+        assert_eq!(
+            Requirements::IN_GAME.unmet(&ctx),
+            Some("a character in game")
+        );
+
+        ctx.game_state = 2;
+        assert_eq!(Requirements::IN_GAME.unmet(&ctx), None);
+    }
+
+    #[test]
+    fn test_shared_state() {
+        let shared = SharedState::new().with_world(Arc::new(String::from("world")));
+        let a = GameContext::new(1, "127.0.0.1:8080".to_string()).with_shared(shared.clone());
+        let b = GameContext::new(2, "127.0.0.1:8081".to_string()).with_shared(shared);
+
+        assert_eq!(a.shared.world::<String>().as_deref().unwrap(), "world");
+        assert!(a.shared.world::<u32>().is_none());
+        assert!(a.shared.db.is_none());
+
+        // Both see the same connections
+        let (outbox, mut outbox_rx) = tokio::sync::mpsc::channel(1);
+        a.shared.connections.register(a.session_id, outbox);
+        assert!(b.shared.connections.send(1, vec![0x01, 0x10]));
+        assert_eq!(outbox_rx.try_recv().unwrap(), [0x01, 0x10]);
+    }
+
+    #[test]
+    fn test_adopt_transfer() {
+        let mut ctx = GameContext::new(123, "127.0.0.1:8080".to_string());
+        let other = GameContext::new(124, "127.0.0.1:8081".to_string());
+        assert_ne!(ctx.correlation_id, other.correlation_id);
+
+        ctx.adopt_transfer(&TransferToken {
+            account_id: 7,
+            character_id: 1001,
+            correlation_id: other.correlation_id,
+            expires_at: 0,
+            nonce: [0; 16],
+        });
+        assert_eq!((ctx.account_id, ctx.character_id), (Some(7), Some(1001)));
+        assert_eq!(ctx.correlation_id, other.correlation_id);
+    }
 }
@@ -0,0 +1,296 @@
+//! Initial handshake (0x0000)
+//!
+//! First game message after the ProudNet key exchange. The client won't
+//! send ReqLogin until the server answers with the same layout, mirroring
+//! the client's fields except for the GUID.
+//!
+//! Payload layout (24 bytes after the opcode), from the official client;
+//! what most fields mean is still a guess, so they're kept as raw bytes and
+//! sent back untouched:
+//!
+//! ```text
+//! 0x00 [version: 2]   01 e1
+//! 0x02 [build: 2]     2e 10 (4142 big-endian, another version?)
+//! 0x04 [unknown: 2]   00 21
+//! 0x06 [guid: u32]    client GUID; the server answers with its own
+//! 0x0A [unknown: 2]   00 01
+//! 0x0C [status: 4]    00 00 00 01, must be echoed
+//! 0x10 [unknown: 4]   07 02 25 00
+//! 0x14 [unknown: 4]   80 3f 00 00
+//! ```
+
+use super::handler::{GameContext, GameMessageHandler, Requirements};
+use crate::Result;
+use crate::wire::{WireReader, WireWriter};
+use anyhow::anyhow;
+use async_trait::async_trait;
+use std::time::Duration;
+use tracing::info;
+
+pub use super::opcodes::INITIAL_HANDSHAKE;
+
+/// Payload length after the opcode
+pub const INITIAL_HANDSHAKE_LEN: usize = 24;
+
+/// Offsets where each field ends, for keeping whole fields of short
+/// messages
+const FIELD_ENDS: [usize; 8] = [2, 4, 6, 10, 12, 16, 20, 24];
+
+/// The 0x0000 handshake payload
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InitialHandshake {
+    pub version: [u8; 2],
+    pub build: [u8; 2],
+    pub unknown_04: [u8; 2],
+    /// Client GUID in the request, server GUID in the response
+    pub guid: u32,
+    pub unknown_0a: [u8; 2],
+    /// The client sends 00 00 00 01 and must get it back
+    pub status: [u8; 4],
+    pub unknown_10: [u8; 4],
+    pub unknown_14: [u8; 4],
+}
+
+impl InitialHandshake {
+    /// The official client's handshake
+    pub const CAPTURED: Self = Self {
+        version: [0x01, 0xE1],
+        build: [0x2E, 0x10],
+        unknown_04: [0x00, 0x21],
+        guid: 0xF116_A4CB,
+        unknown_0a: [0x00, 0x01],
+        status: [0x00, 0x00, 0x00, 0x01],
+        unknown_10: [0x07, 0x02, 0x25, 0x00],
+        unknown_14: [0x80, 0x3F, 0x00, 0x00],
+    };
+
+    /// Parse a payload (after the opcode) of exactly
+    /// [`INITIAL_HANDSHAKE_LEN`] bytes
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        let data: &[u8; INITIAL_HANDSHAKE_LEN] = data.try_into().map_err(|_| {
+            anyhow!(
+                "initial handshake is {} bytes, expected {}",
+                data.len(),
+                INITIAL_HANDSHAKE_LEN
+            )
+        })?;
+        let mut r = WireReader::new(data);
+        Ok(Self {
+            version: r.array("version")?,
+            build: r.array("build")?,
+            unknown_04: r.array("unknown_04")?,
+            guid: r.u32("guid")?,
+            unknown_0a: r.array("unknown_0a")?,
+            status: r.array("status")?,
+            unknown_10: r.array("unknown_10")?,
+            unknown_14: r.array("unknown_14")?,
+        })
+    }
+
+    /// Parse what the client sent, taking fields a short message is
+    /// missing from [`CAPTURED`](Self::CAPTURED) and ignoring anything past
+    /// the last field
+    pub fn from_client(data: &[u8]) -> Self {
+        let mut payload = Self::CAPTURED.encode();
+        let kept = FIELD_ENDS
+            .iter()
+            .copied()
+            .take_while(|end| *end <= data.len())
+            .last()
+            .unwrap_or(0);
+        payload[..kept].copy_from_slice(&data[..kept]);
+        Self::parse(&payload).expect("payload is the right length")
+    }
+
+    /// The server's answer: the same fields with `server_guid`
+    pub fn response(&self, server_guid: u32) -> Self {
+        Self {
+            guid: server_guid,
+            ..*self
+        }
+    }
+
+    /// Encode the payload, without the opcode
+    pub fn encode(&self) -> Vec<u8> {
+        self.write(WireWriter::new())
+    }
+
+    /// Encode as a game message (opcode + payload)
+    pub fn build(&self) -> Vec<u8> {
+        self.write(WireWriter::message(INITIAL_HANDSHAKE))
+    }
+
+    fn write(&self, mut out: WireWriter) -> Vec<u8> {
+        out.bytes(&self.version)
+            .bytes(&self.build)
+            .bytes(&self.unknown_04)
+            .u32(self.guid)
+            .bytes(&self.unknown_0a)
+            .bytes(&self.status)
+            .bytes(&self.unknown_10)
+            .bytes(&self.unknown_14);
+        out.into_bytes()
+    }
+}
+
+/// Answers the client's 0x0000 with its own fields and a server GUID
+pub struct InitialHandshakeHandler;
+
+impl InitialHandshakeHandler {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl Default for InitialHandshakeHandler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl GameMessageHandler for InitialHandshakeHandler {
+    async fn handle(
+        &self,
+        _packet_id: u32,
+        data: &[u8],
+        context: &mut GameContext,
+    ) -> Result<Option<Vec<u8>>> {
+        info!(
+            "Initial handshake (session: {}): {}",
+            context.session_id,
+            hex::encode(data)
+        );
+
+        // The server sends its own GUID (timestamp-based), not the client's
+        let server_guid = context.shared.clock.unix() as u32;
+        let response = InitialHandshake::from_client(data)
+            .response(server_guid)
+            .build();
+        info!(
+            "Sending 0x0000 response ({} bytes, GUID 0x{:08x}): {}",
+            response.len(),
+            server_guid,
+            hex::encode(&response)
+        );
+
+        // The official server answers after ~20ms
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        Ok(Some(response))
+    }
+
+    fn opcode(&self) -> u32 {
+        INITIAL_HANDSHAKE as u32
+    }
+
+    fn name(&self) -> &'static str {
+        "InitialHandshake"
+    }
+
+    fn requirements(&self) -> Requirements {
+        Requirements::ENCRYPTED
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::schema;
+    use crate::testing::Golden;
+    use proptest::prelude::*;
+
+    /// Our answer to the captured request; the official server's hasn't
+    /// been captured, so this is the layout of docs/LOGIN_SERVER_STATUS.md
+    /// with every field mirrored but the GUID
+    const RESPONSE: &str = "
+        0000
+        01e1 2e10 0021
+        ??*4                          # server GUID (timestamp)
+        0001 00000001 07022500 803f0000";
+
+    fn response() -> Golden {
+        Golden::parse("initial_handshake_response", RESPONSE).unwrap()
+    }
+
+    #[test]
+    fn test_parse_captured_request() {
+        let request = Golden::load("initial_handshake_request").bytes();
+        assert_eq!(&request[..2], &INITIAL_HANDSHAKE.to_le_bytes());
+
+        let handshake = InitialHandshake::parse(&request[2..]).unwrap();
+        assert_eq!(handshake, InitialHandshake::CAPTURED);
+        assert_eq!(handshake.guid.to_le_bytes(), [0xCB, 0xA4, 0x16, 0xF1]);
+        assert_eq!(handshake.build(), request);
+
+        assert!(
+            schema::lookup(INITIAL_HANDSHAKE)
+                .unwrap()
+                .decode(&request[2..])
+                .is_exact()
+        );
+        assert!(InitialHandshake::parse(&request[2..20]).is_err());
+    }
+
+    #[test]
+    fn test_response_mirrors_client_fields() {
+        let client = hex::decode("01e12e102100f116a4cb01000100000007022500803f0000").unwrap();
+        let response = InitialHandshake::from_client(&client)
+            .response(0x11223344)
+            .build();
+
+        assert_eq!(response.len(), 26);
+        assert_eq!(
+            &response[..8],
+            &hex::decode("000001e12e102100").unwrap()[..]
+        );
+        assert_eq!(&response[8..12], &0x11223344u32.to_le_bytes());
+        assert_eq!(&response[12..], &client[10..]);
+
+        // Short messages fall back to the captured values, field by field
+        assert_eq!(
+            InitialHandshake::from_client(&[]),
+            InitialHandshake::CAPTURED
+        );
+        let short = InitialHandshake::from_client(&client[..11]);
+        assert_eq!(short.unknown_04, [0x21, 0x00]);
+        assert_eq!(short.guid, 0xCBA4_16F1);
+        assert_eq!(short.unknown_0a, InitialHandshake::CAPTURED.unknown_0a);
+        assert_eq!(short.response(0).encode()[20..], [0x80, 0x3F, 0x00, 0x00]);
+    }
+
+    #[test]
+    fn test_response_layout() {
+        let request = Golden::load("initial_handshake_request").bytes();
+        response().assert_matches(
+            &InitialHandshake::from_client(&request[2..])
+                .response(0x697c2046)
+                .build(),
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handler_answers_with_server_guid() {
+        let request = Golden::load("initial_handshake_request").bytes();
+        let mut context = GameContext::new(1, "127.0.0.1:50000".to_string());
+
+        let response = InitialHandshakeHandler::new()
+            .handle(0x0000, &request[2..], &mut context)
+            .await
+            .unwrap()
+            .unwrap();
+        self::response().assert_matches(&response);
+        assert_ne!(response[8..12], request[8..12]);
+    }
+
+    proptest! {
+        #[test]
+        fn prop_initial_handshake_roundtrip(
+            bytes in prop::collection::vec(any::<u8>(), INITIAL_HANDSHAKE_LEN),
+        ) {
+            let handshake = InitialHandshake::parse(&bytes).unwrap();
+            prop_assert_eq!(handshake.encode(), bytes.clone());
+            prop_assert_eq!(InitialHandshake::from_client(&bytes), handshake);
+        }
+    }
+}
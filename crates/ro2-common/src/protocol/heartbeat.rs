@@ -0,0 +1,180 @@
+//! ProudNet heartbeats (0x1B / 0x1D) and latency tracking
+//!
+//! The client sends 0x1B every few seconds and expects 0x1D echoing its
+//! timestamp next to the server's:
+//!
+//! ```text
+//! 1b [client_time: i64 ms] [reported_ping: u32 ms]
+//! 1d [client_time: i64 ms] [server_time: i64 ms]
+//! ```
+//!
+//! From the official capture (frames 1959 and 1961):
+//! `1b d801000000000000 00000000` and `1d d801000000000000 2374853600000000`.
+//!
+//! The server can't time a round trip itself, since it never sends a ping
+//! of its own. [`LatencyTracker`] takes the round trip the client reports
+//! and measures jitter from how heartbeat arrival drifts against the
+//! client's own clock.
+
+use crate::Result;
+use crate::packet::PacketFrame;
+use crate::wire::WireReader;
+use anyhow::anyhow;
+use std::time::{Duration, Instant};
+
+/// A parsed 0x1B heartbeat
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Heartbeat {
+    /// Client clock in milliseconds
+    pub client_time_ms: i64,
+    /// Presumably the client's last measured round trip (0 at first)
+    pub reported_ping_ms: u32,
+}
+
+impl Heartbeat {
+    /// Parse a 0x1B payload (opcode included)
+    pub fn parse(payload: &[u8]) -> Result<Self> {
+        let mut r = WireReader::new(payload);
+        let opcode = r.u8("opcode")?;
+        if opcode != 0x1B {
+            return Err(anyhow!("not a 0x1B heartbeat: opcode 0x{:02x}", opcode));
+        }
+        Ok(Self {
+            client_time_ms: r.i64("client time")?,
+            reported_ping_ms: r.u32("reported ping")?,
+        })
+    }
+
+    /// Framed 0x1B carrying this heartbeat
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut payload = vec![0x1B];
+        payload.extend_from_slice(&self.client_time_ms.to_le_bytes());
+        payload.extend_from_slice(&self.reported_ping_ms.to_le_bytes());
+        PacketFrame::new(payload).to_bytes()
+    }
+
+    /// Framed 0x1D answering this heartbeat
+    pub fn ack(&self, server_time_ms: i64) -> Vec<u8> {
+        let mut payload = vec![0x1D];
+        payload.extend_from_slice(&self.client_time_ms.to_le_bytes());
+        payload.extend_from_slice(&server_time_ms.to_le_bytes());
+        PacketFrame::new(payload).to_bytes()
+    }
+}
+
+/// Latency of one connection, as seen from its heartbeats
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct LatencyStats {
+    /// Heartbeats received
+    pub heartbeats: u64,
+    /// Last round trip reported by the client
+    pub rtt: Option<Duration>,
+    /// Smoothed variation in heartbeat transit time (RFC 3550 estimator)
+    pub jitter: Duration,
+}
+
+/// Collects [`LatencyStats`] from a connection's heartbeats
+#[derive(Debug, Clone, Default)]
+pub struct LatencyTracker {
+    stats: LatencyStats,
+    /// Previous heartbeat's client time and arrival
+    last: Option<(i64, Instant)>,
+    jitter_ms: f64,
+}
+
+impl LatencyTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record a heartbeat that arrived at `received_at`
+    pub fn record(&mut self, heartbeat: &Heartbeat, received_at: Instant) -> LatencyStats {
+        if let Some((client_time, arrived)) = self.last {
+            let arrival_delta =
+                received_at.saturating_duration_since(arrived).as_secs_f64() * 1000.0;
+            let send_delta = (heartbeat.client_time_ms - client_time) as f64;
+            let transit_change = (arrival_delta - send_delta).abs();
+            self.jitter_ms += (transit_change - self.jitter_ms) / 16.0;
+            self.stats.jitter = Duration::from_secs_f64(self.jitter_ms / 1000.0);
+        }
+
+        if heartbeat.reported_ping_ms > 0 {
+            self.stats.rtt = Some(Duration::from_millis(heartbeat.reported_ping_ms as u64));
+        }
+
+        self.stats.heartbeats += 1;
+        self.last = Some((heartbeat.client_time_ms, received_at));
+        self.stats
+    }
+
+    pub fn stats(&self) -> LatencyStats {
+        self.stats
+    }
+
+    /// When the last heartbeat arrived
+    pub fn last_heartbeat(&self) -> Option<Instant> {
+        self.last.map(|(_, arrived)| arrived)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn test_parse_captured_heartbeat() {
+        let payload = hex::decode("1bd80100000000000000000000").unwrap();
+        let heartbeat = Heartbeat::parse(&payload).unwrap();
+        assert_eq!(heartbeat.client_time_ms, 0x1D8);
+        assert_eq!(heartbeat.reported_ping_ms, 0);
+
+        let (ack, _) = PacketFrame::from_bytes(&heartbeat.ack(0x36857423)).unwrap();
+        assert_eq!(
+            hex::encode(ack.payload),
+            "1dd8010000000000002374853600000000"
+        );
+        assert!(Heartbeat::parse(&payload[..5]).is_err());
+    }
+
+    #[test]
+    fn test_jitter_and_rtt() {
+        let start = Instant::now();
+        let mut tracker = LatencyTracker::new();
+        let beat = |time, ping| Heartbeat {
+            client_time_ms: time,
+            reported_ping_ms: ping,
+        };
+
+        tracker.record(&beat(0, 0), start);
+        assert_eq!(tracker.stats().jitter, Duration::ZERO);
+        assert_eq!(tracker.stats().rtt, None);
+
+        // Sent 5000ms apart, arrived 5160ms apart: 160ms of transit change
+        let stats = tracker.record(&beat(5000, 42), start + Duration::from_millis(5160));
+        assert_eq!(stats.heartbeats, 2);
+        assert_eq!(stats.rtt, Some(Duration::from_millis(42)));
+        assert_eq!(stats.jitter, Duration::from_millis(10));
+        assert_eq!(
+            tracker.last_heartbeat(),
+            Some(start + Duration::from_millis(5160))
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn prop_heartbeat_roundtrip(client_time_ms in any::<i64>(), reported_ping_ms in any::<u32>()) {
+            let heartbeat = Heartbeat { client_time_ms, reported_ping_ms };
+            let (frame, _) = PacketFrame::from_bytes(&heartbeat.to_bytes()).unwrap();
+            prop_assert_eq!(Heartbeat::parse(&frame.payload).unwrap(), heartbeat);
+        }
+
+        #[test]
+        fn prop_truncated_heartbeat_fails(cut in 0..13usize, payload in prop::collection::vec(any::<u8>(), 13)) {
+            let mut payload = payload;
+            payload[0] = 0x1B;
+            prop_assert!(Heartbeat::parse(&payload[..cut]).is_err());
+            prop_assert!(Heartbeat::parse(&payload).is_ok());
+        }
+    }
+}
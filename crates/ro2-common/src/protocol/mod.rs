@@ -78,11 +78,23 @@ pub trait ProudNetPacket: Sized {
 }
 
 pub mod dispatcher;
+pub mod error;
 pub mod handler;
+pub mod handshake;
+pub mod heartbeat;
+pub mod opcodes;
 pub mod proudnet;
 pub mod rmi;
+pub mod schema;
 
 pub use dispatcher::{DispatcherStats, MessageDispatcher};
-pub use handler::{BoxedHandler, ConnectionInfo, GameContext, GameMessageHandler, HandlerRegistry};
+pub use error::{ClientError, ErrorCode, ErrorResponse};
+pub use handler::{
+    BoxedHandler, ConnectionInfo, GameContext, GameMessageHandler, HandlerRegistry, Requirements,
+    SharedState,
+};
+pub use handshake::{InitialHandshake, InitialHandshakeHandler};
+pub use heartbeat::{Heartbeat, LatencyStats, LatencyTracker};
+pub use proudnet::Reliability;
 #[cfg(feature = "server")]
-pub use proudnet::{FLASH_POLICY_XML, ProudNetHandler, ProudNetSettings};
+pub use proudnet::{FLASH_POLICY_XML, ProudNetHandler, ProudNetSettings, build_busy_notice};
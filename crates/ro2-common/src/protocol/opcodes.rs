@@ -0,0 +1,557 @@
+//! Game opcodes shared by the servers and tools
+//!
+//! Every game opcode the servers send or handle is defined here once,
+//! with the direction it travels, what it's for and its payload size when
+//! that's fixed. The login, lobby and world servers and the packet analyzer
+//! all refer to these instead of writing the numbers out. Opcodes in the
+//! 0x3Exx and 0x3Fxx ranges are placeholders until the real ones are found
+//! in captures.
+//!
+//! [`OPCODES`] is checked at compile time, so giving two messages the same
+//! opcode fails the build.
+
+use super::handshake::INITIAL_HANDSHAKE_LEN;
+use crate::net::Direction;
+
+/// Handshake after the ProudNet key exchange, mirrored by the server
+pub const INITIAL_HANDSHAKE: u16 = 0x0000;
+/// System message shown in the chat window
+pub const NFY_SERVER_TIME_TO_LOGIN_PC: u16 = 0x1001;
+/// Client login with username and password
+pub const REQ_LOGIN: u16 = 0x2EE2;
+/// Answer to [`REQ_LOGIN`]
+pub const ACK_LOGIN: u16 = 0x30D5;
+
+/// Placeholder opcode of the client queueing for a dungeon
+pub const REQ_DUNGEON_QUEUE: u16 = 0x3E00;
+/// Placeholder opcode of the client leaving the dungeon queue
+pub const REQ_LEAVE_DUNGEON_QUEUE: u16 = 0x3E01;
+/// Placeholder opcode of a player's place in the dungeon queue
+pub const NFY_DUNGEON_QUEUE: u16 = 0x3E02;
+/// Placeholder opcode of a party formed and taken into a dungeon
+pub const NFY_DUNGEON_READY: u16 = 0x3E03;
+/// Placeholder opcode of the client asking what a stat or skill reset costs
+pub const REQ_RESET_COST: u16 = 0x3E10;
+/// Placeholder opcode of the reset cost answer
+pub const ANS_RESET_COST: u16 = 0x3E11;
+/// Placeholder opcode of resetting stats or skills at an NPC or with an item
+pub const REQ_RESET: u16 = 0x3E12;
+/// Placeholder opcode of the reset result
+pub const ACK_RESET: u16 = 0x3E13;
+
+/// Placeholder opcode of the changes to a zone since the last tick
+pub const NFY_WORLD_DELTA: u16 = 0x3F00;
+/// Placeholder opcode of the movement sync settings
+pub const NFY_MOVE_SYNC: u16 = 0x3F01;
+/// Placeholder opcode of a character's own HP and MP
+pub const NFY_VITALS: u16 = 0x3F02;
+/// Placeholder opcode of the client asking for its Khara list
+pub const REQ_KHARA_LIST: u16 = 0x3F10;
+/// Placeholder opcode of the Khara list answer
+pub const ANS_KHARA_LIST: u16 = 0x3F11;
+/// Placeholder opcode of a challenge's progress changing
+pub const NFY_KHARA_PROGRESS: u16 = 0x3F12;
+/// Placeholder opcode of a title being unlocked
+pub const NFY_TITLE_UNLOCKED: u16 = 0x3F13;
+/// Placeholder opcode of the client equipping or removing a title
+pub const REQ_EQUIP_TITLE: u16 = 0x3F14;
+/// Placeholder opcode of the answer to [`REQ_EQUIP_TITLE`]
+pub const ANS_EQUIP_TITLE: u16 = 0x3F15;
+/// Placeholder opcode of the client asking to mount
+pub const REQ_MOUNT: u16 = 0x3F20;
+/// Placeholder opcode of the client asking to dismount
+pub const REQ_DISMOUNT: u16 = 0x3F21;
+/// Placeholder opcode of an entity mounting or dismounting
+pub const NFY_MOUNT: u16 = 0x3F22;
+/// Placeholder opcode of the client playing an emote
+pub const REQ_EMOTE: u16 = 0x3F30;
+/// Placeholder opcode of an entity playing an emote
+pub const NFY_EMOTE: u16 = 0x3F31;
+/// Placeholder opcode of the client sitting, standing or lying down
+pub const REQ_POSTURE: u16 = 0x3F32;
+/// Placeholder opcode of the login queue position
+pub const NFY_LOGIN_QUEUE: u16 = 0x3F40;
+/// Placeholder opcode of the client renaming a character
+pub const REQ_RENAME: u16 = 0x3F50;
+/// Placeholder opcode of the answer to [`REQ_RENAME`]
+pub const ACK_RENAME: u16 = 0x3F51;
+/// Placeholder opcode of the client changing a character's appearance
+pub const REQ_CHANGE_APPEARANCE: u16 = 0x3F52;
+/// Placeholder opcode of the answer to [`REQ_CHANGE_APPEARANCE`]
+pub const ACK_CHANGE_APPEARANCE: u16 = 0x3F53;
+/// Placeholder opcode of a request failing, with an error code and text
+pub const NFY_ERROR: u16 = 0x3F60;
+/// Placeholder opcode of a use refused because it's cooling down
+pub const NFY_COOLDOWN: u16 = 0x3F70;
+/// Placeholder opcode of a combat log line: a hit, heal or miss
+pub const NFY_COMBAT_LOG: u16 = 0x3F80;
+/// Placeholder opcode of the client using an item in an inventory slot
+pub const REQ_USE_ITEM: u16 = 0x3F90;
+/// Placeholder opcode of a rental item expiring
+pub const NFY_ITEM_EXPIRED: u16 = 0x3F91;
+/// Placeholder opcode of a character starting or resuming the tutorial
+pub const NFY_TUTORIAL_START: u16 = 0x3FA0;
+/// Placeholder opcode of the tutorial moving on to a step
+pub const NFY_TUTORIAL_STEP: u16 = 0x3FA1;
+/// Placeholder opcode of the tutorial ending, finished or skipped
+pub const NFY_TUTORIAL_END: u16 = 0x3FA2;
+/// Placeholder opcode of a megaphone item used to shout to every channel
+pub const REQ_MEGAPHONE: u16 = 0x3FB0;
+/// Placeholder opcode of the client opening its guild's storage
+pub const REQ_GUILD_STORAGE: u16 = 0x3FC0;
+/// Placeholder opcode of the answer to [`REQ_GUILD_STORAGE`]: zeny and stacks
+pub const ANS_GUILD_STORAGE: u16 = 0x3FC1;
+/// Placeholder opcode of a deposit to or withdrawal from guild storage
+pub const REQ_GUILD_STORAGE_MOVE: u16 = 0x3FC2;
+/// Placeholder opcode of the answer to [`REQ_GUILD_STORAGE_MOVE`]
+pub const ACK_GUILD_STORAGE_MOVE: u16 = 0x3FC3;
+/// Placeholder opcode of the client opening an event exchange shop
+pub const REQ_EVENT_SHOP: u16 = 0x3FD0;
+/// Placeholder opcode of the answer to [`REQ_EVENT_SHOP`]: balance and rewards
+pub const ANS_EVENT_SHOP: u16 = 0x3FD1;
+/// Placeholder opcode of event currency exchanged for a reward
+pub const REQ_EVENT_EXCHANGE: u16 = 0x3FD2;
+/// Placeholder opcode of the answer to [`REQ_EVENT_EXCHANGE`]
+pub const ACK_EVENT_EXCHANGE: u16 = 0x3FD3;
+/// Placeholder opcode of an anti-bot challenge the player has to answer
+pub const NFY_BOT_CHALLENGE: u16 = 0x3FE0;
+/// Placeholder opcode of the answer to [`NFY_BOT_CHALLENGE`]
+pub const REQ_BOT_CHALLENGE_ANSWER: u16 = 0x3FE1;
+/// Placeholder opcode of the client asking for the channel list
+pub const REQ_CHANNEL_LIST: u16 = 0x3FF0;
+/// Placeholder opcode of the answer to [`REQ_CHANNEL_LIST`]
+pub const ACK_CHANNEL_LIST_IN_GAME: u16 = 0x3FF1;
+/// Placeholder opcode of the client opening a vending stall
+pub const REQ_OPEN_STALL: u16 = 0x3FF8;
+/// Placeholder opcode of the client closing its vending stall
+pub const REQ_CLOSE_STALL: u16 = 0x3FF9;
+/// Placeholder opcode of a stall opening or closing nearby
+pub const NFY_STALL: u16 = 0x3FFA;
+/// Placeholder opcode of the client looking at a stall's items
+pub const REQ_BROWSE_STALL: u16 = 0x3FFB;
+/// Placeholder opcode of the answer to [`REQ_BROWSE_STALL`]
+pub const ANS_BROWSE_STALL: u16 = 0x3FFC;
+/// Placeholder opcode of the client buying from a stall
+pub const REQ_BUY_FROM_STALL: u16 = 0x3FFD;
+/// Placeholder opcode of the answer to [`REQ_BUY_FROM_STALL`]
+pub const ACK_BUY_FROM_STALL: u16 = 0x3FFE;
+/// Placeholder opcode of a vendor being told of a sale
+pub const NFY_STALL_SALE: u16 = 0x3FFF;
+
+/// What is known about one opcode
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OpcodeInfo {
+    pub id: u16,
+    pub name: &'static str,
+    /// `None` if it's sent both ways
+    pub direction: Option<Direction>,
+    pub description: &'static str,
+    /// Payload size after the opcode, if fixed
+    pub size: Option<usize>,
+}
+
+const fn opcode(
+    id: u16,
+    name: &'static str,
+    direction: Option<Direction>,
+    description: &'static str,
+    size: Option<usize>,
+) -> OpcodeInfo {
+    OpcodeInfo {
+        id,
+        name,
+        direction,
+        description,
+        size,
+    }
+}
+
+const C2S: Option<Direction> = Some(Direction::ClientToServer);
+const S2C: Option<Direction> = Some(Direction::ServerToClient);
+
+/// Every known opcode
+pub static OPCODES: &[OpcodeInfo] = &TABLE;
+
+const TABLE: [OpcodeInfo; 61] = [
+    opcode(
+        INITIAL_HANDSHAKE,
+        "InitialHandshake",
+        None,
+        "Handshake after the key exchange",
+        Some(INITIAL_HANDSHAKE_LEN),
+    ),
+    opcode(
+        NFY_SERVER_TIME_TO_LOGIN_PC,
+        "NfyServerTimeToLoginPC",
+        None,
+        "System message",
+        None,
+    ),
+    opcode(REQ_LOGIN, "ReqLogin", C2S, "Login request", Some(209)),
+    opcode(ACK_LOGIN, "AckLogin", S2C, "Login answer", Some(80)),
+    opcode(
+        REQ_DUNGEON_QUEUE,
+        "ReqDungeonQueue",
+        C2S,
+        "Queue for a dungeon in some roles",
+        Some(5),
+    ),
+    opcode(
+        REQ_LEAVE_DUNGEON_QUEUE,
+        "ReqLeaveDungeonQueue",
+        C2S,
+        "Leave the dungeon queue",
+        Some(0),
+    ),
+    opcode(
+        NFY_DUNGEON_QUEUE,
+        "NfyDungeonQueue",
+        S2C,
+        "Place in the dungeon queue",
+        Some(8),
+    ),
+    opcode(
+        NFY_DUNGEON_READY,
+        "NfyDungeonReady",
+        S2C,
+        "Party formed and taken into a dungeon",
+        None,
+    ),
+    opcode(
+        REQ_RESET_COST,
+        "ReqResetCost",
+        C2S,
+        "Ask what a stat or skill reset costs",
+        Some(1),
+    ),
+    opcode(
+        ANS_RESET_COST,
+        "AnsResetCost",
+        S2C,
+        "Resets so far and the next one's cost",
+        Some(13),
+    ),
+    opcode(
+        REQ_RESET,
+        "ReqReset",
+        C2S,
+        "Reset stats or skills at an NPC or with an item",
+        Some(7),
+    ),
+    opcode(ACK_RESET, "AckReset", S2C, "Reset result", Some(14)),
+    opcode(NFY_WORLD_DELTA, "NfyWorldDelta", S2C, "Zone changes", None),
+    opcode(
+        NFY_MOVE_SYNC,
+        "NfyMoveSync",
+        S2C,
+        "Movement sync settings",
+        Some(6),
+    ),
+    opcode(NFY_VITALS, "NfyVitals", S2C, "Own HP and MP", Some(16)),
+    opcode(
+        REQ_KHARA_LIST,
+        "ReqKharaList",
+        C2S,
+        "Khara list request",
+        Some(0),
+    ),
+    opcode(ANS_KHARA_LIST, "AnsKharaList", S2C, "Khara list", None),
+    opcode(
+        NFY_KHARA_PROGRESS,
+        "NfyKharaProgress",
+        S2C,
+        "Khara challenge progress",
+        Some(8),
+    ),
+    opcode(
+        NFY_TITLE_UNLOCKED,
+        "NfyTitleUnlocked",
+        S2C,
+        "Title unlocked",
+        Some(4),
+    ),
+    opcode(
+        REQ_EQUIP_TITLE,
+        "ReqEquipTitle",
+        C2S,
+        "Equip a title",
+        Some(4),
+    ),
+    opcode(
+        ANS_EQUIP_TITLE,
+        "AnsEquipTitle",
+        S2C,
+        "Title equipped",
+        Some(5),
+    ),
+    opcode(REQ_MOUNT, "ReqMount", C2S, "Mount request", Some(5)),
+    opcode(
+        REQ_DISMOUNT,
+        "ReqDismount",
+        C2S,
+        "Dismount request",
+        Some(0),
+    ),
+    opcode(
+        NFY_MOUNT,
+        "NfyMount",
+        S2C,
+        "Entity mounted or dismounted",
+        Some(10),
+    ),
+    opcode(REQ_EMOTE, "ReqEmote", C2S, "Emote request", Some(2)),
+    opcode(
+        NFY_EMOTE,
+        "NfyEmote",
+        S2C,
+        "Entity played an emote",
+        Some(6),
+    ),
+    opcode(REQ_POSTURE, "ReqPosture", C2S, "Posture change", Some(1)),
+    opcode(
+        NFY_LOGIN_QUEUE,
+        "NfyLoginQueue",
+        S2C,
+        "Login queue position",
+        Some(8),
+    ),
+    opcode(REQ_RENAME, "ReqRename", C2S, "Character rename", None),
+    opcode(ACK_RENAME, "AckRename", S2C, "Rename result", Some(1)),
+    opcode(
+        REQ_CHANGE_APPEARANCE,
+        "ReqChangeAppearance",
+        C2S,
+        "Character appearance change",
+        Some(10),
+    ),
+    opcode(
+        ACK_CHANGE_APPEARANCE,
+        "AckChangeAppearance",
+        S2C,
+        "Appearance change result",
+        Some(1),
+    ),
+    opcode(NFY_ERROR, "NfyError", S2C, "Request failed", None),
+    opcode(
+        NFY_COOLDOWN,
+        "NfyCooldown",
+        S2C,
+        "Use refused while cooling down",
+        Some(9),
+    ),
+    opcode(NFY_COMBAT_LOG, "NfyCombatLog", S2C, "Combat log line", None),
+    opcode(REQ_USE_ITEM, "ReqUseItem", C2S, "Use an item", Some(2)),
+    opcode(
+        NFY_ITEM_EXPIRED,
+        "NfyItemExpired",
+        S2C,
+        "Rental item expired",
+        Some(6),
+    ),
+    opcode(
+        NFY_TUTORIAL_START,
+        "NfyTutorialStart",
+        S2C,
+        "Tutorial started",
+        Some(4),
+    ),
+    opcode(
+        NFY_TUTORIAL_STEP,
+        "NfyTutorialStep",
+        S2C,
+        "Tutorial step",
+        Some(6),
+    ),
+    opcode(
+        NFY_TUTORIAL_END,
+        "NfyTutorialEnd",
+        S2C,
+        "Tutorial ended",
+        Some(1),
+    ),
+    opcode(
+        REQ_MEGAPHONE,
+        "ReqMegaphone",
+        C2S,
+        "Shout to every channel with a megaphone",
+        None,
+    ),
+    opcode(
+        REQ_GUILD_STORAGE,
+        "ReqGuildStorage",
+        C2S,
+        "Open guild storage",
+        Some(0),
+    ),
+    opcode(
+        ANS_GUILD_STORAGE,
+        "AnsGuildStorage",
+        S2C,
+        "Guild storage zeny and items",
+        None,
+    ),
+    opcode(
+        REQ_GUILD_STORAGE_MOVE,
+        "ReqGuildStorageMove",
+        C2S,
+        "Deposit to or withdraw from guild storage",
+        Some(21),
+    ),
+    opcode(
+        ACK_GUILD_STORAGE_MOVE,
+        "AckGuildStorageMove",
+        S2C,
+        "Guild storage move result",
+        Some(5),
+    ),
+    opcode(
+        REQ_EVENT_SHOP,
+        "ReqEventShop",
+        C2S,
+        "Open an event exchange shop",
+        Some(4),
+    ),
+    opcode(
+        ANS_EVENT_SHOP,
+        "AnsEventShop",
+        S2C,
+        "Event currency balance and rewards",
+        None,
+    ),
+    opcode(
+        REQ_EVENT_EXCHANGE,
+        "ReqEventExchange",
+        C2S,
+        "Exchange event currency for a reward",
+        Some(12),
+    ),
+    opcode(
+        ACK_EVENT_EXCHANGE,
+        "AckEventExchange",
+        S2C,
+        "Event exchange result and balance left",
+        Some(13),
+    ),
+    opcode(
+        NFY_BOT_CHALLENGE,
+        "NfyBotChallenge",
+        S2C,
+        "Anti-bot challenge to answer in time",
+        None,
+    ),
+    opcode(
+        REQ_BOT_CHALLENGE_ANSWER,
+        "ReqBotChallengeAnswer",
+        C2S,
+        "Answer to an anti-bot challenge",
+        None,
+    ),
+    opcode(
+        REQ_CHANNEL_LIST,
+        "ReqChannelList",
+        C2S,
+        "Ask for the channels and how busy they are",
+        Some(0),
+    ),
+    opcode(
+        ACK_CHANNEL_LIST_IN_GAME,
+        "AckChannelListInGame",
+        S2C,
+        "Channels, their load and the recommended one",
+        None,
+    ),
+    opcode(
+        REQ_OPEN_STALL,
+        "ReqOpenStall",
+        C2S,
+        "Open a vending stall with priced items",
+        None,
+    ),
+    opcode(
+        REQ_CLOSE_STALL,
+        "ReqCloseStall",
+        C2S,
+        "Close the player's vending stall",
+        Some(0),
+    ),
+    opcode(
+        NFY_STALL,
+        "NfyStall",
+        S2C,
+        "A vending stall opened or closed nearby",
+        None,
+    ),
+    opcode(
+        REQ_BROWSE_STALL,
+        "ReqBrowseStall",
+        C2S,
+        "Look at a vending stall's items",
+        Some(4),
+    ),
+    opcode(
+        ANS_BROWSE_STALL,
+        "AnsBrowseStall",
+        S2C,
+        "A vending stall's items and prices",
+        None,
+    ),
+    opcode(
+        REQ_BUY_FROM_STALL,
+        "ReqBuyFromStall",
+        C2S,
+        "Buy items from a vending stall",
+        Some(24),
+    ),
+    opcode(
+        ACK_BUY_FROM_STALL,
+        "AckBuyFromStall",
+        S2C,
+        "Vending purchase result",
+        Some(5),
+    ),
+    opcode(
+        NFY_STALL_SALE,
+        "NfyStallSale",
+        S2C,
+        "Items sold from the player's stall",
+        Some(18),
+    ),
+];
+
+const _: () = assert!(unique(&TABLE), "two messages share an opcode");
+
+const fn unique(table: &[OpcodeInfo]) -> bool {
+    let mut i = 0;
+    while i < table.len() {
+        let mut j = i + 1;
+        while j < table.len() {
+            if table[i].id == table[j].id {
+                return false;
+            }
+            j += 1;
+        }
+        i += 1;
+    }
+    true
+}
+
+/// Look up an opcode
+pub fn info(id: u16) -> Option<&'static OpcodeInfo> {
+    OPCODES.iter().find(|info| info.id == id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_info() {
+        let login = info(REQ_LOGIN).unwrap();
+        assert_eq!(login.name, "ReqLogin");
+        assert_eq!(login.direction, Some(Direction::ClientToServer));
+        assert_eq!(login.size, Some(209));
+        assert_eq!(info(ACK_LOGIN).unwrap().direction, S2C);
+        assert!(info(0xFFFF).is_none());
+        assert!(!unique(&[TABLE[0], TABLE[0]]));
+    }
+}
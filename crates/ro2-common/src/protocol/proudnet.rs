@@ -1,16 +1,18 @@
 //! ProudNet protocol message handlers (opcodes 0x01-0x32)
 //!
 //! Handles low-level ProudNet protocol messages including:
-//! - 0x01: Disconnect notification (graceful close)
+//! - 0x01: Disconnect notification (graceful close); the server sends one
+//!   with a reason when it's too busy to start a handshake (see
+//!   [`build_busy_notice`])
 //! - 0x2F: Flash policy request (XML response, no framing)
 //! - 0x04: Encryption handshake (send RSA public key)
 //! - 0x05: Encryption response (receive encrypted AES key)
 //! - 0x06: Encryption ready acknowledgment
 //! - 0x07: Version check
 //! - 0x0A: Connection success (session ID)
-//! - 0x1B/0x1D: Heartbeat request/response
+//! - 0x1B/0x1D: Heartbeat request/response (see [`heartbeat`](super::heartbeat))
 //! - 0x1C: Keep-alive ping (no response needed)
-//! - 0x25/0x26: Encrypted game messages
+//! - 0x25/0x26: Encrypted game messages, reliable and unreliable (see [`Reliability`])
 //!
 //! ## TODO: Settings Structure Research
 //!
@@ -31,17 +33,48 @@
 //! 2. Analyze how each field is used after deserialization
 //! 3. Test with modified values to observe client reactions
 //! 4. Cross-reference with ProudNet SDK documentation if available
+//!
+//! ## Replays
+//!
+//! With a [`ReplayGuard`](crate::crypto::replay::ReplayGuard) shared by
+//! every connection using the same server key, a 0x05 whose ciphertext or
+//! session key was already seen is refused as a replay, and so is a second
+//! 0x05 on a connection. 0x07 is then only taken once, after the
+//! connection's own session key is in, so a captured 0x07 is no use on its
+//! own either.
+//!
+//! ## RSA presets
+//!
+//! Besides the fields sent to the client, the settings say how big an RSA
+//! key to generate and which session key paddings to accept. Named
+//! presets ([`ProudNetSettings::preset`]) cover the client builds seen so
+//! far; a build with different padding gets a preset of its own and a row
+//! in the compatibility tests below.
 
+#[cfg(feature = "server")]
+use crate::clock::Clock;
 use crate::crypto::ProudNetCrypto;
+#[cfg(feature = "server")]
+use crate::crypto::SharedRng;
+#[cfg(feature = "server")]
+use crate::crypto::replay::{Fingerprinted, Peer, ReplayGuard};
+#[cfg(feature = "server")]
+use crate::crypto::{DEFAULT_RSA_KEY_BITS, RsaPadding};
 use crate::packet::framing::PacketFrame;
-use anyhow::{anyhow, Result};
+#[cfg(feature = "server")]
+use crate::protocol::heartbeat::{Heartbeat, LatencyStats, LatencyTracker};
+#[cfg(feature = "server")]
+use crate::wire::WireReader;
+use anyhow::{Result, anyhow};
 #[cfg(feature = "server")]
 use rsa::pkcs1::EncodeRsaPublicKey;
 #[cfg(feature = "server")]
 use rsa::traits::PublicKeyParts;
 #[cfg(feature = "server")]
 use std::net::SocketAddr;
-use tracing::{debug, warn};
+#[cfg(feature = "server")]
+use std::time::Instant;
+use tracing::{debug, info, warn};
 
 #[cfg(feature = "server")]
 /// Flash cross-domain policy XML
@@ -51,6 +84,62 @@ use tracing::{debug, warn};
 /// The client expects raw XML data with null terminator (110 bytes total).
 pub const FLASH_POLICY_XML: &[u8] = b"<?xml version=\"1.0\"?><cross-domain-policy><allow-access-from domain=\"*\" to-ports=\"*\" /></cross-domain-policy>\0";
 
+/// Which encrypted wrapper a game message travels in
+///
+/// ProudNet has a reliable and an unreliable channel. 0x25 carries
+/// reliable messages, which the client delivers once and in order; 0x26
+/// carries unreliable ones, which it doesn't sequence or resend, for state
+/// the next message supersedes (movement, positions). Over TCP both
+/// arrive anyway, but handlers shouldn't read anything into the order
+/// unreliable messages come in, or count on every one arriving.
+///
+/// Both have the same layout: the opcode, three flag bytes, then the AES
+/// ciphertext. Captures show `01 01 20` after 0x25; no 0x26 has been
+/// captured yet, so the same flags are sent after it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Reliability {
+    /// 0x25
+    #[default]
+    Reliable,
+    /// 0x26
+    Unreliable,
+}
+
+impl Reliability {
+    /// Bytes before the ciphertext: the opcode and three flag bytes
+    pub const HEADER_LEN: usize = 4;
+
+    /// The wrapper `opcode` is, if it is one
+    pub fn from_opcode(opcode: u8) -> Option<Self> {
+        match opcode {
+            0x25 => Some(Self::Reliable),
+            0x26 => Some(Self::Unreliable),
+            _ => None,
+        }
+    }
+
+    pub fn opcode(self) -> u8 {
+        match self {
+            Self::Reliable => 0x25,
+            Self::Unreliable => 0x26,
+        }
+    }
+
+    /// The opcode and flag bytes that go before the ciphertext
+    pub fn header(self) -> [u8; Self::HEADER_LEN] {
+        [self.opcode(), 0x01, 0x01, 0x20]
+    }
+}
+
+impl std::fmt::Display for Reliability {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Reliable => "reliable",
+            Self::Unreliable => "unreliable",
+        })
+    }
+}
+
 #[cfg(feature = "server")]
 /// ProudNet connection settings for 0x04 packet
 ///
@@ -62,7 +151,7 @@ pub struct ProudNetSettings {
     /// Flags (unknown purpose) - observed: 0x00000000
     pub flags: u32,
 
-    /// Protocol version - observed: 0x01000000 (v1)
+    /// Protocol version - observed: 1 (bytes `01 00 00 00`)
     pub version: u32,
 
     /// Unknown setting 1 - observed: 0x27c00001
@@ -90,6 +179,14 @@ pub struct ProudNetSettings {
 
     /// Unknown setting 3 - observed: 0x02000000 or 2 (LE ambiguous)
     pub unknown3: u32,
+
+    /// Size of the RSA key to generate; not sent to the client, which
+    /// reads it from the key itself
+    pub rsa_key_bits: usize,
+
+    /// Paddings accepted for the session key in 0x05, tried in order; not
+    /// sent to the client
+    pub rsa_paddings: Vec<RsaPadding>,
 }
 
 #[cfg(feature = "server")]
@@ -101,7 +198,7 @@ impl Default for ProudNetSettings {
     fn default() -> Self {
         Self {
             flags: 0x00000000,
-            version: 0x01000000,
+            version: 1,
             unknown1: 0x27c00001,
             unknown2: 0x00010009,
             timeout_secs: 60,           // Best guess based on value
@@ -110,10 +207,52 @@ impl Default for ProudNetSettings {
             unknown_flag1: 1,
             unknown_flag2: 1,
             unknown3: 0x02000000, // Could be 2 or 0x02000000 depending on endianness interpretation
+            rsa_key_bits: DEFAULT_RSA_KEY_BITS,
+            rsa_paddings: RsaPadding::ALL.to_vec(),
         }
     }
 }
 
+#[cfg(feature = "server")]
+impl ProudNetSettings {
+    /// Names of the presets [`preset`](Self::preset) knows
+    pub const PRESETS: [&str; 3] = ["ro2", "ro2-strict", "rsa2048"];
+
+    /// Settings by preset name:
+    ///
+    /// - `ro2`: the defaults; RSA-1024, OAEP-SHA1 with the other paddings
+    ///   as fallbacks
+    /// - `ro2-strict`: RSA-1024 and nothing but the RO2 client's OAEP-SHA1,
+    ///   so a key in another padding is refused rather than now and then
+    ///   misread by a fallback
+    /// - `rsa2048`: RSA-2048 and OAEP-SHA1, for client builds that take a
+    ///   bigger key
+    pub fn preset(name: &str) -> Option<Self> {
+        let settings = Self::default();
+        match name {
+            "ro2" => Some(settings),
+            "ro2-strict" => Some(Self {
+                rsa_paddings: vec![RsaPadding::OaepSha1],
+                ..settings
+            }),
+            "rsa2048" => Some(Self {
+                rsa_key_bits: 2048,
+                rsa_paddings: vec![RsaPadding::OaepSha1],
+                ..settings
+            }),
+            _ => None,
+        }
+    }
+
+    /// Crypto with a fresh RSA keypair of these settings' size, accepting
+    /// their paddings, to share between connections
+    pub fn server_crypto(&self) -> Result<ProudNetCrypto> {
+        let mut crypto = ProudNetCrypto::new().with_rsa_paddings(self.rsa_paddings.clone());
+        crypto.generate_rsa_keypair(self.rsa_key_bits)?;
+        Ok(crypto)
+    }
+}
+
 #[cfg(feature = "server")]
 /// ProudNet protocol handler
 ///
@@ -137,6 +276,21 @@ pub struct ProudNetHandler {
 
     /// ProudNet settings for this connection
     settings: ProudNetSettings,
+
+    /// Round trip and jitter from the client's heartbeats
+    latency: LatencyTracker,
+
+    /// What heartbeats are timed by
+    clock: Clock,
+
+    /// Epoch of the server time sent in 0x1D
+    started: Instant,
+
+    /// The server's end of the connection, if known
+    local_addr: Option<SocketAddr>,
+
+    /// Handshakes seen for this server key, to refuse replays
+    replay_guard: Option<ReplayGuard>,
 }
 
 #[cfg(feature = "server")]
@@ -148,11 +302,8 @@ impl ProudNetHandler {
 
     /// Create a new ProudNet handler with custom settings
     pub fn with_settings(remote_addr: SocketAddr, settings: ProudNetSettings) -> Self {
-        let mut crypto = ProudNetCrypto::new();
-
-        // Generate RSA keypair (1024-bit as used by RO2)
-        crypto
-            .generate_rsa_keypair(1024)
+        let crypto = settings
+            .server_crypto()
             .expect("Failed to generate RSA keypair");
 
         Self {
@@ -162,28 +313,73 @@ impl ProudNetHandler {
             encryption_ready: false,
             client_version: None,
             settings,
+            latency: LatencyTracker::new(),
+            clock: Clock::system(),
+            started: Instant::now(),
+            local_addr: None,
+            replay_guard: None,
         }
     }
 
     /// Create a new ProudNet handler with a shared RSA keypair
     ///
     /// This allows multiple connections to share the same RSA keypair,
-    /// which is necessary for clients that cache server RSA keys.
+    /// which is necessary for clients that cache server RSA keys. The
+    /// paddings accepted are the settings', not the shared crypto's.
     pub fn with_shared_crypto(
         remote_addr: SocketAddr,
         settings: ProudNetSettings,
         crypto: std::sync::Arc<ProudNetCrypto>,
     ) -> Self {
         Self {
-            crypto: (*crypto).clone(),
+            crypto: (*crypto)
+                .clone()
+                .with_rsa_paddings(settings.rsa_paddings.clone()),
             remote_addr,
             session_id: None,
             encryption_ready: false,
             client_version: None,
             settings,
+            latency: LatencyTracker::new(),
+            clock: Clock::system(),
+            started: Instant::now(),
+            local_addr: None,
+            replay_guard: None,
         }
     }
 
+    /// Draw session IDs and GUIDs from `rng`, e.g. a seeded one in tests
+    pub fn with_rng(mut self, rng: SharedRng) -> Self {
+        self.crypto = self.crypto.with_rng(rng);
+        self
+    }
+
+    /// Time heartbeats by `clock`
+    pub fn with_clock(mut self, clock: Clock) -> Self {
+        self.started = clock.instant();
+        self.clock = clock;
+        self
+    }
+
+    /// The clock heartbeats are timed by
+    pub fn clock(&self) -> &Clock {
+        &self.clock
+    }
+
+    /// The server's end of the connection, which session keys are bound
+    /// to along with the client's
+    pub fn with_local_addr(mut self, addr: Option<SocketAddr>) -> Self {
+        self.local_addr = addr;
+        self
+    }
+
+    /// Refuse handshakes `guard` has seen, and take 0x05 and 0x07 only
+    /// once each, in order
+    pub fn with_replay_guard(mut self, guard: ReplayGuard) -> Self {
+        self.replay_guard = Some(guard);
+        self
+    }
+
     /// Handle ProudNet protocol message
     ///
     /// Returns response bytes (may or may not have ProudNet framing)
@@ -241,15 +437,15 @@ impl ProudNetHandler {
     ///   
     ///   ProudNet Settings (40 bytes = 10 x u32 LE):
     ///     00 00 00 00   flags
-    ///     00 00 00 01   version (0x01000000)
-    ///     00 01 00 C0   unknown1 (0x27C00001 mixed endian?)
-    ///     27 09 00 01   unknown2 (0x00010927 or typo?)
-    ///     00 3C 00 00   timeout_secs (60 = 0x3C)
-    ///     00 80 00 00   aes_key_bits (128 = 0x80)
-    ///     00 00 02 00   fast_encrypt_key_bits (512 = 0x200)
-    ///     00 00 00 01   unknown_flag1 (1)
-    ///     00 00 00 01   unknown_flag2 (1)
-    ///     00 00 00 02   unknown3 (2)
+    ///     01 00 00 00   version (1)
+    ///     01 00 C0 27   unknown1 (0x27C00001)
+    ///     09 00 01 00   unknown2 (0x00010009)
+    ///     3C 00 00 00   timeout_secs (60 = 0x3C)
+    ///     80 00 00 00   aes_key_bits (128 = 0x80)
+    ///     00 02 00 00   fast_encrypt_key_bits (512 = 0x200)
+    ///     01 00 00 00   unknown_flag1 (1)
+    ///     01 00 00 00   unknown_flag2 (1)
+    ///     00 00 00 02   unknown3 (0x02000000)
     ///   
     ///   RSA Public Key:
     ///     8C 00           DER length (140 bytes LE = 0x008C)
@@ -321,14 +517,10 @@ impl ProudNetHandler {
     /// └─ Opcode
     /// ```
     fn handle_encryption_response(&mut self, payload: &[u8]) -> Result<Option<Vec<u8>>> {
-        if payload.len() < 5 {
-            return Err(anyhow!("0x05 payload too short: {} bytes", payload.len()));
-        }
-
-        // Parse structure
-        let opcode = payload[0]; // Should be 0x05
-        let _sub_opcode = payload[1]; // Should be 0x02
-        let key_len = u16::from_le_bytes([payload[2], payload[3]]) as usize;
+        let mut r = WireReader::new(payload);
+        let opcode = r.u8("opcode")?; // Should be 0x05
+        let _sub_opcode = r.u8("sub-opcode")?; // Should be 0x02
+        let key_len = r.u16("key length")? as usize;
 
         debug!(
             opcode = format!("0x{:02x}", opcode),
@@ -342,22 +534,20 @@ impl ProudNetHandler {
             return Err(anyhow!("Expected opcode 0x05, got 0x{:02x}", opcode));
         }
 
-        if payload.len() < 4 + key_len {
-            return Err(anyhow!(
-                "0x05 payload truncated: have {} bytes, need {}",
-                payload.len(),
-                4 + key_len
-            ));
-        }
-
         // Extract encrypted AES key
-        let encrypted_key = &payload[4..4 + key_len];
+        let encrypted_key = r.bytes("encrypted key", key_len)?;
+
+        // A replay is turned away before the RSA work
+        if self.replay_guard.is_some() && self.encryption_ready {
+            return Err(anyhow!("Session key already exchanged"));
+        }
+        self.check_replay(Fingerprinted::Ciphertext, encrypted_key)?;
 
         // Note: Extra bytes after encrypted key are present in captures but purpose unknown
         // They may be encrypted IV, signature, or protocol metadata
-        if payload.len() > 4 + key_len {
+        if !r.is_empty() {
             debug!(
-                extra_bytes = payload.len() - 4 - key_len,
+                extra_bytes = r.remaining().len(),
                 "Additional data present after encrypted key"
             );
         }
@@ -365,10 +555,20 @@ impl ProudNetHandler {
         // Decrypt the AES session key using our RSA private key
         match self.crypto.decrypt_session_key_rsa(encrypted_key) {
             Ok(session_key) => {
+                self.check_replay(Fingerprinted::SessionKey, &session_key)?;
                 debug!(
                     session_key_len = session_key.len(),
+                    padding = ?self.crypto.rsa_padding(),
                     "Successfully decrypted AES session key"
                 );
+                if let Some(padding) = self.crypto.rsa_padding() {
+                    info!(
+                        addr = %self.remote_addr,
+                        %padding,
+                        keys_with_padding = padding.decrypted_count(),
+                        "Session key exchanged"
+                    );
+                }
 
                 // LOG SESSION KEY FOR WIRESHARK DECRYPTION
                 // Format: AES_SESSION_KEY: <hex>
@@ -396,6 +596,27 @@ impl ProudNetHandler {
         }
     }
 
+    /// Refuse `bytes` from the 0x05 if the replay guard has seen them
+    fn check_replay(&self, what: Fingerprinted, bytes: &[u8]) -> Result<()> {
+        let Some(guard) = &self.replay_guard else {
+            return Ok(());
+        };
+        let peer = Peer {
+            remote: self.remote_addr,
+            local: self.local_addr,
+        };
+        guard.check(what, bytes, peer).map_err(|replayed| {
+            warn!(
+                addr = %self.remote_addr,
+                first = %replayed.first,
+                what = ?replayed.what,
+                replay_attempts = guard.replays(),
+                "Replayed handshake"
+            );
+            replayed.into()
+        })
+    }
+
     /// Handle 0x07 - Version check
     ///
     /// Structure:
@@ -406,22 +627,26 @@ impl ProudNetHandler {
     /// └─ Opcode
     /// ```
     fn handle_version_check(&mut self, payload: &[u8]) -> Result<Option<Vec<u8>>> {
-        if payload.len() < 23 {
-            return Err(anyhow!("0x07 payload too short"));
+        if self.replay_guard.is_some() {
+            if !self.encryption_ready {
+                return Err(anyhow!("Version check before the session key"));
+            }
+            if self.session_id.is_some() {
+                return Err(anyhow!("Version check repeated"));
+            }
         }
-
-        let version = u16::from_le_bytes([payload[1], payload[2]]);
+        let mut r = WireReader::new(payload);
+        r.u8("opcode")?;
+        let version = r.u16("version")?;
+        let guid = r.guid("client GUID")?;
+        r.bytes("flags", 4)?;
         self.client_version = Some(version as u32);
 
-        debug!(
-            version = version,
-            guid = ?&payload[3..19],
-            "Client version check"
-        );
+        debug!(version = version, guid = ?guid, "Client version check");
 
         // Generate session ID (use LOW value like official server: 14322)
         // Official server uses very low session IDs, not random large values
-        self.session_id = Some(rand::random::<u16>() as u32);
+        self.session_id = Some(u16::from_le_bytes(self.crypto.rng().bytes()) as u32);
 
         // Send 0x0A (Connection success with session ID)
         self.build_connection_success()
@@ -431,8 +656,12 @@ impl ProudNetHandler {
     ///
     /// Structure:
     /// ```text
-    /// 0a [session_id: u32] [server_guid: 16 bytes] 0100 01 01 [ip_len: u8] [ip_string] [crc: u16]
+    /// 0a [session_id: u32] [server_guid: 16 bytes] 0100 01 01 [ip_len: u8] [ip_string] [port: u16]
     /// ```
+    ///
+    /// The official server sends the address it sees the client at: its
+    /// capture carries the client's public IP and an ephemeral port
+    /// (`acf6`, 63148), not a checksum.
     fn build_connection_success(&self) -> Result<Option<Vec<u8>>> {
         let mut payload = Vec::new();
 
@@ -444,7 +673,7 @@ impl ProudNetHandler {
         payload.extend_from_slice(&session_id.to_le_bytes());
 
         // Server GUID (16 random bytes)
-        let server_guid: [u8; 16] = rand::random();
+        let server_guid: [u8; 16] = self.crypto.rng().bytes();
         payload.extend_from_slice(&server_guid);
 
         // Flags
@@ -452,13 +681,13 @@ impl ProudNetHandler {
         payload.push(0x01);
         payload.push(0x01);
 
-        // Server IP address (use connection address)
-        let ip_str = self.remote_addr.ip().to_string();
+        // Address: length-prefixed IP string, then the port. IPv4 clients
+        // of a dual-stack listener are sent their plain IPv4 address.
+        let addr = self.remote_addr;
+        let ip_str = addr.ip().to_canonical().to_string();
         payload.push(ip_str.len() as u8);
         payload.extend_from_slice(ip_str.as_bytes());
-
-        // CRC/checksum field (purpose unclear)
-        payload.extend_from_slice(&[0xac, 0xf6]);
+        payload.extend_from_slice(&addr.port().to_le_bytes());
 
         let frame = PacketFrame::new(payload);
 
@@ -467,42 +696,29 @@ impl ProudNetHandler {
 
     /// Handle 0x1B - Heartbeat request
     ///
-    /// Client sends this periodically (~5 seconds) with timestamp data.
-    /// Server must respond with 0x1D (heartbeat ACK).
-    fn handle_heartbeat_request(&self, payload: &[u8]) -> Result<Option<Vec<u8>>> {
-        // Send 0x1D (Heartbeat ACK) with extended format (17 bytes total)
-        // The client sends a heartbeat with sequence number and expects it echoed back
-
-        // Extract sequence number from client's payload (bytes 0-1 after opcode was already stripped)
-        let sequence = if payload.len() >= 2 {
-            [payload[0], payload[1]]
-        } else {
-            [0x00, 0x00]
+    /// Client sends this periodically (~5 seconds) with its clock and last
+    /// measured ping. Server must respond with 0x1D echoing the client's
+    /// clock next to its own.
+    fn handle_heartbeat_request(&mut self, payload: &[u8]) -> Result<Option<Vec<u8>>> {
+        let heartbeat = match Heartbeat::parse(payload) {
+            Ok(heartbeat) => heartbeat,
+            Err(e) => {
+                warn!(remote_addr = %self.remote_addr, "Ignoring heartbeat: {}", e);
+                return Ok(None);
+            }
         };
 
-        // Build extended heartbeat response (17 bytes)
-        let mut response_payload = vec![
-            0x1D, // Opcode
-            sequence[0],
-            sequence[1], // Echo client's sequence number
-            0x00,
-            0x00,
-            0x00,
-            0x00,
-            0x00,
-            0x00,
-            0x00,
-            0x00, // Reserved (8 bytes)
-            0x00,
-            0x00,
-            0x00,
-            0x00, // Unknown field (4 bytes)
-            0x00,
-            0x00, // Padding (2 bytes)
-        ];
+        let stats = self.latency.record(&heartbeat, self.clock.instant());
+        debug!(
+            remote_addr = %self.remote_addr,
+            client_time_ms = heartbeat.client_time_ms,
+            rtt_ms = stats.rtt.map(|rtt| rtt.as_millis() as u64),
+            jitter_ms = stats.jitter.as_millis() as u64,
+            "Heartbeat"
+        );
 
-        let response = PacketFrame::new(response_payload);
-        Ok(Some(response.to_bytes()))
+        let server_time_ms = (self.clock.instant() - self.started).as_millis() as i64;
+        Ok(Some(heartbeat.ack(server_time_ms)))
     }
 
     /// Handle 0x1C - Keep-alive ping
@@ -524,36 +740,53 @@ impl ProudNetHandler {
         self.session_id
     }
 
-    /// Decrypt an encrypted packet (0x25/0x26)
-    pub fn decrypt_packet(&self, payload: &[u8]) -> Result<Vec<u8>> {
+    /// Settings sent to the client in 0x04
+    pub fn settings(&self) -> &ProudNetSettings {
+        &self.settings
+    }
+
+    /// The padding the client's session key came in, once it has
+    pub fn rsa_padding(&self) -> Option<RsaPadding> {
+        self.crypto.rsa_padding()
+    }
+
+    /// Latency measured from the client's heartbeats so far
+    pub fn latency(&self) -> LatencyStats {
+        self.latency.stats()
+    }
+
+    /// When the client's last heartbeat arrived
+    pub fn last_heartbeat(&self) -> Option<Instant> {
+        self.latency.last_heartbeat()
+    }
+
+    /// Decrypt an encrypted packet (0x25/0x26), and say which it was
+    pub fn decrypt_packet(&self, payload: &[u8]) -> Result<(Reliability, Vec<u8>)> {
         if !self.encryption_ready {
             return Err(anyhow!("Encryption not ready"));
         }
 
-        self.crypto.decrypt_packet_0x25(payload)
+        let reliability = payload
+            .first()
+            .and_then(|&opcode| Reliability::from_opcode(opcode))
+            .ok_or_else(|| anyhow!("Not an encrypted packet"))?;
+        Ok((reliability, self.crypto.decrypt_packet_0x25(payload)?))
     }
 
     /// Encrypt a game message payload and wrap in 0x25 packet
     pub fn encrypt_packet(&self, payload: &[u8]) -> Result<Vec<u8>> {
+        self.encrypt_packet_with(Reliability::Reliable, payload)
+    }
+
+    /// Encrypt a game message payload and wrap it in 0x25 or 0x26
+    pub fn encrypt_packet_with(&self, reliability: Reliability, payload: &[u8]) -> Result<Vec<u8>> {
         if !self.encryption_ready {
             return Err(anyhow!("Encryption not ready"));
         }
 
-        // Encrypt the payload
-        let encrypted = self.crypto.encrypt_aes_ecb(payload)?;
-
-        // Build 0x25 packet frame
         // Structure: [opcode] [flags:3bytes] [encrypted data]
-        // Flags observed from captures: 0x01 0x01 0x20
-        let mut packet_data = vec![
-            0x25, // Opcode (encrypted message)
-            0x01, // Flag byte 1
-            0x01, // Flag byte 2
-            0x20, // Flag byte 3
-        ];
-
-        // Add encrypted data
-        packet_data.extend_from_slice(&encrypted);
+        let mut packet_data = reliability.header().to_vec();
+        packet_data.extend_from_slice(&self.crypto.encrypt_aes_ecb(payload)?);
 
         // Wrap in ProudNet frame (adds magic + varint size)
         let frame = PacketFrame::new(packet_data);
@@ -561,6 +794,18 @@ impl ProudNetHandler {
     }
 }
 
+#[cfg(feature = "server")]
+/// 0x01 from the server: a u16 length and UTF-8 reason, sent unencrypted
+/// before hanging up on a client it's too busy to take
+///
+/// A placeholder until the official server's refusal turns up in a
+/// capture; clients that don't read it still see the connection close.
+pub fn build_busy_notice(reason: &str) -> Vec<u8> {
+    let mut payload = crate::wire::WireWriter::new();
+    payload.u8(0x01).utf8(reason);
+    PacketFrame::new(payload.into_bytes()).to_bytes()
+}
+
 #[cfg(all(test, feature = "server"))]
 mod tests {
     use super::*;
@@ -597,4 +842,211 @@ mod tests {
         // DER should start with 0x30 (SEQUENCE)
         assert_eq!(payload[43], 0x30);
     }
+
+    /// Which client paddings each preset accepts; add a row for each new
+    /// client build
+    #[test]
+    fn test_rsa_padding_compatibility() {
+        use crate::crypto::RsaPadding::*;
+        use crate::testing;
+
+        let matrix: [(&str, &[RsaPadding]); 2] = [
+            ("ro2", &[OaepSha1, Pkcs1v15, OaepSha256]),
+            ("ro2-strict", &[OaepSha1]),
+        ];
+
+        // Seeded throughout, as a fallback padding can now and then accept
+        // a key in another
+        let mut server_crypto = ProudNetCrypto::new().with_rng(SharedRng::seeded(7));
+        server_crypto.generate_rsa_keypair(1024).unwrap();
+        let server_crypto = std::sync::Arc::new(server_crypto);
+        let der = server_crypto
+            .rsa_public_key()
+            .unwrap()
+            .to_pkcs1_der()
+            .unwrap();
+
+        for (preset, accepted) in matrix {
+            for client_padding in RsaPadding::ALL {
+                let mut handler = ProudNetHandler::with_shared_crypto(
+                    "127.0.0.1:7101".parse().unwrap(),
+                    ProudNetSettings::preset(preset).unwrap(),
+                    server_crypto.clone(),
+                );
+                let mut client = ProudNetCrypto::new()
+                    .with_rng(SharedRng::seeded(client_padding as u64))
+                    .with_rsa_paddings(vec![client_padding]);
+                client.set_rsa_public_key_from_der(der.as_bytes()).unwrap();
+                let key = client.generate_aes_session_key();
+                let encrypted = client.encrypt_session_key_rsa(&key).unwrap();
+                let mut response = vec![0x05, 0x02];
+                response.extend_from_slice(&(encrypted.len() as u16).to_le_bytes());
+                response.extend_from_slice(&encrypted);
+
+                let before = client_padding.decrypted_count();
+                let result = handler.handle(0x05, &response);
+                let case = format!("{} client on {}", client_padding, preset);
+                if accepted.contains(&client_padding) {
+                    result.expect(&case);
+                    assert_eq!(handler.rsa_padding(), Some(client_padding), "{}", case);
+                    assert!(client_padding.decrypted_count() > before, "{}", case);
+                    let message = testing::encrypted_payload(key, 0x2EE2, b"user");
+                    assert_eq!(
+                        handler.decrypt_packet(&message).unwrap().1,
+                        testing::game_message(0x2EE2, b"user")
+                    );
+                } else {
+                    assert!(result.is_err(), "{}", case);
+                    assert!(!handler.is_encryption_ready(), "{}", case);
+                }
+            }
+        }
+
+        let strict = ProudNetSettings::preset("rsa2048").unwrap();
+        assert_eq!(strict.rsa_key_bits, 2048);
+        assert_eq!(strict.rsa_paddings, [OaepSha1]);
+        assert!(ProudNetSettings::preset("ro3").is_none());
+    }
+
+    #[test]
+    fn test_handshake_fixture_sequence() {
+        use crate::testing::{self, Handshake};
+
+        let handshake = Handshake::new();
+        let mut handler = ProudNetHandler::with_shared_crypto(
+            "127.0.0.1:7101".parse().unwrap(),
+            ProudNetSettings::default(),
+            handshake.server_crypto(),
+        );
+        let payload = |bytes: Vec<u8>| PacketFrame::from_bytes(&bytes).unwrap().0.payload;
+
+        let response = handler
+            .handle(0x05, &payload(handshake.session_key_response()))
+            .unwrap();
+        assert_eq!(response, Some(testing::encryption_ready()));
+        assert!(handler.is_encryption_ready());
+
+        let response = handler
+            .handle(0x07, &payload(testing::version_check()))
+            .unwrap()
+            .unwrap();
+        assert_eq!(payload(response)[0], 0x0A);
+        assert!(handler.session_id().is_some());
+
+        let response = handler
+            .handle(0x1B, &payload(testing::heartbeat(0x1D8)))
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            &payload(response)[..9],
+            &payload(testing::heartbeat_ack(0x1D8, 0))[..9]
+        );
+        assert_eq!(handler.latency().heartbeats, 1);
+        assert!(handler.last_heartbeat().is_some());
+        assert_eq!(handler.handle(0x1B, &[0x1B, 0x07]).unwrap(), None);
+
+        let message = testing::encrypted_message(handshake.session_key(), 0x2EE2, b"user");
+        assert_eq!(
+            handler.decrypt_packet(&payload(message)).unwrap(),
+            (
+                Reliability::Reliable,
+                testing::game_message(0x2EE2, b"user")
+            )
+        );
+
+        let move_to = testing::game_message(0x1005, &[1, 2, 3, 4]);
+        let message = handler
+            .encrypt_packet_with(Reliability::Unreliable, &move_to)
+            .unwrap();
+        assert_eq!(&payload(message.clone())[..4], [0x26, 0x01, 0x01, 0x20]);
+        assert_eq!(
+            handler.decrypt_packet(&payload(message)).unwrap(),
+            (Reliability::Unreliable, move_to)
+        );
+        assert!(handler.decrypt_packet(&[0x1C]).is_err());
+    }
+
+    #[test]
+    fn test_replayed_handshake() {
+        use crate::crypto::Replayed;
+        use crate::testing::{self, Handshake};
+        use std::time::Duration;
+
+        let handshake = Handshake::new();
+        let guard = ReplayGuard::new(Duration::from_secs(60), 100);
+        let handler = |port: u16| {
+            ProudNetHandler::with_shared_crypto(
+                SocketAddr::from(([10, 0, 0, 1], port)),
+                ProudNetSettings::default(),
+                handshake.server_crypto(),
+            )
+            .with_local_addr(Some("10.0.0.2:7101".parse().unwrap()))
+            .with_replay_guard(guard.clone())
+        };
+        let payload = |bytes: Vec<u8>| PacketFrame::from_bytes(&bytes).unwrap().0.payload;
+        let key_response = payload(handshake.session_key_response());
+        let version_check = payload(testing::version_check());
+
+        // 0x07 only follows the connection's own key, once
+        let mut first = handler(1);
+        assert!(first.handle(0x07, &version_check).is_err());
+        assert!(first.handle(0x05, &key_response).unwrap().is_some());
+        assert!(first.handle(0x05, &key_response).is_err());
+        assert!(first.handle(0x07, &version_check).unwrap().is_some());
+        assert!(first.handle(0x07, &version_check).is_err());
+
+        // The captured 0x05 is no good on another connection
+        let mut replay = handler(2);
+        let error = replay.handle(0x05, &key_response).unwrap_err();
+        let replayed = error.downcast_ref::<Replayed>().unwrap();
+        assert_eq!(replayed.first.remote.port(), 1);
+        assert!(!replay.is_encryption_ready());
+        assert!(replay.handle(0x07, &version_check).is_err());
+
+        // Nor is its key encrypted again
+        let reencrypted = payload(testing::session_key_response(
+            &handshake.server_crypto(),
+            &handshake.session_key(),
+        ));
+        assert_ne!(reencrypted, key_response);
+        let error = handler(3).handle(0x05, &reencrypted).unwrap_err();
+        assert!(error.is::<Replayed>());
+        assert_eq!(guard.replays(), 2);
+    }
+
+    #[test]
+    fn test_connection_success_address_family() {
+        use crate::testing::{
+            self, ScriptedRng, TEST_CLIENT_ADDR, TEST_SERVER_GUID, TEST_SESSION_ID,
+        };
+
+        let rng = || {
+            let mut bytes = (TEST_SESSION_ID as u16).to_le_bytes().to_vec();
+            bytes.extend_from_slice(&TEST_SERVER_GUID);
+            ScriptedRng::shared(bytes)
+        };
+        let version_check = PacketFrame::from_bytes(&testing::version_check())
+            .unwrap()
+            .0
+            .payload;
+
+        // IPv4 clients of a dual-stack listener are sent their IPv4 address
+        let mut handler =
+            ProudNetHandler::new("[::ffff:127.0.0.1]:50123".parse().unwrap()).with_rng(rng());
+        assert_eq!(
+            handler.handle(0x07, &version_check).unwrap(),
+            Some(testing::connection_success(
+                TEST_SESSION_ID,
+                TEST_CLIENT_ADDR
+            ))
+        );
+
+        // The address is a length-prefixed string, so IPv6 fits on the wire
+        let addr = "[2001:db8::1]:50123".parse().unwrap();
+        let mut handler = ProudNetHandler::new(addr).with_rng(rng());
+        assert_eq!(
+            handler.handle(0x07, &version_check).unwrap(),
+            Some(testing::connection_success(TEST_SESSION_ID, addr))
+        );
+    }
 }
@@ -0,0 +1,269 @@
+//! Game message field layouts
+//!
+//! Each known game opcode gets a [`MessageSchema`] describing its payload
+//! field by field. The same table drives the packet analyzer's decoder and
+//! is what message structs are checked against, so a layout only has to be
+//! worked out once.
+//!
+//! Layouts are only added here once confirmed from captures or Ghidra;
+//! until then the analyzer falls back to a hex dump.
+
+use super::opcodes;
+use crate::wire;
+use std::fmt;
+
+/// Wire type of a single field (all integers little-endian)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FieldKind {
+    U8,
+    U16,
+    U32,
+    U64,
+    I32,
+    F32,
+    /// One byte, non-zero is true
+    Bool,
+    /// Fixed-size null-padded string
+    FixedString(usize),
+    /// u16 byte length followed by UTF-8 text
+    String16,
+    /// u16 count of UTF-16 code units followed by the UTF-16LE text
+    WideString16,
+    /// UTF-16LE text ending in a 0x0000 unit
+    WideStringZ,
+    /// Opaque fixed-size bytes
+    Bytes(usize),
+}
+
+/// A named field in a message layout
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FieldDef {
+    pub name: &'static str,
+    pub kind: FieldKind,
+}
+
+impl FieldDef {
+    pub const fn new(name: &'static str, kind: FieldKind) -> Self {
+        Self { name, kind }
+    }
+}
+
+/// Layout of a game message payload (after the u16 opcode)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MessageSchema {
+    pub opcode: u16,
+    pub name: &'static str,
+    pub fields: &'static [FieldDef],
+}
+
+/// A decoded field value
+#[derive(Debug, Clone, PartialEq)]
+pub enum FieldValue {
+    Unsigned(u64),
+    Signed(i64),
+    Float(f32),
+    Bool(bool),
+    Text(String),
+    Bytes(Vec<u8>),
+}
+
+impl fmt::Display for FieldValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FieldValue::Unsigned(v) => write!(f, "{} (0x{:X})", v, v),
+            FieldValue::Signed(v) => write!(f, "{}", v),
+            FieldValue::Float(v) => write!(f, "{}", v),
+            FieldValue::Bool(v) => write!(f, "{}", v),
+            FieldValue::Text(v) => write!(f, "{:?}", v),
+            FieldValue::Bytes(v) => write!(f, "{}", hex::encode(v)),
+        }
+    }
+}
+
+/// Result of decoding a payload against a schema
+#[derive(Debug, Clone, PartialEq)]
+pub struct DecodedMessage {
+    pub schema: &'static MessageSchema,
+
+    /// Fields decoded before the payload ran out
+    pub fields: Vec<(&'static str, FieldValue)>,
+
+    /// First field that didn't fit, if the payload was short
+    pub truncated_at: Option<&'static str>,
+
+    /// Bytes after the last known field
+    pub trailing: Vec<u8>,
+}
+
+impl DecodedMessage {
+    /// Whether the payload matched the layout exactly
+    pub fn is_exact(&self) -> bool {
+        self.truncated_at.is_none() && self.trailing.is_empty()
+    }
+}
+
+impl MessageSchema {
+    /// Decode a payload (without the opcode) field by field
+    pub fn decode(&'static self, data: &[u8]) -> DecodedMessage {
+        let mut fields = Vec::with_capacity(self.fields.len());
+        let mut offset = 0;
+        let mut truncated_at = None;
+
+        for field in self.fields {
+            match decode_field(field.kind, &data[offset..]) {
+                Some((value, size)) => {
+                    fields.push((field.name, value));
+                    offset += size;
+                }
+                None => {
+                    truncated_at = Some(field.name);
+                    break;
+                }
+            }
+        }
+
+        DecodedMessage {
+            schema: self,
+            fields,
+            truncated_at,
+            trailing: data[offset..].to_vec(),
+        }
+    }
+
+    /// Total payload size if every field is fixed-size
+    pub fn fixed_size(&self) -> Option<usize> {
+        self.fields.iter().map(|f| fixed_field_size(f.kind)).sum()
+    }
+}
+
+/// Look up the schema for a game opcode
+pub fn lookup(opcode: u16) -> Option<&'static MessageSchema> {
+    SCHEMAS.iter().find(|s| s.opcode == opcode)
+}
+
+/// Known message layouts
+pub static SCHEMAS: &[MessageSchema] = &[
+    MessageSchema {
+        opcode: opcodes::INITIAL_HANDSHAKE,
+        name: "InitialHandshake",
+        fields: &[
+            FieldDef::new("version", FieldKind::Bytes(2)),
+            FieldDef::new("build", FieldKind::Bytes(2)),
+            FieldDef::new("unknown_04", FieldKind::Bytes(2)),
+            FieldDef::new("guid", FieldKind::U32),
+            FieldDef::new("unknown_0a", FieldKind::Bytes(2)),
+            FieldDef::new("status", FieldKind::Bytes(4)),
+            FieldDef::new("unknown_10", FieldKind::Bytes(4)),
+            FieldDef::new("unknown_14", FieldKind::Bytes(4)),
+        ],
+    },
+    MessageSchema {
+        opcode: opcodes::ACK_LOGIN,
+        name: "AckLogin",
+        fields: &[
+            FieldDef::new("result", FieldKind::U32),
+            FieldDef::new("account_id", FieldKind::U32),
+            FieldDef::new("session_token", FieldKind::Bytes(16)),
+            FieldDef::new("account_data", FieldKind::Bytes(56)),
+        ],
+    },
+    MessageSchema {
+        opcode: opcodes::NFY_SERVER_TIME_TO_LOGIN_PC,
+        name: "NfyServerTimeToLoginPC",
+        fields: &[FieldDef::new("message", FieldKind::WideString16)],
+    },
+];
+
+fn fixed_field_size(kind: FieldKind) -> Option<usize> {
+    match kind {
+        FieldKind::U8 | FieldKind::Bool => Some(1),
+        FieldKind::U16 => Some(2),
+        FieldKind::U32 | FieldKind::I32 | FieldKind::F32 => Some(4),
+        FieldKind::U64 => Some(8),
+        FieldKind::FixedString(n) | FieldKind::Bytes(n) => Some(n),
+        FieldKind::String16 | FieldKind::WideString16 | FieldKind::WideStringZ => None,
+    }
+}
+
+fn decode_field(kind: FieldKind, data: &[u8]) -> Option<(FieldValue, usize)> {
+    match kind {
+        FieldKind::String16 => {
+            let len = u16::from_le_bytes(data.get(0..2)?.try_into().ok()?) as usize;
+            let text = data.get(2..2 + len)?;
+            return Some((
+                FieldValue::Text(String::from_utf8_lossy(text).into_owned()),
+                2 + len,
+            ));
+        }
+        FieldKind::WideString16 => {
+            let (text, len) = wire::read_utf16(data).ok()?;
+            return Some((FieldValue::Text(text), len));
+        }
+        FieldKind::WideStringZ => {
+            let (text, len) = wire::read_utf16z(data).ok()?;
+            return Some((FieldValue::Text(text), len));
+        }
+        _ => {}
+    }
+
+    let size = fixed_field_size(kind)?;
+    let raw = data.get(..size)?;
+    let value = match kind {
+        FieldKind::U8 => FieldValue::Unsigned(raw[0] as u64),
+        FieldKind::U16 => FieldValue::Unsigned(u16::from_le_bytes(raw.try_into().ok()?) as u64),
+        FieldKind::U32 => FieldValue::Unsigned(u32::from_le_bytes(raw.try_into().ok()?) as u64),
+        FieldKind::U64 => FieldValue::Unsigned(u64::from_le_bytes(raw.try_into().ok()?)),
+        FieldKind::I32 => FieldValue::Signed(i32::from_le_bytes(raw.try_into().ok()?) as i64),
+        FieldKind::F32 => FieldValue::Float(f32::from_le_bytes(raw.try_into().ok()?)),
+        FieldKind::Bool => FieldValue::Bool(raw[0] != 0),
+        FieldKind::FixedString(_) => {
+            let end = raw.iter().position(|b| *b == 0).unwrap_or(raw.len());
+            FieldValue::Text(String::from_utf8_lossy(&raw[..end]).into_owned())
+        }
+        FieldKind::Bytes(_) => FieldValue::Bytes(raw.to_vec()),
+        FieldKind::String16 | FieldKind::WideString16 | FieldKind::WideStringZ => {
+            unreachable!("handled above")
+        }
+    };
+
+    Some((value, size))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_ack_login() {
+        let schema = lookup(0x30D5).unwrap();
+        assert_eq!(schema.fixed_size(), Some(80));
+
+        let mut data = vec![0, 0, 0, 0, 7, 0, 0, 0];
+        data.extend([0xAB; 16]);
+        data.extend([0; 56]);
+
+        let decoded = schema.decode(&data);
+        assert!(decoded.is_exact());
+        assert_eq!(decoded.fields[1], ("account_id", FieldValue::Unsigned(7)));
+        assert_eq!(
+            decoded.fields[2],
+            ("session_token", FieldValue::Bytes(vec![0xAB; 16]))
+        );
+    }
+
+    #[test]
+    fn test_decode_trailing_and_truncated() {
+        let schema = lookup(0x1001).unwrap();
+
+        let mut data = Vec::new();
+        wire::write_utf16(&mut data, "hello");
+        data.extend([0xDE, 0xAD]);
+        let decoded = schema.decode(&data);
+        assert_eq!(decoded.fields[0].1, FieldValue::Text(String::from("hello")));
+        assert_eq!(decoded.trailing, vec![0xDE, 0xAD]);
+
+        let decoded = schema.decode(&[10, 0, b'h', 0]);
+        assert_eq!(decoded.truncated_at, Some("message"));
+        assert_eq!(decoded.trailing, vec![10, 0, b'h', 0]);
+    }
+}
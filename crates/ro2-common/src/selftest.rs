@@ -0,0 +1,283 @@
+//! `--self-test`: check a server could start, then exit
+//!
+//! Every server binary run with `--self-test` loads its config, generates
+//! its RSA key, runs a ProudNet handshake against itself through
+//! [`ProudNetClient`] over an in-memory stream, and checks that the
+//! database in `DATABASE_URL` answers and has every migration applied.
+//! It prints one line per check and exits non-zero if any failed, for
+//! container healthchecks and deploy pipelines:
+//!
+//! ```text
+//! $ ro2-login --self-test
+//! ok   config: config/login.toml
+//! ok   handshake: session 3107 over RSA-1024
+//! ok   database: 22 migrations applied
+//! ```
+
+use crate::Result;
+use crate::config::{self, ServerConfig};
+use crate::net::{ProudNetClient, ProudNetConnection};
+use crate::protocol::{MessageDispatcher, ProudNetHandler, ProudNetSettings};
+use anyhow::{Context, anyhow};
+use sqlx::{Pool, Sqlite};
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// The command-line flag
+pub const FLAG: &str = "--self-test";
+
+/// How long the loopback handshake may take
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A query per migration that fails until it has been applied
+pub const MIGRATIONS: &[(&str, &str)] = &[
+    ("001_initial_schema", "SELECT id FROM accounts LIMIT 0"),
+    (
+        "002_playtime_limits",
+        "SELECT account_id FROM account_playtime LIMIT 0",
+    ),
+    (
+        "003_account_language",
+        "SELECT language FROM accounts LIMIT 0",
+    ),
+    (
+        "004_character_appearance",
+        "SELECT slot_index FROM characters LIMIT 0",
+    ),
+    (
+        "005_character_skills",
+        "SELECT character_id FROM character_skills LIMIT 0",
+    ),
+    (
+        "006_character_professions",
+        "SELECT character_id FROM character_professions LIMIT 0",
+    ),
+    ("007_khara", "SELECT title_id FROM characters LIMIT 0"),
+    (
+        "008_character_changes",
+        "SELECT renamed_at FROM characters LIMIT 0",
+    ),
+    (
+        "009_account_deactivation",
+        "SELECT anonymized_at FROM accounts LIMIT 0",
+    ),
+    (
+        "010_item_transactions",
+        "SELECT idempotency_key FROM item_transactions LIMIT 0",
+    ),
+    (
+        "011_item_attributes",
+        "SELECT expires_at FROM inventory LIMIT 0",
+    ),
+    (
+        "012_tutorial",
+        "SELECT character_id FROM character_tutorial LIMIT 0",
+    ),
+    (
+        "013_character_slots",
+        "SELECT account_id FROM account_slot_grants LIMIT 0",
+    ),
+    (
+        "014_global_messages",
+        "SELECT kind FROM global_messages LIMIT 0",
+    ),
+    (
+        "015_guild_storage",
+        "SELECT guild_id FROM guild_storage LIMIT 0",
+    ),
+    (
+        "016_event_currency",
+        "SELECT currency_id FROM event_currency LIMIT 0",
+    ),
+    (
+        "017_bot_challenges",
+        "SELECT account_id FROM bot_challenges LIMIT 0",
+    ),
+    (
+        "018_channel_status",
+        "SELECT name FROM channel_status LIMIT 0",
+    ),
+    (
+        "019_vending",
+        "SELECT stall_slot FROM vending_items LIMIT 0",
+    ),
+    (
+        "020_character_resets",
+        "SELECT skill_points FROM character_stats LIMIT 0",
+    ),
+    (
+        "021_profile_values",
+        "SELECT namespace FROM account_profile_values LIMIT 0",
+    ),
+    (
+        "022_external_accounts",
+        "SELECT external_id FROM external_accounts LIMIT 0",
+    ),
+];
+
+/// Whether the binary was started with [`FLAG`]
+pub fn requested() -> bool {
+    std::env::args().skip(1).any(|arg| arg == FLAG)
+}
+
+/// Results of the checks run so far
+#[derive(Default)]
+pub struct SelfTest {
+    results: Vec<(&'static str, Result<String>)>,
+}
+
+impl SelfTest {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record how check `name` went; `Ok` carries a detail to print
+    pub fn record(&mut self, name: &'static str, outcome: Result<String>) {
+        self.results.push((name, outcome));
+    }
+
+    pub fn passed(&self) -> bool {
+        self.results.iter().all(|(_, outcome)| outcome.is_ok())
+    }
+
+    /// One line per check
+    pub fn report(&self) -> String {
+        self.results
+            .iter()
+            .map(|(name, outcome)| match outcome {
+                Ok(detail) => format!("ok   {}: {}", name, detail),
+                Err(e) => format!("FAIL {}: {:#}", name, e),
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Print the report; an error if any check failed
+    pub fn finish(self) -> Result<()> {
+        println!("{}", self.report());
+        let failed = self.results.iter().filter(|(_, o)| o.is_err()).count();
+        match failed {
+            0 => Ok(()),
+            n => Err(anyhow!(
+                "{} of {} self-checks failed",
+                n,
+                self.results.len()
+            )),
+        }
+    }
+}
+
+/// The checks every server shares: its config at `config_path`, the
+/// handshake with the configured settings, and the database if
+/// `DATABASE_URL` is set. Servers record their own checks on top.
+pub async fn run(config_path: &str, port: u16) -> SelfTest {
+    let mut test = SelfTest::new();
+    match ServerConfig::load(config_path, port).and_then(|config| config.proudnet_settings()) {
+        Ok(settings) => {
+            test.record("config", Ok(config_path.to_string()));
+            test.record("handshake", handshake(&settings).await);
+        }
+        Err(e) => test.record("config", Err(e)),
+    }
+    let database = match config::database_url() {
+        Ok(Some(url)) => database(&url).await,
+        Ok(None) => Ok("DATABASE_URL not set, skipped".to_string()),
+        Err(e) => Err(e),
+    };
+    test.record("database", database);
+    test
+}
+
+/// Generate a key with `settings` and complete a handshake with it
+pub async fn handshake(settings: &ProudNetSettings) -> Result<String> {
+    let crypto = Arc::new(settings.server_crypto().context("generating the RSA key")?);
+    let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, 0));
+    let handler = ProudNetHandler::with_shared_crypto(addr, settings.clone(), crypto);
+
+    let (client, server) = tokio::io::duplex(8192);
+    let mut connection = ProudNetConnection::new(server, addr, handler);
+    let server = tokio::spawn(async move { connection.run(&mut MessageDispatcher::new()).await });
+
+    let client = tokio::time::timeout(HANDSHAKE_TIMEOUT, ProudNetClient::connect(client))
+        .await
+        .map_err(|_| anyhow!("timed out"))??;
+    let session_id = client.session_id();
+    drop(client);
+    server.await??;
+    Ok(format!(
+        "session {} over RSA-{}",
+        session_id, settings.rsa_key_bits
+    ))
+}
+
+/// Check the database at `url` answers and has every migration
+pub async fn database(url: &str) -> Result<String> {
+    let pool = sqlx::SqlitePool::connect(url).await.context("connecting")?;
+    migrations(&pool).await
+}
+
+/// Check every migration in [`MIGRATIONS`] has been applied
+pub async fn migrations(pool: &Pool<Sqlite>) -> Result<String> {
+    for (migration, probe) in MIGRATIONS {
+        sqlx::query(probe)
+            .execute(pool)
+            .await
+            .with_context(|| format!("{} not applied", migration))?;
+    }
+    Ok(format!("{} migrations applied", MIGRATIONS.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_loopback_handshake() {
+        let detail = handshake(&ProudNetSettings::default()).await.unwrap();
+        assert!(detail.ends_with("over RSA-1024"), "{}", detail);
+    }
+
+    #[tokio::test]
+    async fn test_migrations() {
+        // Every migration on file has a probe, in the same order
+        let on_file = crate::testing::database::migrations();
+        assert_eq!(
+            on_file
+                .iter()
+                .map(|(name, _)| name.as_str())
+                .collect::<Vec<_>>(),
+            MIGRATIONS.iter().map(|(name, _)| *name).collect::<Vec<_>>()
+        );
+
+        // and each probe fails until its migration is applied
+        let pool = sqlx::SqlitePool::connect(":memory:").await.unwrap();
+        for (name, sql) in &on_file {
+            let missing = migrations(&pool).await.unwrap_err();
+            assert!(
+                format!("{:#}", missing).starts_with(&format!("{} not applied", name)),
+                "{:#}",
+                missing
+            );
+            sqlx::raw_sql(sql).execute(&pool).await.unwrap();
+        }
+        assert_eq!(
+            migrations(&pool).await.unwrap(),
+            format!("{} migrations applied", on_file.len())
+        );
+    }
+
+    #[test]
+    fn test_report() {
+        let mut test = SelfTest::new();
+        test.record("config", Ok("config/login.toml".to_string()));
+        assert!(test.passed());
+        test.record("database", Err(anyhow!("connecting")));
+        assert!(!test.passed());
+        assert_eq!(
+            test.report(),
+            "ok   config: config/login.toml\nFAIL database: connecting"
+        );
+        assert!(test.finish().is_err());
+    }
+}
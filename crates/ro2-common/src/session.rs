@@ -0,0 +1,292 @@
+//! Sessions handed from one server to another
+//!
+//! When a player picks a character the lobby gives the client a transfer
+//! token to present to the world server. The token says which account and
+//! character it is for and when it expires, and is signed with HMAC-SHA256
+//! under a secret the servers share (`transfer_secret` in their config),
+//! so a client can't make one up or change one. The world server's
+//! [`SessionManager`] accepts each token once.
+//!
+//! The token also carries the player's [`CorrelationId`], so the world
+//! server's logs for the player can be matched up with the lobby's.
+//!
+//! Token layout, little-endian:
+//!
+//! ```text
+//! [version: u8] [account_id: u64] [character_id: i64] [correlation_id: u64] [expires_at: u64] [nonce: 16] [mac: 32]
+//! ```
+//!
+//! `expires_at` is in seconds since the Unix epoch; the MAC covers every
+//! byte before it.
+
+use crate::clock::Clock;
+use crate::crypto::SharedRng;
+use crate::crypto::mac::{self, MAC_LEN, MIN_KEY_LEN};
+use crate::wire::WireReader;
+use anyhow::{Result, anyhow};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Mutex;
+use std::time::Duration;
+use zeroize::Zeroizing;
+
+/// How long a token is good for by default
+pub const DEFAULT_TRANSFER_TTL: Duration = Duration::from_secs(30);
+
+/// Length of an encoded token
+pub const TOKEN_LEN: usize = BODY_LEN + MAC_LEN;
+
+const TOKEN_VERSION: u8 = 2;
+const BODY_LEN: usize = 1 + 8 + 8 + 8 + 8 + 16;
+
+/// Tags every log line about one player, across connections and servers
+///
+/// Each connection starts with a random one and the tracing spans of the
+/// connection and its messages carry it; a connection handed over with a
+/// transfer token takes the token's instead.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CorrelationId(pub u64);
+
+impl CorrelationId {
+    /// A new random ID
+    pub fn random() -> Self {
+        Self(rand::random())
+    }
+}
+
+impl fmt::Display for CorrelationId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:016x}", self.0)
+    }
+}
+
+/// What a transfer token vouches for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TransferToken {
+    pub account_id: u64,
+    pub character_id: i64,
+    pub correlation_id: CorrelationId,
+    /// Seconds since the Unix epoch
+    pub expires_at: u64,
+    /// Makes each token unique, so it can only be used once
+    pub nonce: [u8; 16],
+}
+
+impl TransferToken {
+    /// Encode and sign with `key`
+    pub fn encode(&self, key: &[u8]) -> Vec<u8> {
+        let mut out = Vec::with_capacity(TOKEN_LEN);
+        out.push(TOKEN_VERSION);
+        out.extend_from_slice(&self.account_id.to_le_bytes());
+        out.extend_from_slice(&self.character_id.to_le_bytes());
+        out.extend_from_slice(&self.correlation_id.0.to_le_bytes());
+        out.extend_from_slice(&self.expires_at.to_le_bytes());
+        out.extend_from_slice(&self.nonce);
+        let mac = mac::sign(key, &out);
+        out.extend_from_slice(&mac);
+        out
+    }
+
+    /// Check the signature with `key` and decode; says nothing about
+    /// expiry or reuse
+    pub fn decode(token: &[u8], key: &[u8]) -> Result<Self> {
+        if token.len() != TOKEN_LEN {
+            return Err(anyhow!("transfer token is {} bytes", token.len()));
+        }
+        let (body, signature) = token.split_at(BODY_LEN);
+        if !mac::verify(key, body, signature) {
+            return Err(anyhow!("transfer token signature doesn't match"));
+        }
+        let mut r = WireReader::new(body);
+        let version = r.u8("token version")?;
+        if version != TOKEN_VERSION {
+            return Err(anyhow!("unknown transfer token version {}", version));
+        }
+        Ok(Self {
+            account_id: r.u64("account ID")?,
+            character_id: r.u64("character ID")? as i64,
+            correlation_id: CorrelationId(r.u64("correlation ID")?),
+            expires_at: r.u64("expiry")?,
+            nonce: r.array("nonce")?,
+        })
+    }
+}
+
+/// Issues transfer tokens and accepts each one once
+pub struct SessionManager {
+    /// Wiped when the manager is dropped
+    key: Zeroizing<Vec<u8>>,
+    ttl: Duration,
+    rng: SharedRng,
+    clock: Clock,
+    /// Nonces of tokens already accepted, until they expire
+    redeemed: Mutex<HashMap<[u8; 16], u64>>,
+}
+
+impl SessionManager {
+    /// Sign tokens with `secret`, at least [`MIN_KEY_LEN`] bytes
+    pub fn new(secret: Vec<u8>) -> Result<Self> {
+        let secret = Zeroizing::new(secret);
+        if secret.len() < MIN_KEY_LEN {
+            return Err(anyhow!(
+                "transfer secret must be at least {} bytes",
+                MIN_KEY_LEN
+            ));
+        }
+        Ok(Self {
+            key: secret,
+            ttl: DEFAULT_TRANSFER_TTL,
+            rng: SharedRng::os(),
+            clock: Clock::system(),
+            redeemed: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Make tokens good for `ttl`
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Draw nonces from `rng`
+    pub fn with_rng(mut self, rng: SharedRng) -> Self {
+        self.rng = rng;
+        self
+    }
+
+    /// Tell the time by `clock`
+    pub fn with_clock(mut self, clock: Clock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    fn now(&self) -> u64 {
+        self.clock.unix().max(0) as u64
+    }
+
+    /// A signed token letting `account_id` into the world as
+    /// `character_id`, still tagged `correlation_id`
+    pub fn issue(
+        &self,
+        account_id: u64,
+        character_id: i64,
+        correlation_id: CorrelationId,
+    ) -> Vec<u8> {
+        TransferToken {
+            account_id,
+            character_id,
+            correlation_id,
+            expires_at: self.now() + self.ttl.as_secs(),
+            nonce: self.rng.bytes(),
+        }
+        .encode(&self.key)
+    }
+
+    /// Accept `token` if it's genuine, unexpired and not used before
+    pub fn redeem(&self, token: &[u8]) -> Result<TransferToken> {
+        let token = TransferToken::decode(token, &self.key)?;
+        let now = self.now();
+        if token.expires_at <= now {
+            return Err(anyhow!("transfer token expired"));
+        }
+
+        let mut redeemed = self.redeemed.lock().unwrap_or_else(|e| e.into_inner());
+        redeemed.retain(|_, expires_at| *expires_at > now);
+        if redeemed.insert(token.nonce, token.expires_at).is_some() {
+            return Err(anyhow!("transfer token already used"));
+        }
+        Ok(token)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    const CORRELATION: CorrelationId = CorrelationId(0x0123_4567_89ab_cdef);
+
+    fn manager(clock: &Clock) -> SessionManager {
+        SessionManager::new(vec![42; 32])
+            .unwrap()
+            .with_rng(SharedRng::seeded(1))
+            .with_clock(clock.clone())
+    }
+
+    #[test]
+    fn test_tokens_are_single_use() {
+        let clock = Clock::at_unix(1_000);
+        let sessions = manager(&clock);
+        let token = sessions.issue(7, 1001, CORRELATION);
+        assert_eq!(token.len(), TOKEN_LEN);
+        let other = sessions.issue(7, 1001, CORRELATION);
+
+        clock.advance(Duration::from_secs(10));
+        let redeemed = sessions.redeem(&token).unwrap();
+        assert_eq!((redeemed.account_id, redeemed.character_id), (7, 1001));
+        assert_eq!(redeemed.correlation_id, CORRELATION);
+        assert_eq!(redeemed.correlation_id.to_string(), "0123456789abcdef");
+        assert_eq!(redeemed.expires_at, 1_030);
+        assert!(sessions.redeem(&token).is_err());
+
+        // Each token is unique, and good until just before it expires
+        assert_ne!(other, token);
+        clock.advance(Duration::from_secs(19));
+        sessions.redeem(&other).unwrap();
+    }
+
+    #[test]
+    fn test_tokens_expire() {
+        let clock = Clock::at_unix(1_000);
+        let sessions = manager(&clock);
+        let token = sessions.issue(7, 1001, CORRELATION);
+        clock.advance(DEFAULT_TRANSFER_TTL);
+        assert!(sessions.redeem(&token).is_err());
+    }
+
+    #[test]
+    fn test_forged_tokens_are_refused() {
+        let clock = Clock::at_unix(1_000);
+        let sessions = manager(&clock);
+        let token = sessions.issue(7, 1001, CORRELATION);
+
+        // Another character, with the old signature
+        let mut tampered = token.clone();
+        tampered[9] = 2;
+        assert!(sessions.redeem(&tampered).is_err());
+
+        // Signed with another secret
+        let stranger = SessionManager::new(vec![1; 32]).unwrap();
+        assert!(
+            sessions
+                .redeem(&stranger.issue(7, 1001, CORRELATION))
+                .is_err()
+        );
+
+        assert!(sessions.redeem(&token[..TOKEN_LEN - 1]).is_err());
+        assert!(SessionManager::new(vec![1; 16]).is_err());
+        sessions.redeem(&token).unwrap();
+    }
+
+    proptest! {
+        #[test]
+        fn prop_transfer_token_roundtrip(
+            account_id in any::<u64>(),
+            character_id in any::<i64>(),
+            correlation in any::<u64>(),
+            expires_at in any::<u64>(),
+            nonce in prop::array::uniform16(any::<u8>()),
+        ) {
+            let token = TransferToken {
+                account_id,
+                character_id,
+                correlation_id: CorrelationId(correlation),
+                expires_at,
+                nonce,
+            };
+            let encoded = token.encode(&[42; 32]);
+            prop_assert_eq!(encoded.len(), TOKEN_LEN);
+            prop_assert_eq!(TransferToken::decode(&encoded, &[42; 32]).unwrap(), token);
+        }
+    }
+}
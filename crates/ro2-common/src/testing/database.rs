@@ -0,0 +1,81 @@
+//! Database fixtures
+//!
+//! The SQLite migrations are read from the workspace's `migrations/`
+//! directory when a test asks for them, so a new migration is picked up
+//! without touching any test. [`database`] is an in-memory database with
+//! all of them applied, the schema production runs; [`character`] seeds
+//! it.
+
+use sqlx::sqlite::SqlitePoolOptions;
+use sqlx::{Pool, Sqlite};
+use std::path::PathBuf;
+
+/// The account [`character`] puts characters on: `player`, which the
+/// initial schema seeds
+pub const ACCOUNT_ID: i64 = 2;
+
+/// Directory holding the migrations
+pub fn dir() -> PathBuf {
+    PathBuf::from(concat!(env!("CARGO_MANIFEST_DIR"), "/../../migrations"))
+}
+
+/// Every SQLite migration, as (name, SQL) in the order they apply; the
+/// MySQL versions (`*_mysql.sql`) are left out
+pub fn migrations() -> Vec<(String, String)> {
+    let entries = std::fs::read_dir(dir()).expect("reading the migrations directory");
+    let mut migrations: Vec<_> = entries
+        .map(|entry| entry.expect("listing the migrations directory").path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "sql"))
+        .filter_map(|path| {
+            let name = path.file_stem()?.to_str()?.to_string();
+            (!name.ends_with("_mysql")).then_some((name, path))
+        })
+        .map(|(name, path)| {
+            let sql = std::fs::read_to_string(&path)
+                .unwrap_or_else(|e| panic!("reading {}: {}", path.display(), e));
+            (name, sql)
+        })
+        .collect();
+    migrations.sort();
+    migrations
+}
+
+/// Apply every migration to `pool`
+pub async fn migrate(pool: &Pool<Sqlite>) {
+    for (name, sql) in migrations() {
+        sqlx::raw_sql(&sql)
+            .execute(pool)
+            .await
+            .unwrap_or_else(|e| panic!("applying {}: {}", name, e));
+    }
+}
+
+/// A fresh in-memory database with every migration applied
+///
+/// It has one connection, since each connection to `sqlite::memory:` is a
+/// database of its own.
+pub async fn database() -> Pool<Sqlite> {
+    let pool = SqlitePoolOptions::new()
+        .max_connections(1)
+        .connect("sqlite::memory:")
+        .await
+        .expect("opening an in-memory database");
+    migrate(&pool).await;
+    pool
+}
+
+/// Add character `id` called `name` with `zeny` to [`ACCOUNT_ID`]: a level
+/// 1 class 1 in slot 0, at the origin of map 1 with 100 HP and 50 MP
+pub async fn character(pool: &Pool<Sqlite>, id: i64, name: &str, zeny: i64) {
+    sqlx::query(
+        "INSERT INTO characters (id, account_id, name, class_id, map_id, position_x, position_y, position_z, hp, max_hp, mp, max_mp, gold, created_at)
+         VALUES (?, ?, ?, 1, 1, 0, 0, 0, 100, 100, 50, 50, ?, 0)",
+    )
+    .bind(id)
+    .bind(ACCOUNT_ID)
+    .bind(name)
+    .bind(zeny)
+    .execute(pool)
+    .await
+    .unwrap_or_else(|e| panic!("adding character {} ({}): {}", id, name, e));
+}
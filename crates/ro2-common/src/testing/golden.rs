@@ -0,0 +1,246 @@
+//! Golden packets
+//!
+//! Reference packets are checked in under `tests/golden/` in this crate, one
+//! `.hex` file per packet: whitespace-separated hex with `#` comments. Each
+//! file says where its bytes came from, and only captures belong there;
+//! layouts of packets nobody has captured yet are [`Golden::parse`]d from
+//! text in the unit tests of the code that builds them. Bytes that are
+//! random per connection and can't be reproduced (such as RSA keys) are
+//! written `??`, and `XX*N` repeats a byte (or `??`) N times. Conformance
+//! tests build the same packet with our code, replaying captured random
+//! fields through [`ScriptedRng`](super::ScriptedRng), and assert it is
+//! byte-identical apart from the masked bytes.
+
+use crate::Result;
+use anyhow::{Context, anyhow};
+use std::path::PathBuf;
+
+/// A reference packet; `None` bytes are masked
+#[derive(Debug, Clone, PartialEq)]
+pub struct Golden {
+    name: String,
+    bytes: Vec<Option<u8>>,
+}
+
+impl Golden {
+    /// Load `tests/golden/<name>.hex`, panicking if it's missing or invalid
+    pub fn load(name: &str) -> Self {
+        let path = Self::dir().join(format!("{}.hex", name));
+        let text = std::fs::read_to_string(&path)
+            .unwrap_or_else(|e| panic!("reading {}: {}", path.display(), e));
+        Self::parse(name, &text).unwrap_or_else(|e| panic!("parsing {}: {:#}", path.display(), e))
+    }
+
+    /// Directory holding the corpus
+    pub fn dir() -> PathBuf {
+        PathBuf::from(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/golden"))
+    }
+
+    pub fn parse(name: &str, text: &str) -> Result<Self> {
+        let mut bytes = Vec::new();
+        let tokens = text
+            .lines()
+            .map(|line| line.split('#').next().unwrap_or(""))
+            .flat_map(str::split_whitespace);
+
+        for token in tokens {
+            let (unit, count) = match token.split_once('*') {
+                Some((unit, count)) => (
+                    unit,
+                    count
+                        .parse()
+                        .with_context(|| format!("bad repeat count in `{}`", token))?,
+                ),
+                None => (token, 1),
+            };
+
+            let unit: Vec<Option<u8>> = if unit == "??" {
+                vec![None]
+            } else {
+                hex::decode(unit)
+                    .with_context(|| format!("bad hex `{}`", token))?
+                    .into_iter()
+                    .map(Some)
+                    .collect()
+            };
+            for _ in 0..count {
+                bytes.extend_from_slice(&unit);
+            }
+        }
+
+        Ok(Self {
+            name: name.to_string(),
+            bytes,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    /// The packet's bytes, for packets without masked bytes (e.g. client
+    /// messages fed to our handlers)
+    pub fn bytes(&self) -> Vec<u8> {
+        self.bytes
+            .iter()
+            .map(|b| b.unwrap_or_else(|| panic!("{} has masked bytes", self.name)))
+            .collect()
+    }
+
+    /// The packet's bytes with the masked ones taken from `fill` in order,
+    /// then zeros once it runs out
+    pub fn fill(&self, fill: &[u8]) -> Vec<u8> {
+        let mut fill = fill.iter().copied();
+        self.bytes
+            .iter()
+            .map(|b| b.unwrap_or_else(|| fill.next().unwrap_or(0)))
+            .collect()
+    }
+
+    /// Compare `actual` against the golden bytes, ignoring masked ones
+    pub fn compare(&self, actual: &[u8]) -> Result<()> {
+        let mismatch = self
+            .bytes
+            .iter()
+            .zip(actual)
+            .position(|(golden, actual)| golden.is_some_and(|g| g != *actual));
+
+        if let Some(offset) = mismatch {
+            return Err(anyhow!(
+                "{}: byte {} is {:02x}, golden has {:02x}\n  ours:   {}\n  golden: {}",
+                self.name,
+                offset,
+                actual[offset],
+                self.bytes[offset].unwrap_or_default(),
+                hex::encode(actual),
+                self.render()
+            ));
+        }
+        if actual.len() != self.bytes.len() {
+            return Err(anyhow!(
+                "{}: {} bytes, golden has {}\n  ours:   {}\n  golden: {}",
+                self.name,
+                actual.len(),
+                self.bytes.len(),
+                hex::encode(actual),
+                self.render()
+            ));
+        }
+        Ok(())
+    }
+
+    /// Panic with a readable diff unless `actual` matches
+    pub fn assert_matches(&self, actual: &[u8]) {
+        if let Err(e) = self.compare(actual) {
+            panic!("{}", e);
+        }
+    }
+
+    fn render(&self) -> String {
+        self.bytes
+            .iter()
+            .map(|b| b.map_or("??".to_string(), |b| format!("{:02x}", b)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_masks_and_repeats() {
+        let golden = Golden::parse("t", "1357 # magic\n01 03\n??*2 00*1 ??").unwrap();
+        assert_eq!(golden.len(), 8);
+
+        golden.assert_matches(&[0x13, 0x57, 0x01, 0x03, 0xAA, 0xBB, 0x00, 0xCC]);
+        assert!(
+            golden
+                .compare(&[0x13, 0x57, 0x01, 0x04, 0, 0, 0, 0])
+                .is_err()
+        );
+        assert!(golden.compare(&[0x13, 0x57, 0x01, 0x03, 0, 0, 0]).is_err());
+        assert_eq!(
+            golden.fill(&[0xAA]),
+            [0x13, 0x57, 0x01, 0x03, 0xAA, 0x00, 0x00, 0x00]
+        );
+    }
+}
+
+/// Our builders against the corpus
+#[cfg(all(test, feature = "server"))]
+mod conformance {
+    use super::*;
+    use crate::packet::PacketFrame;
+    use crate::protocol::{ProudNetHandler, ProudNetSettings};
+    use crate::testing::{self, Handshake, ScriptedRng};
+
+    fn payload(bytes: &[u8]) -> Vec<u8> {
+        PacketFrame::from_bytes(bytes).unwrap().0.payload
+    }
+
+    #[test]
+    fn test_client_fixtures() {
+        Golden::load("policy_request").assert_matches(&testing::policy_request());
+        Golden::load("version_check").assert_matches(&testing::version_check());
+        Golden::load("heartbeat").assert_matches(&testing::heartbeat(0x1D8));
+    }
+
+    #[test]
+    fn test_encryption_handshake() {
+        let handshake = Handshake::new();
+        let mut handler = ProudNetHandler::with_shared_crypto(
+            "127.0.0.1:7101".parse().unwrap(),
+            ProudNetSettings::default(),
+            handshake.server_crypto(),
+        );
+
+        Golden::load("encryption_handshake")
+            .assert_matches(&handler.build_encryption_handshake().unwrap());
+
+        let ready = handler
+            .handle(0x05, &payload(&handshake.session_key_response()))
+            .unwrap()
+            .unwrap();
+        Golden::load("encryption_ready").assert_matches(&ready);
+    }
+
+    #[test]
+    fn test_connection_success() {
+        // The capture's 0x0A carries the client's address, and the session
+        // ID and GUID are replayed from it
+        let captured_rng =
+            || ScriptedRng::shared(hex::decode("473a279823e6a11ac54c97b2795747576770").unwrap());
+        let version_check = payload(&Golden::load("version_check").bytes());
+        let golden = Golden::load("connection_success");
+
+        let mut handler =
+            ProudNetHandler::new("67.249.150.97:63148".parse().unwrap()).with_rng(captured_rng());
+        let response = handler.handle(0x07, &version_check).unwrap().unwrap();
+        golden.assert_matches(&response);
+
+        // Another client is sent its own address
+        let mut handler =
+            ProudNetHandler::new("10.0.0.2:40000".parse().unwrap()).with_rng(captured_rng());
+        let response = handler.handle(0x07, &version_check).unwrap().unwrap();
+        assert!(golden.compare(&response).is_err());
+        assert!(response.windows(8).any(|window| window == b"10.0.0.2"));
+    }
+
+    #[test]
+    fn test_heartbeat_ack() {
+        let mut handler = ProudNetHandler::new("127.0.0.1:50123".parse().unwrap());
+        let heartbeat = payload(&Golden::load("heartbeat").bytes());
+
+        let ack = handler.handle(0x1B, &heartbeat).unwrap().unwrap();
+        Golden::load("heartbeat_ack").assert_matches(&ack);
+    }
+}
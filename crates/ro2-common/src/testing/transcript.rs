@@ -0,0 +1,545 @@
+//! Recorded connections
+//!
+//! A transcript is one recorded connection's packets in order, checked in
+//! next to the golden packets as `tests/golden/<name>.transcript`. Each
+//! line is a direction, `>` client to server or `<` server to client, then
+//! the packet: the name of a golden packet, or `policy` for the unframed
+//! Flash policy XML. Golden game messages are plaintext; `encrypted` after
+//! one says it travels in 0x25 under the session key. `uncaptured` marks a
+//! step whose bytes weren't recorded, only that it happened, so the
+//! connection can get past it:
+//!
+//! ```text
+//! > encryption_response uncaptured
+//! < encryption_ready
+//! > version_check
+//! < connection_success
+//! > initial_handshake_request encrypted
+//! ```
+//!
+//! The interoperability tests replay every transcript against our code
+//! from both ends: the recorded client's packets into our server and the
+//! recorded server's into [`ProudNetClient`](crate::net::ProudNetClient).
+//! Each side has to take the other's recorded bytes and answer with the
+//! packet recorded next. Masked bytes are filled with what the connection
+//! needs (our RSA key, a session key encrypted for it) or zeros. Our client
+//! doesn't heartbeat, so its side skips the plain packets after 0x0A.
+//!
+//! Only captured steps say anything about the official client and server.
+//! An uncaptured step is our own packet standing in for theirs, so a
+//! mismatch there (padding, framing) goes unnoticed until a capture of it
+//! replaces it.
+
+use super::{Direction, Golden};
+use crate::Result;
+use anyhow::{Context, anyhow};
+
+/// One recorded packet
+#[derive(Debug, Clone, PartialEq)]
+pub enum Packet {
+    /// The Flash policy XML, sent unframed
+    Policy,
+    Golden {
+        golden: Golden,
+        encrypted: bool,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub direction: Direction,
+    pub packet: Packet,
+    /// Whether the packet's bytes were recorded, not stood in for
+    pub captured: bool,
+}
+
+/// A recorded connection
+#[derive(Debug, Clone, PartialEq)]
+pub struct Transcript {
+    name: String,
+    entries: Vec<Entry>,
+}
+
+impl Transcript {
+    /// Load `tests/golden/<name>.transcript`, panicking if it or a packet
+    /// it names is missing or invalid
+    pub fn load(name: &str) -> Self {
+        let path = Golden::dir().join(format!("{}.transcript", name));
+        let text = std::fs::read_to_string(&path)
+            .unwrap_or_else(|e| panic!("reading {}: {}", path.display(), e));
+        Self::parse(name, &text, Golden::load)
+            .unwrap_or_else(|e| panic!("parsing {}: {:#}", path.display(), e))
+    }
+
+    /// Every transcript in the corpus, by name
+    pub fn all() -> Vec<Self> {
+        let dir = Golden::dir();
+        let mut names: Vec<String> = std::fs::read_dir(&dir)
+            .unwrap_or_else(|e| panic!("reading {}: {}", dir.display(), e))
+            .filter_map(|entry| {
+                let path = entry.ok()?.path();
+                (path.extension()? == "transcript")
+                    .then(|| path.file_stem()?.to_str().map(str::to_string))?
+            })
+            .collect();
+        names.sort();
+        names.iter().map(|name| Self::load(name)).collect()
+    }
+
+    /// Parse transcript text, getting golden packets from `golden`
+    pub fn parse(name: &str, text: &str, golden: impl Fn(&str) -> Golden) -> Result<Self> {
+        let mut entries = Vec::new();
+        let lines = text
+            .lines()
+            .map(|line| line.split('#').next().unwrap_or(""))
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty());
+        for (index, line) in lines {
+            let entry = match line.split_whitespace().collect::<Vec<_>>()[..] {
+                [direction, packet, ref flags @ ..] => {
+                    let captured = !flags.contains(&"uncaptured");
+                    let flags: Vec<&str> = flags
+                        .iter()
+                        .copied()
+                        .filter(|flag| *flag != "uncaptured")
+                        .collect();
+                    let direction = match direction {
+                        ">" => Direction::ClientToServer,
+                        "<" => Direction::ServerToClient,
+                        _ => {
+                            return Err(anyhow!(
+                                "line {}: bad direction `{}`",
+                                index + 1,
+                                direction
+                            ));
+                        }
+                    };
+                    let packet = match (packet, &flags[..]) {
+                        ("policy", []) => Packet::Policy,
+                        (packet, []) => Packet::Golden {
+                            golden: golden(packet),
+                            encrypted: false,
+                        },
+                        (packet, ["encrypted"]) => Packet::Golden {
+                            golden: golden(packet),
+                            encrypted: true,
+                        },
+                        (_, flags) => {
+                            return Err(anyhow!("line {}: bad flags {:?}", index + 1, flags));
+                        }
+                    };
+                    Entry {
+                        direction,
+                        packet,
+                        captured,
+                    }
+                }
+                _ => {
+                    return Err(anyhow!(
+                        "line {}: expected a direction and a packet",
+                        index + 1
+                    ));
+                }
+            };
+            entries.push(entry);
+        }
+        if entries.is_empty() {
+            return Err(anyhow!("no packets")).with_context(|| name.to_string());
+        }
+        Ok(Self {
+            name: name.to_string(),
+            entries,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn golden(name: &str) -> Golden {
+        Golden::parse(name, "1357 01 01 06").unwrap()
+    }
+
+    #[test]
+    fn test_parse() {
+        let transcript = Transcript::parse(
+            "t",
+            "# comment\n> ready\n\n< policy\n< ready encrypted # note\n> ready uncaptured encrypted",
+            golden,
+        )
+        .unwrap();
+        assert_eq!(
+            transcript.entries(),
+            [
+                Entry {
+                    direction: Direction::ClientToServer,
+                    packet: Packet::Golden {
+                        golden: golden("ready"),
+                        encrypted: false
+                    },
+                    captured: true,
+                },
+                Entry {
+                    direction: Direction::ServerToClient,
+                    packet: Packet::Policy,
+                    captured: true,
+                },
+                Entry {
+                    direction: Direction::ServerToClient,
+                    packet: Packet::Golden {
+                        golden: golden("ready"),
+                        encrypted: true
+                    },
+                    captured: true,
+                },
+                Entry {
+                    direction: Direction::ClientToServer,
+                    packet: Packet::Golden {
+                        golden: golden("ready"),
+                        encrypted: true
+                    },
+                    captured: false,
+                },
+            ]
+        );
+
+        for bad in [
+            "",
+            "# only a comment",
+            "ready",
+            "= ready",
+            "> ready compressed",
+        ] {
+            assert!(Transcript::parse("t", bad, golden).is_err(), "{:?}", bad);
+        }
+    }
+
+    #[test]
+    fn test_corpus_loads() {
+        let transcripts = Transcript::all();
+        assert!(transcripts.iter().any(|t| t.name() == "login_handshake"));
+    }
+}
+
+/// Our client and server against the recorded transcripts
+#[cfg(all(test, feature = "server", feature = "client"))]
+mod interop {
+    use super::*;
+    use crate::crypto::ProudNetCrypto;
+    use crate::net::{Chunk, FrameBuffer, ProudNetClient, ProudNetConnection};
+    use crate::packet::PacketFrame;
+    use crate::protocol::{
+        FLASH_POLICY_XML, InitialHandshakeHandler, MessageDispatcher, ProudNetHandler,
+        ProudNetSettings,
+    };
+    use crate::testing::{self, Handshake};
+    use rsa::pkcs1::EncodeRsaPublicKey;
+    use std::net::SocketAddr;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream};
+
+    /// The client address in the recorded 0x0A
+    const RECORDED_CLIENT_ADDR: &str = "67.249.150.97:63148";
+
+    const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+    /// The ProudNet opcode of a framed golden packet
+    fn frame_opcode(golden: &Golden) -> u8 {
+        let (frame, _) = PacketFrame::from_bytes(&golden.fill(&[]))
+            .unwrap_or_else(|e| panic!("{} isn't a frame: {}", golden.name(), e));
+        frame.opcode().unwrap_or_default()
+    }
+
+    /// A plaintext golden game message, encrypted with `key` and framed
+    fn encrypted(golden: &Golden, key: [u8; 16]) -> Vec<u8> {
+        let plain = golden.fill(&[]);
+        let (opcode, body) = plain.split_at(2);
+        testing::encrypted_message(key, u16::from_le_bytes([opcode[0], opcode[1]]), body)
+    }
+
+    fn decrypt(payload: &[u8], key: [u8; 16]) -> Vec<u8> {
+        let mut crypto = ProudNetCrypto::new();
+        crypto.set_aes_session_key(key);
+        crypto.decrypt_packet_0x25(payload).unwrap()
+    }
+
+    async fn next_chunk(stream: &mut DuplexStream, buffer: &mut FrameBuffer) -> Chunk {
+        let read = async {
+            let mut read_buf = [0u8; 4096];
+            loop {
+                if let Some(chunk) = buffer.next_chunk() {
+                    return chunk;
+                }
+                let n = stream.read(&mut read_buf).await.unwrap();
+                assert!(n > 0, "connection closed");
+                buffer.extend(&read_buf[..n]);
+            }
+        };
+        tokio::time::timeout(READ_TIMEOUT, read)
+            .await
+            .expect("no answer")
+    }
+
+    async fn next_frame(stream: &mut DuplexStream, buffer: &mut FrameBuffer) -> PacketFrame {
+        match next_chunk(stream, buffer).await {
+            Chunk::Frame { frame, .. } => frame,
+            Chunk::Unframed(data) => panic!("expected a frame, got {:02x?}", data),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_server_accepts_recorded_client() {
+        for transcript in Transcript::all() {
+            let handshake = Handshake::new();
+            let session_key = handshake.session_key();
+            let addr: SocketAddr = RECORDED_CLIENT_ADDR.parse().unwrap();
+            let handler = ProudNetHandler::with_shared_crypto(
+                addr,
+                ProudNetSettings::default(),
+                handshake.server_crypto(),
+            );
+            let (mut client, server) = tokio::io::duplex(64 * 1024);
+            let mut connection = ProudNetConnection::new(server, addr, handler);
+            tokio::spawn(async move {
+                let mut dispatcher =
+                    MessageDispatcher::with_handlers(vec![
+                        Arc::new(InitialHandshakeHandler::new()),
+                    ]);
+                let _ = connection.run(&mut dispatcher).await;
+            });
+
+            let mut buffer = FrameBuffer::new();
+            for (index, entry) in transcript.entries().iter().enumerate() {
+                let step = format!("{} step {}", transcript.name(), index + 1);
+                match (entry.direction, &entry.packet) {
+                    (Direction::ClientToServer, Packet::Policy) => {
+                        panic!("{}: the client doesn't send the policy", step)
+                    }
+                    (
+                        Direction::ClientToServer,
+                        Packet::Golden {
+                            golden,
+                            encrypted: false,
+                        },
+                    ) => {
+                        // 0x05's key is masked: encrypt ours for this server
+                        let fill = match frame_opcode(golden) {
+                            0x05 => {
+                                let (frame, _) =
+                                    PacketFrame::from_bytes(&handshake.session_key_response())
+                                        .unwrap();
+                                frame.payload[4..].to_vec()
+                            }
+                            _ => Vec::new(),
+                        };
+                        client.write_all(&golden.fill(&fill)).await.unwrap();
+                    }
+                    (
+                        Direction::ClientToServer,
+                        Packet::Golden {
+                            golden,
+                            encrypted: true,
+                        },
+                    ) => {
+                        client
+                            .write_all(&encrypted(golden, session_key))
+                            .await
+                            .unwrap();
+                    }
+                    (Direction::ServerToClient, Packet::Policy) => {
+                        let mut xml = Vec::new();
+                        while !xml.ends_with(b"\0") {
+                            match next_chunk(&mut client, &mut buffer).await {
+                                Chunk::Unframed(data) => xml.extend(data),
+                                Chunk::Frame { .. } => panic!("{}: expected the policy", step),
+                            }
+                        }
+                    }
+                    (
+                        Direction::ServerToClient,
+                        Packet::Golden {
+                            golden,
+                            encrypted: false,
+                        },
+                    ) => {
+                        let frame = next_frame(&mut client, &mut buffer).await;
+                        assert_eq!(
+                            frame.opcode(),
+                            Some(frame_opcode(golden)),
+                            "{}: expected {}",
+                            step,
+                            golden.name()
+                        );
+                    }
+                    (
+                        Direction::ServerToClient,
+                        Packet::Golden {
+                            golden,
+                            encrypted: true,
+                        },
+                    ) => {
+                        let frame = next_frame(&mut client, &mut buffer).await;
+                        golden
+                            .compare(&decrypt(&frame.payload, session_key))
+                            .with_context(|| step.clone())
+                            .unwrap();
+                    }
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_client_accepts_recorded_server() {
+        for transcript in Transcript::all() {
+            let handshake = Handshake::new();
+            let mut server_crypto = (*handshake.server_crypto()).clone();
+            let public_key = handshake
+                .private_key()
+                .to_public_key()
+                .to_pkcs1_der()
+                .unwrap()
+                .as_bytes()
+                .to_vec();
+
+            // After 0x0A our client sends and reads the game messages
+            let connected_at = transcript
+                .entries()
+                .iter()
+                .position(|entry| {
+                    matches!(&entry.packet, Packet::Golden { golden, encrypted: false }
+                        if entry.direction == Direction::ServerToClient && frame_opcode(golden) == 0x0A)
+                })
+                .unwrap_or_else(|| panic!("{} never connects", transcript.name()));
+            let messages: Vec<(Direction, Golden)> = transcript.entries()[connected_at + 1..]
+                .iter()
+                .filter_map(|entry| match &entry.packet {
+                    Packet::Golden {
+                        golden,
+                        encrypted: true,
+                    } => Some((entry.direction, golden.clone())),
+                    _ => None,
+                })
+                .collect();
+            let (stream, mut server) = tokio::io::duplex(64 * 1024);
+            let client = tokio::spawn(async move {
+                let mut client = ProudNetClient::connect(stream).await?;
+                for (direction, golden) in messages {
+                    match direction {
+                        Direction::ClientToServer => client.send_message(&golden.fill(&[])).await?,
+                        Direction::ServerToClient => {
+                            golden.compare(&client.recv_message().await?)?;
+                        }
+                    }
+                }
+                Ok::<_, anyhow::Error>(client.session_id())
+            });
+
+            let mut buffer = FrameBuffer::new();
+            let mut session_key = None;
+            let mut recorded_session_id = 0;
+            for (index, entry) in transcript.entries().iter().enumerate() {
+                let step = format!("{} step {}", transcript.name(), index + 1);
+                let connected = index > connected_at;
+                match (entry.direction, &entry.packet) {
+                    (
+                        _,
+                        Packet::Golden {
+                            encrypted: false, ..
+                        },
+                    ) if connected => {}
+                    (Direction::ClientToServer, Packet::Policy) => {
+                        panic!("{}: the client doesn't send the policy", step)
+                    }
+                    (
+                        Direction::ClientToServer,
+                        Packet::Golden {
+                            golden,
+                            encrypted: false,
+                        },
+                    ) => {
+                        let frame = next_frame(&mut server, &mut buffer).await;
+                        assert_eq!(
+                            frame.opcode(),
+                            Some(frame_opcode(golden)),
+                            "{}: expected {}",
+                            step,
+                            golden.name()
+                        );
+                        if frame.opcode() == Some(0x05) {
+                            let len = u16::from_le_bytes([frame.payload[2], frame.payload[3]]);
+                            let key = server_crypto
+                                .decrypt_session_key_rsa(&frame.payload[4..4 + len as usize])
+                                .unwrap();
+                            session_key = Some(<[u8; 16]>::try_from(&key[..]).unwrap());
+                        }
+                    }
+                    (
+                        Direction::ClientToServer,
+                        Packet::Golden {
+                            golden,
+                            encrypted: true,
+                        },
+                    ) => {
+                        let frame = next_frame(&mut server, &mut buffer).await;
+                        let key = session_key.expect("no session key yet");
+                        golden
+                            .compare(&decrypt(&frame.payload, key))
+                            .with_context(|| step.clone())
+                            .unwrap();
+                    }
+                    (Direction::ServerToClient, Packet::Policy) => {
+                        server.write_all(FLASH_POLICY_XML).await.unwrap();
+                    }
+                    (
+                        Direction::ServerToClient,
+                        Packet::Golden {
+                            golden,
+                            encrypted: false,
+                        },
+                    ) => {
+                        // 0x04's key is masked: send ours
+                        let packet = match frame_opcode(golden) {
+                            0x04 => golden.fill(&public_key),
+                            _ => golden.fill(&[]),
+                        };
+                        if index == connected_at {
+                            let (frame, _) = PacketFrame::from_bytes(&packet).unwrap();
+                            recorded_session_id =
+                                u32::from_le_bytes(frame.payload[1..5].try_into().unwrap());
+                        }
+                        server.write_all(&packet).await.unwrap();
+                    }
+                    (
+                        Direction::ServerToClient,
+                        Packet::Golden {
+                            golden,
+                            encrypted: true,
+                        },
+                    ) => {
+                        let key = session_key.expect("no session key yet");
+                        server.write_all(&encrypted(golden, key)).await.unwrap();
+                    }
+                }
+            }
+
+            let session_id = tokio::time::timeout(READ_TIMEOUT, client)
+                .await
+                .expect("client stuck")
+                .unwrap()
+                .with_context(|| transcript.name().to_string())
+                .unwrap();
+            assert_eq!(session_id, recorded_session_id, "{}", transcript.name());
+        }
+    }
+}
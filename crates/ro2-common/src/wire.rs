@@ -0,0 +1,664 @@
+//! The game's wire format
+//!
+//! A game message is a u16 opcode followed by its fields, packed with no
+//! padding. Builders and parsers write and read them through a
+//! [`WireWriter`] and a [`WireReader`] instead of slicing bytes by hand, so
+//! a short message is a [`WireError`] naming the field rather than a panic,
+//! and each field's encoding lives in one place:
+//!
+//! | Field | Encoding |
+//! |---|---|
+//! | integers, floats | little-endian, their natural size |
+//! | bool | one byte, non-zero is true |
+//! | UTF-8 string | u16 byte count, then the bytes |
+//! | UTF-16 string | u16 count of code units, then UTF-16LE ([`write_utf16`]) |
+//! | terminated UTF-16 string | UTF-16LE, then a 0x0000 unit ([`write_utf16z`]) |
+//! | fixed string | N bytes of UTF-8, padded with NULs |
+//! | blob | u16 byte count, then the bytes |
+//! | GUID | 16 bytes as they are |
+//! | optional | u8 1 and the value, or u8 0 |
+//!
+//! The client keeps text as `wchar_t`, so new messages carry UTF-16; the
+//! UTF-8 strings are for the placeholder messages that already use them.
+
+/// Most code units a counted UTF-16 string can hold
+pub const MAX_UTF16_UNITS: usize = u16::MAX as usize;
+
+/// A 16-byte GUID, as ProudNet sends them
+pub type Guid = [u8; 16];
+
+/// Builds a message field by field
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WireWriter {
+    out: Vec<u8>,
+}
+
+impl WireWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start a game message with its opcode
+    pub fn message(opcode: u16) -> Self {
+        let mut writer = Self::new();
+        writer.u16(opcode);
+        writer
+    }
+
+    pub fn u8(&mut self, value: u8) -> &mut Self {
+        self.out.push(value);
+        self
+    }
+
+    pub fn u16(&mut self, value: u16) -> &mut Self {
+        self.bytes(&value.to_le_bytes())
+    }
+
+    pub fn u32(&mut self, value: u32) -> &mut Self {
+        self.bytes(&value.to_le_bytes())
+    }
+
+    pub fn u64(&mut self, value: u64) -> &mut Self {
+        self.bytes(&value.to_le_bytes())
+    }
+
+    pub fn i32(&mut self, value: i32) -> &mut Self {
+        self.bytes(&value.to_le_bytes())
+    }
+
+    pub fn i64(&mut self, value: i64) -> &mut Self {
+        self.bytes(&value.to_le_bytes())
+    }
+
+    pub fn f32(&mut self, value: f32) -> &mut Self {
+        self.bytes(&value.to_le_bytes())
+    }
+
+    pub fn bool(&mut self, value: bool) -> &mut Self {
+        self.u8(value as u8)
+    }
+
+    /// Raw bytes, such as a fixed-size array
+    pub fn bytes(&mut self, bytes: &[u8]) -> &mut Self {
+        self.out.extend_from_slice(bytes);
+        self
+    }
+
+    pub fn guid(&mut self, guid: &Guid) -> &mut Self {
+        self.bytes(guid)
+    }
+
+    /// A blob; bytes past 65535 are cut off
+    pub fn blob(&mut self, bytes: &[u8]) -> &mut Self {
+        let bytes = &bytes[..bytes.len().min(u16::MAX as usize)];
+        self.u16(bytes.len() as u16).bytes(bytes)
+    }
+
+    /// A UTF-8 string; text past 65535 bytes is cut off at a character
+    pub fn utf8(&mut self, text: &str) -> &mut Self {
+        let text = truncate(text, u16::MAX as usize);
+        self.u16(text.len() as u16).bytes(text.as_bytes())
+    }
+
+    /// A counted UTF-16 string (see [`write_utf16`])
+    pub fn utf16(&mut self, text: &str) -> &mut Self {
+        write_utf16(&mut self.out, text);
+        self
+    }
+
+    /// A terminated UTF-16 string (see [`write_utf16z`])
+    pub fn utf16z(&mut self, text: &str) -> &mut Self {
+        write_utf16z(&mut self.out, text);
+        self
+    }
+
+    /// `text` in exactly `len` bytes, NUL-padded; longer text is cut off
+    /// at a character
+    pub fn fixed_str(&mut self, text: &str, len: usize) -> &mut Self {
+        let text = truncate(text, len);
+        self.bytes(text.as_bytes());
+        self.out.resize(self.out.len() + len - text.len(), 0);
+        self
+    }
+
+    /// A presence flag, then `value` written by `write` if there is one
+    pub fn option<T>(&mut self, value: Option<T>, write: impl FnOnce(&mut Self, T)) -> &mut Self {
+        self.bool(value.is_some());
+        if let Some(value) = value {
+            write(self, value);
+        }
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.out.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.out.is_empty()
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.out
+    }
+}
+
+impl From<WireWriter> for Vec<u8> {
+    fn from(writer: WireWriter) -> Self {
+        writer.out
+    }
+}
+
+/// Why a message couldn't be read
+///
+/// Each read names the field it's for, so the error says which one was
+/// missing or malformed and where, e.g. "character ID truncated: 4 bytes
+/// wanted at offset 2, 1 left".
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum WireError {
+    #[error("{field} truncated: {wanted} bytes wanted at offset {offset}, {left} left")]
+    Truncated {
+        field: &'static str,
+        offset: usize,
+        wanted: usize,
+        left: usize,
+    },
+    #[error("{field} at offset {offset} isn't valid {encoding}")]
+    InvalidText {
+        field: &'static str,
+        offset: usize,
+        encoding: &'static str,
+    },
+    #[error("{field} at offset {offset} has no terminator")]
+    Unterminated { field: &'static str, offset: usize },
+    #[error("not a {name}")]
+    WrongMessage {
+        name: &'static str,
+        /// The opcode found instead, if there was one
+        opcode: Option<u16>,
+    },
+    #[error("{left} bytes after the last field, at offset {offset}")]
+    TrailingBytes { offset: usize, left: usize },
+}
+
+pub type WireResult<T> = std::result::Result<T, WireError>;
+
+/// Reads a message field by field from a cursor
+///
+/// Every read checks the bytes are there first, so a short or malformed
+/// message is a [`WireError`] rather than a panic, and a failed read
+/// leaves the cursor where it was.
+#[derive(Debug, Clone)]
+pub struct WireReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> WireReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    /// Start reading a game message, checking its opcode is `opcode`;
+    /// `name` goes in the error otherwise, e.g. "mount request"
+    pub fn message(data: &'a [u8], opcode: u16, name: &'static str) -> WireResult<Self> {
+        let mut reader = Self::new(data);
+        match reader.u16("opcode") {
+            Ok(found) if found == opcode => Ok(reader),
+            found => Err(WireError::WrongMessage {
+                name,
+                opcode: found.ok(),
+            }),
+        }
+    }
+
+    /// The next `len` bytes
+    pub fn bytes(&mut self, field: &'static str, len: usize) -> WireResult<&'a [u8]> {
+        let rest = self.remaining();
+        let bytes = rest.get(..len).ok_or(WireError::Truncated {
+            field,
+            offset: self.pos,
+            wanted: len,
+            left: rest.len(),
+        })?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    pub fn array<const N: usize>(&mut self, field: &'static str) -> WireResult<[u8; N]> {
+        let bytes = self.bytes(field, N)?;
+        Ok(bytes.try_into().expect("bytes returns exactly N"))
+    }
+
+    pub fn u8(&mut self, field: &'static str) -> WireResult<u8> {
+        Ok(self.array::<1>(field)?[0])
+    }
+
+    pub fn u16(&mut self, field: &'static str) -> WireResult<u16> {
+        Ok(u16::from_le_bytes(self.array(field)?))
+    }
+
+    pub fn u32(&mut self, field: &'static str) -> WireResult<u32> {
+        Ok(u32::from_le_bytes(self.array(field)?))
+    }
+
+    pub fn u64(&mut self, field: &'static str) -> WireResult<u64> {
+        Ok(u64::from_le_bytes(self.array(field)?))
+    }
+
+    pub fn i32(&mut self, field: &'static str) -> WireResult<i32> {
+        Ok(i32::from_le_bytes(self.array(field)?))
+    }
+
+    pub fn i64(&mut self, field: &'static str) -> WireResult<i64> {
+        Ok(i64::from_le_bytes(self.array(field)?))
+    }
+
+    pub fn f32(&mut self, field: &'static str) -> WireResult<f32> {
+        Ok(f32::from_le_bytes(self.array(field)?))
+    }
+
+    pub fn bool(&mut self, field: &'static str) -> WireResult<bool> {
+        Ok(self.u8(field)? != 0)
+    }
+
+    pub fn guid(&mut self, field: &'static str) -> WireResult<Guid> {
+        self.array(field)
+    }
+
+    pub fn blob(&mut self, field: &'static str) -> WireResult<&'a [u8]> {
+        self.counted(field, 1)
+    }
+
+    pub fn utf8(&mut self, field: &'static str) -> WireResult<String> {
+        let start = self.pos;
+        let bytes = self.blob(field)?;
+        self.text(
+            field,
+            start,
+            "UTF-8",
+            String::from_utf8(bytes.to_vec()).ok(),
+        )
+    }
+
+    /// A counted UTF-16 string (see [`write_utf16`])
+    pub fn utf16(&mut self, field: &'static str) -> WireResult<String> {
+        let start = self.pos;
+        let units = self.counted(field, 2)?;
+        self.text(field, start, "UTF-16", decode(get_units(units)))
+    }
+
+    /// A terminated UTF-16 string (see [`write_utf16z`])
+    pub fn utf16z(&mut self, field: &'static str) -> WireResult<String> {
+        let start = self.pos;
+        let end = get_units(self.remaining())
+            .position(|unit| unit == 0)
+            .ok_or(WireError::Unterminated {
+                field,
+                offset: start,
+            })?;
+        let units = self.bytes(field, end * 2 + 2)?;
+        self.text(field, start, "UTF-16", decode(get_units(&units[..end * 2])))
+    }
+
+    /// A NUL-padded string of `len` bytes
+    pub fn fixed_str(&mut self, field: &'static str, len: usize) -> WireResult<String> {
+        let start = self.pos;
+        let bytes = self.bytes(field, len)?;
+        let end = bytes.iter().position(|b| *b == 0).unwrap_or(len);
+        let text = String::from_utf8(bytes[..end].to_vec()).ok();
+        self.text(field, start, "UTF-8", text)
+    }
+
+    /// A presence flag, then the value `read` reads if it's set
+    pub fn option<T>(
+        &mut self,
+        field: &'static str,
+        read: impl FnOnce(&mut Self) -> WireResult<T>,
+    ) -> WireResult<Option<T>> {
+        match self.bool(field)? {
+            true => Ok(Some(read(self)?)),
+            false => Ok(None),
+        }
+    }
+
+    /// Bytes not read yet
+    pub fn remaining(&self) -> &'a [u8] {
+        &self.data[self.pos..]
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.remaining().is_empty()
+    }
+
+    /// Offset of the next field
+    pub fn position(&self) -> usize {
+        self.pos
+    }
+
+    /// Fail if anything is left after the last field
+    pub fn finish(&self) -> WireResult<()> {
+        match self.remaining().len() {
+            0 => Ok(()),
+            left => Err(WireError::TrailingBytes {
+                offset: self.pos,
+                left,
+            }),
+        }
+    }
+
+    /// A u16 count of `unit`-byte units, then the units; nothing is
+    /// consumed if they aren't all there
+    fn counted(&mut self, field: &'static str, unit: usize) -> WireResult<&'a [u8]> {
+        let start = self.pos;
+        let count = usize::from(self.u16(field)?);
+        self.bytes(field, count * unit)
+            .inspect_err(|_| self.pos = start)
+    }
+
+    /// `text`, or an error for the field read from `start` on, which is
+    /// then unread
+    fn text(
+        &mut self,
+        field: &'static str,
+        start: usize,
+        encoding: &'static str,
+        text: Option<String>,
+    ) -> WireResult<String> {
+        text.ok_or_else(|| {
+            self.pos = start;
+            WireError::InvalidText {
+                field,
+                offset: start,
+                encoding,
+            }
+        })
+    }
+}
+
+/// The longest start of `text` that fits in `max` bytes
+fn truncate(text: &str, max: usize) -> &str {
+    if text.len() <= max {
+        return text;
+    }
+    let end = (0..=max)
+        .rev()
+        .find(|&i| text.is_char_boundary(i))
+        .unwrap_or(0);
+    &text[..end]
+}
+
+/// `text` as UTF-16 code units, cut to `max` without splitting a
+/// surrogate pair
+fn units(text: &str, max: usize) -> Vec<u16> {
+    let mut units = Vec::new();
+    for c in text.chars() {
+        let mut buf = [0; 2];
+        let encoded = c.encode_utf16(&mut buf);
+        if units.len() + encoded.len() > max {
+            break;
+        }
+        units.extend_from_slice(encoded);
+    }
+    units
+}
+
+fn put_units(out: &mut Vec<u8>, units: &[u16]) {
+    out.reserve(units.len() * 2);
+    for unit in units {
+        out.extend_from_slice(&unit.to_le_bytes());
+    }
+}
+
+fn get_units(data: &[u8]) -> impl Iterator<Item = u16> + '_ {
+    data.chunks_exact(2)
+        .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+}
+
+fn decode(units: impl Iterator<Item = u16>) -> Option<String> {
+    char::decode_utf16(units)
+        .collect::<std::result::Result<_, _>>()
+        .ok()
+}
+
+/// Append `text` as a counted UTF-16 string; text past
+/// [`MAX_UTF16_UNITS`] is cut off
+pub fn write_utf16(out: &mut Vec<u8>, text: &str) {
+    let units = units(text, MAX_UTF16_UNITS);
+    out.extend_from_slice(&(units.len() as u16).to_le_bytes());
+    put_units(out, &units);
+}
+
+/// Read a counted UTF-16 string from the start of `data`; returns it and
+/// how many bytes it took
+pub fn read_utf16(data: &[u8]) -> WireResult<(String, usize)> {
+    let mut r = WireReader::new(data);
+    Ok((r.utf16("string")?, r.position()))
+}
+
+/// Append `text` as a terminated UTF-16 string; it ends early at a NUL in
+/// it
+pub fn write_utf16z(out: &mut Vec<u8>, text: &str) {
+    let text = text.split('\0').next().unwrap_or_default();
+    put_units(out, &units(text, usize::MAX));
+    out.extend_from_slice(&[0, 0]);
+}
+
+/// Read a terminated UTF-16 string from the start of `data`; returns it
+/// and how many bytes it took
+pub fn read_utf16z(data: &[u8]) -> WireResult<(String, usize)> {
+    let mut r = WireReader::new(data);
+    Ok((r.utf16z("string")?, r.position()))
+}
+
+/// Bytes [`write_utf16`] takes for `text`
+pub fn utf16_len(text: &str) -> usize {
+    2 + text.encode_utf16().count().min(MAX_UTF16_UNITS) * 2
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn every_field() -> Vec<u8> {
+        let mut out = WireWriter::message(0x3F00);
+        out.u8(1)
+            .u16(2)
+            .u32(3)
+            .u64(4)
+            .i32(-5)
+            .i64(-6)
+            .f32(7.5)
+            .bool(true)
+            .guid(&[0xAB; 16])
+            .blob(&[1, 2, 3])
+            .utf8("Poring")
+            .utf16("라그")
+            .utf16z("Lunatic")
+            .fixed_str("Prontera", 12)
+            .option(Some(9u32), |out, v| {
+                out.u32(v);
+            })
+            .option(None::<u32>, |out, v| {
+                out.u32(v);
+            });
+        out.into_bytes()
+    }
+
+    /// Read what [`every_field`] wrote
+    fn read_every_field(message: &[u8]) -> WireResult<()> {
+        let mut r = WireReader::message(message, 0x3F00, "test message")?;
+        assert_eq!(r.u8("u8")?, 1);
+        assert_eq!(r.u16("u16")?, 2);
+        assert_eq!(r.u32("u32")?, 3);
+        assert_eq!(r.u64("u64")?, 4);
+        assert_eq!(r.i32("i32")?, -5);
+        assert_eq!(r.i64("i64")?, -6);
+        assert_eq!(r.f32("f32")?, 7.5);
+        assert!(r.bool("bool")?);
+        assert_eq!(r.guid("guid")?, [0xAB; 16]);
+        assert_eq!(r.blob("blob")?, [1, 2, 3]);
+        assert_eq!(r.utf8("utf8")?, "Poring");
+        assert_eq!(r.utf16("utf16")?, "라그");
+        assert_eq!(r.utf16z("utf16z")?, "Lunatic");
+        let at = r.position();
+        assert_eq!(r.fixed_str("fixed", 12)?, "Prontera");
+        assert_eq!(r.position(), at + 12);
+        assert_eq!(r.option("some", |r| r.u32("value"))?, Some(9));
+        assert_eq!(r.option("none", |r| r.u32("value"))?, None);
+        r.finish()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let message = every_field();
+        assert_eq!(
+            WireReader::message(&message, 0x3F01, "delta").unwrap_err(),
+            WireError::WrongMessage {
+                name: "delta",
+                opcode: Some(0x3F00)
+            }
+        );
+        read_every_field(&message).unwrap();
+    }
+
+    #[test]
+    fn test_errors_name_the_field() {
+        let mut r = WireReader::new(&[1, 2, 3]);
+        let error = r.u32("character ID").unwrap_err();
+        assert_eq!(
+            error,
+            WireError::Truncated {
+                field: "character ID",
+                offset: 0,
+                wanted: 4,
+                left: 3
+            }
+        );
+        assert_eq!(
+            error.to_string(),
+            "character ID truncated: 4 bytes wanted at offset 0, 3 left"
+        );
+        // Nothing was consumed
+        assert_eq!(r.u16("slot").unwrap(), 0x0201);
+        assert_eq!(
+            r.finish().unwrap_err(),
+            WireError::TrailingBytes { offset: 2, left: 1 }
+        );
+
+        let mut r = WireReader::new(&[5, 0, b'a']);
+        assert!(matches!(
+            r.utf8("name"),
+            Err(WireError::Truncated { field: "name", .. })
+        ));
+        assert_eq!(r.position(), 0);
+        let mut r = WireReader::new(&[1, 0, 0xFF]);
+        assert!(matches!(
+            r.utf8("name"),
+            Err(WireError::InvalidText {
+                field: "name",
+                offset: 0,
+                ..
+            })
+        ));
+        assert_eq!(r.position(), 0);
+        assert!(matches!(
+            WireReader::new(&[b'a', 0]).utf16z("text"),
+            Err(WireError::Unterminated { field: "text", .. })
+        ));
+        assert!(
+            WireReader::new(&[])
+                .option("mount", |r| r.u8("id"))
+                .is_err()
+        );
+        assert_eq!(
+            WireReader::message(&[0x00], 0x3F00, "delta").unwrap_err(),
+            WireError::WrongMessage {
+                name: "delta",
+                opcode: None
+            }
+        );
+    }
+
+    #[test]
+    fn test_strings_are_cut_off_whole() {
+        let mut out = WireWriter::new();
+        out.fixed_str("라그나로크", 7);
+        assert_eq!(out.len(), 7);
+        let message = out.into_bytes();
+        assert_eq!(
+            WireReader::new(&message).fixed_str("name", 7).unwrap(),
+            "라그"
+        );
+
+        let mut out = WireWriter::new();
+        out.utf8(&"é".repeat(40_000));
+        let message = out.into_bytes();
+        assert_eq!(message.len(), 2 + 65534);
+        let text = WireReader::new(&message).utf8("text").unwrap();
+        assert_eq!(text.chars().count(), 32767);
+    }
+
+    #[test]
+    fn test_counted_utf16() {
+        let mut out = Vec::new();
+        write_utf16(&mut out, "Hi 라그");
+        assert_eq!(
+            out,
+            [5, 0, b'H', 0, b'i', 0, b' ', 0, 0x7C, 0xB7, 0xF8, 0xAD]
+        );
+        assert_eq!(utf16_len("Hi 라그"), out.len());
+
+        // The next field follows straight after
+        out.push(0xFF);
+        assert_eq!(read_utf16(&out).unwrap(), ("Hi 라그".to_string(), 12));
+
+        assert!(read_utf16(&[1]).is_err());
+        assert!(read_utf16(&[2, 0, b'a', 0]).is_err());
+        // An unpaired surrogate
+        assert!(read_utf16(&[1, 0, 0x00, 0xD8]).is_err());
+
+        // 😀 is a surrogate pair, and mustn't be split at the limit
+        let text = format!("{}😀", "a".repeat(MAX_UTF16_UNITS - 1));
+        let mut out = Vec::new();
+        write_utf16(&mut out, &text);
+        let (read, len) = read_utf16(&out).unwrap();
+        assert_eq!(read, "a".repeat(MAX_UTF16_UNITS - 1));
+        assert_eq!(len, out.len());
+    }
+
+    #[test]
+    fn test_terminated_utf16() {
+        let mut out = Vec::new();
+        write_utf16z(&mut out, "Poring");
+        write_utf16z(&mut out, "a\0b");
+        assert_eq!(out.len(), 14 + 4);
+        let (first, len) = read_utf16z(&out).unwrap();
+        assert_eq!((first.as_str(), len), ("Poring", 14));
+        assert_eq!(read_utf16z(&out[len..]).unwrap(), ("a".to_string(), 4));
+
+        assert!(read_utf16z(&[b'a', 0]).is_err());
+        assert_eq!(read_utf16z(&[0, 0]).unwrap(), (String::new(), 2));
+    }
+
+    proptest! {
+        #[test]
+        fn prop_truncated_messages_fail(cut in 0..every_field().len()) {
+            let message = every_field();
+            prop_assert!(read_every_field(&message[..cut]).is_err());
+        }
+
+        #[test]
+        fn prop_garbage_never_panics(data in prop::collection::vec(any::<u8>(), 0..64)) {
+            let mut r = WireReader::new(&data);
+            let _ = r.utf16("utf16");
+            let _ = r.utf16z("utf16z");
+            let _ = r.utf8("utf8");
+            let _ = r.option("option", |r| r.blob("blob"));
+            let _ = r.fixed_str("fixed", 5);
+            let _ = r.u64("u64");
+            prop_assert!(r.position() <= data.len());
+        }
+    }
+}
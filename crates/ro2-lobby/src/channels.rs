@@ -0,0 +1,295 @@
+//! The channel list, with how busy each channel is
+//!
+//! Every world server is a channel and writes its player count to
+//! `channel_status` every few seconds (see `ro2_world::channel`). The
+//! lobby reads those reports into a [`ChannelList`]: each channel comes
+//! with a [`Load`], and the least busy channel a player can still join is
+//! recommended so players spread out rather than all picking the first
+//! one. A channel whose last report is older than `stale_secs` is shown as
+//! offline.
+//!
+//! The soft caps are set in the `[channels]` section of
+//! `config/lobby.toml`; 0 turns one off:
+//!
+//! ```toml
+//! [channels]
+//! crowded_at = 300    # Players from which a channel shows as crowded
+//! full_at = 500       # ...and as full, never recommended
+//! stale_secs = 30
+//! ```
+//!
+//! Full only steers players away: nothing here turns them away from a
+//! full channel.
+//!
+//! The opcodes below are placeholders like those in `MessageType`.
+//! [`handle_req_channel_list`] answers [`REQ_CHANNEL_LIST`] with the list,
+//! but the lobby doesn't route client messages yet; for now only
+//! `ro2-lobby admin channels` shows it.
+
+use anyhow::{Result, anyhow};
+use ro2_common::config::{self, Validate};
+use ro2_common::database::ChannelStatus;
+use ro2_common::database::queries::ChannelQueries;
+use ro2_common::wire::{WireReader, WireWriter};
+use serde::Deserialize;
+use sqlx::{Pool, Sqlite};
+use std::path::Path;
+
+pub use ro2_common::protocol::opcodes::{ACK_CHANNEL_LIST_IN_GAME, REQ_CHANNEL_LIST};
+
+/// Soft caps and when a channel counts as offline
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct ChannelListConfig {
+    /// Players from which a channel is crowded; 0 for never
+    pub crowded_at: i64,
+    /// Players from which a channel is full; 0 for never
+    pub full_at: i64,
+    /// Seconds without a report before a channel is offline
+    pub stale_secs: i64,
+}
+
+impl Default for ChannelListConfig {
+    fn default() -> Self {
+        Self {
+            crowded_at: 0,
+            full_at: 0,
+            stale_secs: 30,
+        }
+    }
+}
+
+impl ChannelListConfig {
+    /// Read the `[channels]` section of `path`; missing keys keep their
+    /// defaults
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        config::section(path, "channels")
+    }
+
+    /// Parse the `[channels]` section of TOML text
+    pub fn from_toml(text: &str) -> Result<Self> {
+        config::section_from_toml(text, "channels")
+    }
+
+    /// How busy a channel that reported `status` is at `now`
+    pub fn load_of(&self, status: &ChannelStatus, now: i64) -> Load {
+        let at = |cap: i64| cap > 0 && status.players >= cap;
+        if now - status.reported_at > self.stale_secs {
+            Load::Offline
+        } else if at(self.full_at) {
+            Load::Full
+        } else if at(self.crowded_at) {
+            Load::Crowded
+        } else {
+            Load::Normal
+        }
+    }
+}
+
+impl Validate for ChannelListConfig {
+    fn validate(&self) -> Result<()> {
+        if self.crowded_at < 0 || self.full_at < 0 || self.stale_secs <= 0 {
+            return Err(anyhow!(
+                "channel caps can't be negative and stale_secs must be positive"
+            ));
+        }
+        if self.crowded_at > 0 && self.full_at > 0 && self.crowded_at > self.full_at {
+            return Err(anyhow!("channel crowded_at can't be above full_at"));
+        }
+        Ok(())
+    }
+}
+
+/// How busy a channel is, sent as a u8
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u8)]
+pub enum Load {
+    Normal = 0,
+    Crowded = 1,
+    /// At the soft cap; never recommended
+    Full = 2,
+    /// Hasn't reported lately
+    Offline = 3,
+}
+
+/// One channel as the player sees it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Channel {
+    pub id: u32,
+    pub name: String,
+    pub players: i64,
+    pub load: Load,
+}
+
+/// Every channel, and the one to suggest
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChannelList {
+    pub channels: Vec<Channel>,
+    /// The fewest players of those neither full nor offline, the lowest ID
+    /// on a tie; none if every channel is
+    pub recommended: Option<u32>,
+}
+
+impl ChannelList {
+    /// The list from the channels' reports, as of `now`
+    pub fn new(config: &ChannelListConfig, statuses: &[ChannelStatus], now: i64) -> Self {
+        let channels: Vec<Channel> = statuses
+            .iter()
+            .map(|status| Channel {
+                id: status.id as u32,
+                name: status.name.clone(),
+                players: status.players,
+                load: config.load_of(status, now),
+            })
+            .collect();
+        let recommended = channels
+            .iter()
+            .filter(|channel| channel.load <= Load::Crowded)
+            .min_by_key(|channel| (channel.players, channel.id))
+            .map(|channel| channel.id);
+        Self {
+            channels,
+            recommended,
+        }
+    }
+}
+
+/// The channel list from the database, as of `now`
+pub async fn channel_list(
+    pool: &Pool<Sqlite>,
+    config: &ChannelListConfig,
+    now: i64,
+) -> Result<ChannelList> {
+    let statuses = ChannelQueries::list(pool).await?;
+    Ok(ChannelList::new(config, &statuses, now))
+}
+
+/// Parse a channel list request, which has nothing after the opcode
+pub fn parse_req_channel_list(message: &[u8]) -> Result<()> {
+    let message = WireReader::message(message, REQ_CHANNEL_LIST, "channel list request")?;
+    message.finish()?;
+    Ok(())
+}
+
+/// Build [`ACK_CHANNEL_LIST_IN_GAME`]: u32 recommended channel ID (0 for
+/// none), u8 channel count, then each channel's u32 ID, name as a u16
+/// length and UTF-16 text, and u8 [`Load`]
+pub fn build_ack_channel_list(list: &ChannelList) -> Vec<u8> {
+    let mut out = WireWriter::message(ACK_CHANNEL_LIST_IN_GAME);
+    out.u32(list.recommended.unwrap_or(0));
+    let channels = &list.channels[..list.channels.len().min(usize::from(u8::MAX))];
+    out.u8(channels.len() as u8);
+    for channel in channels {
+        out.u32(channel.id)
+            .utf16(&channel.name)
+            .u8(channel.load as u8);
+    }
+    out.into_bytes()
+}
+
+/// Answer [`REQ_CHANNEL_LIST`]
+pub async fn handle_req_channel_list(
+    pool: &Pool<Sqlite>,
+    config: &ChannelListConfig,
+    message: &[u8],
+    now: i64,
+) -> Result<Vec<u8>> {
+    parse_req_channel_list(message)?;
+    let list = channel_list(pool, config, now).await?;
+    Ok(build_ack_channel_list(&list))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ro2_common::testing;
+
+    fn status(id: i64, players: i64, reported_at: i64) -> ChannelStatus {
+        ChannelStatus {
+            id,
+            name: format!("Channel {}", id),
+            address: format!("127.0.0.1:74{:02}", id),
+            players,
+            reported_at,
+        }
+    }
+
+    fn caps() -> ChannelListConfig {
+        ChannelListConfig {
+            crowded_at: 100,
+            full_at: 200,
+            stale_secs: 30,
+        }
+    }
+
+    #[test]
+    fn test_config() {
+        assert_eq!(
+            ChannelListConfig::load("does/not/exist.toml").unwrap(),
+            ChannelListConfig::default()
+        );
+        let config = ChannelListConfig::from_toml("[channels]\ncrowded_at = 100").unwrap();
+        assert_eq!((config.crowded_at, config.full_at), (100, 0));
+        assert!(
+            ChannelListConfig::from_toml("[channels]\ncrowded_at = 300\nfull_at = 200").is_err()
+        );
+        assert!(ChannelListConfig::from_toml("[channels]\nstale_secs = 0").is_err());
+    }
+
+    #[test]
+    fn test_load() {
+        let config = caps();
+        assert_eq!(config.load_of(&status(1, 99, 100), 130), Load::Normal);
+        assert_eq!(config.load_of(&status(1, 100, 100), 100), Load::Crowded);
+        assert_eq!(config.load_of(&status(1, 250, 100), 100), Load::Full);
+        assert_eq!(config.load_of(&status(1, 0, 100), 131), Load::Offline);
+
+        // Without caps a channel is never crowded or full
+        let uncapped = ChannelListConfig::default();
+        assert_eq!(uncapped.load_of(&status(1, 10_000, 100), 100), Load::Normal);
+    }
+
+    #[test]
+    fn test_recommended() {
+        let statuses = [
+            status(1, 150, 100),
+            status(2, 250, 100),
+            status(3, 10, 0),
+            status(4, 120, 100),
+            status(5, 120, 100),
+        ];
+        let list = ChannelList::new(&caps(), &statuses, 100);
+        // 3 is offline and 2 full; of the crowded rest 4 ties 5 on players
+        assert_eq!(list.recommended, Some(4));
+        assert_eq!(list.channels[2].load, Load::Offline);
+
+        let list = ChannelList::new(&caps(), &statuses[1..3], 100);
+        assert_eq!(list.recommended, None);
+    }
+
+    #[tokio::test]
+    async fn test_handle_req_channel_list() {
+        let pool = testing::database().await;
+        for status in [status(1, 150, 100), status(2, 20, 100)] {
+            ChannelQueries::report(&pool, &status).await.unwrap();
+        }
+
+        assert!(parse_req_channel_list(&[0xF0]).is_err());
+        assert!(parse_req_channel_list(&[0xF0, 0x3F, 0]).is_err());
+        let request = REQ_CHANNEL_LIST.to_le_bytes();
+        let answer = handle_req_channel_list(&pool, &caps(), &request, 110)
+            .await
+            .unwrap();
+
+        let mut reader =
+            WireReader::message(&answer, ACK_CHANNEL_LIST_IN_GAME, "channel list").unwrap();
+        assert_eq!(reader.u32("recommended").unwrap(), 2);
+        assert_eq!(reader.u8("count").unwrap(), 2);
+        for (id, load) in [(1, Load::Crowded), (2, Load::Normal)] {
+            assert_eq!(reader.u32("id").unwrap(), id);
+            assert_eq!(reader.utf16("name").unwrap(), format!("Channel {}", id));
+            assert_eq!(reader.u8("load").unwrap(), load as u8);
+        }
+        reader.finish().unwrap();
+    }
+}
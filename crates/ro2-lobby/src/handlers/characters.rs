@@ -0,0 +1,163 @@
+//! Character list sent in AnsLoginChannel
+//!
+//! The client's character select screen needs each character's slot,
+//! appearance, job progression and attributes. The layout below is
+//! tentative until a capture of the character list is decoded; every field
+//! the screen shows is included so only the order should need fixing.
+//! The slot counts tell the client which slots to show locked (see
+//! [`crate::slots`]).
+//!
+//! ```text
+//! u16  opcode (AnsLoginChannel)
+//! u8   unlocked slots
+//! u8   total slots, locked ones included
+//! u8   character count
+//! per character:
+//!   u8   slot
+//!   u32  character id
+//!   u16  name length, then UTF-8 name
+//!   u16  job class
+//!   u16  base level
+//!   u16  job level
+//!   u64  base experience
+//!   u64  job experience
+//!   u8   gender (0 = male, 1 = female)
+//!   u16  hair style
+//!   u16  hair color
+//!   u16  face
+//!   u32  map id
+//!   f32  x, y, z
+//!   u32  hp, max hp, mp, max mp
+//!   u16  str, dex, int, vit, luk
+//! ```
+
+use crate::slots::AccountSlots;
+use anyhow::Result;
+use ro2_common::database::queries::CharacterQueries;
+use ro2_common::database::{Character, CharacterStats};
+use ro2_common::protocol::MessageType;
+use ro2_common::wire::WireWriter;
+use sqlx::{Pool, Sqlite};
+
+/// Attributes of a character without a `character_stats` row
+const DEFAULT_STAT: i32 = 1;
+
+/// One character in the list
+#[derive(Debug, Clone, PartialEq)]
+pub struct CharacterListEntry {
+    pub character: Character,
+    pub stats: Option<CharacterStats>,
+}
+
+impl CharacterListEntry {
+    fn encode(&self, out: &mut WireWriter) {
+        let c = &self.character;
+        out.u8(c.slot_index as u8).u32(c.id as u32).utf8(&c.name);
+        for value in [c.job_class, c.level, c.job_level] {
+            out.u16(value as u16);
+        }
+        for value in [c.experience, c.job_experience] {
+            out.u64(value as u64);
+        }
+        out.u8(c.gender as u8);
+        for value in [c.hair_style, c.hair_color, c.face] {
+            out.u16(value as u16);
+        }
+        out.u32(c.map_id as u32);
+        for value in [c.x, c.y, c.z] {
+            out.f32(value);
+        }
+        for value in [c.hp, c.max_hp, c.mp, c.max_mp] {
+            out.u32(value as u32);
+        }
+
+        let stats = self.stats.map_or([DEFAULT_STAT; 5], |s| {
+            [s.strength, s.dexterity, s.intelligence, s.vitality, s.luck]
+        });
+        for value in stats {
+            out.u16(value as u16);
+        }
+    }
+}
+
+/// Load an account's character list, in slot order
+pub async fn load_character_list(
+    pool: &Pool<Sqlite>,
+    account_id: i64,
+) -> Result<Vec<CharacterListEntry>> {
+    let mut entries = Vec::new();
+    for character in CharacterQueries::list_for_account(pool, account_id).await? {
+        let stats = CharacterQueries::stats(pool, character.id).await?;
+        entries.push(CharacterListEntry { character, stats });
+    }
+    Ok(entries)
+}
+
+/// Build AnsLoginChannel with the account's slots and character list
+pub fn build_ans_login_channel(slots: AccountSlots, entries: &[CharacterListEntry]) -> Vec<u8> {
+    let mut out = WireWriter::message(MessageType::AnsLoginChannel.to_id());
+    out.u8(slots.unlocked as u8)
+        .u8(slots.total as u8)
+        .u8(entries.len() as u8);
+    for entry in entries {
+        entry.encode(&mut out);
+    }
+    out.into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ro2_common::testing;
+
+    #[tokio::test]
+    async fn test_character_list() {
+        let pool = testing::database().await;
+        for (id, slot, name, deleted_at) in [
+            (1, 1, "Second", None),
+            (2, 0, "First", None),
+            (3, 2, "Gone", Some(1)),
+        ] {
+            sqlx::query(
+                "INSERT INTO characters (id, account_id, slot_index, name, class_id, gender, hair_style, hair_color, face, map_id, position_x, position_y, position_z, hp, max_hp, mp, max_mp, created_at, deleted_at)
+                 VALUES (?, 2, ?, ?, 1, 1, 3, 4, 5, 10, 1.0, 2.0, 3.0, 100, 100, 50, 50, 0, ?)",
+            )
+            .bind(id)
+            .bind(slot)
+            .bind(name)
+            .bind(deleted_at)
+            .execute(&pool)
+            .await
+            .unwrap();
+        }
+        sqlx::query("INSERT INTO character_stats (character_id, strength, dexterity, intelligence, vitality, luck) VALUES (2, 9, 8, 7, 6, 5)")
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let entries = load_character_list(&pool, 2).await.unwrap();
+        let names: Vec<_> = entries.iter().map(|e| e.character.name.as_str()).collect();
+        assert_eq!(names, ["First", "Second"]);
+        assert_eq!(entries[0].character.hair_style, 3);
+        assert_eq!(entries[0].stats.unwrap().strength, 9);
+        assert_eq!(entries[1].stats, None);
+
+        let slots = AccountSlots {
+            unlocked: 3,
+            total: 6,
+        };
+        let response = build_ans_login_channel(slots, &entries);
+        assert_eq!(&response[..5], &[0x04, 0x00, 3, 6, 2]);
+        // slot, id, then the name
+        assert_eq!(response[5], 0);
+        assert_eq!(&response[6..10], &2u32.to_le_bytes());
+        assert_eq!(&response[10..17], b"\x05\x00First");
+
+        // Fixed-size fields after the name: 2+2+2 + 8+8 + 1 + 2+2+2 + 4 +
+        // 12 + 16 + 10
+        let entry_len = 1 + 4 + 2 + 5 + 71;
+        let strength = 5 + entry_len - 10;
+        assert_eq!(&response[strength..strength + 2], &9u16.to_le_bytes());
+        assert_eq!(response.len(), 5 + entry_len + entry_len + 1);
+    }
+}
@@ -1,4 +1,9 @@
 //! Lobby message handlers
+//!
+//! Not registered with the lobby connection yet, which still echoes what it
+//! receives.
+
+pub mod characters;
 
 use anyhow::Result;
 
@@ -7,21 +12,13 @@ pub async fn handle_req_login_channel(_data: &[u8]) -> Result<Vec<u8>> {
     // TODO: Implement lobby login handler
     // 1. Parse session key from data
     // 2. Validate session key against database
-    // 3. Query character list for account
-    // 4. Return AnsLoginChannel with character list
+    // 3. Query character list and slots for account (characters::load_character_list,
+    //    slots::account_slots)
+    // 4. Return AnsLoginChannel with character list (characters::build_ans_login_channel)
 
     unimplemented!("ReqLoginChannel handler not yet implemented")
 }
 
-/// Handle ReqChannelList message
-pub async fn handle_req_channel_list(_data: &[u8]) -> Result<Vec<u8>> {
-    // TODO: Implement channel list handler
-    // 1. Query available game channels
-    // 2. Return AckChannelListInGame with channel info
-
-    unimplemented!("ReqChannelList handler not yet implemented")
-}
-
 /// Handle ReqChannelMove message
 pub async fn handle_req_channel_move(_data: &[u8]) -> Result<Vec<u8>> {
     // TODO: Implement channel move handler
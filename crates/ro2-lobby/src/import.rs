@@ -0,0 +1,652 @@
+//! Importing accounts and characters from another emulator
+//!
+//! Servers moving to Ragnoria bring their players along: load the old
+//! emulator's database dump into SQLite (e.g. with `mysql2sqlite`), then
+//! write a mapping file with a query per table that renames its columns to
+//! ours:
+//!
+//! ```toml
+//! [accounts]
+//! query = "SELECT account_id AS id, userid AS username, user_pass AS password_hash, email FROM login"
+//!
+//! [characters]
+//! query = """
+//!     SELECT char_id AS id, account_id, name, class AS class_id, base_level AS level,
+//!            hp, max_hp, sp AS mp, max_sp AS max_mp, zeny AS gold, last_map AS map_id
+//!     FROM char"""
+//!
+//! [inventory]   # Optional
+//! query = "SELECT char_id AS character_id, nameid AS item_id, amount AS quantity, idx AS slot_index, equip AS is_equipped FROM inventory"
+//! ```
+//!
+//! | Table | Required columns | Optional columns |
+//! |---|---|---|
+//! | accounts | `id`, `username`, `password_hash` | `email`, `created_at`, `last_login`, `is_banned`, `ban_reason` |
+//! | characters | `id`, `account_id`, `name`, `class_id`, `map_id`, `hp`, `mp` | `slot_index`, `level`, `experience`, `job_level`, `job_experience`, `gender`, `hair_style`, `hair_color`, `face`, `position_x`, `position_y`, `position_z`, `max_hp`, `max_mp`, `gold`, `created_at`, `last_played` |
+//! | inventory | `character_id`, `item_id`, `slot_index` | `quantity`, `is_equipped` |
+//!
+//! `ro2-lobby admin import <dump.db> <mapping.toml>` checks everything and
+//! reports what would be imported and what wouldn't and why, without
+//! writing anything; with `apply` on the end it imports the rows that
+//! passed, in one transaction. Rows are turned away for names this server
+//! doesn't allow or already has, values out of range, or an owner that
+//! wasn't imported.
+//!
+//! bcrypt password hashes are copied as they are. Anything else (rAthena's
+//! `user_pass` is often the password itself, or its MD5) isn't stored: the
+//! account is imported with no password, like an anonymized one, and can't
+//! log in until it's reset; the report lists them. If the source keeps
+//! plaintext passwords, ending the command with `--hash-plaintext` imports
+//! them bcrypt-hashed instead.
+
+use crate::services::valid_name;
+use anyhow::{Context, Result, anyhow};
+use ro2_common::config::{self, Validate};
+use ro2_common::database::queries::{AccountQueries, CharacterQueries, InventoryQueries};
+use ro2_common::database::{Account, Character};
+use serde::Deserialize;
+use sqlx::sqlite::SqliteRow;
+use sqlx::{Decode, Pool, Row, Sqlite, Type};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::path::Path;
+use tracing::info;
+
+/// The source query of one table
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct TableMapping {
+    pub query: String,
+}
+
+/// Where each table's rows come from in the source database
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Mapping {
+    pub accounts: TableMapping,
+    pub characters: TableMapping,
+    #[serde(default)]
+    pub inventory: Option<TableMapping>,
+}
+
+impl Mapping {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        // Unlike the server configs, an import needs its mapping
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("loading the import mapping from {}", path.display()))?;
+        Self::from_toml(&text)
+    }
+
+    pub fn from_toml(text: &str) -> Result<Self> {
+        config::from_toml(text)
+    }
+}
+
+impl Validate for Mapping {}
+
+/// An item as the source has it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SourceItem {
+    pub character_id: i64,
+    pub item_id: i32,
+    pub slot_index: i32,
+    pub quantity: i32,
+    pub equipped: bool,
+}
+
+/// A row left out, and why
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Problem {
+    /// E.g. `character 12 (Alice)`
+    pub row: String,
+    pub reason: String,
+}
+
+/// What an import does or would do
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Report {
+    pub accounts: usize,
+    pub characters: usize,
+    pub items: usize,
+    /// Rows left out
+    pub skipped: Vec<Problem>,
+    /// Rows imported that need attention
+    pub warnings: Vec<Problem>,
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} accounts, {} characters, {} items; {} rows skipped, {} warnings",
+            self.accounts,
+            self.characters,
+            self.items,
+            self.skipped.len(),
+            self.warnings.len()
+        )?;
+        for problem in &self.skipped {
+            writeln!(f, "skipped {}: {}", problem.row, problem.reason)?;
+        }
+        for problem in &self.warnings {
+            writeln!(f, "warning {}: {}", problem.row, problem.reason)?;
+        }
+        Ok(())
+    }
+}
+
+/// What to do with source passwords that aren't bcrypt hashes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Passwords {
+    /// Import the account without a password, to be reset
+    #[default]
+    Reset,
+    /// The source stores plaintext; bcrypt it with this cost
+    HashPlaintext { cost: u32 },
+}
+
+/// The rows that passed, still under their source IDs
+#[derive(Debug, Default)]
+struct Plan {
+    accounts: Vec<Account>,
+    characters: Vec<Character>,
+    items: Vec<SourceItem>,
+    report: Report,
+}
+
+/// Check the source rows `mapping` selects against `target`, and import
+/// the ones that pass if `apply`; rows without a creation time were
+/// created `now`
+pub async fn import(
+    source: &Pool<Sqlite>,
+    target: &Pool<Sqlite>,
+    mapping: &Mapping,
+    now: i64,
+    passwords: Passwords,
+    apply: bool,
+) -> Result<Report> {
+    let accounts = read(source, &mapping.accounts.query, "accounts", |row| {
+        account(row, now)
+    })
+    .await?;
+    let characters = read(source, &mapping.characters.query, "characters", |row| {
+        character(row, now)
+    })
+    .await?;
+    let items = match &mapping.inventory {
+        Some(inventory) => read(source, &inventory.query, "inventory", item).await?,
+        None => Vec::new(),
+    };
+
+    let plan = check(target, accounts, characters, items, passwords).await?;
+    if apply {
+        write(target, &plan).await?;
+        info!(
+            "Imported {} accounts, {} characters and {} items",
+            plan.report.accounts, plan.report.characters, plan.report.items
+        );
+    }
+    Ok(plan.report)
+}
+
+async fn read<T>(
+    source: &Pool<Sqlite>,
+    query: &str,
+    table: &str,
+    decode: impl Fn(&SqliteRow) -> Result<T>,
+) -> Result<Vec<T>> {
+    sqlx::query(query)
+        .fetch_all(source)
+        .await
+        .with_context(|| format!("running the {} query", table))?
+        .iter()
+        .map(decode)
+        .collect::<Result<_>>()
+        .with_context(|| format!("reading {}", table))
+}
+
+/// `column`, or `None` if the query doesn't select it
+fn optional<'r, T: Decode<'r, Sqlite> + Type<Sqlite>>(
+    row: &'r SqliteRow,
+    column: &str,
+) -> Result<Option<T>> {
+    match row.try_get::<Option<T>, _>(column) {
+        Ok(value) => Ok(value),
+        Err(sqlx::Error::ColumnNotFound(_)) => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn account(row: &SqliteRow, now: i64) -> Result<Account> {
+    Ok(Account {
+        id: row.try_get("id")?,
+        username: row.try_get("username")?,
+        password_hash: row.try_get("password_hash")?,
+        email: optional(row, "email")?,
+        created_at: optional(row, "created_at")?.unwrap_or(now),
+        last_login: optional(row, "last_login")?,
+        is_banned: optional(row, "is_banned")?.unwrap_or(false),
+        ban_reason: optional(row, "ban_reason")?,
+        deactivated_at: None,
+    })
+}
+
+fn character(row: &SqliteRow, now: i64) -> Result<Character> {
+    let hp = row.try_get("hp")?;
+    let mp = row.try_get("mp")?;
+    Ok(Character {
+        id: row.try_get("id")?,
+        account_id: row.try_get("account_id")?,
+        slot_index: optional(row, "slot_index")?.unwrap_or(-1),
+        name: row.try_get("name")?,
+        job_class: row.try_get("class_id")?,
+        level: optional(row, "level")?.unwrap_or(1),
+        experience: optional(row, "experience")?.unwrap_or(0),
+        job_level: optional(row, "job_level")?.unwrap_or(1),
+        job_experience: optional(row, "job_experience")?.unwrap_or(0),
+        gender: optional(row, "gender")?.unwrap_or(0),
+        hair_style: optional(row, "hair_style")?.unwrap_or(0),
+        hair_color: optional(row, "hair_color")?.unwrap_or(0),
+        face: optional(row, "face")?.unwrap_or(0),
+        map_id: row.try_get("map_id")?,
+        x: optional(row, "position_x")?.unwrap_or(0.0),
+        y: optional(row, "position_y")?.unwrap_or(0.0),
+        z: optional(row, "position_z")?.unwrap_or(0.0),
+        hp,
+        max_hp: optional(row, "max_hp")?.unwrap_or(hp),
+        mp,
+        max_mp: optional(row, "max_mp")?.unwrap_or(mp),
+        gold: optional(row, "gold")?.unwrap_or(0),
+        created_at: optional(row, "created_at")?.unwrap_or(now),
+        last_played: optional(row, "last_played")?,
+        deleted_at: None,
+    })
+}
+
+fn item(row: &SqliteRow) -> Result<SourceItem> {
+    Ok(SourceItem {
+        character_id: row.try_get("character_id")?,
+        item_id: row.try_get("item_id")?,
+        slot_index: row.try_get("slot_index")?,
+        quantity: optional(row, "quantity")?.unwrap_or(1),
+        equipped: optional(row, "is_equipped")?.unwrap_or(false),
+    })
+}
+
+fn is_bcrypt(hash: &str) -> bool {
+    ["$2a$", "$2b$", "$2y$"]
+        .iter()
+        .any(|prefix| hash.starts_with(prefix))
+}
+
+/// Sort the rows into those that can be imported and those that can't
+async fn check(
+    target: &Pool<Sqlite>,
+    accounts: Vec<Account>,
+    characters: Vec<Character>,
+    items: Vec<SourceItem>,
+    passwords: Passwords,
+) -> Result<Plan> {
+    let usernames: HashSet<String> =
+        sqlx::query_scalar::<_, String>("SELECT username FROM accounts")
+            .fetch_all(target)
+            .await?
+            .into_iter()
+            .map(|name| name.to_lowercase())
+            .collect();
+    let names: HashSet<String> = sqlx::query_scalar::<_, String>("SELECT name FROM characters")
+        .fetch_all(target)
+        .await?
+        .into_iter()
+        .map(|name| name.to_lowercase())
+        .collect();
+
+    let mut plan = Plan::default();
+    let skip = |plan: &mut Plan, row: String, reason: &str| {
+        plan.report.skipped.push(Problem {
+            row,
+            reason: reason.to_string(),
+        })
+    };
+
+    // IDs seen, so a repeated one is skipped even if its first row was;
+    // and those imported, for the rows they own
+    let (mut seen, mut account_ids) = (HashSet::new(), HashSet::new());
+    let mut taken_usernames = usernames;
+    for mut account in accounts {
+        let row = format!("account {} ({})", account.id, account.username);
+        if !seen.insert(account.id) {
+            skip(&mut plan, row, "another account has the same ID");
+        } else if account.username.trim().is_empty() {
+            skip(&mut plan, row, "no username");
+        } else if !taken_usernames.insert(account.username.to_lowercase()) {
+            skip(&mut plan, row, "username already taken");
+        } else {
+            if !is_bcrypt(&account.password_hash) {
+                match passwords {
+                    Passwords::Reset => {
+                        account.password_hash.clear();
+                        plan.report.warnings.push(Problem {
+                            row,
+                            reason:
+                                "password isn't a bcrypt hash; it must be reset before logging in"
+                                    .to_string(),
+                        });
+                    }
+                    Passwords::HashPlaintext { cost } => {
+                        account.password_hash = bcrypt::hash(&account.password_hash, cost)
+                            .with_context(|| format!("hashing the password of {}", row))?;
+                    }
+                }
+            }
+            account_ids.insert(account.id);
+            plan.accounts.push(account);
+        }
+    }
+
+    let (mut seen, mut character_ids) = (HashSet::new(), HashSet::new());
+    let mut taken_names = names;
+    let mut slots: HashMap<i64, HashSet<i32>> = HashMap::new();
+    for mut character in characters {
+        let row = format!("character {} ({})", character.id, character.name);
+        let c = &character;
+        let used = slots.entry(c.account_id).or_default();
+        let reason = if !seen.insert(c.id) {
+            Some("another character has the same ID")
+        } else if !account_ids.contains(&c.account_id) {
+            Some("its account isn't imported")
+        } else if !valid_name(&c.name) {
+            Some("name isn't 2 to 16 letters and digits")
+        } else if c.level < 1 || c.job_level < 1 || c.experience < 0 || c.job_experience < 0 {
+            Some("level or experience out of range")
+        } else if c.max_hp < 1 || !(0..=c.max_hp).contains(&c.hp) || !(0..=c.max_mp).contains(&c.mp)
+        {
+            Some("HP or MP out of range")
+        } else if c.gold < 0 {
+            Some("negative zeny")
+        } else if taken_names.contains(&c.name.to_lowercase()) {
+            Some("name already taken")
+        } else if used.contains(&c.slot_index) {
+            Some("another character is in the same slot")
+        } else {
+            None
+        };
+        if let Some(reason) = reason {
+            skip(&mut plan, row, reason);
+            continue;
+        }
+
+        // Sources without slots fill each account's from the first
+        if character.slot_index < 0 {
+            character.slot_index = (0..).find(|slot| !used.contains(slot)).unwrap_or_default();
+        }
+        used.insert(character.slot_index);
+        taken_names.insert(character.name.to_lowercase());
+        character_ids.insert(character.id);
+        plan.characters.push(character);
+    }
+
+    let mut item_slots = HashSet::new();
+    for item in items {
+        let row = format!(
+            "item {} in slot {} of character {}",
+            item.item_id, item.slot_index, item.character_id
+        );
+        if !character_ids.contains(&item.character_id) {
+            skip(&mut plan, row, "its character isn't imported");
+        } else if item.quantity < 1 || item.slot_index < 0 {
+            skip(&mut plan, row, "quantity or slot out of range");
+        } else if !item_slots.insert((item.character_id, item.slot_index)) {
+            skip(&mut plan, row, "another item is in the same slot");
+        } else {
+            plan.items.push(item);
+        }
+    }
+
+    plan.report.accounts = plan.accounts.len();
+    plan.report.characters = plan.characters.len();
+    plan.report.items = plan.items.len();
+    Ok(plan)
+}
+
+/// Insert everything in `plan` under new IDs, or nothing if any of it
+/// fails
+async fn write(target: &Pool<Sqlite>, plan: &Plan) -> Result<()> {
+    let mut tx = target.begin().await?;
+    let mut accounts = HashMap::new();
+    for account in &plan.accounts {
+        accounts.insert(account.id, AccountQueries::insert(&mut tx, account).await?);
+    }
+    let mut characters = HashMap::new();
+    for character in &plan.characters {
+        let owned = Character {
+            account_id: accounts[&character.account_id],
+            ..character.clone()
+        };
+        characters.insert(
+            character.id,
+            CharacterQueries::insert(&mut tx, &owned).await?,
+        );
+    }
+    for item in &plan.items {
+        let character_id = *characters
+            .get(&item.character_id)
+            .ok_or_else(|| anyhow!("item for unknown character {}", item.character_id))?;
+        InventoryQueries::insert(
+            &mut tx,
+            character_id,
+            item.slot_index,
+            item.item_id,
+            item.quantity,
+            item.equipped,
+        )
+        .await?;
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ro2_common::testing;
+    use sqlx::sqlite::SqlitePoolOptions;
+
+    const NOW: i64 = 1_700_000_000;
+
+    const MAPPING: &str = r#"
+        [accounts]
+        query = "SELECT account_id AS id, userid AS username, user_pass AS password_hash FROM login"
+
+        [characters]
+        query = "SELECT char_id AS id, account_id, name, class AS class_id, base_level AS level, hp, max_hp, sp AS mp, zeny AS gold, last_map AS map_id FROM char"
+
+        [inventory]
+        query = "SELECT char_id AS character_id, nameid AS item_id, amount AS quantity, idx AS slot_index, equip AS is_equipped FROM inventory"
+    "#;
+
+    async fn memory() -> Pool<Sqlite> {
+        SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap()
+    }
+
+    /// A dump in another emulator's schema
+    async fn source() -> Pool<Sqlite> {
+        let pool = memory().await;
+        sqlx::raw_sql(
+            "CREATE TABLE login (account_id INTEGER, userid TEXT, user_pass TEXT);
+             INSERT INTO login VALUES
+                 (2000, 'alice', '$2b$12$abcdefghijklmnopqrstuv'),
+                 (2001, 'bob', '5f4dcc3b5aa765d61d8327deb882cf99'),
+                 (2002, 'PLAYER', '$2b$12$abcdefghijklmnopqrstuv');
+             CREATE TABLE char (char_id INTEGER, account_id INTEGER, name TEXT, class INTEGER,
+                 base_level INTEGER, hp INTEGER, max_hp INTEGER, sp INTEGER, zeny INTEGER,
+                 last_map INTEGER);
+             INSERT INTO char VALUES
+                 (150, 2000, 'Alice', 1, 40, 900, 1000, 80, 5000, 3),
+                 (151, 2000, 'Alt', 2, 5, 100, 100, 10, 0, 1),
+                 (152, 2001, 'Bob the Bold', 1, 10, 100, 100, 10, 0, 1),
+                 (153, 2002, 'Ghost', 1, 10, 100, 100, 10, 0, 1),
+                 (154, 2001, 'Cheater', 1, 10, 100, 100, 10, -5, 1);
+             CREATE TABLE inventory (char_id INTEGER, nameid INTEGER, amount INTEGER,
+                 idx INTEGER, equip INTEGER);
+             INSERT INTO inventory VALUES
+                 (150, 1201, 1, 0, 1),
+                 (150, 501, 20, 1, 0),
+                 (150, 502, 1, 1, 0),
+                 (152, 501, 1, 0, 0);",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        pool
+    }
+
+    #[tokio::test]
+    async fn test_dry_run_reports_without_writing() {
+        let (source, target) = (source().await, testing::database().await);
+        let mapping = Mapping::from_toml(MAPPING).unwrap();
+        let report = import(&source, &target, &mapping, NOW, Passwords::Reset, false)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            (report.accounts, report.characters, report.items),
+            (2, 2, 2)
+        );
+        let skipped: Vec<(&str, &str)> = report
+            .skipped
+            .iter()
+            .map(|p| (p.row.as_str(), p.reason.as_str()))
+            .collect();
+        assert_eq!(
+            skipped,
+            [
+                ("account 2002 (PLAYER)", "username already taken"),
+                (
+                    "character 152 (Bob the Bold)",
+                    "name isn't 2 to 16 letters and digits"
+                ),
+                ("character 153 (Ghost)", "its account isn't imported"),
+                ("character 154 (Cheater)", "negative zeny"),
+                (
+                    "item 502 in slot 1 of character 150",
+                    "another item is in the same slot"
+                ),
+                (
+                    "item 501 in slot 0 of character 152",
+                    "its character isn't imported"
+                ),
+            ]
+        );
+        assert_eq!(report.warnings.len(), 1);
+        assert_eq!(report.warnings[0].row, "account 2001 (bob)");
+        assert!(
+            report
+                .to_string()
+                .starts_with("2 accounts, 2 characters, 2 items; 6 rows skipped, 1 warnings\n")
+        );
+
+        let (characters,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM characters")
+            .fetch_one(&target)
+            .await
+            .unwrap();
+        assert_eq!(characters, 0);
+    }
+
+    #[tokio::test]
+    async fn test_apply() {
+        let (source, target) = (source().await, testing::database().await);
+        let mapping = Mapping::from_toml(MAPPING).unwrap();
+        import(&source, &target, &mapping, NOW, Passwords::Reset, true)
+            .await
+            .unwrap();
+
+        let alice = AccountQueries::find_by_username(&target, "alice")
+            .await
+            .unwrap()
+            .unwrap();
+        let characters = CharacterQueries::list_for_account(&target, alice.id)
+            .await
+            .unwrap();
+        let summary: Vec<_> = characters
+            .iter()
+            .map(|c| {
+                (
+                    c.name.as_str(),
+                    c.slot_index,
+                    c.level,
+                    c.mp,
+                    c.max_mp,
+                    c.gold,
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            [("Alice", 0, 40, 80, 80, 5000), ("Alt", 1, 5, 10, 10, 0)]
+        );
+        let items: Vec<(i32, i32, i32, bool)> = sqlx::query_as(
+            "SELECT slot_index, item_id, quantity, is_equipped FROM inventory WHERE character_id = ? ORDER BY slot_index",
+        )
+        .bind(characters[0].id)
+        .fetch_all(&target)
+        .await
+        .unwrap();
+        assert_eq!(items, [(0, 1201, 1, true), (1, 501, 20, false)]);
+
+        // bob's password was an MD5, which isn't kept
+        let bob = AccountQueries::find_by_username(&target, "bob")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(bob.password_hash, "");
+        assert_eq!(alice.password_hash, "$2b$12$abcdefghijklmnopqrstuv");
+
+        // Everything is taken the second time round
+        let again = import(&source, &target, &mapping, NOW, Passwords::Reset, false)
+            .await
+            .unwrap();
+        assert_eq!((again.accounts, again.characters), (0, 0));
+    }
+
+    #[tokio::test]
+    async fn test_hash_plaintext() {
+        let (source, target) = (source().await, testing::database().await);
+        let mapping = Mapping::from_toml(MAPPING).unwrap();
+        let passwords = Passwords::HashPlaintext { cost: 4 };
+        let report = import(&source, &target, &mapping, NOW, passwords, true)
+            .await
+            .unwrap();
+        assert!(report.warnings.is_empty());
+
+        let bob = AccountQueries::find_by_username(&target, "bob")
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(bcrypt::verify("5f4dcc3b5aa765d61d8327deb882cf99", &bob.password_hash).unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_mapping_errors() {
+        let source = source().await;
+        assert!(Mapping::from_toml("[accounts]\nquery = \"SELECT 1\"").is_err());
+        let mapping = Mapping::from_toml(
+            "[accounts]\nquery = \"SELECT userid AS username FROM login\"\n[characters]\nquery = \"SELECT 1\"",
+        )
+        .unwrap();
+        let error = import(
+            &source,
+            &testing::database().await,
+            &mapping,
+            NOW,
+            Passwords::Reset,
+            false,
+        )
+        .await
+        .unwrap_err();
+        assert!(format!("{:#}", error).contains("reading accounts"));
+    }
+}
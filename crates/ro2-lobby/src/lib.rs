@@ -0,0 +1,13 @@
+//! RO2 Lobby Server Library
+//!
+//! Channel selection and character management for the lobby server (port
+//! 7201). The lobby binary doesn't route client messages yet, so nothing
+//! here is reachable from a client: the binary only loads the configs and
+//! runs the admin commands.
+
+pub mod channels;
+pub mod handlers;
+pub mod import;
+pub mod services;
+pub mod slots;
+pub mod starter;
@@ -2,51 +2,382 @@
 //!
 //! Handles channel selection and character management on port 7201
 
-mod handlers;
-
-use anyhow::Result;
+use anyhow::{Result, anyhow};
+use ro2_common::clock::Clock;
+use ro2_common::config::{self, ServerConfig};
+use ro2_common::database::ACCOUNT_RESTORE_GRACE_SECS;
+use ro2_common::database::backup::{self, BackupConfig};
+use ro2_common::database::queries::{
+    AccountDeactivationQueries, CharacterChangeQueries, GuildStorageQueries,
+};
+use ro2_common::events::{EventBus, Maintenance};
+use ro2_common::logging::LoggingConfig;
+use ro2_common::maintenance::{MAINTENANCE_PATH, MaintenanceConfig, MaintenanceScheduler};
+use ro2_common::net::Listeners;
+use ro2_common::selftest;
+use ro2_common::session::SessionManager;
+use ro2_lobby::channels::{self, ChannelListConfig};
+use ro2_lobby::import;
+use ro2_lobby::services::{self, Appearance, ServiceConfig};
+use ro2_lobby::slots::{self, SlotConfig};
+use ro2_lobby::starter::{STARTER_KITS_PATH, StarterKits};
 use std::net::SocketAddr;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::{TcpListener, TcpStream};
-use tracing::{error, info};
+use tokio::net::TcpStream;
+use tracing::{Instrument, error, info, info_span, warn};
 
+const CONFIG_PATH: &str = "config/lobby.toml";
 const LOBBY_PORT: u16 = 7201;
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    // Initialize logging
-    tracing_subscriber::fmt()
-        .with_env_filter(
-            tracing_subscriber::EnvFilter::from_default_env()
-                .add_directive(tracing::Level::INFO.into()),
-        )
-        .init();
+    // Logging outputs come from the `[logging]` section
+    let _log = LoggingConfig::load(CONFIG_PATH)?.init("lobby")?;
+
+    // `ro2-lobby admin ...` runs one moderation command and exits
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("admin") {
+        return admin(&args[1..]).await;
+    }
+    // `--self-test` checks the server could start, then exits
+    if selftest::requested() {
+        return self_test().await;
+    }
 
     info!("Starting RO2 Lobby Server v{}", env!("CARGO_PKG_VERSION"));
 
-    // Bind to lobby port
-    let addr = SocketAddr::from(([0, 0, 0, 0], LOBBY_PORT));
-    let listener = TcpListener::bind(addr).await?;
+    // Bind every configured listener
+    let config = ServerConfig::load(CONFIG_PATH, LOBBY_PORT)?;
+    let mut listeners = Listeners::bind(&config.all_listeners()).await?;
+
+    // Check the starter kits now rather than at the first character creation
+    let kits = StarterKits::load(STARTER_KITS_PATH)?;
+    info!(
+        "Loaded starter kits for {} classes plus the default",
+        kits.classes.len()
+    );
+
+    let services = ServiceConfig::load(CONFIG_PATH)?;
+    info!(
+        "Renames cost {} zeny every {} days, appearance changes {} zeny every {} days",
+        services.rename_cost,
+        services.rename_cooldown_days,
+        services.appearance_cost,
+        services.appearance_cooldown_days
+    );
+
+    let slot_config = SlotConfig::load(CONFIG_PATH)?;
+    info!(
+        "Accounts get {} character slots, up to {} with extra ones",
+        slot_config.base_slots, slot_config.max_slots
+    );
+
+    let channel_caps = ChannelListConfig::load(CONFIG_PATH)?;
+    info!(
+        "Channels are crowded from {} players and full from {} (0 = never)",
+        channel_caps.crowded_at, channel_caps.full_at
+    );
+
+    // Tokens for the world server are signed with the secret the two
+    // share. Nothing issues them yet; character selection will.
+    let _sessions = match config.transfer_secret()? {
+        Some(secret) => Some(SessionManager::new(secret)?),
+        None => {
+            warn!("No transfer_secret configured, players can't be handed over to the world");
+            None
+        }
+    };
+
+    // Scheduled backups, if the `[backup]` section asks for them
+    let backups = BackupConfig::load(CONFIG_PATH)?;
+    if backups.interval().is_some() {
+        dotenvy::dotenv().ok();
+        match config::database_url()? {
+            Some(url) => {
+                backup::spawn_backups(url, backups, Clock::system());
+            }
+            None => warn!("DATABASE_URL not set, the database won't be backed up"),
+        }
+    }
+
+    // The lobby exits at a scheduled restart along with the other servers
+    let maintenance = MaintenanceConfig::load(MAINTENANCE_PATH)?;
+    if maintenance.is_enabled() {
+        info!(
+            "Maintenance restarts at {}",
+            maintenance.restarts.join(", ")
+        );
+    }
+    let events = EventBus::new();
+    let mut restart = events.subscribe::<Maintenance>();
+    MaintenanceScheduler::new(maintenance).spawn(&events);
 
-    info!("Lobby server listening on {}", addr);
+    for (listener, addr) in listeners.local_addrs() {
+        info!("Lobby server listening on {} ({})", addr, listener.name);
+    }
 
-    // Accept connections
+    // Accept connections until the maintenance restart
     loop {
-        match listener.accept().await {
-            Ok((socket, addr)) => {
-                info!("New connection from {}", addr);
-
-                tokio::spawn(async move {
-                    if let Err(e) = handle_client(socket, addr).await {
-                        error!("Error handling client {}: {}", addr, e);
-                    }
-                });
+        let accepted = tokio::select! {
+            accepted = listeners.accept() => match accepted {
+                Some(accepted) => accepted,
+                None => break,
+            },
+            Some(step) = restart.recv() => {
+                if step == Maintenance::Restart {
+                    info!("Restarting for maintenance");
+                    break;
+                }
+                continue;
+            }
+        };
+        let (socket, addr) = (accepted.stream, accepted.addr);
+        info!("New connection from {} on {}", addr, accepted.listener.name);
+
+        let span = info_span!(
+            "connection",
+            remote_addr = %addr,
+            listener = %accepted.listener.name
+        );
+        tokio::spawn(
+            async move {
+                if let Err(e) = handle_client(socket, addr).await {
+                    error!("Error handling client {}: {}", addr, e);
+                }
+            }
+            .instrument(span),
+        );
+    }
+
+    Ok(())
+}
+
+/// Run the `--self-test` checks
+async fn self_test() -> Result<()> {
+    dotenvy::dotenv().ok();
+    let mut test = selftest::run(CONFIG_PATH, LOBBY_PORT).await;
+    test.record(
+        "starter kits",
+        StarterKits::load(STARTER_KITS_PATH).map(|kits| format!("{} classes", kits.classes.len())),
+    );
+    test.record(
+        "maintenance",
+        MaintenanceConfig::load(MAINTENANCE_PATH)
+            .map(|maintenance| format!("restarts at {:?}", maintenance.restarts)),
+    );
+    test.record(
+        "backup",
+        BackupConfig::load(CONFIG_PATH).map(|backup| format!("{:?}", backup)),
+    );
+    test.record(
+        "services",
+        ServiceConfig::load(CONFIG_PATH).map(|_| CONFIG_PATH.to_string()),
+    );
+    test.record(
+        "channels",
+        ChannelListConfig::load(CONFIG_PATH).map(|channels| format!("{:?}", channels)),
+    );
+    test.record(
+        "slots",
+        SlotConfig::load(CONFIG_PATH)
+            .map(|slots| format!("{} to {}", slots.base_slots, slots.max_slots)),
+    );
+    test.finish()
+}
+
+/// Run an admin command against the database in `DATABASE_URL`:
+///
+/// - `admin rename <character id> <new name> <admin name>`
+/// - `admin appearance <character id> <hair style> <hair color> <face>`
+/// - `admin names <character id or name>` lists past renames
+/// - `admin deactivate|restore|anonymize <account id> <admin name>`
+/// - `admin purge` anonymizes accounts deactivated longer than the grace
+///   period
+/// - `admin audit <account id>` lists what was done to an account
+/// - `admin slots <account id> [grant <count> <admin name>]` shows, or
+///   unlocks more of, an account's character slots
+/// - `admin channels` lists the channels with their load, as players see
+///   them
+/// - `admin guild <guild id> log` lists the latest guild storage deposits
+///   and withdrawals
+/// - `admin db backup [dir]` snapshots the database into `dir`, or the
+///   `[backup]` one
+/// - `admin db restore <file>` replaces the database with a backup; stop
+///   the servers first
+/// - `admin import <dump.db> <mapping.toml> [apply] [--hash-plaintext]`
+///   checks, and with `apply` imports, accounts and characters from
+///   another emulator's database (see [`import`])
+async fn admin(args: &[String]) -> Result<()> {
+    dotenvy::dotenv().ok();
+    let url = config::database_url()?.ok_or_else(|| anyhow!("DATABASE_URL not set"))?;
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+    // Before connecting, which a restore mustn't be
+    match args[..] {
+        ["db", "backup"] => {
+            let dir = BackupConfig::load(CONFIG_PATH)?.dir;
+            let path = backup::backup(&url, dir, &Clock::system()).await?;
+            println!("Backed up to {}", path.display());
+            return Ok(());
+        }
+        ["db", "backup", dir] => {
+            let path = backup::backup(&url, dir, &Clock::system()).await?;
+            println!("Backed up to {}", path.display());
+            return Ok(());
+        }
+        ["db", "restore", file] => {
+            backup::restore(&url, file).await?;
+            println!("Restored from {}", file);
+            return Ok(());
+        }
+        _ => {}
+    }
+
+    let pool = sqlx::SqlitePool::connect(&url).await?;
+    let now = Clock::system().unix();
+    match args[..] {
+        ["rename", id, name, by] => {
+            let result = services::admin_rename(&pool, id.parse()?, name, by, now).await?;
+            println!("{:?}", result);
+        }
+        ["appearance", id, hair_style, hair_color, face] => {
+            let appearance = Appearance {
+                hair_style: hair_style.parse()?,
+                hair_color: hair_color.parse()?,
+                face: face.parse()?,
+            };
+            let result = services::admin_set_appearance(&pool, id.parse()?, appearance).await?;
+            println!("{:?}", result);
+        }
+        ["names", character] => {
+            let history = match character.parse() {
+                Ok(id) => CharacterChangeQueries::name_history(&pool, id).await?,
+                Err(_) => CharacterChangeQueries::find_name(&pool, character).await?,
+            };
+            for change in history {
+                println!(
+                    "{}\t{}\t{} -> {}\t{}",
+                    change.changed_at,
+                    change.character_id,
+                    change.old_name,
+                    change.new_name,
+                    change.changed_by.as_deref().unwrap_or("(paid)")
+                );
+            }
+        }
+        ["deactivate", id, by] => {
+            let done = AccountDeactivationQueries::deactivate(&pool, id.parse()?, by, now).await?;
+            println!("{}", if done { "Deactivated" } else { "Not active" });
+        }
+        ["restore", id, by] => {
+            let done = AccountDeactivationQueries::restore(
+                &pool,
+                id.parse()?,
+                by,
+                now,
+                ACCOUNT_RESTORE_GRACE_SECS,
+            )
+            .await?;
+            println!(
+                "{}",
+                if done {
+                    "Restored"
+                } else {
+                    "Not deactivated, or past the grace period"
+                }
+            );
+        }
+        ["anonymize", id, by] => {
+            let done = AccountDeactivationQueries::anonymize(&pool, id.parse()?, by, now).await?;
+            println!(
+                "{}",
+                if done {
+                    "Anonymized"
+                } else {
+                    "Not deactivated, or already anonymized"
+                }
+            );
+        }
+        ["purge"] => {
+            let accounts =
+                AccountDeactivationQueries::past_grace(&pool, now, ACCOUNT_RESTORE_GRACE_SECS)
+                    .await?;
+            for id in &accounts {
+                AccountDeactivationQueries::anonymize(&pool, *id, "system", now).await?;
             }
-            Err(e) => {
-                error!("Failed to accept connection: {}", e);
+            println!("Anonymized {} accounts", accounts.len());
+        }
+        ["import", source, mapping, ref options @ ..]
+            if options
+                .iter()
+                .all(|option| ["apply", "--hash-plaintext"].contains(option)) =>
+        {
+            let apply = options.contains(&"apply");
+            let passwords = if options.contains(&"--hash-plaintext") {
+                import::Passwords::HashPlaintext {
+                    cost: bcrypt::DEFAULT_COST,
+                }
+            } else {
+                import::Passwords::Reset
+            };
+            let mapping = import::Mapping::load(mapping)?;
+            let source = sqlx::SqlitePool::connect(&format!("sqlite:{}?mode=ro", source)).await?;
+            let report = import::import(&source, &pool, &mapping, now, passwords, apply).await?;
+            print!("{}", report);
+            if !apply {
+                println!("Nothing written; add `apply` to import");
             }
         }
+        ["slots", id] => {
+            let slots =
+                slots::account_slots(&pool, &SlotConfig::load(CONFIG_PATH)?, id.parse()?).await?;
+            println!("{} of {} slots unlocked", slots.unlocked, slots.total);
+        }
+        ["slots", id, "grant", count, by] => {
+            let config = SlotConfig::load(CONFIG_PATH)?;
+            let result =
+                slots::grant_slots(&pool, &config, id.parse()?, count.parse()?, by, now).await?;
+            println!("{:?}", result);
+        }
+        ["audit", id] => {
+            for entry in AccountDeactivationQueries::audit_log(&pool, id.parse()?).await? {
+                println!("{}\t{}\t{}", entry.at, entry.action, entry.actor);
+            }
+        }
+        ["channels"] => {
+            let list =
+                channels::channel_list(&pool, &ChannelListConfig::load(CONFIG_PATH)?, now).await?;
+            for channel in &list.channels {
+                let recommended = if list.recommended == Some(channel.id) {
+                    "\trecommended"
+                } else {
+                    ""
+                };
+                println!(
+                    "{}\t{}\t{} players\t{:?}{}",
+                    channel.id, channel.name, channel.players, channel.load, recommended
+                );
+            }
+        }
+        ["guild", id, "log"] => {
+            for entry in GuildStorageQueries::log(&pool, id.parse()?, 50).await? {
+                let what = entry
+                    .item_id
+                    .map_or("zeny".to_string(), |item| format!("item {}", item));
+                println!(
+                    "{}\t{}\t{}\t{} {}",
+                    entry.at, entry.character_id, entry.action, entry.amount, what
+                );
+            }
+        }
+        _ => {
+            return Err(anyhow!(
+                "usage: ro2-lobby admin rename <id> <name> <admin> | appearance <id> <hair style> <hair color> <face> | names <id or name> | deactivate|restore|anonymize <account id> <admin> | purge | audit <account id> | slots <account id> [grant <count> <admin>] | channels | guild <id> log | db backup [dir] | db restore <file> | import <dump.db> <mapping.toml> [apply] [--hash-plaintext]"
+            ));
+        }
     }
+
+    Ok(())
 }
 
 /// Handle a single client connection
@@ -0,0 +1,483 @@
+//! Paid character services: renames and appearance changes
+//!
+//! Players pay zeny to rename a character or change its hair and face
+//! (plastic surgery), and then have to wait out a cooldown before doing it
+//! again. Every rename keeps the old name in `character_name_history` so
+//! moderators can find who used to go by a name. Admins can do both for
+//! free with [`admin_rename`] and [`admin_set_appearance`], e.g. from the
+//! `ro2-lobby admin` command line; their renames record who did them.
+//!
+//! Prices and cooldowns are set in the `[services]` section of
+//! `config/lobby.toml`:
+//!
+//! ```toml
+//! [services]
+//! rename_cost = 100000          # Zeny
+//! appearance_cost = 50000
+//! rename_cooldown_days = 30
+//! appearance_cooldown_days = 7
+//! ```
+//!
+//! The opcodes below are placeholders like those in `MessageType`. The
+//! lobby doesn't route client messages yet, so until it does players can't
+//! reach [`handle_req_rename`] or [`handle_req_change_appearance`]; only
+//! the admin commands work.
+
+use anyhow::{Result, anyhow};
+use ro2_common::config::{self, Validate};
+use ro2_common::database::queries::{CharacterChangeQueries, CharacterQueries};
+use ro2_common::wire::{WireReader, WireWriter};
+use serde::Deserialize;
+use sqlx::{Pool, Sqlite};
+use std::path::Path;
+use tracing::info;
+
+pub use ro2_common::protocol::opcodes::{
+    ACK_CHANGE_APPEARANCE, ACK_RENAME, REQ_CHANGE_APPEARANCE, REQ_RENAME,
+};
+
+/// Shortest and longest names, in characters
+pub const NAME_LENGTH: std::ops::RangeInclusive<usize> = 2..=16;
+
+const DAY_SECS: i64 = 24 * 60 * 60;
+
+/// Service prices and cooldowns
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct ServiceConfig {
+    pub rename_cost: i64,
+    pub appearance_cost: i64,
+    pub rename_cooldown_days: i64,
+    pub appearance_cooldown_days: i64,
+}
+
+impl Default for ServiceConfig {
+    fn default() -> Self {
+        Self {
+            rename_cost: 100_000,
+            appearance_cost: 50_000,
+            rename_cooldown_days: 30,
+            appearance_cooldown_days: 7,
+        }
+    }
+}
+
+impl ServiceConfig {
+    /// Read the `[services]` section of `path`; missing keys keep their
+    /// defaults
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        config::section(path, "services")
+    }
+
+    /// Parse the `[services]` section of TOML text
+    pub fn from_toml(text: &str) -> Result<Self> {
+        config::section_from_toml(text, "services")
+    }
+}
+
+impl Validate for ServiceConfig {
+    fn validate(&self) -> Result<()> {
+        if [
+            self.rename_cost,
+            self.appearance_cost,
+            self.rename_cooldown_days,
+            self.appearance_cooldown_days,
+        ]
+        .iter()
+        .any(|value| *value < 0)
+        {
+            return Err(anyhow!(
+                "character service costs and cooldowns can't be negative"
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// How a service request went, sent back as a u8
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ChangeResult {
+    Done = 0,
+    /// No such character, or not the player's
+    NoSuchCharacter = 1,
+    InvalidName = 2,
+    NameTaken = 3,
+    /// Changed too recently
+    Cooldown = 4,
+    NotEnoughZeny = 5,
+}
+
+/// Hair and face
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Appearance {
+    pub hair_style: i32,
+    pub hair_color: i32,
+    pub face: i32,
+}
+
+impl From<Appearance> for (i32, i32, i32) {
+    fn from(look: Appearance) -> Self {
+        (look.hair_style, look.hair_color, look.face)
+    }
+}
+
+/// Whether `name` may be a character name: 2 to 16 letters and digits
+pub fn valid_name(name: &str) -> bool {
+    NAME_LENGTH.contains(&name.chars().count()) && name.chars().all(char::is_alphanumeric)
+}
+
+/// Whether a change made at `last` is still cooling down at `now`
+fn cooling_down(last: Option<i64>, cooldown_days: i64, now: i64) -> bool {
+    last.is_some_and(|last| now < last + cooldown_days * DAY_SECS)
+}
+
+/// Rename `account_id`'s character for [`ServiceConfig::rename_cost`]
+pub async fn rename(
+    pool: &Pool<Sqlite>,
+    config: &ServiceConfig,
+    account_id: i64,
+    character_id: i64,
+    new_name: &str,
+    now: i64,
+) -> Result<ChangeResult> {
+    let Some(character) = CharacterQueries::find(pool, character_id)
+        .await?
+        .filter(|c| c.account_id == account_id)
+    else {
+        return Ok(ChangeResult::NoSuchCharacter);
+    };
+    let (renamed_at, _) = CharacterChangeQueries::last_changes(pool, character_id).await?;
+    if cooling_down(renamed_at, config.rename_cooldown_days, now) {
+        return Ok(ChangeResult::Cooldown);
+    }
+    if !valid_name(new_name) {
+        return Ok(ChangeResult::InvalidName);
+    }
+
+    let mut tx = pool.begin().await?;
+    if CharacterQueries::name_taken(&mut tx, new_name).await? {
+        return Ok(ChangeResult::NameTaken);
+    }
+    if !CharacterQueries::spend_gold(&mut tx, character_id, config.rename_cost).await? {
+        return Ok(ChangeResult::NotEnoughZeny);
+    }
+    CharacterChangeQueries::rename(&mut tx, character_id, &character.name, new_name, now, None)
+        .await?;
+    tx.commit().await?;
+
+    info!(
+        "Character {} renamed from {} to {} for {} zeny",
+        character_id, character.name, new_name, config.rename_cost
+    );
+    Ok(ChangeResult::Done)
+}
+
+/// Change the look of `account_id`'s character for
+/// [`ServiceConfig::appearance_cost`]
+pub async fn change_appearance(
+    pool: &Pool<Sqlite>,
+    config: &ServiceConfig,
+    account_id: i64,
+    character_id: i64,
+    appearance: Appearance,
+    now: i64,
+) -> Result<ChangeResult> {
+    if CharacterQueries::find(pool, character_id)
+        .await?
+        .is_none_or(|c| c.account_id != account_id)
+    {
+        return Ok(ChangeResult::NoSuchCharacter);
+    }
+    let (_, changed_at) = CharacterChangeQueries::last_changes(pool, character_id).await?;
+    if cooling_down(changed_at, config.appearance_cooldown_days, now) {
+        return Ok(ChangeResult::Cooldown);
+    }
+
+    let mut tx = pool.begin().await?;
+    if !CharacterQueries::spend_gold(&mut tx, character_id, config.appearance_cost).await? {
+        return Ok(ChangeResult::NotEnoughZeny);
+    }
+    CharacterChangeQueries::set_appearance(&mut tx, character_id, appearance.into(), Some(now))
+        .await?;
+    tx.commit().await?;
+
+    info!(
+        "Character {} changed appearance to {:?}",
+        character_id, appearance
+    );
+    Ok(ChangeResult::Done)
+}
+
+/// Rename any character for free, without a cooldown, recording `admin`
+/// as having done it
+pub async fn admin_rename(
+    pool: &Pool<Sqlite>,
+    character_id: i64,
+    new_name: &str,
+    admin: &str,
+    now: i64,
+) -> Result<ChangeResult> {
+    let Some(character) = CharacterQueries::find(pool, character_id).await? else {
+        return Ok(ChangeResult::NoSuchCharacter);
+    };
+    if !valid_name(new_name) {
+        return Ok(ChangeResult::InvalidName);
+    }
+
+    let mut tx = pool.begin().await?;
+    if CharacterQueries::name_taken(&mut tx, new_name).await? {
+        return Ok(ChangeResult::NameTaken);
+    }
+    CharacterChangeQueries::rename(
+        &mut tx,
+        character_id,
+        &character.name,
+        new_name,
+        now,
+        Some(admin),
+    )
+    .await?;
+    tx.commit().await?;
+
+    info!(
+        "{} renamed character {} from {} to {}",
+        admin, character_id, character.name, new_name
+    );
+    Ok(ChangeResult::Done)
+}
+
+/// Change any character's look for free, without a cooldown
+pub async fn admin_set_appearance(
+    pool: &Pool<Sqlite>,
+    character_id: i64,
+    appearance: Appearance,
+) -> Result<ChangeResult> {
+    if CharacterQueries::find(pool, character_id).await?.is_none() {
+        return Ok(ChangeResult::NoSuchCharacter);
+    }
+    let mut conn = pool.acquire().await?;
+    CharacterChangeQueries::set_appearance(&mut conn, character_id, appearance.into(), None)
+        .await?;
+    Ok(ChangeResult::Done)
+}
+
+/// Parse a rename request: u32 character ID, u16 name length, UTF-8 name
+pub fn parse_req_rename(message: &[u8]) -> Result<(i64, String)> {
+    let mut message = WireReader::message(message, REQ_RENAME, "rename request")?;
+    let character_id = message.u32("character ID")?;
+    let name = message.utf8("name")?;
+    Ok((character_id as i64, name))
+}
+
+/// Parse an appearance change request: u32 character ID, then u16 hair
+/// style, hair color and face
+pub fn parse_req_change_appearance(message: &[u8]) -> Result<(i64, Appearance)> {
+    let mut message =
+        WireReader::message(message, REQ_CHANGE_APPEARANCE, "appearance change request")?;
+    Ok((
+        message.u32("character ID")? as i64,
+        Appearance {
+            hair_style: message.u16("hair style")? as i32,
+            hair_color: message.u16("hair color")? as i32,
+            face: message.u16("face")? as i32,
+        },
+    ))
+}
+
+fn build_ack(opcode: u16, result: ChangeResult) -> Vec<u8> {
+    let mut out = WireWriter::message(opcode);
+    out.u8(result as u8);
+    out.into_bytes()
+}
+
+/// Answer [`REQ_RENAME`] from `account_id`
+pub async fn handle_req_rename(
+    pool: &Pool<Sqlite>,
+    config: &ServiceConfig,
+    account_id: i64,
+    message: &[u8],
+    now: i64,
+) -> Result<Vec<u8>> {
+    let (character_id, name) = parse_req_rename(message)?;
+    let result = rename(pool, config, account_id, character_id, &name, now).await?;
+    Ok(build_ack(ACK_RENAME, result))
+}
+
+/// Answer [`REQ_CHANGE_APPEARANCE`] from `account_id`
+pub async fn handle_req_change_appearance(
+    pool: &Pool<Sqlite>,
+    config: &ServiceConfig,
+    account_id: i64,
+    message: &[u8],
+    now: i64,
+) -> Result<Vec<u8>> {
+    let (character_id, appearance) = parse_req_change_appearance(message)?;
+    let result = change_appearance(pool, config, account_id, character_id, appearance, now).await?;
+    Ok(build_ack(ACK_CHANGE_APPEARANCE, result))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ro2_common::testing;
+
+    async fn pool() -> Pool<Sqlite> {
+        let pool = testing::database().await;
+        testing::character(&pool, 1, "Alice", 150_000).await;
+        testing::character(&pool, 2, "Bob", 10).await;
+        pool
+    }
+
+    fn request(character_id: u32, name: &str) -> Vec<u8> {
+        let mut out = REQ_RENAME.to_le_bytes().to_vec();
+        out.extend_from_slice(&character_id.to_le_bytes());
+        out.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        out.extend_from_slice(name.as_bytes());
+        out
+    }
+
+    #[test]
+    fn test_truncated_requests_fail() {
+        let rename = request(1, "bob");
+        for cut in 0..rename.len() {
+            let error = parse_req_rename(&rename[..cut]).unwrap_err();
+            assert!(
+                cut < 2 || error.to_string().contains("truncated"),
+                "{}",
+                error
+            );
+        }
+
+        let mut appearance = REQ_CHANGE_APPEARANCE.to_le_bytes().to_vec();
+        appearance.extend_from_slice(&[1, 0, 0, 0, 5, 0, 6, 0, 7, 0]);
+        assert!(parse_req_change_appearance(&appearance).is_ok());
+        for cut in 0..appearance.len() {
+            assert!(parse_req_change_appearance(&appearance[..cut]).is_err());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rename() {
+        let pool = pool().await;
+        let config = ServiceConfig::default();
+        let rename = |id, name: &str, now| {
+            let (pool, message) = (pool.clone(), request(id, name));
+            async move {
+                handle_req_rename(&pool, &config, 2, &message, now)
+                    .await
+                    .unwrap()[2]
+            }
+        };
+
+        assert_eq!(rename(1, "bob", 0).await, ChangeResult::NameTaken as u8);
+        assert_eq!(rename(1, "A", 0).await, ChangeResult::InvalidName as u8);
+        assert_eq!(
+            rename(1, "Al ice", 0).await,
+            ChangeResult::InvalidName as u8
+        );
+        assert_eq!(
+            rename(2, "Robert", 0).await,
+            ChangeResult::NotEnoughZeny as u8
+        );
+        assert_eq!(
+            rename(3, "Carol", 0).await,
+            ChangeResult::NoSuchCharacter as u8
+        );
+        assert_eq!(rename(1, "Alicia", 1_000).await, ChangeResult::Done as u8);
+
+        let alice = CharacterQueries::find(&pool, 1).await.unwrap().unwrap();
+        assert_eq!((alice.name.as_str(), alice.gold), ("Alicia", 50_000));
+
+        // Not again for 30 days
+        let later = 1_000 + 29 * DAY_SECS;
+        assert_eq!(rename(1, "Ally", later).await, ChangeResult::Cooldown as u8);
+
+        // Admins skip the cooldown and are recorded
+        assert_eq!(
+            admin_rename(&pool, 1, "Ally", "gm_jane", later)
+                .await
+                .unwrap(),
+            ChangeResult::Done
+        );
+        let history = CharacterChangeQueries::name_history(&pool, 1)
+            .await
+            .unwrap();
+        assert_eq!(history.len(), 2);
+        assert_eq!(
+            (
+                history[0].old_name.as_str(),
+                history[0].changed_by.as_deref()
+            ),
+            ("Alice", None)
+        );
+        assert_eq!(history[1].changed_by.as_deref(), Some("gm_jane"));
+        let found = CharacterChangeQueries::find_name(&pool, "alice")
+            .await
+            .unwrap();
+        assert_eq!(found[0].character_id, 1);
+
+        // Nobody else can take a name that's in use
+        assert_eq!(
+            admin_rename(&pool, 2, "ALLY", "gm_jane", later)
+                .await
+                .unwrap(),
+            ChangeResult::NameTaken
+        );
+        assert_eq!(
+            rename(1, "Other", later + 31 * DAY_SECS).await,
+            ChangeResult::NotEnoughZeny as u8
+        );
+        assert_eq!(
+            handle_req_rename(
+                &pool,
+                &config,
+                9,
+                &request(1, "Other"),
+                later + 2 * DAY_SECS
+            )
+            .await
+            .unwrap()[2],
+            ChangeResult::NoSuchCharacter as u8
+        );
+    }
+
+    #[tokio::test]
+    async fn test_change_appearance() {
+        let pool = pool().await;
+        let config = ServiceConfig::from_toml("[services]\nappearance_cost = 100000").unwrap();
+        let mut message = REQ_CHANGE_APPEARANCE.to_le_bytes().to_vec();
+        message.extend_from_slice(&1u32.to_le_bytes());
+        for value in [5u16, 6, 7] {
+            message.extend_from_slice(&value.to_le_bytes());
+        }
+
+        let ack = handle_req_change_appearance(&pool, &config, 2, &message, 100)
+            .await
+            .unwrap();
+        assert_eq!(ack, [0x53, 0x3F, ChangeResult::Done as u8]);
+        let alice = CharacterQueries::find(&pool, 1).await.unwrap().unwrap();
+        assert_eq!((alice.hair_style, alice.hair_color, alice.face), (5, 6, 7));
+        assert_eq!(alice.gold, 50_000);
+
+        let ack = handle_req_change_appearance(&pool, &config, 2, &message, 200)
+            .await
+            .unwrap();
+        assert_eq!(ack[2], ChangeResult::Cooldown as u8);
+        let ack = handle_req_change_appearance(&pool, &config, 2, &message, 100 + 7 * DAY_SECS)
+            .await
+            .unwrap();
+        assert_eq!(ack[2], ChangeResult::NotEnoughZeny as u8);
+
+        let look = Appearance {
+            hair_style: 1,
+            hair_color: 1,
+            face: 1,
+        };
+        assert_eq!(
+            admin_set_appearance(&pool, 2, look).await.unwrap(),
+            ChangeResult::Done
+        );
+        assert!(ServiceConfig::from_toml("[services]\nrename_cost = -1").is_err());
+    }
+}
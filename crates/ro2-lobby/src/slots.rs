@@ -0,0 +1,264 @@
+//! Character slots per account
+//!
+//! Every account gets the same number of base slots on the character
+//! select screen; more are unlocked one grant at a time, up to a limit.
+//! Grants come from whatever sells slots (a cash shop, once there is one,
+//! calls [`grant_slots`] with its own name) or from admins through
+//! `ro2-lobby admin slots`. Each is kept in `account_slot_grants`, so who
+//! gave an account its slots can be looked up later.
+//!
+//! The counts are set in the `[slots]` section of `config/lobby.toml`:
+//!
+//! ```toml
+//! [slots]
+//! base_slots = 3      # Every account
+//! max_slots = 6       # With every extra slot unlocked
+//! ```
+//!
+//! Lowering `base_slots` or `max_slots` later doesn't delete characters
+//! already in the slots that lock; they stay playable, but nothing new can
+//! be created there.
+//!
+//! [`check_free_slot`] is the check character creation makes (see
+//! [`crate::starter`]); until the lobby routes client messages it's only
+//! exercised by tests.
+
+use anyhow::{Result, anyhow};
+use ro2_common::config::{self, Validate};
+use ro2_common::database::queries::CharacterSlotQueries;
+use ro2_common::protocol::{ClientError, ErrorCode};
+use serde::Deserialize;
+use sqlx::{Pool, Sqlite, SqliteConnection};
+use std::path::Path;
+use tracing::info;
+
+/// How many slots accounts get
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct SlotConfig {
+    pub base_slots: i32,
+    pub max_slots: i32,
+}
+
+impl Default for SlotConfig {
+    fn default() -> Self {
+        Self {
+            base_slots: 3,
+            max_slots: 6,
+        }
+    }
+}
+
+impl SlotConfig {
+    /// Read the `[slots]` section of `path`; missing keys keep their
+    /// defaults
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        config::section(path, "slots")
+    }
+
+    /// Parse the `[slots]` section of TOML text
+    pub fn from_toml(text: &str) -> Result<Self> {
+        config::section_from_toml(text, "slots")
+    }
+}
+
+impl Validate for SlotConfig {
+    fn validate(&self) -> Result<()> {
+        if self.base_slots < 1 {
+            return Err(anyhow!("accounts need at least one character slot"));
+        }
+        // Sent to the client as a u8
+        if !(self.base_slots..=u8::MAX as i32).contains(&self.max_slots) {
+            return Err(anyhow!(
+                "max_slots must be between base_slots and {}",
+                u8::MAX
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// An account's slots, as the character list shows them
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AccountSlots {
+    /// Slots characters can be created in, from 0
+    pub unlocked: i32,
+    /// Every slot the screen shows, the locked ones included
+    pub total: i32,
+}
+
+impl AccountSlots {
+    pub fn is_unlocked(&self, slot_index: i32) -> bool {
+        (0..self.unlocked).contains(&slot_index)
+    }
+}
+
+/// How a grant went
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GrantResult {
+    /// The account now has this many slots unlocked
+    Granted(i32),
+    /// Already at [`SlotConfig::max_slots`], or the grant would go past it
+    OverLimit,
+}
+
+async fn slots_in(
+    conn: &mut SqliteConnection,
+    config: &SlotConfig,
+    account_id: i64,
+) -> Result<AccountSlots> {
+    let extra = CharacterSlotQueries::extra_slots(conn, account_id).await?;
+    Ok(AccountSlots {
+        unlocked: (config.base_slots + extra).min(config.max_slots),
+        total: config.max_slots,
+    })
+}
+
+/// How many slots `account_id` has unlocked
+pub async fn account_slots(
+    pool: &Pool<Sqlite>,
+    config: &SlotConfig,
+    account_id: i64,
+) -> Result<AccountSlots> {
+    let mut conn = pool.acquire().await?;
+    slots_in(&mut conn, config, account_id).await
+}
+
+/// Unlock `slots` more slots for `account_id`; `granted_by` is the admin,
+/// or what sold them
+pub async fn grant_slots(
+    pool: &Pool<Sqlite>,
+    config: &SlotConfig,
+    account_id: i64,
+    slots: i32,
+    granted_by: &str,
+    now: i64,
+) -> Result<GrantResult> {
+    if slots < 1 {
+        return Err(anyhow!("a grant needs at least one slot"));
+    }
+    let mut tx = pool.begin().await?;
+    let before = slots_in(&mut tx, config, account_id).await?;
+    if before.unlocked + slots > config.max_slots {
+        return Ok(GrantResult::OverLimit);
+    }
+    CharacterSlotQueries::grant(&mut tx, account_id, slots, now, granted_by).await?;
+    tx.commit().await?;
+
+    info!(
+        "{} gave account {} {} more character slots",
+        granted_by, account_id, slots
+    );
+    Ok(GrantResult::Granted(before.unlocked + slots))
+}
+
+/// Check a new character can go in `slot_index`: the slot is unlocked and
+/// nobody is in it; the error is a [`ClientError`] saying which
+///
+/// Takes a connection so the check can share the creation's transaction.
+pub async fn check_free_slot(
+    conn: &mut SqliteConnection,
+    config: &SlotConfig,
+    account_id: i64,
+    slot_index: i32,
+) -> Result<()> {
+    let slots = slots_in(conn, config, account_id).await?;
+    if !slots.is_unlocked(slot_index) {
+        return Err(ClientError::new(ErrorCode::NotAllowed, "character_slots.locked").into());
+    }
+    if CharacterSlotQueries::slot_taken(conn, account_id, slot_index).await? {
+        return Err(ClientError::new(ErrorCode::NotAllowed, "character_slots.taken").into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ro2_common::localization::Localization;
+    use ro2_common::testing;
+
+    fn code(error: anyhow::Error) -> ErrorCode {
+        let refusal = error.downcast_ref::<ClientError>().unwrap();
+        assert_ne!(Localization::builtin().get("en", &refusal.key), refusal.key);
+        refusal.code
+    }
+
+    #[test]
+    fn test_config() {
+        assert_eq!(
+            SlotConfig::load("does/not/exist.toml").unwrap(),
+            SlotConfig::default()
+        );
+        let config = SlotConfig::from_toml("[slots]\nmax_slots = 8").unwrap();
+        assert_eq!((config.base_slots, config.max_slots), (3, 8));
+        for bad in [
+            "[slots]\nbase_slots = 0",
+            "[slots]\nbase_slots = 4\nmax_slots = 2",
+            "[slots]\nmax_slots = 300",
+        ] {
+            assert!(SlotConfig::from_toml(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_grants_unlock_up_to_the_limit() {
+        let pool = testing::database().await;
+        let config = SlotConfig::default();
+        let slots = account_slots(&pool, &config, 2).await.unwrap();
+        assert_eq!(
+            slots,
+            AccountSlots {
+                unlocked: 3,
+                total: 6
+            }
+        );
+        assert!(slots.is_unlocked(2) && !slots.is_unlocked(3));
+
+        assert_eq!(
+            grant_slots(&pool, &config, 2, 2, "cash shop", 100)
+                .await
+                .unwrap(),
+            GrantResult::Granted(5)
+        );
+        assert_eq!(
+            grant_slots(&pool, &config, 2, 2, "gm", 200).await.unwrap(),
+            GrantResult::OverLimit
+        );
+        assert_eq!(
+            grant_slots(&pool, &config, 2, 1, "gm", 200).await.unwrap(),
+            GrantResult::Granted(6)
+        );
+        assert_eq!(account_slots(&pool, &config, 2).await.unwrap().unlocked, 6);
+        assert!(grant_slots(&pool, &config, 2, 0, "gm", 300).await.is_err());
+
+        // A lower limit later locks slots again without losing the grants
+        let lower = SlotConfig {
+            base_slots: 2,
+            max_slots: 4,
+        };
+        assert_eq!(account_slots(&pool, &lower, 2).await.unwrap().unlocked, 4);
+    }
+
+    #[tokio::test]
+    async fn test_check_free_slot() {
+        let pool = testing::database().await;
+        let config = SlotConfig::default();
+        sqlx::query(
+            "INSERT INTO characters (account_id, slot_index, name, class_id, map_id, position_x, position_y, position_z, hp, max_hp, mp, max_mp, created_at)
+             VALUES (2, 1, 'Alice', 1, 1, 0, 0, 0, 100, 100, 50, 50, 0)",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        let mut conn = pool.acquire().await.unwrap();
+        assert!(check_free_slot(&mut conn, &config, 2, 0).await.is_ok());
+        for slot in [1, 3, -1] {
+            let error = check_free_slot(&mut conn, &config, 2, slot)
+                .await
+                .unwrap_err();
+            assert_eq!(code(error), ErrorCode::NotAllowed);
+        }
+    }
+}
@@ -0,0 +1,411 @@
+//! Provisioning new characters from starter kits
+//!
+//! What a new character starts with comes from `config/starter_kits.toml`
+//! rather than code, so each server can set its own. A kit gives the
+//! starting map and position, HP/MP, zeny, items and skills; classes
+//! without a kit of their own get the default one:
+//!
+//! ```toml
+//! [default]
+//! map_id = 1
+//! position = [100.0, 0.0, 200.0]
+//! hp = 100
+//! mp = 50
+//! zeny = 500
+//! items = [{ item_id = 501, quantity = 5 }]
+//!
+//! [[classes]]
+//! class_id = 2
+//! map_id = 3
+//! position = [0.0, 0.0, 0.0]
+//! hp = 120
+//! mp = 30
+//! items = [{ item_id = 1201, quantity = 1 }]
+//! skills = [{ skill_id = 100, level = 1 }]
+//! ```
+//!
+//! The character, its items and its skills are created in one transaction,
+//! so a kit that can't be applied leaves no half-made character behind.
+//! Characters only go in unlocked, empty slots (see [`crate::slots`]).
+//! [`create_character`] is what the character-create handler will call;
+//! the lobby doesn't route client messages yet, so for now only the kits
+//! are loaded and checked at startup.
+
+use crate::slots::{self, SlotConfig};
+use anyhow::{Result, anyhow};
+use ro2_common::config::{self, Validate};
+use ro2_common::database::NewCharacter;
+use ro2_common::database::queries::{CharacterQueries, InventoryQueries, SkillQueries};
+use serde::Deserialize;
+use sqlx::{Pool, Sqlite};
+use std::path::Path;
+use tracing::info;
+
+/// Where the lobby server reads starter kits from
+pub const STARTER_KITS_PATH: &str = "config/starter_kits.toml";
+
+/// Items given to a new character
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub struct StarterItem {
+    pub item_id: i32,
+    #[serde(default = "one")]
+    pub quantity: i32,
+}
+
+/// Skills given to a new character
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub struct StarterSkill {
+    pub skill_id: i32,
+    #[serde(default = "one")]
+    pub level: i32,
+}
+
+fn one() -> i32 {
+    1
+}
+
+/// What a new character of one class starts with
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct StarterKit {
+    /// Unset for the default kit
+    #[serde(default)]
+    pub class_id: Option<i32>,
+    pub map_id: i32,
+    pub position: (f32, f32, f32),
+    pub hp: i32,
+    pub mp: i32,
+    #[serde(default)]
+    pub zeny: i64,
+    #[serde(default)]
+    pub items: Vec<StarterItem>,
+    #[serde(default)]
+    pub skills: Vec<StarterSkill>,
+}
+
+impl Default for StarterKit {
+    fn default() -> Self {
+        Self {
+            class_id: None,
+            map_id: 1,
+            position: (0.0, 0.0, 0.0),
+            hp: 100,
+            mp: 50,
+            zeny: 0,
+            items: Vec::new(),
+            skills: Vec::new(),
+        }
+    }
+}
+
+/// Every configured starter kit
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct StarterKits {
+    #[serde(default)]
+    pub default: StarterKit,
+    #[serde(default)]
+    pub classes: Vec<StarterKit>,
+}
+
+impl StarterKits {
+    /// Read the kits in `path`; without the file every class gets an
+    /// empty-handed default kit
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        config::load(path)
+    }
+
+    /// Parse kits from TOML text
+    pub fn from_toml(text: &str) -> Result<Self> {
+        config::from_toml(text)
+    }
+
+    /// The kit for `class_id`
+    pub fn for_class(&self, class_id: i32) -> &StarterKit {
+        self.classes
+            .iter()
+            .find(|kit| kit.class_id == Some(class_id))
+            .unwrap_or(&self.default)
+    }
+}
+
+impl Validate for StarterKits {
+    /// Catch mistakes at startup rather than at the first character
+    /// creation
+    fn validate(&self) -> Result<()> {
+        let mut seen = Vec::new();
+        for kit in &self.classes {
+            let class_id = kit
+                .class_id
+                .ok_or_else(|| anyhow!("every [[classes]] kit needs a class_id"))?;
+            if seen.contains(&class_id) {
+                return Err(anyhow!("two kits for class {}", class_id));
+            }
+            seen.push(class_id);
+        }
+        for kit in std::iter::once(&self.default).chain(&self.classes) {
+            if kit.hp <= 0 {
+                return Err(anyhow!("kit for class {:?} has no HP", kit.class_id));
+            }
+            if kit.items.iter().any(|item| item.quantity <= 0) {
+                return Err(anyhow!(
+                    "kit for class {:?} has an empty item",
+                    kit.class_id
+                ));
+            }
+            let mut skills: Vec<_> = kit.skills.iter().map(|skill| skill.skill_id).collect();
+            skills.sort_unstable();
+            if skills.windows(2).any(|pair| pair[0] == pair[1]) {
+                return Err(anyhow!("kit for class {:?} repeats a skill", kit.class_id));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// What the player chose on the character creation screen
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CharacterCreation {
+    pub account_id: i64,
+    pub slot_index: i32,
+    pub name: String,
+    pub class_id: i32,
+    pub gender: i32,
+    pub hair_style: i32,
+    pub hair_color: i32,
+    pub face: i32,
+}
+
+/// Create a character with its class's starter kit in one of the
+/// account's free slots; returns its ID
+pub async fn create_character(
+    pool: &Pool<Sqlite>,
+    kits: &StarterKits,
+    slot_config: &SlotConfig,
+    creation: &CharacterCreation,
+) -> Result<i64> {
+    let kit = kits.for_class(creation.class_id);
+    let new = NewCharacter {
+        account_id: creation.account_id,
+        slot_index: creation.slot_index,
+        name: creation.name.clone(),
+        job_class: creation.class_id,
+        gender: creation.gender,
+        hair_style: creation.hair_style,
+        hair_color: creation.hair_color,
+        face: creation.face,
+        map_id: kit.map_id,
+        position: kit.position,
+        hp: kit.hp,
+        mp: kit.mp,
+        gold: kit.zeny,
+    };
+
+    let mut tx = pool.begin().await?;
+    slots::check_free_slot(
+        &mut tx,
+        slot_config,
+        creation.account_id,
+        creation.slot_index,
+    )
+    .await?;
+    let character_id = CharacterQueries::create(&mut tx, &new).await?;
+    for (slot, item) in kit.items.iter().enumerate() {
+        InventoryQueries::add_item(
+            &mut tx,
+            character_id,
+            slot as i32,
+            item.item_id,
+            item.quantity,
+        )
+        .await?;
+    }
+    for skill in &kit.skills {
+        SkillQueries::learn(&mut tx, character_id, skill.skill_id, skill.level).await?;
+    }
+    tx.commit().await?;
+
+    info!(
+        "Created character {} ({}) for account {} with {} items and {} skills",
+        creation.name,
+        character_id,
+        creation.account_id,
+        kit.items.len(),
+        kit.skills.len()
+    );
+    Ok(character_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ro2_common::protocol::ClientError;
+    use ro2_common::testing;
+
+    const KITS: &str = r#"
+        [default]
+        map_id = 1
+        position = [100.0, 0.0, 200.0]
+        hp = 100
+        mp = 50
+        zeny = 500
+        items = [{ item_id = 501, quantity = 5 }]
+
+        [[classes]]
+        class_id = 2
+        map_id = 3
+        position = [1.0, 2.0, 3.0]
+        hp = 120
+        mp = 30
+        items = [{ item_id = 1201 }, { item_id = 501, quantity = 3 }]
+        skills = [{ skill_id = 100 }, { skill_id = 101, level = 2 }]
+    "#;
+
+    fn creation(name: &str, class_id: i32, slot_index: i32) -> CharacterCreation {
+        CharacterCreation {
+            account_id: 2,
+            slot_index,
+            name: name.to_string(),
+            class_id,
+            gender: 1,
+            hair_style: 2,
+            hair_color: 3,
+            face: 4,
+        }
+    }
+
+    #[test]
+    fn test_load_kits() {
+        let kits = StarterKits::from_toml(KITS).unwrap();
+        assert_eq!(kits.for_class(2).map_id, 3);
+        assert_eq!(kits.for_class(2).items[0].quantity, 1);
+        assert_eq!(kits.for_class(7), &kits.default);
+
+        assert_eq!(
+            StarterKits::load("does/not/exist.toml").unwrap(),
+            StarterKits::default()
+        );
+        for bad in [
+            "[[classes]]\nmap_id = 1\nposition = [0, 0, 0]\nhp = 1\nmp = 1",
+            "[default]\nmap_id = 1\nposition = [0, 0, 0]\nhp = 0\nmp = 1",
+            "[default]\nmap_id = 1\nposition = [0, 0, 0]\nhp = 1\nmp = 1\nskills = [{ skill_id = 1 }, { skill_id = 1 }]",
+        ] {
+            assert!(StarterKits::from_toml(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_create_character() {
+        let pool = testing::database().await;
+        let kits = StarterKits::from_toml(KITS).unwrap();
+
+        let id = create_character(
+            &pool,
+            &kits,
+            &SlotConfig::default(),
+            &creation("Swordie", 2, 0),
+        )
+        .await
+        .unwrap();
+        let characters = CharacterQueries::list_for_account(&pool, 2).await.unwrap();
+        assert_eq!(characters.len(), 1);
+        let character = &characters[0];
+        assert_eq!((character.id, character.map_id), (id, 3));
+        assert_eq!((character.x, character.y, character.z), (1.0, 2.0, 3.0));
+        assert_eq!((character.hp, character.max_hp), (120, 120));
+        assert_eq!((character.hair_style, character.face), (2, 4));
+        assert_eq!(character.gold, 0);
+
+        let items: Vec<(i32, i32, i32)> = sqlx::query_as(
+            "SELECT slot_index, item_id, quantity FROM inventory WHERE character_id = ? ORDER BY slot_index",
+        )
+        .bind(id)
+        .fetch_all(&pool)
+        .await
+        .unwrap();
+        assert_eq!(items, [(0, 1201, 1), (1, 501, 3)]);
+        assert_eq!(
+            SkillQueries::list(&pool, id).await.unwrap(),
+            [(100, 1), (101, 2)]
+        );
+        assert!(CharacterQueries::stats(&pool, id).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_failed_creation_leaves_nothing() {
+        let pool = testing::database().await;
+        let kits = StarterKits::from_toml(KITS).unwrap();
+        create_character(
+            &pool,
+            &kits,
+            &SlotConfig::default(),
+            &creation("Taken", 1, 0),
+        )
+        .await
+        .unwrap();
+
+        // Break the kit after validation: the skill insert fails after the
+        // character and its items were written
+        let mut broken = kits.clone();
+        broken.classes[0].skills.push(StarterSkill {
+            skill_id: 100,
+            level: 1,
+        });
+        assert!(
+            create_character(
+                &pool,
+                &broken,
+                &SlotConfig::default(),
+                &creation("Novice", 2, 1)
+            )
+            .await
+            .is_err()
+        );
+
+        let count = |table: &'static str| {
+            let pool = pool.clone();
+            async move {
+                let (n,): (i64,) = sqlx::query_as(&format!("SELECT COUNT(*) FROM {}", table))
+                    .fetch_one(&pool)
+                    .await
+                    .unwrap();
+                n
+            }
+        };
+        assert_eq!(count("characters").await, 1);
+        assert_eq!(count("inventory").await, 1);
+        assert_eq!(count("character_skills").await, 0);
+    }
+
+    #[tokio::test]
+    async fn test_creation_needs_a_free_unlocked_slot() {
+        let pool = testing::database().await;
+        let kits = StarterKits::from_toml(KITS).unwrap();
+        let config = SlotConfig {
+            base_slots: 2,
+            max_slots: 4,
+        };
+        create_character(&pool, &kits, &config, &creation("First", 1, 0))
+            .await
+            .unwrap();
+        for (name, slot) in [("Again", 0), ("Locked", 2)] {
+            let error = create_character(&pool, &kits, &config, &creation(name, 1, slot))
+                .await
+                .unwrap_err();
+            assert!(error.downcast_ref::<ClientError>().is_some(), "{}", name);
+        }
+
+        // A bought slot opens up
+        slots::grant_slots(&pool, &config, 2, 1, "cash shop", 100)
+            .await
+            .unwrap();
+        create_character(&pool, &kits, &config, &creation("Locked", 1, 2))
+            .await
+            .unwrap();
+        assert_eq!(
+            CharacterQueries::list_for_account(&pool, 2)
+                .await
+                .unwrap()
+                .len(),
+            2
+        );
+    }
+}
@@ -0,0 +1,523 @@
+//! Where logins are checked
+//!
+//! ReqLogin asks an [`AuthProvider`] whether the credentials are good.
+//! [`DatabaseAuth`] checks them against the `accounts` table and is the
+//! default; [`HttpAuth`] asks an external account API instead, for servers
+//! whose players already have accounts on a community site.
+//!
+//! The backend is picked in the `[auth]` section of `config/login.toml`:
+//!
+//! ```toml
+//! [auth]
+//! backend = "http"                               # or "database"
+//! url = "https://example.org/api/game-login"    # http only
+//! api_key = "secret"                             # sent as a bearer token
+//! timeout_secs = 5
+//! ```
+//!
+//! The HTTP backend POSTs `{"username": ..., "password": ...}` as JSON. A
+//! 200 answer carries `{"account_id": ...}`, the account's ID on the API;
+//! 401 means a wrong password, 403 a banned account and 404 an unknown one.
+//! Anything else is an error, and the login is refused. Characters,
+//! playtime, language and bans hang off a local account, so the first
+//! login of each API account creates one (without a password) and later
+//! ones find it again; banning or deactivating that local account refuses
+//! the login too. The HTTP backend needs the database for that.
+
+use anyhow::{Context, Result, anyhow};
+use async_trait::async_trait;
+use ro2_common::clock::Clock;
+use ro2_common::config::{self, Validate};
+use ro2_common::database::Account;
+use ro2_common::database::queries::{AccountQueries, ExternalAccountQueries};
+use ro2_common::wire::WireReader;
+use serde::Deserialize;
+use sqlx::{Pool, Sqlite};
+use std::path::Path;
+use std::time::Duration;
+
+/// Bytes of ReqLogin's username field, NUL-padded, at the start
+///
+/// Assumed layout until a capture of ReqLogin confirms it, like the
+/// placeholder opcodes in `MessageType`.
+const USERNAME_LEN: usize = 32;
+
+/// Bytes of the password field after it, NUL-padded (assumed, see
+/// [`USERNAME_LEN`])
+const PASSWORD_LEN: usize = 32;
+
+/// Username and password from ReqLogin
+#[derive(Clone, PartialEq, Eq)]
+pub struct Credentials {
+    pub username: String,
+    pub password: String,
+}
+
+impl std::fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Credentials")
+            .field("username", &self.username)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Credentials {
+    pub fn new(username: impl Into<String>, password: impl Into<String>) -> Self {
+        Self {
+            username: username.into(),
+            password: password.into(),
+        }
+    }
+
+    /// Read the credentials from ReqLogin's payload (after the opcode)
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        let mut r = WireReader::new(data);
+        Ok(Self {
+            username: r.fixed_str("username", USERNAME_LEN)?,
+            password: r.fixed_str("password", PASSWORD_LEN)?,
+        })
+    }
+}
+
+/// Why a login was refused
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rejection {
+    UnknownAccount,
+    WrongPassword,
+    Banned,
+    Deactivated,
+}
+
+/// What an [`AuthProvider`] made of some credentials
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthOutcome {
+    Accepted { account_id: i64 },
+    Rejected(Rejection),
+}
+
+/// Checks login credentials
+///
+/// An `Err` means the backend couldn't answer (database down, account API
+/// unreachable); the login is refused either way.
+#[async_trait]
+pub trait AuthProvider: Send + Sync {
+    async fn authenticate(&self, credentials: &Credentials) -> Result<AuthOutcome>;
+
+    /// Backend name for logs
+    fn name(&self) -> &'static str;
+}
+
+/// Whether `account` may log in once its credentials check out
+fn admit(account: &Account) -> AuthOutcome {
+    if account.is_banned {
+        AuthOutcome::Rejected(Rejection::Banned)
+    } else if account.deactivated_at.is_some() {
+        AuthOutcome::Rejected(Rejection::Deactivated)
+    } else {
+        AuthOutcome::Accepted {
+            account_id: account.id,
+        }
+    }
+}
+
+/// Checks credentials against the bcrypt hashes in `accounts`
+pub struct DatabaseAuth {
+    pool: Pool<Sqlite>,
+}
+
+impl DatabaseAuth {
+    pub fn new(pool: Pool<Sqlite>) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait]
+impl AuthProvider for DatabaseAuth {
+    async fn authenticate(&self, credentials: &Credentials) -> Result<AuthOutcome> {
+        let Some(account) =
+            AccountQueries::find_by_username(&self.pool, &credentials.username).await?
+        else {
+            return Ok(AuthOutcome::Rejected(Rejection::UnknownAccount));
+        };
+
+        // bcrypt is slow on purpose; keep it off the async workers
+        let password = credentials.password.clone();
+        let hash = account.password_hash.clone();
+        let matches = tokio::task::spawn_blocking(move || bcrypt::verify(password, &hash))
+            .await?
+            // Anonymized accounts have no hash left to match
+            .unwrap_or(false);
+
+        Ok(if matches {
+            admit(&account)
+        } else {
+            AuthOutcome::Rejected(Rejection::WrongPassword)
+        })
+    }
+
+    fn name(&self) -> &'static str {
+        "database"
+    }
+}
+
+/// Checks credentials with an external account API (see the module docs)
+#[derive(Clone)]
+pub struct HttpAuth {
+    url: String,
+    api_key: Option<String>,
+    timeout: Duration,
+    pool: Pool<Sqlite>,
+    clock: Clock,
+}
+
+#[derive(Deserialize)]
+struct HttpAccepted {
+    account_id: i64,
+}
+
+impl HttpAuth {
+    /// Ask the API at `url`, keeping the local accounts in `pool`
+    pub fn new(url: impl Into<String>, pool: Pool<Sqlite>) -> Self {
+        Self {
+            url: url.into(),
+            api_key: None,
+            timeout: Duration::from_secs(AuthConfig::default().timeout_secs),
+            pool,
+            clock: Clock::system(),
+        }
+    }
+
+    /// Send `api_key` as a bearer token
+    pub fn with_api_key(mut self, api_key: impl Into<String>) -> Self {
+        self.api_key = Some(api_key.into());
+        self
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Tell the time by `clock`
+    pub fn with_clock(mut self, clock: Clock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// What the API makes of `credentials`; an accepted account ID is the
+    /// API's own
+    fn post(&self, credentials: &Credentials) -> Result<AuthOutcome> {
+        let mut request = ureq::post(&self.url).timeout(self.timeout);
+        if let Some(api_key) = &self.api_key {
+            request = request.set("Authorization", &format!("Bearer {}", api_key));
+        }
+        let body = serde_json::json!({
+            "username": credentials.username,
+            "password": credentials.password,
+        });
+
+        match request.send_json(body) {
+            Ok(response) => {
+                let accepted: HttpAccepted = response
+                    .into_json()
+                    .context("account API answered 200 without an account_id")?;
+                Ok(AuthOutcome::Accepted {
+                    account_id: accepted.account_id,
+                })
+            }
+            Err(ureq::Error::Status(401, _)) => Ok(AuthOutcome::Rejected(Rejection::WrongPassword)),
+            Err(ureq::Error::Status(403, _)) => Ok(AuthOutcome::Rejected(Rejection::Banned)),
+            Err(ureq::Error::Status(404, _)) => {
+                Ok(AuthOutcome::Rejected(Rejection::UnknownAccount))
+            }
+            Err(ureq::Error::Status(status, _)) => Err(anyhow!("account API answered {}", status)),
+            Err(e) => Err(anyhow!("account API unreachable: {}", e)),
+        }
+    }
+}
+
+#[async_trait]
+impl AuthProvider for HttpAuth {
+    async fn authenticate(&self, credentials: &Credentials) -> Result<AuthOutcome> {
+        // ureq blocks; keep it off the async workers
+        let (auth, posted) = (self.clone(), credentials.clone());
+        let outcome = tokio::task::spawn_blocking(move || auth.post(&posted)).await??;
+        let AuthOutcome::Accepted {
+            account_id: external_id,
+        } = outcome
+        else {
+            return Ok(outcome);
+        };
+
+        let now = self.clock.unix();
+        let account =
+            ExternalAccountQueries::provision(&self.pool, external_id, &credentials.username, now)
+                .await
+                .with_context(|| {
+                    format!("finding the local account of API account {}", external_id)
+                })?;
+        Ok(admit(&account))
+    }
+
+    fn name(&self) -> &'static str {
+        "http"
+    }
+}
+
+/// Which [`AuthProvider`] the login server uses
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AuthBackend {
+    #[default]
+    Database,
+    Http,
+}
+
+/// Authentication settings
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct AuthConfig {
+    pub backend: AuthBackend,
+    /// Account API endpoint, for the HTTP backend
+    pub url: Option<String>,
+    pub api_key: Option<String>,
+    pub timeout_secs: u64,
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        Self {
+            backend: AuthBackend::Database,
+            url: None,
+            api_key: None,
+            timeout_secs: 5,
+        }
+    }
+}
+
+impl AuthConfig {
+    /// Read the `[auth]` section of `path`; missing keys keep their
+    /// defaults
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        config::section(path, "auth")
+    }
+
+    /// Parse the `[auth]` section of TOML text
+    pub fn from_toml(text: &str) -> Result<Self> {
+        config::section_from_toml(text, "auth")
+    }
+
+    /// The HTTP backend these settings describe, keeping its local
+    /// accounts in `pool`, if they pick it
+    pub fn http(&self, pool: Pool<Sqlite>) -> Option<HttpAuth> {
+        let url = self
+            .url
+            .as_ref()
+            .filter(|_| self.backend == AuthBackend::Http)?;
+        let auth = HttpAuth::new(url, pool).with_timeout(Duration::from_secs(self.timeout_secs));
+        Some(match &self.api_key {
+            Some(api_key) => auth.with_api_key(api_key),
+            None => auth,
+        })
+    }
+}
+
+impl Validate for AuthConfig {
+    fn validate(&self) -> Result<()> {
+        if self.backend == AuthBackend::Http && self.url.is_none() {
+            return Err(anyhow!("the http auth backend needs a url"));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ro2_common::database::queries::AccountDeactivationQueries;
+    use ro2_common::testing;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+
+    /// Answer one request with `status` and `body`; returns the URL and
+    /// the request's headers and body
+    fn serve_once(status: &str, body: &str) -> (String, std::thread::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/login", listener.local_addr().unwrap());
+        let response = format!(
+            "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            body.len(),
+            body
+        );
+        let handle = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut request = String::new();
+            let mut length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if let Some(value) = line.to_ascii_lowercase().strip_prefix("content-length:") {
+                    length = value.trim().parse().unwrap();
+                }
+                request.push_str(&line);
+                if line == "\r\n" {
+                    break;
+                }
+            }
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).unwrap();
+            request.push_str(&String::from_utf8(body).unwrap());
+            reader.get_mut().write_all(response.as_bytes()).unwrap();
+            request
+        });
+        (url, handle)
+    }
+
+    #[test]
+    fn test_parse_credentials() {
+        let mut data = [0u8; 209];
+        data[..6].copy_from_slice(b"player");
+        data[32..38].copy_from_slice(b"hunter");
+
+        let credentials = Credentials::parse(&data).unwrap();
+        assert_eq!(credentials, Credentials::new("player", "hunter"));
+        assert!(!format!("{:?}", credentials).contains("hunter"));
+        let error = Credentials::parse(&data[..40]).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "password truncated: 32 bytes wanted at offset 32, 8 left"
+        );
+        for cut in 0..64 {
+            assert!(Credentials::parse(&data[..cut]).is_err());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_database_auth() {
+        let pool = testing::database().await;
+        let hash = bcrypt::hash("secret", 4).unwrap();
+        let id = AccountQueries::create(&pool, "alice", &hash).await.unwrap();
+        let auth = DatabaseAuth::new(pool.clone());
+
+        let outcome = |username: &'static str, password: &'static str| {
+            let auth = &auth;
+            async move {
+                auth.authenticate(&Credentials::new(username, password))
+                    .await
+                    .unwrap()
+            }
+        };
+        assert_eq!(
+            outcome("alice", "secret").await,
+            AuthOutcome::Accepted { account_id: id }
+        );
+        assert_eq!(
+            outcome("alice", "guess").await,
+            AuthOutcome::Rejected(Rejection::WrongPassword)
+        );
+        assert_eq!(
+            outcome("bob", "secret").await,
+            AuthOutcome::Rejected(Rejection::UnknownAccount)
+        );
+
+        AccountDeactivationQueries::deactivate(&pool, id, "alice", 100)
+            .await
+            .unwrap();
+        assert_eq!(
+            outcome("alice", "secret").await,
+            AuthOutcome::Rejected(Rejection::Deactivated)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_http_auth() {
+        let pool = testing::database().await;
+        // A local account that isn't the API's 42
+        let hash = bcrypt::hash("secret", 4).unwrap();
+        AccountQueries::create(&pool, "carol", &hash).await.unwrap();
+
+        let (url, request) = serve_once("200 OK", r#"{"account_id": 42}"#);
+        let auth = HttpAuth::new(url, pool.clone())
+            .with_api_key("key")
+            .with_clock(Clock::at_unix(1_700_000_000));
+        let outcome = auth
+            .authenticate(&Credentials::new("alice", "secret"))
+            .await
+            .unwrap();
+        let request = request.join().unwrap();
+        assert!(request.starts_with("POST /login"));
+        assert!(request.contains("Bearer key"));
+        assert!(request.contains(r#""username":"alice""#));
+
+        // The first login makes a local account, without a password
+        let alice = AccountQueries::find_by_username(&pool, "alice")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            outcome,
+            AuthOutcome::Accepted {
+                account_id: alice.id
+            }
+        );
+        assert_ne!(alice.id, 42);
+        assert!(alice.password_hash.is_empty());
+        assert_eq!(alice.created_at, 1_700_000_000);
+
+        // and later ones find it, under whatever name
+        let (url, _) = serve_once("200 OK", r#"{"account_id": 42}"#);
+        let outcome = HttpAuth::new(url, pool.clone())
+            .authenticate(&Credentials::new("Alice2", "secret"))
+            .await
+            .unwrap();
+        assert_eq!(
+            outcome,
+            AuthOutcome::Accepted {
+                account_id: alice.id
+            }
+        );
+
+        AccountDeactivationQueries::deactivate(&pool, alice.id, "gm", 100)
+            .await
+            .unwrap();
+        let (url, _) = serve_once("200 OK", r#"{"account_id": 42}"#);
+        let outcome = HttpAuth::new(url, pool.clone())
+            .authenticate(&Credentials::new("alice", "secret"))
+            .await
+            .unwrap();
+        assert_eq!(outcome, AuthOutcome::Rejected(Rejection::Deactivated));
+
+        let (url, _) = serve_once("403 Forbidden", "{}");
+        let outcome = HttpAuth::new(url, pool.clone())
+            .authenticate(&Credentials::new("alice", "secret"))
+            .await
+            .unwrap();
+        assert_eq!(outcome, AuthOutcome::Rejected(Rejection::Banned));
+
+        let (url, _) = serve_once("500 Internal Server Error", "{}");
+        assert!(
+            HttpAuth::new(url, pool)
+                .authenticate(&Credentials::new("alice", "secret"))
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_config() {
+        let pool = testing::database().await;
+        assert_eq!(AuthConfig::from_toml("").unwrap(), AuthConfig::default());
+        assert!(AuthConfig::default().http(pool.clone()).is_none());
+
+        let config = AuthConfig::from_toml(
+            "[auth]\nbackend = \"http\"\nurl = \"http://127.0.0.1/login\"\ntimeout_secs = 2",
+        )
+        .unwrap();
+        assert_eq!(config.backend, AuthBackend::Http);
+        assert_eq!(config.http(pool).unwrap().timeout, Duration::from_secs(2));
+
+        assert!(AuthConfig::from_toml("[auth]\nbackend = \"http\"").is_err());
+    }
+}
@@ -1,50 +1,243 @@
 //! Login message handlers
+//!
+//! The initial handshake (0x0000) that comes first is answered by
+//! [`InitialHandshakeHandler`](ro2_common::protocol::InitialHandshakeHandler).
 
+use crate::auth::{AuthOutcome, AuthProvider, Credentials};
+use crate::queue::{Admission, Ticket, build_nfy_login_queue};
 use anyhow::Result;
-use tracing::info;
+use async_trait::async_trait;
+use ro2_common::crypto::SharedRng;
+use ro2_common::events::PlayerLoggedIn;
+use ro2_common::maintenance::MaintenanceGate;
+use ro2_common::playtime::{self, PlaytimeStore, Verdict};
+use ro2_common::protocol::handler::{GameContext, GameMessageHandler, Requirements};
+use ro2_common::protocol::opcodes::{ACK_LOGIN, REQ_LOGIN};
+use ro2_common::wire::WireWriter;
+use std::sync::Arc;
+use tracing::{info, warn};
+
+/// AckLogin result: success
+pub const LOGIN_OK: u32 = 0;
+
+/// AckLogin result: generic failure. Only success has been seen on the
+/// wire; this assumes the client's `LoginResult` values follow the order of
+/// its strings (`Login_Ok`, `Login_Failed`, ...).
+pub const LOGIN_FAILED: u32 = 1;
+
+/// Account every login is treated as when no [`AuthProvider`] checks the
+/// credentials
+const PLACEHOLDER_ACCOUNT_ID: u32 = 1;
 
 /// Handle ReqLogin (0x2EE2) message
-/// 
-/// Packet structure (211 bytes total):
-/// - Opcode: 2 bytes (0x2EE2)
-/// - Payload: 209 bytes (username, password, version, etc.)
-/// 
-/// Response: AckLogin (0x30D5) - 82 bytes total (2 byte opcode + 80 byte payload)
-pub async fn handle_req_login(data: &[u8]) -> Result<Vec<u8>> {
+///
+/// `data` is the 209-byte payload after the 0x2EE2 opcode (username,
+/// password, version, etc.)
+///
+/// Response: AckLogin (0x30D5) - 82 bytes total (2 byte opcode + 80 byte payload),
+/// for `account_id` with the session token drawn from `rng`
+pub async fn handle_req_login(data: &[u8], account_id: u32, rng: &SharedRng) -> Result<Vec<u8>> {
     info!("📧 ReqLogin (0x2EE2) received: {} bytes", data.len());
-    info!("   Raw hex (first 64 bytes): {}", hex::encode(&data[..data.len().min(64)]));
-    
-    // For now, accept any login and return success
-    // Real implementation would:
-    // 1. Parse username/password from the 209-byte structure
-    // 2. Validate credentials against database
-    // 3. Generate proper session tokens
-    
-    // Build AckLogin (0x30D5) response
-    // Structure: 2 bytes opcode + 80 bytes payload = 82 bytes total
-    let mut response = Vec::new();
-    
-    // Opcode 0x30D5 (little endian)
-    response.extend_from_slice(&[0xD5, 0x30]);
-    
+    info!(
+        "   Raw hex (first 64 bytes): {}",
+        hex::encode(&data[..data.len().min(64)])
+    );
+
+    let response = build_ack_login(LOGIN_OK, account_id, rng);
+
+    info!("✅ Sending AckLogin (0x30D5) - Login SUCCESS");
+    info!("   Response: {} bytes", response.len());
+
+    Ok(response)
+}
+
+/// Build AckLogin (0x30D5)
+///
+/// Structure: 2 bytes opcode + 80 bytes payload = 82 bytes total
+pub fn build_ack_login(result: u32, account_id: u32, rng: &SharedRng) -> Vec<u8> {
+    let mut response = WireWriter::message(ACK_LOGIN);
+
     // Result code (4 bytes) - 0 = success
-    response.extend_from_slice(&[0x00, 0x00, 0x00, 0x00]);
-    
-    // Account ID (4 bytes) - dummy value
-    response.extend_from_slice(&[0x01, 0x00, 0x00, 0x00]);
-    
+    response.u32(result);
+
+    // Account ID (4 bytes)
+    response.u32(account_id);
+
     // Session token (16 bytes) - random
-    let session_token: [u8; 16] = rand::random();
-    response.extend_from_slice(&session_token);
-    
+    let session_token: [u8; 16] = rng.bytes();
+    response.bytes(&session_token);
+
     // Remaining payload (56 bytes) - fill with zeros for now
     // This would contain: account flags, character slots, premium status, etc.
-    response.extend(vec![0u8; 56]);
-    
-    info!("✅ Sending AckLogin (0x30D5) - Login SUCCESS");
-    info!("   Response: {} bytes", response.len());
-    
-    Ok(response)
+    response.bytes(&[0u8; 56]);
+
+    response.into_bytes()
+}
+
+/// Handler for ReqLogin (0x2EE2)
+pub struct ReqLoginHandler {
+    rng: SharedRng,
+    auth: Option<Arc<dyn AuthProvider>>,
+    playtime: Option<Arc<dyn PlaytimeStore>>,
+    queue: Option<Ticket>,
+    maintenance: Option<MaintenanceGate>,
+}
+
+impl ReqLoginHandler {
+    pub fn new() -> Self {
+        Self::with_rng(SharedRng::os())
+    }
+
+    /// Draw session tokens from `rng`
+    pub fn with_rng(rng: SharedRng) -> Self {
+        Self {
+            rng,
+            auth: None,
+            playtime: None,
+            queue: None,
+            maintenance: None,
+        }
+    }
+
+    /// Check ReqLogin's credentials with `auth`; without one every login
+    /// is let in as a placeholder account
+    pub fn with_auth(mut self, auth: Arc<dyn AuthProvider>) -> Self {
+        self.auth = Some(auth);
+        self
+    }
+
+    /// The account `data`'s credentials belong to, or `None` if they're
+    /// refused
+    async fn authenticate(&self, data: &[u8]) -> Option<u32> {
+        let Some(auth) = &self.auth else {
+            return Some(PLACEHOLDER_ACCOUNT_ID);
+        };
+        let credentials = match Credentials::parse(data) {
+            Ok(credentials) => credentials,
+            Err(e) => {
+                warn!("Refusing malformed ReqLogin: {}", e);
+                return None;
+            }
+        };
+        match auth.authenticate(&credentials).await {
+            Ok(AuthOutcome::Accepted { account_id }) => match u32::try_from(account_id) {
+                Ok(account_id) => Some(account_id),
+                Err(_) => {
+                    warn!(
+                        "Refusing login for {}: account ID {} doesn't fit AckLogin",
+                        credentials.username, account_id
+                    );
+                    None
+                }
+            },
+            Ok(AuthOutcome::Rejected(rejection)) => {
+                info!(
+                    "Refusing login for {} ({}): {:?}",
+                    credentials.username,
+                    auth.name(),
+                    rejection
+                );
+                None
+            }
+            Err(e) => {
+                warn!(
+                    "Refusing login for {}: {} auth failed: {:#}",
+                    credentials.username,
+                    auth.name(),
+                    e
+                );
+                None
+            }
+        }
+    }
+
+    /// Refuse accounts whose playtime limits in `store` don't allow them
+    /// to play right now
+    pub fn with_playtime(mut self, store: Arc<dyn PlaytimeStore>) -> Self {
+        self.playtime = Some(store);
+        self
+    }
+
+    /// Hold logins past the server's cap in line with the connection's
+    /// `ticket` (see [`crate::queue`])
+    pub fn with_queue(mut self, ticket: Ticket) -> Self {
+        self.queue = Some(ticket);
+        self
+    }
+
+    /// Refuse every login once `gate` closes before a maintenance restart
+    pub fn with_maintenance(mut self, gate: MaintenanceGate) -> Self {
+        self.maintenance = Some(gate);
+        self
+    }
+}
+
+impl Default for ReqLoginHandler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl GameMessageHandler for ReqLoginHandler {
+    async fn handle(
+        &self,
+        _packet_id: u32,
+        data: &[u8],
+        context: &mut GameContext,
+    ) -> ro2_common::Result<Option<Vec<u8>>> {
+        if self
+            .maintenance
+            .as_ref()
+            .is_some_and(MaintenanceGate::is_closed)
+        {
+            info!("Refusing login: restarting for maintenance soon");
+            return Ok(Some(build_ack_login(LOGIN_FAILED, 0, &self.rng)));
+        }
+        let Some(account_id) = self.authenticate(data).await else {
+            return Ok(Some(build_ack_login(LOGIN_FAILED, 0, &self.rng)));
+        };
+        if let Some(store) = &self.playtime {
+            let now = context.shared.clock.local();
+            if let Verdict::Denied(restriction) =
+                playtime::check_login(store.as_ref(), account_id as i64, now).await?
+            {
+                warn!(
+                    "Refusing login for account {}: {:?}",
+                    account_id, restriction
+                );
+                return Ok(Some(build_ack_login(LOGIN_FAILED, account_id, &self.rng)));
+            }
+        }
+
+        if let Some(ticket) = &self.queue
+            && let Admission::Queued(position) = ticket.request(account_id)
+        {
+            // AckLogin follows through the outbox once it's their turn
+            context.account_id = Some(account_id);
+            return Ok(Some(build_nfy_login_queue(position, ticket.waiting())));
+        }
+
+        let response = handle_req_login(data, account_id, &self.rng).await?;
+        context.account_id = Some(account_id);
+        context.shared.events.publish(PlayerLoggedIn {
+            account_id,
+            session_id: context.session_id,
+        });
+        Ok(Some(response))
+    }
+
+    fn opcode(&self) -> u32 {
+        REQ_LOGIN.into()
+    }
+
+    fn name(&self) -> &'static str {
+        "ReqLogin"
+    }
+
+    fn requirements(&self) -> Requirements {
+        Requirements::ENCRYPTED
+    }
 }
 
 /// Handle ReqServerStatus message
@@ -55,3 +248,184 @@ pub async fn handle_req_server_status(_data: &[u8]) -> Result<Vec<u8>> {
 
     unimplemented!("ReqServerStatus handler not yet implemented")
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ro2_common::playtime::{MemoryPlaytimeStore, PlaytimeLimits};
+    use ro2_common::protocol::schema;
+    use ro2_common::testing::Golden;
+    use std::time::Duration;
+
+    /// A successful AckLogin, laid out per
+    /// docs/ghidra_analysis/opcode_reference.md; not captured yet
+    const ACK_LOGIN: &str = "
+        d530
+        00000000                      # result: success
+        01000000                      # account ID
+        ??*16                         # session token
+        00*56                         # account flags, slots, premium (unknown)";
+
+    #[tokio::test]
+    async fn test_ack_login_matches_schema() {
+        let response = handle_req_login(&[0u8; 209], PLACEHOLDER_ACCOUNT_ID, &SharedRng::seeded(1))
+            .await
+            .unwrap();
+        let opcode = u16::from_le_bytes([response[0], response[1]]);
+
+        let schema = schema::lookup(opcode).unwrap();
+        assert_eq!(schema.name, "AckLogin");
+        assert!(schema.decode(&response[2..]).is_exact());
+        Golden::parse("ack_login", ACK_LOGIN)
+            .unwrap()
+            .assert_matches(&response);
+        assert_eq!(response[10..26], SharedRng::seeded(1).bytes::<16>());
+    }
+
+    #[tokio::test]
+    async fn test_full_server_queues_login() {
+        use crate::queue::{LoginQueue, NFY_LOGIN_QUEUE};
+        use tokio::sync::mpsc;
+
+        let queue = LoginQueue::with_rng(1, SharedRng::seeded(1));
+        let (first_tx, _first_rx) = mpsc::channel(4);
+        let first =
+            ReqLoginHandler::with_rng(SharedRng::seeded(1)).with_queue(queue.ticket(first_tx));
+        let (second_tx, mut second_rx) = mpsc::channel(4);
+        let second =
+            ReqLoginHandler::with_rng(SharedRng::seeded(1)).with_queue(queue.ticket(second_tx));
+
+        let mut context = GameContext::new(1, "127.0.0.1:50000".to_string());
+        let response = first
+            .handle(REQ_LOGIN.into(), &[0u8; 209], &mut context)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(response[2..6], LOGIN_OK.to_le_bytes());
+
+        let mut context = GameContext::new(2, "127.0.0.1:50001".to_string());
+        let response = second
+            .handle(REQ_LOGIN.into(), &[0u8; 209], &mut context)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(response, build_nfy_login_queue(1, 1));
+        assert_eq!(response[..2], NFY_LOGIN_QUEUE.to_le_bytes());
+
+        drop(first);
+        let ack = second_rx.try_recv().unwrap();
+        assert_eq!(ack.len(), 82);
+        assert_eq!(ack[2..6], LOGIN_OK.to_le_bytes());
+    }
+
+    #[tokio::test]
+    async fn test_maintenance_refuses_login() {
+        let gate = MaintenanceGate::new();
+        let handler =
+            ReqLoginHandler::with_rng(SharedRng::seeded(1)).with_maintenance(gate.clone());
+        let mut context = GameContext::new(1, "127.0.0.1:50000".to_string());
+        let response = handler
+            .handle(REQ_LOGIN.into(), &[0u8; 209], &mut context)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(response[2..6], LOGIN_OK.to_le_bytes());
+
+        gate.close();
+        let mut context = GameContext::new(2, "127.0.0.1:50001".to_string());
+        let response = handler
+            .handle(REQ_LOGIN.into(), &[0u8; 209], &mut context)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(response[2..6], LOGIN_FAILED.to_le_bytes());
+        assert_eq!(context.account_id, None);
+    }
+
+    #[tokio::test]
+    async fn test_playtime_limits_refuse_login() {
+        let store = Arc::new(MemoryPlaytimeStore::new());
+        let handler = ReqLoginHandler::with_rng(SharedRng::seeded(1)).with_playtime(store.clone());
+        let mut context = GameContext::new(1, "127.0.0.1:50000".to_string());
+
+        let response = handler
+            .handle(REQ_LOGIN.into(), &[0u8; 209], &mut context)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(response[2..6], LOGIN_OK.to_le_bytes());
+        assert_eq!(context.account_id, Some(PLACEHOLDER_ACCOUNT_ID));
+
+        // No playtime left today
+        store.set_limits(
+            PLACEHOLDER_ACCOUNT_ID as i64,
+            PlaytimeLimits {
+                daily_cap: Some(Duration::ZERO),
+                allowed_hours: None,
+            },
+        );
+        let mut context = GameContext::new(2, "127.0.0.1:50001".to_string());
+        let response = handler
+            .handle(REQ_LOGIN.into(), &[0u8; 209], &mut context)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(response.len(), 82);
+        assert_eq!(response[2..6], LOGIN_FAILED.to_le_bytes());
+        assert_eq!(context.account_id, None);
+    }
+
+    #[tokio::test]
+    async fn test_auth_provider_picks_account() {
+        use crate::auth::Rejection;
+
+        struct OnlyAlice;
+
+        #[async_trait]
+        impl AuthProvider for OnlyAlice {
+            async fn authenticate(&self, credentials: &Credentials) -> Result<AuthOutcome> {
+                Ok(match credentials.username.as_str() {
+                    "alice" => AuthOutcome::Accepted { account_id: 7 },
+                    _ => AuthOutcome::Rejected(Rejection::UnknownAccount),
+                })
+            }
+
+            fn name(&self) -> &'static str {
+                "test"
+            }
+        }
+
+        let handler =
+            ReqLoginHandler::with_rng(SharedRng::seeded(1)).with_auth(Arc::new(OnlyAlice));
+        let mut data = [0u8; 209];
+        data[..5].copy_from_slice(b"alice");
+
+        let mut context = GameContext::new(1, "127.0.0.1:50000".to_string());
+        let mut logins = context.shared.events.subscribe::<PlayerLoggedIn>();
+        let response = handler
+            .handle(REQ_LOGIN.into(), &data, &mut context)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(response[2..6], LOGIN_OK.to_le_bytes());
+        assert_eq!(response[6..10], 7u32.to_le_bytes());
+        assert_eq!(context.account_id, Some(7));
+        assert_eq!(
+            logins.try_recv(),
+            Some(PlayerLoggedIn {
+                account_id: 7,
+                session_id: 1
+            })
+        );
+
+        data[..5].copy_from_slice(b"bobby");
+        let mut context = GameContext::new(2, "127.0.0.1:50001".to_string());
+        let response = handler
+            .handle(REQ_LOGIN.into(), &data, &mut context)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(response[2..6], LOGIN_FAILED.to_le_bytes());
+        assert_eq!(context.account_id, None);
+    }
+}
@@ -0,0 +1,56 @@
+//! RO2 Login Server Library
+//!
+//! Game message handlers for the login server (port 7101). The ProudNet
+//! layer is handled by [`ro2_common::net::ProudNetConnection`]; this crate
+//! only answers the decrypted login messages.
+
+pub mod auth;
+pub mod handlers;
+pub mod queue;
+
+pub use handlers::ReqLoginHandler;
+
+use ro2_common::protocol::{InitialHandshakeHandler, MessageDispatcher};
+use std::sync::Arc;
+
+/// Dispatcher with every login message handler registered
+pub fn dispatcher() -> MessageDispatcher {
+    dispatcher_with_login(ReqLoginHandler::new())
+}
+
+/// [`dispatcher`] answering ReqLogin with `login`, e.g. one holding the
+/// connection's place in the login queue
+pub fn dispatcher_with_login(login: ReqLoginHandler) -> MessageDispatcher {
+    MessageDispatcher::with_handlers(vec![
+        Arc::new(InitialHandshakeHandler::new()),
+        Arc::new(login),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ro2_common::protocol::GameContext;
+    use ro2_common::protocol::opcodes::{ACK_LOGIN, REQ_LOGIN};
+
+    #[tokio::test]
+    async fn test_dispatcher_answers_login() {
+        let mut dispatcher = dispatcher();
+        let mut context = GameContext::new(1, "127.0.0.1:50000".to_string());
+
+        // Not before the key exchange
+        let response = dispatcher
+            .dispatch(REQ_LOGIN.into(), &[0u8; 209], &mut context)
+            .await
+            .unwrap();
+        assert_eq!(response, None);
+
+        context.connection_info.encrypted = true;
+        let response = dispatcher
+            .dispatch(REQ_LOGIN.into(), &[0u8; 209], &mut context)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(response[..2], ACK_LOGIN.to_le_bytes());
+    }
+}
@@ -0,0 +1,314 @@
+//! Login queue for full servers
+//!
+//! With a cap on players online, logins past it wait in line instead of
+//! being refused. Each connection holds a [`Ticket`]; ReqLogin asks it for
+//! a slot, and a client that doesn't get one is told its place with
+//! [`NFY_LOGIN_QUEUE`] and again every `update_secs` while it waits. When a
+//! slot frees up the first in line is sent the AckLogin it was waiting for
+//! through its connection's outbox. Dropping a ticket (the connection
+//! closing) frees its slot or place in line.
+//!
+//! The cap is set in the `[queue]` section of `config/login.toml`:
+//!
+//! ```toml
+//! [queue]
+//! max_online = 2000  # 0 for no cap
+//! update_secs = 10   # How often waiting clients are told their place
+//! ```
+//!
+//! [`NFY_LOGIN_QUEUE`] is a placeholder opcode like those in
+//! `MessageType`.
+
+use crate::handlers::{LOGIN_OK, build_ack_login};
+use anyhow::{Result, anyhow};
+use ro2_common::config::{self, Validate};
+use ro2_common::crypto::SharedRng;
+use ro2_common::wire::WireWriter;
+use serde::Deserialize;
+use std::collections::{HashSet, VecDeque};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tracing::{debug, info};
+
+pub use ro2_common::protocol::opcodes::NFY_LOGIN_QUEUE;
+
+/// Login queue settings
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct QueueConfig {
+    /// Most players logged in at once; 0 for no cap
+    pub max_online: usize,
+    pub update_secs: u64,
+}
+
+impl Default for QueueConfig {
+    fn default() -> Self {
+        Self {
+            max_online: 0,
+            update_secs: 10,
+        }
+    }
+}
+
+impl QueueConfig {
+    /// Read the `[queue]` section of `path`; missing keys keep their
+    /// defaults
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        config::section(path, "queue")
+    }
+
+    /// Parse the `[queue]` section of TOML text
+    pub fn from_toml(text: &str) -> Result<Self> {
+        config::section_from_toml(text, "queue")
+    }
+
+    pub fn update_interval(&self) -> Duration {
+        Duration::from_secs(self.update_secs)
+    }
+}
+
+impl Validate for QueueConfig {
+    fn validate(&self) -> Result<()> {
+        if self.update_secs == 0 {
+            return Err(anyhow!("queue update_secs must be at least 1"));
+        }
+        Ok(())
+    }
+}
+
+/// Where a login stands
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Admission {
+    Admitted,
+    /// Waiting; 1 is next in line
+    Queued(usize),
+}
+
+/// Build [`NFY_LOGIN_QUEUE`]: u32 place in line, u32 clients waiting
+pub fn build_nfy_login_queue(position: usize, waiting: usize) -> Vec<u8> {
+    let mut out = WireWriter::message(NFY_LOGIN_QUEUE);
+    out.u32(position as u32).u32(waiting as u32);
+    out.into_bytes()
+}
+
+struct Waiter {
+    ticket: u64,
+    account_id: u32,
+    outbox: mpsc::Sender<Vec<u8>>,
+}
+
+#[derive(Default)]
+struct State {
+    next_ticket: u64,
+    online: HashSet<u64>,
+    waiting: VecDeque<Waiter>,
+}
+
+/// Caps the players online and queues the rest in order
+pub struct LoginQueue {
+    max_online: usize,
+    rng: SharedRng,
+    state: Mutex<State>,
+}
+
+impl LoginQueue {
+    /// Let at most `max_online` in at once; 0 for no cap
+    pub fn new(max_online: usize) -> Arc<Self> {
+        Self::with_rng(max_online, SharedRng::os())
+    }
+
+    /// Draw the session tokens of deferred AckLogins from `rng`
+    pub fn with_rng(max_online: usize, rng: SharedRng) -> Arc<Self> {
+        Arc::new(Self {
+            max_online,
+            rng,
+            state: Mutex::new(State::default()),
+        })
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn is_full(&self, state: &State) -> bool {
+        self.max_online != 0 && state.online.len() >= self.max_online
+    }
+
+    /// A ticket for a new connection, whose deferred messages go to
+    /// `outbox`
+    pub fn ticket(self: &Arc<Self>, outbox: mpsc::Sender<Vec<u8>>) -> Ticket {
+        let mut state = self.state();
+        state.next_ticket += 1;
+        Ticket {
+            id: state.next_ticket,
+            outbox,
+            queue: Arc::clone(self),
+        }
+    }
+
+    /// Players logged in
+    pub fn online(&self) -> usize {
+        self.state().online.len()
+    }
+
+    /// Clients waiting
+    pub fn waiting(&self) -> usize {
+        self.state().waiting.len()
+    }
+
+    /// Tell every waiting client its place in line; returns how many were
+    /// told
+    pub fn notify_positions(&self) -> usize {
+        let state = self.state();
+        let waiting = state.waiting.len();
+        for (index, waiter) in state.waiting.iter().enumerate() {
+            let _ = waiter
+                .outbox
+                .try_send(build_nfy_login_queue(index + 1, waiting));
+        }
+        waiting
+    }
+
+    fn request(&self, ticket: &Ticket, account_id: u32) -> Admission {
+        let mut state = self.state();
+        if state.online.contains(&ticket.id) {
+            return Admission::Admitted;
+        }
+        if let Some(index) = state.waiting.iter().position(|w| w.ticket == ticket.id) {
+            return Admission::Queued(index + 1);
+        }
+        if state.waiting.is_empty() && !self.is_full(&state) {
+            state.online.insert(ticket.id);
+            return Admission::Admitted;
+        }
+        state.waiting.push_back(Waiter {
+            ticket: ticket.id,
+            account_id,
+            outbox: ticket.outbox.clone(),
+        });
+        info!(
+            account_id,
+            position = state.waiting.len(),
+            "Server full, login queued"
+        );
+        Admission::Queued(state.waiting.len())
+    }
+
+    fn leave(&self, ticket: u64) {
+        let mut state = self.state();
+        if !state.online.remove(&ticket) {
+            state.waiting.retain(|w| w.ticket != ticket);
+            return;
+        }
+
+        // Let the next in line in
+        while !self.is_full(&state) {
+            let Some(waiter) = state.waiting.pop_front() else {
+                break;
+            };
+            let ack = build_ack_login(LOGIN_OK, waiter.account_id, &self.rng);
+            if waiter.outbox.try_send(ack).is_ok() {
+                debug!(account_id = waiter.account_id, "Admitted from login queue");
+                state.online.insert(waiter.ticket);
+            }
+        }
+    }
+}
+
+/// A connection's claim on a slot or a place in line
+pub struct Ticket {
+    id: u64,
+    outbox: mpsc::Sender<Vec<u8>>,
+    queue: Arc<LoginQueue>,
+}
+
+impl Ticket {
+    /// Ask for a slot for `account_id`, joining the line if there's none
+    pub fn request(&self, account_id: u32) -> Admission {
+        self.queue.request(self, account_id)
+    }
+
+    /// Clients waiting in this ticket's queue
+    pub fn waiting(&self) -> usize {
+        self.queue.waiting()
+    }
+}
+
+impl Drop for Ticket {
+    fn drop(&mut self) {
+        self.queue.leave(self.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ro2_common::protocol::opcodes::ACK_LOGIN;
+
+    #[test]
+    fn test_load() {
+        assert_eq!(
+            QueueConfig::load("does/not/exist.toml").unwrap(),
+            QueueConfig::default()
+        );
+        let config = QueueConfig::from_toml("[queue]\nmax_online = 500").unwrap();
+        assert_eq!(config.max_online, 500);
+        assert_eq!(config.update_interval(), Duration::from_secs(10));
+        assert!(QueueConfig::from_toml("[queue]\nupdate_secs = 0").is_err());
+    }
+
+    #[test]
+    fn test_queue_admits_in_order() {
+        let queue = LoginQueue::with_rng(1, SharedRng::seeded(1));
+        let mut inboxes = Vec::new();
+        let mut tickets = Vec::new();
+        for _ in 0..3 {
+            let (tx, rx) = mpsc::channel(8);
+            tickets.push(Some(queue.ticket(tx)));
+            inboxes.push(rx);
+        }
+        let request = |tickets: &[Option<Ticket>], i: usize| {
+            tickets[i].as_ref().unwrap().request(100 + i as u32)
+        };
+
+        assert_eq!(request(&tickets, 0), Admission::Admitted);
+        assert_eq!(request(&tickets, 1), Admission::Queued(1));
+        assert_eq!(request(&tickets, 2), Admission::Queued(2));
+        assert_eq!(request(&tickets, 2), Admission::Queued(2));
+        assert_eq!((queue.online(), queue.waiting()), (1, 2));
+
+        assert_eq!(queue.notify_positions(), 2);
+        assert_eq!(inboxes[1].try_recv().unwrap(), build_nfy_login_queue(1, 2));
+        assert_eq!(
+            inboxes[2].try_recv().unwrap(),
+            [0x40, 0x3F, 2, 0, 0, 0, 2, 0, 0, 0]
+        );
+
+        // The first logs out; the second gets in
+        tickets[0] = None;
+        let ack = inboxes[1].try_recv().unwrap();
+        assert_eq!(ack[..2], ACK_LOGIN.to_le_bytes());
+        assert_eq!(ack[2..6], LOGIN_OK.to_le_bytes());
+        assert_eq!(ack[6..10], 101u32.to_le_bytes());
+        assert_eq!(request(&tickets, 1), Admission::Admitted);
+
+        queue.notify_positions();
+        assert_eq!(inboxes[2].try_recv().unwrap(), build_nfy_login_queue(1, 1));
+
+        // Giving up your place frees it
+        tickets[2] = None;
+        assert_eq!(queue.waiting(), 0);
+        tickets[1] = None;
+        assert_eq!(queue.online(), 0);
+    }
+
+    #[test]
+    fn test_no_cap() {
+        let queue = LoginQueue::new(0);
+        let tickets: Vec<_> = (0..50).map(|_| queue.ticket(mpsc::channel(1).0)).collect();
+        assert!(tickets.iter().all(|t| t.request(1) == Admission::Admitted));
+        assert_eq!(queue.online(), 50);
+    }
+}
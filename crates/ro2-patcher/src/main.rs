@@ -8,6 +8,8 @@
  * - Always backs up the original executable before patching
  * - Verifies file checksum before applying patches
  * - Can verify patches were applied correctly
+ * - Patches are addressed by virtual address and mapped to file offsets
+ *   through the PE section table, so they only land in executable sections
  *
  * ## Usage
  * ```bash
@@ -17,13 +19,23 @@
  * # Restore backup
  * ro2-patcher restore /path/to/Rag2.exe
  *
+ * # Patch and recompute the PE checksum
+ * ro2-patcher patch --fix-checksum /path/to/Rag2.exe
+ *
+ * # Point the client at a private server
+ * ro2-patcher set-server /path/to/Rag2.exe 192.168.1.10
+ *
  * # Verify patches
  * ro2-patcher verify /path/to/Rag2.exe
  * ```
  */
 
-use anyhow::{bail, Context, Result};
+mod pe;
+mod server_address;
+
+use anyhow::{Context, Result, bail};
 use clap::{Parser, Subcommand};
+use pe::PeImage;
 use sha2::{Digest, Sha256};
 use std::fs;
 use std::path::{Path, PathBuf};
@@ -48,6 +60,10 @@ enum Commands {
         /// Skip backup creation
         #[arg(long)]
         no_backup: bool,
+
+        /// Recompute the PE header checksum after patching
+        #[arg(long)]
+        fix_checksum: bool,
     },
 
     /// Restore from backup
@@ -64,6 +80,30 @@ enum Commands {
         path: PathBuf,
     },
 
+    /// Rewrite the server address embedded in the client
+    SetServer {
+        /// Path to Rag2.exe
+        #[arg(value_name = "FILE")]
+        path: PathBuf,
+
+        /// New server IP or hostname
+        #[arg(value_name = "ADDRESS")]
+        address: String,
+
+        /// Address currently in the executable; without it, the IPv4
+        /// literals found are listed and nothing is changed
+        #[arg(long, value_name = "ADDRESS")]
+        current: Option<String>,
+
+        /// Show what would change without writing
+        #[arg(long)]
+        dry_run: bool,
+
+        /// Skip backup creation
+        #[arg(long)]
+        no_backup: bool,
+    },
+
     /// List available patches
     List,
 }
@@ -73,7 +113,8 @@ enum Commands {
 struct Patch {
     name: &'static str,
     description: &'static str,
-    offset: usize,
+    /// Virtual address as shown in Ghidra (image base 0x00400000)
+    va: u64,
     original: &'static [u8],
     patched: &'static [u8],
 }
@@ -86,7 +127,7 @@ const KNOWN_CHECKSUMS: &[&str] = &[
 /// Patch definitions for Rag2.exe
 const PATCHES: &[Patch] = &[
     // Patch 1: Force CheckGameProtectionEnabled to return FALSE
-    // Virtual Address: 0x00A4FFA0, File Offset: 0x0064F3A0
+    // Virtual Address: 0x00A4FFA0 (.text)
     // This function checks if game protection (HackShield) is enabled
     // We replace the function prologue with: MOV AL, 0; RET (+ NOPs to match original length)
     // This makes the function always return FALSE (protection NOT enabled)
@@ -95,7 +136,7 @@ const PATCHES: &[Patch] = &[
     Patch {
         name: "bypass_game_protection_check",
         description: "Forces CheckGameProtectionEnabled to return FALSE",
-        offset: 0x0064F3A0,
+        va: 0x00A4FFA0,
         original: &[
             0x55, 0x8B, 0xEC, 0x6A, 0xFF, 0x68, 0xB8, 0x2D, 0x2D, 0x01, 0x64, 0xA1, 0x00, 0x00,
             0x00, 0x00,
@@ -106,13 +147,13 @@ const PATCHES: &[Patch] = &[
         ], // MOV AL, 0; RET; NOP×13
     },
     // Patch 2: Force CheckProtectionSystemEnabled to return TRUE
-    // Virtual Address: 0x00A4CEF0, File Offset: 0x0064C2F0
+    // Virtual Address: 0x00A4CEF0 (.text)
     // This function checks if protection system is active
     // We replace the function prologue with: MOV AL, 1; RET (+ NOPs to match original length)
     Patch {
         name: "bypass_protection_system_check",
         description: "Forces CheckProtectionSystemEnabled to return TRUE",
-        offset: 0x0064C2F0,
+        va: 0x00A4CEF0,
         original: &[
             0x55, 0x8B, 0xEC, 0x6A, 0xFF, 0x68, 0x58, 0x25, 0x2D, 0x01, 0x64, 0xA1, 0x00, 0x00,
             0x00, 0x00,
@@ -128,14 +169,25 @@ fn main() -> Result<()> {
     let cli = Cli::parse();
 
     match cli.command {
-        Commands::Patch { path, no_backup } => patch_client(&path, !no_backup),
+        Commands::Patch {
+            path,
+            no_backup,
+            fix_checksum,
+        } => patch_client(&path, !no_backup, fix_checksum),
+        Commands::SetServer {
+            path,
+            address,
+            current,
+            dry_run,
+            no_backup,
+        } => set_server(&path, &address, current.as_deref(), dry_run, !no_backup),
         Commands::Restore { path } => restore_backup(&path),
         Commands::Verify { path } => verify_patches(&path),
         Commands::List => list_patches(),
     }
 }
 
-fn patch_client(path: &Path, create_backup: bool) -> Result<()> {
+fn patch_client(path: &Path, create_backup: bool, fix_checksum: bool) -> Result<()> {
     println!("🔧 RO2 Client Patcher");
     println!("━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━");
     println!();
@@ -160,6 +212,8 @@ fn patch_client(path: &Path, create_backup: bool) -> Result<()> {
         println!();
     }
 
+    let pe = PeImage::parse(&data).context("Failed to parse PE headers")?;
+
     // Create backup
     if create_backup {
         let backup_path = get_backup_path(path);
@@ -176,7 +230,7 @@ fn patch_client(path: &Path, create_backup: bool) -> Result<()> {
     for patch in PATCHES {
         print!("  • {} ... ", patch.description);
 
-        match apply_patch(&mut data, patch) {
+        match apply_patch(&mut data, &pe, patch) {
             Ok(true) => {
                 println!("✓ Applied");
                 applied += 1;
@@ -196,6 +250,13 @@ fn patch_client(path: &Path, create_backup: bool) -> Result<()> {
         return Ok(());
     }
 
+    if fix_checksum {
+        let old = pe.stored_checksum(&data)?;
+        let new = pe.fix_checksum(&mut data);
+        println!();
+        println!("🧮 PE checksum: 0x{:08X} → 0x{:08X}", old, new);
+    }
+
     // Write patched file
     println!();
     println!("💾 Writing patched executable...");
@@ -209,6 +270,82 @@ fn patch_client(path: &Path, create_backup: bool) -> Result<()> {
     Ok(())
 }
 
+fn set_server(
+    path: &Path,
+    address: &str,
+    current: Option<&str>,
+    dry_run: bool,
+    create_backup: bool,
+) -> Result<()> {
+    println!("🌐 Setting server address to {}", address);
+    println!();
+
+    let mut data = fs::read(path).context("Failed to read executable")?;
+    let pe = PeImage::parse(&data).context("Failed to parse PE headers")?;
+
+    let slots = server_address::find_slots(&data, &pe.data_ranges(), current);
+    let Some(current) = current else {
+        // Any dotted quad matches, version strings included, so the
+        // address to replace has to be named
+        if slots.is_empty() {
+            bail!("No embedded IPv4 addresses found; pass --current <ADDRESS>");
+        }
+        println!("Embedded IPv4 addresses:");
+        for slot in &slots {
+            println!(
+                "  • 0x{:08X} {:?} {} (max {})",
+                slot.offset, slot.encoding, slot.current, slot.capacity
+            );
+        }
+        println!();
+        bail!("Nothing changed; pass --current <ADDRESS> with the server address to replace");
+    };
+    if slots.is_empty() {
+        bail!("Address {} not found in executable", current);
+    }
+
+    // Validate every slot before touching anything
+    for slot in &slots {
+        if address.len() > slot.capacity {
+            bail!(
+                "{} does not fit: slot at 0x{:08X} ({}) holds at most {} characters",
+                address,
+                slot.offset,
+                slot.current,
+                slot.capacity
+            );
+        }
+    }
+
+    for slot in &slots {
+        println!(
+            "  • 0x{:08X} {:?} {} → {} (max {})",
+            slot.offset, slot.encoding, slot.current, address, slot.capacity
+        );
+        server_address::write_slot(&mut data, slot, address)?;
+    }
+
+    if dry_run {
+        println!();
+        println!("Dry run, nothing written");
+        return Ok(());
+    }
+
+    // Keep the pristine backup if one already exists
+    let backup_path = get_backup_path(path);
+    if create_backup && !backup_path.exists() {
+        println!();
+        println!("💾 Creating backup: {}", backup_path.display());
+        fs::copy(path, &backup_path).context("Failed to create backup")?;
+    }
+
+    fs::write(path, &data).context("Failed to write patched file")?;
+
+    println!();
+    println!("✅ Rewrote {} address string(s)", slots.len());
+    Ok(())
+}
+
 fn restore_backup(path: &Path) -> Result<()> {
     let backup_path = get_backup_path(path);
 
@@ -228,12 +365,13 @@ fn verify_patches(path: &Path) -> Result<()> {
     println!();
 
     let data = fs::read(path).context("Failed to read executable")?;
+    let pe = PeImage::parse(&data).context("Failed to parse PE headers")?;
 
     let mut verified = 0;
     for patch in PATCHES {
         print!("  • {} ... ", patch.name);
 
-        if is_patch_applied(&data, patch) {
+        if is_patch_applied(&data, &pe, patch) {
             println!("✓ Applied");
             verified += 1;
         } else {
@@ -248,6 +386,15 @@ fn verify_patches(path: &Path) -> Result<()> {
         println!("⚠️  {} of {} patches applied", verified, PATCHES.len());
     }
 
+    let stored = pe.stored_checksum(&data)?;
+    let computed = pe.compute_checksum(&data);
+    if stored != 0 && stored != computed {
+        println!(
+            "⚠️  PE checksum mismatch (stored 0x{:08X}, computed 0x{:08X}); use --fix-checksum",
+            stored, computed
+        );
+    }
+
     Ok(())
 }
 
@@ -256,7 +403,7 @@ fn list_patches() -> Result<()> {
     println!();
 
     for (i, patch) in PATCHES.iter().enumerate() {
-        println!("{}. {} (0x{:08X})", i + 1, patch.name, patch.offset);
+        println!("{}. {} (VA 0x{:08X})", i + 1, patch.name, patch.va);
         println!("   {}", patch.description);
         println!("   Original: {}", hex::encode(patch.original));
         println!("   Patched:  {}", hex::encode(patch.patched));
@@ -266,14 +413,15 @@ fn list_patches() -> Result<()> {
     Ok(())
 }
 
-fn apply_patch(data: &mut [u8], patch: &Patch) -> Result<bool> {
-    let end = patch.offset + patch.original.len();
+fn apply_patch(data: &mut [u8], pe: &PeImage, patch: &Patch) -> Result<bool> {
+    let offset = pe.code_va_to_offset(patch.va)?;
+    let end = offset + patch.original.len();
 
     if end > data.len() {
         bail!("Offset out of bounds");
     }
 
-    let current = &data[patch.offset..end];
+    let current = &data[offset..end];
 
     // Check if already patched
     if current == patch.patched {
@@ -290,19 +438,22 @@ fn apply_patch(data: &mut [u8], patch: &Patch) -> Result<bool> {
     }
 
     // Apply patch
-    data[patch.offset..end].copy_from_slice(patch.patched);
+    data[offset..end].copy_from_slice(patch.patched);
 
     Ok(true)
 }
 
-fn is_patch_applied(data: &[u8], patch: &Patch) -> bool {
-    let end = patch.offset + patch.patched.len();
+fn is_patch_applied(data: &[u8], pe: &PeImage, patch: &Patch) -> bool {
+    let Ok(offset) = pe.code_va_to_offset(patch.va) else {
+        return false;
+    };
+    let end = offset + patch.patched.len();
 
     if end > data.len() {
         return false;
     }
 
-    &data[patch.offset..end] == patch.patched
+    &data[offset..end] == patch.patched
 }
 
 fn calculate_checksum(data: &[u8]) -> String {
@@ -0,0 +1,323 @@
+//! Minimal PE (Portable Executable) parser
+//!
+//! Just enough of the PE format to map virtual addresses from Ghidra to
+//! file offsets, check which section a patch lands in, and recompute the
+//! optional header checksum after patching.
+
+use anyhow::{Result, bail};
+use std::ops::Range;
+
+/// `IMAGE_SCN_CNT_CODE`
+const SCN_CNT_CODE: u32 = 0x0000_0020;
+
+/// `IMAGE_SCN_MEM_EXECUTE`
+const SCN_MEM_EXECUTE: u32 = 0x2000_0000;
+
+/// PE32 optional header magic
+const PE32_MAGIC: u16 = 0x10b;
+
+/// PE32+ optional header magic
+const PE32_PLUS_MAGIC: u16 = 0x20b;
+
+/// A section table entry
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Section {
+    /// Section name (e.g. `.text`)
+    pub name: String,
+
+    /// RVA of the section once loaded
+    pub virtual_address: u32,
+
+    /// Size of the section in memory
+    pub virtual_size: u32,
+
+    /// File offset of the section's raw data
+    pub raw_offset: u32,
+
+    /// Size of the section's raw data in the file
+    pub raw_size: u32,
+
+    /// `IMAGE_SCN_*` flags
+    pub characteristics: u32,
+}
+
+impl Section {
+    /// Whether the section contains executable code
+    pub fn is_executable(&self) -> bool {
+        self.characteristics & (SCN_MEM_EXECUTE | SCN_CNT_CODE) != 0
+    }
+
+    fn contains_rva(&self, rva: u32) -> bool {
+        let size = self.virtual_size.max(self.raw_size);
+        rva >= self.virtual_address && rva - self.virtual_address < size
+    }
+}
+
+/// Parsed PE headers
+#[derive(Debug, Clone)]
+pub struct PeImage {
+    /// Preferred load address
+    pub image_base: u64,
+
+    /// File offset of the optional header's `CheckSum` field
+    pub checksum_offset: usize,
+
+    /// Section table
+    pub sections: Vec<Section>,
+}
+
+impl PeImage {
+    /// Parse the headers of a PE file
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        if data.len() < 0x40 || &data[0..2] != b"MZ" {
+            bail!("Not a PE file (missing MZ header)");
+        }
+
+        let pe_offset = read_u32(data, 0x3C)? as usize;
+        if data.get(pe_offset..pe_offset + 4) != Some(b"PE\0\0".as_slice()) {
+            bail!("Invalid PE signature at 0x{:X}", pe_offset);
+        }
+
+        let coff = pe_offset + 4;
+        let section_count = read_u16(data, coff + 2)? as usize;
+        let optional_size = read_u16(data, coff + 16)? as usize;
+
+        let optional = coff + 20;
+        let image_base = match read_u16(data, optional)? {
+            PE32_MAGIC => read_u32(data, optional + 28)? as u64,
+            PE32_PLUS_MAGIC => read_u64(data, optional + 24)?,
+            magic => bail!("Unknown optional header magic 0x{:X}", magic),
+        };
+        let checksum_offset = optional + 64;
+
+        let table = optional + optional_size;
+        let mut sections = Vec::with_capacity(section_count);
+        for i in 0..section_count {
+            let entry = table + i * 40;
+            let raw_name = data
+                .get(entry..entry + 8)
+                .ok_or_else(|| anyhow::anyhow!("Section table truncated"))?;
+            let name = String::from_utf8_lossy(raw_name)
+                .trim_end_matches('\0')
+                .to_string();
+
+            sections.push(Section {
+                name,
+                virtual_size: read_u32(data, entry + 8)?,
+                virtual_address: read_u32(data, entry + 12)?,
+                raw_size: read_u32(data, entry + 16)?,
+                raw_offset: read_u32(data, entry + 20)?,
+                characteristics: read_u32(data, entry + 36)?,
+            });
+        }
+
+        Ok(Self {
+            image_base,
+            checksum_offset,
+            sections,
+        })
+    }
+
+    /// Find the section containing a virtual address
+    pub fn section_for_va(&self, va: u64) -> Option<&Section> {
+        let rva = u32::try_from(va.checked_sub(self.image_base)?).ok()?;
+        self.sections.iter().find(|s| s.contains_rva(rva))
+    }
+
+    /// Map a virtual address to a file offset
+    pub fn va_to_offset(&self, va: u64) -> Result<usize> {
+        let section = self
+            .section_for_va(va)
+            .ok_or_else(|| anyhow::anyhow!("VA 0x{:08X} is not inside any section", va))?;
+
+        let delta = (va - self.image_base) as u32 - section.virtual_address;
+        if delta >= section.raw_size {
+            bail!(
+                "VA 0x{:08X} is in the uninitialized part of {}",
+                va,
+                section.name
+            );
+        }
+
+        Ok((section.raw_offset + delta) as usize)
+    }
+
+    /// Map a VA to a file offset, requiring an executable section
+    pub fn code_va_to_offset(&self, va: u64) -> Result<usize> {
+        let offset = self.va_to_offset(va)?;
+        // va_to_offset succeeded, so the section exists
+        let section = self.section_for_va(va).expect("section resolved above");
+        if !section.is_executable() {
+            bail!(
+                "VA 0x{:08X} is in non-executable section {}",
+                va,
+                section.name
+            );
+        }
+        Ok(offset)
+    }
+
+    /// File ranges of all non-executable sections (where literals live)
+    pub fn data_ranges(&self) -> Vec<Range<usize>> {
+        self.sections
+            .iter()
+            .filter(|s| !s.is_executable())
+            .map(|s| s.raw_offset as usize..(s.raw_offset + s.raw_size) as usize)
+            .collect()
+    }
+
+    /// Read the checksum currently stored in the optional header
+    pub fn stored_checksum(&self, data: &[u8]) -> Result<u32> {
+        read_u32(data, self.checksum_offset)
+    }
+
+    /// Compute the PE checksum (same algorithm as `CheckSumMappedFile`)
+    pub fn compute_checksum(&self, data: &[u8]) -> u32 {
+        let mut sum: u64 = 0;
+        let skip = self.checksum_offset..self.checksum_offset + 4;
+
+        for (i, chunk) in data.chunks(2).enumerate() {
+            let offset = i * 2;
+            if skip.contains(&offset) {
+                continue;
+            }
+            let word = match chunk {
+                [lo, hi] => u16::from_le_bytes([*lo, *hi]),
+                [lo] => *lo as u16,
+                _ => unreachable!(),
+            };
+            sum += word as u64;
+            sum = (sum & 0xFFFF) + (sum >> 16);
+        }
+
+        sum = (sum & 0xFFFF) + (sum >> 16);
+        (sum as u32).wrapping_add(data.len() as u32)
+    }
+
+    /// Recompute and store the checksum, returning the new value
+    pub fn fix_checksum(&self, data: &mut [u8]) -> u32 {
+        let checksum = self.compute_checksum(data);
+        data[self.checksum_offset..self.checksum_offset + 4]
+            .copy_from_slice(&checksum.to_le_bytes());
+        checksum
+    }
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Result<u16> {
+    data.get(offset..offset + 2)
+        .map(|b| u16::from_le_bytes([b[0], b[1]]))
+        .ok_or_else(|| anyhow::anyhow!("PE header truncated at 0x{:X}", offset))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Result<u32> {
+    data.get(offset..offset + 4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .ok_or_else(|| anyhow::anyhow!("PE header truncated at 0x{:X}", offset))
+}
+
+fn read_u64(data: &[u8], offset: usize) -> Result<u64> {
+    data.get(offset..offset + 8)
+        .map(|b| u64::from_le_bytes(b.try_into().unwrap()))
+        .ok_or_else(|| anyhow::anyhow!("PE header truncated at 0x{:X}", offset))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Build a tiny PE32 image with a `.text` and a `.data` section
+    ///
+    /// Layout mirrors Rag2.exe: image base 0x400000, `.text` at RVA 0x1000
+    /// stored at file offset 0x400.
+    fn build_test_pe() -> Vec<u8> {
+        let mut data = vec![0u8; 0x800];
+        data[0..2].copy_from_slice(b"MZ");
+        data[0x3C..0x40].copy_from_slice(&0x80u32.to_le_bytes());
+
+        let pe = 0x80;
+        data[pe..pe + 4].copy_from_slice(b"PE\0\0");
+        let coff = pe + 4;
+        data[coff..coff + 2].copy_from_slice(&0x14Cu16.to_le_bytes()); // i386
+        data[coff + 2..coff + 4].copy_from_slice(&2u16.to_le_bytes());
+        data[coff + 16..coff + 18].copy_from_slice(&0xE0u16.to_le_bytes());
+
+        let opt = coff + 20;
+        data[opt..opt + 2].copy_from_slice(&PE32_MAGIC.to_le_bytes());
+        data[opt + 28..opt + 32].copy_from_slice(&0x0040_0000u32.to_le_bytes());
+
+        let table = opt + 0xE0;
+        let sections = [
+            Section {
+                name: String::from(".text"),
+                virtual_address: 0x1000,
+                virtual_size: 0x200,
+                raw_offset: 0x400,
+                raw_size: 0x200,
+                characteristics: SCN_CNT_CODE | SCN_MEM_EXECUTE,
+            },
+            Section {
+                name: String::from(".data"),
+                virtual_address: 0x2000,
+                virtual_size: 0x200,
+                raw_offset: 0x600,
+                raw_size: 0x200,
+                characteristics: 0xC000_0040,
+            },
+        ];
+        for (i, section) in sections.iter().enumerate() {
+            let e = table + i * 40;
+            data[e..e + section.name.len()].copy_from_slice(section.name.as_bytes());
+            data[e + 8..e + 12].copy_from_slice(&section.virtual_size.to_le_bytes());
+            data[e + 12..e + 16].copy_from_slice(&section.virtual_address.to_le_bytes());
+            data[e + 16..e + 20].copy_from_slice(&section.raw_size.to_le_bytes());
+            data[e + 20..e + 24].copy_from_slice(&section.raw_offset.to_le_bytes());
+            data[e + 36..e + 40].copy_from_slice(&section.characteristics.to_le_bytes());
+        }
+
+        data
+    }
+
+    #[test]
+    fn test_parse_sections() {
+        let pe = PeImage::parse(&build_test_pe()).unwrap();
+
+        assert_eq!(pe.image_base, 0x400000);
+        assert_eq!(pe.sections.len(), 2);
+        assert_eq!(pe.sections[0].name, ".text");
+        assert!(pe.sections[0].is_executable());
+        assert!(!pe.sections[1].is_executable());
+        assert_eq!(pe.data_ranges(), vec![0x600..0x800]);
+    }
+
+    #[test]
+    fn test_va_mapping() {
+        let pe = PeImage::parse(&build_test_pe()).unwrap();
+
+        assert_eq!(pe.va_to_offset(0x401010).unwrap(), 0x410);
+        assert_eq!(pe.code_va_to_offset(0x401010).unwrap(), 0x410);
+        assert_eq!(pe.va_to_offset(0x402004).unwrap(), 0x604);
+        assert!(pe.code_va_to_offset(0x402004).is_err());
+        assert!(pe.va_to_offset(0x500000).is_err());
+    }
+
+    #[test]
+    fn test_checksum_roundtrip() {
+        let mut data = build_test_pe();
+        let pe = PeImage::parse(&data).unwrap();
+
+        let checksum = pe.fix_checksum(&mut data);
+        assert_ne!(checksum, 0);
+        assert_eq!(pe.stored_checksum(&data).unwrap(), checksum);
+
+        // Checksum field is excluded, so recomputing gives the same value
+        assert_eq!(pe.compute_checksum(&data), checksum);
+
+        data[0x410] = 0x90;
+        assert_ne!(pe.compute_checksum(&data), checksum);
+    }
+
+    #[test]
+    fn test_reject_non_pe() {
+        assert!(PeImage::parse(b"not a pe file at all, definitely not").is_err());
+    }
+}
@@ -0,0 +1,106 @@
+//! Tick systems over a HashMap of boxed entities vs the struct-of-arrays
+//! `EntityStore`
+//!
+//! Run with `cargo bench -p ro2-world --bench entity_storage`.
+
+use criterion::{BenchmarkId, Criterion, black_box, criterion_group, criterion_main};
+use ro2_world::world::{Entity, EntityId, EntityKind, EntityStore, Position};
+use std::collections::HashMap;
+
+const SIZES: [u32; 3] = [1_000, 10_000, 50_000];
+
+fn monster(id: u32) -> Entity {
+    Entity {
+        id: EntityId(id),
+        kind: EntityKind::Monster,
+        position: Position::new((id % 500) as f32 * 10.0, (id / 500) as f32 * 10.0, 0.0),
+        direction: 0,
+        hp: id % 100,
+        max_hp: 100,
+        state: 0,
+    }
+}
+
+fn boxed(count: u32) -> HashMap<EntityId, Box<Entity>> {
+    (1..=count)
+        .map(|id| (EntityId(id), Box::new(monster(id))))
+        .collect()
+}
+
+fn store(count: u32) -> EntityStore {
+    let mut store = EntityStore::new();
+    for id in 1..=count {
+        store.insert(monster(id));
+    }
+    store
+}
+
+/// Walk every entity across the map, wrapping at the edge
+fn step(position: &mut Position) {
+    position.x = (position.x + 1.5) % 5000.0;
+    position.y = (position.y + 0.5) % 5000.0;
+}
+
+fn regen(hp: &mut u32, max_hp: u32) {
+    *hp = (*hp + 1).min(max_hp);
+}
+
+fn movement(c: &mut Criterion) {
+    let mut group = c.benchmark_group("movement");
+    for count in SIZES {
+        let mut entities = boxed(count);
+        group.bench_with_input(BenchmarkId::new("hashmap_boxed", count), &count, |b, _| {
+            b.iter(|| {
+                for entity in entities.values_mut() {
+                    step(&mut entity.position);
+                }
+                black_box(&entities);
+            })
+        });
+
+        let mut entities = store(count);
+        group.bench_with_input(BenchmarkId::new("soa", count), &count, |b, _| {
+            b.iter(|| {
+                for position in entities.positions_mut() {
+                    step(position);
+                }
+                black_box(&entities);
+            })
+        });
+    }
+    group.finish();
+}
+
+fn movement_and_regen(c: &mut Criterion) {
+    let mut group = c.benchmark_group("movement_and_regen");
+    for count in SIZES {
+        let mut entities = boxed(count);
+        group.bench_with_input(BenchmarkId::new("hashmap_boxed", count), &count, |b, _| {
+            b.iter(|| {
+                for entity in entities.values_mut() {
+                    step(&mut entity.position);
+                    regen(&mut entity.hp, entity.max_hp);
+                }
+                black_box(&entities);
+            })
+        });
+
+        let mut entities = store(count);
+        group.bench_with_input(BenchmarkId::new("soa", count), &count, |b, _| {
+            b.iter(|| {
+                let columns = entities.columns_mut();
+                for position in columns.positions.iter_mut() {
+                    step(position);
+                }
+                for (hp, &max_hp) in columns.hp.iter_mut().zip(columns.max_hp.iter()) {
+                    regen(hp, max_hp);
+                }
+                black_box(&entities);
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, movement, movement_and_regen);
+criterion_main!(benches);
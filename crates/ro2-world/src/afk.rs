@@ -0,0 +1,385 @@
+//! Idle (AFK) detection
+//!
+//! A player who doesn't move or act for a long time still holds a place in
+//! their zone and in the population the channel list shows. [`AfkMonitor`]
+//! tracks when each session in the world last did something: handlers
+//! call [`AfkMonitor::activity`] on movement and actions, while heartbeats
+//! and chat don't count. A player idle for nearly `idle_minutes` is warned
+//! by system message; still idle once they're up, they're sent back to
+//! character select or disconnected. Either way the monitor takes them
+//! out of their zone itself, so they stop counting towards its population
+//! on the next tick rather than whenever their connection goes.
+//!
+//! Set in the `[afk]` section of `config/world.toml`:
+//!
+//! ```toml
+//! [afk]
+//! idle_minutes = 15                # 0 turns AFK detection off
+//! warn_minutes = 1                 # Warn this long before acting
+//! action = "character_select"      # or "disconnect"
+//! ```
+
+use crate::handlers::system::build_system_message;
+use crate::world::ZoneHandle;
+use anyhow::{Result, anyhow};
+use ro2_common::clock::Clock;
+use ro2_common::config::{self, Validate};
+use ro2_common::localization::Localization;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
+use tracing::{debug, info};
+
+/// How often idle sessions are looked for
+pub const CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// What happens to a player idle for too long
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AfkAction {
+    /// Back to character select, still logged in
+    #[default]
+    CharacterSelect,
+    Disconnect,
+}
+
+impl AfkAction {
+    fn warning_key(&self) -> &'static str {
+        match self {
+            Self::CharacterSelect => "afk.warn_character_select",
+            Self::Disconnect => "afk.warn_disconnect",
+        }
+    }
+}
+
+/// AFK detection settings
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct AfkConfig {
+    pub idle_minutes: u32,
+    pub warn_minutes: u32,
+    pub action: AfkAction,
+}
+
+impl Default for AfkConfig {
+    fn default() -> Self {
+        Self {
+            idle_minutes: 15,
+            warn_minutes: 1,
+            action: AfkAction::CharacterSelect,
+        }
+    }
+}
+
+impl AfkConfig {
+    /// Read the `[afk]` section of `path`; missing keys keep their
+    /// defaults
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        config::section(path, "afk")
+    }
+
+    /// Parse the `[afk]` section of TOML text
+    pub fn from_toml(text: &str) -> Result<Self> {
+        config::section_from_toml(text, "afk")
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.idle_minutes > 0
+    }
+
+    /// Idle time before the player is moved out
+    pub fn idle(&self) -> Duration {
+        Duration::from_secs(self.idle_minutes as u64 * 60)
+    }
+
+    /// Idle time before the player is warned
+    pub fn warn_after(&self) -> Duration {
+        self.idle()
+            .saturating_sub(Duration::from_secs(self.warn_minutes as u64 * 60))
+    }
+}
+
+impl Validate for AfkConfig {
+    fn validate(&self) -> Result<()> {
+        if self.enabled() && self.warn_minutes >= self.idle_minutes {
+            return Err(anyhow!("afk warn_minutes must be under idle_minutes"));
+        }
+        Ok(())
+    }
+}
+
+struct Session {
+    last_active: Instant,
+    warned: bool,
+    language: String,
+    zone: Option<ZoneHandle>,
+    outbox: mpsc::Sender<Vec<u8>>,
+    idle: Option<oneshot::Sender<AfkAction>>,
+}
+
+impl Session {
+    fn send(&self, text: &str) {
+        // A full or closed outbox means the client is going away anyway
+        let _ = self.outbox.try_send(build_system_message(text));
+    }
+}
+
+/// When every session in the world last did something
+///
+/// Cheap to clone; every clone tracks the same sessions.
+#[derive(Clone)]
+pub struct AfkMonitor {
+    config: AfkConfig,
+    localization: Arc<Localization>,
+    clock: Clock,
+    sessions: Arc<Mutex<HashMap<u64, Session>>>,
+}
+
+impl AfkMonitor {
+    pub fn new(config: AfkConfig, localization: Arc<Localization>) -> Self {
+        Self {
+            config,
+            localization,
+            clock: Clock::system(),
+            sessions: Arc::default(),
+        }
+    }
+
+    /// Tell the time by `clock`
+    pub fn with_clock(mut self, clock: Clock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Sessions being tracked
+    pub fn len(&self) -> usize {
+        self.sessions.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sessions.lock().unwrap().is_empty()
+    }
+
+    /// Start tracking a session that entered the world, active as of now
+    ///
+    /// Warnings go to `outbox` in `language`. If the session idles out,
+    /// it's taken out of `zone` and what's to become of it is sent on
+    /// `idle` for the connection to carry out. Nothing is tracked with
+    /// AFK detection off.
+    pub fn enter(
+        &self,
+        session_id: u64,
+        language: Option<&str>,
+        zone: Option<ZoneHandle>,
+        outbox: mpsc::Sender<Vec<u8>>,
+        idle: oneshot::Sender<AfkAction>,
+    ) {
+        if !self.config.enabled() {
+            return;
+        }
+        let session = Session {
+            last_active: self.clock.instant(),
+            warned: false,
+            language: self.localization.resolve(language).to_string(),
+            zone,
+            outbox,
+            idle: Some(idle),
+        };
+        self.sessions.lock().unwrap().insert(session_id, session);
+    }
+
+    /// Record that a session moved to another zone
+    pub fn set_zone(&self, session_id: u64, zone: Option<ZoneHandle>) {
+        if let Some(session) = self.sessions.lock().unwrap().get_mut(&session_id) {
+            session.zone = zone;
+        }
+    }
+
+    /// The session moved or acted
+    pub fn activity(&self, session_id: u64) {
+        if let Some(session) = self.sessions.lock().unwrap().get_mut(&session_id) {
+            session.last_active = self.clock.instant();
+            session.warned = false;
+        }
+    }
+
+    pub fn leave(&self, session_id: u64) {
+        self.sessions.lock().unwrap().remove(&session_id);
+    }
+
+    /// Warn sessions about to idle out and move out the ones that have;
+    /// returns the sessions moved out
+    pub async fn check(&self) -> Vec<u64> {
+        let now = self.clock.instant();
+        let mut idled = Vec::new();
+        {
+            let mut sessions = self.sessions.lock().unwrap();
+            for (&session_id, session) in sessions.iter_mut() {
+                let idle = now.saturating_duration_since(session.last_active);
+                if idle >= self.config.idle() {
+                    session.send(
+                        self.localization
+                            .get(&session.language, "afk.idle_too_long"),
+                    );
+                    if let Some(notify) = session.idle.take() {
+                        let _ = notify.send(self.config.action);
+                    }
+                    idled.push(session_id);
+                } else if idle >= self.config.warn_after() && !session.warned {
+                    session.warned = true;
+                    session.send(
+                        self.localization
+                            .get(&session.language, self.config.action.warning_key()),
+                    );
+                }
+            }
+        }
+
+        let mut moved = Vec::new();
+        for session_id in idled {
+            let Some(session) = self.sessions.lock().unwrap().remove(&session_id) else {
+                continue;
+            };
+            info!(
+                "Session {} idle for {} minutes: {:?}",
+                session_id, self.config.idle_minutes, self.config.action
+            );
+            if let Some(zone) = session.zone
+                && let Err(e) = zone.unsubscribe(session_id).await
+            {
+                debug!("Session {} idled out of a stopped zone: {}", session_id, e);
+            }
+            moved.push(session_id);
+        }
+        moved
+    }
+
+    /// Check every [`CHECK_INTERVAL`] on a task of its own; does nothing
+    /// with AFK detection off
+    pub fn spawn(&self) -> Option<JoinHandle<()>> {
+        if !self.config.enabled() {
+            return None;
+        }
+        let monitor = self.clone();
+        Some(tokio::spawn(async move {
+            let mut interval = tokio::time::interval(CHECK_INTERVAL);
+            loop {
+                interval.tick().await;
+                monitor.check().await;
+            }
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::{EntityKind, Position, World, Zone, ZoneId};
+    use chrono::DateTime;
+
+    fn text(message: &[u8]) -> String {
+        ro2_common::wire::read_utf16(&message[2..]).unwrap().0
+    }
+
+    fn monitor(config: AfkConfig) -> (AfkMonitor, Clock) {
+        let mut localization = Localization::builtin();
+        localization
+            .add_table(
+                "en",
+                "[afk]\nwarn_character_select = \"to select\"\n\
+                 warn_disconnect = \"to disconnect\"\nidle_too_long = \"idle\"",
+            )
+            .unwrap();
+        let clock = Clock::manual(DateTime::from_timestamp(1_700_000_000, 0).unwrap());
+        let monitor = AfkMonitor::new(config, Arc::new(localization)).with_clock(clock.clone());
+        (monitor, clock)
+    }
+
+    fn minutes(minutes: u64) -> Duration {
+        Duration::from_secs(minutes * 60)
+    }
+
+    #[tokio::test]
+    async fn test_warn_then_character_select() {
+        let mut world = World::new(Duration::from_millis(5));
+        let zone = world.start_zone(ZoneId(1), Zone::new());
+        let player = zone
+            .spawn(EntityKind::Player, Position::default(), 100)
+            .await
+            .unwrap();
+        let (updates, _updates) = mpsc::channel(64);
+        zone.subscribe(1, player, updates).await.unwrap();
+
+        let (monitor, clock) = monitor(AfkConfig::default());
+        let (outbox, mut messages) = mpsc::channel(8);
+        let (idle, mut idled) = oneshot::channel();
+        monitor.enter(1, None, Some(zone.clone()), outbox, idle);
+
+        clock.advance(minutes(10));
+        assert!(monitor.check().await.is_empty());
+        assert!(messages.try_recv().is_err());
+
+        // Moving puts the clock back
+        monitor.activity(1);
+        clock.advance(minutes(14));
+        monitor.check().await;
+        assert_eq!(text(&messages.try_recv().unwrap()), "to select");
+        monitor.check().await;
+        assert!(messages.try_recv().is_err());
+
+        clock.advance(minutes(1));
+        assert_eq!(monitor.check().await, [1]);
+        assert_eq!(text(&messages.try_recv().unwrap()), "idle");
+        assert_eq!(idled.try_recv().unwrap(), AfkAction::CharacterSelect);
+        assert!(monitor.is_empty());
+
+        // Its place in the zone is freed
+        tokio::time::timeout(Duration::from_secs(1), async {
+            while zone.tick_stats().clients > 0 {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .expect("session still in its zone");
+    }
+
+    #[tokio::test]
+    async fn test_disconnect_and_disabled() {
+        let config =
+            AfkConfig::from_toml("[afk]\nidle_minutes = 5\naction = \"disconnect\"").unwrap();
+        let (monitor, clock) = monitor(config);
+        let (outbox, mut messages) = mpsc::channel(8);
+        let (idle, mut idled) = oneshot::channel();
+        monitor.enter(1, None, None, outbox, idle);
+
+        // Skipping the warning goes straight to the action
+        clock.advance(minutes(5));
+        assert_eq!(monitor.check().await, [1]);
+        assert_eq!(text(&messages.try_recv().unwrap()), "idle");
+        assert_eq!(idled.try_recv().unwrap(), AfkAction::Disconnect);
+
+        let off = AfkConfig::from_toml("[afk]\nidle_minutes = 0").unwrap();
+        let (monitor, _) = self::monitor(off);
+        let (outbox, _messages) = mpsc::channel(8);
+        let (idle, _idled) = oneshot::channel();
+        monitor.enter(1, None, None, outbox, idle);
+        assert!(monitor.is_empty());
+        assert!(monitor.spawn().is_none());
+    }
+
+    #[test]
+    fn test_config() {
+        let config = AfkConfig::default();
+        assert_eq!(config.warn_after(), minutes(14));
+        assert!(AfkConfig::from_toml("[afk]\nidle_minutes = 1\nwarn_minutes = 1").is_err());
+        assert!(AfkConfig::from_toml("[afk]\naction = \"explode\"").is_err());
+        assert_eq!(
+            AfkConfig::load("does/not/exist.toml").unwrap(),
+            AfkConfig::default()
+        );
+    }
+}
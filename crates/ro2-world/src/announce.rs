@@ -0,0 +1,469 @@
+//! Sending system messages (0x1001) from the server
+//!
+//! [`SystemMessenger`] knows every session in the world, which zone it's
+//! in and what language it reads, and sends system messages to one
+//! player, a zone or everyone. Text is looked up in the [`Localization`]
+//! per recipient, so the same announcement goes out in each player's
+//! language; text that isn't a localization key is sent as is.
+//!
+//! GMs send messages with [`parse_command`]; recurring announcements are
+//! configured in `config/world.toml`:
+//!
+//! ```toml
+//! [[announcements]]
+//! message = "announce.vote"    # a localization key, or plain text
+//! interval_secs = 3600
+//!
+//! [[announcements]]
+//! message = "The Prontera event starts soon!"
+//! interval_secs = 600
+//! zone = 1                     # only this zone; everyone if omitted
+//! ```
+//!
+//! Countdowns to a scheduled restart go to everyone too (see
+//! [`warn_of_maintenance`]).
+
+use crate::handlers::system::build_system_message;
+use crate::world::ZoneId;
+use anyhow::{Context, Result, anyhow};
+use ro2_common::config::{self, Validate};
+use ro2_common::events::{EventBus, Maintenance};
+use ro2_common::localization::Localization;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tracing::{debug, info};
+
+/// Who a system message goes to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    Player(u64),
+    Zone(ZoneId),
+    All,
+}
+
+struct Recipient {
+    zone: Option<ZoneId>,
+    language: String,
+    outbox: mpsc::Sender<Vec<u8>>,
+}
+
+/// Sends system messages to sessions in the world
+///
+/// Cheap to clone; every clone sends to the same sessions.
+#[derive(Clone)]
+pub struct SystemMessenger {
+    localization: Arc<Localization>,
+    recipients: Arc<RwLock<HashMap<u64, Recipient>>>,
+}
+
+impl SystemMessenger {
+    pub fn new(localization: Arc<Localization>) -> Self {
+        Self {
+            localization,
+            recipients: Arc::default(),
+        }
+    }
+
+    /// Send session `session_id` messages in `language` (or the default
+    /// language if it has no table) through `outbox`
+    pub fn register(
+        &self,
+        session_id: u64,
+        zone: Option<ZoneId>,
+        language: Option<&str>,
+        outbox: mpsc::Sender<Vec<u8>>,
+    ) {
+        let language = self.localization.resolve(language).to_string();
+        self.recipients.write().unwrap().insert(
+            session_id,
+            Recipient {
+                zone,
+                language,
+                outbox,
+            },
+        );
+    }
+
+    /// Record that a session moved to another zone
+    pub fn set_zone(&self, session_id: u64, zone: Option<ZoneId>) {
+        if let Some(recipient) = self.recipients.write().unwrap().get_mut(&session_id) {
+            recipient.zone = zone;
+        }
+    }
+
+    pub fn unregister(&self, session_id: u64) {
+        self.recipients.write().unwrap().remove(&session_id);
+    }
+
+    /// Sessions that can be sent messages
+    pub fn len(&self) -> usize {
+        self.recipients.read().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.recipients.read().unwrap().is_empty()
+    }
+
+    /// Send `message` (a localization key or plain text) to `target`;
+    /// returns how many sessions it was sent to
+    pub fn send(&self, target: Target, message: &str) -> usize {
+        self.send_with(target, message, &[])
+    }
+
+    /// Send localized `key` with its `{name}` placeholders filled in
+    pub fn send_with(
+        &self,
+        target: Target,
+        key: &str,
+        args: &[(&str, &dyn std::fmt::Display)],
+    ) -> usize {
+        let recipients = self.recipients.read().unwrap();
+        let selected: Vec<_> = match target {
+            Target::Player(session_id) => recipients.get(&session_id).into_iter().collect(),
+            Target::Zone(zone) => recipients
+                .values()
+                .filter(|recipient| recipient.zone == Some(zone))
+                .collect(),
+            Target::All => recipients.values().collect(),
+        };
+
+        // One message per language rather than per recipient
+        let mut messages: HashMap<&str, Vec<u8>> = HashMap::new();
+        let mut sent = 0;
+        for recipient in selected {
+            let message = messages
+                .entry(recipient.language.as_str())
+                .or_insert_with(|| {
+                    build_system_message(&self.localization.format(&recipient.language, key, args))
+                });
+            // A full outbox drops the message rather than stall the sender
+            if recipient.outbox.try_send(message.clone()).is_ok() {
+                sent += 1;
+            }
+        }
+        debug!("System message {:?} to {:?}: {} sent", key, target, sent);
+        sent
+    }
+}
+
+/// Discord's `!online` and `!broadcast` reach everyone in the world
+#[cfg(feature = "discord")]
+impl ro2_common::discord::BotActions for SystemMessenger {
+    fn online(&self) -> usize {
+        self.len()
+    }
+
+    fn broadcast(&self, text: &str) -> usize {
+        self.send(Target::All, text)
+    }
+}
+
+/// Parse a GM broadcast command:
+///
+/// ```text
+/// /notice all <message>
+/// /notice zone <zone id> <message>
+/// /notice player <session id> <message>
+/// ```
+pub fn parse_command(line: &str) -> Result<(Target, &str)> {
+    const USAGE: &str = "usage: /notice all|zone <id>|player <session> <message>";
+
+    let rest = line
+        .trim()
+        .strip_prefix("/notice")
+        .ok_or_else(|| anyhow!("not a /notice command"))?;
+    let (scope, rest) = split_word(rest).ok_or_else(|| anyhow!(USAGE))?;
+    let (target, message) = match scope {
+        "all" => (Target::All, rest),
+        "zone" | "player" => {
+            let (id, message) = split_word(rest).ok_or_else(|| anyhow!(USAGE))?;
+            let target = if scope == "zone" {
+                Target::Zone(ZoneId(id.parse().context("bad zone id")?))
+            } else {
+                Target::Player(id.parse().context("bad session id")?)
+            };
+            (target, message)
+        }
+        _ => return Err(anyhow!(USAGE)),
+    };
+
+    let message = message.trim();
+    if message.is_empty() {
+        return Err(anyhow!(USAGE));
+    }
+    Ok((target, message))
+}
+
+/// The first word of `text` and what follows it
+fn split_word(text: &str) -> Option<(&str, &str)> {
+    let text = text.trim_start();
+    if text.is_empty() {
+        return None;
+    }
+    Some(text.split_once(char::is_whitespace).unwrap_or((text, "")))
+}
+
+/// A message sent on a timer
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Announcement {
+    /// Localization key or plain text
+    pub message: String,
+    pub interval_secs: u64,
+    /// Only this zone; everyone when unset
+    #[serde(default)]
+    pub zone: Option<u32>,
+}
+
+impl Announcement {
+    pub fn target(&self) -> Target {
+        self.zone
+            .map_or(Target::All, |zone| Target::Zone(ZoneId(zone)))
+    }
+}
+
+#[derive(Default, Deserialize)]
+#[serde(transparent)]
+struct Announcements(Vec<Announcement>);
+
+impl Validate for Announcements {
+    fn validate(&self) -> Result<()> {
+        if let Some(bad) = self.0.iter().find(|a| a.interval_secs == 0) {
+            return Err(anyhow!("announcement {:?} has no interval", bad.message));
+        }
+        Ok(())
+    }
+}
+
+/// Read the `[[announcements]]` in `path`; a missing file has none
+pub fn load_announcements(path: impl AsRef<Path>) -> Result<Vec<Announcement>> {
+    config::section(path, "announcements").map(|Announcements(all)| all)
+}
+
+/// Parse the `[[announcements]]` in TOML `text`
+pub fn announcements_from_toml(text: &str) -> Result<Vec<Announcement>> {
+    config::section_from_toml(text, "announcements").map(|Announcements(all)| all)
+}
+
+/// Send each announcement every `interval_secs`, first after one interval
+pub fn schedule(
+    messenger: &SystemMessenger,
+    announcements: Vec<Announcement>,
+) -> Vec<JoinHandle<()>> {
+    announcements
+        .into_iter()
+        .map(|announcement| {
+            let messenger = messenger.clone();
+            let every = Duration::from_secs(announcement.interval_secs);
+            info!(
+                "Announcing {:?} to {:?} every {:?}",
+                announcement.message,
+                announcement.target(),
+                every
+            );
+            tokio::spawn(async move {
+                let mut interval =
+                    tokio::time::interval_at(tokio::time::Instant::now() + every, every);
+                loop {
+                    interval.tick().await;
+                    messenger.send(announcement.target(), &announcement.message);
+                }
+            })
+        })
+        .collect()
+}
+
+/// Tell everyone how long until each [`Maintenance::Warning`] on
+/// `events`'s restart, until the bus closes
+pub fn warn_of_maintenance(messenger: &SystemMessenger, events: &EventBus) -> JoinHandle<()> {
+    let messenger = messenger.clone();
+    let mut steps = events.subscribe::<Maintenance>();
+    tokio::spawn(async move {
+        while let Some(step) = steps.recv().await {
+            if let Maintenance::Warning { secs } = step {
+                send_maintenance_warning(&messenger, secs);
+            }
+        }
+    })
+}
+
+fn send_maintenance_warning(messenger: &SystemMessenger, secs: u64) -> usize {
+    match secs {
+        ..60 => messenger.send_with(
+            Target::All,
+            "maintenance.restart_in_seconds",
+            &[("seconds", &secs)],
+        ),
+        _ => messenger.send_with(
+            Target::All,
+            "maintenance.restart_in_minutes",
+            &[("minutes", &(secs / 60))],
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(message: &[u8]) -> String {
+        ro2_common::wire::read_utf16(&message[2..]).unwrap().0
+    }
+
+    fn messenger() -> SystemMessenger {
+        let mut localization = Localization::builtin();
+        localization
+            .add_table("en", "[test]\ngreeting = \"Welcome, {name}!\"")
+            .unwrap();
+        localization
+            .add_table("ko", "[test]\ngreeting = \"{name}님, 환영합니다!\"")
+            .unwrap();
+        SystemMessenger::new(Arc::new(localization))
+    }
+
+    #[test]
+    fn test_targets_and_languages() {
+        let messenger = messenger();
+        let (zone1_en, mut zone1_en_rx) = mpsc::channel(8);
+        let (zone1_ko, mut zone1_ko_rx) = mpsc::channel(8);
+        let (zone2, mut zone2_rx) = mpsc::channel(8);
+        messenger.register(1, Some(ZoneId(1)), None, zone1_en);
+        messenger.register(2, Some(ZoneId(1)), Some("ko"), zone1_ko);
+        messenger.register(3, Some(ZoneId(2)), Some("fr"), zone2);
+
+        assert_eq!(
+            messenger.send_with(
+                Target::Zone(ZoneId(1)),
+                "test.greeting",
+                &[("name", &"Poring")]
+            ),
+            2
+        );
+        assert_eq!(text(&zone1_en_rx.try_recv().unwrap()), "Welcome, Poring!");
+        assert_eq!(
+            text(&zone1_ko_rx.try_recv().unwrap()),
+            "Poring님, 환영합니다!"
+        );
+        assert!(zone2_rx.try_recv().is_err());
+
+        // Plain text goes out as is
+        assert_eq!(
+            messenger.send(Target::Player(3), "Server restart in 5 minutes"),
+            1
+        );
+        assert_eq!(
+            text(&zone2_rx.try_recv().unwrap()),
+            "Server restart in 5 minutes"
+        );
+
+        messenger.set_zone(3, Some(ZoneId(1)));
+        messenger.unregister(1);
+        assert_eq!(messenger.send(Target::Zone(ZoneId(1)), "hi"), 2);
+        assert_eq!(messenger.send(Target::All, "hi"), 2);
+        assert_eq!(messenger.send(Target::Player(1), "hi"), 0);
+    }
+
+    #[test]
+    fn test_parse_command() {
+        assert_eq!(
+            parse_command("/notice all Server restart in 5 minutes").unwrap(),
+            (Target::All, "Server restart in 5 minutes")
+        );
+        assert_eq!(
+            parse_command("/notice zone 3 Boss spawned!").unwrap(),
+            (Target::Zone(ZoneId(3)), "Boss spawned!")
+        );
+        assert_eq!(
+            parse_command("/notice player 42 hello").unwrap(),
+            (Target::Player(42), "hello")
+        );
+        for bad in [
+            "/notice",
+            "/notice all",
+            "/notice zone x hi",
+            "/notice team hi",
+            "/kick 3",
+        ] {
+            assert!(parse_command(bad).is_err(), "{:?}", bad);
+        }
+    }
+
+    #[test]
+    fn test_announcements_config() {
+        let announcements = announcements_from_toml(
+            r#"
+            bind = "0.0.0.0:7401"
+
+            [[announcements]]
+            message = "announce.vote"
+            interval_secs = 3600
+
+            [[announcements]]
+            message = "Event soon!"
+            interval_secs = 600
+            zone = 1
+            "#,
+        )
+        .unwrap();
+        assert_eq!(announcements.len(), 2);
+        assert_eq!(announcements[0].target(), Target::All);
+        assert_eq!(announcements[1].target(), Target::Zone(ZoneId(1)));
+
+        assert!(
+            load_announcements("does/not/exist.toml")
+                .unwrap()
+                .is_empty()
+        );
+        assert!(
+            announcements_from_toml("[[announcements]]\nmessage = \"x\"\ninterval_secs = 0")
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_schedule() {
+        let messenger = messenger();
+        let (outbox, mut messages) = mpsc::channel(8);
+        messenger.register(1, None, None, outbox);
+
+        let tasks = schedule(
+            &messenger,
+            vec![Announcement {
+                message: "Remember to vote!".to_string(),
+                interval_secs: 1,
+                zone: None,
+            }],
+        );
+        let message = tokio::time::timeout(Duration::from_secs(5), messages.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(text(&message), "Remember to vote!");
+
+        for task in tasks {
+            task.abort();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_maintenance_warnings() {
+        let messenger = messenger();
+        let (outbox, mut messages) = mpsc::channel(8);
+        messenger.register(1, Some(ZoneId(4)), None, outbox);
+        let events = EventBus::new();
+        let task = warn_of_maintenance(&messenger, &events);
+
+        events.publish(Maintenance::Warning { secs: 600 });
+        events.publish(Maintenance::LoginsClosed);
+        events.publish(Maintenance::Warning { secs: 10 });
+        drop(events);
+        task.await.unwrap();
+        assert!(text(&messages.try_recv().unwrap()).contains("in 10 minutes"));
+        assert!(text(&messages.try_recv().unwrap()).contains("in 10 seconds"));
+        assert!(messages.try_recv().is_err());
+    }
+}
@@ -0,0 +1,785 @@
+//! Anti-bot challenges
+//!
+//! Anti-cheat checks report what they catch as [`Violation`]s; a session
+//! that racks up `threshold` of them within `window_secs` is sent a
+//! challenge a bot can't answer by replaying packets: a question in
+//! [`NFY_BOT_CHALLENGE`] and a system message. The player answers in the
+//! UI ([`REQ_BOT_CHALLENGE_ANSWER`]) or with `/answer <answer>` in chat
+//! within `answer_secs`. A wrong answer or no answer gets another
+//! challenge, until `attempts` have failed and the player is kicked. GMs
+//! can challenge a session by hand from the console.
+//!
+//! Every challenge's result is written to `bot_challenges` (see
+//! [`BotChallengeQueries`]) for GMs to review with `botcheck log`.
+//!
+//! What's asked is up to the [`ChallengeKind`]s registered; each challenge
+//! picks one at random. [`Arithmetic`] and [`ReverseWord`] are built in,
+//! and [`BotCheck::with_kinds`] replaces them, e.g. with one that asks
+//! about something on screen.
+//!
+//! Set in the `[bot_check]` section of `config/world.toml`:
+//!
+//! ```toml
+//! [bot_check]
+//! threshold = 5          # Violations before a challenge; 0 turns it off
+//! window_secs = 600      # that they're counted over
+//! answer_secs = 60       # To answer each challenge
+//! attempts = 3           # Failed challenges before a kick
+//! ```
+//!
+//! Like the other `0x3Fxx` opcodes, these are placeholders.
+
+use crate::handlers::system::build_system_message;
+use anyhow::{Result, anyhow};
+use rand::rngs::StdRng;
+use rand::{Rng, RngCore, SeedableRng};
+use ro2_common::clock::Clock;
+use ro2_common::config::{self, Validate};
+use ro2_common::database::BotChallengeEntry;
+use ro2_common::database::queries::BotChallengeQueries;
+use ro2_common::events::{EventBus, Violation};
+use ro2_common::localization::Localization;
+use ro2_common::wire::{WireReader, WireWriter};
+use serde::Deserialize;
+use sqlx::{Pool, Sqlite};
+use std::collections::{HashMap, VecDeque};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+pub use ro2_common::protocol::opcodes::{NFY_BOT_CHALLENGE, REQ_BOT_CHALLENGE_ANSWER};
+
+/// How often unanswered challenges are looked for
+pub const CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Anti-bot challenge settings
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct BotCheckConfig {
+    pub threshold: u32,
+    pub window_secs: u32,
+    pub answer_secs: u32,
+    pub attempts: u32,
+}
+
+impl Default for BotCheckConfig {
+    fn default() -> Self {
+        Self {
+            threshold: 5,
+            window_secs: 600,
+            answer_secs: 60,
+            attempts: 3,
+        }
+    }
+}
+
+impl BotCheckConfig {
+    /// Read the `[bot_check]` section of `path`; missing keys keep their
+    /// defaults
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        config::section(path, "bot_check")
+    }
+
+    /// Parse the `[bot_check]` section of TOML text
+    pub fn from_toml(text: &str) -> Result<Self> {
+        config::section_from_toml(text, "bot_check")
+    }
+
+    /// Whether violations set off challenges; GMs can challenge anyway
+    pub fn enabled(&self) -> bool {
+        self.threshold > 0
+    }
+
+    pub fn window(&self) -> Duration {
+        Duration::from_secs(self.window_secs as u64)
+    }
+
+    pub fn answer_time(&self) -> Duration {
+        Duration::from_secs(self.answer_secs as u64)
+    }
+}
+
+impl Validate for BotCheckConfig {
+    fn validate(&self) -> Result<()> {
+        if self.window_secs == 0 || self.answer_secs == 0 || self.attempts == 0 {
+            return Err(anyhow!(
+                "bot_check window_secs, answer_secs and attempts can't be 0"
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// A question and the answer expected
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Prompt {
+    pub question: String,
+    /// Compared without case or surrounding spaces
+    pub answer: String,
+}
+
+/// A kind of challenge
+pub trait ChallengeKind: Send + Sync {
+    /// Short name for the log, e.g. `arithmetic`
+    fn name(&self) -> &'static str;
+
+    fn generate(&self, rng: &mut dyn RngCore) -> Prompt;
+}
+
+/// "What is 7 plus 4?"
+pub struct Arithmetic;
+
+impl ChallengeKind for Arithmetic {
+    fn name(&self) -> &'static str {
+        "arithmetic"
+    }
+
+    fn generate(&self, rng: &mut dyn RngCore) -> Prompt {
+        let (a, b) = (rng.gen_range(2..20), rng.gen_range(2..20));
+        Prompt {
+            question: format!("What is {} plus {}?", a, b),
+            answer: (a + b).to_string(),
+        }
+    }
+}
+
+/// "Type the word poring backwards."
+pub struct ReverseWord;
+
+const WORDS: [&str; 8] = [
+    "poring", "prontera", "zeny", "knight", "sword", "potion", "archer", "dragon",
+];
+
+impl ChallengeKind for ReverseWord {
+    fn name(&self) -> &'static str {
+        "reverse_word"
+    }
+
+    fn generate(&self, rng: &mut dyn RngCore) -> Prompt {
+        let word = WORDS[rng.gen_range(0..WORDS.len())];
+        Prompt {
+            question: format!("Type the word {} backwards.", word),
+            answer: word.chars().rev().collect(),
+        }
+    }
+}
+
+/// Parse a player's `/answer <answer>`
+pub fn parse_command(line: &str) -> Result<&str> {
+    let answer = line
+        .trim()
+        .strip_prefix("/answer")
+        .filter(|rest| rest.is_empty() || rest.starts_with(char::is_whitespace))
+        .ok_or_else(|| anyhow!("not an /answer command"))?
+        .trim();
+    if answer.is_empty() {
+        return Err(anyhow!("usage: /answer <answer>"));
+    }
+    Ok(answer)
+}
+
+/// Build [`NFY_BOT_CHALLENGE`]: u32 challenge ID, u16 seconds to answer,
+/// then the question as a u16 length and UTF-16 text
+pub fn build_nfy_bot_challenge(id: u32, seconds: u32, question: &str) -> Vec<u8> {
+    let mut out = WireWriter::message(NFY_BOT_CHALLENGE);
+    out.u32(id)
+        .u16(seconds.min(u16::MAX as u32) as u16)
+        .utf16(question);
+    out.into_bytes()
+}
+
+/// Parse [`REQ_BOT_CHALLENGE_ANSWER`]: u32 challenge ID, then the answer as
+/// a u16 length and UTF-16 text
+pub fn parse_req_bot_challenge_answer(message: &[u8]) -> Result<(u32, String)> {
+    let mut message =
+        WireReader::message(message, REQ_BOT_CHALLENGE_ANSWER, "bot challenge answer")?;
+    let id = message.u32("id")?;
+    let answer = message.utf16("answer")?;
+    Ok((id, answer))
+}
+
+/// A challenge waiting for its answer
+struct Open {
+    id: u32,
+    kind: &'static str,
+    answer: String,
+    reason: String,
+    deadline: Instant,
+    issued_at: i64,
+    /// Challenges already failed before this one
+    failed: u32,
+}
+
+struct Session {
+    account_id: Option<u32>,
+    character_id: i64,
+    language: String,
+    outbox: mpsc::Sender<Vec<u8>>,
+    kick: Option<oneshot::Sender<String>>,
+    violations: VecDeque<Instant>,
+    open: Option<Open>,
+}
+
+impl Session {
+    fn send(&self, message: Vec<u8>) {
+        // A full or closed outbox means the client is going away anyway
+        let _ = self.outbox.try_send(message);
+    }
+
+    fn entry(&self, open: &Open, result: &str, kicked: bool, now: i64) -> BotChallengeEntry {
+        BotChallengeEntry {
+            id: 0,
+            account_id: self.account_id.map(i64::from),
+            character_id: self.character_id,
+            reason: open.reason.clone(),
+            kind: open.kind.to_string(),
+            result: result.to_string(),
+            kicked,
+            issued_at: open.issued_at,
+            resolved_at: now,
+        }
+    }
+}
+
+/// Challenges for every session in the world
+///
+/// Cheap to clone; every clone tracks the same sessions.
+#[derive(Clone)]
+pub struct BotCheck {
+    config: BotCheckConfig,
+    localization: Arc<Localization>,
+    clock: Clock,
+    kinds: Arc<Vec<Arc<dyn ChallengeKind>>>,
+    rng: Arc<Mutex<StdRng>>,
+    pool: Option<Pool<Sqlite>>,
+    sessions: Arc<Mutex<HashMap<u64, Session>>>,
+    next_id: Arc<Mutex<u32>>,
+}
+
+impl BotCheck {
+    pub fn new(config: BotCheckConfig, localization: Arc<Localization>) -> Self {
+        Self {
+            config,
+            localization,
+            clock: Clock::system(),
+            kinds: Arc::new(vec![Arc::new(Arithmetic), Arc::new(ReverseWord)]),
+            rng: Arc::new(Mutex::new(StdRng::from_entropy())),
+            pool: None,
+            sessions: Arc::default(),
+            next_id: Arc::default(),
+        }
+    }
+
+    /// Tell the time by `clock`
+    pub fn with_clock(mut self, clock: Clock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Ask these instead of the built-in challenges
+    pub fn with_kinds(mut self, kinds: Vec<Arc<dyn ChallengeKind>>) -> Result<Self> {
+        if kinds.is_empty() {
+            return Err(anyhow!("no challenge kinds to ask"));
+        }
+        self.kinds = Arc::new(kinds);
+        Ok(self)
+    }
+
+    /// Pick challenges with a seeded generator, so they repeat
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.rng = Arc::new(Mutex::new(StdRng::seed_from_u64(seed)));
+        self
+    }
+
+    /// Write results to the database for review
+    pub fn with_pool(mut self, pool: Pool<Sqlite>) -> Self {
+        self.pool = Some(pool);
+        self
+    }
+
+    /// Sessions being tracked
+    pub fn len(&self) -> usize {
+        self.sessions.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sessions.lock().unwrap().is_empty()
+    }
+
+    /// Start tracking a session that entered the world
+    ///
+    /// Challenges go to `outbox` in `language`; a reason is sent on `kick`
+    /// to disconnect a player who fails them.
+    pub fn enter(
+        &self,
+        session_id: u64,
+        account_id: Option<u32>,
+        character_id: i64,
+        language: Option<&str>,
+        outbox: mpsc::Sender<Vec<u8>>,
+        kick: oneshot::Sender<String>,
+    ) {
+        let session = Session {
+            account_id,
+            character_id,
+            language: self.localization.resolve(language).to_string(),
+            outbox,
+            kick: Some(kick),
+            violations: VecDeque::new(),
+            open: None,
+        };
+        self.sessions.lock().unwrap().insert(session_id, session);
+    }
+
+    /// Stop tracking a session; a challenge it left unanswered is logged
+    pub async fn leave(&self, session_id: u64) {
+        let now = self.clock.unix();
+        let entry = {
+            let mut sessions = self.sessions.lock().unwrap();
+            sessions.remove(&session_id).and_then(|session| {
+                let open = session.open.as_ref()?;
+                Some(session.entry(open, "left", false, now))
+            })
+        };
+        self.log(entry).await;
+    }
+
+    /// Whether the session has a challenge waiting for its answer
+    pub fn is_challenged(&self, session_id: u64) -> bool {
+        self.sessions
+            .lock()
+            .unwrap()
+            .get(&session_id)
+            .is_some_and(|session| session.open.is_some())
+    }
+
+    /// Count a violation against its session, challenging it once there
+    /// are `threshold` in the window; returns whether it was challenged
+    pub fn violation(&self, violation: &Violation) -> bool {
+        if !self.config.enabled() {
+            return false;
+        }
+        let now = self.clock.instant();
+        let mut sessions = self.sessions.lock().unwrap();
+        let Some(session) = sessions.get_mut(&violation.session_id) else {
+            return false;
+        };
+        if session.open.is_some() {
+            return false;
+        }
+        session.violations.push_back(now);
+        while session
+            .violations
+            .front()
+            .is_some_and(|&at| now.saturating_duration_since(at) > self.config.window())
+        {
+            session.violations.pop_front();
+        }
+        if session.violations.len() < self.config.threshold as usize {
+            return false;
+        }
+        info!(
+            "Session {} had {} violations in {} s, latest {}: challenging it",
+            violation.session_id,
+            session.violations.len(),
+            self.config.window_secs,
+            violation.kind
+        );
+        self.issue(session, violation.kind.clone(), 0);
+        true
+    }
+
+    /// Challenge a session now, e.g. for a GM; `reason` goes in the log.
+    /// Returns whether it's in the world and wasn't already challenged.
+    pub fn challenge(&self, session_id: u64, reason: &str) -> bool {
+        let mut sessions = self.sessions.lock().unwrap();
+        match sessions.get_mut(&session_id) {
+            Some(session) if session.open.is_none() => {
+                info!("Session {} challenged: {}", session_id, reason);
+                self.issue(session, reason.to_string(), 0);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Take the session's answer to challenge `id` (`None` from chat, where
+    /// it's the open one); returns whether it was right, or `None` if there
+    /// was nothing to answer
+    pub async fn answer(&self, session_id: u64, id: Option<u32>, answer: &str) -> Option<bool> {
+        let now = self.clock.unix();
+        let (entry, right) = {
+            let mut sessions = self.sessions.lock().unwrap();
+            let session = sessions.get_mut(&session_id)?;
+            if id.is_some_and(|id| session.open.as_ref().is_some_and(|open| open.id != id)) {
+                return None;
+            }
+            let open = session.open.take()?;
+            if answer.trim().eq_ignore_ascii_case(&open.answer) {
+                session.violations.clear();
+                session.send(build_system_message(
+                    self.localization.get(&session.language, "bot_check.passed"),
+                ));
+                (session.entry(&open, "passed", false, now), true)
+            } else {
+                let entry = self.fail(session_id, &mut sessions, open, "wrong", now);
+                (entry, false)
+            }
+        };
+        self.log(Some(entry)).await;
+        Some(right)
+    }
+
+    /// Fail challenges that weren't answered in time; returns the sessions
+    /// kicked
+    pub async fn check(&self) -> Vec<u64> {
+        let now = self.clock.instant();
+        let unix = self.clock.unix();
+        let mut entries = Vec::new();
+        let mut kicked = Vec::new();
+        {
+            let mut sessions = self.sessions.lock().unwrap();
+            let late: Vec<u64> = sessions
+                .iter()
+                .filter(|(_, session)| {
+                    session
+                        .open
+                        .as_ref()
+                        .is_some_and(|open| now >= open.deadline)
+                })
+                .map(|(&session_id, _)| session_id)
+                .collect();
+            for session_id in late {
+                let open = sessions
+                    .get_mut(&session_id)
+                    .and_then(|session| session.open.take())
+                    .expect("filtered for an open challenge");
+                let entry = self.fail(session_id, &mut sessions, open, "timed_out", unix);
+                if entry.kicked {
+                    kicked.push(session_id);
+                }
+                entries.push(entry);
+            }
+        }
+        for entry in entries {
+            self.log(Some(entry)).await;
+        }
+        kicked
+    }
+
+    /// Check every [`CHECK_INTERVAL`] and count every [`Violation`] on
+    /// `events`, on a task of its own, until the bus closes
+    pub fn spawn(&self, events: &EventBus) -> JoinHandle<()> {
+        let check = self.clone();
+        let mut violations = events.subscribe::<Violation>();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(CHECK_INTERVAL);
+            loop {
+                tokio::select! {
+                    violation = violations.recv() => match violation {
+                        Some(violation) => {
+                            check.violation(&violation);
+                        }
+                        None => return,
+                    },
+                    _ = interval.tick() => {
+                        check.check().await;
+                    }
+                }
+            }
+        })
+    }
+
+    /// Send `session` a new challenge
+    fn issue(&self, session: &mut Session, reason: String, failed: u32) {
+        let (kind, prompt) = {
+            let mut rng = self.rng.lock().unwrap();
+            let kind = &self.kinds[rng.gen_range(0..self.kinds.len())];
+            (kind.name(), kind.generate(&mut *rng))
+        };
+        let id = {
+            let mut next_id = self.next_id.lock().unwrap();
+            *next_id = next_id.wrapping_add(1);
+            *next_id
+        };
+        session.send(build_nfy_bot_challenge(
+            id,
+            self.config.answer_secs,
+            &prompt.question,
+        ));
+        session.send(build_system_message(&self.localization.format(
+            &session.language,
+            "bot_check.prompt",
+            &[
+                ("question", &prompt.question),
+                ("seconds", &self.config.answer_secs),
+            ],
+        )));
+        session.open = Some(Open {
+            id,
+            kind,
+            answer: prompt.answer,
+            reason,
+            deadline: self.clock.instant() + self.config.answer_time(),
+            issued_at: self.clock.unix(),
+            failed,
+        });
+    }
+
+    /// Count a failed challenge: another one, or a kick once `attempts`
+    /// have failed
+    fn fail(
+        &self,
+        session_id: u64,
+        sessions: &mut HashMap<u64, Session>,
+        open: Open,
+        result: &str,
+        now: i64,
+    ) -> BotChallengeEntry {
+        let failed = open.failed + 1;
+        let session = sessions
+            .get_mut(&session_id)
+            .expect("session has the challenge");
+        if failed < self.config.attempts {
+            let entry = session.entry(&open, result, false, now);
+            session.send(build_system_message(
+                self.localization.get(&session.language, "bot_check.wrong"),
+            ));
+            self.issue(session, open.reason, failed);
+            return entry;
+        }
+
+        let mut session = sessions
+            .remove(&session_id)
+            .expect("session has the challenge");
+        warn!(
+            "Session {} (character {}) failed {} anti-bot challenges: kicking it",
+            session_id, session.character_id, failed
+        );
+        session.send(build_system_message(
+            self.localization.get(&session.language, "bot_check.failed"),
+        ));
+        if let Some(kick) = session.kick.take() {
+            let _ = kick.send(format!("failed {} anti-bot challenges", failed));
+        }
+        session.entry(&open, result, true, now)
+    }
+
+    async fn log(&self, entry: Option<BotChallengeEntry>) {
+        let (Some(pool), Some(entry)) = (&self.pool, entry) else {
+            return;
+        };
+        if let Err(e) = BotChallengeQueries::record(pool, &entry).await {
+            warn!("Logging an anti-bot challenge failed: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::DateTime;
+
+    /// Always "Say yes."
+    struct SayYes;
+
+    impl ChallengeKind for SayYes {
+        fn name(&self) -> &'static str {
+            "say_yes"
+        }
+
+        fn generate(&self, _rng: &mut dyn RngCore) -> Prompt {
+            Prompt {
+                question: "Say yes.".to_string(),
+                answer: "yes".to_string(),
+            }
+        }
+    }
+
+    fn text(message: &[u8]) -> String {
+        ro2_common::wire::read_utf16(&message[2..]).unwrap().0
+    }
+
+    fn violation() -> Violation {
+        Violation {
+            session_id: 1,
+            account_id: Some(2),
+            kind: "cooldown".to_string(),
+            detail: String::new(),
+        }
+    }
+
+    async fn check(config: BotCheckConfig) -> (BotCheck, Clock, Pool<Sqlite>) {
+        let pool = ro2_common::testing::database().await;
+        let clock = Clock::manual(DateTime::from_timestamp(1_700_000_000, 0).unwrap());
+        let check = BotCheck::new(config, Arc::new(Localization::builtin()))
+            .with_clock(clock.clone())
+            .with_kinds(vec![Arc::new(SayYes)])
+            .unwrap()
+            .with_pool(pool.clone());
+        (check, clock, pool)
+    }
+
+    /// The challenge's ID and question, after its system message
+    fn challenge(messages: &mut mpsc::Receiver<Vec<u8>>) -> (u32, String) {
+        let notify = messages.try_recv().unwrap();
+        let mut reader = WireReader::message(&notify, NFY_BOT_CHALLENGE, "challenge").unwrap();
+        let id = reader.u32("id").unwrap();
+        reader.u16("seconds").unwrap();
+        let question = reader.utf16("question").unwrap();
+        assert!(text(&messages.try_recv().unwrap()).contains(&question));
+        (id, question)
+    }
+
+    #[test]
+    fn test_config_and_parsing() {
+        assert_eq!(
+            BotCheckConfig::load("does/not/exist.toml").unwrap(),
+            BotCheckConfig::default()
+        );
+        assert!(BotCheckConfig::from_toml("[bot_check]\nattempts = 0").is_err());
+        assert!(
+            !BotCheckConfig::from_toml("[bot_check]\nthreshold = 0")
+                .unwrap()
+                .enabled()
+        );
+
+        assert_eq!(parse_command("/answer  olleh ").unwrap(), "olleh");
+        assert!(parse_command("/answer").is_err());
+        assert!(parse_command("/answering 1").is_err());
+
+        let mut message = WireWriter::message(REQ_BOT_CHALLENGE_ANSWER);
+        message.u32(7).utf16("11");
+        let message = message.into_bytes();
+        assert_eq!(
+            parse_req_bot_challenge_answer(&message).unwrap(),
+            (7, "11".to_string())
+        );
+        for cut in 0..message.len() {
+            assert!(parse_req_bot_challenge_answer(&message[..cut]).is_err());
+        }
+    }
+
+    #[test]
+    fn test_builtin_kinds() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let sum = Arithmetic.generate(&mut rng);
+        let numbers: Vec<u32> = sum
+            .question
+            .trim_end_matches('?')
+            .split(' ')
+            .filter_map(|word| word.parse().ok())
+            .collect();
+        assert_eq!(sum.answer, (numbers[0] + numbers[1]).to_string());
+        let word = ReverseWord.generate(&mut rng);
+        let reversed: String = word.answer.chars().rev().collect();
+        assert!(word.question.contains(&reversed));
+    }
+
+    #[tokio::test]
+    async fn test_violations_then_pass() {
+        let (check, clock, pool) = check(BotCheckConfig::default()).await;
+        let (outbox, mut messages) = mpsc::channel(16);
+        let (kick, mut kicked) = oneshot::channel();
+        check.enter(1, Some(2), 10, None, outbox, kick);
+
+        // Old violations fall out of the window
+        for _ in 0..4 {
+            assert!(!check.violation(&violation()));
+        }
+        clock.advance(Duration::from_secs(601));
+        for _ in 0..4 {
+            assert!(!check.violation(&violation()));
+        }
+        assert!(check.violation(&violation()));
+        let (id, question) = challenge(&mut messages);
+        assert_eq!(question, "Say yes.");
+        assert!(check.is_challenged(1));
+
+        assert_eq!(check.answer(1, Some(id + 1), "yes").await, None);
+        assert_eq!(check.answer(1, Some(id), " YES ").await, Some(true));
+        assert!(text(&messages.try_recv().unwrap()).contains("done"));
+        assert_eq!(check.answer(1, None, "yes").await, None);
+        assert!(kicked.try_recv().is_err());
+
+        // The count starts again
+        assert!(!check.violation(&violation()));
+        let log = BotChallengeQueries::recent(&pool, Some(2), 10)
+            .await
+            .unwrap();
+        assert_eq!(log.len(), 1);
+        assert_eq!(
+            (
+                log[0].reason.as_str(),
+                log[0].kind.as_str(),
+                log[0].result.as_str()
+            ),
+            ("cooldown", "say_yes", "passed")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fail_then_kick() {
+        let (check, clock, pool) = check(BotCheckConfig::default()).await;
+        let (outbox, mut messages) = mpsc::channel(16);
+        let (kick, mut kicked) = oneshot::channel();
+        check.enter(1, Some(2), 10, None, outbox, kick);
+        assert!(check.challenge(1, "gm:alice"));
+        assert!(!check.challenge(1, "gm:alice"));
+        challenge(&mut messages);
+
+        // A wrong answer gets another challenge
+        assert_eq!(check.answer(1, None, "no").await, Some(false));
+        assert!(text(&messages.try_recv().unwrap()).contains("try another"));
+        challenge(&mut messages);
+
+        // So does no answer in time
+        clock.advance(Duration::from_secs(59));
+        assert!(check.check().await.is_empty());
+        clock.advance(Duration::from_secs(1));
+        assert!(check.check().await.is_empty());
+        messages.try_recv().unwrap();
+        challenge(&mut messages);
+
+        // The third failure is the last
+        clock.advance(Duration::from_secs(60));
+        assert_eq!(check.check().await, [1]);
+        assert!(text(&messages.try_recv().unwrap()).contains("disconnected"));
+        assert!(kicked.try_recv().unwrap().contains("3 anti-bot challenges"));
+        assert!(check.is_empty());
+
+        let log = BotChallengeQueries::recent(&pool, None, 10).await.unwrap();
+        let results: Vec<_> = log
+            .iter()
+            .rev()
+            .map(|entry| (entry.result.as_str(), entry.kicked))
+            .collect();
+        assert_eq!(
+            results,
+            [("wrong", false), ("timed_out", false), ("timed_out", true)]
+        );
+        assert!(log.iter().all(|entry| entry.reason == "gm:alice"));
+    }
+
+    #[tokio::test]
+    async fn test_disabled_and_leaving() {
+        let config = BotCheckConfig::from_toml("[bot_check]\nthreshold = 0").unwrap();
+        let (check, _, pool) = check(config).await;
+        let (outbox, _messages) = mpsc::channel(16);
+        let (kick, _kicked) = oneshot::channel();
+        check.enter(1, None, 10, None, outbox, kick);
+        for _ in 0..10 {
+            assert!(!check.violation(&violation()));
+        }
+
+        // GMs still can
+        assert!(check.challenge(1, "gm:alice"));
+        check.leave(1).await;
+        assert!(check.is_empty());
+        let log = BotChallengeQueries::recent(&pool, None, 10).await.unwrap();
+        assert_eq!((log[0].account_id, log[0].result.as_str()), (None, "left"));
+    }
+}
@@ -0,0 +1,191 @@
+//! Reporting this channel's population to the lobby
+//!
+//! Each world server is one channel. The lobby lists them, marks busy ones
+//! crowded or full and recommends the least loaded, so every channel tells
+//! it how many players it has. Like megaphones (see
+//! [`megaphone`](crate::megaphone)) that goes through the shared database:
+//! [`spawn_reporter`] adds up the [`ZonePopulation`]s published on the
+//! event bus and writes the total to `channel_status` (see
+//! [`ChannelQueries`]) every `report_secs`. The lobby treats a channel that
+//! stops reporting as offline.
+//!
+//! Set in the `[channel]` section of `config/world.toml`:
+//!
+//! ```toml
+//! [channel]
+//! id = 1                      # Unique among the channels; 0 to not report
+//! name = "Channel 1"
+//! address = "127.0.0.1:7401"  # Where players connect to it
+//! report_secs = 10
+//! ```
+
+use anyhow::{Result, anyhow};
+use ro2_common::clock::Clock;
+use ro2_common::config::{self, Validate};
+use ro2_common::database::ChannelStatus;
+use ro2_common::database::queries::ChannelQueries;
+use ro2_common::events::{EventBus, ZonePopulation};
+use serde::Deserialize;
+use sqlx::{Pool, Sqlite};
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tracing::warn;
+
+/// Which channel this server is and how often it reports
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct ChannelConfig {
+    /// 0 to not report
+    pub id: u32,
+    pub name: String,
+    pub address: String,
+    pub report_secs: u64,
+}
+
+impl Default for ChannelConfig {
+    fn default() -> Self {
+        Self {
+            id: 1,
+            name: "Channel 1".to_string(),
+            address: "127.0.0.1:7401".to_string(),
+            report_secs: 10,
+        }
+    }
+}
+
+impl ChannelConfig {
+    /// Read the `[channel]` section of `path`; missing keys keep their
+    /// defaults
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        config::section(path, "channel")
+    }
+
+    /// Parse the `[channel]` section of TOML text
+    pub fn from_toml(text: &str) -> Result<Self> {
+        config::section_from_toml(text, "channel")
+    }
+
+    /// Whether this server reports its population at all
+    pub fn reports(&self) -> bool {
+        self.id > 0
+    }
+
+    pub fn report_interval(&self) -> Duration {
+        Duration::from_secs(self.report_secs)
+    }
+}
+
+impl Validate for ChannelConfig {
+    fn validate(&self) -> Result<()> {
+        if self.report_secs == 0 {
+            return Err(anyhow!("channel report_secs can't be 0"));
+        }
+        Ok(())
+    }
+}
+
+/// The latest player count of each zone
+#[derive(Debug, Default)]
+pub struct Populations {
+    zones: HashMap<u32, usize>,
+}
+
+impl Populations {
+    pub fn record(&mut self, population: &ZonePopulation) {
+        self.zones.insert(population.zone, population.players);
+    }
+
+    /// Players in every zone
+    pub fn players(&self) -> usize {
+        self.zones.values().sum()
+    }
+
+    /// What to tell the lobby about this channel at `now`
+    pub fn status(&self, config: &ChannelConfig, now: i64) -> ChannelStatus {
+        ChannelStatus {
+            id: i64::from(config.id),
+            name: config.name.clone(),
+            address: config.address.clone(),
+            players: self.players() as i64,
+            reported_at: now,
+        }
+    }
+}
+
+/// Report the channel's population every `report_secs` until the event bus
+/// is gone
+pub fn spawn_reporter(
+    pool: Pool<Sqlite>,
+    config: ChannelConfig,
+    events: &EventBus,
+    clock: Clock,
+) -> JoinHandle<()> {
+    let mut populations = events.subscribe::<ZonePopulation>();
+    tokio::spawn(async move {
+        let mut zones = Populations::default();
+        let mut interval = tokio::time::interval(config.report_interval());
+        loop {
+            tokio::select! {
+                population = populations.recv() => match population {
+                    Some(population) => zones.record(&population),
+                    None => return,
+                },
+                _ = interval.tick() => {
+                    let status = zones.status(&config, clock.unix());
+                    if let Err(e) = ChannelQueries::report(&pool, &status).await {
+                        warn!("Reporting channel {} failed: {}", config.id, e);
+                    }
+                }
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn population(zone: u32, players: usize) -> ZonePopulation {
+        ZonePopulation {
+            zone,
+            players,
+            monsters: 0,
+            messages_per_sec: 0,
+        }
+    }
+
+    #[test]
+    fn test_config() {
+        assert_eq!(
+            ChannelConfig::load("does/not/exist.toml").unwrap(),
+            ChannelConfig::default()
+        );
+        let config = ChannelConfig::from_toml("[channel]\nid = 3\nname = \"Three\"").unwrap();
+        assert_eq!((config.id, config.name.as_str()), (3, "Three"));
+        assert!(config.reports());
+        assert!(
+            !ChannelConfig::from_toml("[channel]\nid = 0")
+                .unwrap()
+                .reports()
+        );
+        assert!(ChannelConfig::from_toml("[channel]\nreport_secs = 0").is_err());
+    }
+
+    #[test]
+    fn test_populations() {
+        let mut zones = Populations::default();
+        zones.record(&population(1, 10));
+        zones.record(&population(2, 5));
+        // A zone's latest count replaces its last
+        zones.record(&population(1, 7));
+        assert_eq!(zones.players(), 12);
+
+        let status = zones.status(&ChannelConfig::default(), 100);
+        assert_eq!(
+            (status.id, status.players, status.reported_at),
+            (1, 12, 100)
+        );
+    }
+}
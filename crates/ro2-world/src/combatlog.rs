@@ -0,0 +1,325 @@
+//! Combat log
+//!
+//! Every hit, heal and miss in a zone is a [`CombatEvent`]. [`CombatLog::log`]
+//! sends it as [`NFY_COMBAT_LOG`] to the clients on both ends, so a player's
+//! combat log shows the damage they dealt and took, and the skill names,
+//! without asking for anything.
+//!
+//! With a recording directory set in the `[combat_log]` section of
+//! `config/world.toml`, each zone also appends its events to a file of its
+//! own there, one JSON object per line, for checking balance after the
+//! fact:
+//!
+//! ```toml
+//! [combat_log]
+//! record_dir = "logs/combat"   # Off when unset
+//! ```
+//!
+//! Recordings are named after the zone and the time it started recording,
+//! so a restarted zone doesn't write over the last run's. Writes are
+//! buffered but blocking, like the zone's other work between ticks.
+//!
+//! Like the other `0x3Fxx` opcodes, [`NFY_COMBAT_LOG`] is a placeholder.
+
+use crate::world::{Broadcaster, EntityId, ZoneId};
+use anyhow::{Context, Result};
+use ro2_common::clock::Clock;
+use ro2_common::config::{self, Validate};
+use ro2_common::wire::WireWriter;
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+pub use ro2_common::protocol::opcodes::NFY_COMBAT_LOG;
+
+/// Combat log settings
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct CombatLogConfig {
+    /// Where zones record their combat; off when unset
+    pub record_dir: Option<PathBuf>,
+}
+
+impl CombatLogConfig {
+    /// Read the `[combat_log]` section of `path`
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        config::section(path, "combat_log")
+    }
+
+    /// Parse the `[combat_log]` section of TOML text
+    pub fn from_toml(text: &str) -> Result<Self> {
+        config::section_from_toml(text, "combat_log")
+    }
+}
+
+impl Validate for CombatLogConfig {}
+
+/// What happened
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[repr(u8)]
+pub enum CombatEventKind {
+    Damage = 1,
+    Critical = 2,
+    Heal = 3,
+    Miss = 4,
+}
+
+/// A skill as the combat log names it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SkillName {
+    pub id: i32,
+    pub name: String,
+}
+
+/// One line of the combat log
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CombatEvent {
+    pub source: EntityId,
+    pub target: EntityId,
+    pub kind: CombatEventKind,
+    /// HP taken or given; 0 for a miss
+    pub amount: u32,
+    /// The skill used; a plain attack when unset
+    #[serde(default)]
+    pub skill: Option<SkillName>,
+}
+
+impl CombatEvent {
+    pub fn damage(source: EntityId, target: EntityId, amount: u32) -> Self {
+        Self::new(source, target, CombatEventKind::Damage, amount)
+    }
+
+    pub fn critical(source: EntityId, target: EntityId, amount: u32) -> Self {
+        Self::new(source, target, CombatEventKind::Critical, amount)
+    }
+
+    pub fn heal(source: EntityId, target: EntityId, amount: u32) -> Self {
+        Self::new(source, target, CombatEventKind::Heal, amount)
+    }
+
+    pub fn miss(source: EntityId, target: EntityId) -> Self {
+        Self::new(source, target, CombatEventKind::Miss, 0)
+    }
+
+    fn new(source: EntityId, target: EntityId, kind: CombatEventKind, amount: u32) -> Self {
+        Self {
+            source,
+            target,
+            kind,
+            amount,
+            skill: None,
+        }
+    }
+
+    /// The same event, done with skill `id`
+    pub fn with_skill(mut self, id: i32, name: impl Into<String>) -> Self {
+        self.skill = Some(SkillName {
+            id,
+            name: name.into(),
+        });
+        self
+    }
+
+    /// Encode [`NFY_COMBAT_LOG`]: u8 kind (1 = damage, 2 = critical,
+    /// 3 = heal, 4 = miss), u32 source, u32 target, u32 amount, i32 skill
+    /// ID (0 for a plain attack), u16 name length and the UTF-8 skill name
+    pub fn encode(&self) -> Vec<u8> {
+        let (skill_id, name) = match &self.skill {
+            Some(skill) => (skill.id, skill.name.as_str()),
+            None => (0, ""),
+        };
+        let mut out = WireWriter::message(NFY_COMBAT_LOG);
+        out.u8(self.kind as u8)
+            .u32(self.source.0)
+            .u32(self.target.0)
+            .u32(self.amount)
+            .i32(skill_id)
+            .utf8(name);
+        out.into_bytes()
+    }
+}
+
+/// A recorded event and when it happened
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Recorded {
+    /// Unix time in milliseconds
+    pub at: i64,
+    #[serde(flatten)]
+    pub event: CombatEvent,
+}
+
+/// Sends one zone's combat events to its clients and records them
+pub struct CombatLog {
+    clock: Clock,
+    recording: Option<(PathBuf, BufWriter<std::fs::File>)>,
+}
+
+impl CombatLog {
+    pub fn new(clock: Clock) -> Self {
+        Self {
+            clock,
+            recording: None,
+        }
+    }
+
+    /// Also record zone `zone`'s events to a new file in `dir`
+    pub fn record_to(mut self, dir: impl AsRef<Path>, zone: ZoneId) -> Result<Self> {
+        let dir = dir.as_ref();
+        std::fs::create_dir_all(dir).with_context(|| format!("creating {}", dir.display()))?;
+        let path = dir.join(format!("zone-{}-{}.jsonl", zone.0, self.clock.unix()));
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .with_context(|| format!("opening {}", path.display()))?;
+        self.recording = Some((path, BufWriter::new(file)));
+        Ok(self)
+    }
+
+    /// The file events are recorded to, if any
+    pub fn recording(&self) -> Option<&Path> {
+        self.recording.as_ref().map(|(path, _)| path.as_path())
+    }
+
+    /// Send `event` to the clients of its source and target, and record it
+    pub fn log(&mut self, broadcaster: &Broadcaster, event: CombatEvent) -> Result<()> {
+        let message = event.encode();
+        broadcaster.send_to(event.source, message.clone());
+        if event.target != event.source {
+            broadcaster.send_to(event.target, message);
+        }
+
+        if let Some((path, out)) = &mut self.recording {
+            let recorded = Recorded {
+                at: self.clock.now().timestamp_millis(),
+                event,
+            };
+            serde_json::to_writer(&mut *out, &recorded)?;
+            out.write_all(b"\n")
+                .with_context(|| format!("recording to {}", path.display()))?;
+        }
+        Ok(())
+    }
+
+    /// Write out buffered events; call on ticks or before shutting down
+    pub fn flush(&mut self) -> Result<()> {
+        if let Some((path, out)) = &mut self.recording {
+            out.flush()
+                .with_context(|| format!("recording to {}", path.display()))?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for CombatLog {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+/// Read back a recording
+pub fn read_recording(path: impl AsRef<Path>) -> Result<Vec<Recorded>> {
+    let path = path.as_ref();
+    let file = std::fs::File::open(path).with_context(|| format!("opening {}", path.display()))?;
+    BufReader::new(file)
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| line.as_ref().map_or(true, |line| !line.is_empty()))
+        .map(|(number, line)| {
+            serde_json::from_str(&line?)
+                .with_context(|| format!("{} line {}", path.display(), number + 1))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::{EntityKind, Position, Zone};
+    use chrono::DateTime;
+    use std::time::Duration;
+
+    #[test]
+    fn test_encode() {
+        assert_eq!(
+            CombatEvent::damage(EntityId(1), EntityId(2), 300)
+                .with_skill(7, "Bash")
+                .encode(),
+            [
+                0x80, 0x3F, 1, 1, 0, 0, 0, 2, 0, 0, 0, 0x2C, 0x01, 0, 0, 7, 0, 0, 0, 4, 0, b'B',
+                b'a', b's', b'h'
+            ]
+        );
+        assert_eq!(
+            CombatEvent::miss(EntityId(1), EntityId(2)).encode(),
+            [
+                0x80, 0x3F, 4, 1, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0
+            ]
+        );
+    }
+
+    #[test]
+    fn test_log_and_record() {
+        let mut zone = Zone::new();
+        let player = zone.spawn(EntityKind::Player, Position::default(), 100);
+        let healer = zone.spawn(EntityKind::Player, Position::default(), 100);
+        let bystander = zone.spawn(EntityKind::Player, Position::default(), 100);
+        let poring = zone.spawn(EntityKind::Monster, Position::default(), 50);
+        let mut broadcaster = Broadcaster::default();
+        let mut inboxes = Vec::new();
+        for (session, entity) in [(1, player), (2, healer), (3, bystander)] {
+            let (tx, rx) = tokio::sync::mpsc::channel(4);
+            broadcaster.subscribe(session, entity, tx);
+            inboxes.push(rx);
+        }
+
+        let dir = std::env::temp_dir().join(format!("ro2-combatlog-{}", std::process::id()));
+        let clock = Clock::manual(DateTime::from_timestamp(1_700_000_000, 0).unwrap());
+        let mut log = CombatLog::new(clock.clone())
+            .record_to(&dir, ZoneId(3))
+            .unwrap();
+        let path = log.recording().unwrap().to_path_buf();
+        assert_eq!(path, dir.join("zone-3-1700000000.jsonl"));
+
+        let hit = CombatEvent::critical(player, poring, 42).with_skill(7, "Bash");
+        log.log(&broadcaster, hit.clone()).unwrap();
+        clock.advance(Duration::from_millis(250));
+        let heal = CombatEvent::heal(healer, player, 30);
+        log.log(&broadcaster, heal.clone()).unwrap();
+
+        // Both ends see it, nobody else does
+        assert_eq!(inboxes[0].try_recv().unwrap(), hit.encode());
+        assert_eq!(inboxes[0].try_recv().unwrap(), heal.encode());
+        assert_eq!(inboxes[1].try_recv().unwrap(), heal.encode());
+        assert!(inboxes[2].try_recv().is_err());
+
+        drop(log);
+        let recorded = read_recording(&path).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(
+            recorded,
+            [
+                Recorded {
+                    at: 1_700_000_000_000,
+                    event: hit
+                },
+                Recorded {
+                    at: 1_700_000_000_250,
+                    event: heal
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_config() {
+        assert_eq!(CombatLogConfig::from_toml("").unwrap().record_dir, None);
+        assert_eq!(
+            CombatLogConfig::from_toml("[combat_log]\nrecord_dir = \"logs/combat\"")
+                .unwrap()
+                .record_dir,
+            Some(PathBuf::from("logs/combat"))
+        );
+    }
+}
@@ -0,0 +1,444 @@
+//! World server admin console commands
+//!
+//! Added to the common [`Console`](ro2_common::console::Console) next to
+//! its session commands:
+//!
+//! - `rates [exp|drop|zeny <rate>]`: show or change the rates
+//! - `reload`: re-read the rates from the config file
+//! - `notice all|zone <id>|player <session> <message>`: a system message
+//! - `global <message>`: an announcement on every channel; `global log`
+//!   lists the latest megaphones and announcements
+//! - `savestate`: write the dev save state now
+//! - `population [export <path>]`: each zone's population history, or
+//!   all its samples written to a `.csv` or `.json` file
+//! - `botcheck <session>`: send a player an anti-bot challenge;
+//!   `botcheck log [account]` lists the latest results
+//! - `stalls [character]`: the vending stalls open on every channel, or
+//!   what one character's has left
+
+use crate::announce::{self, SystemMessenger};
+use crate::botcheck::BotCheck;
+use crate::megaphone;
+use crate::population::PopulationHistory;
+use crate::rates::{RateConfig, Rates};
+use crate::world::{SaveState, World};
+use anyhow::{Context, Result, anyhow};
+use async_trait::async_trait;
+use ro2_common::clock::Clock;
+use ro2_common::console::ConsoleCommand;
+use ro2_common::database::queries::{BotChallengeQueries, GlobalMessageQueries, VendingQueries};
+use sqlx::{Pool, Sqlite};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use tracing::info;
+
+/// `rates [exp|drop|zeny <rate>]`
+pub struct RatesCommand(pub Arc<Rates>);
+
+#[async_trait]
+impl ConsoleCommand for RatesCommand {
+    fn usage(&self) -> &'static str {
+        "[exp|drop|zeny <rate>]"
+    }
+
+    async fn run(&self, args: &[&str]) -> Result<String> {
+        match args {
+            [] => {}
+            [name, rate] => {
+                let rate = rate.parse().context("bad rate")?;
+                self.0.set(name, rate)?;
+                info!("Console set the {} rate to {}", name, rate);
+            }
+            _ => return Err(anyhow!("usage: rates [exp|drop|zeny <rate>]")),
+        }
+        Ok(describe(&self.0.get()))
+    }
+}
+
+/// `reload`: the rates from `path`
+pub struct ReloadCommand {
+    pub rates: Arc<Rates>,
+    pub path: PathBuf,
+}
+
+#[async_trait]
+impl ConsoleCommand for ReloadCommand {
+    async fn run(&self, _args: &[&str]) -> Result<String> {
+        let rates = RateConfig::load(&self.path)?;
+        self.rates.replace(rates);
+        info!("Console reloaded {}", self.path.display());
+        Ok(describe(&rates))
+    }
+}
+
+fn describe(rates: &RateConfig) -> String {
+    format!("exp {} drop {} zeny {}", rates.exp, rates.drop, rates.zeny)
+}
+
+/// `notice all|zone <id>|player <session> <message>`
+pub struct NoticeCommand(pub SystemMessenger);
+
+#[async_trait]
+impl ConsoleCommand for NoticeCommand {
+    fn usage(&self) -> &'static str {
+        "all|zone <id>|player <session> <message>"
+    }
+
+    async fn run(&self, args: &[&str]) -> Result<String> {
+        let line = format!("/notice {}", args.join(" "));
+        let (target, message) = announce::parse_command(&line)?;
+        let sent = self.0.send(target, message);
+        Ok(format!("sent to {} sessions", sent))
+    }
+}
+
+/// `global <message>|log`
+pub struct GlobalCommand(pub Pool<Sqlite>);
+
+/// Messages `global log` lists
+const GLOBAL_LOG_LINES: i64 = 20;
+
+#[async_trait]
+impl ConsoleCommand for GlobalCommand {
+    fn usage(&self) -> &'static str {
+        "<message>|log"
+    }
+
+    async fn run(&self, args: &[&str]) -> Result<String> {
+        match args {
+            [] => Err(anyhow!("usage: global <message>|log")),
+            ["log"] => {
+                let lines: Vec<String> = GlobalMessageQueries::recent(&self.0, GLOBAL_LOG_LINES)
+                    .await?
+                    .iter()
+                    .rev()
+                    .map(|message| {
+                        format!(
+                            "{}\t{}\t{}\t{}",
+                            message.sent_at, message.kind, message.sender, message.text
+                        )
+                    })
+                    .collect();
+                if lines.is_empty() {
+                    return Ok("no global messages yet".to_string());
+                }
+                Ok(lines.join("\n"))
+            }
+            words => {
+                let text = words.join(" ");
+                megaphone::announce(&self.0, "console", &text, Clock::system().unix()).await?;
+                Ok("posted to every channel".to_string())
+            }
+        }
+    }
+}
+
+/// `stalls [character]`
+pub struct StallsCommand(pub Pool<Sqlite>);
+
+#[async_trait]
+impl ConsoleCommand for StallsCommand {
+    fn usage(&self) -> &'static str {
+        "[character id]"
+    }
+
+    async fn run(&self, args: &[&str]) -> Result<String> {
+        let lines: Vec<String> = match args {
+            [] => VendingQueries::stalls(&self.0)
+                .await?
+                .iter()
+                .map(|stall| {
+                    format!(
+                        "{}\tchannel {}\tmap {} ({:.0}, {:.0})\t{}",
+                        stall.character_id,
+                        stall.channel_id,
+                        stall.map_id,
+                        stall.x,
+                        stall.z,
+                        stall.title
+                    )
+                })
+                .collect(),
+            [character] => {
+                let character_id = character.parse().context("character id")?;
+                VendingQueries::items(&self.0, character_id)
+                    .await?
+                    .iter()
+                    .map(|item| {
+                        format!(
+                            "{}\titem {} x{}\t{} zeny each",
+                            item.stall_slot, item.item_id, item.quantity, item.price
+                        )
+                    })
+                    .collect()
+            }
+            _ => return Err(anyhow!("usage: stalls [character id]")),
+        };
+        if lines.is_empty() {
+            return Ok("no stalls open".to_string());
+        }
+        Ok(lines.join("\n"))
+    }
+}
+
+/// `botcheck <session>|log [account]`
+pub struct BotCheckCommand {
+    pub check: BotCheck,
+    pub pool: Pool<Sqlite>,
+}
+
+/// Results `botcheck log` lists
+const BOT_CHECK_LOG_LINES: i64 = 20;
+
+#[async_trait]
+impl ConsoleCommand for BotCheckCommand {
+    fn usage(&self) -> &'static str {
+        "<session id>|log [account id]"
+    }
+
+    async fn run(&self, args: &[&str]) -> Result<String> {
+        let account_id = match args {
+            ["log"] => None,
+            ["log", account_id] => Some(account_id.parse().context("bad account ID")?),
+            [session_id] => {
+                let session_id: u64 = session_id.parse().context("bad session ID")?;
+                if !self.check.challenge(session_id, "gm:console") {
+                    return Err(anyhow!(
+                        "session {} isn't in the world or is already challenged",
+                        session_id
+                    ));
+                }
+                return Ok(format!("challenged session {}", session_id));
+            }
+            _ => return Err(anyhow!("usage: botcheck <session id>|log [account id]")),
+        };
+        let lines: Vec<String> =
+            BotChallengeQueries::recent(&self.pool, account_id, BOT_CHECK_LOG_LINES)
+                .await?
+                .iter()
+                .rev()
+                .map(|entry| {
+                    format!(
+                        "{}\taccount {}\tcharacter {}\t{}\t{}\t{}{}",
+                        entry.resolved_at,
+                        entry
+                            .account_id
+                            .map_or_else(|| "-".to_string(), |id| id.to_string()),
+                        entry.character_id,
+                        entry.reason,
+                        entry.kind,
+                        entry.result,
+                        if entry.kicked { "\tkicked" } else { "" }
+                    )
+                })
+                .collect();
+        if lines.is_empty() {
+            return Ok("no anti-bot challenges yet".to_string());
+        }
+        Ok(lines.join("\n"))
+    }
+}
+
+/// `savestate`: write the world to the dev save state
+pub struct SaveStateCommand {
+    pub world: Arc<World>,
+    /// Nothing to write to without one
+    pub path: Option<PathBuf>,
+}
+
+#[async_trait]
+impl ConsoleCommand for SaveStateCommand {
+    async fn run(&self, _args: &[&str]) -> Result<String> {
+        let path = self
+            .path
+            .as_ref()
+            .ok_or_else(|| anyhow!("no [dev] savestate path configured"))?;
+        let state = SaveState::take(&self.world, Clock::system().unix()).await?;
+        state.write(path)?;
+        Ok(format!(
+            "saved {} entities to {}",
+            state.entities(),
+            path.display()
+        ))
+    }
+}
+
+/// `population [export <path>]`
+pub struct PopulationCommand(pub Arc<Mutex<PopulationHistory>>);
+
+#[async_trait]
+impl ConsoleCommand for PopulationCommand {
+    fn usage(&self) -> &'static str {
+        "[export <path>]"
+    }
+
+    async fn run(&self, args: &[&str]) -> Result<String> {
+        let history = self.0.lock().unwrap();
+        match args {
+            [] => {
+                let lines: Vec<String> = history
+                    .summary()
+                    .iter()
+                    .map(|zone| {
+                        format!(
+                            "zone {}: {} players peak, {:.1} mean, {}-{} monsters, {} msg/s peak",
+                            zone.zone,
+                            zone.peak_players,
+                            zone.mean_players,
+                            zone.min_monsters,
+                            zone.max_monsters,
+                            zone.peak_messages_per_sec
+                        )
+                    })
+                    .collect();
+                if lines.is_empty() {
+                    return Ok("no samples yet".to_string());
+                }
+                Ok(lines.join("\n"))
+            }
+            ["export", path] => {
+                let exported = history.export(path)?;
+                info!("Console exported zone populations to {}", path);
+                Ok(format!("wrote {} samples to {}", exported, path))
+            }
+            _ => Err(anyhow!("usage: population [export <path>]")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ro2_common::localization::Localization;
+
+    #[tokio::test]
+    async fn test_rates_and_reload() {
+        let rates = Arc::new(Rates::default());
+        let command = RatesCommand(Arc::clone(&rates));
+        assert_eq!(
+            command.run(&["exp", "3"]).await.unwrap(),
+            "exp 3 drop 1 zeny 1"
+        );
+        assert!(command.run(&["exp"]).await.is_err());
+        assert!(command.run(&["exp", "lots"]).await.is_err());
+
+        let path = std::env::temp_dir().join(format!("ro2-rates-{}.toml", std::process::id()));
+        std::fs::write(&path, "[rates]\ndrop = 2.5").unwrap();
+        let reload = ReloadCommand {
+            rates: Arc::clone(&rates),
+            path: path.clone(),
+        };
+        let reloaded = reload.run(&[]).await;
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(reloaded.unwrap(), "exp 1 drop 2.5 zeny 1");
+        assert_eq!(rates.get().drop, 2.5);
+    }
+
+    #[tokio::test]
+    async fn test_notice() {
+        let messenger = SystemMessenger::new(Arc::new(Localization::default()));
+        let (outbox, mut outbox_rx) = tokio::sync::mpsc::channel(1);
+        messenger.register(3, None, None, outbox);
+        let command = NoticeCommand(messenger);
+
+        assert_eq!(
+            command.run(&["player", "3", "hello"]).await.unwrap(),
+            "sent to 1 sessions"
+        );
+        assert!(outbox_rx.try_recv().is_ok());
+        assert!(command.run(&["everyone", "hello"]).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_global() {
+        let pool = ro2_common::testing::database().await;
+        let command = GlobalCommand(pool);
+
+        assert_eq!(
+            command.run(&["log"]).await.unwrap(),
+            "no global messages yet"
+        );
+        assert_eq!(
+            command.run(&["Double", "EXP", "tonight"]).await.unwrap(),
+            "posted to every channel"
+        );
+        let log = command.run(&["log"]).await.unwrap();
+        assert!(
+            log.ends_with("announcement\tconsole\tDouble EXP tonight"),
+            "{}",
+            log
+        );
+        assert!(command.run(&[]).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_stalls() {
+        use ro2_common::database::{VendingItem, VendingStall};
+
+        let pool = ro2_common::testing::database().await;
+        ro2_common::testing::character(&pool, 1, "Alice", 0).await;
+        let command = StallsCommand(pool.clone());
+        assert_eq!(command.run(&[]).await.unwrap(), "no stalls open");
+
+        let stall = VendingStall {
+            character_id: 1,
+            channel_id: 2,
+            title: "Potions".to_string(),
+            map_id: 3,
+            x: 10.0,
+            y: 0.0,
+            z: 20.0,
+            opened_at: 100,
+        };
+        let item = VendingItem {
+            character_id: 1,
+            stall_slot: 0,
+            inventory_slot: 4,
+            item_id: 501,
+            quantity: 5,
+            price: 50,
+        };
+        VendingQueries::open(&pool, &stall, &[item]).await.unwrap();
+        assert_eq!(
+            command.run(&[]).await.unwrap(),
+            "1\tchannel 2\tmap 3 (10, 20)\tPotions"
+        );
+        assert_eq!(
+            command.run(&["1"]).await.unwrap(),
+            "0\titem 501 x5\t50 zeny each"
+        );
+        assert!(command.run(&["alice"]).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_population() {
+        use crate::population::PopulationConfig;
+        use crate::world::{TickStats, ZoneId};
+
+        let history = Arc::new(Mutex::new(PopulationHistory::new(
+            PopulationConfig::default(),
+        )));
+        let command = PopulationCommand(Arc::clone(&history));
+        assert_eq!(command.run(&[]).await.unwrap(), "no samples yet");
+
+        let stats = TickStats {
+            clients: 4,
+            monsters: 12,
+            ..TickStats::default()
+        };
+        history.lock().unwrap().record(100, &[(ZoneId(3), stats)]);
+        assert_eq!(
+            command.run(&[]).await.unwrap(),
+            "zone 3: 4 players peak, 4.0 mean, 12-12 monsters, 0 msg/s peak"
+        );
+
+        let path =
+            std::env::temp_dir().join(format!("ro2-console-population-{}.csv", std::process::id()));
+        let exported = command.run(&["export", path.to_str().unwrap()]).await;
+        let csv = std::fs::read_to_string(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert!(exported.unwrap().starts_with("wrote 1 samples"));
+        assert!(csv.unwrap().ends_with("100,3,4,12,0,0,0\n"));
+        assert!(command.run(&["export"]).await.is_err());
+    }
+}
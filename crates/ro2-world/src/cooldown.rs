@@ -0,0 +1,416 @@
+//! Cooldowns and the global cooldown
+//!
+//! The client greys out skills, items and actions while they cool down,
+//! but only the server's word counts: every use goes through
+//! [`Cooldowns::try_use`] first. Skills and items also start the global
+//! cooldown (GCD), which blocks every other skill and item for a moment.
+//! Cooldowns are counted in zone ticks, so they expire with the zone's
+//! clock and cost nothing while nobody uses anything.
+//!
+//! A refused use is answered with [`NFY_COOLDOWN`], telling the client how
+//! long is left so it can fix its bars. A use that comes in well before
+//! the cooldown could have run out, more than network jitter explains, is
+//! a client skipping its own cooldowns; the [`Refusal`] is then marked
+//! [`suspicious`](Refusal::suspicious) and can be published as a
+//! [`Violation`].
+//!
+//! Durations are set in the `[cooldowns]` section of `config/world.toml`:
+//!
+//! ```toml
+//! [cooldowns]
+//! gcd_ms = 1000           # Global cooldown after a skill or item
+//! item_ms = 1000          # Before the same item can be used again
+//! emote_ms = 2000
+//! mount_ms = 3000
+//! tolerance_ms = 250      # How early a use may come before it's suspicious
+//! ```
+//!
+//! Like the other `0x3Fxx` opcodes, [`NFY_COOLDOWN`] is a placeholder.
+
+use crate::world::{Broadcaster, EntityId};
+use anyhow::{Result, anyhow};
+use ro2_common::config::{self, Validate};
+use ro2_common::events::Violation;
+use ro2_common::wire::WireWriter;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+use std::time::Duration;
+
+pub use ro2_common::protocol::opcodes::NFY_COOLDOWN;
+
+/// Cooldown durations
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct CooldownConfig {
+    pub gcd_ms: u32,
+    pub item_ms: u32,
+    pub emote_ms: u32,
+    pub mount_ms: u32,
+    pub tolerance_ms: u32,
+}
+
+impl Default for CooldownConfig {
+    fn default() -> Self {
+        Self {
+            gcd_ms: 1000,
+            item_ms: 1000,
+            emote_ms: 2000,
+            mount_ms: 3000,
+            tolerance_ms: 250,
+        }
+    }
+}
+
+impl CooldownConfig {
+    /// Read the `[cooldowns]` section of `path`; missing keys keep their
+    /// defaults
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        config::section(path, "cooldowns")
+    }
+
+    /// Parse the `[cooldowns]` section of TOML text
+    pub fn from_toml(text: &str) -> Result<Self> {
+        config::section_from_toml(text, "cooldowns")
+    }
+
+    /// How long `action` cools down for
+    pub fn action(&self, action: Action) -> Duration {
+        let ms = match action {
+            Action::Emote => self.emote_ms,
+            Action::Mount => self.mount_ms,
+        };
+        Duration::from_millis(ms as u64)
+    }
+
+    pub fn item(&self) -> Duration {
+        Duration::from_millis(self.item_ms as u64)
+    }
+}
+
+impl Validate for CooldownConfig {
+    fn validate(&self) -> Result<()> {
+        // As long as the GCD and skipping it could never be suspicious
+        if self.gcd_ms > 0 && self.tolerance_ms >= self.gcd_ms {
+            return Err(anyhow!("cooldown tolerance_ms must be under gcd_ms"));
+        }
+        Ok(())
+    }
+}
+
+/// Something players do that has a cooldown of its own, besides skills
+/// and items
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(u8)]
+pub enum Action {
+    Emote = 1,
+    Mount = 2,
+}
+
+/// What is cooling down
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CooldownKey {
+    /// A skill, by skill ID
+    Skill(i32),
+    /// An item, by item ID
+    Item(i32),
+    Action(Action),
+    /// The global cooldown every skill and item starts
+    Global,
+}
+
+impl CooldownKey {
+    /// Whether it starts and waits for the global cooldown
+    pub fn uses_gcd(&self) -> bool {
+        matches!(self, Self::Skill(_) | Self::Item(_))
+    }
+
+    /// Kind byte and ID as sent in [`NFY_COOLDOWN`]
+    fn wire(&self) -> (u8, u32) {
+        match *self {
+            Self::Global => (0, 0),
+            Self::Skill(id) => (1, id as u32),
+            Self::Item(id) => (2, id as u32),
+            Self::Action(action) => (3, action as u8 as u32),
+        }
+    }
+}
+
+/// A use refused because something was still cooling down
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Refusal {
+    /// What was still cooling down; [`CooldownKey::Global`] if only the
+    /// GCD was
+    pub key: CooldownKey,
+    pub remaining: Duration,
+    /// Too early for network jitter to explain
+    pub suspicious: bool,
+}
+
+impl Refusal {
+    /// Encode [`NFY_COOLDOWN`]: u8 kind (0 = global, 1 = skill, 2 = item,
+    /// 3 = action), u32 ID and u32 milliseconds left
+    pub fn encode(&self) -> Vec<u8> {
+        let (kind, id) = self.key.wire();
+        let mut out = WireWriter::message(NFY_COOLDOWN);
+        out.u8(kind).u32(id).u32(self.remaining.as_millis() as u32);
+        out.into_bytes()
+    }
+
+    /// The anti-cheat report of a suspicious refusal
+    pub fn violation(&self, session_id: u64, account_id: Option<u32>) -> Violation {
+        Violation {
+            session_id,
+            account_id,
+            kind: "cooldown".to_string(),
+            detail: self.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Refusal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:?} is cooling down for another {} ms",
+            self.key,
+            self.remaining.as_millis()
+        )
+    }
+}
+
+impl std::error::Error for Refusal {}
+
+/// Every player's cooldowns in one zone
+#[derive(Debug)]
+pub struct Cooldowns {
+    config: CooldownConfig,
+    tick_interval: Duration,
+    tick: u64,
+    /// Tick each key is ready again at
+    ready_at: HashMap<EntityId, HashMap<CooldownKey, u64>>,
+}
+
+impl Cooldowns {
+    pub fn new(config: CooldownConfig, tick_interval: Duration) -> Self {
+        Self {
+            config,
+            tick_interval,
+            tick: 0,
+            ready_at: HashMap::new(),
+        }
+    }
+
+    pub fn config(&self) -> &CooldownConfig {
+        &self.config
+    }
+
+    /// Advance one zone tick, forgetting what's done cooling down
+    pub fn tick(&mut self) {
+        self.tick += 1;
+        let now = self.tick;
+        self.ready_at.retain(|_, keys| {
+            keys.retain(|_, ready_at| *ready_at > now);
+            !keys.is_empty()
+        });
+    }
+
+    /// Time left on `entity`'s `key`
+    pub fn remaining(&self, entity: EntityId, key: CooldownKey) -> Duration {
+        let ready_at = self
+            .ready_at
+            .get(&entity)
+            .and_then(|keys| keys.get(&key))
+            .copied()
+            .unwrap_or(0);
+        self.tick_interval * ready_at.saturating_sub(self.tick) as u32
+    }
+
+    /// Check `entity` may use `key` now: it and, for skills and items, the
+    /// GCD are done cooling down
+    pub fn check(&self, entity: EntityId, key: CooldownKey) -> std::result::Result<(), Refusal> {
+        let mut blocking = (key, self.remaining(entity, key));
+        if key.uses_gcd() {
+            let gcd = self.remaining(entity, CooldownKey::Global);
+            if gcd > blocking.1 {
+                blocking = (CooldownKey::Global, gcd);
+            }
+        }
+        let (blocked_by, remaining) = blocking;
+        if !remaining.is_zero() {
+            return Err(Refusal {
+                key: blocked_by,
+                remaining,
+                suspicious: remaining > Duration::from_millis(self.config.tolerance_ms as u64),
+            });
+        }
+        Ok(())
+    }
+
+    /// [`check`](Self::check) `key`, and if it may be used start its
+    /// `cooldown` and, for skills and items, the GCD
+    pub fn try_use(
+        &mut self,
+        entity: EntityId,
+        key: CooldownKey,
+        cooldown: Duration,
+    ) -> std::result::Result<(), Refusal> {
+        self.check(entity, key)?;
+        self.start_use(entity, key, cooldown);
+        Ok(())
+    }
+
+    /// [`check`](Self::check) `key` for a handler, sending the player's
+    /// client [`NFY_COOLDOWN`] if it's refused; the error is the
+    /// [`Refusal`]
+    pub fn enforce(
+        &self,
+        broadcaster: &Broadcaster,
+        entity: EntityId,
+        key: CooldownKey,
+    ) -> Result<()> {
+        self.check(entity, key).map_err(|refusal| {
+            broadcaster.send_to(entity, refusal.encode());
+            refusal.into()
+        })
+    }
+
+    /// Start `entity`'s `key` cooling down for `cooldown`, and for skills
+    /// and items the GCD; for uses that can only be checked up front and
+    /// counted once they've worked
+    pub fn start_use(&mut self, entity: EntityId, key: CooldownKey, cooldown: Duration) {
+        self.start(entity, key, cooldown);
+        if key.uses_gcd() {
+            let gcd = Duration::from_millis(self.config.gcd_ms as u64);
+            self.start(entity, CooldownKey::Global, gcd);
+        }
+    }
+
+    /// Start `entity`'s `key` cooling down for `cooldown`, e.g. when a
+    /// skill's cooldown is reset by something else
+    pub fn start(&mut self, entity: EntityId, key: CooldownKey, cooldown: Duration) {
+        let ticks = cooldown.as_secs_f64() / self.tick_interval.as_secs_f64();
+        let ticks = ticks.ceil() as u64;
+        if ticks > 0 {
+            self.ready_at
+                .entry(entity)
+                .or_default()
+                .insert(key, self.tick + ticks);
+        }
+    }
+
+    /// Forget `entity`, when it leaves the zone
+    pub fn remove(&mut self, entity: EntityId) {
+        self.ready_at.remove(&entity);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TICK: Duration = Duration::from_millis(100);
+
+    fn cooldowns() -> Cooldowns {
+        Cooldowns::new(CooldownConfig::default(), TICK)
+    }
+
+    #[test]
+    fn test_load() {
+        assert_eq!(
+            CooldownConfig::load("does/not/exist.toml").unwrap(),
+            CooldownConfig::default()
+        );
+        let config = CooldownConfig::from_toml("[cooldowns]\nmount_ms = 500").unwrap();
+        assert_eq!(config.action(Action::Mount), Duration::from_millis(500));
+        assert!(CooldownConfig::from_toml("[cooldowns]\ntolerance_ms = 1000").is_err());
+    }
+
+    #[test]
+    fn test_cooldown_expires_with_ticks() {
+        let mut cooldowns = cooldowns();
+        let player = EntityId(1);
+        let emote = CooldownKey::Action(Action::Emote);
+        let two_seconds = Duration::from_secs(2);
+
+        cooldowns.try_use(player, emote, two_seconds).unwrap();
+        let refusal = cooldowns.try_use(player, emote, two_seconds).unwrap_err();
+        assert_eq!(refusal.key, emote);
+        assert_eq!(refusal.remaining, two_seconds);
+        assert!(refusal.suspicious);
+
+        // Other players and keys aren't affected, and actions don't use the
+        // GCD
+        cooldowns.try_use(EntityId(2), emote, two_seconds).unwrap();
+        cooldowns
+            .try_use(player, CooldownKey::Skill(100), Duration::ZERO)
+            .unwrap();
+
+        for _ in 0..18 {
+            cooldowns.tick();
+        }
+        // 200 ms early is within the tolerance
+        let refusal = cooldowns.try_use(player, emote, two_seconds).unwrap_err();
+        assert_eq!(refusal.remaining, Duration::from_millis(200));
+        assert!(!refusal.suspicious);
+
+        cooldowns.tick();
+        cooldowns.tick();
+        cooldowns.try_use(player, emote, two_seconds).unwrap();
+    }
+
+    #[test]
+    fn test_global_cooldown() {
+        let mut cooldowns = cooldowns();
+        let player = EntityId(1);
+
+        cooldowns
+            .try_use(player, CooldownKey::Skill(100), Duration::from_secs(5))
+            .unwrap();
+        // Another skill waits for the GCD
+        let refusal = cooldowns
+            .try_use(player, CooldownKey::Item(501), Duration::from_millis(500))
+            .unwrap_err();
+        assert_eq!(refusal.key, CooldownKey::Global);
+        assert_eq!(refusal.remaining, Duration::from_secs(1));
+
+        for _ in 0..10 {
+            cooldowns.tick();
+        }
+        cooldowns
+            .try_use(player, CooldownKey::Item(501), Duration::from_millis(500))
+            .unwrap();
+        // The skill's own cooldown outlasts the GCD
+        let refusal = cooldowns
+            .try_use(player, CooldownKey::Skill(100), Duration::from_secs(5))
+            .unwrap_err();
+        assert_eq!(refusal.key, CooldownKey::Skill(100));
+        assert_eq!(refusal.remaining, Duration::from_secs(4));
+
+        cooldowns.remove(player);
+        assert!(
+            cooldowns
+                .remaining(player, CooldownKey::Skill(100))
+                .is_zero()
+        );
+    }
+
+    #[test]
+    fn test_refusal_packet() {
+        let refusal = Refusal {
+            key: CooldownKey::Skill(100),
+            remaining: Duration::from_millis(1500),
+            suspicious: true,
+        };
+        let mut expected = NFY_COOLDOWN.to_le_bytes().to_vec();
+        expected.push(1);
+        expected.extend_from_slice(&100u32.to_le_bytes());
+        expected.extend_from_slice(&1500u32.to_le_bytes());
+        assert_eq!(refusal.encode(), expected);
+
+        let violation = refusal.violation(3, Some(7));
+        assert_eq!(violation.kind, "cooldown");
+        assert!(violation.detail.contains("1500 ms"));
+    }
+}
@@ -0,0 +1,623 @@
+//! Event currencies and their exchange shops
+//!
+//! A time-limited event has a currency of its own, kept per character in
+//! `event_currency` (see [`EventCurrencyQueries`]). While the event runs,
+//! monsters killed drop it straight into the killer's balance at the
+//! server's drop rate; afterwards it can still be spent until it expires,
+//! and then it's gone. Exchange shop NPCs sell rewards for it: the
+//! currency is spent and the reward given in one [`ItemTransaction`], so
+//! neither happens without the other, and an exchange the client resends
+//! is only made once.
+//!
+//! Currencies and shops are data, read from `config/events.toml`:
+//!
+//! ```toml
+//! [[currencies]]
+//! id = 1
+//! name = "Pumpkin Token"
+//! starts_at = 1761868800      # Unix time drops start
+//! ends_at = 1762473600        # and stop
+//! expires_at = 1763078400     # Spendable until; left out = ends_at
+//! drops = [
+//!     { chance = 0.05 },                          # Any monster, 1 token
+//!     { monster = 1001, chance = 0.5, amount = 3 },
+//! ]
+//!
+//! [[shops]]
+//! npc = 5001
+//! currency = 1
+//! rewards = [
+//!     { item_id = 501, quantity = 5, price = 10 },
+//!     { item_id = 12221, price = 50 },
+//! ]
+//! ```
+//!
+//! The killer isn't told about a drop yet, since kills don't say which
+//! session made them; the shop shows the balance. Like the other `0x3Fxx`
+//! opcodes, these are placeholders.
+
+use crate::inventory::{Inventory, ItemStack, MAX_STACK};
+use crate::journal;
+use crate::rates::Rates;
+use crate::world::Load;
+use anyhow::{Result, anyhow};
+use rand::Rng;
+use ro2_common::clock::Clock;
+use ro2_common::config::{self, Validate};
+use ro2_common::database::queries::EventCurrencyQueries;
+use ro2_common::database::{ItemTransaction, Outcome, Refusal, TransactionKind};
+use ro2_common::events::{EventBus, MonsterKilled};
+use ro2_common::wire::{WireReader, WireWriter};
+use serde::Deserialize;
+use sqlx::{Pool, Sqlite};
+use std::collections::HashSet;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+pub use ro2_common::protocol::opcodes::{
+    ACK_EVENT_EXCHANGE, ANS_EVENT_SHOP, REQ_EVENT_EXCHANGE, REQ_EVENT_SHOP,
+};
+
+/// Where the world server reads event currencies and shops from
+pub const EVENTS_PATH: &str = "config/events.toml";
+
+fn one() -> i64 {
+    1
+}
+
+/// A chance of currency from each kill
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub struct CurrencyDrop {
+    /// Monster ID; 0 or left out = any monster
+    #[serde(default)]
+    pub monster: u32,
+    /// Before the drop rate
+    pub chance: f64,
+    #[serde(default = "one")]
+    pub amount: i64,
+}
+
+/// One event's currency
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct EventCurrency {
+    pub id: i32,
+    pub name: String,
+    pub starts_at: i64,
+    pub ends_at: i64,
+    #[serde(default)]
+    pub expires_at: Option<i64>,
+    #[serde(default)]
+    pub drops: Vec<CurrencyDrop>,
+}
+
+impl EventCurrency {
+    /// Whether monsters drop it at `now`
+    pub fn is_running(&self, now: i64) -> bool {
+        (self.starts_at..self.ends_at).contains(&now)
+    }
+
+    /// When balances of it are gone
+    pub fn expires_at(&self) -> i64 {
+        self.expires_at.unwrap_or(self.ends_at)
+    }
+}
+
+/// Something an exchange shop sells
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub struct Reward {
+    pub item_id: i32,
+    #[serde(default = "default_quantity")]
+    pub quantity: i32,
+    pub price: i64,
+}
+
+fn default_quantity() -> i32 {
+    1
+}
+
+/// An NPC exchanging one currency for rewards
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct ExchangeShop {
+    pub npc: u32,
+    pub currency: i32,
+    /// Picked by their position
+    pub rewards: Vec<Reward>,
+}
+
+/// Every event currency and exchange shop
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct EventData {
+    #[serde(default)]
+    pub currencies: Vec<EventCurrency>,
+    #[serde(default)]
+    pub shops: Vec<ExchangeShop>,
+}
+
+impl EventData {
+    /// Read `path`; a missing file means no events
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        config::load(path)
+    }
+
+    /// Parse TOML text
+    pub fn from_toml(text: &str) -> Result<Self> {
+        config::from_toml(text)
+    }
+
+    pub fn currency(&self, id: i32) -> Option<&EventCurrency> {
+        self.currencies.iter().find(|currency| currency.id == id)
+    }
+
+    pub fn shop(&self, npc: u32) -> Option<&ExchangeShop> {
+        self.shops.iter().find(|shop| shop.npc == npc)
+    }
+
+    /// Roll the currency killing `monster_id` at `now` drops: each running
+    /// currency's ID and amount
+    pub fn roll_drops(
+        &self,
+        monster_id: u32,
+        now: i64,
+        rates: &Rates,
+        rng: &mut impl Rng,
+    ) -> Vec<(i32, i64)> {
+        let mut dropped = Vec::new();
+        for currency in self.currencies.iter().filter(|c| c.is_running(now)) {
+            let amount: i64 = currency
+                .drops
+                .iter()
+                .filter(|drop| drop.monster == 0 || drop.monster == monster_id)
+                .filter(|drop| rng.gen_bool(rates.drop_chance(drop.chance)))
+                .map(|drop| drop.amount)
+                .sum();
+            if amount > 0 {
+                dropped.push((currency.id, amount));
+            }
+        }
+        dropped
+    }
+}
+
+impl Validate for EventData {
+    fn validate(&self) -> Result<()> {
+        let mut ids = HashSet::new();
+        for currency in &self.currencies {
+            if !ids.insert(currency.id) {
+                return Err(anyhow!("two currencies with id {}", currency.id));
+            }
+            if currency.starts_at >= currency.ends_at || currency.expires_at() < currency.ends_at {
+                return Err(anyhow!(
+                    "currency {}: starts_at, ends_at and expires_at are out of order",
+                    currency.id
+                ));
+            }
+            for drop in &currency.drops {
+                if !(drop.chance > 0.0 && drop.chance <= 1.0) || drop.amount < 1 {
+                    return Err(anyhow!(
+                        "currency {}: drop chances must be over 0 and at most 1, amounts at least 1",
+                        currency.id
+                    ));
+                }
+            }
+        }
+        let mut npcs = HashSet::new();
+        for shop in &self.shops {
+            if !npcs.insert(shop.npc) {
+                return Err(anyhow!("two shops for NPC {}", shop.npc));
+            }
+            if !ids.contains(&shop.currency) {
+                return Err(anyhow!("shop {}: no currency {}", shop.npc, shop.currency));
+            }
+            for reward in &shop.rewards {
+                if reward.price < 1 || !(1..=MAX_STACK).contains(&reward.quantity) {
+                    return Err(anyhow!(
+                        "shop {}: reward {} needs a price and a quantity of 1 to {}",
+                        shop.npc,
+                        reward.item_id,
+                        MAX_STACK
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Credit the currency each [`MonsterKilled`] on `events` drops, until the
+/// bus closes
+pub fn spawn_drops(
+    pool: Pool<Sqlite>,
+    data: Arc<EventData>,
+    rates: Arc<Rates>,
+    clock: Clock,
+    events: &EventBus,
+) -> JoinHandle<()> {
+    let mut kills = events.subscribe::<MonsterKilled>();
+    tokio::spawn(async move {
+        while let Some(kill) = kills.recv().await {
+            let now = clock.unix();
+            let dropped = data.roll_drops(kill.monster_id, now, &rates, &mut rand::thread_rng());
+            for (currency_id, amount) in dropped {
+                let expires_at = data.currency(currency_id).map_or(now, |c| c.expires_at());
+                let character_id = i64::from(kill.character_id);
+                if let Err(e) = EventCurrencyQueries::add(
+                    &pool,
+                    character_id,
+                    currency_id,
+                    amount,
+                    expires_at,
+                    now,
+                )
+                .await
+                {
+                    warn!(
+                        "Crediting character {} with event currency {} failed: {}",
+                        character_id, currency_id, e
+                    );
+                }
+            }
+        }
+    })
+}
+
+/// Delete expired event currency every `interval`; runs are skipped while
+/// the world sheds `load`
+pub fn spawn_purge(pool: Pool<Sqlite>, clock: Clock, interval: Duration, load: Load) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(interval);
+        loop {
+            interval.tick().await;
+            if load.is_overloaded() {
+                continue;
+            }
+            match EventCurrencyQueries::purge_expired(&pool, clock.unix()).await {
+                Ok(0) => {}
+                Ok(purged) => info!("Purged {} expired event currency balances", purged),
+                Err(e) => warn!("Purging expired event currency failed: {}", e),
+            }
+        }
+    });
+}
+
+/// An exchange as the client asks for it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExchangeRequest {
+    /// Picked by the client; the same ID is only exchanged once
+    pub request_id: u32,
+    pub npc: u32,
+    /// Position in the shop's rewards
+    pub reward: u16,
+    /// Where the reward goes
+    pub inventory_slot: u16,
+}
+
+/// How an exchange went, as sent in [`ACK_EVENT_EXCHANGE`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ExchangeResult {
+    Done = 0,
+    NotEnough = 1,
+    /// The currency has expired
+    Expired = 2,
+    /// The inventory slot holds something the reward can't stack on
+    SlotTaken = 3,
+}
+
+/// Parse a shop request: u32 NPC ID
+pub fn parse_req_event_shop(message: &[u8]) -> Result<u32> {
+    let npc = WireReader::message(message, REQ_EVENT_SHOP, "event shop request")?.u32("npc")?;
+    Ok(npc)
+}
+
+/// Build [`ANS_EVENT_SHOP`]: u32 NPC ID, u32 currency ID, i64 balance, i64
+/// expiry, u16 reward count, then each reward's i32 item ID, i32 quantity
+/// and i64 price
+pub fn build_ans_event_shop(shop: &ExchangeShop, balance: i64, expires_at: i64) -> Vec<u8> {
+    let mut out = WireWriter::message(ANS_EVENT_SHOP);
+    out.u32(shop.npc)
+        .u32(shop.currency as u32)
+        .i64(balance)
+        .i64(expires_at)
+        .u16(shop.rewards.len() as u16);
+    for reward in &shop.rewards {
+        out.i32(reward.item_id)
+            .i32(reward.quantity)
+            .i64(reward.price);
+    }
+    out.into_bytes()
+}
+
+/// Parse an exchange: u32 request ID, u32 NPC ID, u16 reward, u16
+/// inventory slot
+pub fn parse_req_event_exchange(message: &[u8]) -> Result<ExchangeRequest> {
+    let mut message = WireReader::message(message, REQ_EVENT_EXCHANGE, "event exchange request")?;
+    Ok(ExchangeRequest {
+        request_id: message.u32("request_id")?,
+        npc: message.u32("npc")?,
+        reward: message.u16("reward")?,
+        inventory_slot: message.u16("inventory_slot")?,
+    })
+}
+
+/// Build [`ACK_EVENT_EXCHANGE`]: the request ID, an [`ExchangeResult`] and
+/// the i64 balance left
+pub fn build_ack_event_exchange(request_id: u32, result: ExchangeResult, balance: i64) -> Vec<u8> {
+    let mut out = WireWriter::message(ACK_EVENT_EXCHANGE);
+    out.u32(request_id).u8(result as u8).i64(balance);
+    out.into_bytes()
+}
+
+fn shop_and_currency(data: &EventData, npc: u32) -> Result<(&ExchangeShop, &EventCurrency)> {
+    let shop = data
+        .shop(npc)
+        .ok_or_else(|| anyhow!("no event shop at NPC {}", npc))?;
+    // Validated to exist
+    let currency = data
+        .currency(shop.currency)
+        .ok_or_else(|| anyhow!("no event currency {}", shop.currency))?;
+    Ok((shop, currency))
+}
+
+/// Handle [`REQ_EVENT_SHOP`]: the [`ANS_EVENT_SHOP`] to send back
+pub async fn handle_req_event_shop(
+    pool: &Pool<Sqlite>,
+    data: &EventData,
+    character_id: i64,
+    message: &[u8],
+    now: i64,
+) -> Result<Vec<u8>> {
+    let (shop, currency) = shop_and_currency(data, parse_req_event_shop(message)?)?;
+    let balance = EventCurrencyQueries::balance(pool, character_id, currency.id, now).await?;
+    Ok(build_ans_event_shop(shop, balance, currency.expires_at()))
+}
+
+/// Handle [`REQ_EVENT_EXCHANGE`] from `character_id`: the
+/// [`ACK_EVENT_EXCHANGE`] to send back, and the inventory slots to journal
+pub async fn handle_req_event_exchange(
+    pool: &Pool<Sqlite>,
+    data: &EventData,
+    inventory: &mut Inventory,
+    character_id: i64,
+    message: &[u8],
+    now: i64,
+) -> Result<(Vec<u8>, Vec<usize>)> {
+    let request = parse_req_event_exchange(message)?;
+    let (shop, currency) = shop_and_currency(data, request.npc)?;
+    let reward = *shop
+        .rewards
+        .get(usize::from(request.reward))
+        .ok_or_else(|| anyhow!("event shop {} has no reward {}", shop.npc, request.reward))?;
+    let slot = usize::from(request.inventory_slot);
+    let held = *inventory
+        .slots()
+        .get(slot)
+        .ok_or_else(|| anyhow!("no inventory slot {}", slot))?;
+
+    let transaction = ItemTransaction::new(
+        TransactionKind::EventExchange,
+        format!("event:{}:{}", character_id, request.request_id),
+    )
+    .take_event_currency(character_id, currency.id, reward.price)
+    .give_item(character_id, slot as i32, reward.item_id, reward.quantity);
+    let (result, slots) = if transaction.is_done(pool).await? {
+        // Already made when the client first sent it
+        (ExchangeResult::Done, vec![])
+    } else if now >= currency.expires_at() {
+        (ExchangeResult::Expired, vec![])
+    } else {
+        let after = match held {
+            None => ItemStack::new(reward.item_id, reward.quantity),
+            Some(stack)
+                if stack.item_id == reward.item_id
+                    && !stack.bound
+                    && stack.expires_at.is_none()
+                    && stack.quantity + reward.quantity <= MAX_STACK =>
+            {
+                ItemStack {
+                    quantity: stack.quantity + reward.quantity,
+                    ..stack
+                }
+            }
+            Some(_) => {
+                let balance =
+                    EventCurrencyQueries::balance(pool, character_id, currency.id, now).await?;
+                let ack = build_ack_event_exchange(
+                    request.request_id,
+                    ExchangeResult::SlotTaken,
+                    balance,
+                );
+                return Ok((ack, vec![]));
+            }
+        };
+        // The transaction works on what's saved, which may be behind
+        journal::replay(pool, &inventory.journal_entries(character_id, &[slot])).await?;
+        match transaction.apply(pool, now).await? {
+            Outcome::Done => {
+                inventory.set_slot(slot, Some(after))?;
+                info!(
+                    "Character {} exchanged {} {} for {} of item {}",
+                    character_id, reward.price, currency.name, reward.quantity, reward.item_id
+                );
+                (ExchangeResult::Done, vec![slot])
+            }
+            Outcome::AlreadyDone => (ExchangeResult::Done, vec![]),
+            Outcome::Refused(Refusal::NotEnoughCurrency { .. }) => {
+                (ExchangeResult::NotEnough, vec![])
+            }
+            Outcome::Refused(_) => (ExchangeResult::SlotTaken, vec![]),
+        }
+    };
+    let balance = EventCurrencyQueries::balance(pool, character_id, currency.id, now).await?;
+    Ok((
+        build_ack_event_exchange(request.request_id, result, balance),
+        slots,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rates::RateConfig;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+    use ro2_common::testing;
+
+    const POTION: i32 = 501;
+
+    fn data() -> EventData {
+        EventData::from_toml(
+            r#"
+            [[currencies]]
+            id = 1
+            name = "Pumpkin Token"
+            starts_at = 100
+            ends_at = 200
+            expires_at = 300
+            drops = [{ chance = 1.0 }, { monster = 1001, chance = 1.0, amount = 3 }]
+
+            [[shops]]
+            npc = 5001
+            currency = 1
+            rewards = [{ item_id = 501, quantity = 5, price = 10 }, { item_id = 1101, price = 50 }]
+            "#,
+        )
+        .unwrap()
+    }
+
+    async fn pool() -> Pool<Sqlite> {
+        let pool = testing::database().await;
+        testing::character(&pool, 1, "Alice", 0).await;
+        pool
+    }
+
+    fn exchange(request_id: u32, reward: u16) -> Vec<u8> {
+        let mut out = WireWriter::message(REQ_EVENT_EXCHANGE);
+        out.u32(request_id).u32(5001).u16(reward).u16(0);
+        out.into_bytes()
+    }
+
+    fn result(ack: &[u8]) -> (u8, i64) {
+        let mut reader = WireReader::message(ack, ACK_EVENT_EXCHANGE, "ack").unwrap();
+        reader.u32("request_id").unwrap();
+        (reader.u8("result").unwrap(), reader.i64("balance").unwrap())
+    }
+
+    #[test]
+    fn test_load() {
+        assert_eq!(
+            EventData::load("does/not/exist.toml").unwrap(),
+            EventData::default()
+        );
+        let currency = &data().currencies[0];
+        assert!(!currency.is_running(99) && currency.is_running(100) && !currency.is_running(200));
+        assert_eq!(currency.expires_at(), 300);
+
+        let event = "[[currencies]]\nid = 1\nname = \"A\"\nstarts_at = 1\nends_at = 2\n";
+        assert!(EventData::from_toml(event).is_ok());
+        for bad in [
+            format!("{event}{}", event),
+            event.replace("ends_at = 2", "ends_at = 1"),
+            format!("{event}expires_at = 1\n"),
+            format!("{event}drops = [{{ chance = 0.0 }}]\n"),
+            format!("{event}[[shops]]\nnpc = 1\ncurrency = 2\nrewards = []\n"),
+            format!(
+                "{event}[[shops]]\nnpc = 1\ncurrency = 1\nrewards = [{{ item_id = 1, price = 0 }}]\n"
+            ),
+        ] {
+            assert!(EventData::from_toml(&bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_roll_drops() {
+        let data = data();
+        let rates = Rates::new(RateConfig::default());
+        let mut rng = StdRng::seed_from_u64(1);
+        assert_eq!(data.roll_drops(1001, 150, &rates, &mut rng), [(1, 4)]);
+        assert_eq!(data.roll_drops(1002, 150, &rates, &mut rng), [(1, 1)]);
+        assert!(data.roll_drops(1001, 200, &rates, &mut rng).is_empty());
+        rates.set("drop", 0.0).unwrap();
+        assert!(data.roll_drops(1001, 150, &rates, &mut rng).is_empty());
+    }
+
+    #[test]
+    fn test_parse_and_build() {
+        let message = exchange(7, 1);
+        assert_eq!(
+            parse_req_event_exchange(&message).unwrap(),
+            ExchangeRequest {
+                request_id: 7,
+                npc: 5001,
+                reward: 1,
+                inventory_slot: 0
+            }
+        );
+        for cut in 0..message.len() {
+            assert!(parse_req_event_exchange(&message[..cut]).is_err());
+        }
+
+        let answer = build_ans_event_shop(&data().shops[0], 12, 300);
+        let mut reader = WireReader::message(&answer, ANS_EVENT_SHOP, "answer").unwrap();
+        assert_eq!(reader.u32("npc").unwrap(), 5001);
+        assert_eq!(reader.u32("currency").unwrap(), 1);
+        assert_eq!(reader.i64("balance").unwrap(), 12);
+        assert_eq!(reader.i64("expires_at").unwrap(), 300);
+        assert_eq!(reader.u16("count").unwrap(), 2);
+        assert_eq!(reader.remaining().len(), 2 * 16);
+    }
+
+    #[tokio::test]
+    async fn test_drops_and_exchange() {
+        let pool = pool().await;
+        let data = Arc::new(data());
+        let events = EventBus::new();
+        let drops = spawn_drops(
+            pool.clone(),
+            Arc::clone(&data),
+            Arc::new(Rates::new(RateConfig::default())),
+            Clock::at_unix(150),
+            &events,
+        );
+        for _ in 0..3 {
+            events.publish(MonsterKilled {
+                character_id: 1,
+                monster_id: 1001,
+                zone: 1,
+            });
+        }
+        drop(events);
+        drops.await.unwrap();
+
+        let mut shop = WireWriter::message(REQ_EVENT_SHOP);
+        shop.u32(5001);
+        let answer = handle_req_event_shop(&pool, &data, 1, &shop.into_bytes(), 150)
+            .await
+            .unwrap();
+        assert_eq!(answer, build_ans_event_shop(&data.shops[0], 12, 300));
+
+        let mut inventory = Inventory::new(4);
+        let mut buy = async |request_id, reward, now| {
+            let (ack, slots) = handle_req_event_exchange(
+                &pool,
+                &data,
+                &mut inventory,
+                1,
+                &exchange(request_id, reward),
+                now,
+            )
+            .await
+            .unwrap();
+            (result(&ack), slots)
+        };
+        assert_eq!(buy(1, 0, 250).await, ((0, 2), vec![0]));
+        // Resent: not bought again
+        assert_eq!(buy(1, 0, 250).await, ((0, 2), vec![]));
+        assert_eq!(buy(2, 1, 250).await, ((3, 2), vec![]));
+        assert_eq!(buy(3, 0, 250).await, ((1, 2), vec![]));
+        assert_eq!(buy(4, 0, 300).await, ((2, 0), vec![]));
+        assert_eq!(inventory.slots()[0], Some(ItemStack::new(POTION, 5)));
+    }
+}
@@ -0,0 +1,319 @@
+//! GM observer commands
+//!
+//! GMs investigating a report want to watch without being seen. These
+//! commands are only for sessions of GM accounts (`accounts.is_gm`); the
+//! caller checks before running them:
+//!
+//! ```text
+//! /gm invisible on|off      Hide from everyone but other GMs
+//! /gm goto <session>        Teleport to a player, in any zone
+//! /gm follow <session>|off  Stay with a player as they move
+//! /gm spectate <session>|off
+//!                           See what a player sees and get copies of
+//!                           what they're sent
+//! ```
+//!
+//! Following or spectating a player in another zone takes the GM there
+//! first. Spectating only copies what the player's zone sends them; a
+//! session's replies to its own requests aren't included.
+
+use crate::world::{EntityId, World, ZoneHandle, ZoneId};
+use anyhow::{Context, Result, anyhow};
+use tokio::sync::mpsc;
+use tracing::info;
+
+/// A GM command, naming other players by session ID
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GmCommand {
+    Invisible(bool),
+    Goto(u64),
+    Follow(Option<u64>),
+    Spectate(Option<u64>),
+}
+
+impl GmCommand {
+    /// The player the command is about, if any
+    pub fn target(&self) -> Option<u64> {
+        match *self {
+            Self::Invisible(_) => None,
+            Self::Goto(session_id) => Some(session_id),
+            Self::Follow(session_id) | Self::Spectate(session_id) => session_id,
+        }
+    }
+}
+
+/// Parse a `/gm` command
+pub fn parse_command(line: &str) -> Result<GmCommand> {
+    const USAGE: &str = "usage: /gm invisible on|off | goto <session> | follow <session>|off | spectate <session>|off";
+
+    let rest = line
+        .trim()
+        .strip_prefix("/gm")
+        .ok_or_else(|| anyhow!("not a /gm command"))?;
+    let words: Vec<&str> = rest.split_whitespace().collect();
+    let [command, argument] = words[..] else {
+        return Err(anyhow!(USAGE));
+    };
+    let session = |word: &str| -> Result<Option<u64>> {
+        match word {
+            "off" => Ok(None),
+            id => id.parse().map(Some).context("bad session id"),
+        }
+    };
+    Ok(match command {
+        "invisible" => match argument {
+            "on" => GmCommand::Invisible(true),
+            "off" => GmCommand::Invisible(false),
+            _ => return Err(anyhow!(USAGE)),
+        },
+        "goto" => GmCommand::Goto(argument.parse().context("bad session id")?),
+        "follow" => GmCommand::Follow(session(argument)?),
+        "spectate" => GmCommand::Spectate(session(argument)?),
+        _ => return Err(anyhow!(USAGE)),
+    })
+}
+
+/// A session's place in the world
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Presence {
+    pub session_id: u64,
+    pub zone: ZoneId,
+    pub entity: EntityId,
+}
+
+/// Run `command` for the GM at `gm`, whose connection's outbox is
+/// `outbox`; `target` is where the player it names is
+///
+/// Returns where the GM is afterwards, which changes when they're taken
+/// to another zone.
+pub async fn execute(
+    world: &World,
+    gm: Presence,
+    outbox: &mpsc::Sender<Vec<u8>>,
+    command: GmCommand,
+    target: Option<Presence>,
+) -> Result<Presence> {
+    let here = zone(world, gm.zone)?;
+    if command.target().is_some() && target.is_none() {
+        return Err(anyhow!("no such player online"));
+    }
+    info!("GM session {} runs {:?}", gm.session_id, command);
+
+    match (command, target) {
+        (GmCommand::Invisible(invisible), _) => {
+            here.set_invisible(gm.entity, invisible).await?;
+            Ok(gm)
+        }
+        (GmCommand::Follow(None), _) => {
+            here.follow(gm.entity, None).await?;
+            Ok(gm)
+        }
+        (GmCommand::Spectate(None), _) => {
+            here.spectate(gm.session_id, None).await?;
+            Ok(gm)
+        }
+        (GmCommand::Goto(_), Some(target)) => go_to(world, gm, outbox, target, true).await,
+        (GmCommand::Follow(Some(_)), Some(target)) => {
+            let gm = go_to(world, gm, outbox, target, true).await?;
+            zone(world, gm.zone)?
+                .follow(gm.entity, Some(target.entity))
+                .await?;
+            Ok(gm)
+        }
+        (GmCommand::Spectate(Some(_)), Some(target)) => {
+            let gm = go_to(world, gm, outbox, target, false).await?;
+            if !zone(world, gm.zone)?
+                .spectate(gm.session_id, Some(target.entity))
+                .await?
+            {
+                return Err(anyhow!("can't spectate session {}", target.session_id));
+            }
+            Ok(gm)
+        }
+        (_, None) => unreachable!("checked above"),
+    }
+}
+
+fn zone(world: &World, id: ZoneId) -> Result<&ZoneHandle> {
+    world
+        .zone(id)
+        .ok_or_else(|| anyhow!("zone {} isn't running", id.0))
+}
+
+/// Take the GM to `target`'s zone, and with `beside` to the target
+/// itself; their session follows them to a new zone as a GM
+async fn go_to(
+    world: &World,
+    gm: Presence,
+    outbox: &mpsc::Sender<Vec<u8>>,
+    target: Presence,
+    beside: bool,
+) -> Result<Presence> {
+    if gm.zone == target.zone && !beside {
+        return Ok(gm);
+    }
+    let from = zone(world, gm.zone)?;
+    let to = zone(world, target.zone)?;
+    let moved = if beside || gm.zone != target.zone {
+        from.teleport_to(gm.entity, to, target.entity)
+            .await?
+            .ok_or_else(|| anyhow!("session {} has left", target.session_id))?
+    } else {
+        gm.entity
+    };
+
+    if gm.zone != target.zone {
+        from.unsubscribe(gm.session_id).await?;
+        to.subscribe(gm.session_id, moved, outbox.clone()).await?;
+        to.set_gm(gm.session_id, true).await?;
+    }
+    Ok(Presence {
+        entity: moved,
+        zone: target.zone,
+        ..gm
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::{Delta, EntityKind, Position, Zone};
+    use std::time::Duration;
+
+    #[test]
+    fn test_parse_command() {
+        assert_eq!(
+            parse_command("/gm invisible on").unwrap(),
+            GmCommand::Invisible(true)
+        );
+        assert_eq!(parse_command("/gm goto 42").unwrap(), GmCommand::Goto(42));
+        assert_eq!(
+            parse_command(" /gm follow off ").unwrap(),
+            GmCommand::Follow(None)
+        );
+        assert_eq!(
+            parse_command("/gm spectate 7").unwrap(),
+            GmCommand::Spectate(Some(7))
+        );
+        for bad in [
+            "/gm",
+            "/gm invisible",
+            "/gm invisible maybe",
+            "/gm goto off",
+            "/gm follow x",
+            "/gm fly 3",
+            "/gm goto 1 2",
+            "/notice all hi",
+        ] {
+            assert!(parse_command(bad).is_err(), "{:?}", bad);
+        }
+    }
+
+    async fn recv_delta(rx: &mut mpsc::Receiver<Vec<u8>>) -> Delta {
+        tokio::time::timeout(Duration::from_secs(1), async {
+            loop {
+                if let Ok(delta) = Delta::decode(&rx.recv().await.unwrap()) {
+                    return delta;
+                }
+            }
+        })
+        .await
+        .expect("no delta")
+    }
+
+    #[tokio::test]
+    async fn test_invisible_goto_and_spectate() {
+        let mut world = World::new(Duration::from_millis(5));
+        let town = world.start_zone(ZoneId(1), Zone::new());
+        let field = world.start_zone(ZoneId(2), Zone::new());
+
+        let gm_entity = town
+            .spawn(EntityKind::Player, Position::default(), 100)
+            .await
+            .unwrap();
+        let (gm_outbox, mut gm_rx) = mpsc::channel(64);
+        town.subscribe(1, gm_entity, gm_outbox.clone())
+            .await
+            .unwrap();
+        town.set_gm(1, true).await.unwrap();
+        let gm = Presence {
+            session_id: 1,
+            zone: ZoneId(1),
+            entity: gm_entity,
+        };
+
+        let player_entity = field
+            .spawn(EntityKind::Player, Position::new(50.0, 0.0, 0.0), 100)
+            .await
+            .unwrap();
+        let (outbox, mut player_rx) = mpsc::channel(64);
+        field.subscribe(2, player_entity, outbox).await.unwrap();
+        let player = Presence {
+            session_id: 2,
+            zone: ZoneId(2),
+            entity: player_entity,
+        };
+
+        let gm = execute(&world, gm, &gm_outbox, GmCommand::Invisible(true), None)
+            .await
+            .unwrap();
+        assert!(
+            execute(&world, gm, &gm_outbox, GmCommand::Goto(2), None)
+                .await
+                .is_err()
+        );
+
+        // The GM arrives beside the player, who never sees them
+        let gm = execute(&world, gm, &gm_outbox, GmCommand::Goto(2), Some(player))
+            .await
+            .unwrap();
+        assert_eq!(gm.zone, ZoneId(2));
+        let arrived = field.entity(gm.entity).await.unwrap().unwrap();
+        assert_eq!(arrived.position, Position::new(50.0, 0.0, 0.0));
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        while let Ok(message) = player_rx.try_recv() {
+            if let Ok(delta) = Delta::decode(&message) {
+                assert!(delta.entered.iter().all(|e| e.id != gm.entity));
+            }
+        }
+
+        // The GM's session came along and sees the player
+        let delta = recv_delta(&mut gm_rx).await;
+        assert!(delta.entered.iter().any(|e| e.id == player_entity));
+
+        let gm = execute(
+            &world,
+            gm,
+            &gm_outbox,
+            GmCommand::Spectate(Some(2)),
+            Some(player),
+        )
+        .await
+        .unwrap();
+        let gm = execute(
+            &world,
+            gm,
+            &gm_outbox,
+            GmCommand::Follow(Some(2)),
+            Some(player),
+        )
+        .await
+        .unwrap();
+        field
+            .move_entity(player_entity, Position::new(90.0, 0.0, 0.0), 0)
+            .await
+            .unwrap();
+        tokio::time::timeout(Duration::from_secs(1), async {
+            while field.entity(gm.entity).await.unwrap().unwrap().position.x != 90.0 {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .expect("GM didn't follow");
+
+        execute(&world, gm, &gm_outbox, GmCommand::Spectate(None), None)
+            .await
+            .unwrap();
+        assert!(town.entity(gm_entity).await.unwrap().is_none());
+    }
+}
@@ -0,0 +1,533 @@
+//! Guild storage: items and zeny a guild's members share
+//!
+//! What a member may do with it depends on their rank (see
+//! [`GuildRank`]): whether they can see it at all, whether they can put
+//! things in, and how many items and how much zeny they can take out a day.
+//! Rank 0, the guild master, has no limits; a rank without a row can't
+//! use the storage.
+//!
+//! Every deposit and withdrawal is one [`ItemTransaction`], which takes
+//! from one side, gives to the other and writes the guild's storage log
+//! together, refusing a withdrawal that goes past the day's limit. The log
+//! is what the limits are counted from, and the record of who took what
+//! (`ro2-lobby admin guild <id> log`). Each move carries a request ID, so
+//! one the client resends is only made once.
+//!
+//! The online character's inventory slot is written to the database before
+//! the transaction reads it, and the result copied back into [`Inventory`]
+//! after; the slot is returned for journaling like any other change. Bound
+//! items and rentals stay with their owner. Stacks in storage have no size
+//! limit, but a withdrawal still has to fit in one inventory slot.
+//!
+//! Like the other `0x3Fxx` opcodes, these are placeholders.
+
+use crate::inventory::{Inventory, ItemStack, MAX_STACK};
+use crate::journal;
+use anyhow::{Result, anyhow};
+use ro2_common::database::queries::{GuildQueries, GuildStorageQueries};
+use ro2_common::database::{
+    GuildRank, GuildStorageAction, GuildStorageUse, ItemTransaction, Outcome, Refusal,
+    TransactionKind,
+};
+use ro2_common::protocol::{ClientError, ErrorCode};
+use ro2_common::wire::{WireReader, WireWriter};
+use sqlx::{Pool, Sqlite};
+use tracing::info;
+
+pub use ro2_common::protocol::opcodes::{
+    ACK_GUILD_STORAGE_MOVE, ANS_GUILD_STORAGE, REQ_GUILD_STORAGE, REQ_GUILD_STORAGE_MOVE,
+};
+
+/// Slots in a guild's storage
+pub const GUILD_STORAGE_SLOTS: u16 = 100;
+
+/// A deposit or withdrawal as the client asks for it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MoveRequest {
+    /// Picked by the client; the same ID is only moved once
+    pub request_id: u32,
+    pub action: GuildStorageAction,
+    /// Ignored for zeny
+    pub inventory_slot: u16,
+    /// Ignored for zeny
+    pub storage_slot: u16,
+    /// Ignored for zeny
+    pub item_id: i32,
+    /// Items or zeny
+    pub amount: i64,
+}
+
+/// How a move went, as sent in [`ACK_GUILD_STORAGE_MOVE`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum MoveResult {
+    Done = 0,
+    /// The member's rank doesn't allow it, or the item can't be stored
+    NotAllowed = 1,
+    /// Over the rank's daily withdrawal limit
+    DailyLimit = 2,
+    /// Not that many items or that much zeny to move
+    NotEnough = 3,
+    /// The slot moved to holds something else, or would overflow
+    SlotTaken = 4,
+}
+
+impl From<Refusal> for MoveResult {
+    fn from(refusal: Refusal) -> Self {
+        match refusal {
+            Refusal::DailyLimit { .. } => Self::DailyLimit,
+            Refusal::MissingItem { .. }
+            | Refusal::MissingGuildItem { .. }
+            | Refusal::NotEnoughZeny { .. }
+            | Refusal::NotEnoughGuildZeny { .. }
+            | Refusal::NotEnoughCurrency { .. }
+            | Refusal::StallItemGone { .. } => Self::NotEnough,
+            Refusal::SlotTaken { .. } | Refusal::GuildSlotTaken { .. } => Self::SlotTaken,
+            Refusal::BoundItem { .. }
+            | Refusal::RentalItem { .. }
+            | Refusal::NoSuchCharacter { .. } => Self::NotAllowed,
+        }
+    }
+}
+
+fn action(code: u8) -> Result<GuildStorageAction> {
+    Ok(match code {
+        0 => GuildStorageAction::DepositItem,
+        1 => GuildStorageAction::WithdrawItem,
+        2 => GuildStorageAction::DepositZeny,
+        3 => GuildStorageAction::WithdrawZeny,
+        _ => return Err(anyhow!("unknown guild storage action {}", code)),
+    })
+}
+
+/// Parse a move: u32 request ID, u8 action (deposit item, withdraw item,
+/// deposit zeny, withdraw zeny), u16 inventory slot, u16 storage slot, i32
+/// item ID, i64 amount
+pub fn parse_req_guild_storage_move(message: &[u8]) -> Result<MoveRequest> {
+    let mut message = WireReader::message(
+        message,
+        REQ_GUILD_STORAGE_MOVE,
+        "guild storage move request",
+    )?;
+    Ok(MoveRequest {
+        request_id: message.u32("request_id")?,
+        action: action(message.u8("action")?)?,
+        inventory_slot: message.u16("inventory_slot")?,
+        storage_slot: message.u16("storage_slot")?,
+        item_id: message.i32("item_id")?,
+        amount: message.i64("amount")?,
+    })
+}
+
+/// Build [`ANS_GUILD_STORAGE`]: u64 zeny, u16 stack count, then each
+/// stack's u16 slot, i32 item ID and i32 quantity
+pub fn build_ans_guild_storage(zeny: i64, items: &[(u16, ItemStack)]) -> Vec<u8> {
+    let mut out = WireWriter::message(ANS_GUILD_STORAGE);
+    out.u64(zeny.max(0) as u64).u16(items.len() as u16);
+    for (slot, stack) in items {
+        out.u16(*slot).i32(stack.item_id).i32(stack.quantity);
+    }
+    out.into_bytes()
+}
+
+/// Build [`ACK_GUILD_STORAGE_MOVE`]: the request ID and a [`MoveResult`]
+pub fn build_ack_guild_storage_move(request_id: u32, result: MoveResult) -> Vec<u8> {
+    let mut out = WireWriter::message(ACK_GUILD_STORAGE_MOVE);
+    out.u32(request_id).u8(result as u8);
+    out.into_bytes()
+}
+
+/// A refusal with the text at `key` in the `[guild_storage]` string table
+fn refuse(key: &str) -> anyhow::Error {
+    ClientError::new(ErrorCode::NotAllowed, key).into()
+}
+
+/// The character's guild and what their rank may do; the error is a
+/// [`ClientError`] if they can't see the storage
+async fn member_rank(pool: &Pool<Sqlite>, character_id: i64) -> Result<GuildRank> {
+    let (guild_id, rank) = GuildQueries::membership(pool, character_id)
+        .await?
+        .ok_or_else(|| refuse("guild_storage.no_guild"))?;
+    GuildQueries::rank(pool, guild_id, rank)
+        .await?
+        .filter(|rank| rank.can_view)
+        .ok_or_else(|| refuse("guild_storage.rank"))
+}
+
+/// Handle [`REQ_GUILD_STORAGE`]: the [`ANS_GUILD_STORAGE`] to send back
+pub async fn handle_req_guild_storage(
+    pool: &Pool<Sqlite>,
+    character_id: i64,
+    message: &[u8],
+) -> Result<Vec<u8>> {
+    WireReader::message(message, REQ_GUILD_STORAGE, "guild storage request")?.finish()?;
+    let rank = member_rank(pool, character_id).await?;
+    let (zeny, items) = GuildStorageQueries::contents(pool, rank.guild_id).await?;
+    let items: Vec<_> = items
+        .into_iter()
+        .map(|item| {
+            (
+                item.slot_index as u16,
+                ItemStack::new(item.item_id, item.quantity),
+            )
+        })
+        .collect();
+    Ok(build_ans_guild_storage(zeny, &items))
+}
+
+/// Handle [`REQ_GUILD_STORAGE_MOVE`] from `character_id`: the
+/// [`ACK_GUILD_STORAGE_MOVE`] to send back, and the inventory slots to
+/// journal
+pub async fn handle_req_guild_storage_move(
+    pool: &Pool<Sqlite>,
+    inventory: &mut Inventory,
+    character_id: i64,
+    message: &[u8],
+    now: i64,
+) -> Result<(Vec<u8>, Vec<usize>)> {
+    let request = parse_req_guild_storage_move(message)?;
+    let rank = member_rank(pool, character_id).await?;
+    let (result, slots) = move_items(pool, inventory, character_id, &rank, &request, now).await?;
+    if result == MoveResult::Done {
+        info!(
+            "Character {} made guild {} storage move {}: {} {} of {}",
+            character_id,
+            rank.guild_id,
+            request.request_id,
+            request.action.as_str(),
+            request.amount,
+            request.item_id
+        );
+    }
+    Ok((
+        build_ack_guild_storage_move(request.request_id, result),
+        slots,
+    ))
+}
+
+async fn move_items(
+    pool: &Pool<Sqlite>,
+    inventory: &mut Inventory,
+    character_id: i64,
+    rank: &GuildRank,
+    request: &MoveRequest,
+    now: i64,
+) -> Result<(MoveResult, Vec<usize>)> {
+    use GuildStorageAction::*;
+
+    let is_item = matches!(request.action, DepositItem | WithdrawItem);
+    if request.amount <= 0 || (is_item && request.amount > MAX_STACK as i64) {
+        return Err(ClientError::new(ErrorCode::InvalidRequest, "guild_storage.bad_amount").into());
+    }
+    if is_item && request.storage_slot >= GUILD_STORAGE_SLOTS {
+        return Err(anyhow!("no guild storage slot {}", request.storage_slot));
+    }
+    let daily_limit = match request.action {
+        DepositItem | DepositZeny if !rank.can_deposit => {
+            return Ok((MoveResult::NotAllowed, vec![]));
+        }
+        DepositItem | DepositZeny => None,
+        WithdrawItem => rank.items_per_day,
+        WithdrawZeny => rank.zeny_per_day,
+    };
+    if daily_limit == Some(0) {
+        return Ok((MoveResult::NotAllowed, vec![]));
+    }
+
+    let guild_id = rank.guild_id;
+    let slot = usize::from(request.inventory_slot);
+    let storage_slot = i32::from(request.storage_slot);
+    let quantity = request.amount as i32;
+    let held = if is_item {
+        *inventory
+            .slots()
+            .get(slot)
+            .ok_or_else(|| anyhow!("no inventory slot {}", slot))?
+    } else {
+        None
+    };
+
+    let mut transaction = ItemTransaction::new(
+        TransactionKind::GuildStorage,
+        format!("guild:{}:{}:{}", guild_id, character_id, request.request_id),
+    );
+    if transaction.is_done(pool).await? {
+        // Already made when the client first sent it
+        return Ok((MoveResult::Done, vec![]));
+    }
+    let mut after = held;
+    // Checked here first so the common refusals don't need the database
+    transaction = match request.action {
+        DepositItem => {
+            let Some(stack) = held.filter(|stack| stack.item_id == request.item_id) else {
+                return Ok((MoveResult::NotEnough, vec![]));
+            };
+            if stack.bound || stack.expires_at.is_some() {
+                return Ok((MoveResult::NotAllowed, vec![]));
+            }
+            if stack.quantity < quantity {
+                return Ok((MoveResult::NotEnough, vec![]));
+            }
+            after = Some(ItemStack {
+                quantity: stack.quantity - quantity,
+                ..stack
+            });
+            transaction
+                .take_item(character_id, slot as i32, request.item_id, quantity)
+                .give_guild_item(guild_id, storage_slot, request.item_id, quantity)
+        }
+        WithdrawItem => {
+            let stack = match held {
+                None => ItemStack::new(request.item_id, quantity),
+                Some(stack)
+                    if stack.item_id == request.item_id
+                        && !stack.bound
+                        && stack.expires_at.is_none()
+                        && stack.quantity + quantity <= MAX_STACK =>
+                {
+                    ItemStack {
+                        quantity: stack.quantity + quantity,
+                        ..stack
+                    }
+                }
+                Some(_) => return Ok((MoveResult::SlotTaken, vec![])),
+            };
+            after = Some(stack);
+            transaction
+                .take_guild_item(guild_id, storage_slot, request.item_id, quantity)
+                .give_item(character_id, slot as i32, request.item_id, quantity)
+        }
+        DepositZeny => transaction
+            .take_zeny(character_id, request.amount)
+            .give_guild_zeny(guild_id, request.amount),
+        WithdrawZeny => transaction
+            .take_guild_zeny(guild_id, request.amount)
+            .give_zeny(character_id, request.amount),
+    };
+    let used = GuildStorageUse {
+        guild_id,
+        character_id,
+        action: request.action,
+        item_id: is_item.then_some(request.item_id),
+        amount: request.amount,
+    };
+    transaction = transaction.log_guild_use(used, daily_limit);
+
+    if is_item {
+        // The transaction works on what's saved, which may be behind
+        journal::replay(pool, &inventory.journal_entries(character_id, &[slot])).await?;
+    }
+    match transaction.apply(pool, now).await? {
+        Outcome::Done if is_item => {
+            inventory.set_slot(slot, after)?;
+            Ok((MoveResult::Done, vec![slot]))
+        }
+        Outcome::Done | Outcome::AlreadyDone => Ok((MoveResult::Done, vec![])),
+        Outcome::Refused(refusal) => Ok((refusal.into(), vec![])),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ro2_common::localization::Localization;
+    use ro2_common::testing;
+
+    const POTION: i32 = 501;
+
+    /// Alice (1) masters guild 1 and holds 10 potions in slot 0 and 1000
+    /// zeny; Bob (2) is a rank 1 member, who may take 5 potions and no zeny
+    /// a day; Carol (3) is rank 2, which has no row
+    async fn pool() -> Pool<Sqlite> {
+        let pool = testing::database().await;
+        testing::character(&pool, 1, "Alice", 1000).await;
+        testing::character(&pool, 2, "Bob", 0).await;
+        testing::character(&pool, 3, "Carol", 0).await;
+        let guild = GuildQueries::create(&pool, "Knights", 1, 0).await.unwrap();
+        GuildQueries::set_rank(
+            &pool,
+            &GuildRank {
+                guild_id: guild,
+                rank: 1,
+                name: "Member".to_string(),
+                can_view: true,
+                can_deposit: true,
+                items_per_day: Some(5),
+                zeny_per_day: Some(0),
+            },
+        )
+        .await
+        .unwrap();
+        GuildQueries::add_member(&pool, guild, 2, 1, 0)
+            .await
+            .unwrap();
+        GuildQueries::add_member(&pool, guild, 3, 2, 0)
+            .await
+            .unwrap();
+        pool
+    }
+
+    fn inventory(stack: Option<ItemStack>) -> Inventory {
+        let mut inventory = Inventory::new(4);
+        inventory.set_slot(0, stack).unwrap();
+        inventory
+    }
+
+    fn req(request_id: u32, action: u8, item_id: i32, amount: i64) -> Vec<u8> {
+        let mut out = WireWriter::message(REQ_GUILD_STORAGE_MOVE);
+        out.u32(request_id)
+            .u8(action)
+            .u16(0)
+            .u16(5)
+            .i32(item_id)
+            .i64(amount);
+        out.into_bytes()
+    }
+
+    fn result(ack: &[u8]) -> u8 {
+        ack[ack.len() - 1]
+    }
+
+    #[test]
+    fn test_parse_and_build() {
+        let message = req(9, 1, POTION, 3);
+        assert_eq!(
+            parse_req_guild_storage_move(&message).unwrap(),
+            MoveRequest {
+                request_id: 9,
+                action: GuildStorageAction::WithdrawItem,
+                inventory_slot: 0,
+                storage_slot: 5,
+                item_id: POTION,
+                amount: 3,
+            }
+        );
+        for cut in 0..message.len() {
+            assert!(parse_req_guild_storage_move(&message[..cut]).is_err());
+        }
+        assert!(parse_req_guild_storage_move(&req(9, 4, POTION, 3)).is_err());
+
+        let answer = build_ans_guild_storage(500, &[(5, ItemStack::new(POTION, 3))]);
+        let mut reader = WireReader::message(&answer, ANS_GUILD_STORAGE, "answer").unwrap();
+        assert_eq!(reader.u64("zeny").unwrap(), 500);
+        assert_eq!(reader.u16("count").unwrap(), 1);
+        assert_eq!(reader.u16("slot").unwrap(), 5);
+        assert_eq!(reader.i32("item_id").unwrap(), POTION);
+        assert_eq!(reader.i32("quantity").unwrap(), 3);
+        assert!(reader.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_deposit_and_withdraw() {
+        let pool = pool().await;
+        let mut alice = inventory(Some(ItemStack::new(POTION, 10)));
+        let (ack, slots) =
+            handle_req_guild_storage_move(&pool, &mut alice, 1, &req(1, 0, POTION, 8), 100)
+                .await
+                .unwrap();
+        assert_eq!((result(&ack), slots), (MoveResult::Done as u8, vec![0]));
+        assert_eq!(alice.slots()[0], Some(ItemStack::new(POTION, 2)));
+        // Resent: not moved again
+        let (ack, slots) =
+            handle_req_guild_storage_move(&pool, &mut alice, 1, &req(1, 0, POTION, 8), 100)
+                .await
+                .unwrap();
+        assert_eq!((result(&ack), slots), (MoveResult::Done as u8, vec![]));
+        assert_eq!(alice.slots()[0], Some(ItemStack::new(POTION, 2)));
+        let (ack, _) = handle_req_guild_storage_move(&pool, &mut alice, 1, &req(2, 2, 0, 400), 100)
+            .await
+            .unwrap();
+        assert_eq!(result(&ack), MoveResult::Done as u8);
+
+        let answer = handle_req_guild_storage(
+            &pool,
+            2,
+            &WireWriter::message(REQ_GUILD_STORAGE).into_bytes(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            answer,
+            build_ans_guild_storage(400, &[(5, ItemStack::new(POTION, 8))])
+        );
+
+        // Bob may take five potions a day and no zeny
+        let mut bob = inventory(None);
+        let mut withdraw = async |id, action, item_id, amount, now| {
+            let (ack, _) = handle_req_guild_storage_move(
+                &pool,
+                &mut bob,
+                2,
+                &req(id, action, item_id, amount),
+                now,
+            )
+            .await
+            .unwrap();
+            result(&ack)
+        };
+        assert_eq!(withdraw(3, 1, POTION, 4, 200).await, MoveResult::Done as u8);
+        assert_eq!(
+            withdraw(4, 1, POTION, 2, 300).await,
+            MoveResult::DailyLimit as u8
+        );
+        assert_eq!(
+            withdraw(5, 3, 0, 1, 300).await,
+            MoveResult::NotAllowed as u8
+        );
+        assert_eq!(
+            withdraw(6, 1, 1101, 1, 300).await,
+            MoveResult::SlotTaken as u8
+        );
+        assert_eq!(
+            withdraw(7, 1, POTION, 5, 200 + 86_400).await,
+            MoveResult::NotEnough as u8
+        );
+        assert_eq!(
+            withdraw(8, 1, POTION, 4, 200 + 86_400).await,
+            MoveResult::Done as u8
+        );
+        assert_eq!(bob.slots()[0], Some(ItemStack::new(POTION, 8)));
+        let saved: (i32,) = sqlx::query_as("SELECT quantity FROM inventory WHERE character_id = 2")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(saved.0, 8);
+
+        let log = GuildStorageQueries::log(&pool, 1, 10).await.unwrap();
+        assert_eq!(log.len(), 4);
+        assert_eq!(
+            (log[0].character_id, log[0].action.as_str()),
+            (2, "withdraw_item")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_refused() {
+        let pool = pool().await;
+        let open = WireWriter::message(REQ_GUILD_STORAGE).into_bytes();
+        for character_id in [3, 4] {
+            let error = handle_req_guild_storage(&pool, character_id, &open)
+                .await
+                .unwrap_err();
+            let refusal = error.downcast_ref::<ClientError>().unwrap();
+            assert_eq!(refusal.code, ErrorCode::NotAllowed);
+            assert_ne!(Localization::builtin().get("en", &refusal.key), refusal.key);
+        }
+
+        let mut alice = inventory(Some(ItemStack::new(POTION, 10).bound()));
+        let (ack, _) =
+            handle_req_guild_storage_move(&pool, &mut alice, 1, &req(1, 0, POTION, 1), 0)
+                .await
+                .unwrap();
+        assert_eq!(result(&ack), MoveResult::NotAllowed as u8);
+        assert!(
+            handle_req_guild_storage_move(&pool, &mut alice, 1, &req(2, 0, POTION, 0), 0)
+                .await
+                .is_err()
+        );
+        let (ack, _) = handle_req_guild_storage_move(&pool, &mut alice, 1, &req(3, 2, 0, 5000), 0)
+            .await
+            .unwrap();
+        assert_eq!(result(&ack), MoveResult::NotEnough as u8);
+    }
+}
@@ -11,7 +11,9 @@
 
 use async_trait::async_trait;
 use ro2_common::Result;
-use ro2_common::protocol::handler::{GameContext, GameMessageHandler};
+use ro2_common::protocol::handler::{GameContext, GameMessageHandler, Requirements};
+use ro2_common::protocol::opcodes::NFY_SERVER_TIME_TO_LOGIN_PC;
+use ro2_common::wire::{self, WireWriter};
 use tracing::{debug, info};
 
 /// Handler for system messages/notifications (0x1001)
@@ -51,7 +53,7 @@ impl GameMessageHandler for SystemMessageHandler {
         context: &mut GameContext,
     ) -> Result<Option<Vec<u8>>> {
         // Verify packet ID matches expected opcode
-        if packet_id != 0x1001 {
+        if packet_id != u32::from(NFY_SERVER_TIME_TO_LOGIN_PC) {
             return Err(anyhow::anyhow!(
                 "SystemMessageHandler received wrong opcode: 0x{:04x}",
                 packet_id
@@ -69,7 +71,6 @@ impl GameMessageHandler for SystemMessageHandler {
         }
 
         // Parse message text from packet data
-        // Client expects wide string (UTF-16), we use UTF-8
         let message = match parse_message_text(data) {
             Ok(msg) => msg,
             Err(e) => {
@@ -96,42 +97,35 @@ impl GameMessageHandler for SystemMessageHandler {
     }
 
     fn opcode(&self) -> u32 {
-        0x1001
+        NFY_SERVER_TIME_TO_LOGIN_PC.into()
     }
 
     fn name(&self) -> &'static str {
         "SystemMessageHandler"
     }
+
+    fn requirements(&self) -> Requirements {
+        Requirements::AUTHENTICATED
+    }
+}
+
+/// Build a system message (opcode + payload) to send to a client, in the
+/// same layout [`parse_message_text`] reads
+pub fn build_system_message(text: &str) -> Vec<u8> {
+    let mut out = WireWriter::message(NFY_SERVER_TIME_TO_LOGIN_PC);
+    out.utf16(text);
+    out.into_bytes()
 }
 
 /// Parse message text from packet data
 ///
 /// In the client, messages are wide strings (UTF-16).
-/// For the server, we'll use UTF-8 encoded strings.
 ///
 /// Packet format (tentative):
-/// - u16: message_length (number of characters)
-/// - u8[]: message_text (UTF-8 encoded)
+/// - u16: message_length (number of UTF-16 code units)
+/// - u16[]: message_text (UTF-16LE)
 fn parse_message_text(data: &[u8]) -> Result<String> {
-    if data.len() < 2 {
-        return Err(anyhow::anyhow!("Packet too short for message length"));
-    }
-
-    // Read message length (u16 little-endian)
-    let length = u16::from_le_bytes([data[0], data[1]]) as usize;
-
-    if data.len() < 2 + length {
-        return Err(anyhow::anyhow!(
-            "Packet too short for message text (expected {} bytes, got {})",
-            2 + length,
-            data.len()
-        ));
-    }
-
-    // Parse UTF-8 string
-    let message = String::from_utf8(data[2..2 + length].to_vec())
-        .map_err(|e| anyhow::anyhow!("Invalid UTF-8 in message: {}", e))?;
-
+    let (message, _) = wire::read_utf16(data)?;
     Ok(message)
 }
 
@@ -143,13 +137,20 @@ mod tests {
     fn test_parse_message_text() {
         let message = "Hello, world!";
         let mut data = vec![];
-        data.extend_from_slice(&(message.len() as u16).to_le_bytes());
-        data.extend_from_slice(message.as_bytes());
+        wire::write_utf16(&mut data, message);
 
         let parsed = parse_message_text(&data).unwrap();
         assert_eq!(parsed, message);
     }
 
+    #[test]
+    fn test_build_system_message() {
+        let message = build_system_message("Hello");
+        assert_eq!(&message[..4], &[0x01, 0x10, 5, 0]);
+        assert_eq!(message.len(), 4 + 10);
+        assert_eq!(parse_message_text(&message[2..]).unwrap(), "Hello");
+    }
+
     #[test]
     fn test_parse_message_text_empty() {
         let data = vec![0, 0]; // Length = 0
@@ -173,10 +174,8 @@ mod tests {
         context.game_state = 2; // In-game
 
         // Create test message packet
-        let message = "Test system message";
         let mut data = vec![];
-        data.extend_from_slice(&(message.len() as u16).to_le_bytes());
-        data.extend_from_slice(message.as_bytes());
+        wire::write_utf16(&mut data, "Test system message");
 
         let response = handler.handle(0x1001, &data, &mut context).await;
 
@@ -200,10 +199,8 @@ mod tests {
         let mut context = GameContext::new(123, "127.0.0.1:8080".to_string());
         context.game_state = 0; // Disconnected
 
-        let message = "Test";
         let mut data = vec![];
-        data.extend_from_slice(&(message.len() as u16).to_le_bytes());
-        data.extend_from_slice(message.as_bytes());
+        wire::write_utf16(&mut data, "Test");
 
         let response = handler.handle(0x1001, &data, &mut context).await;
 
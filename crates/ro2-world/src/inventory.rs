@@ -0,0 +1,356 @@
+//! A character's inventory while they're in the world
+//!
+//! Changes that take and give several items at once (crafting, trades) go
+//! through [`Inventory::exchange`], which applies all of them or none. Each
+//! change reports the slots it touched so the caller can journal them (see
+//! [`Inventory::journal_entries`]) before acknowledging it to the client.
+//!
+//! A stack may be bound to its owner, so it can't be traded, mailed or
+//! auctioned, or be a rental that vanishes at a set time (see
+//! [`crate::items`]). Stacks only merge with stacks of the same item that
+//! are bound the same way and expire at the same time.
+
+use crate::journal::JournalEntry;
+use anyhow::{Result, anyhow};
+use serde::Deserialize;
+
+/// Inventory size of a new character
+pub const DEFAULT_INVENTORY_SLOTS: usize = 48;
+
+/// Most of one item a slot holds
+pub const MAX_STACK: i32 = 99;
+
+/// Some quantity of one item
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub struct ItemStack {
+    pub item_id: i32,
+    pub quantity: i32,
+    /// Bound to the character holding it
+    #[serde(default)]
+    pub bound: bool,
+    /// Unix time a rental vanishes at
+    #[serde(default)]
+    pub expires_at: Option<i64>,
+}
+
+impl ItemStack {
+    pub fn new(item_id: i32, quantity: i32) -> Self {
+        Self {
+            item_id,
+            quantity,
+            bound: false,
+            expires_at: None,
+        }
+    }
+
+    /// The same items, bound
+    pub fn bound(self) -> Self {
+        Self {
+            bound: true,
+            ..self
+        }
+    }
+
+    /// The same items, rented until `expires_at`
+    pub fn expiring_at(self, expires_at: i64) -> Self {
+        Self {
+            expires_at: Some(expires_at),
+            ..self
+        }
+    }
+
+    pub fn is_expired(&self, now: i64) -> bool {
+        self.expires_at.is_some_and(|at| at <= now)
+    }
+
+    /// Whether `other` can go in the same slot
+    fn stacks_with(&self, other: &Self) -> bool {
+        self.item_id == other.item_id
+            && self.bound == other.bound
+            && self.expires_at == other.expires_at
+    }
+}
+
+/// Fixed number of slots, each empty or holding one stack
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Inventory {
+    slots: Vec<Option<ItemStack>>,
+}
+
+impl Default for Inventory {
+    fn default() -> Self {
+        Self::new(DEFAULT_INVENTORY_SLOTS)
+    }
+}
+
+impl Inventory {
+    pub fn new(slots: usize) -> Self {
+        Self {
+            slots: vec![None; slots],
+        }
+    }
+
+    pub fn slots(&self) -> &[Option<ItemStack>] {
+        &self.slots
+    }
+
+    /// Put a stack in a slot, e.g. when loading the inventory
+    pub fn set_slot(&mut self, slot: usize, stack: Option<ItemStack>) -> Result<()> {
+        let entry = self
+            .slots
+            .get_mut(slot)
+            .ok_or_else(|| anyhow!("no inventory slot {}", slot))?;
+        *entry = stack.filter(|stack| stack.quantity > 0);
+        Ok(())
+    }
+
+    /// How many of `item_id` are held across all slots
+    pub fn count(&self, item_id: i32) -> i32 {
+        self.slots
+            .iter()
+            .flatten()
+            .filter(|stack| stack.item_id == item_id)
+            .map(|stack| stack.quantity)
+            .sum()
+    }
+
+    /// Bind the stack in `slot`, e.g. when it's equipped; returns whether
+    /// it wasn't bound already
+    pub fn bind(&mut self, slot: usize) -> Result<bool> {
+        let stack = self
+            .slots
+            .get_mut(slot)
+            .and_then(Option::as_mut)
+            .ok_or_else(|| anyhow!("inventory slot {} is empty", slot))?;
+        Ok(!std::mem::replace(&mut stack.bound, true))
+    }
+
+    /// Empty the slots holding rentals expired by `now`; returns the slots
+    /// changed
+    pub fn expire(&mut self, now: i64) -> Vec<usize> {
+        let mut changed = Vec::new();
+        for (slot, entry) in self.slots.iter_mut().enumerate() {
+            if entry.is_some_and(|stack| stack.is_expired(now)) {
+                *entry = None;
+                changed.push(slot);
+            }
+        }
+        changed
+    }
+
+    /// Add items, topping up existing stacks before using empty slots;
+    /// returns the slots changed. Nothing is added unless all of it fits.
+    pub fn add(&mut self, stack: ItemStack) -> Result<Vec<usize>> {
+        self.exchange(&[], &[stack])
+    }
+
+    /// Remove items, from the last slots first; returns the slots changed.
+    /// Nothing is removed unless there's enough.
+    pub fn remove(&mut self, stack: ItemStack) -> Result<Vec<usize>> {
+        self.exchange(&[stack], &[])
+    }
+
+    /// Remove `take` and then add `give`, all or nothing; returns the slots
+    /// changed
+    pub fn exchange(&mut self, take: &[ItemStack], give: &[ItemStack]) -> Result<Vec<usize>> {
+        let mut next = self.clone();
+        let mut changed = Vec::new();
+        for &stack in take {
+            next.take(stack, &mut changed)?;
+        }
+        for &stack in give {
+            next.give(stack, &mut changed)?;
+        }
+        *self = next;
+
+        changed.sort_unstable();
+        changed.dedup();
+        Ok(changed)
+    }
+
+    fn take(&mut self, stack: ItemStack, changed: &mut Vec<usize>) -> Result<()> {
+        if self.count(stack.item_id) < stack.quantity {
+            return Err(anyhow!(
+                "not enough of item {} (need {})",
+                stack.item_id,
+                stack.quantity
+            ));
+        }
+        let mut left = stack.quantity;
+        for (slot, entry) in self.slots.iter_mut().enumerate().rev() {
+            let Some(held) = entry.as_mut().filter(|held| held.item_id == stack.item_id) else {
+                continue;
+            };
+            let taken = left.min(held.quantity);
+            held.quantity -= taken;
+            if held.quantity == 0 {
+                *entry = None;
+            }
+            changed.push(slot);
+            left -= taken;
+            if left == 0 {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    fn give(&mut self, stack: ItemStack, changed: &mut Vec<usize>) -> Result<()> {
+        let mut left = stack.quantity;
+        for (slot, entry) in self.slots.iter_mut().enumerate() {
+            if left == 0 {
+                break;
+            }
+            if let Some(held) = entry
+                .as_mut()
+                .filter(|held| held.stacks_with(&stack) && held.quantity < MAX_STACK)
+            {
+                let added = left.min(MAX_STACK - held.quantity);
+                held.quantity += added;
+                left -= added;
+                changed.push(slot);
+            }
+        }
+        for (slot, entry) in self.slots.iter_mut().enumerate() {
+            if left == 0 {
+                break;
+            }
+            if entry.is_none() {
+                let added = left.min(MAX_STACK);
+                *entry = Some(ItemStack {
+                    quantity: added,
+                    ..stack
+                });
+                left -= added;
+                changed.push(slot);
+            }
+        }
+        if left > 0 {
+            return Err(anyhow!("no room for {} of item {}", left, stack.item_id));
+        }
+        Ok(())
+    }
+
+    /// Journal entries recording the current contents of `slots`
+    pub fn journal_entries(&self, character_id: i64, slots: &[usize]) -> Vec<JournalEntry> {
+        let mut entries = Vec::new();
+        for &slot in slots {
+            let stack = self.slots[slot].unwrap_or(ItemStack::new(0, 0));
+            entries.push(JournalEntry::InventorySlot {
+                character_id,
+                slot: slot as i32,
+                item_id: stack.item_id,
+                quantity: stack.quantity,
+            });
+            // A slot is journaled as plain items; only bound and rented
+            // ones need more
+            if stack.bound || stack.expires_at.is_some() {
+                entries.push(JournalEntry::ItemAttributes {
+                    character_id,
+                    slot: slot as i32,
+                    bound: stack.bound,
+                    expires_at: stack.expires_at,
+                });
+            }
+        }
+        entries
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_add_and_remove() {
+        let mut inventory = Inventory::new(3);
+        assert_eq!(inventory.add(ItemStack::new(501, 150)).unwrap(), [0, 1]);
+        assert_eq!(inventory.count(501), 150);
+        assert_eq!(inventory.slots()[1], Some(ItemStack::new(501, 51)));
+
+        // Tops up slot 1 before using slot 2
+        assert_eq!(inventory.add(ItemStack::new(501, 50)).unwrap(), [1, 2]);
+        assert_eq!(inventory.slots()[2], Some(ItemStack::new(501, 2)));
+
+        assert!(inventory.add(ItemStack::new(502, 1)).is_err());
+        assert!(inventory.remove(ItemStack::new(501, 201)).is_err());
+        assert_eq!(inventory.count(501), 200);
+
+        assert_eq!(inventory.remove(ItemStack::new(501, 10)).unwrap(), [1, 2]);
+        assert_eq!(inventory.slots()[2], None);
+        assert_eq!(inventory.count(501), 190);
+    }
+
+    #[test]
+    fn test_bound_and_rented_stacks() {
+        let mut inventory = Inventory::new(4);
+        inventory.add(ItemStack::new(501, 5)).unwrap();
+        inventory.add(ItemStack::new(501, 5).bound()).unwrap();
+        inventory
+            .add(ItemStack::new(501, 5).expiring_at(100))
+            .unwrap();
+        // Only like stacks merge
+        assert_eq!(inventory.add(ItemStack::new(501, 1).bound()).unwrap(), [1]);
+        assert_eq!(inventory.slots()[1], Some(ItemStack::new(501, 6).bound()));
+        assert_eq!(inventory.count(501), 16);
+
+        inventory.add(ItemStack::new(1101, 1)).unwrap();
+        assert!(inventory.bind(3).unwrap());
+        assert!(!inventory.bind(3).unwrap());
+        assert_eq!(
+            inventory.journal_entries(7, &[3]),
+            [
+                JournalEntry::InventorySlot {
+                    character_id: 7,
+                    slot: 3,
+                    item_id: 1101,
+                    quantity: 1
+                },
+                JournalEntry::ItemAttributes {
+                    character_id: 7,
+                    slot: 3,
+                    bound: true,
+                    expires_at: None
+                }
+            ]
+        );
+
+        assert!(inventory.expire(99).is_empty());
+        assert_eq!(inventory.expire(100), [2]);
+        assert_eq!(inventory.count(501), 11);
+        assert!(inventory.bind(2).is_err());
+    }
+
+    #[test]
+    fn test_exchange_is_all_or_nothing() {
+        let mut inventory = Inventory::new(2);
+        inventory.add(ItemStack::new(501, 3)).unwrap();
+        inventory.add(ItemStack::new(502, 1)).unwrap();
+        let before = inventory.clone();
+
+        // The 502 freed up can't make room for two new kinds of item
+        assert!(
+            inventory
+                .exchange(
+                    &[ItemStack::new(502, 1)],
+                    &[ItemStack::new(601, 1), ItemStack::new(602, 1)]
+                )
+                .is_err()
+        );
+        assert_eq!(inventory, before);
+
+        let changed = inventory
+            .exchange(&[ItemStack::new(502, 1)], &[ItemStack::new(601, 1)])
+            .unwrap();
+        assert_eq!(changed, [1]);
+        assert_eq!(
+            inventory.journal_entries(7, &changed),
+            [JournalEntry::InventorySlot {
+                character_id: 7,
+                slot: 1,
+                item_id: 601,
+                quantity: 1
+            }]
+        );
+    }
+}
@@ -0,0 +1,464 @@
+//! Item templates: binding, rentals and use cooldowns
+//!
+//! What an item is comes from its template; what a character holds is an
+//! [`ItemStack`] made from it by [`ItemData::instance`]. Templates can say
+//! an item binds to whoever picks it up or equips it, after which it can't
+//! be traded, mailed or auctioned (the database refuses to move it, see
+//! `ro2_common::database::transaction`). A rental vanishes a set time after
+//! it's obtained: zones drop it from carried inventories and tell the
+//! client with [`NFY_ITEM_EXPIRED`], and [`spawn_purge`] deletes expired
+//! rows from the database in the background. Using an item goes through
+//! [`Cooldowns`] with the template's own cooldown.
+//!
+//! Templates are data, read from `config/items.toml`:
+//!
+//! ```toml
+//! [[items]]
+//! id = 501
+//! name = "Red Potion"
+//! cooldown_ms = 5000       # [cooldowns] item_ms when unset
+//!
+//! [[items]]
+//! id = 1101
+//! name = "Knife"
+//! bind = "equip"           # never (default), equip or pickup
+//!
+//! [[items]]
+//! id = 12002
+//! name = "Rental Horse"
+//! rental_minutes = 10080   # Vanishes a week after it's obtained
+//!
+//! [[items]]
+//! id = 12221
+//! name = "Megaphone"
+//! megaphone = true         # Shouts to every channel (see `crate::megaphone`)
+//!
+//! [[items]]
+//! id = 12210
+//! name = "Skill Reset Scroll"
+//! resets = "skills"        # stats or skills (see `crate::respec`)
+//! ```
+//!
+//! Items without a template never bind, never expire and use the default
+//! cooldown. Like the other `0x3Fxx` opcodes, [`REQ_USE_ITEM`] and
+//! [`NFY_ITEM_EXPIRED`] are placeholders.
+
+use crate::cooldown::{CooldownConfig, CooldownKey, Cooldowns};
+use crate::inventory::{Inventory, ItemStack};
+use crate::respec::ResetKind;
+use crate::world::{Broadcaster, EntityId, Load};
+use anyhow::{Result, anyhow};
+use ro2_common::clock::Clock;
+use ro2_common::config::{self, Validate};
+use ro2_common::database::queries::InventoryQueries;
+use ro2_common::wire::{WireReader, WireWriter};
+use serde::Deserialize;
+use sqlx::{Pool, Sqlite};
+use std::collections::HashSet;
+use std::path::Path;
+use std::time::Duration;
+use tracing::{info, warn};
+
+pub use ro2_common::protocol::opcodes::{NFY_ITEM_EXPIRED, REQ_USE_ITEM};
+
+/// Where the world server reads item templates from
+pub const ITEMS_PATH: &str = "config/items.toml";
+
+/// When an item binds to its holder
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Binding {
+    #[default]
+    Never,
+    Equip,
+    Pickup,
+}
+
+/// A kind of item
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct ItemTemplate {
+    pub id: i32,
+    pub name: String,
+    #[serde(default)]
+    pub bind: Binding,
+    /// Before another can be used; the default item cooldown if unset
+    #[serde(default)]
+    pub cooldown_ms: Option<u32>,
+    /// How long a rental lasts once obtained; forever if unset
+    #[serde(default)]
+    pub rental_minutes: Option<u32>,
+    /// Used up to send a message to every channel
+    #[serde(default)]
+    pub megaphone: bool,
+    /// Used up to reset stats or skills without paying
+    #[serde(default)]
+    pub resets: Option<ResetKind>,
+}
+
+/// Every item template
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+pub struct ItemData {
+    #[serde(default)]
+    pub items: Vec<ItemTemplate>,
+}
+
+impl ItemData {
+    /// Read `path`; a missing file means no templates
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        config::load(path)
+    }
+
+    /// Parse TOML text
+    pub fn from_toml(text: &str) -> Result<Self> {
+        config::from_toml(text)
+    }
+
+    pub fn item(&self, id: i32) -> Option<&ItemTemplate> {
+        self.items.iter().find(|item| item.id == id)
+    }
+
+    pub fn is_megaphone(&self, id: i32) -> bool {
+        self.item(id).is_some_and(|item| item.megaphone)
+    }
+
+    /// What item `id` resets, if it's a reset item
+    pub fn resets(&self, id: i32) -> Option<ResetKind> {
+        self.item(id).and_then(|item| item.resets)
+    }
+
+    /// `quantity` of `item_id` as obtained at Unix time `now`: bound if
+    /// it binds on pickup, and expiring if it's a rental
+    pub fn instance(&self, item_id: i32, quantity: i32, now: i64) -> ItemStack {
+        let mut stack = ItemStack::new(item_id, quantity);
+        if let Some(item) = self.item(item_id) {
+            if item.bind == Binding::Pickup {
+                stack = stack.bound();
+            }
+            if let Some(minutes) = item.rental_minutes {
+                stack = stack.expiring_at(now + minutes as i64 * 60);
+            }
+        }
+        stack
+    }
+
+    /// How long `item_id` cools down for after it's used
+    pub fn cooldown(&self, item_id: i32, config: &CooldownConfig) -> Duration {
+        match self.item(item_id).and_then(|item| item.cooldown_ms) {
+            Some(ms) => Duration::from_millis(ms as u64),
+            None => config.item(),
+        }
+    }
+
+    /// Bind the item in `slot` if it binds on equip, for the equip
+    /// handler; returns whether the slot changed and needs journaling
+    pub fn equip(&self, inventory: &mut Inventory, slot: usize) -> Result<bool> {
+        let stack = inventory
+            .slots()
+            .get(slot)
+            .copied()
+            .flatten()
+            .ok_or_else(|| anyhow!("inventory slot {} is empty", slot))?;
+        match self.item(stack.item_id).map(|item| item.bind) {
+            Some(Binding::Equip | Binding::Pickup) => inventory.bind(slot),
+            _ => Ok(false),
+        }
+    }
+
+    /// Handle [`REQ_USE_ITEM`] from the player controlling `entity`: use
+    /// one item from the slot asked for, if it hasn't expired and isn't
+    /// cooling down. Returns the item used and the slot to journal.
+    ///
+    /// What the item does is up to the caller.
+    pub fn handle_req_use_item(
+        &self,
+        inventory: &mut Inventory,
+        broadcaster: &Broadcaster,
+        cooldowns: &mut Cooldowns,
+        entity: EntityId,
+        message: &[u8],
+        now: i64,
+    ) -> Result<(ItemStack, usize)> {
+        let slot = parse_req_use_item(message)?;
+        let stack = inventory
+            .slots()
+            .get(slot)
+            .copied()
+            .flatten()
+            .ok_or_else(|| anyhow!("inventory slot {} is empty", slot))?;
+        if stack.is_expired(now) {
+            return Err(anyhow!(
+                "item {} in slot {} has expired",
+                stack.item_id,
+                slot
+            ));
+        }
+        let key = CooldownKey::Item(stack.item_id);
+        cooldowns.enforce(broadcaster, entity, key)?;
+
+        inventory.set_slot(
+            slot,
+            Some(ItemStack {
+                quantity: stack.quantity - 1,
+                ..stack
+            }),
+        )?;
+        let cooldown = self.cooldown(stack.item_id, cooldowns.config());
+        cooldowns.start_use(entity, key, cooldown);
+        Ok((stack, slot))
+    }
+}
+
+impl Validate for ItemData {
+    fn validate(&self) -> Result<()> {
+        let mut ids = HashSet::new();
+        for item in &self.items {
+            if item.id <= 0 || !ids.insert(item.id) {
+                return Err(anyhow!(
+                    "item id {} isn't positive or is used twice",
+                    item.id
+                ));
+            }
+            if item.rental_minutes == Some(0) {
+                return Err(anyhow!("item {}: rental_minutes is 0", item.id));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Drop the rentals in `entity`'s inventory expired by `now`, telling its
+/// client of each; returns the slots to journal
+pub fn expire(
+    inventory: &mut Inventory,
+    broadcaster: &Broadcaster,
+    entity: EntityId,
+    now: i64,
+) -> Vec<usize> {
+    let expired: Vec<(usize, i32)> = inventory
+        .slots()
+        .iter()
+        .enumerate()
+        .filter_map(|(slot, stack)| {
+            stack
+                .filter(|stack| stack.is_expired(now))
+                .map(|stack| (slot, stack.item_id))
+        })
+        .collect();
+    for &(slot, item_id) in &expired {
+        broadcaster.send_to(entity, build_nfy_item_expired(slot, item_id));
+    }
+    inventory.expire(now)
+}
+
+/// Delete expired rentals from the database every `interval`, for
+/// characters who aren't online to have them dropped by their zone; runs
+/// are skipped while the world sheds `load`
+pub fn spawn_purge(pool: Pool<Sqlite>, clock: Clock, interval: Duration, load: Load) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(interval);
+        loop {
+            interval.tick().await;
+            if load.is_overloaded() {
+                continue;
+            }
+            match InventoryQueries::purge_expired(&pool, clock.unix()).await {
+                Ok(purged) if purged.is_empty() => {}
+                Ok(purged) => info!("Purged {} expired rental items", purged.len()),
+                Err(e) => warn!("Purging expired rental items failed: {}", e),
+            }
+        }
+    });
+}
+
+/// Parse an item use request: u16 inventory slot
+pub fn parse_req_use_item(message: &[u8]) -> Result<usize> {
+    let slot = WireReader::message(message, REQ_USE_ITEM, "item use request")?.u16("slot")?;
+    Ok(usize::from(slot))
+}
+
+/// Build the notification of the rental in `slot` expiring: u16 slot and
+/// i32 item ID
+pub fn build_nfy_item_expired(slot: usize, item_id: i32) -> Vec<u8> {
+    let mut out = WireWriter::message(NFY_ITEM_EXPIRED);
+    out.u16(slot as u16).i32(item_id);
+    out.into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cooldown::Refusal;
+
+    const TICK: Duration = Duration::from_millis(100);
+
+    fn data() -> ItemData {
+        ItemData::from_toml(
+            r#"
+            [[items]]
+            id = 501
+            name = "Red Potion"
+            cooldown_ms = 5000
+
+            [[items]]
+            id = 1101
+            name = "Knife"
+            bind = "equip"
+
+            [[items]]
+            id = 1201
+            name = "Quest Sword"
+            bind = "pickup"
+
+            [[items]]
+            id = 12002
+            name = "Rental Horse"
+            rental_minutes = 60
+            "#,
+        )
+        .unwrap()
+    }
+
+    fn use_item(slot: u16) -> Vec<u8> {
+        let mut message = REQ_USE_ITEM.to_le_bytes().to_vec();
+        message.extend_from_slice(&slot.to_le_bytes());
+        message
+    }
+
+    #[test]
+    fn test_load() {
+        assert_eq!(
+            ItemData::load("does/not/exist.toml").unwrap(),
+            ItemData::default()
+        );
+        assert!(
+            ItemData::from_toml("[[items]]\nid = 1\nname = \"A\"\nrental_minutes = 0").is_err()
+        );
+        assert!(
+            ItemData::from_toml("[[items]]\nid = 1\nname = \"A\"\n[[items]]\nid = 1\nname = \"B\"")
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_instance_and_equip() {
+        let data = data();
+        assert_eq!(data.instance(501, 3, 1000), ItemStack::new(501, 3));
+        assert_eq!(
+            data.instance(1201, 1, 1000),
+            ItemStack::new(1201, 1).bound()
+        );
+        assert_eq!(
+            data.instance(12002, 1, 1000),
+            ItemStack::new(12002, 1).expiring_at(4600)
+        );
+        // No template, nothing special
+        assert_eq!(data.instance(9999, 1, 1000), ItemStack::new(9999, 1));
+
+        let mut inventory = Inventory::new(2);
+        inventory.add(data.instance(1101, 1, 0)).unwrap();
+        inventory.add(data.instance(501, 1, 0)).unwrap();
+        assert!(data.equip(&mut inventory, 0).unwrap());
+        assert!(!data.equip(&mut inventory, 0).unwrap());
+        assert!(!data.equip(&mut inventory, 1).unwrap());
+        assert_eq!(inventory.slots()[0], Some(ItemStack::new(1101, 1).bound()));
+        assert_eq!(inventory.slots()[1], Some(ItemStack::new(501, 1)));
+    }
+
+    #[test]
+    fn test_use_item() {
+        let data = data();
+        let config = CooldownConfig::default();
+        let mut cooldowns = Cooldowns::new(config, TICK);
+        let mut broadcaster = Broadcaster::default();
+        let entity = EntityId(1);
+        let (tx, mut rx) = tokio::sync::mpsc::channel(4);
+        broadcaster.subscribe(1, entity, tx);
+
+        let mut inventory = Inventory::new(3);
+        inventory.add(ItemStack::new(501, 2)).unwrap();
+        inventory.add(ItemStack::new(502, 1)).unwrap();
+        inventory
+            .add(data.instance(12002, 1, 0).expiring_at(10))
+            .unwrap();
+
+        let (used, slot) = data
+            .handle_req_use_item(
+                &mut inventory,
+                &broadcaster,
+                &mut cooldowns,
+                entity,
+                &use_item(0),
+                5,
+            )
+            .unwrap();
+        assert_eq!((used.item_id, slot), (501, 0));
+        assert_eq!(inventory.count(501), 1);
+        assert_eq!(
+            cooldowns.remaining(entity, CooldownKey::Item(501)),
+            Duration::from_secs(5)
+        );
+
+        // Refused while the potion cools down, with the client told why
+        let error = data
+            .handle_req_use_item(
+                &mut inventory,
+                &broadcaster,
+                &mut cooldowns,
+                entity,
+                &use_item(0),
+                5,
+            )
+            .unwrap_err();
+        let refusal = error.downcast_ref::<Refusal>().unwrap();
+        assert_eq!(rx.try_recv().unwrap(), refusal.encode());
+        assert_eq!(inventory.count(501), 1);
+
+        // Once the GCD is over, other items take the default cooldown
+        for _ in 0..10 {
+            cooldowns.tick();
+        }
+        data.handle_req_use_item(
+            &mut inventory,
+            &broadcaster,
+            &mut cooldowns,
+            entity,
+            &use_item(1),
+            5,
+        )
+        .unwrap();
+        assert_eq!(inventory.slots()[1], None);
+        assert_eq!(
+            cooldowns.remaining(entity, CooldownKey::Item(502)),
+            config.item()
+        );
+
+        // An expired rental can't be used and goes on the next expiry
+        for _ in 0..50 {
+            cooldowns.tick();
+        }
+        assert!(
+            data.handle_req_use_item(
+                &mut inventory,
+                &broadcaster,
+                &mut cooldowns,
+                entity,
+                &use_item(2),
+                10
+            )
+            .is_err()
+        );
+        assert_eq!(expire(&mut inventory, &broadcaster, entity, 10), [2]);
+        assert_eq!(rx.try_recv().unwrap(), build_nfy_item_expired(2, 12002));
+        assert!(inventory.slots()[2].is_none());
+    }
+
+    #[test]
+    fn test_parse_req_use_item() {
+        assert_eq!(parse_req_use_item(&use_item(7)).unwrap(), 7);
+        let message = use_item(7);
+        for cut in 0..message.len() {
+            assert!(parse_req_use_item(&message[..cut]).is_err());
+        }
+        assert!(parse_req_use_item(&[0, 0, 7, 0]).is_err());
+    }
+}
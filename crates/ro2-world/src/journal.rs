@@ -0,0 +1,383 @@
+//! Write-ahead journal for critical character state
+//!
+//! Full autosaves are too far apart to lose a rare drop or the result of a
+//! trade to a crash, so changes like those are appended here and synced to
+//! disk before the server acknowledges them. Once an autosave has written
+//! everything to the database the journal is checkpointed (emptied). A
+//! journal that still has entries at startup means the server didn't shut
+//! down cleanly; [`replay`] writes them to the database.
+//!
+//! Entries record the value after the change rather than the change itself,
+//! so replaying an entry that had already been saved is harmless.
+//!
+//! Each record on disk is one batch of entries:
+//!
+//! ```text
+//! [length: u32] [crc32: u32] [seq: u64] [entries: postcard]
+//! ```
+//!
+//! `length` covers `seq` and the entries, and the CRC covers the same bytes.
+//! A batch is written in one go, so a crash mid-write leaves a torn record
+//! at the end that fails its CRC; it's dropped on open, and with it the
+//! whole batch (both sides of a trade, never just one).
+//!
+//! Writes are blocking; call from a blocking task or a dedicated thread.
+
+use anyhow::{Context, Result};
+use ro2_common::database::queries::{CharacterQueries, InventoryQueries};
+use serde::{Deserialize, Serialize};
+use sqlx::{Pool, Sqlite};
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use tracing::warn;
+
+/// Where the world server keeps its journal
+pub const DEFAULT_JOURNAL_PATH: &str = "data/world.journal";
+
+const RECORD_HEADER_LEN: usize = 8;
+const SEQ_LEN: usize = 8;
+
+/// A change to persist before the next autosave
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum JournalEntry {
+    /// A character's zeny after a change
+    Zeny { character_id: i64, zeny: i64 },
+    /// A character's level and experience after a change
+    Experience {
+        character_id: i64,
+        level: i32,
+        experience: i64,
+    },
+    /// The contents of an inventory slot after an item was gained or lost;
+    /// `quantity` 0 means the slot is empty
+    InventorySlot {
+        character_id: i64,
+        slot: i32,
+        item_id: i32,
+        quantity: i32,
+    },
+    /// Where a character is
+    Position {
+        character_id: i64,
+        map_id: i32,
+        x: f32,
+        y: f32,
+        z: f32,
+    },
+    /// Whether the item in an inventory slot is bound and when it expires,
+    /// following the [`InventorySlot`](Self::InventorySlot) entry that
+    /// put it there
+    ItemAttributes {
+        character_id: i64,
+        slot: i32,
+        bound: bool,
+        expires_at: Option<i64>,
+    },
+}
+
+/// An append-only journal file
+pub struct Journal {
+    file: File,
+    path: PathBuf,
+    next_seq: u64,
+    pending: usize,
+}
+
+impl Journal {
+    /// Open or create the journal at `path`
+    ///
+    /// Returns the entries left over from an unclean shutdown, in order. A
+    /// torn record at the end is cut off.
+    pub fn open(path: impl AsRef<Path>) -> Result<(Self, Vec<JournalEntry>)> {
+        let path = path.as_ref();
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .with_context(|| format!("opening journal {}", path.display()))?;
+
+        let mut data = Vec::new();
+        file.read_to_end(&mut data)?;
+
+        let mut entries = Vec::new();
+        let mut pending = 0;
+        let mut next_seq = 0;
+        let mut offset = 0;
+        while let Some((seq, batch, len)) = read_record(&data[offset..]) {
+            next_seq = seq + 1;
+            pending += 1;
+            entries.extend(batch);
+            offset += len;
+        }
+        if offset < data.len() {
+            warn!(
+                "Journal {}: dropping {} bytes of torn or corrupt records",
+                path.display(),
+                data.len() - offset
+            );
+            file.set_len(offset as u64)?;
+            file.sync_all()?;
+        }
+        file.seek(SeekFrom::Start(offset as u64))?;
+
+        Ok((
+            Self {
+                file,
+                path: path.to_path_buf(),
+                next_seq,
+                pending,
+            },
+            entries,
+        ))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Batches written since the last checkpoint
+    pub fn len(&self) -> usize {
+        self.pending
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending == 0
+    }
+
+    /// Append one entry and sync it to disk
+    pub fn append(&mut self, entry: JournalEntry) -> Result<u64> {
+        self.append_batch(&[entry])
+    }
+
+    /// Append entries that must be replayed all or not at all (the two
+    /// sides of a trade) and sync them to disk
+    pub fn append_batch(&mut self, entries: &[JournalEntry]) -> Result<u64> {
+        let seq = self.next_seq;
+        let mut body = seq.to_le_bytes().to_vec();
+        body.extend(postcard::to_allocvec(entries)?);
+
+        let mut record = Vec::with_capacity(RECORD_HEADER_LEN + body.len());
+        record.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        record.extend_from_slice(&crc32fast::hash(&body).to_le_bytes());
+        record.extend_from_slice(&body);
+
+        let start = self.file.stream_position()?;
+        if let Err(e) = self
+            .file
+            .write_all(&record)
+            .and_then(|()| self.file.sync_data())
+        {
+            // Don't leave a torn record for later ones to be appended after
+            let _ = self.file.set_len(start);
+            let _ = self.file.seek(SeekFrom::Start(start));
+            return Err(e.into());
+        }
+        self.next_seq += 1;
+        self.pending += 1;
+        Ok(seq)
+    }
+
+    /// Empty the journal once everything in it has been saved
+    pub fn checkpoint(&mut self) -> Result<()> {
+        self.file.set_len(0)?;
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file.sync_all()?;
+        self.pending = 0;
+        Ok(())
+    }
+}
+
+/// Parse the record at the start of `data`: its sequence number, entries
+/// and length. `None` at the end of the data or at a torn record.
+fn read_record(data: &[u8]) -> Option<(u64, Vec<JournalEntry>, usize)> {
+    let header = data.get(..RECORD_HEADER_LEN)?;
+    let len = u32::from_le_bytes(header[..4].try_into().unwrap()) as usize;
+    let crc = u32::from_le_bytes(header[4..].try_into().unwrap());
+    let body = data.get(RECORD_HEADER_LEN..RECORD_HEADER_LEN + len)?;
+    if len < SEQ_LEN || crc32fast::hash(body) != crc {
+        return None;
+    }
+
+    let seq = u64::from_le_bytes(body[..SEQ_LEN].try_into().unwrap());
+    let entries = postcard::from_bytes(&body[SEQ_LEN..]).ok()?;
+    Some((seq, entries, RECORD_HEADER_LEN + len))
+}
+
+/// Write journal entries to the database
+pub async fn replay(pool: &Pool<Sqlite>, entries: &[JournalEntry]) -> Result<()> {
+    for entry in entries {
+        match *entry {
+            JournalEntry::Zeny { character_id, zeny } => {
+                CharacterQueries::set_gold(pool, character_id, zeny).await?
+            }
+            JournalEntry::Experience {
+                character_id,
+                level,
+                experience,
+            } => CharacterQueries::set_experience(pool, character_id, level, experience).await?,
+            JournalEntry::InventorySlot {
+                character_id,
+                slot,
+                item_id,
+                quantity,
+            } => InventoryQueries::set_slot(pool, character_id, slot, item_id, quantity).await?,
+            JournalEntry::Position {
+                character_id,
+                map_id,
+                x,
+                y,
+                z,
+            } => CharacterQueries::set_position(pool, character_id, map_id, (x, y, z)).await?,
+            JournalEntry::ItemAttributes {
+                character_id,
+                slot,
+                bound,
+                expires_at,
+            } => {
+                InventoryQueries::set_attributes(pool, character_id, slot, bound, expires_at)
+                    .await?
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ro2_common::testing;
+
+    fn journal_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "ro2-journal-{}-{}.journal",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    fn trade() -> [JournalEntry; 3] {
+        [
+            JournalEntry::Zeny {
+                character_id: 1,
+                zeny: 500,
+            },
+            JournalEntry::Zeny {
+                character_id: 2,
+                zeny: 1500,
+            },
+            JournalEntry::InventorySlot {
+                character_id: 1,
+                slot: 3,
+                item_id: 4001,
+                quantity: 1,
+            },
+        ]
+    }
+
+    #[test]
+    fn test_recovery_after_crash() {
+        let path = journal_path("recovery");
+        let (mut journal, entries) = Journal::open(&path).unwrap();
+        assert!(entries.is_empty());
+
+        let rare_drop = JournalEntry::InventorySlot {
+            character_id: 1,
+            slot: 0,
+            item_id: 7001,
+            quantity: 1,
+        };
+        journal.append(rare_drop.clone()).unwrap();
+        journal.append_batch(&trade()).unwrap();
+        // Crash without checkpointing
+        drop(journal);
+
+        let (mut journal, entries) = Journal::open(&path).unwrap();
+        assert_eq!(entries.len(), 4);
+        assert_eq!(entries[0], rare_drop);
+        assert_eq!(&entries[1..], &trade());
+        assert_eq!(journal.len(), 2);
+
+        // Sequence numbers carry on after a restart
+        assert_eq!(journal.append(rare_drop.clone()).unwrap(), 2);
+
+        journal.checkpoint().unwrap();
+        assert!(journal.is_empty());
+        journal.append(rare_drop.clone()).unwrap();
+        let (_, entries) = Journal::open(&path).unwrap();
+        assert_eq!(entries, [rare_drop]);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_torn_batch_is_dropped_whole() {
+        let path = journal_path("torn");
+        let (mut journal, _) = Journal::open(&path).unwrap();
+        journal
+            .append(JournalEntry::Zeny {
+                character_id: 1,
+                zeny: 10,
+            })
+            .unwrap();
+        let intact = std::fs::metadata(&path).unwrap().len();
+        journal.append_batch(&trade()).unwrap();
+        drop(journal);
+
+        // Crash halfway through writing the trade
+        let full = std::fs::metadata(&path).unwrap().len();
+        let file = OpenOptions::new().write(true).open(&path).unwrap();
+        file.set_len((intact + full) / 2).unwrap();
+
+        let (_, entries) = Journal::open(&path).unwrap();
+        assert_eq!(
+            entries,
+            [JournalEntry::Zeny {
+                character_id: 1,
+                zeny: 10
+            }]
+        );
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), intact);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_replay() {
+        let pool = testing::database().await;
+        testing::character(&pool, 1, "Alice", 0).await;
+        testing::character(&pool, 2, "Bob", 0).await;
+
+        // Replaying twice is the same as replaying once
+        let mut entries = trade().to_vec();
+        entries.push(JournalEntry::ItemAttributes {
+            character_id: 1,
+            slot: 3,
+            bound: true,
+            expires_at: Some(100),
+        });
+        for _ in 0..2 {
+            replay(&pool, &entries).await.unwrap();
+        }
+
+        let gold: Vec<(i64,)> = sqlx::query_as("SELECT gold FROM characters ORDER BY id")
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+        assert_eq!(gold, [(500,), (1500,)]);
+        let items: Vec<(i32, i32, bool, Option<i64>)> = sqlx::query_as(
+            "SELECT item_id, quantity, is_bound, expires_at FROM inventory WHERE character_id = 1",
+        )
+        .fetch_all(&pool)
+        .await
+        .unwrap();
+        assert_eq!(items, [(4001, 1, true, Some(100))]);
+    }
+}
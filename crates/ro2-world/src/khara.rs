@@ -0,0 +1,544 @@
+//! Khara: challenges that unlock titles
+//!
+//! A Khara challenge asks for something to be done a number of times:
+//! monsters killed, items gathered or crafted, zones visited, or a level
+//! reached. Gameplay reports what happened as a [`ChallengeEvent`] (kills
+//! and level ups convert from their [`ro2_common::events`]) and
+//! [`KharaProgress::record`] advances every challenge it counts towards.
+//! Completing a challenge unlocks its title; the equipped title's stat
+//! bonus goes into the character's [`StatModifiers`].
+//!
+//! Challenges and titles are data, read from `config/khara.toml`:
+//!
+//! ```toml
+//! [[titles]]
+//! id = 1
+//! name = "Slime Hunter"
+//! bonus = { str = 1, max_hp = 50 }
+//!
+//! [[challenges]]
+//! id = 1
+//! name = "Hunt 100 Slimes"
+//! goal = "kill"
+//! target = 1001        # Monster ID; 0 or left out = any monster
+//! count = 100
+//! title = 1
+//! ```
+//!
+//! The request and notification opcodes below are placeholders like those
+//! in `MessageType`.
+
+use crate::stats::{BonusSource, StatBonus, StatModifiers};
+use anyhow::{Result, anyhow};
+use ro2_common::config::{self, Validate};
+use ro2_common::events::{LevelUp, MonsterKilled};
+use ro2_common::wire::{WireReader, WireWriter};
+use serde::Deserialize;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::path::Path;
+
+pub use ro2_common::protocol::opcodes::{
+    ANS_EQUIP_TITLE, ANS_KHARA_LIST, NFY_KHARA_PROGRESS, NFY_TITLE_UNLOCKED, REQ_EQUIP_TITLE,
+    REQ_KHARA_LIST,
+};
+
+/// Where the world server reads challenges and titles from
+pub const KHARA_PATH: &str = "config/khara.toml";
+
+fn one() -> u32 {
+    1
+}
+
+/// What a challenge counts
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Goal {
+    Kill,
+    Gather,
+    Craft,
+    Visit,
+    /// Reach a character level; the level is the challenge's `count`
+    Level,
+}
+
+/// Something that happened which challenges may count
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChallengeEvent {
+    MonsterKilled { monster_id: u32 },
+    ItemGathered { item_id: u32, quantity: u32 },
+    ItemCrafted { item_id: u32, quantity: u32 },
+    ZoneEntered { zone: u32 },
+    LevelReached { level: u32 },
+}
+
+impl From<MonsterKilled> for ChallengeEvent {
+    fn from(event: MonsterKilled) -> Self {
+        ChallengeEvent::MonsterKilled {
+            monster_id: event.monster_id,
+        }
+    }
+}
+
+impl From<LevelUp> for ChallengeEvent {
+    fn from(event: LevelUp) -> Self {
+        ChallengeEvent::LevelReached { level: event.level }
+    }
+}
+
+/// A challenge and the title it unlocks
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Challenge {
+    pub id: u32,
+    pub name: String,
+    pub goal: Goal,
+    /// Monster, item or zone ID; 0 counts any
+    #[serde(default)]
+    pub target: u32,
+    #[serde(default = "one")]
+    pub count: u32,
+    pub title: u32,
+}
+
+impl Challenge {
+    /// How far `event` takes this challenge from `progress`, if it counts
+    fn advance(&self, progress: u32, event: ChallengeEvent) -> Option<u32> {
+        let matches = |id: u32| self.target == 0 || self.target == id;
+        let progress = match (self.goal, event) {
+            (Goal::Kill, ChallengeEvent::MonsterKilled { monster_id }) if matches(monster_id) => {
+                progress.saturating_add(1)
+            }
+            (Goal::Gather, ChallengeEvent::ItemGathered { item_id, quantity })
+            | (Goal::Craft, ChallengeEvent::ItemCrafted { item_id, quantity })
+                if matches(item_id) =>
+            {
+                progress.saturating_add(quantity)
+            }
+            (Goal::Visit, ChallengeEvent::ZoneEntered { zone }) if matches(zone) => {
+                progress.saturating_add(1)
+            }
+            (Goal::Level, ChallengeEvent::LevelReached { level }) if level > progress => level,
+            _ => return None,
+        };
+        Some(progress.min(self.count))
+    }
+}
+
+/// A title and what wearing it gives
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Title {
+    pub id: u32,
+    pub name: String,
+    #[serde(default)]
+    pub bonus: StatBonus,
+}
+
+/// Every challenge and title
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct KharaData {
+    #[serde(default)]
+    pub titles: Vec<Title>,
+    #[serde(default)]
+    pub challenges: Vec<Challenge>,
+}
+
+impl KharaData {
+    /// Read `path`; a missing file means no challenges
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        config::load(path)
+    }
+
+    /// Parse TOML text
+    pub fn from_toml(text: &str) -> Result<Self> {
+        config::from_toml(text)
+    }
+
+    pub fn challenge(&self, id: u32) -> Option<&Challenge> {
+        self.challenges.iter().find(|challenge| challenge.id == id)
+    }
+
+    pub fn title(&self, id: u32) -> Option<&Title> {
+        self.titles.iter().find(|title| title.id == id)
+    }
+}
+
+impl Validate for KharaData {
+    fn validate(&self) -> Result<()> {
+        let mut title_ids = HashSet::new();
+        for title in &self.titles {
+            if title.id == 0 || !title_ids.insert(title.id) {
+                return Err(anyhow!("title id {} is 0 or used twice", title.id));
+            }
+        }
+        let mut challenge_ids = HashSet::new();
+        for challenge in &self.challenges {
+            if !challenge_ids.insert(challenge.id) {
+                return Err(anyhow!("two challenges with id {}", challenge.id));
+            }
+            if challenge.count == 0 {
+                return Err(anyhow!("challenge {}: count is 0", challenge.id));
+            }
+            if !title_ids.contains(&challenge.title) {
+                return Err(anyhow!(
+                    "challenge {}: no title {}",
+                    challenge.id,
+                    challenge.title
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// What recording an event changed
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KharaUpdate {
+    /// `(challenge ID, new progress)` to save and notify
+    pub progressed: Vec<(u32, u32)>,
+    /// Titles newly unlocked
+    pub unlocked: Vec<u32>,
+}
+
+impl KharaUpdate {
+    pub fn is_empty(&self) -> bool {
+        self.progressed.is_empty() && self.unlocked.is_empty()
+    }
+
+    /// The notifications to send the player
+    pub fn messages(&self) -> Vec<Vec<u8>> {
+        self.progressed
+            .iter()
+            .map(|&(challenge, progress)| build_nfy_khara_progress(challenge, progress))
+            .chain(
+                self.unlocked
+                    .iter()
+                    .map(|&title| build_nfy_title_unlocked(title)),
+            )
+            .collect()
+    }
+}
+
+/// A character's challenge progress and titles
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KharaProgress {
+    progress: BTreeMap<u32, u32>,
+    titles: BTreeSet<u32>,
+    equipped: Option<u32>,
+}
+
+impl KharaProgress {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// From what [`KharaQueries`] loads: `(challenge ID, progress)` rows,
+    /// unlocked title IDs and the equipped title
+    ///
+    /// [`KharaQueries`]: ro2_common::database::queries::KharaQueries
+    pub fn from_rows(progress: &[(i32, i64)], titles: &[i32], equipped: Option<i32>) -> Self {
+        let progress = progress
+            .iter()
+            .filter_map(|&(challenge, progress)| {
+                Some((
+                    u32::try_from(challenge).ok()?,
+                    u32::try_from(progress).ok()?,
+                ))
+            })
+            .collect();
+        let titles: BTreeSet<u32> = titles
+            .iter()
+            .filter_map(|&title| u32::try_from(title).ok())
+            .collect();
+        let equipped = equipped
+            .and_then(|title| u32::try_from(title).ok())
+            .filter(|title| titles.contains(title));
+        Self {
+            progress,
+            titles,
+            equipped,
+        }
+    }
+
+    pub fn progress(&self, challenge: u32) -> u32 {
+        self.progress.get(&challenge).copied().unwrap_or(0)
+    }
+
+    pub fn has_title(&self, title: u32) -> bool {
+        self.titles.contains(&title)
+    }
+
+    pub fn titles(&self) -> impl Iterator<Item = u32> + '_ {
+        self.titles.iter().copied()
+    }
+
+    pub fn equipped(&self) -> Option<u32> {
+        self.equipped
+    }
+
+    /// Count `event` towards every unfinished challenge it matches
+    pub fn record(&mut self, data: &KharaData, event: ChallengeEvent) -> KharaUpdate {
+        let mut update = KharaUpdate::default();
+        for challenge in &data.challenges {
+            let current = self.progress(challenge.id);
+            if current >= challenge.count {
+                continue;
+            }
+            let Some(progress) = challenge.advance(current, event) else {
+                continue;
+            };
+            self.progress.insert(challenge.id, progress);
+            update.progressed.push((challenge.id, progress));
+            if progress >= challenge.count && self.titles.insert(challenge.title) {
+                update.unlocked.push(challenge.title);
+            }
+        }
+        update
+    }
+
+    /// Equip an unlocked title, or take it off with `None`, and update the
+    /// title bonus in `modifiers`
+    pub fn equip(
+        &mut self,
+        data: &KharaData,
+        title: Option<u32>,
+        modifiers: &mut StatModifiers,
+    ) -> Result<()> {
+        if let Some(title) = title {
+            if !self.has_title(title) {
+                return Err(anyhow!("title {} is not unlocked", title));
+            }
+            if data.title(title).is_none() {
+                return Err(anyhow!("no title {}", title));
+            }
+        }
+        self.equipped = title;
+        self.apply(data, modifiers);
+        Ok(())
+    }
+
+    /// The equipped title's bonus
+    pub fn bonus(&self, data: &KharaData) -> StatBonus {
+        self.equipped
+            .and_then(|title| data.title(title))
+            .map(|title| title.bonus)
+            .unwrap_or_default()
+    }
+
+    /// Put the equipped title's bonus in `modifiers`
+    pub fn apply(&self, data: &KharaData, modifiers: &mut StatModifiers) {
+        modifiers.set(BonusSource::Title, self.bonus(data));
+    }
+}
+
+/// Build the Khara list: every challenge with its progress, the unlocked
+/// titles and the equipped one
+///
+/// Layout: u16 challenge count, then per challenge u32 ID, u32 progress
+/// and u32 count; u16 title count and u32 title IDs; u32 equipped title
+/// (0 = none).
+pub fn build_ans_khara_list(data: &KharaData, progress: &KharaProgress) -> Vec<u8> {
+    let mut out = WireWriter::message(ANS_KHARA_LIST);
+    out.u16(data.challenges.len() as u16);
+    for challenge in &data.challenges {
+        out.u32(challenge.id)
+            .u32(progress.progress(challenge.id))
+            .u32(challenge.count);
+    }
+    out.u16(progress.titles.len() as u16);
+    for title in progress.titles() {
+        out.u32(title);
+    }
+    out.u32(progress.equipped.unwrap_or(0));
+    out.into_bytes()
+}
+
+/// Build the notification of a challenge's new progress
+pub fn build_nfy_khara_progress(challenge: u32, progress: u32) -> Vec<u8> {
+    let mut out = WireWriter::message(NFY_KHARA_PROGRESS);
+    out.u32(challenge).u32(progress);
+    out.into_bytes()
+}
+
+/// Build the notification of an unlocked title
+pub fn build_nfy_title_unlocked(title: u32) -> Vec<u8> {
+    let mut out = WireWriter::message(NFY_TITLE_UNLOCKED);
+    out.u32(title);
+    out.into_bytes()
+}
+
+/// Parse an equip title request (u32 title ID, 0 = take it off)
+pub fn parse_req_equip_title(message: &[u8]) -> Result<Option<u32>> {
+    let title =
+        WireReader::message(message, REQ_EQUIP_TITLE, "equip title request")?.u32("title ID")?;
+    Ok((title != 0).then_some(title))
+}
+
+/// Build the answer to an equip title request (u8 1 = done, u32 title)
+pub fn build_ans_equip_title(ok: bool, title: Option<u32>) -> Vec<u8> {
+    let mut out = WireWriter::message(ANS_EQUIP_TITLE);
+    out.bool(ok).u32(title.unwrap_or(0));
+    out.into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn data() -> KharaData {
+        KharaData::from_toml(
+            r#"
+            [[titles]]
+            id = 1
+            name = "Slime Hunter"
+            bonus = { str = 1, max_hp = 50 }
+
+            [[titles]]
+            id = 2
+            name = "Veteran"
+            bonus = { vit = 3 }
+
+            [[challenges]]
+            id = 1
+            name = "Hunt 3 Slimes"
+            goal = "kill"
+            target = 1001
+            count = 3
+            title = 1
+
+            [[challenges]]
+            id = 2
+            name = "Hunt anything"
+            goal = "kill"
+            count = 10
+            title = 1
+
+            [[challenges]]
+            id = 3
+            name = "Reach level 20"
+            goal = "level"
+            count = 20
+            title = 2
+            "#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_challenges_unlock_titles() {
+        let data = data();
+        let mut khara = KharaProgress::new();
+        let slime = ChallengeEvent::MonsterKilled { monster_id: 1001 };
+
+        let update = khara.record(&data, slime);
+        assert_eq!(update.progressed, [(1, 1), (2, 1)]);
+        assert!(update.unlocked.is_empty());
+
+        // Other monsters only count for the "any monster" challenge
+        let kill = MonsterKilled {
+            character_id: 1,
+            monster_id: 7,
+            zone: 1,
+        };
+        let update = khara.record(&data, kill.into());
+        assert_eq!(update.progressed, [(2, 2)]);
+        assert!(
+            khara
+                .record(&data, ChallengeEvent::ZoneEntered { zone: 1 })
+                .is_empty()
+        );
+
+        khara.record(&data, slime);
+        let update = khara.record(&data, slime);
+        assert_eq!(update.unlocked, [1]);
+        assert!(khara.has_title(1));
+        // Done challenges stop counting; the title isn't unlocked twice
+        assert_eq!(khara.record(&data, slime).progressed, [(2, 5)]);
+
+        let update = khara.record(&data, ChallengeEvent::LevelReached { level: 25 });
+        assert_eq!(
+            (update.progressed, update.unlocked),
+            (vec![(3, 20)], vec![2])
+        );
+        assert_eq!(
+            khara.record(&data, ChallengeEvent::LevelReached { level: 26 }),
+            KharaUpdate::default()
+        );
+    }
+
+    #[test]
+    fn test_equipped_title_bonus() {
+        let data = data();
+        let mut khara = KharaProgress::from_rows(&[(1, 3)], &[1], Some(2));
+        // Title 2 isn't unlocked, so it can't be equipped
+        assert_eq!(khara.equipped(), None);
+
+        let mut modifiers = StatModifiers::new();
+        assert!(khara.equip(&data, Some(2), &mut modifiers).is_err());
+        khara.equip(&data, Some(1), &mut modifiers).unwrap();
+        assert_eq!(modifiers.total().str, 1);
+        assert_eq!(modifiers.get(BonusSource::Title).max_hp, 50);
+
+        khara.equip(&data, None, &mut modifiers).unwrap();
+        assert_eq!(modifiers.total(), StatBonus::default());
+    }
+
+    #[test]
+    fn test_load_data() {
+        assert_eq!(
+            KharaData::load("does/not/exist.toml").unwrap(),
+            KharaData::default()
+        );
+        assert_eq!(data().challenge(3).unwrap().target, 0);
+
+        let title = "[[titles]]\nid = 1\nname = \"x\"\n";
+        let challenge = "[[challenges]]\nid = 1\nname = \"x\"\ngoal = \"kill\"\ntitle = 1\n";
+        for bad in [
+            format!("{title}{title}"),
+            format!("{title}{challenge}{challenge}"),
+            format!("{title}{}", challenge.replace("title = 1", "title = 2")),
+            format!("{title}{challenge}count = 0\n"),
+            format!("{title}{}", challenge.replace("kill", "dance")),
+        ] {
+            assert!(KharaData::from_toml(&bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_messages() {
+        let data = data();
+        let khara = KharaProgress::from_rows(&[(1, 2)], &[1], Some(1));
+        let list = build_ans_khara_list(&data, &khara);
+        assert_eq!(&list[..4], &[0x11, 0x3F, 3, 0]);
+        assert_eq!(&list[4..16], &[1, 0, 0, 0, 2, 0, 0, 0, 3, 0, 0, 0]);
+        assert_eq!(&list[40..], &[1, 0, 1, 0, 0, 0, 1, 0, 0, 0]);
+
+        let update = KharaUpdate {
+            progressed: vec![(1, 3)],
+            unlocked: vec![1],
+        };
+        assert_eq!(
+            update.messages(),
+            [
+                vec![0x12, 0x3F, 1, 0, 0, 0, 3, 0, 0, 0],
+                vec![0x13, 0x3F, 1, 0, 0, 0]
+            ]
+        );
+
+        assert_eq!(
+            parse_req_equip_title(&[0x14, 0x3F, 2, 0, 0, 0]).unwrap(),
+            Some(2)
+        );
+        assert_eq!(
+            parse_req_equip_title(&[0x14, 0x3F, 0, 0, 0, 0]).unwrap(),
+            None
+        );
+        let message = [0x14, 0x3F, 2, 0, 0, 0];
+        for cut in 0..message.len() {
+            assert!(parse_req_equip_title(&message[..cut]).is_err());
+        }
+        assert!(parse_req_equip_title(&[0x15, 0x3F, 2, 0, 0, 0]).is_err());
+        assert_eq!(
+            build_ans_equip_title(true, Some(2)),
+            [0x15, 0x3F, 1, 2, 0, 0, 0]
+        );
+    }
+}
@@ -3,6 +3,37 @@
 //! Game world server for Ragnarok Online 2 server emulator.
 //! Handles in-game logic including player movement, combat, NPCs, monsters, etc.
 
+pub mod afk;
+pub mod announce;
+pub mod botcheck;
+pub mod channel;
+pub mod combatlog;
+pub mod console;
+pub mod cooldown;
+pub mod event_currency;
+pub mod gm;
+pub mod guild_storage;
 pub mod handlers;
+pub mod inventory;
+pub mod items;
+pub mod journal;
+pub mod khara;
+pub mod maps;
+pub mod megaphone;
+pub mod monster;
+pub mod mount;
+pub mod party_finder;
+pub mod playtime;
+pub mod plugins;
+pub mod population;
+pub mod professions;
+pub mod profile;
+pub mod rates;
+pub mod respec;
+pub mod social;
+pub mod stats;
+pub mod tutorial;
+pub mod vending;
+pub mod world;
 
 pub use handlers::system::SystemMessageHandler;
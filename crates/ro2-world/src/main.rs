@@ -3,52 +3,729 @@
 //! Handles game world simulation on port 7401
 //! (Minimal implementation for proof of concept)
 
-mod handlers;
-
 use anyhow::Result;
+use ro2_common::clock::Clock;
+use ro2_common::config::{self, ServerConfig};
+use ro2_common::console::{Console, ConsoleConfig};
+#[cfg(feature = "discord")]
+use ro2_common::discord::{Discord, DiscordConfig};
+use ro2_common::events::{EventBus, Maintenance, ServerEvent, ZonePopulation};
+use ro2_common::localization::{DEFAULT_LOCALE_DIR, Localization};
+use ro2_common::logging::LoggingConfig;
+use ro2_common::maintenance::{MAINTENANCE_PATH, MaintenanceConfig, MaintenanceScheduler};
+use ro2_common::net::{ConnectionRegistry, Listeners};
+use ro2_common::plugin::{PluginConfig, PluginContext, PluginHost};
+use ro2_common::selftest;
+use ro2_common::session::SessionManager;
+use ro2_world::afk::{AfkConfig, AfkMonitor};
+use ro2_world::announce::{self, SystemMessenger};
+use ro2_world::botcheck::{BotCheck, BotCheckConfig};
+use ro2_world::channel::{self, ChannelConfig};
+use ro2_world::combatlog::CombatLogConfig;
+use ro2_world::console::{
+    BotCheckCommand, GlobalCommand, NoticeCommand, PopulationCommand, RatesCommand, ReloadCommand,
+    SaveStateCommand, StallsCommand,
+};
+use ro2_world::cooldown::CooldownConfig;
+use ro2_world::event_currency::{self, EVENTS_PATH, EventData};
+use ro2_world::items::{self, ITEMS_PATH, ItemData};
+use ro2_world::journal::{self, DEFAULT_JOURNAL_PATH, Journal};
+use ro2_world::khara::{KHARA_PATH, KharaData};
+use ro2_world::maps::{MAPS_PATH, MapData};
+use ro2_world::megaphone::{self, MegaphoneConfig};
+use ro2_world::monster::{MONSTERS_PATH, MonsterData, ThreatConfig};
+use ro2_world::mount::{MOUNTS_PATH, MountData};
+use ro2_world::party_finder::PartyFinderConfig;
+use ro2_world::plugins;
+use ro2_world::population::{PopulationConfig, PopulationHistory};
+use ro2_world::professions::{PROFESSIONS_PATH, ProfessionData};
+use ro2_world::profile::{self, ProfileConfig, Profiles};
+use ro2_world::rates::{RateConfig, Rates};
+use ro2_world::respec::RespecConfig;
+use ro2_world::stats::{JOBS_PATH, JobData};
+use ro2_world::tutorial::{TUTORIAL_PATH, Tutorial, TutorialConfig, TutorialData};
+use ro2_world::vending::{self, VendingConfig};
+use ro2_world::world::{
+    DEFAULT_TICK_INTERVAL, DevConfig, MovementSync, OverloadConfig, RegenConfig, SaveState, World,
+    Zone, ZoneId, debug,
+};
 use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::{TcpListener, TcpStream};
-use tracing::{error, info};
+use tokio::net::TcpStream;
+use tracing::{Instrument, error, info, info_span, warn};
 
+const CONFIG_PATH: &str = "config/world.toml";
 const WORLD_PORT: u16 = 7401;
+const TICK_STATS_INTERVAL: Duration = Duration::from_secs(60);
+/// How often expired rental items are deleted from the database
+const ITEM_PURGE_INTERVAL: Duration = Duration::from_secs(60);
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    // Initialize logging
-    tracing_subscriber::fmt()
-        .with_env_filter(
-            tracing_subscriber::EnvFilter::from_default_env()
-                .add_directive(tracing::Level::INFO.into()),
-        )
-        .init();
+    // Logging outputs come from the `[logging]` section
+    let _log = LoggingConfig::load(CONFIG_PATH)?.init("world")?;
+
+    // `--self-test` checks the server could start, then exits
+    if selftest::requested() {
+        return self_test().await;
+    }
 
     info!("Starting RO2 World Server v{}", env!("CARGO_PKG_VERSION"));
 
-    // Bind to world port
-    let addr = SocketAddr::from(([0, 0, 0, 0], WORLD_PORT));
-    let listener = TcpListener::bind(addr).await?;
+    // Recover anything the last run journaled but didn't save. Nothing
+    // writes to the journal yet; gameplay handlers will.
+    let _journal = recover_journal().await?;
+
+    // Bind every configured listener
+    let config = ServerConfig::load(CONFIG_PATH, WORLD_PORT)?;
+    let mut listeners = Listeners::bind(&config.all_listeners()).await?;
 
-    info!("World server listening on {}", addr);
+    // Players arrive with a token from the lobby, signed with the secret
+    // the two share. Nothing redeems them yet; the world entry handler will.
+    let _sessions = match config.transfer_secret()? {
+        Some(secret) => Some(Arc::new(SessionManager::new(secret)?)),
+        None => {
+            warn!("No transfer_secret configured, players can't be handed over from the lobby");
+            None
+        }
+    };
+    for (listener, addr) in listeners.local_addrs() {
+        info!("World server listening on {} ({})", addr, listener.name);
+    }
     info!("NOTE: World server is minimal PoC implementation");
 
-    // Accept connections
+    // System messages and scheduled announcements. Sessions register with
+    // the messenger once they enter the world.
+    let localization = Arc::new(Localization::load_dir(DEFAULT_LOCALE_DIR)?);
+    info!("Loaded languages: {}", localization.languages().join(", "));
+    let messenger = SystemMessenger::new(Arc::clone(&localization));
+    announce::schedule(&messenger, announce::load_announcements(CONFIG_PATH)?);
+
+    // Idle players are warned, then moved out. Sessions are tracked once
+    // they enter the world.
+    let afk_config = AfkConfig::load(CONFIG_PATH)?;
+    if afk_config.enabled() {
+        info!(
+            "Players idle for {} minutes go to {:?}",
+            afk_config.idle_minutes, afk_config.action
+        );
+    }
+    let afk = AfkMonitor::new(afk_config, Arc::clone(&localization));
+    afk.spawn();
+
+    let events = EventBus::new();
+
+    // Players are counted down to a scheduled restart, new connections
+    // turned away shortly before it, and the world saved at it
+    let maintenance = MaintenanceConfig::load(MAINTENANCE_PATH)?;
+    if maintenance.is_enabled() {
+        info!(
+            "Maintenance restarts at {}",
+            maintenance.restarts.join(", ")
+        );
+    }
+    let maintenance = MaintenanceScheduler::new(maintenance);
+    let gate = maintenance.gate();
+    let mut restart = events.subscribe::<Maintenance>();
+    announce::warn_of_maintenance(&messenger, &events);
+    maintenance.spawn(&events);
+    #[cfg(feature = "discord")]
+    let discord = {
+        let discord = Discord::new(DiscordConfig::load(CONFIG_PATH)?);
+        discord.spawn_bot(Arc::new(messenger.clone()));
+        discord.subscribe(&events)
+    };
+
+    let professions = ProfessionData::load(PROFESSIONS_PATH)?;
+    info!(
+        "Loaded {} gathering nodes ({} spawns) and {} recipes",
+        professions.nodes.len(),
+        professions.spawns.len(),
+        professions.recipes.len()
+    );
+    let khara = KharaData::load(KHARA_PATH)?;
+    info!(
+        "Loaded {} Khara challenges and {} titles",
+        khara.challenges.len(),
+        khara.titles.len()
+    );
+    let mounts = MountData::load(MOUNTS_PATH)?;
+    info!("Loaded {} mounts", mounts.mounts.len());
+    let jobs = JobData::load(JOBS_PATH)?;
+    info!("Loaded growth for {} jobs", jobs.jobs.len());
+    let monsters = MonsterData::load(MONSTERS_PATH)?;
+    info!(
+        "Loaded {} monsters ({} bosses)",
+        monsters.monsters.len(),
+        monsters.monsters.iter().filter(|m| m.is_boss()).count()
+    );
+    // Characters can only enter these; the world entry handler will check
+    let maps = MapData::load(MAPS_PATH)?;
+    if maps.maps.is_empty() {
+        warn!("No maps in {}, characters can't enter the world", MAPS_PATH);
+    }
+    // New characters start here; the world entry handler will place them
+    let tutorial = Tutorial::new(
+        TutorialConfig::load(CONFIG_PATH)?,
+        TutorialData::load(TUTORIAL_PATH)?,
+    )
+    .with_events(&events);
+    if tutorial.is_enabled() {
+        info!(
+            "Tutorial of {} steps in map {}",
+            tutorial.data().steps.len(),
+            tutorial.data().map
+        );
+        if maps.map(tutorial.data().map).is_none() {
+            warn!(
+                "Tutorial map {} isn't in {}",
+                tutorial.data().map,
+                MAPS_PATH
+            );
+        }
+    }
+    // Parties from the dungeon queue get instances of these maps
+    let party_finder = PartyFinderConfig::load(CONFIG_PATH)?;
+    if !party_finder.dungeons.is_empty() {
+        info!(
+            "Party finder for {} dungeons, up to {} instances from zone {}",
+            party_finder.dungeons.len(),
+            party_finder.max_instances,
+            party_finder.first_instance_zone
+        );
+        if let Err(e) = party_finder.check_maps(&maps) {
+            warn!("Party finder: {:#}", e);
+        }
+    }
+
+    let regen = RegenConfig::load(CONFIG_PATH)?;
+    info!(
+        "Regen every {} ms, {} ms after combat",
+        regen.interval_ms, regen.combat_timeout_ms
+    );
+    let cooldowns = CooldownConfig::load(CONFIG_PATH)?;
+    info!(
+        "Global cooldown {} ms, {} ms tolerance",
+        cooldowns.gcd_ms, cooldowns.tolerance_ms
+    );
+    let threat = ThreatConfig::load(CONFIG_PATH)?;
+    info!(
+        "Threat: {}% from healing, {}% to switch targets, {} ms taunts",
+        threat.heal_percent, threat.switch_percent, threat.taunt_ms
+    );
+    // Zones record their combat log here once combat runs in them
+    let combat_log = CombatLogConfig::load(CONFIG_PATH)?;
+    if let Some(dir) = &combat_log.record_dir {
+        info!("Recording combat logs to {}", dir.display());
+    }
+    let overload = OverloadConfig::load(CONFIG_PATH)?;
+    info!(
+        "Shedding load once {}% of ticks overrun or {} commands queue up",
+        overload.overrun_percent, overload.queue_depth
+    );
+    let movement = MovementSync::load(CONFIG_PATH)?;
+    info!(
+        "Movement sync: {} updates/s, {} ms interpolation, {} ms max extrapolation",
+        movement.update_hz, movement.interpolation_ms, movement.max_extrapolation_ms
+    );
+
+    // A zone per hosted map (one empty zone if there are none), or the
+    // last run's save state in development
+    let dev = DevConfig::load(CONFIG_PATH)?;
+    let mut world = World::new(DEFAULT_TICK_INTERVAL)
+        .with_movement_sync(movement)
+        .with_regen(regen)
+        .with_overload(overload);
+    match dev
+        .savestate
+        .as_deref()
+        .map(SaveState::read)
+        .transpose()?
+        .flatten()
+    {
+        Some(state) => {
+            info!(
+                "Restored {} zones ({} entities) from the save state",
+                state.zones.len(),
+                state.entities()
+            );
+            state.restore(&mut world);
+        }
+        None if maps.maps.is_empty() => {
+            world.start_zone(ZoneId(1), Zone::new());
+        }
+        None => {
+            for map in &maps.maps {
+                world.start_zone(ZoneId(map.id), Zone::new());
+            }
+            info!("Started {} map zones", maps.maps.len());
+        }
+    }
+    let world = Arc::new(world);
+    if let Some(addr) = dev.debug_http {
+        debug::start(addr, Arc::clone(&world)).await?;
+    }
+    tokio::spawn({
+        let world = Arc::clone(&world);
+        async move {
+            let mut interval = tokio::time::interval(TICK_STATS_INTERVAL);
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                world.log_tick_stats();
+            }
+        }
+    });
+    // Zone populations are sampled for the console and published for the
+    // dashboard
+    let population = PopulationConfig::load(CONFIG_PATH)?;
+    info!(
+        "Sampling zone populations every {} s, keeping {}",
+        population.interval_secs, population.history
+    );
+    let history = Arc::new(Mutex::new(PopulationHistory::new(population)));
+    tokio::spawn({
+        let world = Arc::clone(&world);
+        let history = Arc::clone(&history);
+        let events = events.downgrade();
+        async move {
+            let mut interval = tokio::time::interval(population.interval());
+            loop {
+                interval.tick().await;
+                let Some(events) = events.upgrade() else {
+                    return;
+                };
+                // Sampled even while shedding load, so the history shows it
+                let samples = history
+                    .lock()
+                    .unwrap()
+                    .record(Clock::system().unix(), &world.tick_stats());
+                if world.load().is_overloaded() {
+                    continue;
+                }
+                for sample in samples {
+                    events.publish(ZonePopulation::from(sample));
+                }
+            }
+        }
+    });
+
+    // Rentals vanish from the database once they expire, whether or not
+    // their owner is online
+    let item_data = ItemData::load(ITEMS_PATH)?;
+    info!(
+        "Loaded {} item templates ({} megaphones)",
+        item_data.items.len(),
+        item_data.items.iter().filter(|item| item.megaphone).count()
+    );
+    dotenvy::dotenv().ok();
+    let pool = match config::database_url()? {
+        Some(url) => Some(sqlx::SqlitePool::connect(&url).await?),
+        None => None,
+    };
+    match &pool {
+        Some(pool) => items::spawn_purge(
+            pool.clone(),
+            Clock::system(),
+            ITEM_PURGE_INTERVAL,
+            world.load().clone(),
+        ),
+        None => warn!("DATABASE_URL not set, expired rental items won't be purged"),
+    }
+
+    // Megaphones and GM announcements reach every channel through the
+    // shared database
+    let megaphones = MegaphoneConfig::load(CONFIG_PATH)?;
+    match &pool {
+        Some(pool) => {
+            info!(
+                "Megaphones every {} s, up to {} characters",
+                megaphones.cooldown_secs, megaphones.max_length
+            );
+            megaphone::spawn_relay(pool.clone(), messenger.clone(), megaphones.poll_interval());
+        }
+        None => warn!("DATABASE_URL not set, messages from other channels won't be relayed"),
+    }
+
+    // The lobby lists channels by the populations they report there too
+    let channel_config = ChannelConfig::load(CONFIG_PATH)?;
+    match &pool {
+        Some(pool) if channel_config.reports() => {
+            info!(
+                "Reporting as channel {} ({}) every {} s",
+                channel_config.id, channel_config.name, channel_config.report_secs
+            );
+            channel::spawn_reporter(
+                pool.clone(),
+                channel_config.clone(),
+                &events,
+                Clock::system(),
+            );
+        }
+        Some(_) => info!("Channel id is 0, not reporting to the lobby"),
+        None => warn!("DATABASE_URL not set, the lobby won't see this channel's population"),
+    }
+
+    // Stalls close with their owners, so any still saved for this channel
+    // were left by a crash
+    let vending_config = VendingConfig::load(CONFIG_PATH)?;
+    info!(
+        "Vending stalls sell up to {} items at up to {} zeny each",
+        vending_config.max_items, vending_config.max_price
+    );
+    match &pool {
+        Some(pool) => {
+            let cleared = vending::clear_stalls(pool, channel_config.id).await?;
+            if cleared > 0 {
+                warn!(
+                    "Closed {} vending stalls left open by the last run",
+                    cleared
+                );
+            }
+        }
+        None => warn!("DATABASE_URL not set, players can't open vending stalls"),
+    }
+
+    // Scripts and systems keep their flags in memory; what they change is
+    // saved in the background
+    let profile_config = ProfileConfig::load(CONFIG_PATH)?;
+    let profiles = Profiles::new(profile_config);
+    match &pool {
+        Some(pool) => {
+            info!(
+                "Saving profile values every {} s, up to {} bytes each",
+                profile_config.save_secs, profile_config.max_value_bytes
+            );
+            profile::spawn_saver(pool.clone(), profiles.clone(), Clock::system());
+        }
+        None => warn!("DATABASE_URL not set, profile values won't be saved"),
+    }
+
+    let respec = RespecConfig::load(CONFIG_PATH)?;
+    info!(
+        "Stat and skill resets at {} NPCs cost {} zeny after {} free, {}% more each time up to {}",
+        respec.npcs.len(),
+        respec.base_cost,
+        respec.free_resets,
+        respec.growth_percent,
+        respec.max_cost
+    );
+
+    let rates = Arc::new(Rates::new(RateConfig::load(CONFIG_PATH)?));
+    info!("Rates: {:?}", rates.get());
+
+    // Event currency drops from kills and expires on its own schedule
+    let event_data = Arc::new(EventData::load(EVENTS_PATH)?);
+    info!(
+        "Loaded {} event currencies and {} exchange shops",
+        event_data.currencies.len(),
+        event_data.shops.len()
+    );
+    match &pool {
+        Some(pool) => {
+            event_currency::spawn_drops(
+                pool.clone(),
+                Arc::clone(&event_data),
+                Arc::clone(&rates),
+                Clock::system(),
+                &events,
+            );
+            event_currency::spawn_purge(
+                pool.clone(),
+                Clock::system(),
+                ITEM_PURGE_INTERVAL,
+                world.load().clone(),
+            );
+        }
+        None => warn!("DATABASE_URL not set, event currency won't drop"),
+    }
+
+    // Sessions with too many anti-cheat violations have to answer a
+    // challenge or be kicked
+    let bot_check_config = BotCheckConfig::load(CONFIG_PATH)?;
+    if bot_check_config.enabled() {
+        info!(
+            "Anti-bot challenges after {} violations in {} s",
+            bot_check_config.threshold, bot_check_config.window_secs
+        );
+    }
+    let bot_check = match &pool {
+        Some(pool) => BotCheck::new(bot_check_config, localization).with_pool(pool.clone()),
+        None => BotCheck::new(bot_check_config, localization),
+    };
+    bot_check.spawn(&events);
+
+    // Plugins built into this binary add their commands next to ours and
+    // start once the server is up
+    let mut plugin_host = PluginHost::new(PluginConfig::load(CONFIG_PATH)?);
+    for plugin in plugins::compiled_in() {
+        plugin_host.load(plugin);
+    }
+    if !plugin_host.names().is_empty() {
+        info!("Loaded plugins: {}", plugin_host.names().join(", "));
+    }
+
+    // Players don't go through the shared connection loop yet, so the
+    // console's session list stays empty until they do
+    let console = Console::new(Arc::new(ConnectionRegistry::new()))
+        .with_events(events.clone())
+        .command("rates", RatesCommand(Arc::clone(&rates)))
+        .command(
+            "reload",
+            ReloadCommand {
+                rates: Arc::clone(&rates),
+                path: CONFIG_PATH.into(),
+            },
+        )
+        .command("notice", NoticeCommand(messenger.clone()))
+        .command("population", PopulationCommand(Arc::clone(&history)))
+        .command(
+            "savestate",
+            SaveStateCommand {
+                world: Arc::clone(&world),
+                path: dev.savestate.clone(),
+            },
+        );
+    let console = match &pool {
+        Some(pool) => console
+            .command("global", GlobalCommand(pool.clone()))
+            .command(
+                "botcheck",
+                BotCheckCommand {
+                    check: bot_check.clone(),
+                    pool: pool.clone(),
+                },
+            )
+            .command("stalls", StallsCommand(pool.clone())),
+        None => console,
+    };
+    let console = plugin_host.console(console);
+    console.start(&ConsoleConfig::load(CONFIG_PATH)?).await?;
+
+    events.publish(ServerEvent::ServerStarted {
+        server: "World server".to_string(),
+    });
+    plugin_host
+        .start(&PluginContext {
+            server: "world",
+            events: events.clone(),
+            clock: Clock::system(),
+            pool: pool.clone(),
+        })
+        .await;
+
+    // Accept connections until Ctrl-C
     loop {
-        match listener.accept().await {
-            Ok((socket, addr)) => {
-                info!("New connection from {}", addr);
-
-                tokio::spawn(async move {
-                    if let Err(e) = handle_client(socket, addr).await {
-                        error!("Error handling client {}: {}", addr, e);
-                    }
-                });
+        let accepted = tokio::select! {
+            accepted = listeners.accept() => match accepted {
+                Some(accepted) => accepted,
+                None => break,
+            },
+            _ = tokio::signal::ctrl_c() => {
+                info!("Shutting down");
+                break;
             }
-            Err(e) => {
-                error!("Failed to accept connection: {}", e);
+            Some(step) = restart.recv() => {
+                if step == Maintenance::Restart {
+                    info!("Restarting for maintenance");
+                    break;
+                }
+                continue;
             }
+        };
+        let (socket, addr) = (accepted.stream, accepted.addr);
+        if let Err(e) = gate.admit() {
+            info!("Turning away {}: {}", addr, e);
+            continue;
         }
+        info!("New connection from {} on {}", addr, accepted.listener.name);
+
+        let span = info_span!(
+            "connection",
+            remote_addr = %addr,
+            listener = %accepted.listener.name
+        );
+        tokio::spawn(
+            async move {
+                if let Err(e) = handle_client(socket, addr).await {
+                    error!("Error handling client {}: {}", addr, e);
+                }
+            }
+            .instrument(span),
+        );
+    }
+
+    if let Some(path) = &dev.savestate {
+        let state = SaveState::take(&world, Clock::system().unix()).await?;
+        state.write(path)?;
+        info!("Saved {} entities to {}", state.entities(), path.display());
+    }
+
+    plugin_host.stop().await;
+    events.publish(ServerEvent::ServerStopped {
+        server: "World server".to_string(),
+    });
+    // Let subscribers see the bus close and finish up
+    drop(events);
+    #[cfg(feature = "discord")]
+    let _ = discord.await;
+
+    Ok(())
+}
+
+/// Run the `--self-test` checks
+async fn self_test() -> Result<()> {
+    dotenvy::dotenv().ok();
+    let mut test = selftest::run(CONFIG_PATH, WORLD_PORT).await;
+    test.record(
+        "localization",
+        Localization::load_dir(DEFAULT_LOCALE_DIR)
+            .map(|localization| localization.languages().join(", ")),
+    );
+    test.record(
+        "professions",
+        ProfessionData::load(PROFESSIONS_PATH)
+            .map(|data| format!("{} nodes, {} recipes", data.nodes.len(), data.recipes.len())),
+    );
+    test.record(
+        "events",
+        EventData::load(EVENTS_PATH).map(|data| {
+            format!(
+                "{} currencies, {} shops",
+                data.currencies.len(),
+                data.shops.len()
+            )
+        }),
+    );
+    test.record(
+        "khara",
+        KharaData::load(KHARA_PATH).map(|data| format!("{} challenges", data.challenges.len())),
+    );
+    test.record(
+        "mounts",
+        MountData::load(MOUNTS_PATH).map(|data| format!("{} mounts", data.mounts.len())),
+    );
+    test.record(
+        "jobs",
+        JobData::load(JOBS_PATH).map(|data| format!("{} jobs", data.jobs.len())),
+    );
+    test.record(
+        "items",
+        ItemData::load(ITEMS_PATH).map(|data| format!("{} items", data.items.len())),
+    );
+    test.record(
+        "monsters",
+        MonsterData::load(MONSTERS_PATH).map(|data| format!("{} monsters", data.monsters.len())),
+    );
+    test.record(
+        "maps",
+        MapData::load(MAPS_PATH).map(|data| format!("{} maps", data.maps.len())),
+    );
+    test.record(
+        "tutorial",
+        TutorialData::load(TUTORIAL_PATH).and_then(|data| {
+            let enabled = TutorialConfig::load(CONFIG_PATH)?.enabled;
+            Ok(format!("{} steps, enabled: {}", data.steps.len(), enabled))
+        }),
+    );
+    test.record(
+        "party_finder",
+        PartyFinderConfig::load(CONFIG_PATH).and_then(|finder| {
+            finder.check_maps(&MapData::load(MAPS_PATH)?)?;
+            Ok(format!("{} dungeons", finder.dungeons.len()))
+        }),
+    );
+    test.record(
+        "cooldowns",
+        CooldownConfig::load(CONFIG_PATH).map(|cooldowns| format!("{:?}", cooldowns)),
+    );
+    test.record(
+        "maintenance",
+        MaintenanceConfig::load(MAINTENANCE_PATH)
+            .map(|maintenance| format!("restarts at {:?}", maintenance.restarts)),
+    );
+    test.record(
+        "overload",
+        OverloadConfig::load(CONFIG_PATH).map(|overload| format!("{:?}", overload)),
+    );
+    test.record(
+        "population",
+        PopulationConfig::load(CONFIG_PATH).map(|population| format!("{:?}", population)),
+    );
+    test.record(
+        "threat",
+        ThreatConfig::load(CONFIG_PATH).map(|threat| format!("{:?}", threat)),
+    );
+    test.record(
+        "combat log",
+        CombatLogConfig::load(CONFIG_PATH).map(|combat_log| format!("{:?}", combat_log)),
+    );
+    test.record(
+        "afk",
+        AfkConfig::load(CONFIG_PATH).map(|afk| format!("{:?}", afk)),
+    );
+    test.record(
+        "bot check",
+        BotCheckConfig::load(CONFIG_PATH).map(|bot_check| format!("{:?}", bot_check)),
+    );
+    test.record(
+        "channel",
+        ChannelConfig::load(CONFIG_PATH).map(|channel| format!("{:?}", channel)),
+    );
+    test.record(
+        "vending",
+        VendingConfig::load(CONFIG_PATH).map(|vending| format!("{:?}", vending)),
+    );
+    test.record(
+        "plugins",
+        PluginConfig::load(CONFIG_PATH).map(|plugins| format!("{:?}", plugins)),
+    );
+    test.record(
+        "profile",
+        ProfileConfig::load(CONFIG_PATH).map(|profile| format!("{:?}", profile)),
+    );
+    test.record(
+        "respec",
+        RespecConfig::load(CONFIG_PATH).map(|respec| format!("{:?}", respec)),
+    );
+    test.record(
+        "megaphone",
+        MegaphoneConfig::load(CONFIG_PATH).map(|megaphone| format!("{:?}", megaphone)),
+    );
+    test.record(
+        "rates",
+        RateConfig::load(CONFIG_PATH).map(|rates| format!("{:?}", rates)),
+    );
+    test.finish()
+}
+
+/// Open the journal, replaying it into the database if the last run
+/// didn't shut down cleanly
+async fn recover_journal() -> Result<Journal> {
+    let (mut journal, entries) = Journal::open(DEFAULT_JOURNAL_PATH)?;
+    if entries.is_empty() {
+        return Ok(journal);
     }
+
+    warn!(
+        "Unclean shutdown: {} journal entries to replay from {}",
+        entries.len(),
+        journal.path().display()
+    );
+    dotenvy::dotenv().ok();
+    let Some(url) = config::database_url()? else {
+        // Keep the entries for a run that can reach the database
+        warn!("DATABASE_URL not set, leaving the journal for the next start");
+        return Ok(journal);
+    };
+    let pool = sqlx::SqlitePool::connect(&url).await?;
+    journal::replay(&pool, &entries).await?;
+    journal.checkpoint()?;
+    info!("Replayed {} journal entries", entries.len());
+
+    Ok(journal)
 }
 
 /// Handle a single client connection
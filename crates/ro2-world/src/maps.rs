@@ -0,0 +1,292 @@
+//! Maps this server hosts
+//!
+//! A world server hosts some of the game's maps, one [`Zone`](crate::world::Zone)
+//! each. Which ones, and what a character may do on them, is data read
+//! from `config/maps.toml`:
+//!
+//! ```toml
+//! [[maps]]
+//! id = 1
+//! name = "Prontera"
+//! area = { min = [0.0, -50.0, 0.0], max = [2048.0, 500.0, 2048.0] }
+//! spawn = [1024.0, 0.0, 1024.0]   # Where characters found outside the area go
+//! channels = [1, 2]               # Channels it runs on; every channel if left out
+//! music = 12                      # Background music ID
+//! flags = ["town", "no_mount"]
+//!
+//! [[maps.warps]]
+//! position = [2040.0, 0.0, 1024.0]
+//! radius = 8.0
+//! to_map = 2
+//! to_position = [16.0, 0.0, 512.0]
+//! ```
+//!
+//! Loading checks that every warp leads inside the area of a map listed
+//! here, on the same server. [`MapData::entry`], [`MapData::check_teleport`]
+//! and [`MapData::warp_at`] are the checks for characters entering a map,
+//! teleporting and using warps, but they aren't enforced yet: the world
+//! server doesn't handle those requests, and GM commands ([`crate::gm`])
+//! take GMs to players without them. Only dungeon entrances are checked
+//! against the maps, when the dungeon queue starts.
+
+use crate::world::Position;
+use anyhow::{Context, Result, anyhow};
+use ro2_common::config::{self, Validate};
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::path::Path;
+
+/// Where the world server reads its maps from
+pub const MAPS_PATH: &str = "config/maps.toml";
+
+/// A box characters may stand in
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub struct Area {
+    pub min: Position,
+    pub max: Position,
+}
+
+impl Area {
+    pub fn contains(&self, position: Position) -> bool {
+        (self.min.x..=self.max.x).contains(&position.x)
+            && (self.min.y..=self.max.y).contains(&position.y)
+            && (self.min.z..=self.max.z).contains(&position.z)
+    }
+}
+
+/// A portal to another map
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub struct Warp {
+    pub position: Position,
+    /// How close a character has to come to use it
+    pub radius: f32,
+    pub to_map: u32,
+    pub to_position: Position,
+}
+
+/// What a map allows or is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MapFlag {
+    /// A safe town
+    Town,
+    /// Players can fight each other
+    Pvp,
+    /// No teleporting away; warps still work
+    NoTeleport,
+    /// No riding mounts
+    NoMount,
+}
+
+/// One hosted map
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct MapInfo {
+    pub id: u32,
+    pub name: String,
+    pub area: Area,
+    pub spawn: Position,
+    #[serde(default)]
+    pub warps: Vec<Warp>,
+    /// Empty for every channel
+    #[serde(default)]
+    pub channels: Vec<u16>,
+    #[serde(default)]
+    pub music: Option<u32>,
+    #[serde(default)]
+    pub flags: Vec<MapFlag>,
+}
+
+impl MapInfo {
+    pub fn has(&self, flag: MapFlag) -> bool {
+        self.flags.contains(&flag)
+    }
+
+    pub fn runs_on(&self, channel: u16) -> bool {
+        self.channels.is_empty() || self.channels.contains(&channel)
+    }
+}
+
+/// Every map this server hosts
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct MapData {
+    #[serde(default)]
+    pub maps: Vec<MapInfo>,
+}
+
+impl MapData {
+    /// Read `path`; a missing file means no maps
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        config::load(path)
+    }
+
+    /// Parse TOML text
+    pub fn from_toml(text: &str) -> Result<Self> {
+        config::from_toml(text)
+    }
+
+    /// The map `id`, if this server hosts it
+    pub fn map(&self, id: u32) -> Option<&MapInfo> {
+        self.maps.iter().find(|map| map.id == id)
+    }
+
+    fn hosted(&self, id: u32) -> Result<&MapInfo> {
+        self.map(id)
+            .ok_or_else(|| anyhow!("map {} isn't hosted on this server", id))
+    }
+
+    /// Check a character may stand at `position` on map `map_id`
+    pub fn check_position(&self, map_id: u32, position: Position) -> Result<()> {
+        let map = self.hosted(map_id)?;
+        if !map.area.contains(position) {
+            return Err(anyhow!(
+                "{:?} is outside the area of map {} ({})",
+                position,
+                map.id,
+                map.name
+            ));
+        }
+        Ok(())
+    }
+
+    /// Where a character saved at `position` on `map_id` enters on
+    /// `channel`: where they were, or the map's spawn if that's outside
+    /// its area. Refused if the map isn't hosted here or on that channel.
+    pub fn entry(&self, map_id: u32, channel: u16, position: Position) -> Result<Position> {
+        let map = self.hosted(map_id)?;
+        if !map.runs_on(channel) {
+            return Err(anyhow!(
+                "map {} ({}) isn't on channel {}",
+                map.id,
+                map.name,
+                channel
+            ));
+        }
+        Ok(if map.area.contains(position) {
+            position
+        } else {
+            map.spawn
+        })
+    }
+
+    /// Check a character on `from_map` may teleport to `position` on
+    /// `to_map`
+    pub fn check_teleport(&self, from_map: u32, to_map: u32, position: Position) -> Result<()> {
+        let from = self.hosted(from_map)?;
+        if from.has(MapFlag::NoTeleport) {
+            return Err(anyhow!(
+                "can't teleport out of map {} ({})",
+                from.id,
+                from.name
+            ));
+        }
+        self.check_position(to_map, position)
+    }
+
+    /// The warp a character at `position` on `map_id` is standing in
+    pub fn warp_at(&self, map_id: u32, position: Position) -> Option<&Warp> {
+        self.map(map_id)?
+            .warps
+            .iter()
+            .find(|warp| warp.position.distance_squared(&position) <= warp.radius * warp.radius)
+    }
+}
+
+impl Validate for MapData {
+    fn validate(&self) -> Result<()> {
+        let mut ids = HashSet::new();
+        for map in &self.maps {
+            if map.id == 0 || !ids.insert(map.id) {
+                return Err(anyhow!("map id {} is 0 or used twice", map.id));
+            }
+            if !map.area.contains(map.spawn) {
+                return Err(anyhow!("map {}: spawn is outside its area", map.id));
+            }
+            if map.channels.contains(&0) {
+                return Err(anyhow!("map {}: channel 0", map.id));
+            }
+        }
+        for map in &self.maps {
+            for warp in &map.warps {
+                if !map.area.contains(warp.position) || warp.radius <= 0.0 {
+                    return Err(anyhow!(
+                        "map {}: a warp is outside the area or has no radius",
+                        map.id
+                    ));
+                }
+                self.check_position(warp.to_map, warp.to_position)
+                    .with_context(|| format!("map {}: warp to map {}", map.id, warp.to_map))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAPS: &str = r#"
+        [[maps]]
+        id = 1
+        name = "Prontera"
+        area = { min = [0.0, -50.0, 0.0], max = [2048.0, 500.0, 2048.0] }
+        spawn = [1024.0, 0.0, 1024.0]
+        channels = [1, 2]
+        music = 12
+        flags = ["town", "no_teleport"]
+
+        [[maps.warps]]
+        position = [2040.0, 0.0, 1024.0]
+        radius = 8.0
+        to_map = 2
+        to_position = [16.0, 0.0, 512.0]
+
+        [[maps]]
+        id = 2
+        name = "Prontera Fields"
+        area = { min = [0.0, -50.0, 0.0], max = [1024.0, 500.0, 1024.0] }
+        spawn = [16.0, 0.0, 512.0]
+    "#;
+
+    #[test]
+    fn test_load() {
+        assert_eq!(MapData::from_toml("").unwrap(), MapData::default());
+        let data = MapData::from_toml(MAPS).unwrap();
+        assert_eq!(data.maps.len(), 2);
+        assert!(data.map(1).unwrap().has(MapFlag::Town));
+        assert_eq!(data.map(1).unwrap().music, Some(12));
+        assert!(data.map(2).unwrap().runs_on(7));
+
+        // Warps have to land inside a hosted map
+        let bad = MAPS.replace("to_map = 2", "to_map = 3");
+        assert!(MapData::from_toml(&bad).is_err());
+        let bad = MAPS.replace("to_position = [16.0", "to_position = [4096.0");
+        assert!(MapData::from_toml(&bad).is_err());
+        let bad = MAPS.replace("spawn = [16.0", "spawn = [-16.0");
+        assert!(MapData::from_toml(&bad).is_err());
+    }
+
+    #[test]
+    fn test_entry_and_teleport() {
+        let data = MapData::from_toml(MAPS).unwrap();
+        let inside = Position::new(100.0, 0.0, 100.0);
+        let outside = Position::new(-100.0, 0.0, 100.0);
+
+        assert_eq!(data.entry(1, 1, inside).unwrap(), inside);
+        assert_eq!(
+            data.entry(1, 2, outside).unwrap(),
+            Position::new(1024.0, 0.0, 1024.0)
+        );
+        assert!(data.entry(1, 3, inside).is_err());
+        assert!(data.entry(9, 1, inside).is_err());
+
+        assert!(data.check_position(2, inside).is_ok());
+        assert!(data.check_position(2, outside).is_err());
+        assert!(data.check_teleport(2, 1, inside).is_ok());
+        assert!(data.check_teleport(1, 2, inside).is_err());
+
+        let warp = data.warp_at(1, Position::new(2036.0, 0.0, 1020.0)).unwrap();
+        assert_eq!(warp.to_map, 2);
+        assert!(data.warp_at(1, inside).is_none());
+    }
+}
@@ -0,0 +1,456 @@
+//! Megaphones and GM announcements to every channel
+//!
+//! Each world server is a channel of its own and they don't talk to each
+//! other, but they share the database: a message for all of them is posted
+//! to `global_messages` (see [`GlobalMessageQueries`]) and every server's
+//! [`spawn_relay`] sends the rows it hasn't seen yet to its players as
+//! system messages. The rows stay as the record of who sent what, with
+//! which item; `global log` on the console lists the latest.
+//!
+//! Players shout with a megaphone item (`megaphone = true` in
+//! `config/items.toml`), sent as [`REQ_MEGAPHONE`]. Each message uses one
+//! up, and a character can only shout once per cooldown, on whichever
+//! channel. GMs announce with `/global <message>` in game or
+//! `global <message>` on the console, with neither limit.
+//!
+//! Limits are set in the `[megaphone]` section of `config/world.toml`:
+//!
+//! ```toml
+//! [megaphone]
+//! cooldown_secs = 60      # Between one character's shouts
+//! max_length = 80         # Characters in a message
+//! poll_ms = 1000          # How often each server looks for new messages
+//! ```
+//!
+//! Like the other `0x3Fxx` opcodes, [`REQ_MEGAPHONE`] is a placeholder.
+
+use crate::announce::{SystemMessenger, Target};
+use crate::inventory::{Inventory, ItemStack};
+use crate::items::ItemData;
+use anyhow::{Result, anyhow};
+use ro2_common::config::{self, Validate};
+use ro2_common::database::GlobalMessage;
+use ro2_common::database::queries::GlobalMessageQueries;
+use ro2_common::protocol::{ClientError, ErrorCode};
+use ro2_common::wire::WireReader;
+use serde::Deserialize;
+use sqlx::{Pool, Sqlite};
+use std::path::Path;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+pub use ro2_common::protocol::opcodes::REQ_MEGAPHONE;
+
+/// Most messages relayed per poll; the rest wait for the next one
+const RELAY_BATCH: i64 = 100;
+
+const MEGAPHONE: &str = "megaphone";
+const ANNOUNCEMENT: &str = "announcement";
+
+/// Megaphone limits
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct MegaphoneConfig {
+    pub cooldown_secs: u32,
+    pub max_length: usize,
+    pub poll_ms: u64,
+}
+
+impl Default for MegaphoneConfig {
+    fn default() -> Self {
+        Self {
+            cooldown_secs: 60,
+            max_length: 80,
+            poll_ms: 1000,
+        }
+    }
+}
+
+impl MegaphoneConfig {
+    /// Read the `[megaphone]` section of `path`; missing keys keep their
+    /// defaults
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        config::section(path, "megaphone")
+    }
+
+    /// Parse the `[megaphone]` section of TOML text
+    pub fn from_toml(text: &str) -> Result<Self> {
+        config::section_from_toml(text, "megaphone")
+    }
+
+    pub fn poll_interval(&self) -> Duration {
+        Duration::from_millis(self.poll_ms)
+    }
+}
+
+impl Validate for MegaphoneConfig {
+    fn validate(&self) -> Result<()> {
+        if self.max_length == 0 || self.poll_ms == 0 {
+            return Err(anyhow!("megaphone max_length and poll_ms can't be 0"));
+        }
+        Ok(())
+    }
+}
+
+/// The character shouting
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Shouter {
+    pub character_id: i64,
+    pub name: String,
+}
+
+/// Parse a megaphone request: u16 inventory slot of the megaphone, then
+/// the message as a u16 length and UTF-16 text
+pub fn parse_req_megaphone(message: &[u8]) -> Result<(usize, String)> {
+    let mut message = WireReader::message(message, REQ_MEGAPHONE, "megaphone request")?;
+    let slot = message.u16("slot")?;
+    let text = message.utf16("text")?;
+    Ok((usize::from(slot), text))
+}
+
+/// Parse a GM's `/global <message>`
+pub fn parse_command(line: &str) -> Result<&str> {
+    let text = line
+        .trim()
+        .strip_prefix("/global")
+        .filter(|rest| rest.is_empty() || rest.starts_with(char::is_whitespace))
+        .ok_or_else(|| anyhow!("not a /global command"))?
+        .trim();
+    if text.is_empty() {
+        return Err(anyhow!("usage: /global <message>"));
+    }
+    Ok(text)
+}
+
+/// A refusal with the text at `key` in the `[megaphone]` string table
+fn refuse(key: &str) -> ClientError {
+    ClientError::new(ErrorCode::NotAllowed, key)
+}
+
+/// Handle [`REQ_MEGAPHONE`] from `shouter`: use up the megaphone in the
+/// slot asked for and post the message for every channel. Returns the slot
+/// to journal.
+pub async fn handle_req_megaphone(
+    pool: &Pool<Sqlite>,
+    config: &MegaphoneConfig,
+    items: &ItemData,
+    inventory: &mut Inventory,
+    shouter: &Shouter,
+    message: &[u8],
+    now: i64,
+) -> Result<usize> {
+    let (slot, text) = parse_req_megaphone(message)?;
+    let stack = inventory
+        .slots()
+        .get(slot)
+        .copied()
+        .flatten()
+        .filter(|stack| items.is_megaphone(stack.item_id) && !stack.is_expired(now))
+        .ok_or_else(|| anyhow!("no megaphone in inventory slot {}", slot))?;
+
+    let text = text.trim();
+    if text.is_empty() || text.chars().any(char::is_control) {
+        return Err(ClientError::new(ErrorCode::InvalidRequest, "megaphone.bad_text").into());
+    }
+    if text.chars().count() > config.max_length {
+        return Err(refuse("megaphone.too_long")
+            .with_arg("max", config.max_length)
+            .into());
+    }
+    if let Some(last) = GlobalMessageQueries::last_megaphone(pool, shouter.character_id).await? {
+        let left = last + config.cooldown_secs as i64 - now;
+        if left > 0 {
+            return Err(refuse("megaphone.cooldown")
+                .with_arg("seconds", left)
+                .into());
+        }
+    }
+
+    GlobalMessageQueries::post(
+        pool,
+        &GlobalMessage {
+            id: 0,
+            kind: MEGAPHONE.to_string(),
+            sender: shouter.name.clone(),
+            character_id: Some(shouter.character_id),
+            item_id: Some(stack.item_id),
+            text: text.to_string(),
+            sent_at: now,
+        },
+    )
+    .await?;
+    inventory.set_slot(
+        slot,
+        Some(ItemStack {
+            quantity: stack.quantity - 1,
+            ..stack
+        }),
+    )?;
+
+    info!(
+        "Character {} ({}) used megaphone {}: {:?}",
+        shouter.name, shouter.character_id, stack.item_id, text
+    );
+    Ok(slot)
+}
+
+/// Post a GM announcement for every channel; `sender` is the GM's name
+pub async fn announce(pool: &Pool<Sqlite>, sender: &str, text: &str, now: i64) -> Result<()> {
+    GlobalMessageQueries::post(
+        pool,
+        &GlobalMessage {
+            id: 0,
+            kind: ANNOUNCEMENT.to_string(),
+            sender: sender.to_string(),
+            character_id: None,
+            item_id: None,
+            text: text.to_string(),
+            sent_at: now,
+        },
+    )
+    .await?;
+    info!("{} announced to every channel: {:?}", sender, text);
+    Ok(())
+}
+
+/// Send one posted message to everyone on this server; returns how many
+/// sessions it was sent to
+pub fn deliver(messenger: &SystemMessenger, message: &GlobalMessage) -> usize {
+    match message.kind.as_str() {
+        MEGAPHONE => messenger.send_with(
+            Target::All,
+            "global.megaphone",
+            // The text last, so nothing in it is taken for a placeholder
+            &[("name", &message.sender), ("text", &message.text)],
+        ),
+        // Like `/notice`, a localization key or plain text
+        _ => messenger.send(Target::All, &message.text),
+    }
+}
+
+/// Send every message posted from now on to this server's players,
+/// checking every `interval`
+pub fn spawn_relay(
+    pool: Pool<Sqlite>,
+    messenger: SystemMessenger,
+    interval: Duration,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(interval);
+        let mut last = None;
+        loop {
+            interval.tick().await;
+            // Messages from before the server started aren't replayed
+            let after = match last {
+                Some(id) => id,
+                None => match GlobalMessageQueries::latest_id(&pool).await {
+                    Ok(id) => *last.insert(id),
+                    Err(e) => {
+                        warn!("Reading global messages failed: {}", e);
+                        continue;
+                    }
+                },
+            };
+            match GlobalMessageQueries::since(&pool, after, RELAY_BATCH).await {
+                Ok(messages) => {
+                    for message in &messages {
+                        deliver(&messenger, message);
+                        last = Some(message.id);
+                    }
+                }
+                Err(e) => warn!("Reading global messages failed: {}", e),
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ro2_common::localization::Localization;
+    use ro2_common::testing;
+    use ro2_common::wire::WireWriter;
+    use std::sync::Arc;
+    use tokio::sync::mpsc;
+
+    fn items() -> ItemData {
+        ItemData::from_toml(
+            "[[items]]\nid = 12221\nname = \"Megaphone\"\nmegaphone = true\n\n[[items]]\nid = 501\nname = \"Red Potion\"",
+        )
+        .unwrap()
+    }
+
+    fn request(slot: u16, text: &str) -> Vec<u8> {
+        let mut out = WireWriter::message(REQ_MEGAPHONE);
+        out.u16(slot).utf16(text);
+        out.into_bytes()
+    }
+
+    fn text(message: &[u8]) -> String {
+        ro2_common::wire::read_utf16(&message[2..]).unwrap().0
+    }
+
+    /// The refusal's code, once its text is known to render in full
+    fn code(error: anyhow::Error) -> ErrorCode {
+        let refusal = error.downcast_ref::<ClientError>().unwrap();
+        let text = refusal.message(&Localization::builtin(), "en");
+        assert!(text != refusal.key && !text.contains('{'), "{}", text);
+        refusal.code
+    }
+
+    #[test]
+    fn test_config_and_parsing() {
+        assert_eq!(
+            MegaphoneConfig::load("does/not/exist.toml").unwrap(),
+            MegaphoneConfig::default()
+        );
+        let config = MegaphoneConfig::from_toml("[megaphone]\ncooldown_secs = 5").unwrap();
+        assert_eq!((config.cooldown_secs, config.max_length), (5, 80));
+        assert!(MegaphoneConfig::from_toml("[megaphone]\nmax_length = 0").is_err());
+
+        let message = request(3, "WTS 포링 card");
+        assert_eq!(
+            parse_req_megaphone(&message).unwrap(),
+            (3, "WTS 포링 card".to_string())
+        );
+        for cut in 0..message.len() {
+            assert!(parse_req_megaphone(&message[..cut]).is_err());
+        }
+
+        assert_eq!(
+            parse_command(" /global  Double EXP tonight ").unwrap(),
+            "Double EXP tonight"
+        );
+        for bad in ["/global", "/global   ", "/globalx hi", "/notice all hi"] {
+            assert!(parse_command(bad).is_err(), "{:?}", bad);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_megaphone() {
+        let pool = testing::database().await;
+        let config = MegaphoneConfig::default();
+        let items = items();
+        let mut inventory = Inventory::new(4);
+        inventory
+            .set_slot(0, Some(ItemStack::new(12221, 2)))
+            .unwrap();
+        inventory.set_slot(1, Some(ItemStack::new(501, 5))).unwrap();
+        let shouter = Shouter {
+            character_id: 7,
+            name: "Alice".to_string(),
+        };
+
+        let slot = handle_req_megaphone(
+            &pool,
+            &config,
+            &items,
+            &mut inventory,
+            &shouter,
+            &request(0, " WTS Poring card "),
+            1000,
+        )
+        .await
+        .unwrap();
+        assert_eq!(slot, 0);
+        assert_eq!(inventory.count(12221), 1);
+        let posted = GlobalMessageQueries::recent(&pool, 1).await.unwrap();
+        assert_eq!(
+            (posted[0].kind.as_str(), posted[0].text.as_str()),
+            ("megaphone", "WTS Poring card")
+        );
+        assert_eq!(posted[0].item_id, Some(12221));
+
+        // Nothing is used up by a refused shout
+        let mut shout = async |slot: u16, text: &str, now: i64| {
+            handle_req_megaphone(
+                &pool,
+                &config,
+                &items,
+                &mut inventory,
+                &shouter,
+                &request(slot, text),
+                now,
+            )
+            .await
+        };
+        assert_eq!(
+            code(shout(0, "again", 1030).await.unwrap_err()),
+            ErrorCode::NotAllowed
+        );
+        assert_eq!(
+            code(shout(0, &"a".repeat(81), 1060).await.unwrap_err()),
+            ErrorCode::NotAllowed
+        );
+        assert_eq!(
+            code(shout(0, "two\nlines", 1060).await.unwrap_err()),
+            ErrorCode::InvalidRequest
+        );
+        // Not megaphones
+        assert!(shout(1, "potion", 1060).await.is_err());
+        assert!(shout(2, "empty", 1060).await.is_err());
+        assert_eq!(shout(0, "again", 1060).await.unwrap(), 0);
+        assert_eq!(inventory.count(12221), 0);
+        assert_eq!(inventory.count(501), 5);
+    }
+
+    #[tokio::test]
+    async fn test_relay_reaches_every_server() {
+        let pool = testing::database().await;
+        announce(&pool, "gm_jane", "Before start", 1).await.unwrap();
+
+        // Two world servers on the same database
+        let mut inboxes = Vec::new();
+        let mut relays = Vec::new();
+        for _ in 0..2 {
+            let messenger = SystemMessenger::new(Arc::new(Localization::builtin()));
+            let (outbox, inbox) = mpsc::channel(8);
+            messenger.register(1, None, None, outbox);
+            inboxes.push(inbox);
+            relays.push(spawn_relay(
+                pool.clone(),
+                messenger,
+                Duration::from_millis(10),
+            ));
+        }
+        // Let both relays see where the log starts
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        GlobalMessageQueries::post(
+            &pool,
+            &GlobalMessage {
+                id: 0,
+                kind: "megaphone".to_string(),
+                sender: "Alice".to_string(),
+                character_id: Some(7),
+                item_id: Some(12221),
+                text: "LF {name} party".to_string(),
+                sent_at: 2,
+            },
+        )
+        .await
+        .unwrap();
+        announce(&pool, "gm_jane", "Double EXP tonight", 3)
+            .await
+            .unwrap();
+
+        for inbox in &mut inboxes {
+            let mut next = async || {
+                let message = tokio::time::timeout(Duration::from_secs(5), inbox.recv())
+                    .await
+                    .unwrap()
+                    .unwrap();
+                text(&message)
+            };
+            assert_eq!(next().await, "[Megaphone] Alice: LF {name} party");
+            assert_eq!(next().await, "Double EXP tonight");
+        }
+        for inbox in &mut inboxes {
+            assert!(inbox.try_recv().is_err());
+        }
+        for relay in relays {
+            relay.abort();
+        }
+    }
+}
@@ -0,0 +1,790 @@
+//! Monsters: templates, AI, elites and bosses
+//!
+//! Every monster in a zone has a [`Brain`], a small state machine ticked
+//! with the zone: it idles until a player comes within its aggro range or
+//! hits it, then fights whoever tops its [`ThreatTable`]. Once everyone on
+//! the table is dead or gone, or it's been pulled past its leash range, it
+//! resets: it forgets its threat, walks home and heals up, ignoring hits on
+//! the way. Dead monsters stay dead until they're despawned.
+//!
+//! Elites and bosses are ranked as such. A monster can have phases,
+//! entered as its HP drops below each phase's threshold and never left,
+//! and an enrage timer, after which it hits much harder. Behavior beyond
+//! damage goes in a [`BossScript`], called as the monster changes phase or
+//! enrages. Bosses are announced to their zone through the
+//! [`SystemMessenger`] when they appear, enrage, fall or leave.
+//!
+//! Monsters are data, read from `config/monsters.toml`:
+//!
+//! ```toml
+//! [[monsters]]
+//! id = 1001
+//! name = "Poring"
+//! max_hp = 50
+//! attack = 5
+//!
+//! [[monsters]]
+//! id = 2001
+//! name = "Baphomet"
+//! rank = "boss"            # normal (default), elite or boss
+//! max_hp = 500000
+//! attack = 900
+//! aggro_range = 800.0      # 0 (default) only fights back
+//! leash_range = 3000.0     # Gives up this far from home
+//! enrage_secs = 600        # Enrages this long into a fight
+//! enrage_damage_percent = 300
+//!
+//! [[monsters.phases]]
+//! hp_percent = 50          # At or below half HP...
+//! damage_percent = 150     # ...hits half again as hard
+//! ```
+
+mod threat;
+
+pub use threat::{ThreatConfig, ThreatTable};
+
+use crate::announce::{SystemMessenger, Target};
+use crate::world::{EntityId, EntityKind, Position, Zone, ZoneId};
+use anyhow::{Result, anyhow};
+use ro2_common::config::{self, Validate};
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::time::Duration;
+use tracing::debug;
+
+/// Where the world server reads monsters from
+pub const MONSTERS_PATH: &str = "config/monsters.toml";
+
+fn full_damage() -> u32 {
+    100
+}
+
+fn double_damage() -> u32 {
+    200
+}
+
+fn default_leash_range() -> f32 {
+    2000.0
+}
+
+/// How much of a threat a monster is
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Rank {
+    #[default]
+    Normal,
+    Elite,
+    /// Announced to the zone
+    Boss,
+}
+
+/// A stage of a fight, entered once the monster's HP drops to
+/// `hp_percent` of its max
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub struct Phase {
+    pub hp_percent: u32,
+    /// Damage in this phase, in percent of the monster's attack
+    #[serde(default = "full_damage")]
+    pub damage_percent: u32,
+}
+
+/// A kind of monster
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct MonsterTemplate {
+    pub id: u32,
+    pub name: String,
+    #[serde(default)]
+    pub rank: Rank,
+    pub max_hp: u32,
+    #[serde(default)]
+    pub attack: u32,
+    /// Players this close are attacked on sight; 0 never attacks first
+    #[serde(default)]
+    pub aggro_range: f32,
+    /// The monster gives up and goes home this far from where it spawned
+    #[serde(default = "default_leash_range")]
+    pub leash_range: f32,
+    /// Phases after the first, from the highest `hp_percent` down
+    #[serde(default)]
+    pub phases: Vec<Phase>,
+    /// Seconds into a fight the monster enrages; never if unset
+    #[serde(default)]
+    pub enrage_secs: Option<u32>,
+    /// Damage once enraged, in percent of what it would be otherwise
+    #[serde(default = "double_damage")]
+    pub enrage_damage_percent: u32,
+}
+
+impl MonsterTemplate {
+    pub fn is_boss(&self) -> bool {
+        self.rank == Rank::Boss
+    }
+
+    /// The phase for HP `hp`: 0 before the first threshold, then one
+    /// more for each threshold reached
+    pub fn phase_at(&self, hp: u32) -> usize {
+        let percent = hp as u64 * 100 / self.max_hp.max(1) as u64;
+        self.phases
+            .iter()
+            .take_while(|phase| percent <= phase.hp_percent as u64)
+            .count()
+    }
+}
+
+/// Every kind of monster
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct MonsterData {
+    #[serde(default)]
+    pub monsters: Vec<MonsterTemplate>,
+}
+
+impl MonsterData {
+    /// Read `path`; a missing file means no monsters
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        config::load(path)
+    }
+
+    /// Parse TOML text
+    pub fn from_toml(text: &str) -> Result<Self> {
+        config::from_toml(text)
+    }
+
+    pub fn monster(&self, id: u32) -> Option<&MonsterTemplate> {
+        self.monsters.iter().find(|monster| monster.id == id)
+    }
+}
+
+impl Validate for MonsterData {
+    fn validate(&self) -> Result<()> {
+        let mut ids = HashSet::new();
+        for monster in &self.monsters {
+            if monster.id == 0 || !ids.insert(monster.id) {
+                return Err(anyhow!("monster id {} is 0 or used twice", monster.id));
+            }
+            if monster.max_hp == 0 {
+                return Err(anyhow!("monster {}: max_hp is 0", monster.id));
+            }
+            if monster.aggro_range < 0.0 || monster.leash_range <= monster.aggro_range {
+                return Err(anyhow!(
+                    "monster {}: leash_range must be above aggro_range",
+                    monster.id
+                ));
+            }
+            let mut above = 100;
+            for phase in &monster.phases {
+                if phase.hp_percent == 0 || phase.hp_percent >= above {
+                    return Err(anyhow!(
+                        "monster {}: phase hp_percent must fall from 99 to 1",
+                        monster.id
+                    ));
+                }
+                above = phase.hp_percent;
+            }
+            if monster.enrage_secs == Some(0) {
+                return Err(anyhow!("monster {}: enrage_secs is 0", monster.id));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// What a monster is doing
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AiState {
+    Idle,
+    Combat {
+        target: EntityId,
+    },
+    /// Going home after a fight, to idle there at full HP
+    Returning,
+    Dead,
+}
+
+/// One monster's AI
+#[derive(Debug, Clone, PartialEq)]
+pub struct Brain {
+    pub template: u32,
+    pub home: Position,
+    pub state: AiState,
+    pub phase: usize,
+    pub enraged: bool,
+    pub threat: ThreatTable,
+    /// Ticks since the current fight started
+    combat_ticks: u32,
+}
+
+impl Brain {
+    fn new(template: u32, home: Position) -> Self {
+        Self {
+            template,
+            home,
+            state: AiState::Idle,
+            phase: 0,
+            enraged: false,
+            threat: ThreatTable::new(),
+            combat_ticks: 0,
+        }
+    }
+
+    /// Start fighting `target`, if not fighting already
+    fn engage(&mut self, target: EntityId) {
+        self.threat.add(target, 0);
+        if self.state == AiState::Idle {
+            self.combat_ticks = 0;
+            self.state = AiState::Combat { target };
+        }
+    }
+
+    /// Whether it takes hits, heals and taunts into account
+    fn in_fight(&self) -> bool {
+        matches!(self.state, AiState::Idle | AiState::Combat { .. })
+    }
+}
+
+/// Behavior a monster's fight needs beyond the numbers in its template,
+/// such as summoning adds or changing skills
+///
+/// Runs on the zone's task, between ticks of the monster's AI.
+pub trait BossScript: Send {
+    /// `monster` entered `phase` (1 for the first in its template)
+    fn on_phase(&mut self, _zone: &mut Zone, _monster: EntityId, _phase: usize) {}
+
+    /// `monster`'s enrage timer ran out
+    fn on_enrage(&mut self, _zone: &mut Zone, _monster: EntityId) {}
+
+    /// `monster` gave up its fight and is going home
+    fn on_reset(&mut self, _zone: &mut Zone, _monster: EntityId) {}
+}
+
+/// Every monster in one zone
+pub struct Monsters {
+    data: MonsterData,
+    tick_interval: Duration,
+    threat: ThreatConfig,
+    brains: HashMap<EntityId, Brain>,
+    /// By template ID
+    scripts: HashMap<u32, Box<dyn BossScript>>,
+    /// Where boss announcements go
+    announce: Option<(SystemMessenger, ZoneId)>,
+}
+
+impl Monsters {
+    pub fn new(data: MonsterData, tick_interval: Duration) -> Self {
+        Self {
+            data,
+            tick_interval,
+            threat: ThreatConfig::default(),
+            brains: HashMap::new(),
+            scripts: HashMap::new(),
+            announce: None,
+        }
+    }
+
+    /// Weigh threat as `threat` says rather than by the defaults
+    pub fn with_threat(mut self, threat: ThreatConfig) -> Self {
+        self.threat = threat;
+        self
+    }
+
+    /// Announce bosses to everyone in `zone`
+    pub fn with_announcements(mut self, messenger: SystemMessenger, zone: ZoneId) -> Self {
+        self.announce = Some((messenger, zone));
+        self
+    }
+
+    /// Run `script` for every monster of template `template`
+    pub fn script(&mut self, template: u32, script: impl BossScript + 'static) {
+        self.scripts.insert(template, Box::new(script));
+    }
+
+    pub fn data(&self) -> &MonsterData {
+        &self.data
+    }
+
+    pub fn len(&self) -> usize {
+        self.brains.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.brains.is_empty()
+    }
+
+    pub fn brain(&self, id: EntityId) -> Option<&Brain> {
+        self.brains.get(&id)
+    }
+
+    /// The template of monster `id`
+    pub fn template(&self, id: EntityId) -> Option<&MonsterTemplate> {
+        self.brains
+            .get(&id)
+            .and_then(|brain| self.data.monster(brain.template))
+    }
+
+    /// Spawn a monster of template `template` at `position`
+    pub fn spawn(
+        &mut self,
+        zone: &mut Zone,
+        template: u32,
+        position: Position,
+    ) -> Result<EntityId> {
+        let monster = self
+            .data
+            .monster(template)
+            .ok_or_else(|| anyhow!("no monster {}", template))?;
+        let id = zone.spawn(EntityKind::Monster, position, monster.max_hp);
+        if monster.is_boss() {
+            self.announce(monster, "boss.spawned");
+        }
+        self.brains.insert(id, Brain::new(template, position));
+        Ok(id)
+    }
+
+    /// Remove monster `id` from the zone; a boss leaving alive is
+    /// announced
+    pub fn despawn(&mut self, zone: &mut Zone, id: EntityId) -> Option<Brain> {
+        let brain = self.brains.remove(&id)?;
+        zone.despawn(id);
+        if brain.state != AiState::Dead
+            && let Some(monster) = self.data.monster(brain.template)
+            && monster.is_boss()
+        {
+            self.announce(monster, "boss.despawned");
+        }
+        Some(brain)
+    }
+
+    /// Monster `id` took `amount` damage from `attacker`, who gains that
+    /// much threat; an idle monster fights back
+    pub fn on_damage(&mut self, id: EntityId, attacker: EntityId, amount: u32) {
+        if let Some(brain) = self.brains.get_mut(&id)
+            && brain.in_fight()
+        {
+            brain.engage(attacker);
+            brain.threat.add(attacker, amount as u64);
+        }
+    }
+
+    /// `healer` healed `healed` for `amount`; every monster fighting
+    /// `healed` gets angry at the healer too
+    pub fn on_heal(&mut self, healer: EntityId, healed: EntityId, amount: u32) {
+        let threat = self.threat.heal_threat(amount);
+        for brain in self.brains.values_mut() {
+            if matches!(brain.state, AiState::Combat { .. }) && brain.threat.contains(healed) {
+                brain.threat.add(healer, threat);
+            }
+        }
+    }
+
+    /// `taunter` taunted monster `id`, which attacks them for a while
+    pub fn taunt(&mut self, id: EntityId, taunter: EntityId) -> Result<()> {
+        let ticks = self.threat.taunt_ticks(self.tick_interval);
+        let brain = self
+            .brains
+            .get_mut(&id)
+            .filter(|brain| brain.in_fight())
+            .ok_or_else(|| anyhow!("monster {:?} can't be taunted", id))?;
+        brain.engage(taunter);
+        brain.threat.taunt(taunter, ticks);
+        Ok(())
+    }
+
+    /// What monster `id` hits for now, after its phase and enrage
+    pub fn damage(&self, id: EntityId) -> u32 {
+        let (Some(brain), Some(monster)) = (self.brains.get(&id), self.template(id)) else {
+            return 0;
+        };
+        let mut damage = monster.attack as u64;
+        if let Some(phase) = brain.phase.checked_sub(1).map(|i| monster.phases[i]) {
+            damage = damage * phase.damage_percent as u64 / 100;
+        }
+        if brain.enraged {
+            damage = damage * monster.enrage_damage_percent as u64 / 100;
+        }
+        damage.min(u32::MAX as u64) as u32
+    }
+
+    /// Advance every monster's AI one zone tick
+    pub fn tick(&mut self, zone: &mut Zone) {
+        // Monsters that left without being despawned are forgotten
+        self.brains.retain(|id, _| zone.get(*id).is_some());
+
+        let ids: Vec<_> = self.brains.keys().copied().collect();
+        for id in ids {
+            self.think(zone, id);
+        }
+    }
+
+    fn think(&mut self, zone: &mut Zone, id: EntityId) {
+        let (Some(brain), Some(entity)) = (self.brains.get_mut(&id), zone.get(id)) else {
+            return;
+        };
+        let Some(monster) = self.data.monster(brain.template) else {
+            return;
+        };
+
+        if entity.hp == 0 {
+            if brain.state != AiState::Dead {
+                brain.state = AiState::Dead;
+                if monster.is_boss() {
+                    let monster = monster.clone();
+                    self.announce(&monster, "boss.defeated");
+                }
+            }
+            return;
+        }
+
+        match brain.state {
+            AiState::Dead => {}
+            AiState::Idle => {
+                if monster.aggro_range > 0.0
+                    && let Some(target) = nearest_player(zone, entity.position, monster.aggro_range)
+                {
+                    brain.engage(target);
+                }
+            }
+            AiState::Returning => {
+                if let Some(entity) = zone.get_mut(id) {
+                    *entity.position = brain.home;
+                    *entity.hp = *entity.max_hp;
+                }
+                brain.state = AiState::Idle;
+            }
+            AiState::Combat { target } => {
+                // Only the living can be fought
+                brain
+                    .threat
+                    .retain(|enemy| zone.get(enemy).is_some_and(|enemy| enemy.hp > 0));
+                let leashed = entity.position.distance_squared(&brain.home)
+                    > monster.leash_range * monster.leash_range;
+                let next = brain
+                    .threat
+                    .next_target(Some(target), self.threat.switch_percent);
+                let Some(target) = next.filter(|_| !leashed) else {
+                    brain.state = AiState::Returning;
+                    brain.phase = 0;
+                    brain.enraged = false;
+                    brain.threat.clear();
+                    debug!("Monster {:?} resets", id);
+                    if let Some(script) = self.scripts.get_mut(&brain.template) {
+                        script.on_reset(zone, id);
+                    }
+                    return;
+                };
+                brain.state = AiState::Combat { target };
+
+                brain.combat_ticks = brain.combat_ticks.saturating_add(1);
+                let phase = monster.phase_at(entity.hp);
+                let enrage = !brain.enraged
+                    && monster.enrage_secs.is_some_and(|secs| {
+                        self.tick_interval * brain.combat_ticks >= Duration::from_secs(secs as u64)
+                    });
+                let entered = (brain.phase + 1..=phase).collect::<Vec<_>>();
+                brain.phase = brain.phase.max(phase);
+                brain.enraged |= enrage;
+
+                let template = brain.template;
+                if let Some(script) = self.scripts.get_mut(&template) {
+                    for phase in entered {
+                        script.on_phase(zone, id, phase);
+                    }
+                    if enrage {
+                        script.on_enrage(zone, id);
+                    }
+                }
+                if enrage && monster.is_boss() {
+                    let monster = monster.clone();
+                    self.announce(&monster, "boss.enraged");
+                }
+            }
+        }
+    }
+
+    fn announce(&self, monster: &MonsterTemplate, key: &str) {
+        if let Some((messenger, zone)) = &self.announce {
+            messenger.send_with(Target::Zone(*zone), key, &[("name", &monster.name)]);
+        }
+    }
+}
+
+/// The closest living player within `range` of `around`
+fn nearest_player(zone: &Zone, around: Position, range: f32) -> Option<EntityId> {
+    zone.entities()
+        .iter()
+        .filter(|entity| entity.kind == EntityKind::Player && entity.hp > 0)
+        .map(|entity| (entity.position.distance_squared(&around), entity.id))
+        .filter(|(distance, _)| *distance <= range * range)
+        .min_by(|a, b| a.0.total_cmp(&b.0))
+        .map(|(_, id)| id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ro2_common::localization::Localization;
+    use std::sync::{Arc, Mutex};
+    use tokio::sync::mpsc;
+
+    const TICK: Duration = Duration::from_millis(100);
+
+    fn data() -> MonsterData {
+        MonsterData::from_toml(
+            r#"
+            [[monsters]]
+            id = 1001
+            name = "Poring"
+            max_hp = 50
+            attack = 5
+
+            [[monsters]]
+            id = 2001
+            name = "Baphomet"
+            rank = "boss"
+            max_hp = 1000
+            attack = 100
+            aggro_range = 50.0
+            leash_range = 500.0
+            enrage_secs = 1
+            enrage_damage_percent = 300
+
+            [[monsters.phases]]
+            hp_percent = 60
+            damage_percent = 150
+
+            [[monsters.phases]]
+            hp_percent = 20
+            damage_percent = 200
+            "#,
+        )
+        .unwrap()
+    }
+
+    #[derive(Default, Clone)]
+    struct Recorder(Arc<Mutex<Vec<String>>>);
+
+    impl BossScript for Recorder {
+        fn on_phase(&mut self, _zone: &mut Zone, _monster: EntityId, phase: usize) {
+            self.0.lock().unwrap().push(format!("phase {}", phase));
+        }
+
+        fn on_enrage(&mut self, _zone: &mut Zone, _monster: EntityId) {
+            self.0.lock().unwrap().push("enrage".to_string());
+        }
+
+        fn on_reset(&mut self, _zone: &mut Zone, _monster: EntityId) {
+            self.0.lock().unwrap().push("reset".to_string());
+        }
+    }
+
+    fn text(message: &[u8]) -> String {
+        ro2_common::wire::read_utf16(&message[2..]).unwrap().0
+    }
+
+    #[test]
+    fn test_aggro_and_fighting_back() {
+        let mut zone = Zone::new();
+        let mut monsters = Monsters::new(data(), TICK);
+        let poring = monsters
+            .spawn(&mut zone, 1001, Position::default())
+            .unwrap();
+        let boss = monsters
+            .spawn(&mut zone, 2001, Position::new(1000.0, 0.0, 0.0))
+            .unwrap();
+        assert!(
+            monsters
+                .spawn(&mut zone, 9999, Position::default())
+                .is_err()
+        );
+        let player = zone.spawn(EntityKind::Player, Position::new(10.0, 0.0, 0.0), 100);
+
+        // The Poring is passive; the boss is too far away to notice
+        monsters.tick(&mut zone);
+        assert_eq!(monsters.brain(poring).unwrap().state, AiState::Idle);
+        assert_eq!(monsters.brain(boss).unwrap().state, AiState::Idle);
+
+        monsters.on_damage(poring, player, 1);
+        zone.get_mut(player).unwrap().position.x = 990.0;
+        monsters.tick(&mut zone);
+        let target = AiState::Combat { target: player };
+        assert_eq!(monsters.brain(poring).unwrap().state, target);
+        assert_eq!(monsters.brain(boss).unwrap().state, target);
+
+        // Once the player dies both go home and heal up
+        *zone.get_mut(boss).unwrap().hp = 10;
+        *zone.get_mut(player).unwrap().hp = 0;
+        monsters.tick(&mut zone);
+        assert_eq!(monsters.brain(boss).unwrap().state, AiState::Returning);
+        monsters.tick(&mut zone);
+        assert_eq!(monsters.brain(boss).unwrap().state, AiState::Idle);
+        assert_eq!(zone.get(boss).unwrap().hp, 1000);
+    }
+
+    #[test]
+    fn test_party_threat() {
+        let mut zone = Zone::new();
+        let mut monsters = Monsters::new(data(), TICK);
+        let poring = monsters
+            .spawn(&mut zone, 1001, Position::default())
+            .unwrap();
+        let [tank, healer, dps] = [0.0, 5.0, 10.0]
+            .map(|x| zone.spawn(EntityKind::Player, Position::new(x, 0.0, 0.0), 100));
+        let target = |monsters: &Monsters| monsters.brain(poring).unwrap().state;
+
+        monsters.on_damage(poring, tank, 100);
+        monsters.tick(&mut zone);
+        assert_eq!(target(&monsters), AiState::Combat { target: tank });
+
+        // Not enough to pull it off the tank, but healing the tank is
+        monsters.on_damage(poring, dps, 105);
+        monsters.tick(&mut zone);
+        assert_eq!(target(&monsters), AiState::Combat { target: tank });
+        monsters.on_heal(healer, tank, 400);
+        monsters.tick(&mut zone);
+        assert_eq!(target(&monsters), AiState::Combat { target: healer });
+
+        monsters.taunt(poring, tank).unwrap();
+        monsters.tick(&mut zone);
+        assert_eq!(target(&monsters), AiState::Combat { target: tank });
+        assert_eq!(monsters.brain(poring).unwrap().threat.threat(tank), 200);
+
+        // The taunting tank dies, and the healer is next
+        *zone.get_mut(tank).unwrap().hp = 0;
+        monsters.tick(&mut zone);
+        assert_eq!(target(&monsters), AiState::Combat { target: healer });
+
+        // With nobody left it resets and ignores hits on the way home
+        *zone.get_mut(healer).unwrap().hp = 0;
+        zone.despawn(dps);
+        monsters.tick(&mut zone);
+        assert_eq!(target(&monsters), AiState::Returning);
+        monsters.on_damage(poring, tank, 10);
+        assert!(monsters.taunt(poring, tank).is_err());
+        assert!(monsters.brain(poring).unwrap().threat.is_empty());
+    }
+
+    #[test]
+    fn test_phases_enrage_and_leash() {
+        let mut zone = Zone::new();
+        let mut monsters = Monsters::new(data(), TICK);
+        let recorder = Recorder::default();
+        monsters.script(2001, recorder.clone());
+        let boss = monsters
+            .spawn(&mut zone, 2001, Position::default())
+            .unwrap();
+        let player = zone.spawn(EntityKind::Player, Position::default(), 100);
+        monsters.on_damage(boss, player, 1);
+        monsters.tick(&mut zone);
+        assert_eq!(monsters.damage(boss), 100);
+
+        // Dropping straight past both thresholds enters both phases
+        *zone.get_mut(boss).unwrap().hp = 150;
+        monsters.tick(&mut zone);
+        assert_eq!(monsters.brain(boss).unwrap().phase, 2);
+        assert_eq!(monsters.damage(boss), 200);
+        // Healing doesn't leave a phase
+        *zone.get_mut(boss).unwrap().hp = 1000;
+        monsters.tick(&mut zone);
+        assert_eq!(monsters.brain(boss).unwrap().phase, 2);
+
+        for _ in 0..7 {
+            monsters.tick(&mut zone);
+        }
+        assert!(monsters.brain(boss).unwrap().enraged);
+        assert_eq!(monsters.damage(boss), 600);
+
+        // Pulled too far from home, it resets
+        *zone.get_mut(boss).unwrap().position = Position::new(600.0, 0.0, 0.0);
+        monsters.tick(&mut zone);
+        let brain = monsters.brain(boss).unwrap();
+        assert_eq!(brain.state, AiState::Returning);
+        assert_eq!((brain.phase, brain.enraged), (0, false));
+        monsters.tick(&mut zone);
+        assert_eq!(zone.get(boss).unwrap().position, Position::default());
+
+        assert_eq!(
+            *recorder.0.lock().unwrap(),
+            ["phase 1", "phase 2", "enrage", "reset"]
+        );
+    }
+
+    #[test]
+    fn test_boss_announcements() {
+        let mut localization = Localization::builtin();
+        localization
+            .add_table(
+                "en",
+                "[boss]\nspawned = \"{name} appeared\"\nenraged = \"{name} enraged\"\n\
+                 defeated = \"{name} defeated\"\ndespawned = \"{name} left\"",
+            )
+            .unwrap();
+        let messenger = SystemMessenger::new(Arc::new(localization));
+        let (here, mut here_rx) = mpsc::channel(8);
+        let (elsewhere, mut elsewhere_rx) = mpsc::channel(8);
+        messenger.register(1, Some(ZoneId(1)), None, here);
+        messenger.register(2, Some(ZoneId(2)), None, elsewhere);
+
+        let mut zone = Zone::new();
+        let mut monsters = Monsters::new(data(), TICK).with_announcements(messenger, ZoneId(1));
+        monsters
+            .spawn(&mut zone, 1001, Position::default())
+            .unwrap();
+        let boss = monsters
+            .spawn(&mut zone, 2001, Position::default())
+            .unwrap();
+        assert_eq!(text(&here_rx.try_recv().unwrap()), "Baphomet appeared");
+
+        let player = zone.spawn(EntityKind::Player, Position::default(), 100);
+        monsters.on_damage(boss, player, 1);
+        for _ in 0..10 {
+            monsters.tick(&mut zone);
+        }
+        assert_eq!(text(&here_rx.try_recv().unwrap()), "Baphomet enraged");
+
+        *zone.get_mut(boss).unwrap().hp = 0;
+        monsters.tick(&mut zone);
+        monsters.tick(&mut zone);
+        assert_eq!(text(&here_rx.try_recv().unwrap()), "Baphomet defeated");
+        // Despawning the body isn't announced again
+        monsters.despawn(&mut zone, boss).unwrap();
+        assert!(here_rx.try_recv().is_err());
+
+        let boss = monsters
+            .spawn(&mut zone, 2001, Position::default())
+            .unwrap();
+        monsters.despawn(&mut zone, boss).unwrap();
+        assert_eq!(text(&here_rx.try_recv().unwrap()), "Baphomet appeared");
+        assert_eq!(text(&here_rx.try_recv().unwrap()), "Baphomet left");
+        assert!(elsewhere_rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_load_data() {
+        assert_eq!(
+            MonsterData::load("does/not/exist.toml").unwrap(),
+            MonsterData::default()
+        );
+        let data = data();
+        let boss = data.monster(2001).unwrap();
+        assert!(boss.is_boss());
+        assert_eq!(data.monster(1001).unwrap().rank, Rank::Normal);
+        assert_eq!(
+            [1000, 600, 599, 200, 0].map(|hp| boss.phase_at(hp)),
+            [0, 1, 1, 2, 2]
+        );
+
+        let monster = "[[monsters]]\nid = 1\nname = \"x\"\nmax_hp = 10\n";
+        for bad in [
+            format!("{monster}{monster}"),
+            monster.replace("max_hp = 10", "max_hp = 0"),
+            format!("{monster}aggro_range = 5000.0\n"),
+            format!("{monster}enrage_secs = 0\n"),
+            format!("{monster}[[monsters.phases]]\nhp_percent = 100\n"),
+            format!(
+                "{monster}[[monsters.phases]]\nhp_percent = 20\n\
+                 [[monsters.phases]]\nhp_percent = 50\n"
+            ),
+        ] {
+            assert!(MonsterData::from_toml(&bad).is_err(), "{}", bad);
+        }
+    }
+}
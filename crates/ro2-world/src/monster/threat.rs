@@ -0,0 +1,238 @@
+//! Threat
+//!
+//! A monster in a fight keeps a [`ThreatTable`] of how much each enemy
+//! has angered it. Damage to the monster is threat for whoever dealt it;
+//! healing someone on the table is a share of threat for the healer. The
+//! monster attacks whoever has the most, but only leaves its current
+//! target for someone who beats it by a margin, so a tank holding a
+//! monster doesn't lose it to one lucky hit. A taunt puts the taunter at
+//! the top of the table and holds the monster on them for a while.
+//!
+//! Set in the `[threat]` section of `config/world.toml`:
+//!
+//! ```toml
+//! [threat]
+//! heal_percent = 50       # Threat per point healed, in percent
+//! switch_percent = 110    # Threat to pull a monster off its target, in
+//!                         # percent of the target's
+//! taunt_ms = 3000         # How long a taunt holds the monster
+//! ```
+
+use crate::world::EntityId;
+use anyhow::{Result, anyhow};
+use ro2_common::config::{self, Validate};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::Duration;
+
+/// Threat settings
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct ThreatConfig {
+    pub heal_percent: u32,
+    pub switch_percent: u32,
+    pub taunt_ms: u32,
+}
+
+impl Default for ThreatConfig {
+    fn default() -> Self {
+        Self {
+            heal_percent: 50,
+            switch_percent: 110,
+            taunt_ms: 3000,
+        }
+    }
+}
+
+impl ThreatConfig {
+    /// Read the `[threat]` section of `path`; missing keys keep their
+    /// defaults
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        config::section(path, "threat")
+    }
+
+    /// Parse the `[threat]` section of TOML text
+    pub fn from_toml(text: &str) -> Result<Self> {
+        config::section_from_toml(text, "threat")
+    }
+
+    /// Threat from healing `amount`
+    pub fn heal_threat(&self, amount: u32) -> u64 {
+        amount as u64 * self.heal_percent as u64 / 100
+    }
+
+    /// Zone ticks a taunt holds for in zones ticking every `tick_interval`
+    pub fn taunt_ticks(&self, tick_interval: Duration) -> u32 {
+        (Duration::from_millis(self.taunt_ms as u64).as_secs_f64() / tick_interval.as_secs_f64())
+            .round() as u32
+    }
+}
+
+impl Validate for ThreatConfig {
+    fn validate(&self) -> Result<()> {
+        if self.switch_percent < 100 {
+            return Err(anyhow!("threat switch_percent must be at least 100"));
+        }
+        Ok(())
+    }
+}
+
+/// How angry one monster is at each of its enemies
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ThreatTable {
+    threat: HashMap<EntityId, u64>,
+    /// Who taunted the monster, and for how many more ticks
+    taunt: Option<(EntityId, u32)>,
+}
+
+impl ThreatTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add `amount` to `entity`'s threat, putting it on the table
+    pub fn add(&mut self, entity: EntityId, amount: u64) {
+        let threat = self.threat.entry(entity).or_default();
+        *threat = threat.saturating_add(amount);
+    }
+
+    pub fn threat(&self, entity: EntityId) -> u64 {
+        self.threat.get(&entity).copied().unwrap_or(0)
+    }
+
+    pub fn contains(&self, entity: EntityId) -> bool {
+        self.threat.contains_key(&entity)
+    }
+
+    pub fn len(&self) -> usize {
+        self.threat.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.threat.is_empty()
+    }
+
+    /// Enemies by threat, highest first
+    pub fn ranked(&self) -> Vec<(EntityId, u64)> {
+        let mut ranked: Vec<_> = self
+            .threat
+            .iter()
+            .map(|(&id, &threat)| (id, threat))
+            .collect();
+        ranked.sort_by_key(|&(id, threat)| (std::cmp::Reverse(threat), id));
+        ranked
+    }
+
+    /// `taunter` goes to the top of the table and is attacked for the
+    /// next `ticks` ticks, whatever anyone else does
+    pub fn taunt(&mut self, taunter: EntityId, ticks: u32) {
+        let top = self.threat.values().copied().max().unwrap_or(0);
+        let threat = self.threat.entry(taunter).or_default();
+        *threat = (*threat).max(top);
+        self.taunt = Some((taunter, ticks));
+    }
+
+    /// Drop the enemies `keep` says no to
+    pub fn retain(&mut self, mut keep: impl FnMut(EntityId) -> bool) {
+        self.threat.retain(|&id, _| keep(id));
+        if self
+            .taunt
+            .is_some_and(|(taunter, _)| !self.threat.contains_key(&taunter))
+        {
+            self.taunt = None;
+        }
+    }
+
+    /// Forget everything, as the monster resets
+    pub fn clear(&mut self) {
+        self.threat.clear();
+        self.taunt = None;
+    }
+
+    /// Advance one tick and pick who to attack, given the `current`
+    /// target and the [`ThreatConfig::switch_percent`] to pull it off them
+    pub fn next_target(
+        &mut self,
+        current: Option<EntityId>,
+        switch_percent: u32,
+    ) -> Option<EntityId> {
+        if let Some((taunter, ticks)) = &mut self.taunt {
+            if *ticks > 0 {
+                *ticks -= 1;
+                return Some(*taunter);
+            }
+            self.taunt = None;
+        }
+
+        let (top, top_threat) = self.ranked().into_iter().next()?;
+        match current.filter(|current| self.contains(*current)) {
+            Some(current)
+                if top_threat as u128 * 100
+                    <= self.threat(current) as u128 * switch_percent as u128 =>
+            {
+                Some(current)
+            }
+            _ => Some(top),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_switching_needs_a_margin() {
+        let (tank, dps) = (EntityId(1), EntityId(2));
+        let mut table = ThreatTable::new();
+        table.add(tank, 100);
+        assert_eq!(table.next_target(None, 110), Some(tank));
+
+        table.add(dps, 105);
+        assert_eq!(table.next_target(Some(tank), 110), Some(tank));
+        table.add(dps, 10);
+        assert_eq!(table.next_target(Some(tank), 110), Some(dps));
+        assert_eq!(table.ranked(), [(dps, 115), (tank, 100)]);
+
+        // A target that's gone hands over to the top of the table
+        table.retain(|id| id != dps);
+        assert_eq!(table.next_target(Some(dps), 110), Some(tank));
+        table.clear();
+        assert_eq!(table.next_target(Some(tank), 110), None);
+    }
+
+    #[test]
+    fn test_taunt() {
+        let (tank, dps) = (EntityId(1), EntityId(2));
+        let mut table = ThreatTable::new();
+        table.add(tank, 10);
+        table.add(dps, 500);
+
+        table.taunt(tank, 2);
+        assert_eq!(table.threat(tank), 500);
+        table.add(dps, 1000);
+        assert_eq!(table.next_target(Some(dps), 110), Some(tank));
+        assert_eq!(table.next_target(Some(tank), 110), Some(tank));
+        // Once it runs out threat decides again
+        assert_eq!(table.next_target(Some(tank), 110), Some(dps));
+
+        // A taunter that leaves the table stops holding the monster
+        table.taunt(tank, 10);
+        table.retain(|id| id != tank);
+        assert_eq!(table.next_target(None, 110), Some(dps));
+    }
+
+    #[test]
+    fn test_config() {
+        let config = ThreatConfig::from_toml("[threat]\nheal_percent = 25").unwrap();
+        assert_eq!(config.heal_threat(100), 25);
+        assert_eq!(config.switch_percent, 110);
+        assert_eq!(config.taunt_ticks(Duration::from_millis(100)), 30);
+        assert!(ThreatConfig::from_toml("[threat]\nswitch_percent = 90").is_err());
+        assert_eq!(
+            ThreatConfig::load("does/not/exist.toml").unwrap(),
+            ThreatConfig::default()
+        );
+    }
+}
@@ -0,0 +1,525 @@
+//! Mounts
+//!
+//! A player mounts by using a mount item they carry or a mount skill they
+//! have learned. Riding sets [`STATE_MOUNTED`] on their entity, so clients
+//! in view learn of it through the regular world delta; which mount it is
+//! goes out separately as [`NFY_MOUNT`] for them to draw. While mounted a
+//! player moves faster and can only use the few skills allowed on a mount.
+//!
+//! Mounts are data, read from `config/mounts.toml`:
+//!
+//! ```toml
+//! usable_while_mounted = [1, 2]   # Skill IDs; every other skill is refused
+//!
+//! [[mounts]]
+//! id = 1
+//! name = "Brown Horse"
+//! item_id = 12001      # Mount by using this item...
+//! skill_id = 2001      # ...or this skill; at least one of the two
+//! speed_percent = 160
+//! ```
+//!
+//! The opcodes below are placeholders like those in `MessageType`.
+
+use crate::cooldown::{Action, CooldownKey, Cooldowns};
+use crate::inventory::Inventory;
+use crate::world::{Broadcaster, EntityId, EntityKind, STATE_MOUNTED, STATE_SITTING, Zone};
+use anyhow::{Result, anyhow};
+use ro2_common::config::{self, Validate};
+use ro2_common::wire::{WireReader, WireWriter};
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+pub use ro2_common::protocol::opcodes::{NFY_MOUNT, REQ_DISMOUNT, REQ_MOUNT};
+
+/// Where the world server reads mounts from
+pub const MOUNTS_PATH: &str = "config/mounts.toml";
+
+fn full_speed() -> u32 {
+    100
+}
+
+/// A kind of mount
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Mount {
+    pub id: u32,
+    pub name: String,
+    #[serde(default)]
+    pub item_id: Option<i32>,
+    #[serde(default)]
+    pub skill_id: Option<i32>,
+    /// Movement speed while riding, in percent of walking speed
+    #[serde(default = "full_speed")]
+    pub speed_percent: u32,
+}
+
+/// Every mount and the skills usable while riding
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct MountData {
+    #[serde(default)]
+    pub mounts: Vec<Mount>,
+    #[serde(default)]
+    pub usable_while_mounted: Vec<i32>,
+}
+
+impl MountData {
+    /// Read `path`; a missing file means no mounts
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        config::load(path)
+    }
+
+    /// Parse TOML text
+    pub fn from_toml(text: &str) -> Result<Self> {
+        config::from_toml(text)
+    }
+
+    pub fn mount(&self, id: u32) -> Option<&Mount> {
+        self.mounts.iter().find(|mount| mount.id == id)
+    }
+}
+
+impl Validate for MountData {
+    fn validate(&self) -> Result<()> {
+        let mut ids = HashSet::new();
+        let mut items = HashSet::new();
+        let mut skills = HashSet::new();
+        for mount in &self.mounts {
+            if mount.id == 0 || !ids.insert(mount.id) {
+                return Err(anyhow!("mount id {} is 0 or used twice", mount.id));
+            }
+            if mount.item_id.is_none() && mount.skill_id.is_none() {
+                return Err(anyhow!("mount {}: needs an item_id or skill_id", mount.id));
+            }
+            if mount.item_id.is_some_and(|item| !items.insert(item))
+                || mount.skill_id.is_some_and(|skill| !skills.insert(skill))
+            {
+                return Err(anyhow!(
+                    "mount {}: item or skill already summons another mount",
+                    mount.id
+                ));
+            }
+            if mount.speed_percent == 0 {
+                return Err(anyhow!("mount {}: speed_percent is 0", mount.id));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// How a player asks to mount
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MountWith {
+    Item(i32),
+    Skill(i32),
+}
+
+/// Who is riding what in one zone
+pub struct Riders {
+    data: MountData,
+    riding: HashMap<EntityId, u32>,
+}
+
+impl Riders {
+    pub fn new(data: MountData) -> Self {
+        Self {
+            data,
+            riding: HashMap::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.riding.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.riding.is_empty()
+    }
+
+    /// The mount `entity` is riding
+    pub fn riding(&self, entity: EntityId) -> Option<&Mount> {
+        self.riding.get(&entity).and_then(|&id| self.data.mount(id))
+    }
+
+    /// Mount `entity` using an item from `inventory` or one of `skills`
+    /// (`(skill ID, level)` as stored); returns the mount
+    pub fn mount(
+        &mut self,
+        zone: &mut Zone,
+        entity: EntityId,
+        with: MountWith,
+        inventory: &Inventory,
+        skills: &[(i32, i32)],
+    ) -> Result<&Mount> {
+        let mount = match with {
+            MountWith::Item(item_id) => {
+                if inventory.count(item_id) == 0 {
+                    return Err(anyhow!("item {} not carried", item_id));
+                }
+                self.data
+                    .mounts
+                    .iter()
+                    .find(|mount| mount.item_id == Some(item_id))
+            }
+            MountWith::Skill(skill_id) => {
+                if !skills.iter().any(|&(skill, _)| skill == skill_id) {
+                    return Err(anyhow!("skill {} not learned", skill_id));
+                }
+                self.data
+                    .mounts
+                    .iter()
+                    .find(|mount| mount.skill_id == Some(skill_id))
+            }
+        }
+        .ok_or_else(|| anyhow!("{:?} doesn't summon a mount", with))?;
+
+        if self.riding.contains_key(&entity) {
+            return Err(anyhow!("already mounted"));
+        }
+        let rider = zone
+            .get_mut(entity)
+            .ok_or_else(|| anyhow!("no entity {:?}", entity))?;
+        if rider.kind != EntityKind::Player || *rider.hp == 0 {
+            return Err(anyhow!("{:?} can't ride", entity));
+        }
+        // Mounting stands a sitting player up
+        *rider.state = (*rider.state & !STATE_SITTING) | STATE_MOUNTED;
+
+        self.riding.insert(entity, mount.id);
+        Ok(mount)
+    }
+
+    /// Get `entity` off its mount
+    pub fn dismount(&mut self, zone: &mut Zone, entity: EntityId) -> Result<()> {
+        self.riding
+            .remove(&entity)
+            .ok_or_else(|| anyhow!("not mounted"))?;
+        if let Some(rider) = zone.get_mut(entity) {
+            *rider.state &= !STATE_MOUNTED;
+        }
+        Ok(())
+    }
+
+    /// Handle [`REQ_MOUNT`] from the player controlling `entity` and tell
+    /// everyone in view which mount they're on
+    ///
+    /// Mounting has a cooldown, started once the player is on the mount.
+    #[allow(clippy::too_many_arguments)]
+    pub fn handle_req_mount(
+        &mut self,
+        zone: &mut Zone,
+        broadcaster: &Broadcaster,
+        cooldowns: &mut Cooldowns,
+        entity: EntityId,
+        message: &[u8],
+        inventory: &Inventory,
+        skills: &[(i32, i32)],
+    ) -> Result<()> {
+        let with = parse_req_mount(message)?;
+        let key = CooldownKey::Action(Action::Mount);
+        cooldowns.enforce(broadcaster, entity, key)?;
+        let mount = self.mount(zone, entity, with, inventory, skills)?;
+        cooldowns.start_use(entity, key, cooldowns.config().action(Action::Mount));
+        let notify = build_nfy_mount(entity, Some(mount));
+        if let Some(rider) = zone.get(entity) {
+            broadcaster.send_near(zone, rider.position, &notify);
+        }
+        Ok(())
+    }
+
+    /// Handle [`REQ_DISMOUNT`] from the player controlling `entity`
+    pub fn handle_req_dismount(
+        &mut self,
+        zone: &mut Zone,
+        broadcaster: &Broadcaster,
+        entity: EntityId,
+    ) -> Result<()> {
+        self.dismount(zone, entity)?;
+        if let Some(rider) = zone.get(entity) {
+            broadcaster.send_near(zone, rider.position, &build_nfy_mount(entity, None));
+        }
+        Ok(())
+    }
+
+    /// Forget `entity` without touching the zone, when it leaves
+    pub fn remove(&mut self, entity: EntityId) {
+        self.riding.remove(&entity);
+    }
+
+    /// `entity`'s movement speed given its walking speed
+    pub fn speed(&self, entity: EntityId, walking: f32) -> f32 {
+        match self.riding(entity) {
+            Some(mount) => walking * mount.speed_percent as f32 / 100.0,
+            None => walking,
+        }
+    }
+
+    /// Refuse skills that can't be used on a mount
+    pub fn check_skill(&self, entity: EntityId, skill_id: i32) -> Result<()> {
+        if self.riding.contains_key(&entity) && !self.data.usable_while_mounted.contains(&skill_id)
+        {
+            return Err(anyhow!("skill {} can't be used while mounted", skill_id));
+        }
+        Ok(())
+    }
+}
+
+/// Parse a mount request: u8 1 = item, 2 = skill; u32 item or skill ID
+pub fn parse_req_mount(message: &[u8]) -> Result<MountWith> {
+    let mut message = WireReader::message(message, REQ_MOUNT, "mount request")?;
+    let source = message.u8("mount source")?;
+    let id = message.i32("item or skill ID")?;
+    match source {
+        1 => Ok(MountWith::Item(id)),
+        2 => Ok(MountWith::Skill(id)),
+        other => Err(anyhow!("unknown mount source {}", other)),
+    }
+}
+
+/// Build the notification of `entity` mounting `mount` (0 = dismounted),
+/// with its speed in percent
+pub fn build_nfy_mount(entity: EntityId, mount: Option<&Mount>) -> Vec<u8> {
+    let mut out = WireWriter::message(NFY_MOUNT);
+    out.u32(entity.0)
+        .u32(mount.map_or(0, |mount| mount.id))
+        .u16(mount.map_or(100, |mount| mount.speed_percent) as u16);
+    out.into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cooldown::{CooldownConfig, NFY_COOLDOWN, Refusal};
+    use crate::inventory::ItemStack;
+    use crate::world::Position;
+    use std::time::Duration;
+
+    fn data() -> MountData {
+        MountData::from_toml(
+            r#"
+            usable_while_mounted = [1]
+
+            [[mounts]]
+            id = 1
+            name = "Brown Horse"
+            item_id = 12001
+            speed_percent = 160
+
+            [[mounts]]
+            id = 2
+            name = "Spirit Wolf"
+            skill_id = 2001
+            speed_percent = 200
+            "#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_mount_and_dismount() {
+        let mut zone = Zone::new();
+        let player = zone.spawn(EntityKind::Player, Position::default(), 100);
+        let monster = zone.spawn(EntityKind::Monster, Position::default(), 100);
+        let mut riders = Riders::new(data());
+        let mut inventory = Inventory::new(4);
+        let skills = [(2001, 1)];
+
+        // Need the item in the bag
+        let horse = MountWith::Item(12001);
+        assert!(
+            riders
+                .mount(&mut zone, player, horse, &inventory, &skills)
+                .is_err()
+        );
+        inventory.add(ItemStack::new(12001, 1)).unwrap();
+        assert!(
+            riders
+                .mount(&mut zone, player, MountWith::Item(501), &inventory, &skills)
+                .is_err()
+        );
+        assert!(
+            riders
+                .mount(&mut zone, monster, horse, &inventory, &skills)
+                .is_err()
+        );
+
+        let mount = riders
+            .mount(&mut zone, player, horse, &inventory, &skills)
+            .unwrap();
+        assert_eq!(mount.name, "Brown Horse");
+        assert_eq!(
+            zone.get(player).unwrap().state & STATE_MOUNTED,
+            STATE_MOUNTED
+        );
+        assert_eq!(riders.speed(player, 100.0), 160.0);
+        assert!(
+            riders
+                .mount(
+                    &mut zone,
+                    player,
+                    MountWith::Skill(2001),
+                    &inventory,
+                    &skills
+                )
+                .is_err()
+        );
+
+        riders.dismount(&mut zone, player).unwrap();
+        assert_eq!(zone.get(player).unwrap().state, 0);
+        assert_eq!(riders.speed(player, 100.0), 100.0);
+        assert!(riders.dismount(&mut zone, player).is_err());
+
+        // Skills work too, if learned
+        riders
+            .mount(
+                &mut zone,
+                player,
+                MountWith::Skill(2001),
+                &inventory,
+                &skills,
+            )
+            .unwrap();
+        assert_eq!(riders.riding(player).unwrap().id, 2);
+        assert!(
+            riders
+                .mount(&mut zone, player, MountWith::Skill(2001), &inventory, &[])
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_skills_while_mounted() {
+        let mut zone = Zone::new();
+        let player = zone.spawn(EntityKind::Player, Position::default(), 100);
+        let mut riders = Riders::new(data());
+        let skills = [(2001, 1)];
+
+        assert!(riders.check_skill(player, 50).is_ok());
+        riders
+            .mount(
+                &mut zone,
+                player,
+                MountWith::Skill(2001),
+                &Inventory::new(1),
+                &skills,
+            )
+            .unwrap();
+        assert!(riders.check_skill(player, 1).is_ok());
+        assert!(riders.check_skill(player, 50).is_err());
+
+        riders.remove(player);
+        assert!(riders.check_skill(player, 50).is_ok());
+    }
+
+    #[test]
+    fn test_handlers_notify_players_in_view() {
+        let mut zone = Zone::new();
+        let player = zone.spawn(EntityKind::Player, Position::default(), 100);
+        let near = zone.spawn(EntityKind::Player, Position::new(10.0, 0.0, 0.0), 100);
+        let far = zone.spawn(EntityKind::Player, Position::new(5000.0, 0.0, 0.0), 100);
+        let mut broadcaster = Broadcaster::default();
+        let mut inboxes = Vec::new();
+        for (session, entity) in [(1, player), (2, near), (3, far)] {
+            let (tx, rx) = tokio::sync::mpsc::channel(4);
+            broadcaster.subscribe(session, entity, tx);
+            inboxes.push(rx);
+        }
+
+        let mut riders = Riders::new(data());
+        let mut cooldowns = Cooldowns::new(CooldownConfig::default(), Duration::from_millis(100));
+        let request = [0x20, 0x3F, 2, 0xD1, 0x07, 0, 0];
+        riders
+            .handle_req_mount(
+                &mut zone,
+                &broadcaster,
+                &mut cooldowns,
+                player,
+                &request,
+                &Inventory::new(1),
+                &[(2001, 1)],
+            )
+            .unwrap();
+        let expected = build_nfy_mount(player, data().mount(2));
+        assert_eq!(inboxes[0].try_recv().unwrap(), expected);
+        assert_eq!(inboxes[1].try_recv().unwrap(), expected);
+        assert!(inboxes[2].try_recv().is_err());
+
+        riders
+            .handle_req_dismount(&mut zone, &broadcaster, player)
+            .unwrap();
+        assert_eq!(
+            inboxes[1].try_recv().unwrap(),
+            build_nfy_mount(player, None)
+        );
+        assert!(
+            riders
+                .handle_req_dismount(&mut zone, &broadcaster, player)
+                .is_err()
+        );
+
+        // Mounting again right away is refused
+        inboxes[0].try_recv().unwrap();
+        let refused = riders.handle_req_mount(
+            &mut zone,
+            &broadcaster,
+            &mut cooldowns,
+            player,
+            &request,
+            &Inventory::new(1),
+            &[(2001, 1)],
+        );
+        assert!(refused.unwrap_err().downcast_ref::<Refusal>().is_some());
+        assert_eq!(
+            &inboxes[0].try_recv().unwrap()[..2],
+            &NFY_COOLDOWN.to_le_bytes()
+        );
+        assert!(zone.get(player).unwrap().state & STATE_MOUNTED == 0);
+    }
+
+    #[test]
+    fn test_load_data() {
+        assert_eq!(
+            MountData::load("does/not/exist.toml").unwrap(),
+            MountData::default()
+        );
+        assert_eq!(data().mount(2).unwrap().item_id, None);
+
+        let mount = "[[mounts]]\nid = 1\nname = \"x\"\nitem_id = 1\n";
+        for bad in [
+            format!("{mount}{mount}"),
+            mount.replace("item_id = 1", ""),
+            format!("{mount}{}", mount.replace("id = 1\nname", "id = 2\nname")),
+            format!("{mount}speed_percent = 0\n"),
+        ] {
+            assert!(MountData::from_toml(&bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_messages() {
+        assert_eq!(
+            parse_req_mount(&[0x20, 0x3F, 1, 0xE1, 0x2E, 0, 0]).unwrap(),
+            MountWith::Item(12001)
+        );
+        assert_eq!(
+            parse_req_mount(&[0x20, 0x3F, 2, 1, 0, 0, 0]).unwrap(),
+            MountWith::Skill(1)
+        );
+        assert!(parse_req_mount(&[0x20, 0x3F, 3, 1, 0, 0, 0]).is_err());
+        let message = [0x20, 0x3F, 2, 1, 0, 0, 0];
+        for cut in 0..message.len() {
+            assert!(parse_req_mount(&message[..cut]).is_err());
+        }
+        assert!(parse_req_mount(&[0x21, 0x3F, 1, 1, 0, 0, 0]).is_err());
+
+        let data = data();
+        assert_eq!(
+            build_nfy_mount(EntityId(7), data.mount(1)),
+            [0x22, 0x3F, 7, 0, 0, 0, 1, 0, 0, 0, 160, 0]
+        );
+        assert_eq!(
+            build_nfy_mount(EntityId(7), None),
+            [0x22, 0x3F, 7, 0, 0, 0, 0, 0, 0, 0, 100, 0]
+        );
+    }
+}
@@ -0,0 +1,958 @@
+//! The party finder: queueing for dungeons
+//!
+//! A player queues for a dungeon ([`REQ_DUNGEON_QUEUE`]) with the roles
+//! they'll play: tank, healer or damage, any mix of them. Each dungeon
+//! says how many of each role a party needs. Whenever the queue changes
+//! the finder forms what parties it can, oldest in the queue first: a
+//! player who'd play any role can be moved to another one to make room
+//! for a later player who only plays that one, but never left out for
+//! them. Everyone still waiting is told their place in the queue
+//! ([`NFY_DUNGEON_QUEUE`]) as it changes.
+//!
+//! A party is taken into an instance of its own: a new zone for the
+//! dungeon's map, run in the finder's own [`World`] under a zone ID from
+//! `first_instance_zone` up, so instances never clash with the maps' zones.
+//! Members are moved there from whichever zone they're in, put at the
+//! dungeon's entrance and sent [`NFY_DUNGEON_READY`] with the party's
+//! roles. An instance is closed once nobody has been in it for a tick,
+//! but not before it's `instance_grace_secs` old, so members have time to
+//! load in ([`PartyFinder::close_empty`]).
+//!
+//! There's no party to speak of beyond the instance yet: the members are
+//! only together in the finder's [`Launched`] and in the dungeon.
+//!
+//! Dungeons are set in the `[party_finder]` section of
+//! `config/world.toml`; each one's map has to be hosted (see
+//! [`maps`](crate::maps)):
+//!
+//! ```toml
+//! [party_finder]
+//! first_instance_zone = 10000   # Above every hosted map's ID
+//! max_instances = 50
+//! instance_grace_secs = 60
+//!
+//! [[party_finder.dungeons]]
+//! id = 1
+//! name = "Poring Cave"
+//! map_id = 20
+//! entrance = [16.0, 0.0, 16.0]
+//! tanks = 1
+//! healers = 1
+//! damage = 3
+//! ```
+//!
+//! The opcodes are placeholders, in the `0x3Exx` range as the `0x3Fxx`
+//! one is used up.
+
+use crate::gm::Presence;
+use crate::maps::MapData;
+use crate::world::{Position, World, Zone, ZoneId};
+use anyhow::{Context, Result, anyhow};
+use ro2_common::config::{self, Validate};
+use ro2_common::protocol::{ClientError, ErrorCode};
+use ro2_common::wire::{WireReader, WireWriter};
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::path::Path;
+use tokio::sync::mpsc;
+use tracing::{info, warn};
+
+pub use ro2_common::protocol::opcodes::{
+    NFY_DUNGEON_QUEUE, NFY_DUNGEON_READY, REQ_DUNGEON_QUEUE, REQ_LEAVE_DUNGEON_QUEUE,
+};
+
+/// Most players in one party
+pub const MAX_PARTY_SIZE: usize = 8;
+
+/// What a party member does, sent as a u8
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(u8)]
+pub enum Role {
+    Tank = 0,
+    Healer = 1,
+    Damage = 2,
+}
+
+impl Role {
+    fn bit(self) -> u8 {
+        1 << self as u8
+    }
+}
+
+/// The roles a player will play, sent as a bit per [`Role`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Roles(u8);
+
+impl Roles {
+    pub const ALL: Roles = Roles(0b111);
+
+    /// `None` without a role or with bits that aren't roles
+    pub fn from_bits(bits: u8) -> Option<Self> {
+        (bits != 0 && bits & !Self::ALL.0 == 0).then_some(Self(bits))
+    }
+
+    pub fn bits(self) -> u8 {
+        self.0
+    }
+
+    pub fn contains(self, role: Role) -> bool {
+        self.0 & role.bit() != 0
+    }
+}
+
+/// A dungeon players can queue for
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Dungeon {
+    pub id: u32,
+    pub name: String,
+    pub map_id: u32,
+    /// Where the party starts
+    pub entrance: Position,
+    #[serde(default)]
+    pub tanks: usize,
+    #[serde(default)]
+    pub healers: usize,
+    #[serde(default)]
+    pub damage: usize,
+}
+
+impl Dungeon {
+    /// The roles a party needs, one per member
+    pub fn slots(&self) -> Vec<Role> {
+        let mut slots = vec![Role::Tank; self.tanks];
+        slots.extend(std::iter::repeat_n(Role::Healer, self.healers));
+        slots.extend(std::iter::repeat_n(Role::Damage, self.damage));
+        slots
+    }
+
+    pub fn size(&self) -> usize {
+        self.tanks + self.healers + self.damage
+    }
+
+    /// Whether a party needs any of `roles`
+    pub fn needs_any(&self, roles: Roles) -> bool {
+        self.slots().into_iter().any(|role| roles.contains(role))
+    }
+}
+
+/// Dungeons and instance limits
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct PartyFinderConfig {
+    /// Instances' zone IDs count up from here
+    pub first_instance_zone: u32,
+    /// Instances running at once; parties wait for one to close
+    pub max_instances: u32,
+    /// How old an instance has to be before it's closed for being empty
+    pub instance_grace_secs: i64,
+    pub dungeons: Vec<Dungeon>,
+}
+
+impl Default for PartyFinderConfig {
+    fn default() -> Self {
+        Self {
+            first_instance_zone: 10_000,
+            max_instances: 50,
+            instance_grace_secs: 60,
+            dungeons: Vec::new(),
+        }
+    }
+}
+
+impl PartyFinderConfig {
+    /// Read the `[party_finder]` section of `path`; missing keys keep their
+    /// defaults
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        config::section(path, "party_finder")
+    }
+
+    /// Parse the `[party_finder]` section of TOML text
+    pub fn from_toml(text: &str) -> Result<Self> {
+        config::section_from_toml(text, "party_finder")
+    }
+
+    /// Check every dungeon's entrance is on a hosted map and no hosted map
+    /// has a zone ID instances use
+    pub fn check_maps(&self, maps: &MapData) -> Result<()> {
+        for dungeon in &self.dungeons {
+            maps.check_position(dungeon.map_id, dungeon.entrance)
+                .with_context(|| format!("dungeon {} ({})", dungeon.id, dungeon.name))?;
+        }
+        if let Some(map) = maps
+            .maps
+            .iter()
+            .find(|map| self.is_instance(ZoneId(map.id)))
+        {
+            return Err(anyhow!(
+                "map {} is among the instances' zone IDs from {}",
+                map.id,
+                self.first_instance_zone
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn dungeon(&self, id: u32) -> Option<&Dungeon> {
+        self.dungeons.iter().find(|dungeon| dungeon.id == id)
+    }
+
+    /// Whether `zone` is one of the instances' IDs
+    pub fn is_instance(&self, zone: ZoneId) -> bool {
+        (self.first_instance_zone..self.first_instance_zone + self.max_instances).contains(&zone.0)
+    }
+}
+
+impl Validate for PartyFinderConfig {
+    fn validate(&self) -> Result<()> {
+        if self.first_instance_zone == 0
+            || self.max_instances == 0
+            || self
+                .first_instance_zone
+                .checked_add(self.max_instances)
+                .is_none()
+            || self.instance_grace_secs < 0
+        {
+            return Err(anyhow!(
+                "party finder first_instance_zone and max_instances must be positive and fit in a zone ID, and instance_grace_secs can't be negative"
+            ));
+        }
+        let mut ids = HashSet::new();
+        for dungeon in &self.dungeons {
+            if dungeon.id == 0 || !ids.insert(dungeon.id) {
+                return Err(anyhow!("dungeon id {} is 0 or used twice", dungeon.id));
+            }
+            if dungeon.size() == 0 || dungeon.size() > MAX_PARTY_SIZE {
+                return Err(anyhow!(
+                    "dungeon {}: a party must be 1 to {} players",
+                    dungeon.id,
+                    MAX_PARTY_SIZE
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A player joining the queue
+#[derive(Debug, Clone)]
+pub struct Queuer {
+    pub presence: Presence,
+    pub character_id: i64,
+    /// Their connection's outbox, for notices wherever they are
+    pub outbox: mpsc::Sender<Vec<u8>>,
+}
+
+struct Waiting {
+    queuer: Queuer,
+    roles: Roles,
+}
+
+/// Players matched for a dungeon, each with the role they'll play
+#[derive(Debug, Clone)]
+pub struct Party {
+    pub dungeon: u32,
+    pub members: Vec<(Queuer, Role)>,
+}
+
+/// A party taken into its instance
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Launched {
+    pub dungeon: u32,
+    pub zone: ZoneId,
+    /// Where each member that made it is now
+    pub members: Vec<Presence>,
+}
+
+/// A running dungeon instance
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Instance {
+    pub dungeon: u32,
+    pub started_at: i64,
+}
+
+/// A refusal with the text at `key` in the `[party_finder]` string table
+fn refuse(code: ErrorCode, key: &str) -> anyhow::Error {
+    ClientError::new(code, key).into()
+}
+
+/// Pick players for `slots` from those `waiting`, oldest first
+///
+/// Returns the picked players' indices into `waiting` with the role each
+/// plays, in queue order, or `None` if the queue can't fill every slot.
+/// Someone picked stays picked: a later player only takes their slot if
+/// they can move to another one.
+pub fn match_party(slots: &[Role], waiting: &[Roles]) -> Option<Vec<(usize, Role)>> {
+    fn place(
+        candidate: usize,
+        slots: &[Role],
+        waiting: &[Roles],
+        holders: &mut [Option<usize>],
+        tried: &mut [bool],
+    ) -> bool {
+        for (slot, &role) in slots.iter().enumerate() {
+            if tried[slot] || !waiting[candidate].contains(role) {
+                continue;
+            }
+            tried[slot] = true;
+            let free = match holders[slot] {
+                None => true,
+                Some(holder) => place(holder, slots, waiting, holders, tried),
+            };
+            if free {
+                holders[slot] = Some(candidate);
+                return true;
+            }
+        }
+        false
+    }
+
+    let mut holders = vec![None; slots.len()];
+    let mut placed = 0;
+    for candidate in 0..waiting.len() {
+        if placed == slots.len() {
+            break;
+        }
+        let mut tried = vec![false; slots.len()];
+        if place(candidate, slots, waiting, &mut holders, &mut tried) {
+            placed += 1;
+        }
+    }
+    if placed < slots.len() {
+        return None;
+    }
+    let mut party: Vec<(usize, Role)> = holders
+        .into_iter()
+        .zip(slots)
+        .map(|(holder, &role)| (holder.expect("every slot is filled"), role))
+        .collect();
+    party.sort_by_key(|&(index, _)| index);
+    Some(party)
+}
+
+/// The dungeon queues and the instances parties were taken into
+pub struct PartyFinder {
+    config: PartyFinderConfig,
+    /// Where the instances run
+    world: World,
+    queues: HashMap<u32, VecDeque<Waiting>>,
+    instances: HashMap<ZoneId, Instance>,
+}
+
+impl PartyFinder {
+    /// Run instances in `world`, which should have no zones of its own
+    pub fn new(config: PartyFinderConfig, world: World) -> Self {
+        Self {
+            config,
+            world,
+            queues: HashMap::new(),
+            instances: HashMap::new(),
+        }
+    }
+
+    pub fn config(&self) -> &PartyFinderConfig {
+        &self.config
+    }
+
+    /// The world the instances run in
+    pub fn world(&self) -> &World {
+        &self.world
+    }
+
+    /// Players waiting for `dungeon`
+    pub fn waiting(&self, dungeon: u32) -> usize {
+        self.queues.get(&dungeon).map_or(0, VecDeque::len)
+    }
+
+    /// The dungeon session `session_id` is queued for
+    pub fn queued_for(&self, session_id: u64) -> Option<u32> {
+        self.queues.iter().find_map(|(&dungeon, queue)| {
+            queue
+                .iter()
+                .any(|waiting| waiting.queuer.presence.session_id == session_id)
+                .then_some(dungeon)
+        })
+    }
+
+    pub fn instance(&self, zone: ZoneId) -> Option<&Instance> {
+        self.instances.get(&zone)
+    }
+
+    /// Instances running
+    pub fn instances(&self) -> usize {
+        self.instances.len()
+    }
+
+    /// Queue `queuer` for `dungeon` in `roles` and tell everyone waiting
+    /// for it their place
+    ///
+    /// Refusals are [`ClientError`]s.
+    pub fn join(&mut self, queuer: Queuer, dungeon: u32, roles: Roles) -> Result<()> {
+        let Some(found) = self.config.dungeon(dungeon) else {
+            return Err(refuse(ErrorCode::NotFound, "party_finder.no_dungeon"));
+        };
+        if !found.needs_any(roles) {
+            return Err(refuse(ErrorCode::InvalidRequest, "party_finder.no_roles"));
+        }
+        if self.queued_for(queuer.presence.session_id).is_some() {
+            return Err(refuse(ErrorCode::NotAllowed, "party_finder.already_queued"));
+        }
+        if self.instances.contains_key(&queuer.presence.zone) {
+            return Err(refuse(ErrorCode::NotAllowed, "party_finder.in_dungeon"));
+        }
+
+        info!(
+            "Character {} queued for dungeon {} as {:03b}",
+            queuer.character_id,
+            dungeon,
+            roles.bits()
+        );
+        self.queues
+            .entry(dungeon)
+            .or_default()
+            .push_back(Waiting { queuer, roles });
+        self.send_places(dungeon);
+        Ok(())
+    }
+
+    /// Take session `session_id` out of the queue, telling them and those
+    /// behind them; whether they were in it
+    pub fn leave(&mut self, session_id: u64) -> bool {
+        let Some(dungeon) = self.queued_for(session_id) else {
+            return false;
+        };
+        let queue = self.queues.get_mut(&dungeon).expect("queued for it");
+        let index = queue
+            .iter()
+            .position(|waiting| waiting.queuer.presence.session_id == session_id)
+            .expect("queued for it");
+        let left = queue.remove(index).expect("in the queue");
+        let waiting = queue.len();
+        let _ = left
+            .queuer
+            .outbox
+            .try_send(build_nfy_dungeon_queue(dungeon, 0, waiting));
+        self.send_places(dungeon);
+        true
+    }
+
+    /// Record where a queued player is now, e.g. after changing zones
+    pub fn moved(&mut self, presence: Presence) {
+        for waiting in self.queues.values_mut().flat_map(|queue| queue.iter_mut()) {
+            if waiting.queuer.presence.session_id == presence.session_id {
+                waiting.queuer.presence = presence;
+            }
+        }
+    }
+
+    /// Tell everyone waiting for `dungeon` their place
+    fn send_places(&self, dungeon: u32) {
+        let Some(queue) = self.queues.get(&dungeon) else {
+            return;
+        };
+        for (index, waiting) in queue.iter().enumerate() {
+            // A full outbox misses this one; the next change updates it
+            let _ = waiting.queuer.outbox.try_send(build_nfy_dungeon_queue(
+                dungeon,
+                index + 1,
+                queue.len(),
+            ));
+        }
+    }
+
+    /// Take the next party for `dungeon` out of its queue, if the queue
+    /// can fill one and another instance can start
+    pub fn next_party(&mut self, dungeon: u32) -> Option<Party> {
+        if self.instances.len() >= self.config.max_instances as usize {
+            return None;
+        }
+        let slots = self.config.dungeon(dungeon)?.slots();
+        let queue = self.queues.get_mut(&dungeon)?;
+        let roles: Vec<Roles> = queue.iter().map(|waiting| waiting.roles).collect();
+        let picked = match_party(&slots, &roles)?;
+
+        // Back to front so the indices still hold
+        let mut members: Vec<(Queuer, Role)> = picked
+            .into_iter()
+            .rev()
+            .map(|(index, role)| (queue.remove(index).expect("picked").queuer, role))
+            .collect();
+        members.reverse();
+        if queue.is_empty() {
+            self.queues.remove(&dungeon);
+        }
+        self.send_places(dungeon);
+        Some(Party { dungeon, members })
+    }
+
+    /// Start an instance of `party`'s dungeon and take its members from
+    /// their zones in `world` to its entrance, telling each of them
+    ///
+    /// Members who have left in the meantime are left out; an instance no
+    /// one made it to is closed again.
+    pub async fn launch(&mut self, world: &World, party: Party, now: i64) -> Result<Launched> {
+        let dungeon = self
+            .config
+            .dungeon(party.dungeon)
+            .cloned()
+            .ok_or_else(|| anyhow!("no dungeon {}", party.dungeon))?;
+        let zone = (0..self.config.max_instances)
+            .map(|offset| ZoneId(self.config.first_instance_zone + offset))
+            .find(|zone| !self.instances.contains_key(zone))
+            .ok_or_else(|| anyhow!("every instance zone is in use"))?;
+        let instance = self.world.start_zone(zone, Zone::new());
+
+        let mut arrived = Vec::new();
+        for (queuer, role) in &party.members {
+            let presence = queuer.presence;
+            let Some(from) = world.zone(presence.zone) else {
+                warn!(
+                    "Character {} is in zone {}, which isn't running",
+                    queuer.character_id, presence.zone.0
+                );
+                continue;
+            };
+            let entity = match from.transfer(presence.entity, &instance).await {
+                Ok(Some(entity)) => entity,
+                Ok(None) => continue,
+                Err(e) => {
+                    warn!(
+                        "Taking character {} to dungeon {} failed: {}",
+                        queuer.character_id, dungeon.id, e
+                    );
+                    continue;
+                }
+            };
+            instance.move_entity(entity, dungeon.entrance, 0).await?;
+            from.unsubscribe(presence.session_id).await?;
+            instance
+                .subscribe(presence.session_id, entity, queuer.outbox.clone())
+                .await?;
+            let presence = Presence {
+                session_id: presence.session_id,
+                zone,
+                entity,
+            };
+            arrived.push((queuer, *role, presence));
+        }
+        if arrived.is_empty() {
+            self.world.stop_zone(zone);
+            return Err(anyhow!(
+                "nobody in the party for dungeon {} is still around",
+                dungeon.id
+            ));
+        }
+
+        let roster: Vec<_> = arrived
+            .iter()
+            .map(|(_, role, presence)| (presence.entity, *role))
+            .collect();
+        let ready = build_nfy_dungeon_ready(&dungeon, zone, &roster);
+        for (queuer, _, _) in &arrived {
+            let _ = queuer.outbox.try_send(ready.clone());
+        }
+        self.instances.insert(
+            zone,
+            Instance {
+                dungeon: dungeon.id,
+                started_at: now,
+            },
+        );
+        info!(
+            "Took a party of {} into dungeon {} ({}), zone {}",
+            arrived.len(),
+            dungeon.id,
+            dungeon.name,
+            zone.0
+        );
+        Ok(Launched {
+            dungeon: dungeon.id,
+            zone,
+            members: arrived
+                .into_iter()
+                .map(|(_, _, presence)| presence)
+                .collect(),
+        })
+    }
+
+    /// Form every party the queue for `dungeon` can and take each into an
+    /// instance
+    pub async fn fill(&mut self, world: &World, dungeon: u32, now: i64) -> Result<Vec<Launched>> {
+        let mut launched = Vec::new();
+        while let Some(party) = self.next_party(dungeon) {
+            launched.push(self.launch(world, party, now).await?);
+        }
+        Ok(launched)
+    }
+
+    /// Close the instances nobody is in that are past their grace period;
+    /// the zones closed
+    pub fn close_empty(&mut self, now: i64) -> Vec<ZoneId> {
+        let empty: Vec<ZoneId> = self
+            .instances
+            .iter()
+            .filter(|(zone, instance)| {
+                now - instance.started_at >= self.config.instance_grace_secs
+                    && self.world.zone(**zone).is_none_or(|handle| {
+                        let stats = handle.tick_stats();
+                        stats.ticks > 0 && stats.clients == 0
+                    })
+            })
+            .map(|(zone, _)| *zone)
+            .collect();
+        for zone in &empty {
+            let instance = self.instances.remove(zone).expect("found above");
+            self.world.stop_zone(*zone);
+            info!(
+                "Closed empty dungeon {} instance, zone {}",
+                instance.dungeon, zone.0
+            );
+        }
+        empty
+    }
+
+    /// Answer [`REQ_DUNGEON_QUEUE`] from `queuer`, then take any parties
+    /// that formed into their instances
+    pub async fn handle_req_dungeon_queue(
+        &mut self,
+        world: &World,
+        queuer: Queuer,
+        message: &[u8],
+        now: i64,
+    ) -> Result<Vec<Launched>> {
+        let (dungeon, roles) = parse_req_dungeon_queue(message)?;
+        self.join(queuer, dungeon, roles)?;
+        self.fill(world, dungeon, now).await
+    }
+
+    /// Answer [`REQ_LEAVE_DUNGEON_QUEUE`]; whether they were queued
+    pub fn handle_req_leave_dungeon_queue(
+        &mut self,
+        session_id: u64,
+        message: &[u8],
+    ) -> Result<bool> {
+        let message = WireReader::message(message, REQ_LEAVE_DUNGEON_QUEUE, "leave dungeon queue")?;
+        message.finish()?;
+        Ok(self.leave(session_id))
+    }
+}
+
+/// Parse [`REQ_DUNGEON_QUEUE`]: u32 dungeon ID and u8 [`Roles`]
+pub fn parse_req_dungeon_queue(message: &[u8]) -> Result<(u32, Roles)> {
+    let mut message = WireReader::message(message, REQ_DUNGEON_QUEUE, "dungeon queue request")?;
+    let dungeon = message.u32("dungeon")?;
+    let roles = message.u8("roles")?;
+    message.finish()?;
+    let roles = Roles::from_bits(roles).ok_or_else(|| anyhow!("bad roles {:#04x}", roles))?;
+    Ok((dungeon, roles))
+}
+
+/// Build [`NFY_DUNGEON_QUEUE`]: u32 dungeon ID, u16 place in the queue
+/// (1 for next, 0 once out of it) and u16 players waiting
+pub fn build_nfy_dungeon_queue(dungeon: u32, place: usize, waiting: usize) -> Vec<u8> {
+    let clamp = |n: usize| n.min(usize::from(u16::MAX)) as u16;
+    let mut out = WireWriter::message(NFY_DUNGEON_QUEUE);
+    out.u32(dungeon).u16(clamp(place)).u16(clamp(waiting));
+    out.into_bytes()
+}
+
+/// Build [`NFY_DUNGEON_READY`]: u32 dungeon ID, u32 map ID, u32 instance
+/// zone ID, the entrance as three f32s, u8 member count, then each
+/// member's u32 entity ID in the instance and u8 [`Role`]
+pub fn build_nfy_dungeon_ready(
+    dungeon: &Dungeon,
+    zone: ZoneId,
+    members: &[(crate::world::EntityId, Role)],
+) -> Vec<u8> {
+    let mut out = WireWriter::message(NFY_DUNGEON_READY);
+    out.u32(dungeon.id)
+        .u32(dungeon.map_id)
+        .u32(zone.0)
+        .f32(dungeon.entrance.x)
+        .f32(dungeon.entrance.y)
+        .f32(dungeon.entrance.z)
+        .u8(members.len() as u8);
+    for (entity, role) in members {
+        out.u32(entity.0).u8(*role as u8);
+    }
+    out.into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::{EntityId, EntityKind, ZoneHandle};
+    use ro2_common::localization::Localization;
+    use std::time::Duration;
+
+    const TANK: Roles = Roles(0b001);
+    const HEALER: Roles = Roles(0b010);
+    const DAMAGE: Roles = Roles(0b100);
+
+    fn config() -> PartyFinderConfig {
+        PartyFinderConfig::from_toml(
+            "[party_finder]
+             first_instance_zone = 100
+             max_instances = 1
+             instance_grace_secs = 10
+
+             [[party_finder.dungeons]]
+             id = 1
+             name = \"Poring Cave\"
+             map_id = 20
+             entrance = [16.0, 0.0, 16.0]
+             tanks = 1
+             healers = 1
+             damage = 1",
+        )
+        .unwrap()
+    }
+
+    fn queuer(
+        session_id: u64,
+        zone: ZoneId,
+        entity: EntityId,
+    ) -> (Queuer, mpsc::Receiver<Vec<u8>>) {
+        let (outbox, inbox) = mpsc::channel(64);
+        let queuer = Queuer {
+            presence: Presence {
+                session_id,
+                zone,
+                entity,
+            },
+            character_id: session_id as i64,
+            outbox,
+        };
+        (queuer, inbox)
+    }
+
+    /// The latest message with `opcode` sent so far
+    fn last(inbox: &mut mpsc::Receiver<Vec<u8>>, opcode: u16) -> Option<Vec<u8>> {
+        let mut found = None;
+        while let Ok(message) = inbox.try_recv() {
+            if message[..2] == opcode.to_le_bytes() {
+                found = Some(message);
+            }
+        }
+        found
+    }
+
+    #[test]
+    fn test_config() {
+        assert_eq!(
+            PartyFinderConfig::load("does/not/exist.toml").unwrap(),
+            PartyFinderConfig::default()
+        );
+        let config = config();
+        assert_eq!(
+            config.dungeon(1).unwrap().slots(),
+            [Role::Tank, Role::Healer, Role::Damage]
+        );
+        assert!(config.is_instance(ZoneId(100)));
+        assert!(!config.is_instance(ZoneId(101)));
+        assert!(PartyFinderConfig::from_toml("[party_finder]\nmax_instances = 0").is_err());
+        for dungeon in [
+            "id = 1\nname = \"A\"\nmap_id = 1\nentrance = [0.0, 0.0, 0.0]",
+            "id = 0\nname = \"A\"\nmap_id = 1\nentrance = [0.0, 0.0, 0.0]\ntanks = 1",
+            "id = 1\nname = \"A\"\nmap_id = 1\nentrance = [0.0, 0.0, 0.0]\ndamage = 9",
+        ] {
+            let text = format!("[[party_finder.dungeons]]\n{}", dungeon);
+            assert!(PartyFinderConfig::from_toml(&text).is_err(), "{}", dungeon);
+        }
+
+        let maps = MapData::from_toml(
+            "[[maps]]
+             id = 20
+             name = \"Poring Cave\"
+             area = { min = [0.0, 0.0, 0.0], max = [64.0, 10.0, 64.0] }
+             spawn = [8.0, 0.0, 8.0]
+
+             [[maps]]
+             id = 100
+             name = \"Clash\"
+             area = { min = [0.0, 0.0, 0.0], max = [64.0, 10.0, 64.0] }
+             spawn = [8.0, 0.0, 8.0]",
+        )
+        .unwrap();
+        assert!(config.check_maps(&maps).is_err());
+        let maps = MapData {
+            maps: maps.maps[..1].to_vec(),
+        };
+        config.check_maps(&maps).unwrap();
+        assert!(config.check_maps(&MapData::default()).is_err());
+    }
+
+    #[test]
+    fn test_match_party() {
+        let slots = [Role::Tank, Role::Healer, Role::Damage];
+        assert_eq!(match_party(&slots, &[TANK, HEALER]), None);
+        assert_eq!(
+            match_party(&slots, &[DAMAGE, HEALER, DAMAGE, TANK]),
+            Some(vec![(0, Role::Damage), (1, Role::Healer), (3, Role::Tank)])
+        );
+        // The first, who'd play anything, makes way for the tank-only
+        // second rather than leaving them out
+        assert_eq!(
+            match_party(&slots, &[Roles::ALL, TANK, Roles(0b011)]),
+            Some(vec![(0, Role::Damage), (1, Role::Tank), (2, Role::Healer)])
+        );
+        // ...but never gets left out for a later player
+        assert_eq!(
+            match_party(&[Role::Tank], &[Roles::ALL, TANK]),
+            Some(vec![(0, Role::Tank)])
+        );
+    }
+
+    #[test]
+    fn test_parse_req_dungeon_queue() {
+        assert_eq!(
+            parse_req_dungeon_queue(&[0x00, 0x3E, 1, 0, 0, 0, 0b101]).unwrap(),
+            (1, Roles(0b101))
+        );
+        for bad in [
+            &[0x00, 0x3E, 1, 0, 0, 0, 0][..],
+            &[0x00, 0x3E, 1, 0, 0, 0, 0b1000],
+            &[0x00, 0x3E, 1, 0, 0, 0],
+            &[0x01, 0x3E, 1, 0, 0, 0, 1],
+        ] {
+            assert!(parse_req_dungeon_queue(bad).is_err(), "{:?}", bad);
+        }
+    }
+
+    #[test]
+    fn test_queue_places() {
+        let mut finder = PartyFinder::new(config(), World::new(Duration::from_millis(5)));
+        let (alice, mut alice_rx) = queuer(1, ZoneId(1), EntityId(1));
+        let (bob, mut bob_rx) = queuer(2, ZoneId(1), EntityId(2));
+
+        let refused = |result: Result<()>| {
+            let refusal = result.unwrap_err().downcast::<ClientError>().unwrap();
+            assert_ne!(Localization::builtin().get("en", &refusal.key), refusal.key);
+            refusal
+        };
+        assert_eq!(
+            refused(finder.join(alice.clone(), 9, Roles::ALL)).code,
+            ErrorCode::NotFound
+        );
+        finder.join(alice.clone(), 1, TANK).unwrap();
+        assert_eq!(
+            refused(finder.join(alice.clone(), 1, HEALER)).code,
+            ErrorCode::NotAllowed
+        );
+        finder.join(bob, 1, TANK).unwrap();
+        assert_eq!(finder.waiting(1), 2);
+        assert_eq!(
+            last(&mut alice_rx, NFY_DUNGEON_QUEUE),
+            Some(build_nfy_dungeon_queue(1, 1, 2))
+        );
+        assert_eq!(
+            last(&mut bob_rx, NFY_DUNGEON_QUEUE),
+            Some(build_nfy_dungeon_queue(1, 2, 2))
+        );
+        assert!(finder.next_party(1).is_none());
+
+        // Bob moves up when Alice leaves
+        let leave = REQ_LEAVE_DUNGEON_QUEUE.to_le_bytes();
+        assert!(finder.handle_req_leave_dungeon_queue(1, &leave).unwrap());
+        assert!(!finder.handle_req_leave_dungeon_queue(1, &leave).unwrap());
+        assert_eq!(
+            last(&mut alice_rx, NFY_DUNGEON_QUEUE),
+            Some(build_nfy_dungeon_queue(1, 0, 1))
+        );
+        assert_eq!(
+            last(&mut bob_rx, NFY_DUNGEON_QUEUE),
+            Some(build_nfy_dungeon_queue(1, 1, 1))
+        );
+        assert_eq!(finder.queued_for(2), Some(1));
+    }
+
+    async fn player(zone: &ZoneHandle, x: f32) -> EntityId {
+        zone.spawn(EntityKind::Player, Position::new(x, 0.0, 0.0), 100)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_launch() {
+        let mut world = World::new(Duration::from_millis(5));
+        let town = world.start_zone(ZoneId(1), Zone::new());
+        let field = world.start_zone(ZoneId(2), Zone::new());
+        let mut finder = PartyFinder::new(config(), World::new(Duration::from_millis(5)));
+
+        let mut inboxes = Vec::new();
+        let mut join = async |session_id: u64, zone: &ZoneHandle, roles: Roles| {
+            let entity = player(zone, session_id as f32).await;
+            let (queuer, inbox) = queuer(session_id, zone.id(), entity);
+            zone.subscribe(session_id, entity, queuer.outbox.clone())
+                .await
+                .unwrap();
+            inboxes.push(inbox);
+            let mut request = REQ_DUNGEON_QUEUE.to_le_bytes().to_vec();
+            request.extend(1u32.to_le_bytes());
+            request.push(roles.bits());
+            finder
+                .handle_req_dungeon_queue(&world, queuer, &request, 1000)
+                .await
+                .unwrap()
+        };
+        let mut launched = Vec::new();
+        for (session_id, zone, roles) in [
+            (1, &town, Roles::ALL),
+            (2, &field, TANK),
+            (3, &town, TANK),
+            (4, &town, DAMAGE),
+            (5, &town, DAMAGE),
+            // The second party has to wait for the one instance
+            (6, &field, HEALER),
+        ] {
+            launched.extend(join(session_id, zone, roles).await);
+        }
+
+        // The first player makes way for the second as tank, and the
+        // third waits as there's only one
+        assert_eq!(launched.len(), 1);
+        let launched = &launched[0];
+        assert_eq!((launched.dungeon, launched.zone), (1, ZoneId(100)));
+        let sessions: Vec<u64> = launched.members.iter().map(|m| m.session_id).collect();
+        assert_eq!(sessions, [1, 2, 4]);
+        assert_eq!(finder.waiting(1), 3);
+        assert_eq!(finder.instances(), 1);
+
+        let instance = finder.world().zone(ZoneId(100)).unwrap().clone();
+        for member in &launched.members {
+            let entity = instance.entity(member.entity).await.unwrap().unwrap();
+            assert_eq!(entity.position, Position::new(16.0, 0.0, 16.0));
+        }
+        let roster: Vec<_> = launched
+            .members
+            .iter()
+            .zip([Role::Healer, Role::Tank, Role::Damage])
+            .map(|(member, role)| (member.entity, role))
+            .collect();
+        let ready = build_nfy_dungeon_ready(config().dungeon(1).unwrap(), ZoneId(100), &roster);
+        for index in [0, 1, 3] {
+            assert_eq!(
+                last(&mut inboxes[index], NFY_DUNGEON_READY),
+                Some(ready.clone())
+            );
+        }
+        assert_eq!(
+            last(&mut inboxes[2], NFY_DUNGEON_QUEUE),
+            Some(build_nfy_dungeon_queue(1, 1, 3))
+        );
+        assert_eq!(last(&mut inboxes[5], NFY_DUNGEON_READY), None);
+
+        // Kept through its grace period, then closed once everyone's gone
+        for member in &launched.members {
+            instance.unsubscribe(member.session_id).await.unwrap();
+        }
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        assert!(finder.close_empty(1005).is_empty());
+        assert_eq!(finder.close_empty(1010), [ZoneId(100)]);
+        assert!(finder.world().zone(ZoneId(100)).is_none());
+
+        // Which lets the next party in
+        let launched = finder.fill(&world, 1, 1020).await.unwrap();
+        assert_eq!(launched[0].members.len(), 3);
+        assert_eq!(finder.waiting(1), 0);
+    }
+}
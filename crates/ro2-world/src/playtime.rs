@@ -0,0 +1,339 @@
+//! Enforcing playtime limits on players already in the world
+//!
+//! The login server refuses accounts that can't play at all; this catches
+//! the ones whose time runs out while they're online. [`PlaytimeMonitor`]
+//! tracks every restricted session, is checked every
+//! [`CHECK_INTERVAL`], warns the player by system message as the end
+//! approaches and then logs them out.
+
+use crate::handlers::system::build_system_message;
+use anyhow::Result;
+use chrono::{NaiveDate, NaiveDateTime};
+use ro2_common::clock::Clock;
+use ro2_common::localization::Localization;
+use ro2_common::playtime::{PlaytimeLimits, PlaytimeStore, Restriction, Verdict, played_by_day};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
+use tracing::{info, warn};
+
+/// How often online sessions are re-checked
+pub const CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Warnings are sent when the time left drops below each of these
+pub const WARNINGS: [Duration; 3] = [
+    Duration::from_secs(15 * 60),
+    Duration::from_secs(5 * 60),
+    Duration::from_secs(60),
+];
+
+struct Session {
+    account_id: i64,
+    limits: PlaytimeLimits,
+    /// Played on `day` before `since`
+    played_before: Duration,
+    day: NaiveDate,
+    /// Start of the time not yet written to the store
+    since: NaiveDateTime,
+    /// Warnings already sent
+    warned: usize,
+    language: String,
+    outbox: mpsc::Sender<Vec<u8>>,
+    disconnect: Option<oneshot::Sender<String>>,
+}
+
+impl Session {
+    /// Write the time played since the last flush to the store
+    async fn flush(&mut self, store: &dyn PlaytimeStore, now: NaiveDateTime) -> Result<()> {
+        for (day, played) in played_by_day(self.since, now) {
+            store.add_played(self.account_id, day, played).await?;
+            if day == self.day {
+                self.played_before += played;
+            } else {
+                // Past midnight: a new day's allowance
+                self.day = day;
+                self.played_before = played;
+                self.warned = 0;
+            }
+        }
+        self.since = now;
+        Ok(())
+    }
+
+    fn send(&self, text: &str) {
+        // A full or closed outbox means the client is going away anyway
+        let _ = self.outbox.try_send(build_system_message(text));
+    }
+
+    /// Tell the player why and disconnect them
+    fn log_out(&mut self, localization: &Localization, restriction: Restriction) {
+        self.send(localization.get(&self.language, restriction.message_key()));
+        if let Some(disconnect) = self.disconnect.take() {
+            let _ = disconnect.send(format!("playtime: {:?}", restriction));
+        }
+    }
+}
+
+/// Playtime of every restricted session in the world
+pub struct PlaytimeMonitor {
+    store: Arc<dyn PlaytimeStore>,
+    localization: Arc<Localization>,
+    sessions: HashMap<u64, Session>,
+    clock: Clock,
+}
+
+impl PlaytimeMonitor {
+    pub fn new(store: Arc<dyn PlaytimeStore>, localization: Arc<Localization>) -> Self {
+        Self {
+            store,
+            localization,
+            sessions: HashMap::new(),
+            clock: Clock::system(),
+        }
+    }
+
+    /// Tell the time by `clock` in [`run`](Self::run)
+    pub fn with_clock(mut self, clock: Clock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Sessions being tracked
+    pub fn len(&self) -> usize {
+        self.sessions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sessions.is_empty()
+    }
+
+    /// Start tracking a session that entered the world
+    ///
+    /// Unrestricted accounts aren't tracked. Warnings go to `outbox` in the
+    /// account's `language`, and a reason is sent on `disconnect` to log
+    /// the player out.
+    pub async fn enter(
+        &mut self,
+        session_id: u64,
+        account_id: i64,
+        language: Option<&str>,
+        now: NaiveDateTime,
+        outbox: mpsc::Sender<Vec<u8>>,
+        disconnect: oneshot::Sender<String>,
+    ) -> Result<Verdict> {
+        let Some(limits) = self.store.limits(account_id).await? else {
+            return Ok(Verdict::Allowed { remaining: None });
+        };
+        let played_before = self.store.played(account_id, now.date()).await?;
+
+        let mut session = Session {
+            account_id,
+            limits,
+            played_before,
+            day: now.date(),
+            since: now,
+            warned: 0,
+            language: self.localization.resolve(language).to_string(),
+            outbox,
+            disconnect: Some(disconnect),
+        };
+        let verdict = limits.check(now, played_before);
+        match verdict {
+            Verdict::Denied(restriction) => session.log_out(&self.localization, restriction),
+            Verdict::Allowed { .. } => {
+                self.sessions.insert(session_id, session);
+            }
+        }
+        Ok(verdict)
+    }
+
+    /// Stop tracking a session, saving its playtime
+    pub async fn leave(&mut self, session_id: u64, now: NaiveDateTime) -> Result<()> {
+        if let Some(mut session) = self.sessions.remove(&session_id) {
+            session.flush(self.store.as_ref(), now).await?;
+        }
+        Ok(())
+    }
+
+    /// Save playtime, warn sessions that are running out and log out the
+    /// ones that have; returns how many were logged out
+    pub async fn check(&mut self, now: NaiveDateTime) -> Result<usize> {
+        let mut expired = Vec::new();
+        for (&session_id, session) in &mut self.sessions {
+            session.flush(self.store.as_ref(), now).await?;
+
+            match session.limits.check(now, session.played_before) {
+                Verdict::Denied(restriction) => {
+                    session.log_out(&self.localization, restriction);
+                    info!(
+                        "Logging out session {} (account {}): {:?}",
+                        session_id, session.account_id, restriction
+                    );
+                    expired.push(session_id);
+                }
+                Verdict::Allowed {
+                    remaining: Some(remaining),
+                } => {
+                    let due = WARNINGS.iter().filter(|&&at| remaining <= at).count();
+                    if due > session.warned {
+                        session.warned = due;
+                        let minutes = remaining.as_secs().div_ceil(60);
+                        let key = if minutes == 1 {
+                            "playtime.ends_in_minute"
+                        } else {
+                            "playtime.ends_in_minutes"
+                        };
+                        session.send(&self.localization.format(
+                            &session.language,
+                            key,
+                            &[("minutes", &minutes)],
+                        ));
+                    }
+                }
+                Verdict::Allowed { remaining: None } => {}
+            }
+        }
+
+        for session_id in &expired {
+            self.sessions.remove(session_id);
+        }
+        Ok(expired.len())
+    }
+
+    /// Check every [`CHECK_INTERVAL`] until the task is dropped
+    pub async fn run(mut self) {
+        let mut interval = tokio::time::interval(CHECK_INTERVAL);
+        loop {
+            interval.tick().await;
+            let now = self.clock.local();
+            if let Err(e) = self.check(now).await {
+                warn!("Playtime check failed: {}", e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ro2_common::playtime::{AllowedHours, MemoryPlaytimeStore};
+
+    fn at(hour: u32, minute: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2025, 3, 1)
+            .unwrap()
+            .and_hms_opt(hour, minute, 0)
+            .unwrap()
+    }
+
+    fn text(message: &[u8]) -> String {
+        ro2_common::wire::read_utf16(&message[2..]).unwrap().0
+    }
+
+    #[tokio::test]
+    async fn test_warn_then_log_out() {
+        let store = Arc::new(MemoryPlaytimeStore::new());
+        store.set_limits(
+            7,
+            PlaytimeLimits {
+                daily_cap: Some(Duration::from_secs(3600)),
+                allowed_hours: None,
+            },
+        );
+        store
+            .add_played(7, at(0, 0).date(), Duration::from_secs(30 * 60))
+            .await
+            .unwrap();
+        let mut monitor = PlaytimeMonitor::new(store.clone(), Arc::default());
+
+        let (outbox, mut messages) = mpsc::channel(8);
+        let (disconnect, mut kicked) = oneshot::channel();
+        let verdict = monitor
+            .enter(1, 7, None, at(12, 0), outbox, disconnect)
+            .await
+            .unwrap();
+        assert_eq!(
+            verdict,
+            Verdict::Allowed {
+                remaining: Some(Duration::from_secs(30 * 60))
+            }
+        );
+
+        assert_eq!(monitor.check(at(12, 10)).await.unwrap(), 0);
+        assert!(messages.try_recv().is_err());
+
+        // 14 minutes left: one warning, not repeated
+        monitor.check(at(12, 16)).await.unwrap();
+        assert_eq!(
+            text(&messages.try_recv().unwrap()),
+            "Your playtime ends in 14 minutes. You will be logged out."
+        );
+        monitor.check(at(12, 17)).await.unwrap();
+        assert!(messages.try_recv().is_err());
+
+        // Skipping past two thresholds sends only the latest
+        monitor.check(at(12, 29)).await.unwrap();
+        assert_eq!(
+            text(&messages.try_recv().unwrap()),
+            "Your playtime ends in 1 minute. You will be logged out."
+        );
+        assert!(messages.try_recv().is_err());
+
+        assert_eq!(monitor.check(at(12, 30)).await.unwrap(), 1);
+        assert_eq!(
+            text(&messages.try_recv().unwrap()),
+            "You have reached your playtime limit for today."
+        );
+        assert!(kicked.try_recv().is_ok());
+        assert!(monitor.is_empty());
+        assert_eq!(
+            store.played(7, at(0, 0).date()).await.unwrap(),
+            Duration::from_secs(3600)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unrestricted_and_denied() {
+        let store = Arc::new(MemoryPlaytimeStore::new());
+        store.set_limits(
+            7,
+            PlaytimeLimits {
+                daily_cap: None,
+                allowed_hours: Some(AllowedHours {
+                    start: at(18, 0).time(),
+                    end: at(21, 0).time(),
+                }),
+            },
+        );
+        let mut localization = Localization::builtin();
+        localization
+            .add_table(
+                "ko",
+                "[playtime]\noutside_allowed_hours = \"지금은 플레이할 수 없습니다.\"",
+            )
+            .unwrap();
+        let mut monitor = PlaytimeMonitor::new(store, Arc::new(localization));
+
+        let (outbox, _messages) = mpsc::channel(8);
+        let (disconnect, _kicked) = oneshot::channel();
+        monitor
+            .enter(1, 8, None, at(12, 0), outbox, disconnect)
+            .await
+            .unwrap();
+        assert!(monitor.is_empty());
+
+        let (outbox, mut messages) = mpsc::channel(8);
+        let (disconnect, mut kicked) = oneshot::channel();
+        let verdict = monitor
+            .enter(2, 7, Some("ko"), at(12, 0), outbox, disconnect)
+            .await
+            .unwrap();
+        assert_eq!(verdict, Verdict::Denied(Restriction::OutsideAllowedHours));
+        assert_eq!(
+            text(&messages.try_recv().unwrap()),
+            "지금은 플레이할 수 없습니다."
+        );
+        assert!(kicked.try_recv().is_ok());
+        assert!(monitor.is_empty());
+    }
+}
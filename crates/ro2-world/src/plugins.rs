@@ -0,0 +1,26 @@
+//! Plugins compiled into the world server
+//!
+//! A plugin lives in its own crate and implements
+//! [`ro2_common::plugin::Plugin`]. To build it in, add the crate to this
+//! one's `Cargo.toml` as an optional dependency behind a feature of the
+//! same name, and list it in [`compiled_in`]:
+//!
+//! ```ignore
+//! #[cfg(feature = "motd")]
+//! plugins.push(Arc::new(motd::Motd::default()));
+//! ```
+//!
+//! `cargo build --features motd` then builds the world server with it, and
+//! `disabled` in the `[plugins]` section of `config/world.toml` leaves it
+//! out without a rebuild.
+
+use ro2_common::plugin::Plugin;
+use std::sync::Arc;
+
+/// Every plugin this build has, in load order
+#[allow(clippy::vec_init_then_push)]
+pub fn compiled_in() -> Vec<Arc<dyn Plugin>> {
+    #[allow(unused_mut)]
+    let mut plugins: Vec<Arc<dyn Plugin>> = Vec::new();
+    plugins
+}
@@ -0,0 +1,326 @@
+//! Zone population statistics
+//!
+//! Every few seconds each zone's player and monster counts and the rate it
+//! sends messages to its clients are sampled from its [`TickStats`]. The
+//! latest sample is published on the event bus as a [`ZonePopulation`], for
+//! the dashboard; the last `history` samples of every zone are kept so
+//! operators can see how a channel fills up over the day, decide how many
+//! channels to run, and spot maps whose monsters never spawn or die out.
+//!
+//! The `population` console command summarizes the history, and exports it
+//! as CSV or JSON (see [`PopulationHistory::export`]). Set in the
+//! `[population]` section of `config/world.toml`:
+//!
+//! ```toml
+//! [population]
+//! interval_secs = 5   # Time between samples
+//! history = 720       # Samples kept per zone (an hour at 5 s)
+//! ```
+
+use crate::world::{TickStats, ZoneId};
+use anyhow::{Context, Result, anyhow};
+use ro2_common::config::{self, Validate};
+use ro2_common::events::ZonePopulation;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt::Write;
+use std::path::Path;
+use std::time::Duration;
+
+/// Sampling settings
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct PopulationConfig {
+    pub interval_secs: u64,
+    pub history: usize,
+}
+
+impl Default for PopulationConfig {
+    fn default() -> Self {
+        Self {
+            interval_secs: 5,
+            history: 720,
+        }
+    }
+}
+
+impl PopulationConfig {
+    /// Read the `[population]` section of `path`; missing keys keep their
+    /// defaults
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        config::section(path, "population")
+    }
+
+    /// Parse the `[population]` section of TOML text
+    pub fn from_toml(text: &str) -> Result<Self> {
+        config::section_from_toml(text, "population")
+    }
+
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(self.interval_secs)
+    }
+}
+
+impl Validate for PopulationConfig {
+    fn validate(&self) -> Result<()> {
+        if self.interval_secs == 0 || self.history == 0 {
+            return Err(anyhow!(
+                "population interval_secs and history must be above 0"
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// One zone at one moment
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Sample {
+    /// Unix time it was taken
+    pub unix: i64,
+    pub zone: u32,
+    pub players: usize,
+    pub monsters: usize,
+    pub entities: usize,
+    /// Since the zone's previous sample; 0 for its first
+    pub messages_per_sec: u32,
+    pub queue_depth: usize,
+}
+
+impl From<Sample> for ZonePopulation {
+    fn from(sample: Sample) -> Self {
+        Self {
+            zone: sample.zone,
+            players: sample.players,
+            monsters: sample.monsters,
+            messages_per_sec: sample.messages_per_sec,
+        }
+    }
+}
+
+/// What one zone's history adds up to
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct ZoneSummary {
+    pub zone: u32,
+    pub samples: usize,
+    pub peak_players: usize,
+    pub mean_players: f64,
+    pub min_monsters: usize,
+    pub max_monsters: usize,
+    pub peak_messages_per_sec: u32,
+}
+
+/// Recent samples of every zone
+#[derive(Debug)]
+pub struct PopulationHistory {
+    config: PopulationConfig,
+    /// Oldest first
+    zones: BTreeMap<ZoneId, VecDeque<Sample>>,
+    /// Each zone's message count when it was last sampled
+    messages: BTreeMap<ZoneId, u64>,
+}
+
+impl PopulationHistory {
+    pub fn new(config: PopulationConfig) -> Self {
+        Self {
+            config,
+            zones: BTreeMap::new(),
+            messages: BTreeMap::new(),
+        }
+    }
+
+    /// Sample every zone in `stats` at `unix`; returns the samples, in
+    /// zone order
+    pub fn record(&mut self, unix: i64, stats: &[(ZoneId, TickStats)]) -> Vec<Sample> {
+        let mut recorded: Vec<Sample> = stats
+            .iter()
+            .map(|&(zone, stats)| {
+                let samples = self.zones.entry(zone).or_default();
+                // A restarted zone counts its messages from 0 again
+                let sent = self
+                    .messages
+                    .insert(zone, stats.messages)
+                    .map_or(0, |last| match stats.messages.checked_sub(last) {
+                        Some(sent) => sent,
+                        None => stats.messages,
+                    });
+                let secs = samples.back().map_or(0, |last| unix - last.unix);
+                let sample = Sample {
+                    unix,
+                    zone: zone.0,
+                    players: stats.clients,
+                    monsters: stats.monsters,
+                    entities: stats.entities,
+                    messages_per_sec: match secs {
+                        1.. => (sent / secs as u64).min(u32::MAX as u64) as u32,
+                        _ => 0,
+                    },
+                    queue_depth: stats.queue_depth,
+                };
+                if samples.len() == self.config.history {
+                    samples.pop_front();
+                }
+                samples.push_back(sample);
+                sample
+            })
+            .collect();
+        recorded.sort_by_key(|sample| sample.zone);
+        recorded
+    }
+
+    /// Every sample, by zone and then oldest first
+    pub fn samples(&self) -> impl Iterator<Item = &Sample> {
+        self.zones.values().flatten()
+    }
+
+    pub fn summary(&self) -> Vec<ZoneSummary> {
+        self.zones
+            .iter()
+            .filter(|(_, samples)| !samples.is_empty())
+            .map(|(zone, samples)| ZoneSummary {
+                zone: zone.0,
+                samples: samples.len(),
+                peak_players: samples.iter().map(|s| s.players).max().unwrap_or(0),
+                mean_players: samples.iter().map(|s| s.players).sum::<usize>() as f64
+                    / samples.len() as f64,
+                min_monsters: samples.iter().map(|s| s.monsters).min().unwrap_or(0),
+                max_monsters: samples.iter().map(|s| s.monsters).max().unwrap_or(0),
+                peak_messages_per_sec: samples
+                    .iter()
+                    .map(|s| s.messages_per_sec)
+                    .max()
+                    .unwrap_or(0),
+            })
+            .collect()
+    }
+
+    /// Every sample as CSV, with a header line
+    pub fn to_csv(&self) -> String {
+        let mut csv =
+            String::from("unix,zone,players,monsters,entities,messages_per_sec,queue_depth\n");
+        for s in self.samples() {
+            // Writing to a String can't fail
+            let _ = writeln!(
+                csv,
+                "{},{},{},{},{},{},{}",
+                s.unix,
+                s.zone,
+                s.players,
+                s.monsters,
+                s.entities,
+                s.messages_per_sec,
+                s.queue_depth
+            );
+        }
+        csv
+    }
+
+    /// Every sample as a JSON array
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string(&self.samples().collect::<Vec<_>>())?)
+    }
+
+    /// Write every sample to `path`, as JSON if it ends in `.json` and CSV
+    /// otherwise; returns how many were written
+    pub fn export(&self, path: impl AsRef<Path>) -> Result<usize> {
+        let path = path.as_ref();
+        let text = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("json") => self.to_json()?,
+            _ => self.to_csv(),
+        };
+        std::fs::write(path, text).with_context(|| format!("writing {}", path.display()))?;
+        Ok(self.samples().count())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stats(clients: usize, monsters: usize, messages: u64) -> TickStats {
+        TickStats {
+            clients,
+            monsters,
+            entities: clients + monsters,
+            messages,
+            ..TickStats::default()
+        }
+    }
+
+    #[test]
+    fn test_record_and_summary() {
+        let mut history = PopulationHistory::new(PopulationConfig {
+            interval_secs: 5,
+            history: 2,
+        });
+        let first = history.record(
+            100,
+            &[(ZoneId(2), stats(0, 0, 0)), (ZoneId(1), stats(3, 10, 50))],
+        );
+        assert_eq!(first.iter().map(|s| s.zone).collect::<Vec<_>>(), [1, 2]);
+        assert_eq!(first[0].messages_per_sec, 0);
+
+        let second = history.record(105, &[(ZoneId(1), stats(5, 8, 150))]);
+        assert_eq!(second[0].messages_per_sec, 20);
+        assert_eq!(
+            ZonePopulation::from(second[0]),
+            ZonePopulation {
+                zone: 1,
+                players: 5,
+                monsters: 8,
+                messages_per_sec: 20,
+            }
+        );
+
+        // Only the last two samples are kept, and a restart isn't a burst
+        let third = history.record(110, &[(ZoneId(1), stats(1, 0, 5))]);
+        assert_eq!(third[0].messages_per_sec, 1);
+        assert_eq!(history.samples().count(), 3);
+
+        let summary = history.summary();
+        assert_eq!(summary.len(), 2);
+        assert_eq!(
+            summary[0],
+            ZoneSummary {
+                zone: 1,
+                samples: 2,
+                peak_players: 5,
+                mean_players: 3.0,
+                min_monsters: 0,
+                max_monsters: 8,
+                peak_messages_per_sec: 20,
+            }
+        );
+        assert_eq!(summary[1].max_monsters, 0);
+    }
+
+    #[test]
+    fn test_export() {
+        let mut history = PopulationHistory::new(PopulationConfig::default());
+        history.record(100, &[(ZoneId(1), stats(3, 10, 0))]);
+        assert_eq!(
+            history.to_csv(),
+            "unix,zone,players,monsters,entities,messages_per_sec,queue_depth\n100,1,3,10,13,0,0\n"
+        );
+
+        let path = std::env::temp_dir().join(format!("ro2-population-{}.json", std::process::id()));
+        let exported = history.export(&path);
+        let json = std::fs::read_to_string(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(exported.unwrap(), 1);
+        let json: serde_json::Value = serde_json::from_str(&json.unwrap()).unwrap();
+        assert_eq!(json[0]["monsters"], 10);
+        assert_eq!(json[0]["unix"], 100);
+    }
+
+    #[test]
+    fn test_config() {
+        assert_eq!(
+            PopulationConfig::load("does/not/exist.toml").unwrap(),
+            PopulationConfig::default()
+        );
+        let config = PopulationConfig::from_toml("[population]\ninterval_secs = 60").unwrap();
+        assert_eq!(config.interval(), Duration::from_secs(60));
+        assert_eq!(config.history, 720);
+        assert!(PopulationConfig::from_toml("[population]\nhistory = 0").is_err());
+    }
+}
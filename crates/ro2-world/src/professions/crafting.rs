@@ -0,0 +1,104 @@
+//! Recipe-based production (cooking, crafting)
+
+use super::{Profession, ProfessionSkills};
+use crate::inventory::{Inventory, ItemStack};
+use anyhow::{Result, anyhow};
+use serde::Deserialize;
+
+fn one_level() -> u32 {
+    1
+}
+
+/// Items that turn into another item
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Recipe {
+    pub id: u32,
+    pub name: String,
+    pub profession: Profession,
+    #[serde(default = "one_level")]
+    pub required_level: u32,
+    pub ingredients: Vec<ItemStack>,
+    pub output: ItemStack,
+    pub experience: u32,
+}
+
+/// The result of crafting
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Crafted {
+    pub output: ItemStack,
+    /// Inventory slots changed, to journal
+    pub slots: Vec<usize>,
+    /// The profession's new level, if it went up
+    pub level_up: Option<u32>,
+}
+
+/// Craft `recipe` once from the ingredients in `inventory`
+///
+/// The ingredients are used up and the output added together, or nothing
+/// happens: missing ingredients or no room for the output is an error.
+pub fn craft(
+    recipe: &Recipe,
+    inventory: &mut Inventory,
+    skills: &mut ProfessionSkills,
+) -> Result<Crafted> {
+    let level = skills.level(recipe.profession);
+    if level < recipe.required_level {
+        return Err(anyhow!(
+            "{} needs {:?} level {}",
+            recipe.name,
+            recipe.profession,
+            recipe.required_level
+        ));
+    }
+
+    let slots = inventory.exchange(&recipe.ingredients, &[recipe.output])?;
+    let level_up = skills.gain(recipe.profession, recipe.required_level, recipe.experience);
+    Ok(Crafted {
+        output: recipe.output,
+        slots,
+        level_up,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grilled_meat() -> Recipe {
+        Recipe {
+            id: 1,
+            name: "Grilled Meat".to_string(),
+            profession: Profession::Cooking,
+            required_level: 1,
+            ingredients: vec![ItemStack::new(7101, 2), ItemStack::new(7102, 1)],
+            output: ItemStack::new(7201, 1),
+            experience: 100,
+        }
+    }
+
+    #[test]
+    fn test_craft() {
+        let mut inventory = Inventory::new(3);
+        inventory.add(ItemStack::new(7101, 3)).unwrap();
+        inventory.add(ItemStack::new(7102, 1)).unwrap();
+        let mut skills = ProfessionSkills::new();
+
+        let crafted = craft(&grilled_meat(), &mut inventory, &mut skills).unwrap();
+        assert_eq!(crafted.level_up, Some(2));
+        assert_eq!(crafted.slots, [0, 1]);
+        assert_eq!(inventory.count(7101), 1);
+        assert_eq!(inventory.count(7102), 0);
+        assert_eq!(inventory.count(7201), 1);
+
+        // Out of ingredients: nothing changes
+        let before = inventory.clone();
+        assert!(craft(&grilled_meat(), &mut inventory, &mut skills).is_err());
+        assert_eq!(inventory, before);
+
+        let feast = Recipe {
+            required_level: 5,
+            ..grilled_meat()
+        };
+        assert!(craft(&feast, &mut inventory, &mut skills).is_err());
+    }
+}
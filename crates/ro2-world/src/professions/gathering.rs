@@ -0,0 +1,395 @@
+//! Resource nodes and gathering from them
+//!
+//! Each zone's [`GatheringNodes`] spawns its nodes as [`EntityKind::Node`]
+//! entities so clients see them like anything else. Gathering takes
+//! [`NodeKind::gather_secs`]: [`GatheringNodes::begin`] claims the node
+//! for the player and [`GatheringNodes::finish`] harvests it once the time
+//! is up. A harvested node disappears until it respawns.
+
+use super::{Profession, ProfessionData, ProfessionSkills};
+use crate::inventory::{Inventory, ItemStack};
+use crate::world::{EntityId, EntityKind, Position, Zone, ZoneId};
+use anyhow::{Result, anyhow};
+use rand::Rng;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// How close a player has to be to gather from a node
+pub const GATHER_RANGE: f32 = 300.0;
+
+/// HP given to node entities; nodes can't be attacked
+const NODE_HP: u32 = 1;
+
+fn one() -> i32 {
+    1
+}
+
+fn one_level() -> u32 {
+    1
+}
+
+/// A kind of resource node
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct NodeKind {
+    pub id: u32,
+    pub name: String,
+    pub profession: Profession,
+    #[serde(default = "one_level")]
+    pub required_level: u32,
+    pub item_id: i32,
+    #[serde(default = "one")]
+    pub min_yield: i32,
+    #[serde(default = "one")]
+    pub max_yield: i32,
+    pub gather_secs: u64,
+    pub respawn_secs: u64,
+    pub experience: u32,
+}
+
+/// Where a node spawns
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct NodeSpawn {
+    pub zone: u32,
+    pub node: u32,
+    pub position: Position,
+}
+
+/// The result of a harvest
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Gathered {
+    pub profession: Profession,
+    pub item: ItemStack,
+    /// Inventory slots changed, to journal
+    pub slots: Vec<usize>,
+    /// The profession's new level, if it went up
+    pub level_up: Option<u32>,
+}
+
+struct Node {
+    kind: NodeKind,
+    position: Position,
+    /// `None` while harvested
+    entity: Option<EntityId>,
+    respawn_at: Option<Instant>,
+    gatherer: Option<u64>,
+}
+
+struct Gathering {
+    node: usize,
+    done_at: Instant,
+}
+
+/// The resource nodes of one zone and who is gathering from them
+pub struct GatheringNodes {
+    nodes: Vec<Node>,
+    by_entity: HashMap<EntityId, usize>,
+    gathering: HashMap<u64, Gathering>,
+}
+
+impl GatheringNodes {
+    /// The nodes `data` spawns in `zone`
+    pub fn new(data: &ProfessionData, zone: ZoneId) -> Self {
+        let nodes = data
+            .spawns
+            .iter()
+            .filter(|spawn| spawn.zone == zone.0)
+            .filter_map(|spawn| {
+                Some(Node {
+                    kind: data.node(spawn.node)?.clone(),
+                    position: spawn.position,
+                    entity: None,
+                    respawn_at: None,
+                    gatherer: None,
+                })
+            })
+            .collect();
+        Self {
+            nodes,
+            by_entity: HashMap::new(),
+            gathering: HashMap::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Nodes that can be gathered from right now
+    pub fn available(&self) -> usize {
+        self.nodes
+            .iter()
+            .filter(|node| node.entity.is_some())
+            .count()
+    }
+
+    /// Spawn every node that isn't up and isn't waiting to respawn
+    pub fn spawn_all(&mut self, zone: &mut Zone) {
+        for index in 0..self.nodes.len() {
+            if self.nodes[index].entity.is_none() && self.nodes[index].respawn_at.is_none() {
+                self.spawn(zone, index);
+            }
+        }
+    }
+
+    /// Respawn the nodes whose time has come
+    pub fn tick(&mut self, zone: &mut Zone, now: Instant) {
+        for index in 0..self.nodes.len() {
+            if self.nodes[index].respawn_at.is_some_and(|at| at <= now) {
+                self.spawn(zone, index);
+            }
+        }
+    }
+
+    fn spawn(&mut self, zone: &mut Zone, index: usize) {
+        let node = &mut self.nodes[index];
+        let entity = zone.spawn(EntityKind::Node, node.position, NODE_HP);
+        node.entity = Some(entity);
+        node.respawn_at = None;
+        self.by_entity.insert(entity, index);
+    }
+
+    /// Start gathering from `node` for `session_id`, standing at `from`;
+    /// returns when it will be done
+    pub fn begin(
+        &mut self,
+        session_id: u64,
+        node: EntityId,
+        from: Position,
+        skills: &ProfessionSkills,
+        now: Instant,
+    ) -> Result<Instant> {
+        if self.gathering.contains_key(&session_id) {
+            return Err(anyhow!("already gathering"));
+        }
+        let index = *self
+            .by_entity
+            .get(&node)
+            .ok_or_else(|| anyhow!("{:?} is not a node", node))?;
+        let node = &mut self.nodes[index];
+        if node.gatherer.is_some() {
+            return Err(anyhow!("someone else is gathering there"));
+        }
+        if from.distance_squared(&node.position) > GATHER_RANGE * GATHER_RANGE {
+            return Err(anyhow!("too far away"));
+        }
+        if skills.level(node.kind.profession) < node.kind.required_level {
+            return Err(anyhow!(
+                "{} needs {:?} level {}",
+                node.kind.name,
+                node.kind.profession,
+                node.kind.required_level
+            ));
+        }
+
+        node.gatherer = Some(session_id);
+        let done_at = now + Duration::from_secs(node.kind.gather_secs);
+        self.gathering.insert(
+            session_id,
+            Gathering {
+                node: index,
+                done_at,
+            },
+        );
+        Ok(done_at)
+    }
+
+    /// Stop gathering (the player moved, was hit or logged out)
+    pub fn cancel(&mut self, session_id: u64) {
+        if let Some(gathering) = self.gathering.remove(&session_id) {
+            self.nodes[gathering.node].gatherer = None;
+        }
+    }
+
+    /// Harvest the node `session_id` has been gathering from into their
+    /// inventory
+    ///
+    /// Fails before the gathering time is up. If the harvest doesn't fit
+    /// in the inventory, nothing is taken and the node stays up.
+    pub fn finish(
+        &mut self,
+        zone: &mut Zone,
+        session_id: u64,
+        now: Instant,
+        rng: &mut impl Rng,
+        inventory: &mut Inventory,
+        skills: &mut ProfessionSkills,
+    ) -> Result<Gathered> {
+        let gathering = self
+            .gathering
+            .get(&session_id)
+            .ok_or_else(|| anyhow!("not gathering"))?;
+        if now < gathering.done_at {
+            return Err(anyhow!("not done gathering yet"));
+        }
+        let index = gathering.node;
+        self.cancel(session_id);
+
+        let node = &mut self.nodes[index];
+        let kind = &node.kind;
+        let item = ItemStack::new(kind.item_id, rng.gen_range(kind.min_yield..=kind.max_yield));
+        let slots = inventory.add(item)?;
+        let level_up = skills.gain(kind.profession, kind.required_level, kind.experience);
+        let profession = kind.profession;
+
+        node.respawn_at = Some(now + Duration::from_secs(kind.respawn_secs));
+        if let Some(entity) = node.entity.take() {
+            zone.despawn(entity);
+            self.by_entity.remove(&entity);
+        }
+
+        Ok(Gathered {
+            profession,
+            item,
+            slots,
+            level_up,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    fn data() -> ProfessionData {
+        ProfessionData::from_toml(
+            r#"
+            [[nodes]]
+            id = 1
+            name = "Copper Vein"
+            profession = "mining"
+            item_id = 7001
+            min_yield = 2
+            max_yield = 3
+            gather_secs = 3
+            respawn_secs = 60
+            experience = 60
+
+            [[nodes]]
+            id = 2
+            name = "Mithril Vein"
+            profession = "mining"
+            required_level = 20
+            item_id = 7002
+            gather_secs = 5
+            respawn_secs = 600
+            experience = 200
+
+            [[spawns]]
+            zone = 1
+            node = 1
+            position = [0.0, 0.0, 0.0]
+
+            [[spawns]]
+            zone = 1
+            node = 2
+            position = [100.0, 0.0, 0.0]
+
+            [[spawns]]
+            zone = 2
+            node = 1
+            position = [0.0, 0.0, 0.0]
+            "#,
+        )
+        .unwrap()
+    }
+
+    fn node_ids(zone: &Zone) -> Vec<EntityId> {
+        zone.entities()
+            .iter()
+            .filter(|e| e.kind == EntityKind::Node)
+            .map(|e| e.id)
+            .collect()
+    }
+
+    #[test]
+    fn test_gather_and_respawn() {
+        let mut zone = Zone::new();
+        let mut nodes = GatheringNodes::new(&data(), ZoneId(1));
+        nodes.spawn_all(&mut zone);
+        assert_eq!(nodes.len(), 2);
+        assert_eq!(zone.len(), 2);
+        let (copper, mithril) = (node_ids(&zone)[0], node_ids(&zone)[1]);
+
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut inventory = Inventory::new(4);
+        let mut skills = ProfessionSkills::new();
+        let here = Position::default();
+        let start = Instant::now();
+
+        // Skill too low, too far, not a node
+        assert!(nodes.begin(1, mithril, here, &skills, start).is_err());
+        let far = Position::new(1000.0, 0.0, 0.0);
+        assert!(nodes.begin(1, copper, far, &skills, start).is_err());
+        assert!(nodes.begin(1, EntityId(99), here, &skills, start).is_err());
+
+        let done_at = nodes.begin(1, copper, here, &skills, start).unwrap();
+        assert_eq!(done_at, start + Duration::from_secs(3));
+        // One gatherer per node
+        assert!(nodes.begin(2, copper, here, &skills, start).is_err());
+        assert!(
+            nodes
+                .finish(&mut zone, 1, start, &mut rng, &mut inventory, &mut skills)
+                .is_err()
+        );
+
+        let gathered = nodes
+            .finish(&mut zone, 1, done_at, &mut rng, &mut inventory, &mut skills)
+            .unwrap();
+        assert_eq!(gathered.item.item_id, 7001);
+        assert!((2..=3).contains(&gathered.item.quantity));
+        assert_eq!(inventory.count(7001), gathered.item.quantity);
+        assert_eq!(gathered.slots, [0]);
+        assert_eq!(skills.progress(Profession::Mining).experience, 60);
+
+        // The node is gone until it respawns
+        assert_eq!(nodes.available(), 1);
+        assert!(zone.get(copper).is_none());
+        assert!(nodes.begin(2, copper, here, &skills, done_at).is_err());
+        nodes.tick(&mut zone, done_at + Duration::from_secs(59));
+        assert_eq!(nodes.available(), 1);
+        nodes.tick(&mut zone, done_at + Duration::from_secs(60));
+        assert_eq!(nodes.available(), 2);
+        assert_eq!(zone.len(), 2);
+    }
+
+    #[test]
+    fn test_full_inventory_keeps_node() {
+        let mut zone = Zone::new();
+        let mut nodes = GatheringNodes::new(&data(), ZoneId(2));
+        nodes.spawn_all(&mut zone);
+        let copper = node_ids(&zone)[0];
+
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut inventory = Inventory::new(1);
+        inventory.add(ItemStack::new(501, 1)).unwrap();
+        let mut skills = ProfessionSkills::new();
+        let start = Instant::now();
+
+        let done_at = nodes
+            .begin(1, copper, Position::default(), &skills, start)
+            .unwrap();
+        assert!(
+            nodes
+                .finish(&mut zone, 1, done_at, &mut rng, &mut inventory, &mut skills)
+                .is_err()
+        );
+        assert_eq!(nodes.available(), 1);
+        assert_eq!(skills, ProfessionSkills::new());
+
+        // Free again for the next try
+        nodes
+            .begin(1, copper, Position::default(), &skills, done_at)
+            .unwrap();
+        nodes.cancel(1);
+        nodes
+            .begin(2, copper, Position::default(), &skills, done_at)
+            .unwrap();
+    }
+}
@@ -0,0 +1,354 @@
+//! Life skills: gathering, cooking and crafting
+//!
+//! Gathering professions harvest resource nodes spawned in zones (see
+//! [`GatheringNodes`]); production professions turn items into other items
+//! by recipe (see [`craft`]). Both give profession experience, and a
+//! profession levels up independently of the character.
+//!
+//! Nodes, where they spawn and recipes are data, read from
+//! `config/professions.toml`:
+//!
+//! ```toml
+//! [[nodes]]
+//! id = 1
+//! name = "Copper Vein"
+//! profession = "mining"
+//! required_level = 1
+//! item_id = 7001
+//! max_yield = 3        # 1 to 3 per harvest; min_yield defaults to 1
+//! gather_secs = 3
+//! respawn_secs = 60
+//! experience = 20
+//!
+//! [[spawns]]
+//! zone = 1
+//! node = 1
+//! position = [120.0, 0.0, 340.0]
+//!
+//! [[recipes]]
+//! id = 1
+//! name = "Grilled Meat"
+//! profession = "cooking"
+//! required_level = 1
+//! ingredients = [{ item_id = 7101, quantity = 2 }]
+//! output = { item_id = 7201, quantity = 1 }
+//! experience = 15
+//! ```
+//!
+//! Activity well below a character's level is trivial and gives no
+//! experience (see [`TRIVIAL_LEVELS`]).
+
+mod crafting;
+mod gathering;
+
+pub use crafting::{Crafted, Recipe, craft};
+pub use gathering::{GATHER_RANGE, Gathered, GatheringNodes, NodeKind, NodeSpawn};
+
+use anyhow::{Result, anyhow};
+use ro2_common::config::{self, Validate};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashSet};
+use std::path::Path;
+
+/// Where the world server reads profession data from
+pub const PROFESSIONS_PATH: &str = "config/professions.toml";
+
+/// Highest level of any profession
+pub const MAX_PROFESSION_LEVEL: u32 = 50;
+
+/// Activity this many levels below a character's skill gives no experience
+pub const TRIVIAL_LEVELS: u32 = 10;
+
+/// A life skill
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[repr(u8)]
+pub enum Profession {
+    Mining = 1,
+    Herbalism = 2,
+    Logging = 3,
+    Cooking = 4,
+    Crafting = 5,
+}
+
+impl Profession {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            1 => Some(Self::Mining),
+            2 => Some(Self::Herbalism),
+            3 => Some(Self::Logging),
+            4 => Some(Self::Cooking),
+            5 => Some(Self::Crafting),
+            _ => None,
+        }
+    }
+
+    /// Whether the profession harvests nodes rather than following recipes
+    pub fn is_gathering(self) -> bool {
+        matches!(self, Self::Mining | Self::Herbalism | Self::Logging)
+    }
+}
+
+/// Experience needed to go from `level` to the next
+pub fn experience_to_next(level: u32) -> u32 {
+    100 * level
+}
+
+/// Level and progress in one profession
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SkillProgress {
+    pub level: u32,
+    /// Towards the next level
+    pub experience: u32,
+}
+
+impl Default for SkillProgress {
+    fn default() -> Self {
+        Self {
+            level: 1,
+            experience: 0,
+        }
+    }
+}
+
+/// A character's professions
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProfessionSkills {
+    skills: BTreeMap<Profession, SkillProgress>,
+}
+
+impl ProfessionSkills {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// From `(profession ID, level, experience)` rows as stored in
+    /// `character_professions`; unknown professions are skipped
+    pub fn from_rows(rows: &[(i32, i32, i64)]) -> Self {
+        let skills = rows
+            .iter()
+            .filter_map(|&(profession, level, experience)| {
+                let profession = Profession::from_u8(u8::try_from(profession).ok()?)?;
+                let progress = SkillProgress {
+                    level: (level.max(1) as u32).min(MAX_PROFESSION_LEVEL),
+                    experience: experience.max(0) as u32,
+                };
+                Some((profession, progress))
+            })
+            .collect();
+        Self { skills }
+    }
+
+    /// Every profession the character has progressed in
+    pub fn iter(&self) -> impl Iterator<Item = (Profession, SkillProgress)> + '_ {
+        self.skills
+            .iter()
+            .map(|(&profession, &progress)| (profession, progress))
+    }
+
+    pub fn progress(&self, profession: Profession) -> SkillProgress {
+        self.skills.get(&profession).copied().unwrap_or_default()
+    }
+
+    pub fn level(&self, profession: Profession) -> u32 {
+        self.progress(profession).level
+    }
+
+    /// Give experience for an activity of `activity_level`; returns the
+    /// new level if the profession levelled up
+    pub fn gain(
+        &mut self,
+        profession: Profession,
+        activity_level: u32,
+        experience: u32,
+    ) -> Option<u32> {
+        let progress = self.skills.entry(profession).or_default();
+        if progress.level >= MAX_PROFESSION_LEVEL
+            || progress.level >= activity_level + TRIVIAL_LEVELS
+        {
+            return None;
+        }
+
+        let start = progress.level;
+        progress.experience += experience;
+        while progress.level < MAX_PROFESSION_LEVEL
+            && progress.experience >= experience_to_next(progress.level)
+        {
+            progress.experience -= experience_to_next(progress.level);
+            progress.level += 1;
+        }
+        if progress.level == MAX_PROFESSION_LEVEL {
+            progress.experience = 0;
+        }
+        (progress.level > start).then_some(progress.level)
+    }
+}
+
+/// Every node, spawn and recipe
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct ProfessionData {
+    #[serde(default)]
+    pub nodes: Vec<NodeKind>,
+    #[serde(default)]
+    pub spawns: Vec<NodeSpawn>,
+    #[serde(default)]
+    pub recipes: Vec<Recipe>,
+}
+
+impl ProfessionData {
+    /// Read `path`; a missing file means no nodes or recipes
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        config::load(path)
+    }
+
+    /// Parse TOML text
+    pub fn from_toml(text: &str) -> Result<Self> {
+        config::from_toml(text)
+    }
+
+    pub fn node(&self, id: u32) -> Option<&NodeKind> {
+        self.nodes.iter().find(|node| node.id == id)
+    }
+
+    pub fn recipe(&self, id: u32) -> Option<&Recipe> {
+        self.recipes.iter().find(|recipe| recipe.id == id)
+    }
+}
+
+impl Validate for ProfessionData {
+    fn validate(&self) -> Result<()> {
+        let mut node_ids = HashSet::new();
+        for node in &self.nodes {
+            if !node_ids.insert(node.id) {
+                return Err(anyhow!("two nodes with id {}", node.id));
+            }
+            if !node.profession.is_gathering() {
+                return Err(anyhow!(
+                    "node {}: {:?} doesn't gather",
+                    node.id,
+                    node.profession
+                ));
+            }
+            if node.min_yield < 1 || node.max_yield < node.min_yield {
+                return Err(anyhow!("node {}: bad yield", node.id));
+            }
+        }
+        if let Some(spawn) = self.spawns.iter().find(|s| !node_ids.contains(&s.node)) {
+            return Err(anyhow!(
+                "spawn in zone {}: no node {}",
+                spawn.zone,
+                spawn.node
+            ));
+        }
+
+        let mut recipe_ids = HashSet::new();
+        for recipe in &self.recipes {
+            if !recipe_ids.insert(recipe.id) {
+                return Err(anyhow!("two recipes with id {}", recipe.id));
+            }
+            if recipe.profession.is_gathering() {
+                return Err(anyhow!(
+                    "recipe {}: {:?} doesn't craft",
+                    recipe.id,
+                    recipe.profession
+                ));
+            }
+            if recipe.ingredients.is_empty()
+                || recipe
+                    .ingredients
+                    .iter()
+                    .chain([&recipe.output])
+                    .any(|stack| stack.quantity < 1)
+            {
+                return Err(anyhow!("recipe {}: bad ingredients or output", recipe.id));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_skill_ups() {
+        let mut skills = ProfessionSkills::new();
+        assert_eq!(skills.level(Profession::Mining), 1);
+
+        assert_eq!(skills.gain(Profession::Mining, 1, 60), None);
+        assert_eq!(skills.gain(Profession::Mining, 1, 60), Some(2));
+        assert_eq!(
+            skills.progress(Profession::Mining),
+            SkillProgress {
+                level: 2,
+                experience: 20
+            }
+        );
+        // Enough for several levels at once
+        assert_eq!(skills.gain(Profession::Mining, 5, 500), Some(4));
+
+        // Too easy to learn from
+        let mut skills = ProfessionSkills::from_rows(&[(4, 15, 0), (99, 1, 0)]);
+        assert_eq!(skills.gain(Profession::Cooking, 5, 100), None);
+        assert_eq!(skills.gain(Profession::Cooking, 6, 100), None);
+        assert_eq!(skills.progress(Profession::Cooking).experience, 100);
+        assert_eq!(skills.iter().count(), 1);
+
+        let mut skills = ProfessionSkills::from_rows(&[(5, 49, 0)]);
+        assert_eq!(skills.gain(Profession::Crafting, 50, 1_000_000), Some(50));
+        assert_eq!(skills.gain(Profession::Crafting, 50, 100), None);
+        assert_eq!(skills.progress(Profession::Crafting).experience, 0);
+    }
+
+    #[test]
+    fn test_load_data() {
+        let data = ProfessionData::from_toml(
+            r#"
+            [[nodes]]
+            id = 1
+            name = "Copper Vein"
+            profession = "mining"
+            required_level = 1
+            item_id = 7001
+            max_yield = 3
+            gather_secs = 3
+            respawn_secs = 60
+            experience = 20
+
+            [[spawns]]
+            zone = 1
+            node = 1
+            position = [120.0, 0.0, 340.0]
+
+            [[recipes]]
+            id = 1
+            name = "Grilled Meat"
+            profession = "cooking"
+            ingredients = [{ item_id = 7101, quantity = 2 }]
+            output = { item_id = 7201, quantity = 1 }
+            experience = 15
+            "#,
+        )
+        .unwrap();
+        assert_eq!(data.node(1).unwrap().min_yield, 1);
+        assert_eq!(data.recipe(1).unwrap().required_level, 1);
+        assert_eq!(data.spawns[0].position.x, 120.0);
+
+        assert_eq!(
+            ProfessionData::load("does/not/exist.toml").unwrap(),
+            ProfessionData::default()
+        );
+
+        let node = "[[nodes]]\nid = 1\nname = \"x\"\nprofession = \"mining\"\nitem_id = 1\ngather_secs = 1\nrespawn_secs = 1\nexperience = 1\n";
+        for bad in [
+            format!("{node}{node}"),
+            node.replace("mining", "cooking"),
+            node.replace("item_id = 1", "item_id = 1\nmin_yield = 2\nmax_yield = 1"),
+            format!("{node}[[spawns]]\nzone = 1\nnode = 2\nposition = [0, 0, 0]"),
+            "[[recipes]]\nid = 1\nname = \"x\"\nprofession = \"cooking\"\ningredients = []\noutput = { item_id = 1, quantity = 1 }\nexperience = 1".to_string(),
+        ] {
+            assert!(ProfessionData::from_toml(&bad).is_err(), "{}", bad);
+        }
+    }
+}
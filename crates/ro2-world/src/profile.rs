@@ -0,0 +1,417 @@
+//! Key-value flags kept for characters and accounts
+//!
+//! Quests, events and scripts often need to remember something about a
+//! character or an account, like how far along a quest it is or whether it
+//! already claimed a one-off reward, that doesn't deserve a column of its
+//! own. Each such value is JSON under a key, in the namespace of the
+//! system or script that owns it (`quest`, `event.summer`, ...) so that two
+//! never trip over each other's keys, and belongs to a character or an
+//! account ([`ProfileOwner`]).
+//!
+//! Scripts run on a zone's task and can't wait on the database, so a
+//! character's values and its account's are loaded into [`Profiles`] when
+//! it enters the world, then read with [`Profiles::get`] and written with
+//! [`Profiles::set`] in memory. What changed is saved every `save_secs` by
+//! [`spawn_saver`], and when the owner is [unloaded](Profiles::unload).
+//! Systems that don't run for a character in the world read and write
+//! through [`ProfileQueries`] instead.
+//!
+//! Limits are set in the `[profile]` section of `config/world.toml`:
+//!
+//! ```toml
+//! [profile]
+//! save_secs = 60
+//! max_value_bytes = 1024   # Of a value's JSON
+//! ```
+
+use anyhow::{Context, Result, anyhow};
+use ro2_common::clock::Clock;
+use ro2_common::config::{self, Validate};
+use ro2_common::database::queries::ProfileQueries;
+use ro2_common::database::{ProfileOwner, ProfileValue};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::{Pool, Sqlite};
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tracing::warn;
+
+/// Longest namespace, in bytes
+pub const MAX_NAMESPACE_LEN: usize = 32;
+/// Longest key, in bytes
+pub const MAX_KEY_LEN: usize = 64;
+
+/// How often profiles are saved and how big a value can be
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct ProfileConfig {
+    pub save_secs: u64,
+    pub max_value_bytes: usize,
+}
+
+impl Default for ProfileConfig {
+    fn default() -> Self {
+        Self {
+            save_secs: 60,
+            max_value_bytes: 1024,
+        }
+    }
+}
+
+impl ProfileConfig {
+    /// Read the `[profile]` section of `path`; missing keys keep their
+    /// defaults
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        config::section(path, "profile")
+    }
+
+    /// Parse the `[profile]` section of TOML text
+    pub fn from_toml(text: &str) -> Result<Self> {
+        config::section_from_toml(text, "profile")
+    }
+
+    pub fn save_interval(&self) -> Duration {
+        Duration::from_secs(self.save_secs)
+    }
+}
+
+impl Validate for ProfileConfig {
+    fn validate(&self) -> Result<()> {
+        if self.save_secs == 0 || self.max_value_bytes == 0 {
+            return Err(anyhow!("profile save_secs and max_value_bytes can't be 0"));
+        }
+        Ok(())
+    }
+}
+
+/// A namespace and a key in it
+type Key = (String, String);
+
+/// One owner's values, and which changed since they were last saved
+#[derive(Debug, Default)]
+struct Profile {
+    values: HashMap<Key, Value>,
+    changed: HashSet<Key>,
+}
+
+/// A value to write, or remove when `None`
+type Change = (ProfileOwner, Key, Option<String>);
+
+/// The profiles of everyone loaded on this server
+///
+/// Cheap to clone; every clone sees the same values, so scripts and
+/// systems can each keep one.
+#[derive(Debug, Clone)]
+pub struct Profiles {
+    config: ProfileConfig,
+    loaded: Arc<Mutex<HashMap<ProfileOwner, Profile>>>,
+}
+
+fn check_key(namespace: &str, key: &str) -> Result<()> {
+    if namespace.is_empty() || namespace.len() > MAX_NAMESPACE_LEN {
+        return Err(anyhow!(
+            "profile namespace {:?} must be 1 to {} bytes",
+            namespace,
+            MAX_NAMESPACE_LEN
+        ));
+    }
+    if key.is_empty() || key.len() > MAX_KEY_LEN {
+        return Err(anyhow!(
+            "profile key {:?} must be 1 to {} bytes",
+            key,
+            MAX_KEY_LEN
+        ));
+    }
+    Ok(())
+}
+
+impl Profiles {
+    pub fn new(config: ProfileConfig) -> Self {
+        Self {
+            config,
+            loaded: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Read `owner`'s values from the database, unless they're loaded
+    /// already
+    pub async fn load(&self, pool: &Pool<Sqlite>, owner: ProfileOwner) -> Result<()> {
+        if self.is_loaded(owner) {
+            return Ok(());
+        }
+        let mut profile = Profile::default();
+        for row in ProfileQueries::all(pool, owner).await? {
+            match serde_json::from_str(&row.value) {
+                Ok(value) => {
+                    profile.values.insert((row.namespace, row.key), value);
+                }
+                Err(e) => warn!(
+                    "Skipping profile value {}.{} of {:?}: {}",
+                    row.namespace, row.key, owner, e
+                ),
+            }
+        }
+        // Loaded twice at once: keep the first, which may have changed
+        self.loaded.lock().unwrap().entry(owner).or_insert(profile);
+        Ok(())
+    }
+
+    pub fn is_loaded(&self, owner: ProfileOwner) -> bool {
+        self.loaded.lock().unwrap().contains_key(&owner)
+    }
+
+    /// `key` in `namespace` for `owner`, if it's set; an error if `owner`
+    /// isn't loaded or the value isn't a `T`
+    pub fn get<T: DeserializeOwned>(
+        &self,
+        owner: ProfileOwner,
+        namespace: &str,
+        key: &str,
+    ) -> Result<Option<T>> {
+        let loaded = self.loaded.lock().unwrap();
+        let profile = loaded
+            .get(&owner)
+            .ok_or_else(|| anyhow!("profile of {:?} isn't loaded", owner))?;
+        profile
+            .values
+            .get(&(namespace.to_string(), key.to_string()))
+            .map(|value| T::deserialize(value))
+            .transpose()
+            .with_context(|| format!("reading profile value {}.{}", namespace, key))
+    }
+
+    /// Set `key` in `namespace` for `owner`; saved with the next
+    /// [`save`](Self::save)
+    pub fn set<T: Serialize + ?Sized>(
+        &self,
+        owner: ProfileOwner,
+        namespace: &str,
+        key: &str,
+        value: &T,
+    ) -> Result<()> {
+        check_key(namespace, key)?;
+        let value = serde_json::to_value(value)?;
+        let size = value.to_string().len();
+        if size > self.config.max_value_bytes {
+            return Err(anyhow!(
+                "profile value {}.{} is {} bytes, over the {} allowed",
+                namespace,
+                key,
+                size,
+                self.config.max_value_bytes
+            ));
+        }
+        let mut loaded = self.loaded.lock().unwrap();
+        let profile = loaded
+            .get_mut(&owner)
+            .ok_or_else(|| anyhow!("profile of {:?} isn't loaded", owner))?;
+        let key = (namespace.to_string(), key.to_string());
+        profile.values.insert(key.clone(), value);
+        profile.changed.insert(key);
+        Ok(())
+    }
+
+    /// Remove `key` in `namespace` for `owner`; returns whether it was set
+    pub fn remove(&self, owner: ProfileOwner, namespace: &str, key: &str) -> Result<bool> {
+        let mut loaded = self.loaded.lock().unwrap();
+        let profile = loaded
+            .get_mut(&owner)
+            .ok_or_else(|| anyhow!("profile of {:?} isn't loaded", owner))?;
+        let key = (namespace.to_string(), key.to_string());
+        let removed = profile.values.remove(&key).is_some();
+        if removed {
+            profile.changed.insert(key);
+        }
+        Ok(removed)
+    }
+
+    /// Take the changes of `owners`, or of everyone when `None`
+    fn take_changes(&self, owners: Option<&[ProfileOwner]>) -> Vec<Change> {
+        let mut loaded = self.loaded.lock().unwrap();
+        let mut changes = vec![];
+        for (owner, profile) in loaded.iter_mut() {
+            if owners.is_some_and(|owners| !owners.contains(owner)) {
+                continue;
+            }
+            for key in profile.changed.drain() {
+                let value = profile.values.get(&key).map(Value::to_string);
+                changes.push((*owner, key, value));
+            }
+        }
+        changes
+    }
+
+    /// Mark changes that failed to save as changed again, if their owners
+    /// are still loaded
+    fn restore_changes(&self, changes: Vec<Change>) {
+        let mut loaded = self.loaded.lock().unwrap();
+        for (owner, key, _) in changes {
+            if let Some(profile) = loaded.get_mut(&owner) {
+                profile.changed.insert(key);
+            }
+        }
+    }
+
+    async fn write(&self, pool: &Pool<Sqlite>, changes: &[Change], now: i64) -> Result<()> {
+        let mut tx = pool.begin().await?;
+        for (owner, (namespace, key), value) in changes {
+            match value {
+                Some(value) => {
+                    let row = ProfileValue {
+                        namespace: namespace.clone(),
+                        key: key.clone(),
+                        value: value.clone(),
+                        updated_at: now,
+                    };
+                    ProfileQueries::set(&mut tx, *owner, &row).await?;
+                }
+                None => {
+                    ProfileQueries::remove(&mut tx, *owner, namespace, key).await?;
+                }
+            }
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn save_changes(
+        &self,
+        pool: &Pool<Sqlite>,
+        owners: Option<&[ProfileOwner]>,
+        now: i64,
+    ) -> Result<usize> {
+        let changes = self.take_changes(owners);
+        if changes.is_empty() {
+            return Ok(0);
+        }
+        if let Err(e) = self.write(pool, &changes, now).await {
+            self.restore_changes(changes);
+            return Err(e);
+        }
+        Ok(changes.len())
+    }
+
+    /// Save everything changed since the last save, in one transaction;
+    /// returns how many values were written or removed
+    pub async fn save(&self, pool: &Pool<Sqlite>, now: i64) -> Result<usize> {
+        self.save_changes(pool, None, now).await
+    }
+
+    /// Save `owner`'s changes and forget its values, e.g. when its
+    /// character leaves the world
+    pub async fn unload(&self, pool: &Pool<Sqlite>, owner: ProfileOwner, now: i64) -> Result<()> {
+        self.save_changes(pool, Some(&[owner]), now).await?;
+        self.loaded.lock().unwrap().remove(&owner);
+        Ok(())
+    }
+}
+
+/// Save the profiles' changes every `save_secs`
+pub fn spawn_saver(pool: Pool<Sqlite>, profiles: Profiles, clock: Clock) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(profiles.config.save_interval());
+        loop {
+            interval.tick().await;
+            if let Err(e) = profiles.save(&pool, clock.unix()).await {
+                warn!("Saving profiles failed: {}", e);
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ro2_common::testing;
+
+    const ALICE: ProfileOwner = ProfileOwner::Character(1);
+    const PLAYER: ProfileOwner = ProfileOwner::Account(2);
+
+    async fn pool() -> Pool<Sqlite> {
+        let pool = testing::database().await;
+        testing::character(&pool, 1, "Alice", 0).await;
+        pool
+    }
+
+    #[test]
+    fn test_config() {
+        assert_eq!(
+            ProfileConfig::load("does/not/exist.toml").unwrap(),
+            ProfileConfig::default()
+        );
+        let config = ProfileConfig::from_toml("[profile]\nmax_value_bytes = 64").unwrap();
+        assert_eq!((config.save_secs, config.max_value_bytes), (60, 64));
+        assert!(ProfileConfig::from_toml("[profile]\nsave_secs = 0").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_profiles() {
+        let pool = pool().await;
+        let profiles =
+            Profiles::new(ProfileConfig::from_toml("[profile]\nmax_value_bytes = 16").unwrap());
+        // Not loaded yet
+        assert!(profiles.set(ALICE, "quest", "stage", &1).is_err());
+
+        profiles.load(&pool, ALICE).await.unwrap();
+        profiles.load(&pool, PLAYER).await.unwrap();
+        profiles.set(ALICE, "quest", "stage", &2).unwrap();
+        profiles.set(ALICE, "quest", "done", &false).unwrap();
+        profiles
+            .set(PLAYER, "event.summer", "claimed", &[1, 2])
+            .unwrap();
+        assert_eq!(
+            profiles.get::<u32>(ALICE, "quest", "stage").unwrap(),
+            Some(2)
+        );
+        assert_eq!(
+            profiles
+                .get::<u32>(ALICE, "event.summer", "claimed")
+                .unwrap(),
+            None
+        );
+        assert!(profiles.get::<String>(ALICE, "quest", "stage").is_err());
+
+        // Too big, and keys that can't be stored
+        assert!(
+            profiles
+                .set(ALICE, "quest", "note", "a long note here")
+                .is_err()
+        );
+        assert!(profiles.set(ALICE, "", "stage", &1).is_err());
+        assert!(profiles.set(ALICE, "quest", &"k".repeat(65), &1).is_err());
+
+        assert_eq!(profiles.save(&pool, 100).await.unwrap(), 3);
+        assert_eq!(profiles.save(&pool, 101).await.unwrap(), 0);
+        assert!(profiles.remove(ALICE, "quest", "done").unwrap());
+        assert!(!profiles.remove(ALICE, "quest", "done").unwrap());
+        profiles.set(ALICE, "quest", "stage", &3).unwrap();
+        profiles.unload(&pool, ALICE, 110).await.unwrap();
+        assert!(!profiles.is_loaded(ALICE));
+
+        assert_eq!(
+            ProfileQueries::all(&pool, ALICE).await.unwrap(),
+            [ProfileValue {
+                namespace: "quest".to_string(),
+                key: "stage".to_string(),
+                value: "3".to_string(),
+                updated_at: 110,
+            }]
+        );
+        // Back in the world with what was saved
+        profiles.load(&pool, ALICE).await.unwrap();
+        assert_eq!(
+            profiles.get::<u32>(ALICE, "quest", "stage").unwrap(),
+            Some(3)
+        );
+        assert_eq!(
+            profiles
+                .get::<Vec<u32>>(PLAYER, "event.summer", "claimed")
+                .unwrap(),
+            Some(vec![1, 2])
+        );
+    }
+}
@@ -0,0 +1,140 @@
+//! Server-wide experience, drop and zeny rates
+//!
+//! Multipliers applied to what players earn, set in `config/world.toml`
+//! and changeable while the server runs from the admin console:
+//!
+//! ```toml
+//! [rates]
+//! exp = 2.0     # double experience weekend
+//! drop = 1.0
+//! zeny = 1.5
+//! ```
+//!
+//! Gameplay handlers read them through [`Rates`] as they grant rewards.
+
+use anyhow::{Result, anyhow};
+use ro2_common::config::{self, Validate};
+use serde::Deserialize;
+use std::path::Path;
+use std::sync::RwLock;
+
+/// The multipliers
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct RateConfig {
+    pub exp: f64,
+    pub drop: f64,
+    pub zeny: f64,
+}
+
+impl Default for RateConfig {
+    fn default() -> Self {
+        Self {
+            exp: 1.0,
+            drop: 1.0,
+            zeny: 1.0,
+        }
+    }
+}
+
+impl RateConfig {
+    /// Read the `[rates]` section of `path`
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        config::section(path, "rates")
+    }
+
+    /// Parse the `[rates]` section of TOML text
+    pub fn from_toml(text: &str) -> Result<Self> {
+        config::section_from_toml(text, "rates")
+    }
+
+    /// Set the rate called `name`
+    pub fn set(&mut self, name: &str, rate: f64) -> Result<()> {
+        let mut rates = *self;
+        match name {
+            "exp" => rates.exp = rate,
+            "drop" => rates.drop = rate,
+            "zeny" => rates.zeny = rate,
+            _ => return Err(anyhow!("no {:?} rate, only exp, drop and zeny", name)),
+        }
+        rates.validate()?;
+        *self = rates;
+        Ok(())
+    }
+}
+
+impl Validate for RateConfig {
+    fn validate(&self) -> Result<()> {
+        for (name, rate) in [("exp", self.exp), ("drop", self.drop), ("zeny", self.zeny)] {
+            if !rate.is_finite() || rate < 0.0 {
+                return Err(anyhow!("{} rate must be a number of at least 0", name));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The rates in effect, shared by everything that grants rewards
+#[derive(Debug, Default)]
+pub struct Rates(RwLock<RateConfig>);
+
+impl Rates {
+    pub fn new(rates: RateConfig) -> Self {
+        Self(RwLock::new(rates))
+    }
+
+    pub fn get(&self) -> RateConfig {
+        *self.0.read().unwrap()
+    }
+
+    pub fn replace(&self, rates: RateConfig) {
+        *self.0.write().unwrap() = rates;
+    }
+
+    /// Set the rate called `name`
+    pub fn set(&self, name: &str, rate: f64) -> Result<()> {
+        self.0.write().unwrap().set(name, rate)
+    }
+
+    /// `base` experience at the current rate
+    pub fn exp(&self, base: u32) -> u32 {
+        scale(base, self.get().exp)
+    }
+
+    /// `base` zeny at the current rate
+    pub fn zeny(&self, base: u32) -> u32 {
+        scale(base, self.get().zeny)
+    }
+
+    /// A drop chance at the current rate, capped at certain
+    pub fn drop_chance(&self, chance: f64) -> f64 {
+        (chance * self.get().drop).min(1.0)
+    }
+}
+
+fn scale(base: u32, rate: f64) -> u32 {
+    (base as f64 * rate).round().min(u32::MAX as f64) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rates() {
+        assert_eq!(RateConfig::from_toml("").unwrap(), RateConfig::default());
+        let config = RateConfig::from_toml("[rates]\nexp = 2.0\nzeny = 1.5").unwrap();
+        assert!(RateConfig::from_toml("[rates]\ndrop = -1.0").is_err());
+
+        let rates = Rates::new(config);
+        assert_eq!(rates.exp(101), 202);
+        assert_eq!(rates.zeny(10), 15);
+        assert_eq!(rates.drop_chance(0.25), 0.25);
+
+        rates.set("drop", 5.0).unwrap();
+        assert_eq!(rates.drop_chance(0.25), 1.0);
+        assert!(rates.set("karma", 2.0).is_err());
+        assert!(rates.set("exp", f64::NAN).is_err());
+        assert_eq!(rates.get().exp, 2.0);
+    }
+}
@@ -0,0 +1,652 @@
+//! Stat and skill resets
+//!
+//! A character can take back the points it spent and spend them again.
+//! Resetting stats puts every attribute back to 1 and returns what was put
+//! into them as stat points; resetting skills puts every learned skill
+//! back to level 1 and returns a skill point for each level above. Either
+//! is done at a reset NPC for zeny or with a reset item (`resets = "stats"`
+//! or `"skills"` in `config/items.toml`), which is used up instead.
+//!
+//! Each reset of a kind costs more at an NPC than the last: the first
+//! `free_resets` are free, then `base_cost`, `growth_percent` more each
+//! time after, up to `max_cost`. Item resets cost no zeny but still count
+//! toward the next price. Giving the points back, paying and recording the
+//! reset (in `character_resets` and the account's audit log, see
+//! [`CharacterResetQueries`]) happen in one transaction, so a reset is
+//! never paid for without being done or done without being paid for.
+//! Once stats are reset the character's [`Stats`] have to follow:
+//! [`apply_stat_reset`] recomputes them and fits HP, MP and regen to the
+//! result.
+//!
+//! Like the event shops, reset NPCs are only known by ID; nothing checks
+//! the character is near one. Set in the `[respec]` section of
+//! `config/world.toml`:
+//!
+//! ```toml
+//! [respec]
+//! npcs = [5101, 5102]
+//! free_resets = 1          # Before the first one costs anything
+//! base_cost = 10000
+//! growth_percent = 50      # On top of the last reset's cost
+//! max_cost = 10000000
+//! ```
+//!
+//! The opcodes are placeholders, in the `0x3Exx` range.
+
+use crate::inventory::{Inventory, ItemStack};
+use crate::items::ItemData;
+use crate::stats::{BaseStats, DEFAULT_ATTRIBUTE, StatKind, StatValues, Stats};
+use crate::world::{EntityId, Regen, RegenConfig, RegenRates, Vitals, Zone};
+use anyhow::{Result, anyhow};
+use ro2_common::config::{self, Validate};
+use ro2_common::database::CharacterReset;
+use ro2_common::database::queries::{CharacterQueries, CharacterResetQueries};
+use ro2_common::wire::{WireReader, WireWriter};
+use serde::Deserialize;
+use sqlx::{Pool, Sqlite};
+use std::path::Path;
+use tracing::info;
+
+pub use ro2_common::protocol::opcodes::{ACK_RESET, ANS_RESET_COST, REQ_RESET, REQ_RESET_COST};
+
+/// What a reset gives back, sent as a u8
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[repr(u8)]
+pub enum ResetKind {
+    Stats = 0,
+    Skills = 1,
+}
+
+impl ResetKind {
+    pub fn from_u8(kind: u8) -> Option<Self> {
+        match kind {
+            0 => Some(Self::Stats),
+            1 => Some(Self::Skills),
+            _ => None,
+        }
+    }
+
+    /// As stored in `character_resets`
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Stats => "stats",
+            Self::Skills => "skills",
+        }
+    }
+}
+
+/// Where resets are done and what they cost
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct RespecConfig {
+    /// NPCs that reset for zeny
+    pub npcs: Vec<u32>,
+    /// Resets of each kind before the first one costs anything
+    pub free_resets: u32,
+    pub base_cost: i64,
+    /// How much more each reset costs than the last
+    pub growth_percent: u32,
+    pub max_cost: i64,
+}
+
+impl Default for RespecConfig {
+    fn default() -> Self {
+        Self {
+            npcs: Vec::new(),
+            free_resets: 0,
+            base_cost: 10_000,
+            growth_percent: 50,
+            max_cost: 10_000_000,
+        }
+    }
+}
+
+impl RespecConfig {
+    /// Read the `[respec]` section of `path`; missing keys keep their
+    /// defaults
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        config::section(path, "respec")
+    }
+
+    /// Parse the `[respec]` section of TOML text
+    pub fn from_toml(text: &str) -> Result<Self> {
+        config::section_from_toml(text, "respec")
+    }
+
+    pub fn is_npc(&self, npc: u32) -> bool {
+        self.npcs.contains(&npc)
+    }
+
+    /// Zeny the next reset at an NPC costs after `previous` resets of the
+    /// same kind
+    pub fn cost(&self, previous: i64) -> i64 {
+        let paid = previous - i64::from(self.free_resets);
+        if paid < 0 {
+            return 0;
+        }
+        let mut cost = self.base_cost;
+        for _ in 0..paid {
+            if cost >= self.max_cost {
+                break;
+            }
+            cost += cost * i64::from(self.growth_percent) / 100;
+        }
+        cost.min(self.max_cost)
+    }
+}
+
+impl Validate for RespecConfig {
+    fn validate(&self) -> Result<()> {
+        if self.base_cost < 0 || self.max_cost < self.base_cost {
+            return Err(anyhow!(
+                "reset base_cost can't be negative or above max_cost"
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// How a reset went, as sent in [`ACK_RESET`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ResetResult {
+    Done = 0,
+    NotEnoughZeny = 1,
+    /// No point was spent, so nothing was reset or paid
+    NothingToReset = 2,
+}
+
+/// A reset as the client asks for it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResetRequest {
+    pub kind: ResetKind,
+    /// The NPC doing it, 0 with an item
+    pub npc: u32,
+    /// Where the reset item is; unused at an NPC
+    pub inventory_slot: u16,
+}
+
+fn parse_kind(message: &mut WireReader<'_>) -> Result<ResetKind> {
+    let kind = message.u8("kind")?;
+    ResetKind::from_u8(kind).ok_or_else(|| anyhow!("no reset kind {}", kind))
+}
+
+/// Parse a cost request: u8 [`ResetKind`]
+pub fn parse_req_reset_cost(message: &[u8]) -> Result<ResetKind> {
+    let mut message = WireReader::message(message, REQ_RESET_COST, "reset cost request")?;
+    parse_kind(&mut message)
+}
+
+/// Build [`ANS_RESET_COST`]: u8 [`ResetKind`], u32 resets of it so far and
+/// the i64 zeny the next one costs at an NPC
+pub fn build_ans_reset_cost(kind: ResetKind, resets: i64, cost: i64) -> Vec<u8> {
+    let mut out = WireWriter::message(ANS_RESET_COST);
+    out.u8(kind as u8)
+        .u32(resets.clamp(0, i64::from(u32::MAX)) as u32)
+        .i64(cost);
+    out.into_bytes()
+}
+
+/// Parse a reset: u8 [`ResetKind`], u32 NPC ID (0 with an item), u16
+/// inventory slot of the item
+pub fn parse_req_reset(message: &[u8]) -> Result<ResetRequest> {
+    let mut message = WireReader::message(message, REQ_RESET, "reset request")?;
+    Ok(ResetRequest {
+        kind: parse_kind(&mut message)?,
+        npc: message.u32("npc")?,
+        inventory_slot: message.u16("inventory_slot")?,
+    })
+}
+
+/// Build [`ACK_RESET`]: u8 [`ResetKind`], a [`ResetResult`], the i32 points
+/// given back and the i64 zeny the next reset costs at an NPC
+pub fn build_ack_reset(kind: ResetKind, result: ResetResult, refunded: i32, cost: i64) -> Vec<u8> {
+    let mut out = WireWriter::message(ACK_RESET);
+    out.u8(kind as u8).u8(result as u8).i32(refunded).i64(cost);
+    out.into_bytes()
+}
+
+async fn resets(pool: &Pool<Sqlite>, character_id: i64, kind: ResetKind) -> Result<i64> {
+    let mut conn = pool.acquire().await?;
+    CharacterResetQueries::count(&mut conn, character_id, kind.as_str()).await
+}
+
+/// Handle [`REQ_RESET_COST`]: the [`ANS_RESET_COST`] to send back
+pub async fn handle_req_reset_cost(
+    pool: &Pool<Sqlite>,
+    config: &RespecConfig,
+    character_id: i64,
+    message: &[u8],
+) -> Result<Vec<u8>> {
+    let kind = parse_req_reset_cost(message)?;
+    let resets = resets(pool, character_id, kind).await?;
+    Ok(build_ans_reset_cost(kind, resets, config.cost(resets)))
+}
+
+/// Handle [`REQ_RESET`] from `character_id`: the [`ACK_RESET`] to send
+/// back, the kind reset if it was, and the inventory slots to journal.
+/// After a stats reset, call [`apply_stat_reset`].
+pub async fn handle_req_reset(
+    pool: &Pool<Sqlite>,
+    config: &RespecConfig,
+    items: &ItemData,
+    inventory: &mut Inventory,
+    character_id: i64,
+    message: &[u8],
+    now: i64,
+) -> Result<(Vec<u8>, Option<ResetKind>, Vec<usize>)> {
+    let request = parse_req_reset(message)?;
+    let kind = request.kind;
+    let slot = usize::from(request.inventory_slot);
+    let item = if request.npc == 0 {
+        let stack = inventory
+            .slots()
+            .get(slot)
+            .copied()
+            .flatten()
+            .filter(|stack| items.resets(stack.item_id) == Some(kind) && !stack.is_expired(now))
+            .ok_or_else(|| anyhow!("no {} reset item in inventory slot {}", kind.as_str(), slot))?;
+        Some(stack)
+    } else if config.is_npc(request.npc) {
+        None
+    } else {
+        return Err(anyhow!("NPC {} doesn't reset", request.npc));
+    };
+
+    let mut tx = pool.begin().await?;
+    let refunded = match kind {
+        ResetKind::Stats => CharacterResetQueries::reset_stats(&mut tx, character_id).await?,
+        ResetKind::Skills => CharacterResetQueries::reset_skills(&mut tx, character_id).await?,
+    };
+    // Counted after the reset has taken the write lock, so two at once
+    // can't both pay the same price
+    let previous = CharacterResetQueries::count(&mut tx, character_id, kind.as_str()).await?;
+    let cost = if item.is_some() {
+        0
+    } else {
+        config.cost(previous)
+    };
+    let result = if refunded == 0 {
+        ResetResult::NothingToReset
+    } else if cost > 0 && !CharacterQueries::spend_gold(&mut tx, character_id, cost).await? {
+        ResetResult::NotEnoughZeny
+    } else {
+        CharacterResetQueries::record(
+            &mut tx,
+            &CharacterReset {
+                character_id,
+                kind: kind.as_str().to_string(),
+                cost,
+                item_id: item.map(|stack| stack.item_id),
+                refunded,
+                reset_at: now,
+            },
+        )
+        .await?;
+        tx.commit().await?;
+        ResetResult::Done
+    };
+    if result != ResetResult::Done {
+        let ack = build_ack_reset(kind, result, 0, config.cost(previous));
+        return Ok((ack, None, vec![]));
+    }
+
+    let mut slots = vec![];
+    if let Some(stack) = item {
+        inventory.set_slot(
+            slot,
+            Some(ItemStack {
+                quantity: stack.quantity - 1,
+                ..stack
+            }),
+        )?;
+        slots.push(slot);
+    }
+    info!(
+        "Character {} reset {} for {} zeny{}, {} points back",
+        character_id,
+        kind.as_str(),
+        cost,
+        item.map_or(String::new(), |stack| format!(
+            " with item {}",
+            stack.item_id
+        )),
+        refunded
+    );
+    let ack = build_ack_reset(kind, result, refunded, config.cost(previous + 1));
+    Ok((ack, Some(kind), slots))
+}
+
+/// Bring a character whose stats were just reset up to date: its
+/// attributes back to 1 and everything computed from them, its entity's
+/// max HP (and HP, if above it) and its MP and regen rates. Returns the
+/// new stats.
+pub fn apply_stat_reset(
+    stats: &mut Stats,
+    zone: &mut Zone,
+    regen: &mut Regen,
+    config: &RegenConfig,
+    entity: EntityId,
+) -> StatValues {
+    stats.set_base(BaseStats {
+        str: DEFAULT_ATTRIBUTE,
+        dex: DEFAULT_ATTRIBUTE,
+        int: DEFAULT_ATTRIBUTE,
+        vit: DEFAULT_ATTRIBUTE,
+        luk: DEFAULT_ATTRIBUTE,
+        ..*stats.base()
+    });
+    let values = *stats.recompute();
+    if let Some(entity) = zone.get_mut(entity) {
+        *entity.max_hp = values.get(StatKind::MaxHp) as u32;
+        *entity.hp = (*entity.hp).min(*entity.max_hp);
+    }
+    if let Some(vitals) = regen.vitals(entity) {
+        let rates = RegenRates::from_stats(stats, config);
+        let max_mp = values.get(StatKind::MaxMp) as u32;
+        // Fresh vitals are sent to the client on the next regen tick
+        regen.track(entity, Vitals::new(vitals.mp, max_mp, rates));
+    }
+    values
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stats::JobGrowth;
+    use crate::world::{EntityKind, Position};
+    use ro2_common::database::queries::{AccountDeactivationQueries, SkillQueries};
+    use ro2_common::testing;
+    use std::time::Duration;
+
+    async fn pool() -> Pool<Sqlite> {
+        let pool = testing::database().await;
+        testing::character(&pool, 1, "Alice", 25000).await;
+        sqlx::raw_sql(
+            "INSERT INTO character_stats (character_id, strength, vitality, stat_points) VALUES (1, 10, 5, 0);
+             INSERT INTO character_skills (character_id, skill_id, level) VALUES (1, 10, 3);",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        pool
+    }
+
+    async fn zeny(pool: &Pool<Sqlite>) -> i64 {
+        sqlx::query_as::<_, (i64,)>("SELECT gold FROM characters WHERE id = 1")
+            .fetch_one(pool)
+            .await
+            .unwrap()
+            .0
+    }
+
+    fn config() -> RespecConfig {
+        RespecConfig::from_toml("[respec]\nnpcs = [5101]\nbase_cost = 10000").unwrap()
+    }
+
+    fn items() -> ItemData {
+        ItemData::from_toml(
+            "[[items]]\nid = 12210\nname = \"Skill Reset Scroll\"\nresets = \"skills\"",
+        )
+        .unwrap()
+    }
+
+    fn request(kind: ResetKind, npc: u32, slot: u16) -> Vec<u8> {
+        let mut message = WireWriter::message(REQ_RESET);
+        message.u8(kind as u8).u32(npc).u16(slot);
+        message.into_bytes()
+    }
+
+    fn ack(answer: &[u8]) -> (u8, u8, i32, i64) {
+        let mut reader = WireReader::message(answer, ACK_RESET, "reset ack").unwrap();
+        let ack = (
+            reader.u8("kind").unwrap(),
+            reader.u8("result").unwrap(),
+            reader.i32("refunded").unwrap(),
+            reader.i64("cost").unwrap(),
+        );
+        reader.finish().unwrap();
+        ack
+    }
+
+    #[test]
+    fn test_config() {
+        assert_eq!(
+            RespecConfig::load("does/not/exist.toml").unwrap(),
+            RespecConfig::default()
+        );
+        assert!(config().is_npc(5101));
+        assert!(!config().is_npc(5001));
+        assert!(RespecConfig::from_toml("[respec]\nbase_cost = -1").is_err());
+        assert!(RespecConfig::from_toml("[respec]\nmax_cost = 5000").is_err());
+    }
+
+    #[test]
+    fn test_cost() {
+        let config = RespecConfig {
+            free_resets: 1,
+            max_cost: 30_000,
+            ..RespecConfig::default()
+        };
+        let costs: Vec<i64> = (0..5).map(|previous| config.cost(previous)).collect();
+        assert_eq!(costs, [0, 10_000, 15_000, 22_500, 30_000]);
+        assert_eq!(config.cost(i64::MAX), 30_000);
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            parse_req_reset(&request(ResetKind::Skills, 0, 3)).unwrap(),
+            ResetRequest {
+                kind: ResetKind::Skills,
+                npc: 0,
+                inventory_slot: 3,
+            }
+        );
+        let mut unknown = request(ResetKind::Stats, 5101, 0);
+        unknown[2] = 2;
+        assert!(parse_req_reset(&unknown).is_err());
+        assert!(parse_req_reset_cost(&REQ_RESET_COST.to_le_bytes()).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_reset_at_npc() {
+        let pool = pool().await;
+        let config = config();
+        let mut inventory = Inventory::new(10);
+
+        let mut cost_request = WireWriter::message(REQ_RESET_COST);
+        cost_request.u8(ResetKind::Stats as u8);
+        let answer = handle_req_reset_cost(&pool, &config, 1, &cost_request.into_bytes())
+            .await
+            .unwrap();
+        assert_eq!(answer, build_ans_reset_cost(ResetKind::Stats, 0, 10_000));
+
+        let (answer, reset, slots) = handle_req_reset(
+            &pool,
+            &config,
+            &items(),
+            &mut inventory,
+            1,
+            &request(ResetKind::Stats, 5101, 0),
+            100,
+        )
+        .await
+        .unwrap();
+        // 9 points from STR and 4 from VIT; the next one costs half more
+        assert_eq!(ack(&answer), (0, ResetResult::Done as u8, 13, 15_000));
+        assert_eq!((reset, slots), (Some(ResetKind::Stats), vec![]));
+        assert_eq!(zeny(&pool).await, 15_000);
+        let stats = CharacterQueries::stats(&pool, 1).await.unwrap().unwrap();
+        assert_eq!((stats.strength, stats.stat_points), (1, 13));
+
+        // Again: nothing left to give back, so nothing paid
+        let (answer, reset, _) = handle_req_reset(
+            &pool,
+            &config,
+            &items(),
+            &mut inventory,
+            1,
+            &request(ResetKind::Stats, 5101, 0),
+            101,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            ack(&answer),
+            (0, ResetResult::NothingToReset as u8, 0, 15_000)
+        );
+        assert_eq!((reset, zeny(&pool).await), (None, 15_000));
+
+        // Too poor for a second one
+        sqlx::query("UPDATE character_stats SET strength = 2 WHERE character_id = 1")
+            .execute(&pool)
+            .await
+            .unwrap();
+        sqlx::query("UPDATE characters SET gold = 14999 WHERE id = 1")
+            .execute(&pool)
+            .await
+            .unwrap();
+        let (answer, _, _) = handle_req_reset(
+            &pool,
+            &config,
+            &items(),
+            &mut inventory,
+            1,
+            &request(ResetKind::Stats, 5101, 0),
+            102,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            ack(&answer),
+            (0, ResetResult::NotEnoughZeny as u8, 0, 15_000)
+        );
+        let stats = CharacterQueries::stats(&pool, 1).await.unwrap().unwrap();
+        assert_eq!((stats.strength, stats.stat_points), (2, 13));
+
+        let history = CharacterResetQueries::history(&pool, 1).await.unwrap();
+        assert_eq!(
+            history,
+            [CharacterReset {
+                character_id: 1,
+                kind: "stats".to_string(),
+                cost: 10_000,
+                item_id: None,
+                refunded: 13,
+                reset_at: 100,
+            }]
+        );
+        let log = AccountDeactivationQueries::audit_log(&pool, 2)
+            .await
+            .unwrap();
+        assert_eq!(log.len(), 1);
+        assert_eq!(log[0].action, "reset_stats");
+
+        // Not a reset NPC
+        assert!(
+            handle_req_reset(
+                &pool,
+                &config,
+                &items(),
+                &mut inventory,
+                1,
+                &request(ResetKind::Stats, 5001, 0),
+                103,
+            )
+            .await
+            .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_reset_with_item() {
+        let pool = pool().await;
+        let config = config();
+        let items = items();
+        let mut inventory = Inventory::new(10);
+        inventory
+            .set_slot(0, Some(ItemStack::new(12210, 1)))
+            .unwrap();
+        inventory.set_slot(1, Some(ItemStack::new(501, 5))).unwrap();
+
+        // The wrong item, and the wrong kind for the scroll
+        for (kind, slot) in [(ResetKind::Skills, 1), (ResetKind::Stats, 0)] {
+            assert!(
+                handle_req_reset(
+                    &pool,
+                    &config,
+                    &items,
+                    &mut inventory,
+                    1,
+                    &request(kind, 0, slot),
+                    100,
+                )
+                .await
+                .is_err()
+            );
+        }
+
+        let (answer, reset, slots) = handle_req_reset(
+            &pool,
+            &config,
+            &items,
+            &mut inventory,
+            1,
+            &request(ResetKind::Skills, 0, 0),
+            100,
+        )
+        .await
+        .unwrap();
+        // Free with the scroll, but it still counts toward the next price
+        assert_eq!(ack(&answer), (1, ResetResult::Done as u8, 2, 15_000));
+        assert_eq!((reset, slots), (Some(ResetKind::Skills), vec![0]));
+        assert_eq!(inventory.slots()[0], None);
+        assert_eq!(zeny(&pool).await, 25_000);
+        assert_eq!(SkillQueries::list(&pool, 1).await.unwrap(), [(10, 1)]);
+        assert_eq!(
+            CharacterResetQueries::skill_points(&pool, 1).await.unwrap(),
+            2
+        );
+        assert_eq!(
+            CharacterResetQueries::history(&pool, 1).await.unwrap()[0].item_id,
+            Some(12210)
+        );
+    }
+
+    #[test]
+    fn test_apply_stat_reset() {
+        let mut stats = Stats::new(BaseStats {
+            level: 1,
+            str: 10,
+            dex: 1,
+            int: 20,
+            vit: 50,
+            luk: 1,
+            job: JobGrowth::default(),
+        });
+        let mut zone = Zone::new();
+        let player = zone.spawn(EntityKind::Player, Position::default(), 150);
+        let config = RegenConfig::default();
+        let mut regen = Regen::new(config, Duration::from_millis(100));
+        regen.track(
+            player,
+            Vitals::new(
+                60,
+                60,
+                RegenRates::from_stats(&stats, &RegenConfig::default()),
+            ),
+        );
+
+        let values = apply_stat_reset(&mut stats, &mut zone, &mut regen, &config, player);
+        assert_eq!(values.get(StatKind::Str), 1);
+        // 100 base HP and 50 base MP, raised 1% by VIT and INT
+        assert_eq!(values.get(StatKind::MaxHp), 101);
+        let entity = zone.get_mut(player).unwrap();
+        assert_eq!((*entity.hp, *entity.max_hp), (101, 101));
+        let vitals = regen.vitals(player).unwrap();
+        assert_eq!((vitals.mp, vitals.max_mp), (50, 50));
+        assert_eq!(vitals.rates, RegenRates::from_stats(&stats, &config));
+    }
+}
@@ -0,0 +1,224 @@
+//! Emotes and postures
+//!
+//! An emote is a one-off animation shown to the players around the one
+//! doing it. A posture (standing, sitting) lasts until changed and is a
+//! state flag on the entity, so it reaches clients through the regular
+//! world delta; other systems read it with [`posture`], e.g. to regenerate
+//! faster while sitting.
+//!
+//! Players change posture with the sit/stand button, sent as
+//! [`REQ_POSTURE`], or by typing `/sit` or `/stand` (see
+//! [`parse_command`]).
+//!
+//! The opcodes below are placeholders like those in `MessageType`.
+
+use crate::cooldown::{Action, CooldownKey, Cooldowns};
+use crate::world::{Broadcaster, EntityId, EntityKind, STATE_MOUNTED, STATE_SITTING, Zone};
+use anyhow::{Result, anyhow};
+use ro2_common::wire::{WireReader, WireWriter};
+
+pub use ro2_common::protocol::opcodes::{NFY_EMOTE, REQ_EMOTE, REQ_POSTURE};
+
+/// Highest emote ID the client has an animation for; tentative
+pub const MAX_EMOTE_ID: u16 = 48;
+
+/// How a player is holding themselves
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Posture {
+    Standing = 0,
+    Sitting = 1,
+}
+
+impl Posture {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(Self::Standing),
+            1 => Some(Self::Sitting),
+            _ => None,
+        }
+    }
+
+    /// The posture an entity's state flags show
+    pub fn from_state(state: u8) -> Self {
+        if state & STATE_SITTING != 0 {
+            Self::Sitting
+        } else {
+            Self::Standing
+        }
+    }
+}
+
+/// `entity`'s posture; entities that aren't there are standing
+pub fn posture(zone: &Zone, entity: EntityId) -> Posture {
+    zone.get(entity).map_or(Posture::Standing, |entity| {
+        Posture::from_state(entity.state)
+    })
+}
+
+/// Put `entity` in `posture`; returns whether it changed
+///
+/// Only living players change posture, and not while riding.
+pub fn set_posture(zone: &mut Zone, entity: EntityId, posture: Posture) -> Result<bool> {
+    let player = zone
+        .get_mut(entity)
+        .ok_or_else(|| anyhow!("no entity {:?}", entity))?;
+    if player.kind != EntityKind::Player || *player.hp == 0 {
+        return Err(anyhow!("{:?} can't change posture", entity));
+    }
+    if posture == Posture::Sitting && *player.state & STATE_MOUNTED != 0 {
+        return Err(anyhow!("can't sit while mounted"));
+    }
+
+    let before = *player.state;
+    match posture {
+        Posture::Standing => *player.state &= !STATE_SITTING,
+        Posture::Sitting => *player.state |= STATE_SITTING,
+    }
+    Ok(*player.state != before)
+}
+
+/// Parse a posture command typed in chat (`/sit` or `/stand`)
+pub fn parse_command(line: &str) -> Option<Posture> {
+    match line.trim() {
+        "/sit" => Some(Posture::Sitting),
+        "/stand" => Some(Posture::Standing),
+        _ => None,
+    }
+}
+
+/// Parse a posture request (u8 posture)
+pub fn parse_req_posture(message: &[u8]) -> Result<Posture> {
+    let value = WireReader::message(message, REQ_POSTURE, "posture request")?.u8("posture")?;
+    Posture::from_u8(value).ok_or_else(|| anyhow!("unknown posture {}", value))
+}
+
+/// Parse an emote request (u16 emote ID), checking the emote exists
+pub fn parse_req_emote(message: &[u8]) -> Result<u16> {
+    let emote = WireReader::message(message, REQ_EMOTE, "emote request")?.u16("emote ID")?;
+    if emote == 0 || emote > MAX_EMOTE_ID {
+        return Err(anyhow!("unknown emote {}", emote));
+    }
+    Ok(emote)
+}
+
+/// Build the notification of `entity` playing `emote`
+pub fn build_nfy_emote(entity: EntityId, emote: u16) -> Vec<u8> {
+    let mut out = WireWriter::message(NFY_EMOTE);
+    out.u32(entity.0).u16(emote);
+    out.into_bytes()
+}
+
+/// Handle [`REQ_EMOTE`] from the player controlling `entity`: show it to
+/// everyone in view, themselves included, unless they emoted too recently;
+/// returns how many were sent it
+pub fn handle_req_emote(
+    zone: &Zone,
+    broadcaster: &Broadcaster,
+    cooldowns: &mut Cooldowns,
+    entity: EntityId,
+    message: &[u8],
+) -> Result<usize> {
+    let emote = parse_req_emote(message)?;
+    let player = zone
+        .get(entity)
+        .filter(|player| player.kind == EntityKind::Player && player.hp > 0)
+        .ok_or_else(|| anyhow!("{:?} can't emote", entity))?;
+    let key = CooldownKey::Action(Action::Emote);
+    cooldowns.enforce(broadcaster, entity, key)?;
+    cooldowns.start_use(entity, key, cooldowns.config().action(Action::Emote));
+    Ok(broadcaster.send_near(zone, player.position, &build_nfy_emote(entity, emote)))
+}
+
+/// Handle [`REQ_POSTURE`] from the player controlling `entity`; returns
+/// whether the posture changed
+pub fn handle_req_posture(zone: &mut Zone, entity: EntityId, message: &[u8]) -> Result<bool> {
+    set_posture(zone, entity, parse_req_posture(message)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cooldown::{CooldownConfig, NFY_COOLDOWN, Refusal};
+    use crate::world::{Delta, Position};
+    use std::time::Duration;
+    use tokio::sync::mpsc;
+
+    #[test]
+    fn test_emote_reaches_players_in_view() {
+        let mut zone = Zone::new();
+        let player = zone.spawn(EntityKind::Player, Position::default(), 100);
+        let near = zone.spawn(EntityKind::Player, Position::new(10.0, 0.0, 0.0), 100);
+        let far = zone.spawn(EntityKind::Player, Position::new(5000.0, 0.0, 0.0), 100);
+        let mut broadcaster = Broadcaster::default();
+        let mut inboxes = Vec::new();
+        for (session, entity) in [(1, player), (2, near), (3, far)] {
+            let (tx, rx) = mpsc::channel(4);
+            broadcaster.subscribe(session, entity, tx);
+            inboxes.push(rx);
+        }
+
+        let mut cooldowns = Cooldowns::new(CooldownConfig::default(), Duration::from_millis(100));
+        let wave = [0x30, 0x3F, 5, 0];
+
+        let sent = handle_req_emote(&zone, &broadcaster, &mut cooldowns, player, &wave).unwrap();
+        assert_eq!(sent, 2);
+        let expected = build_nfy_emote(player, 5);
+        assert_eq!(expected, [0x31, 0x3F, 1, 0, 0, 0, 5, 0]);
+        assert_eq!(inboxes[0].try_recv().unwrap(), expected);
+        assert_eq!(inboxes[1].try_recv().unwrap(), expected);
+        assert!(inboxes[2].try_recv().is_err());
+
+        // Again straight away is refused, and only the player is told
+        let error =
+            handle_req_emote(&zone, &broadcaster, &mut cooldowns, player, &wave).unwrap_err();
+        assert!(error.downcast_ref::<Refusal>().unwrap().suspicious);
+        assert_eq!(
+            &inboxes[0].try_recv().unwrap()[..2],
+            &NFY_COOLDOWN.to_le_bytes()
+        );
+        assert!(inboxes[1].try_recv().is_err());
+
+        // Unknown or cut-short emotes and the dead don't emote
+        for cut in 0..wave.len() {
+            assert!(parse_req_emote(&wave[..cut]).is_err());
+        }
+        assert!(parse_req_posture(&[0x32, 0x3F]).is_err());
+        for bad in [[0x30, 0x3F, 0, 0], [0x30, 0x3F, 0xFF, 0]] {
+            assert!(handle_req_emote(&zone, &broadcaster, &mut cooldowns, far, &bad).is_err());
+        }
+        *zone.get_mut(near).unwrap().hp = 0;
+        assert!(handle_req_emote(&zone, &broadcaster, &mut cooldowns, near, &wave).is_err());
+    }
+
+    #[test]
+    fn test_posture() {
+        let mut zone = Zone::new();
+        let player = zone.spawn(EntityKind::Player, Position::default(), 100);
+        let monster = zone.spawn(EntityKind::Monster, Position::default(), 100);
+        let mut broadcaster = Broadcaster::default();
+        let (tx, mut rx) = mpsc::channel(4);
+        broadcaster.subscribe(1, monster, tx);
+        broadcaster.broadcast(&zone.snapshot());
+        rx.try_recv().unwrap();
+
+        assert_eq!(posture(&zone, player), Posture::Standing);
+        assert!(handle_req_posture(&mut zone, player, &[0x32, 0x3F, 1]).unwrap());
+        assert!(!set_posture(&mut zone, player, Posture::Sitting).unwrap());
+        assert_eq!(posture(&zone, player), Posture::Sitting);
+
+        // Others see it in the next delta
+        broadcaster.broadcast(&zone.snapshot());
+        let delta = Delta::decode(&rx.try_recv().unwrap()).unwrap();
+        assert_eq!(delta.updated[0].state, Some(STATE_SITTING));
+
+        assert!(set_posture(&mut zone, player, parse_command(" /stand").unwrap()).unwrap());
+        assert_eq!(posture(&zone, player), Posture::Standing);
+        assert_eq!(parse_command("/dance"), None);
+
+        *zone.get_mut(player).unwrap().state |= STATE_MOUNTED;
+        assert!(set_posture(&mut zone, player, Posture::Sitting).is_err());
+        assert!(set_posture(&mut zone, monster, Posture::Sitting).is_err());
+        assert!(handle_req_posture(&mut zone, player, &[0x32, 0x3F, 9]).is_err());
+    }
+}
@@ -0,0 +1,579 @@
+//! A character's final stats
+//!
+//! [`Stats`] computes what combat and the client's status window use from
+//! a character's attributes, job and level plus the bonuses in its
+//! [`StatModifiers`], in layers:
+//!
+//! 1. attributes from the character's `character_stats` row ([`BaseStats`])
+//! 2. attribute bonuses (STR, DEX, ...) from every source
+//! 3. HP and MP from the job's growth ([`JobGrowth`]) and level, raised by
+//!    VIT and INT, and the other derived stats from the attributes
+//! 4. derived stat bonuses (ATK, max HP, ...) from every source
+//!
+//! Each source of bonuses (equipment, the equipped title, each buff) is
+//! kept apart in [`StatModifiers`], so that changing one source replaces
+//! its bonus without recomputing the others. The result is cached until
+//! something changes; call [`Stats::recompute`] after a batch of changes.
+//!
+//! Job growth is data, read from `config/jobs.toml`; jobs without an entry
+//! grow like the default one:
+//!
+//! ```toml
+//! [default]
+//! hp = 100           # At level 1
+//! mp = 50
+//! hp_per_level = 20
+//! mp_per_level = 10
+//!
+//! [[jobs]]
+//! job_class = 2
+//! hp = 120
+//! mp = 30
+//! hp_per_level = 30
+//! mp_per_level = 5
+//! bonus = { str = 2 }
+//! ```
+
+use anyhow::{Result, anyhow};
+use ro2_common::config::{self, Validate};
+use ro2_common::database::{Character, CharacterStats};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::ops::{Add, AddAssign};
+use std::path::Path;
+
+/// Where the world server reads job growth from
+pub const JOBS_PATH: &str = "config/jobs.toml";
+
+/// Attributes of a character without a `character_stats` row
+pub const DEFAULT_ATTRIBUTE: i32 = 1;
+
+/// A stat combat or the status window reads
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum StatKind {
+    Str,
+    Dex,
+    Int,
+    Vit,
+    Luk,
+    MaxHp,
+    MaxMp,
+    Atk,
+    Matk,
+    Def,
+    Mdef,
+    Hit,
+    Flee,
+    Crit,
+}
+
+impl StatKind {
+    pub const ALL: [StatKind; 14] = [
+        StatKind::Str,
+        StatKind::Dex,
+        StatKind::Int,
+        StatKind::Vit,
+        StatKind::Luk,
+        StatKind::MaxHp,
+        StatKind::MaxMp,
+        StatKind::Atk,
+        StatKind::Matk,
+        StatKind::Def,
+        StatKind::Mdef,
+        StatKind::Hit,
+        StatKind::Flee,
+        StatKind::Crit,
+    ];
+}
+
+/// Flat additions to a character's stats; `{ str = 2, max_hp = 50 }` in
+/// config files, with anything left out being zero
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct StatBonus {
+    pub str: i32,
+    pub dex: i32,
+    pub int: i32,
+    pub vit: i32,
+    pub luk: i32,
+    pub max_hp: i32,
+    pub max_mp: i32,
+    pub atk: i32,
+    pub matk: i32,
+    pub def: i32,
+    pub mdef: i32,
+    pub hit: i32,
+    pub flee: i32,
+    pub crit: i32,
+}
+
+impl StatBonus {
+    pub fn is_zero(&self) -> bool {
+        *self == Self::default()
+    }
+
+    pub fn get(&self, kind: StatKind) -> i32 {
+        match kind {
+            StatKind::Str => self.str,
+            StatKind::Dex => self.dex,
+            StatKind::Int => self.int,
+            StatKind::Vit => self.vit,
+            StatKind::Luk => self.luk,
+            StatKind::MaxHp => self.max_hp,
+            StatKind::MaxMp => self.max_mp,
+            StatKind::Atk => self.atk,
+            StatKind::Matk => self.matk,
+            StatKind::Def => self.def,
+            StatKind::Mdef => self.mdef,
+            StatKind::Hit => self.hit,
+            StatKind::Flee => self.flee,
+            StatKind::Crit => self.crit,
+        }
+    }
+}
+
+impl Add for StatBonus {
+    type Output = Self;
+
+    fn add(mut self, other: Self) -> Self {
+        self += other;
+        self
+    }
+}
+
+impl AddAssign for StatBonus {
+    fn add_assign(&mut self, other: Self) {
+        self.str += other.str;
+        self.dex += other.dex;
+        self.int += other.int;
+        self.vit += other.vit;
+        self.luk += other.luk;
+        self.max_hp += other.max_hp;
+        self.max_mp += other.max_mp;
+        self.atk += other.atk;
+        self.matk += other.matk;
+        self.def += other.def;
+        self.mdef += other.mdef;
+        self.hit += other.hit;
+        self.flee += other.flee;
+        self.crit += other.crit;
+    }
+}
+
+/// Where a bonus comes from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum BonusSource {
+    /// Every equipped item, added up
+    Equipment,
+    /// The equipped Khara title (see [`crate::khara`])
+    Title,
+    /// One active buff, by buff ID
+    Buff(u32),
+}
+
+/// A character's bonuses, by source
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StatModifiers {
+    bonuses: BTreeMap<BonusSource, StatBonus>,
+}
+
+impl StatModifiers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replace the bonus from `source`
+    pub fn set(&mut self, source: BonusSource, bonus: StatBonus) {
+        if bonus.is_zero() {
+            self.bonuses.remove(&source);
+        } else {
+            self.bonuses.insert(source, bonus);
+        }
+    }
+
+    pub fn get(&self, source: BonusSource) -> StatBonus {
+        self.bonuses.get(&source).copied().unwrap_or_default()
+    }
+
+    /// Every source's bonus added up
+    pub fn total(&self) -> StatBonus {
+        self.bonuses
+            .values()
+            .fold(StatBonus::default(), |total, &bonus| total + bonus)
+    }
+}
+
+/// How a job's HP and MP grow with level, and what it adds on top
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub struct JobGrowth {
+    /// Unset for the default growth
+    #[serde(default)]
+    pub job_class: Option<i32>,
+    /// At level 1
+    pub hp: i32,
+    pub mp: i32,
+    pub hp_per_level: i32,
+    pub mp_per_level: i32,
+    #[serde(default)]
+    pub bonus: StatBonus,
+}
+
+impl Default for JobGrowth {
+    fn default() -> Self {
+        Self {
+            job_class: None,
+            hp: 100,
+            mp: 50,
+            hp_per_level: 20,
+            mp_per_level: 10,
+            bonus: StatBonus::default(),
+        }
+    }
+}
+
+/// Every job's growth
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+pub struct JobData {
+    #[serde(default)]
+    pub default: JobGrowth,
+    #[serde(default)]
+    pub jobs: Vec<JobGrowth>,
+}
+
+impl JobData {
+    /// Read `path`; a missing file means every job grows the default way
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        config::load(path)
+    }
+
+    /// Parse TOML text
+    pub fn from_toml(text: &str) -> Result<Self> {
+        config::from_toml(text)
+    }
+
+    /// The growth of `job_class`
+    pub fn job(&self, job_class: i32) -> &JobGrowth {
+        self.jobs
+            .iter()
+            .find(|job| job.job_class == Some(job_class))
+            .unwrap_or(&self.default)
+    }
+}
+
+impl Validate for JobData {
+    fn validate(&self) -> Result<()> {
+        let mut seen = Vec::new();
+        for job in &self.jobs {
+            let job_class = job
+                .job_class
+                .ok_or_else(|| anyhow!("every [[jobs]] entry needs a job_class"))?;
+            if seen.contains(&job_class) {
+                return Err(anyhow!("job {} is listed twice", job_class));
+            }
+            seen.push(job_class);
+        }
+        for job in std::iter::once(&self.default).chain(&self.jobs) {
+            if job.hp <= 0 || job.mp < 0 || job.hp_per_level < 0 || job.mp_per_level < 0 {
+                return Err(anyhow!("job {:?} has negative or no HP/MP", job.job_class));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// What a character's stats start from, before any bonus
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BaseStats {
+    pub level: i32,
+    pub str: i32,
+    pub dex: i32,
+    pub int: i32,
+    pub vit: i32,
+    pub luk: i32,
+    pub job: JobGrowth,
+}
+
+impl BaseStats {
+    /// From a character, its `character_stats` row if it has one, and its
+    /// job's growth
+    pub fn from_character(
+        character: &Character,
+        stats: Option<&CharacterStats>,
+        jobs: &JobData,
+    ) -> Self {
+        let attribute = |get: fn(&CharacterStats) -> i32| stats.map_or(DEFAULT_ATTRIBUTE, get);
+        Self {
+            level: character.level,
+            str: attribute(|s| s.strength),
+            dex: attribute(|s| s.dexterity),
+            int: attribute(|s| s.intelligence),
+            vit: attribute(|s| s.vitality),
+            luk: attribute(|s| s.luck),
+            job: *jobs.job(character.job_class),
+        }
+    }
+}
+
+/// Final stats as of the last computation
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StatValues(StatBonus);
+
+impl StatValues {
+    pub fn get(&self, kind: StatKind) -> i32 {
+        self.0.get(kind)
+    }
+
+    /// Run the layers over `base` and `bonus`
+    fn compute(base: &BaseStats, bonus: StatBonus) -> Self {
+        let bonus = bonus + base.job.bonus;
+        let level = base.level.max(1);
+
+        // Attributes never drop below 1, whatever debuffs say
+        let str = (base.str + bonus.str).max(1);
+        let dex = (base.dex + bonus.dex).max(1);
+        let int = (base.int + bonus.int).max(1);
+        let vit = (base.vit + bonus.vit).max(1);
+        let luk = (base.luk + bonus.luk).max(1);
+
+        let hp = base.job.hp + base.job.hp_per_level * (level - 1);
+        let mp = base.job.mp + base.job.mp_per_level * (level - 1);
+        let derived = StatBonus {
+            str,
+            dex,
+            int,
+            vit,
+            luk,
+            max_hp: hp * (100 + vit) / 100,
+            max_mp: mp * (100 + int) / 100,
+            atk: str * 2 + dex / 5 + luk / 5 + level,
+            matk: int * 2 + dex / 5 + level,
+            def: vit + level / 2,
+            mdef: int / 2 + vit / 5,
+            hit: level + dex,
+            flee: level + (dex + luk) / 2,
+            crit: luk / 3,
+        };
+
+        let mut values = derived
+            + StatBonus {
+                str: 0,
+                dex: 0,
+                int: 0,
+                vit: 0,
+                luk: 0,
+                ..bonus
+            };
+        values.max_hp = values.max_hp.max(1);
+        for value in [
+            &mut values.max_mp,
+            &mut values.atk,
+            &mut values.matk,
+            &mut values.def,
+            &mut values.mdef,
+            &mut values.hit,
+            &mut values.flee,
+            &mut values.crit,
+        ] {
+            *value = (*value).max(0);
+        }
+        Self(values)
+    }
+}
+
+/// A character's stats, cached until its base or bonuses change
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Stats {
+    base: BaseStats,
+    modifiers: StatModifiers,
+    values: StatValues,
+    dirty: bool,
+}
+
+impl Stats {
+    pub fn new(base: BaseStats) -> Self {
+        let mut stats = Self {
+            base,
+            modifiers: StatModifiers::new(),
+            values: StatValues::default(),
+            dirty: true,
+        };
+        stats.recompute();
+        stats
+    }
+
+    pub fn base(&self) -> &BaseStats {
+        &self.base
+    }
+
+    /// Replace the base, e.g. on level up or after spending stat points
+    pub fn set_base(&mut self, base: BaseStats) {
+        if base != self.base {
+            self.base = base;
+            self.dirty = true;
+        }
+    }
+
+    pub fn modifiers(&self) -> &StatModifiers {
+        &self.modifiers
+    }
+
+    /// Change the bonuses, e.g. with [`KharaProgress::equip`]; the stats
+    /// are recomputed at the next [`recompute`](Self::recompute)
+    ///
+    /// [`KharaProgress::equip`]: crate::khara::KharaProgress::equip
+    pub fn modifiers_mut(&mut self) -> &mut StatModifiers {
+        self.dirty = true;
+        &mut self.modifiers
+    }
+
+    /// Replace the bonus from `source`
+    pub fn set_bonus(&mut self, source: BonusSource, bonus: StatBonus) {
+        if self.modifiers.get(source) != bonus {
+            self.modifiers.set(source, bonus);
+            self.dirty = true;
+        }
+    }
+
+    /// Whether something changed since the last [`recompute`](Self::recompute)
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    /// Bring the cached stats up to date, if anything changed
+    pub fn recompute(&mut self) -> &StatValues {
+        if self.dirty {
+            self.values = StatValues::compute(&self.base, self.modifiers.total());
+            self.dirty = false;
+        }
+        &self.values
+    }
+
+    /// One final stat; computed afresh when something changed since the
+    /// last [`recompute`](Self::recompute), so it's never stale
+    pub fn get(&self, kind: StatKind) -> i32 {
+        if self.dirty {
+            StatValues::compute(&self.base, self.modifiers.total()).get(kind)
+        } else {
+            self.values.get(kind)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const JOBS: &str = r#"
+        [default]
+        hp = 100
+        mp = 50
+        hp_per_level = 20
+        mp_per_level = 10
+
+        [[jobs]]
+        job_class = 2
+        hp = 120
+        mp = 30
+        hp_per_level = 30
+        mp_per_level = 5
+        bonus = { str = 2 }
+    "#;
+
+    fn base(jobs: &JobData, job_class: i32) -> BaseStats {
+        BaseStats {
+            level: 11,
+            str: 10,
+            dex: 10,
+            int: 5,
+            vit: 20,
+            luk: 3,
+            job: *jobs.job(job_class),
+        }
+    }
+
+    #[test]
+    fn test_load_jobs() {
+        assert_eq!(JobData::from_toml("").unwrap(), JobData::default());
+        let jobs = JobData::from_toml(JOBS).unwrap();
+        assert_eq!(jobs.job(2).hp_per_level, 30);
+        assert_eq!(jobs.job(7), &jobs.default);
+
+        let twice = format!(
+            "{}\n[[jobs]]\njob_class = 2\nhp = 1\nmp = 1\nhp_per_level = 1\nmp_per_level = 1",
+            JOBS
+        );
+        assert!(JobData::from_toml(&twice).is_err());
+        assert!(JobData::from_toml(&JOBS.replace("hp = 120", "hp = 0")).is_err());
+    }
+
+    #[test]
+    fn test_pipeline() {
+        let jobs = JobData::from_toml(JOBS).unwrap();
+        let stats = Stats::new(base(&jobs, 1));
+        // 100 + 20 * 10 HP, raised 20% by VIT
+        assert_eq!(stats.get(StatKind::MaxHp), 360);
+        assert_eq!(stats.get(StatKind::MaxMp), 157);
+        assert_eq!(stats.get(StatKind::Atk), 20 + 2 + 11);
+        assert_eq!(stats.get(StatKind::Flee), 11 + 6);
+
+        // The job's bonus counts as an attribute bonus, so it raises ATK too
+        let stats = Stats::new(base(&jobs, 2));
+        assert_eq!(stats.get(StatKind::Str), 12);
+        assert_eq!(stats.get(StatKind::Atk), 24 + 2 + 11);
+    }
+
+    #[test]
+    fn test_bonuses_and_cache() {
+        let jobs = JobData::default();
+        let mut stats = Stats::new(base(&jobs, 1));
+        let atk = stats.get(StatKind::Atk);
+
+        stats.set_bonus(
+            BonusSource::Equipment,
+            StatBonus {
+                str: 5,
+                atk: 7,
+                ..Default::default()
+            },
+        );
+        stats.set_bonus(
+            BonusSource::Buff(3),
+            StatBonus {
+                max_hp: 40,
+                ..Default::default()
+            },
+        );
+        assert!(stats.is_dirty());
+        // Read before the recompute, it's still up to date
+        assert_eq!(stats.get(StatKind::Atk), atk + 10 + 7);
+        assert_eq!(stats.recompute().get(StatKind::MaxHp), 400);
+        assert!(!stats.is_dirty());
+
+        // Setting the same bonus again changes nothing
+        stats.set_bonus(
+            BonusSource::Buff(3),
+            StatBonus {
+                max_hp: 40,
+                ..Default::default()
+            },
+        );
+        assert!(!stats.is_dirty());
+
+        // The buff wearing off
+        stats.set_bonus(BonusSource::Buff(3), StatBonus::default());
+        assert_eq!(stats.recompute().get(StatKind::MaxHp), 360);
+
+        // Debuffs can't take attributes below 1 or ATK below 0
+        stats.set_bonus(
+            BonusSource::Buff(4),
+            StatBonus {
+                str: -100,
+                atk: -1000,
+                ..Default::default()
+            },
+        );
+        stats.recompute();
+        assert_eq!(stats.get(StatKind::Str), 1);
+        assert_eq!(stats.get(StatKind::Atk), 0);
+    }
+}
@@ -0,0 +1,652 @@
+//! First-login tutorial
+//!
+//! A character entering the world for the first time spawns in the
+//! tutorial map instead of their starting map, and is walked through its
+//! steps one at a time: go somewhere, kill a monster, talk to an NPC, use
+//! an item or play an emote. Gameplay reports what the player did as a
+//! [`TutorialEvent`] and [`Tutorial::record`] moves them on when it's what
+//! their step asks for; after the last step they're sent to the tutorial's
+//! exit. The step they're on is saved (see [`TutorialQueries`]), so a
+//! player who logs out half way carries on where they left off.
+//!
+//! Steps are data, read from `config/tutorial.toml`; anything a step needs
+//! beyond that, like spawning a training dummy, is a [`TutorialScript`]:
+//!
+//! ```toml
+//! map = 100              # Where new characters spawn...
+//! spawn = [0.0, 0.0, 0.0]
+//! exit_map = 1           # ...and where they go once it's done
+//! exit = [120.0, 0.0, 80.0]
+//!
+//! [[steps]]
+//! id = 1
+//! name = "Walk to the signpost"
+//! goal = "reach"
+//! at = [10.0, 0.0, 5.0]
+//! radius = 3.0           # Default 3
+//!
+//! [[steps]]
+//! id = 2
+//! name = "Defeat the training dummy"
+//! goal = "kill"          # Or talk, use_item, emote
+//! target = 9001          # Monster, NPC, item or emote ID; 0 or left out = any
+//! ```
+//!
+//! Operators can turn the tutorial off for everyone in the `[tutorial]`
+//! section of `config/world.toml`; new characters then start in their
+//! starting map, and those part way through are sent to the exit the next
+//! time they enter the world:
+//!
+//! ```toml
+//! [tutorial]
+//! enabled = false
+//! ```
+//!
+//! Starting, each step and leaving are published as [`events::Tutorial`]
+//! for anything else that wants to react. The opcodes below are
+//! placeholders like those in `MessageType`.
+//!
+//! [`TutorialQueries`]: ro2_common::database::queries::TutorialQueries
+
+use crate::world::{EntityId, Position, Zone};
+use anyhow::{Result, anyhow};
+use ro2_common::config::{self, Validate};
+use ro2_common::events::{self, EventBus, MonsterKilled};
+use ro2_common::wire::WireWriter;
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+pub use ro2_common::protocol::opcodes::{NFY_TUTORIAL_END, NFY_TUTORIAL_START, NFY_TUTORIAL_STEP};
+
+/// Where the world server reads the tutorial from
+pub const TUTORIAL_PATH: &str = "config/tutorial.toml";
+
+fn enabled() -> bool {
+    true
+}
+
+fn default_radius() -> f32 {
+    3.0
+}
+
+/// Tutorial settings
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct TutorialConfig {
+    /// Off skips the tutorial for every character
+    pub enabled: bool,
+}
+
+impl Default for TutorialConfig {
+    fn default() -> Self {
+        Self { enabled: enabled() }
+    }
+}
+
+impl TutorialConfig {
+    /// Read the `[tutorial]` section of `path`; missing keys keep their
+    /// defaults
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        config::section(path, "tutorial")
+    }
+
+    /// Parse the `[tutorial]` section of TOML text
+    pub fn from_toml(text: &str) -> Result<Self> {
+        config::section_from_toml(text, "tutorial")
+    }
+}
+
+impl Validate for TutorialConfig {}
+
+/// What a step asks the player to do
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Goal {
+    /// Get within the step's `radius` of `at`
+    Reach,
+    Kill,
+    Talk,
+    UseItem,
+    Emote,
+}
+
+/// Something a player did which a step may ask for
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TutorialEvent {
+    Moved { position: Position },
+    MonsterKilled { monster_id: u32 },
+    NpcTalkedTo { npc_id: u32 },
+    ItemUsed { item_id: u32 },
+    Emoted { emote: u16 },
+}
+
+impl From<MonsterKilled> for TutorialEvent {
+    fn from(event: MonsterKilled) -> Self {
+        TutorialEvent::MonsterKilled {
+            monster_id: event.monster_id,
+        }
+    }
+}
+
+/// One step of the tutorial
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Step {
+    pub id: u32,
+    pub name: String,
+    pub goal: Goal,
+    /// Monster, NPC, item or emote ID; 0 accepts any
+    #[serde(default)]
+    pub target: u32,
+    /// Where a [`Goal::Reach`] step leads
+    #[serde(default)]
+    pub at: Option<Position>,
+    #[serde(default = "default_radius")]
+    pub radius: f32,
+}
+
+impl Step {
+    /// Whether `event` is what this step asks for
+    pub fn is_done_by(&self, event: TutorialEvent) -> bool {
+        let matches = |id: u32| self.target == 0 || self.target == id;
+        match (self.goal, event) {
+            (Goal::Reach, TutorialEvent::Moved { position }) => self
+                .at
+                .is_some_and(|at| at.distance_squared(&position) <= self.radius * self.radius),
+            (Goal::Kill, TutorialEvent::MonsterKilled { monster_id }) => matches(monster_id),
+            (Goal::Talk, TutorialEvent::NpcTalkedTo { npc_id }) => matches(npc_id),
+            (Goal::UseItem, TutorialEvent::ItemUsed { item_id }) => matches(item_id),
+            (Goal::Emote, TutorialEvent::Emoted { emote }) => matches(u32::from(emote)),
+            _ => false,
+        }
+    }
+}
+
+/// The tutorial map and its steps
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct TutorialData {
+    #[serde(default)]
+    pub map: u32,
+    #[serde(default)]
+    pub spawn: Position,
+    #[serde(default)]
+    pub exit_map: u32,
+    #[serde(default)]
+    pub exit: Position,
+    #[serde(default)]
+    pub steps: Vec<Step>,
+}
+
+impl TutorialData {
+    /// Read `path`; a missing file means no tutorial
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        config::load(path)
+    }
+
+    /// Parse TOML text
+    pub fn from_toml(text: &str) -> Result<Self> {
+        config::from_toml(text)
+    }
+}
+
+impl Validate for TutorialData {
+    fn validate(&self) -> Result<()> {
+        if self.steps.is_empty() {
+            return Ok(());
+        }
+        if self.map == 0 || self.exit_map == 0 {
+            return Err(anyhow!("tutorial map and exit_map must be set"));
+        }
+        if self.steps.len() > usize::from(u16::MAX) {
+            return Err(anyhow!("more than {} tutorial steps", u16::MAX));
+        }
+        let mut ids = HashSet::new();
+        for step in &self.steps {
+            if step.id == 0 || !ids.insert(step.id) {
+                return Err(anyhow!("tutorial step id {} is 0 or used twice", step.id));
+            }
+            if step.goal == Goal::Reach && step.at.is_none() {
+                return Err(anyhow!("tutorial step {}: reach needs `at`", step.id));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// How far a character has got, as saved
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TutorialProgress {
+    NotStarted,
+    /// On the step at this index
+    Step(usize),
+    /// Finished or skipped
+    Done,
+}
+
+impl TutorialProgress {
+    /// From what [`TutorialQueries::state`] loads
+    ///
+    /// [`TutorialQueries::state`]: ro2_common::database::queries::TutorialQueries::state
+    pub fn from_row(row: Option<(i32, Option<i64>)>) -> Self {
+        match row {
+            None => TutorialProgress::NotStarted,
+            Some((_, Some(_))) => TutorialProgress::Done,
+            Some((step, None)) => TutorialProgress::Step(usize::try_from(step).unwrap_or(0)),
+        }
+    }
+}
+
+/// Where a character entering the world spawns
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Placement {
+    /// Where they were saved
+    Saved,
+    /// In the tutorial; start or resume it with [`Tutorial::begin`] once
+    /// they're in
+    Tutorial { map: u32, position: Position },
+    /// At the tutorial's exit, because it was turned off while they were
+    /// in it; [`Tutorial::skip`] once they're in
+    Exit { map: u32, position: Position },
+}
+
+/// Behavior a step needs beyond what its data says, such as spawning the
+/// monster it asks for or handing out the item
+///
+/// Runs on the zone's task, with the player's entity.
+pub trait TutorialScript: Send {
+    /// `player` reached this step
+    fn on_step(&mut self, _zone: &mut Zone, _player: EntityId) {}
+
+    /// `player` did what this step asked
+    fn on_complete(&mut self, _zone: &mut Zone, _player: EntityId) {}
+}
+
+/// What starting or moving through the tutorial changed
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TutorialUpdate {
+    /// Notifications to send the player
+    pub messages: Vec<Vec<u8>>,
+    /// The progress changed and should be saved
+    pub save: bool,
+    /// Move the player here: they're done
+    pub exit: Option<(u32, Position)>,
+}
+
+/// The tutorial in one zone
+pub struct Tutorial {
+    config: TutorialConfig,
+    data: TutorialData,
+    /// By step ID
+    scripts: HashMap<u32, Box<dyn TutorialScript>>,
+    events: Option<EventBus>,
+}
+
+impl Tutorial {
+    pub fn new(config: TutorialConfig, data: TutorialData) -> Self {
+        Self {
+            config,
+            data,
+            scripts: HashMap::new(),
+            events: None,
+        }
+    }
+
+    /// Publish each character's progress on `events`
+    pub fn with_events(mut self, events: &EventBus) -> Self {
+        self.events = Some(events.clone());
+        self
+    }
+
+    /// Run `script` for step `step`
+    pub fn script(&mut self, step: u32, script: impl TutorialScript + 'static) {
+        self.scripts.insert(step, Box::new(script));
+    }
+
+    pub fn data(&self) -> &TutorialData {
+        &self.data
+    }
+
+    /// On, with steps to take
+    pub fn is_enabled(&self) -> bool {
+        self.config.enabled && !self.data.steps.is_empty()
+    }
+
+    /// Where a character with `progress` spawns; `first_login` if they've
+    /// never been in the world
+    pub fn placement(&self, progress: TutorialProgress, first_login: bool) -> Placement {
+        match progress {
+            TutorialProgress::NotStarted if first_login && self.is_enabled() => {
+                Placement::Tutorial {
+                    map: self.data.map,
+                    position: self.data.spawn,
+                }
+            }
+            TutorialProgress::Step(_) if self.is_enabled() => Placement::Tutorial {
+                map: self.data.map,
+                position: self.data.spawn,
+            },
+            TutorialProgress::Step(_) => Placement::Exit {
+                map: self.data.exit_map,
+                position: self.data.exit,
+            },
+            _ => Placement::Saved,
+        }
+    }
+
+    /// Start the tutorial for `player`, or pick up where they left off
+    pub fn begin(
+        &mut self,
+        zone: &mut Zone,
+        player: EntityId,
+        character_id: u32,
+        progress: &mut TutorialProgress,
+    ) -> TutorialUpdate {
+        let mut update = TutorialUpdate::default();
+        let index = match *progress {
+            TutorialProgress::NotStarted => {
+                update.save = true;
+                self.publish(events::Tutorial::Started { character_id });
+                0
+            }
+            TutorialProgress::Step(index) => index.min(self.data.steps.len().saturating_sub(1)),
+            TutorialProgress::Done => return update,
+        };
+        *progress = TutorialProgress::Step(index);
+        update
+            .messages
+            .push(build_nfy_tutorial_start(self.data.steps.len(), index));
+        self.enter_step(zone, player, index, &mut update);
+        update
+    }
+
+    /// Move `player` on if `event` is what their step asks for
+    pub fn record(
+        &mut self,
+        zone: &mut Zone,
+        player: EntityId,
+        character_id: u32,
+        progress: &mut TutorialProgress,
+        event: TutorialEvent,
+    ) -> TutorialUpdate {
+        let mut update = TutorialUpdate::default();
+        let TutorialProgress::Step(index) = *progress else {
+            return update;
+        };
+        let Some(step) = self.data.steps.get(index) else {
+            return update;
+        };
+        if !step.is_done_by(event) {
+            return update;
+        }
+
+        let step = step.id;
+        if let Some(script) = self.scripts.get_mut(&step) {
+            script.on_complete(zone, player);
+        }
+        self.publish(events::Tutorial::StepCompleted { character_id, step });
+        update.save = true;
+        if index + 1 < self.data.steps.len() {
+            *progress = TutorialProgress::Step(index + 1);
+            self.enter_step(zone, player, index + 1, &mut update);
+        } else {
+            self.finish(character_id, progress, false, &mut update);
+        }
+        update
+    }
+
+    /// Take `player` out of the tutorial without finishing it
+    pub fn skip(&mut self, character_id: u32, progress: &mut TutorialProgress) -> TutorialUpdate {
+        let mut update = TutorialUpdate::default();
+        if *progress != TutorialProgress::Done {
+            self.finish(character_id, progress, true, &mut update);
+        }
+        update
+    }
+
+    fn enter_step(
+        &mut self,
+        zone: &mut Zone,
+        player: EntityId,
+        index: usize,
+        update: &mut TutorialUpdate,
+    ) {
+        let step = self.data.steps[index].id;
+        update.messages.push(build_nfy_tutorial_step(index, step));
+        if let Some(script) = self.scripts.get_mut(&step) {
+            script.on_step(zone, player);
+        }
+    }
+
+    fn finish(
+        &self,
+        character_id: u32,
+        progress: &mut TutorialProgress,
+        skipped: bool,
+        update: &mut TutorialUpdate,
+    ) {
+        *progress = TutorialProgress::Done;
+        update.save = true;
+        update.messages.push(build_nfy_tutorial_end(skipped));
+        update.exit = Some((self.data.exit_map, self.data.exit));
+        self.publish(events::Tutorial::Finished {
+            character_id,
+            skipped,
+        });
+    }
+
+    fn publish(&self, event: events::Tutorial) {
+        if let Some(events) = &self.events {
+            events.publish(event);
+        }
+    }
+}
+
+/// Build the tutorial start notification (u16 step count, u16 index of
+/// the current step)
+pub fn build_nfy_tutorial_start(steps: usize, index: usize) -> Vec<u8> {
+    let mut out = WireWriter::message(NFY_TUTORIAL_START);
+    out.u16(steps as u16).u16(index as u16);
+    out.into_bytes()
+}
+
+/// Build the notification of the step a player is on (u16 index, u32 step
+/// ID)
+pub fn build_nfy_tutorial_step(index: usize, step: u32) -> Vec<u8> {
+    let mut out = WireWriter::message(NFY_TUTORIAL_STEP);
+    out.u16(index as u16).u32(step);
+    out.into_bytes()
+}
+
+/// Build the tutorial end notification (u8 1 = skipped)
+pub fn build_nfy_tutorial_end(skipped: bool) -> Vec<u8> {
+    let mut out = WireWriter::message(NFY_TUTORIAL_END);
+    out.bool(skipped);
+    out.into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::EntityKind;
+    use std::sync::{Arc, Mutex};
+
+    fn data() -> TutorialData {
+        TutorialData::from_toml(
+            r#"
+            map = 100
+            spawn = [1.0, 0.0, 1.0]
+            exit_map = 1
+            exit = [120.0, 0.0, 80.0]
+
+            [[steps]]
+            id = 1
+            name = "Walk to the signpost"
+            goal = "reach"
+            at = [10.0, 0.0, 5.0]
+
+            [[steps]]
+            id = 2
+            name = "Defeat the training dummy"
+            goal = "kill"
+            target = 9001
+            "#,
+        )
+        .unwrap()
+    }
+
+    /// Spawns the dummy step 2 asks for, and notes when it's killed
+    struct Dummy(Arc<Mutex<Vec<String>>>);
+
+    impl TutorialScript for Dummy {
+        fn on_step(&mut self, zone: &mut Zone, _player: EntityId) {
+            zone.spawn(EntityKind::Monster, Position::new(12.0, 0.0, 5.0), 10);
+            self.0.lock().unwrap().push("spawned".to_string());
+        }
+
+        fn on_complete(&mut self, _zone: &mut Zone, _player: EntityId) {
+            self.0.lock().unwrap().push("killed".to_string());
+        }
+    }
+
+    #[test]
+    fn test_walkthrough() {
+        let events = EventBus::new();
+        let mut published = events.subscribe::<events::Tutorial>();
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let mut tutorial = Tutorial::new(TutorialConfig::default(), data()).with_events(&events);
+        tutorial.script(2, Dummy(Arc::clone(&log)));
+
+        let mut zone = Zone::new();
+        let player = zone.spawn(EntityKind::Player, Position::new(1.0, 0.0, 1.0), 100);
+        let mut progress = TutorialProgress::NotStarted;
+        assert_eq!(
+            tutorial.placement(progress, true),
+            Placement::Tutorial {
+                map: 100,
+                position: Position::new(1.0, 0.0, 1.0)
+            }
+        );
+        assert_eq!(tutorial.placement(progress, false), Placement::Saved);
+
+        let update = tutorial.begin(&mut zone, player, 7, &mut progress);
+        assert!(update.save);
+        assert_eq!(
+            update.messages,
+            [
+                build_nfy_tutorial_start(2, 0),
+                build_nfy_tutorial_step(0, 1)
+            ]
+        );
+        assert_eq!(progress, TutorialProgress::Step(0));
+
+        // Only the step's own goal moves the player on
+        let kill = TutorialEvent::MonsterKilled { monster_id: 9001 };
+        assert_eq!(
+            tutorial.record(&mut zone, player, 7, &mut progress, kill),
+            TutorialUpdate::default()
+        );
+        let far = TutorialEvent::Moved {
+            position: Position::new(20.0, 0.0, 5.0),
+        };
+        assert!(
+            !tutorial
+                .record(&mut zone, player, 7, &mut progress, far)
+                .save
+        );
+
+        let near = TutorialEvent::Moved {
+            position: Position::new(11.0, 0.0, 6.0),
+        };
+        let update = tutorial.record(&mut zone, player, 7, &mut progress, near);
+        assert_eq!(update.messages, [build_nfy_tutorial_step(1, 2)]);
+        assert_eq!(progress, TutorialProgress::Step(1));
+        assert_eq!(zone.len(), 2);
+
+        let update = tutorial.record(&mut zone, player, 7, &mut progress, kill);
+        assert_eq!(update.messages, [build_nfy_tutorial_end(false)]);
+        assert_eq!(update.exit, Some((1, Position::new(120.0, 0.0, 80.0))));
+        assert_eq!(progress, TutorialProgress::Done);
+        assert_eq!(*log.lock().unwrap(), ["spawned", "killed"]);
+
+        let mut seen = Vec::new();
+        while let Some(event) = published.try_recv() {
+            seen.push(event);
+        }
+        assert_eq!(
+            seen,
+            [
+                events::Tutorial::Started { character_id: 7 },
+                events::Tutorial::StepCompleted {
+                    character_id: 7,
+                    step: 1
+                },
+                events::Tutorial::StepCompleted {
+                    character_id: 7,
+                    step: 2
+                },
+                events::Tutorial::Finished {
+                    character_id: 7,
+                    skipped: false
+                },
+            ]
+        );
+        assert_eq!(tutorial.placement(progress, false), Placement::Saved);
+    }
+
+    #[test]
+    fn test_resume_and_turned_off() {
+        let mut zone = Zone::new();
+        let player = zone.spawn(EntityKind::Player, Position::default(), 100);
+        let mut progress = TutorialProgress::from_row(Some((1, None)));
+        assert_eq!(progress, TutorialProgress::Step(1));
+        assert_eq!(
+            TutorialProgress::from_row(Some((1, Some(100)))),
+            TutorialProgress::Done
+        );
+
+        let mut tutorial = Tutorial::new(TutorialConfig::default(), data());
+        let update = tutorial.begin(&mut zone, player, 7, &mut progress);
+        assert!(!update.save);
+        assert_eq!(update.messages[0], build_nfy_tutorial_start(2, 1));
+
+        // Turned off: new characters start normally, and those in it leave
+        let off = TutorialConfig::from_toml("[tutorial]\nenabled = false").unwrap();
+        let mut tutorial = Tutorial::new(off, data());
+        assert_eq!(
+            tutorial.placement(TutorialProgress::NotStarted, true),
+            Placement::Saved
+        );
+        assert_eq!(
+            tutorial.placement(progress, false),
+            Placement::Exit {
+                map: 1,
+                position: Position::new(120.0, 0.0, 80.0)
+            }
+        );
+        let update = tutorial.skip(7, &mut progress);
+        assert_eq!(update.messages, [build_nfy_tutorial_end(true)]);
+        assert_eq!(progress, TutorialProgress::Done);
+        assert!(!tutorial.skip(7, &mut progress).save);
+    }
+
+    #[test]
+    fn test_data() {
+        assert_eq!(
+            TutorialConfig::load("does/not/exist.toml").unwrap(),
+            TutorialConfig::default()
+        );
+        let empty = TutorialData::load("does/not/exist.toml").unwrap();
+        assert!(!Tutorial::new(TutorialConfig::default(), empty).is_enabled());
+        assert_eq!(data().steps[0].radius, 3.0);
+
+        assert!(
+            TutorialData::from_toml("[[steps]]\nid = 1\nname = \"Go\"\ngoal = \"emote\"").is_err()
+        );
+        assert!(
+            TutorialData::from_toml(
+                "map = 100\nexit_map = 1\n[[steps]]\nid = 1\nname = \"Go\"\ngoal = \"reach\""
+            )
+            .is_err()
+        );
+    }
+}
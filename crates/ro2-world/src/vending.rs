@@ -0,0 +1,987 @@
+//! Player vending stalls
+//!
+//! A player opens a stall ([`REQ_OPEN_STALL`]) with a title and up to
+//! `max_items` stacks from their inventory, each with a price per item.
+//! The items stay in their inventory; the stall only says which slots are
+//! for sale. While it's open the player carries [`STATE_VENDING`], which
+//! reaches clients with the world delta, and the players around them are
+//! told its title ([`NFY_STALL`]), as they are when it closes.
+//!
+//! Others look at what's for sale with [`REQ_BROWSE_STALL`] and buy with
+//! [`REQ_BUY_FROM_STALL`] from within `reach` of the vendor. A purchase is
+//! one [`ItemTransaction`]: it takes the items off the stall's listing and
+//! out of the vendor's slot, gives them to the buyer and moves the zeny,
+//! all or nothing, so two buyers can't both get the last of a stack. The
+//! buyer names the price they saw, and a purchase at another price is
+//! refused. Each purchase carries a request ID, so one the client resends
+//! is only made once. The vendor is told of each sale ([`NFY_STALL_SALE`])
+//! and the stall closes once it's sold out.
+//!
+//! Open stalls are saved in `vending_stalls` (see [`VendingQueries`]) so
+//! the purchase transaction can check them and GMs can see them; a stall
+//! closes when its owner closes it or leaves ([`Stalls::close`]), and the
+//! ones a crash left behind are cleared when the channel starts
+//! ([`clear_stalls`]). Bound items and rentals can't be sold.
+//!
+//! Limits are set in the `[vending]` section of `config/world.toml`:
+//!
+//! ```toml
+//! [vending]
+//! max_items = 12
+//! max_price = 1000000000  # Zeny per item
+//! max_title_length = 40
+//! reach = 500.0           # How near a buyer has to be, in world units
+//! ```
+//!
+//! Like the other `0x3Fxx` opcodes, these are placeholders.
+
+use crate::inventory::{Inventory, ItemStack, MAX_STACK};
+use crate::journal;
+use crate::world::{Broadcaster, EntityId, EntityKind, STATE_MOUNTED, STATE_VENDING, Zone};
+use anyhow::{Result, anyhow};
+use ro2_common::config::{self, Validate};
+use ro2_common::database::queries::VendingQueries;
+use ro2_common::database::{
+    ItemTransaction, Outcome, Refusal, TransactionKind, VendingItem, VendingStall,
+};
+use ro2_common::protocol::{ClientError, ErrorCode};
+use ro2_common::wire::{WireReader, WireWriter};
+use serde::Deserialize;
+use sqlx::{Pool, Sqlite};
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use tracing::info;
+
+pub use ro2_common::protocol::opcodes::{
+    ACK_BUY_FROM_STALL, ANS_BROWSE_STALL, NFY_STALL, NFY_STALL_SALE, REQ_BROWSE_STALL,
+    REQ_BUY_FROM_STALL, REQ_CLOSE_STALL, REQ_OPEN_STALL,
+};
+
+/// Stall limits
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct VendingConfig {
+    /// Stacks one stall sells
+    pub max_items: usize,
+    /// Zeny per item
+    pub max_price: i64,
+    /// Characters in a stall's title
+    pub max_title_length: usize,
+    /// How near a buyer has to be to the vendor
+    pub reach: f32,
+}
+
+impl Default for VendingConfig {
+    fn default() -> Self {
+        Self {
+            max_items: 12,
+            max_price: 1_000_000_000,
+            max_title_length: 40,
+            reach: 500.0,
+        }
+    }
+}
+
+impl VendingConfig {
+    /// Read the `[vending]` section of `path`; missing keys keep their
+    /// defaults
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        config::section(path, "vending")
+    }
+
+    /// Parse the `[vending]` section of TOML text
+    pub fn from_toml(text: &str) -> Result<Self> {
+        config::section_from_toml(text, "vending")
+    }
+}
+
+impl Validate for VendingConfig {
+    fn validate(&self) -> Result<()> {
+        if self.max_items == 0
+            || self.max_items > usize::from(u8::MAX)
+            || self.max_price <= 0
+            || self.max_title_length == 0
+            || self.reach <= 0.0
+        {
+            return Err(anyhow!(
+                "vending max_items must be 1 to 255, and max_price, max_title_length and reach positive"
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// One stack for sale
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StallItem {
+    /// The vendor's slot it's sold from
+    pub inventory_slot: u16,
+    pub item_id: i32,
+    /// Left for sale; 0 once sold out
+    pub quantity: i32,
+    /// Zeny each
+    pub price: i64,
+}
+
+/// An open stall
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Stall {
+    pub character_id: i64,
+    pub title: String,
+    /// By stall slot
+    pub items: Vec<StallItem>,
+}
+
+impl Stall {
+    fn sold_out(&self) -> bool {
+        self.items.iter().all(|item| item.quantity == 0)
+    }
+}
+
+/// A purchase as the client asks for it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BuyRequest {
+    /// Picked by the client; the same ID is only bought once
+    pub request_id: u32,
+    pub vendor: EntityId,
+    pub stall_slot: u16,
+    pub quantity: i32,
+    /// The buyer's slot the items go in
+    pub inventory_slot: u16,
+    /// Zeny each, as the buyer saw it
+    pub price: i64,
+}
+
+/// The player buying
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Buyer {
+    pub entity: EntityId,
+    pub character_id: i64,
+}
+
+/// How a purchase went, as sent in [`ACK_BUY_FROM_STALL`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum BuyResult {
+    Done = 0,
+    /// The stall doesn't have that many left, or is gone
+    SoldOut = 1,
+    /// The stall sells it for something else now
+    PriceChanged = 2,
+    NotEnoughZeny = 3,
+    /// The buyer's slot holds something else, or would overflow
+    SlotTaken = 4,
+    /// The buyer is too far from the stall
+    TooFar = 5,
+}
+
+/// A refusal with the text at `key` in the `[vending]` string table
+fn refuse(code: ErrorCode, key: &str) -> anyhow::Error {
+    ClientError::new(code, key).into()
+}
+
+/// The open stalls of one zone
+#[derive(Debug)]
+pub struct Stalls {
+    config: VendingConfig,
+    channel_id: i64,
+    map_id: i32,
+    open: HashMap<EntityId, Stall>,
+}
+
+impl Stalls {
+    /// Stalls on `map_id` of channel `channel_id`
+    pub fn new(config: VendingConfig, channel_id: u32, map_id: u32) -> Self {
+        Self {
+            config,
+            channel_id: i64::from(channel_id),
+            map_id: map_id as i32,
+            open: HashMap::new(),
+        }
+    }
+
+    pub fn config(&self) -> &VendingConfig {
+        &self.config
+    }
+
+    /// `vendor`'s stall, if they have one open
+    pub fn stall(&self, vendor: EntityId) -> Option<&Stall> {
+        self.open.get(&vendor)
+    }
+
+    pub fn len(&self) -> usize {
+        self.open.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.open.is_empty()
+    }
+
+    /// Open a stall for the player controlling `entity`, selling `items`
+    /// from `inventory`, and tell everyone in view
+    ///
+    /// Refusals are [`ClientError`]s.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn open(
+        &mut self,
+        pool: &Pool<Sqlite>,
+        zone: &mut Zone,
+        broadcaster: &Broadcaster,
+        entity: EntityId,
+        character_id: i64,
+        inventory: &Inventory,
+        title: &str,
+        items: &[StallItem],
+        now: i64,
+    ) -> Result<()> {
+        if self.open.contains_key(&entity) {
+            return Err(refuse(ErrorCode::NotAllowed, "vending.already_vending"));
+        }
+        let length = title.trim().chars().count();
+        if length == 0 || length > self.config.max_title_length {
+            return Err(refuse(ErrorCode::InvalidRequest, "vending.bad_title"));
+        }
+        if items.is_empty() || items.len() > self.config.max_items {
+            return Err(refuse(ErrorCode::InvalidRequest, "vending.too_many_items"));
+        }
+        self.check_items(inventory, items)?;
+
+        let player = zone
+            .get(entity)
+            .ok_or_else(|| anyhow!("no entity {:?}", entity))?;
+        if player.kind != EntityKind::Player || player.hp == 0 {
+            return Err(anyhow!("{:?} can't vend", entity));
+        }
+        if player.state & STATE_MOUNTED != 0 {
+            return Err(refuse(ErrorCode::NotAllowed, "vending.mounted"));
+        }
+
+        let title = title.trim().to_string();
+        let saved = VendingStall {
+            character_id,
+            channel_id: self.channel_id,
+            title: title.clone(),
+            map_id: self.map_id,
+            x: player.position.x,
+            y: player.position.y,
+            z: player.position.z,
+            opened_at: now,
+        };
+        let listed: Vec<_> = items
+            .iter()
+            .enumerate()
+            .map(|(stall_slot, item)| VendingItem {
+                character_id,
+                stall_slot: stall_slot as i32,
+                inventory_slot: i32::from(item.inventory_slot),
+                item_id: item.item_id,
+                quantity: item.quantity,
+                price: item.price,
+            })
+            .collect();
+        VendingQueries::open(pool, &saved, &listed).await?;
+
+        if let Some(player) = zone.get_mut(entity) {
+            *player.state |= STATE_VENDING;
+        }
+        broadcaster.send_near(zone, player.position, &build_nfy_stall(entity, &title));
+        info!(
+            "Character {} opened a stall with {} items: {}",
+            character_id,
+            items.len(),
+            title
+        );
+        self.open.insert(
+            entity,
+            Stall {
+                character_id,
+                title,
+                items: items.to_vec(),
+            },
+        );
+        Ok(())
+    }
+
+    /// Refuse items the vendor doesn't hold or can't sell
+    fn check_items(&self, inventory: &Inventory, items: &[StallItem]) -> Result<()> {
+        let mut slots = HashSet::new();
+        for item in items {
+            if !slots.insert(item.inventory_slot) {
+                return Err(refuse(ErrorCode::InvalidRequest, "vending.slot_twice"));
+            }
+            if item.price <= 0 || item.price > self.config.max_price {
+                return Err(refuse(ErrorCode::InvalidRequest, "vending.bad_price"));
+            }
+            let held = inventory
+                .slots()
+                .get(usize::from(item.inventory_slot))
+                .copied()
+                .flatten()
+                .filter(|stack| stack.item_id == item.item_id);
+            match held {
+                Some(stack) if stack.bound || stack.expires_at.is_some() => {
+                    return Err(refuse(ErrorCode::NotAllowed, "vending.unsellable"));
+                }
+                Some(stack) if item.quantity > 0 && item.quantity <= stack.quantity => {}
+                _ => {
+                    return Err(refuse(ErrorCode::InvalidRequest, "vending.missing_items"));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Close `vendor`'s stall, e.g. when they ask to or leave, and tell
+    /// everyone in view; whether they had one
+    pub async fn close(
+        &mut self,
+        pool: &Pool<Sqlite>,
+        zone: &mut Zone,
+        broadcaster: &Broadcaster,
+        vendor: EntityId,
+    ) -> Result<bool> {
+        let Some(stall) = self.open.remove(&vendor) else {
+            return Ok(false);
+        };
+        VendingQueries::close(pool, stall.character_id).await?;
+        if let Some(player) = zone.get_mut(vendor) {
+            *player.state &= !STATE_VENDING;
+            let position = *player.position;
+            broadcaster.send_near(zone, position, &build_nfy_stall(vendor, ""));
+        }
+        info!("Character {} closed their stall", stall.character_id);
+        Ok(true)
+    }
+
+    /// Buy from a stall for `buyer`, whose inventory is `inventory`, out of
+    /// the vendor's `vendor_inventory`; the inventory slots changed, the
+    /// buyer's then the vendor's, are returned for journaling
+    ///
+    /// The vendor is told of the sale, and the stall closes once it's sold
+    /// out.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn buy(
+        &mut self,
+        pool: &Pool<Sqlite>,
+        zone: &mut Zone,
+        broadcaster: &Broadcaster,
+        buyer: Buyer,
+        inventory: &mut Inventory,
+        vendor_inventory: &mut Inventory,
+        request: &BuyRequest,
+        now: i64,
+    ) -> Result<(BuyResult, Vec<usize>, Vec<usize>)> {
+        if request.quantity <= 0 || request.quantity > MAX_STACK {
+            return Err(refuse(ErrorCode::InvalidRequest, "vending.bad_quantity"));
+        }
+        if buyer.entity == request.vendor {
+            return Err(refuse(ErrorCode::NotAllowed, "vending.own_stall"));
+        }
+        let Some(stall) = self.open.get(&request.vendor) else {
+            return Ok((BuyResult::SoldOut, vec![], vec![]));
+        };
+        let Some(&listed) = stall.items.get(usize::from(request.stall_slot)) else {
+            return Err(anyhow!("no stall slot {}", request.stall_slot));
+        };
+        let vendor_id = stall.character_id;
+        let total = listed
+            .price
+            .checked_mul(i64::from(request.quantity))
+            .ok_or_else(|| anyhow!("purchase total overflows"))?;
+
+        let transaction = ItemTransaction::new(
+            TransactionKind::Vending,
+            format!("vending:{}:{}", buyer.character_id, request.request_id),
+        )
+        .take_stall_item(
+            vendor_id,
+            i32::from(request.stall_slot),
+            listed.item_id,
+            request.quantity,
+            request.price,
+        )
+        .take_item(
+            vendor_id,
+            i32::from(listed.inventory_slot),
+            listed.item_id,
+            request.quantity,
+        )
+        .give_item(
+            buyer.character_id,
+            i32::from(request.inventory_slot),
+            listed.item_id,
+            request.quantity,
+        )
+        .take_zeny(buyer.character_id, total)
+        .give_zeny(vendor_id, total);
+        if transaction.is_done(pool).await? {
+            // Already bought when the client first sent it
+            return Ok((BuyResult::Done, vec![], vec![]));
+        }
+        // Checked here first so the common refusals don't need the database
+        if request.price != listed.price {
+            return Ok((BuyResult::PriceChanged, vec![], vec![]));
+        }
+        if listed.quantity < request.quantity {
+            return Ok((BuyResult::SoldOut, vec![], vec![]));
+        }
+        let near = match (zone.get(buyer.entity), zone.get(request.vendor)) {
+            (Some(buyer), Some(vendor)) => {
+                buyer.position.distance_squared(&vendor.position)
+                    <= self.config.reach * self.config.reach
+            }
+            _ => false,
+        };
+        if !near {
+            return Ok((BuyResult::TooFar, vec![], vec![]));
+        }
+
+        let slot = usize::from(request.inventory_slot);
+        let held = *inventory
+            .slots()
+            .get(slot)
+            .ok_or_else(|| anyhow!("no inventory slot {}", slot))?;
+        let after = match held {
+            None => ItemStack::new(listed.item_id, request.quantity),
+            Some(stack)
+                if stack.item_id == listed.item_id
+                    && !stack.bound
+                    && stack.expires_at.is_none()
+                    && stack.quantity + request.quantity <= MAX_STACK =>
+            {
+                ItemStack {
+                    quantity: stack.quantity + request.quantity,
+                    ..stack
+                }
+            }
+            Some(_) => return Ok((BuyResult::SlotTaken, vec![], vec![])),
+        };
+        let vendor_slot = usize::from(listed.inventory_slot);
+        let vendor_after = match vendor_inventory.slots().get(vendor_slot).copied().flatten() {
+            Some(stack)
+                if stack.item_id == listed.item_id && stack.quantity >= request.quantity =>
+            {
+                ItemStack {
+                    quantity: stack.quantity - request.quantity,
+                    ..stack
+                }
+            }
+            // Used or moved since the stall opened
+            _ => return Ok((BuyResult::SoldOut, vec![], vec![])),
+        };
+
+        // The transaction works on what's saved, which may be behind
+        journal::replay(
+            pool,
+            &inventory.journal_entries(buyer.character_id, &[slot]),
+        )
+        .await?;
+        journal::replay(
+            pool,
+            &vendor_inventory.journal_entries(vendor_id, &[vendor_slot]),
+        )
+        .await?;
+        let result = match transaction.apply(pool, now).await? {
+            Outcome::Done => {
+                inventory.set_slot(slot, Some(after))?;
+                vendor_inventory.set_slot(vendor_slot, Some(vendor_after))?;
+                BuyResult::Done
+            }
+            Outcome::AlreadyDone => return Ok((BuyResult::Done, vec![], vec![])),
+            Outcome::Refused(Refusal::NotEnoughZeny { .. }) => BuyResult::NotEnoughZeny,
+            Outcome::Refused(Refusal::SlotTaken { .. }) => BuyResult::SlotTaken,
+            Outcome::Refused(_) => BuyResult::SoldOut,
+        };
+        if result != BuyResult::Done {
+            return Ok((result, vec![], vec![]));
+        }
+
+        let left = {
+            let stall = self.open.get_mut(&request.vendor).expect("checked above");
+            let item = &mut stall.items[usize::from(request.stall_slot)];
+            item.quantity -= request.quantity;
+            item.quantity
+        };
+        info!(
+            "Character {} bought {} of item {} for {} zeny from character {}'s stall",
+            buyer.character_id, request.quantity, listed.item_id, total, vendor_id
+        );
+        broadcaster.send_to(
+            request.vendor,
+            build_nfy_stall_sale(request.stall_slot, request.quantity, total, left),
+        );
+        if self.open[&request.vendor].sold_out() {
+            self.close(pool, zone, broadcaster, request.vendor).await?;
+        }
+        Ok((BuyResult::Done, vec![slot], vec![vendor_slot]))
+    }
+
+    /// Handle [`REQ_OPEN_STALL`] from the player controlling `entity`
+    #[allow(clippy::too_many_arguments)]
+    pub async fn handle_req_open_stall(
+        &mut self,
+        pool: &Pool<Sqlite>,
+        zone: &mut Zone,
+        broadcaster: &Broadcaster,
+        entity: EntityId,
+        character_id: i64,
+        inventory: &Inventory,
+        message: &[u8],
+        now: i64,
+    ) -> Result<()> {
+        let (title, items) = parse_req_open_stall(message)?;
+        self.open(
+            pool,
+            zone,
+            broadcaster,
+            entity,
+            character_id,
+            inventory,
+            &title,
+            &items,
+            now,
+        )
+        .await
+    }
+
+    /// Handle [`REQ_CLOSE_STALL`] from the player controlling `entity`
+    pub async fn handle_req_close_stall(
+        &mut self,
+        pool: &Pool<Sqlite>,
+        zone: &mut Zone,
+        broadcaster: &Broadcaster,
+        entity: EntityId,
+        message: &[u8],
+    ) -> Result<()> {
+        WireReader::message(message, REQ_CLOSE_STALL, "stall close request")?.finish()?;
+        if !self.close(pool, zone, broadcaster, entity).await? {
+            return Err(refuse(ErrorCode::NotAllowed, "vending.not_vending"));
+        }
+        Ok(())
+    }
+
+    /// Handle [`REQ_BROWSE_STALL`]: the [`ANS_BROWSE_STALL`] to send back
+    pub fn handle_req_browse_stall(&self, message: &[u8]) -> Result<Vec<u8>> {
+        let vendor = parse_req_browse_stall(message)?;
+        let stall = self
+            .open
+            .get(&vendor)
+            .ok_or_else(|| refuse(ErrorCode::NotFound, "vending.closed"))?;
+        Ok(build_ans_browse_stall(vendor, stall))
+    }
+
+    /// Handle [`REQ_BUY_FROM_STALL`] from `buyer`: the
+    /// [`ACK_BUY_FROM_STALL`] to send back, and the buyer's and vendor's
+    /// inventory slots to journal
+    #[allow(clippy::too_many_arguments)]
+    pub async fn handle_req_buy_from_stall(
+        &mut self,
+        pool: &Pool<Sqlite>,
+        zone: &mut Zone,
+        broadcaster: &Broadcaster,
+        buyer: Buyer,
+        inventory: &mut Inventory,
+        vendor_inventory: &mut Inventory,
+        message: &[u8],
+        now: i64,
+    ) -> Result<(Vec<u8>, Vec<usize>, Vec<usize>)> {
+        let request = parse_req_buy_from_stall(message)?;
+        let (result, slots, vendor_slots) = self
+            .buy(
+                pool,
+                zone,
+                broadcaster,
+                buyer,
+                inventory,
+                vendor_inventory,
+                &request,
+                now,
+            )
+            .await?;
+        Ok((
+            build_ack_buy_from_stall(request.request_id, result),
+            slots,
+            vendor_slots,
+        ))
+    }
+}
+
+/// Clear the stalls channel `channel_id` left open when it last stopped;
+/// returns how many there were
+pub async fn clear_stalls(pool: &Pool<Sqlite>, channel_id: u32) -> Result<u64> {
+    VendingQueries::close_channel(pool, i64::from(channel_id)).await
+}
+
+/// Parse a stall opening: the title as a u16 length and UTF-16 text, u8
+/// item count, then each item's u16 inventory slot, i32 item ID, i32
+/// quantity and i64 price each
+pub fn parse_req_open_stall(message: &[u8]) -> Result<(String, Vec<StallItem>)> {
+    let mut message = WireReader::message(message, REQ_OPEN_STALL, "stall open request")?;
+    let title = message.utf16("title")?;
+    let count = message.u8("item count")?;
+    let mut items = Vec::with_capacity(usize::from(count));
+    for _ in 0..count {
+        items.push(StallItem {
+            inventory_slot: message.u16("inventory_slot")?,
+            item_id: message.i32("item_id")?,
+            quantity: message.i32("quantity")?,
+            price: message.i64("price")?,
+        });
+    }
+    message.finish()?;
+    Ok((title, items))
+}
+
+/// Parse a browse request: the u32 entity ID of the vendor
+pub fn parse_req_browse_stall(message: &[u8]) -> Result<EntityId> {
+    let mut message = WireReader::message(message, REQ_BROWSE_STALL, "stall browse request")?;
+    Ok(EntityId(message.u32("vendor")?))
+}
+
+/// Parse a purchase: u32 request ID, u32 vendor entity ID, u16 stall slot,
+/// i32 quantity, u16 inventory slot, i64 price each
+pub fn parse_req_buy_from_stall(message: &[u8]) -> Result<BuyRequest> {
+    let mut message = WireReader::message(message, REQ_BUY_FROM_STALL, "stall purchase request")?;
+    Ok(BuyRequest {
+        request_id: message.u32("request_id")?,
+        vendor: EntityId(message.u32("vendor")?),
+        stall_slot: message.u16("stall_slot")?,
+        quantity: message.i32("quantity")?,
+        inventory_slot: message.u16("inventory_slot")?,
+        price: message.i64("price")?,
+    })
+}
+
+/// Build [`NFY_STALL`]: the vendor's u32 entity ID and the stall's title as
+/// a u16 length and UTF-16 text, empty once it's closed
+pub fn build_nfy_stall(vendor: EntityId, title: &str) -> Vec<u8> {
+    let mut out = WireWriter::message(NFY_STALL);
+    out.u32(vendor.0).utf16(title);
+    out.into_bytes()
+}
+
+/// Build [`ANS_BROWSE_STALL`]: the vendor's u32 entity ID, the title, u8
+/// count of items left, then each one's u16 stall slot, i32 item ID, i32
+/// quantity and i64 price each
+pub fn build_ans_browse_stall(vendor: EntityId, stall: &Stall) -> Vec<u8> {
+    let mut out = WireWriter::message(ANS_BROWSE_STALL);
+    let left: Vec<_> = stall
+        .items
+        .iter()
+        .enumerate()
+        .filter(|(_, item)| item.quantity > 0)
+        .collect();
+    out.u32(vendor.0).utf16(&stall.title).u8(left.len() as u8);
+    for (stall_slot, item) in left {
+        out.u16(stall_slot as u16)
+            .i32(item.item_id)
+            .i32(item.quantity)
+            .i64(item.price);
+    }
+    out.into_bytes()
+}
+
+/// Build [`ACK_BUY_FROM_STALL`]: the request ID and a [`BuyResult`]
+pub fn build_ack_buy_from_stall(request_id: u32, result: BuyResult) -> Vec<u8> {
+    let mut out = WireWriter::message(ACK_BUY_FROM_STALL);
+    out.u32(request_id).u8(result as u8);
+    out.into_bytes()
+}
+
+/// Build [`NFY_STALL_SALE`]: u16 stall slot, i32 quantity sold, i64 zeny
+/// earned and i32 quantity left
+pub fn build_nfy_stall_sale(stall_slot: u16, quantity: i32, zeny: i64, left: i32) -> Vec<u8> {
+    let mut out = WireWriter::message(NFY_STALL_SALE);
+    out.u16(stall_slot).i32(quantity).i64(zeny).i32(left);
+    out.into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::{DEFAULT_VIEW_DISTANCE, Position};
+    use ro2_common::localization::Localization;
+    use ro2_common::testing;
+    use tokio::sync::mpsc;
+
+    const POTION: i32 = 501;
+
+    /// Alice (1) vends, Bob (2) buys with 1000 zeny
+    async fn pool() -> Pool<Sqlite> {
+        let pool = testing::database().await;
+        testing::character(&pool, 1, "Alice", 0).await;
+        testing::character(&pool, 2, "Bob", 1000).await;
+        pool
+    }
+
+    async fn zeny(pool: &Pool<Sqlite>, character_id: i64) -> i64 {
+        sqlx::query_as::<_, (i64,)>("SELECT gold FROM characters WHERE id = ?")
+            .bind(character_id)
+            .fetch_one(pool)
+            .await
+            .unwrap()
+            .0
+    }
+
+    /// A zone with the vendor and the buyer next to each other, each
+    /// client subscribed
+    fn zone() -> (
+        Zone,
+        Broadcaster,
+        [EntityId; 2],
+        [mpsc::Receiver<Vec<u8>>; 2],
+    ) {
+        let mut zone = Zone::new();
+        let mut broadcaster = Broadcaster::new(DEFAULT_VIEW_DISTANCE);
+        let vendor = zone.spawn(EntityKind::Player, Position::default(), 100);
+        let buyer = zone.spawn(EntityKind::Player, Position::new(100.0, 0.0, 0.0), 100);
+        let (vendor_tx, vendor_rx) = mpsc::channel(16);
+        let (buyer_tx, buyer_rx) = mpsc::channel(16);
+        broadcaster.subscribe(1, vendor, vendor_tx);
+        broadcaster.subscribe(2, buyer, buyer_tx);
+        (zone, broadcaster, [vendor, buyer], [vendor_rx, buyer_rx])
+    }
+
+    fn potions(quantity: i32, price: i64) -> StallItem {
+        StallItem {
+            inventory_slot: 0,
+            item_id: POTION,
+            quantity,
+            price,
+        }
+    }
+
+    fn vendor_inventory() -> Inventory {
+        let mut inventory = Inventory::new(4);
+        inventory
+            .set_slot(0, Some(ItemStack::new(POTION, 10)))
+            .unwrap();
+        inventory
+            .set_slot(1, Some(ItemStack::new(1101, 1).bound()))
+            .unwrap();
+        inventory
+    }
+
+    fn purchase(request_id: u32, vendor: EntityId, quantity: i32, price: i64) -> BuyRequest {
+        BuyRequest {
+            request_id,
+            vendor,
+            stall_slot: 0,
+            quantity,
+            inventory_slot: 0,
+            price,
+        }
+    }
+
+    #[test]
+    fn test_config() {
+        assert_eq!(
+            VendingConfig::load("does/not/exist.toml").unwrap(),
+            VendingConfig::default()
+        );
+        let config = VendingConfig::from_toml("[vending]\nmax_items = 5").unwrap();
+        assert_eq!((config.max_items, config.max_title_length), (5, 40));
+        assert!(VendingConfig::from_toml("[vending]\nmax_items = 0").is_err());
+        assert!(VendingConfig::from_toml("[vending]\nmax_price = 0").is_err());
+    }
+
+    #[test]
+    fn test_parse_req_open_stall() {
+        let mut out = WireWriter::message(REQ_OPEN_STALL);
+        out.utf16("Potions").u8(1).u16(0).i32(POTION).i32(5).i64(50);
+        let message = out.into_bytes();
+        assert_eq!(
+            parse_req_open_stall(&message).unwrap(),
+            ("Potions".to_string(), vec![potions(5, 50)])
+        );
+        assert!(parse_req_open_stall(&message[..message.len() - 1]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_open_and_close() {
+        let pool = pool().await;
+        let (mut zone, broadcaster, [vendor, _], [_, mut buyer_rx]) = zone();
+        let mut stalls = Stalls::new(VendingConfig::default(), 1, 1);
+        let inventory = vendor_inventory();
+
+        let bound = StallItem {
+            inventory_slot: 1,
+            item_id: 1101,
+            quantity: 1,
+            price: 10,
+        };
+        for (title, items) in [
+            ("", vec![potions(5, 50)]),
+            ("Potions", vec![]),
+            ("Potions", vec![potions(11, 50)]),
+            ("Potions", vec![potions(5, 0)]),
+            ("Potions", vec![potions(2, 50), potions(3, 50)]),
+            ("Potions", vec![bound]),
+        ] {
+            let error = stalls
+                .open(
+                    &pool,
+                    &mut zone,
+                    &broadcaster,
+                    vendor,
+                    1,
+                    &inventory,
+                    title,
+                    &items,
+                    100,
+                )
+                .await
+                .unwrap_err();
+            let refusal = ClientError::find(&error).unwrap_or_else(|| panic!("{}", error));
+            assert_ne!(Localization::builtin().get("en", &refusal.key), refusal.key);
+        }
+        assert!(stalls.is_empty());
+
+        stalls
+            .open(
+                &pool,
+                &mut zone,
+                &broadcaster,
+                vendor,
+                1,
+                &inventory,
+                " Potions ",
+                &[potions(5, 50)],
+                100,
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            zone.get(vendor).unwrap().state & STATE_VENDING,
+            STATE_VENDING
+        );
+        assert_eq!(
+            buyer_rx.try_recv().unwrap(),
+            build_nfy_stall(vendor, "Potions")
+        );
+        assert_eq!(VendingQueries::items(&pool, 1).await.unwrap().len(), 1);
+        let browse = {
+            let mut out = WireWriter::message(REQ_BROWSE_STALL);
+            out.u32(vendor.0);
+            out.into_bytes()
+        };
+        assert_eq!(
+            stalls.handle_req_browse_stall(&browse).unwrap(),
+            build_ans_browse_stall(vendor, stalls.stall(vendor).unwrap())
+        );
+
+        assert!(
+            stalls
+                .close(&pool, &mut zone, &broadcaster, vendor)
+                .await
+                .unwrap()
+        );
+        assert_eq!(zone.get(vendor).unwrap().state, 0);
+        assert_eq!(buyer_rx.try_recv().unwrap(), build_nfy_stall(vendor, ""));
+        assert!(VendingQueries::stalls(&pool).await.unwrap().is_empty());
+        assert!(stalls.handle_req_browse_stall(&browse).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_buy() {
+        let pool = pool().await;
+        let (mut zone, broadcaster, [vendor, buyer], [mut vendor_rx, _]) = zone();
+        let mut stalls = Stalls::new(VendingConfig::default(), 1, 1);
+        let mut vendor_inventory = vendor_inventory();
+        let mut inventory = Inventory::new(4);
+        let bob = Buyer {
+            entity: buyer,
+            character_id: 2,
+        };
+        stalls
+            .open(
+                &pool,
+                &mut zone,
+                &broadcaster,
+                vendor,
+                1,
+                &vendor_inventory,
+                "Potions",
+                &[potions(5, 50)],
+                100,
+            )
+            .await
+            .unwrap();
+        while vendor_rx.try_recv().is_ok() {}
+
+        let mut buy = async |request: BuyRequest| {
+            stalls
+                .buy(
+                    &pool,
+                    &mut zone,
+                    &broadcaster,
+                    bob,
+                    &mut inventory,
+                    &mut vendor_inventory,
+                    &request,
+                    100,
+                )
+                .await
+                .unwrap()
+                .0
+        };
+        assert_eq!(
+            buy(purchase(1, vendor, 3, 40)).await,
+            BuyResult::PriceChanged
+        );
+        assert_eq!(buy(purchase(2, vendor, 6, 50)).await, BuyResult::SoldOut);
+        assert_eq!(buy(purchase(3, vendor, 3, 50)).await, BuyResult::Done);
+        // Resent: bought once
+        assert_eq!(buy(purchase(3, vendor, 3, 50)).await, BuyResult::Done);
+
+        assert_eq!(inventory.count(POTION), 3);
+        assert_eq!(vendor_inventory.count(POTION), 7);
+        assert_eq!((zeny(&pool, 1).await, zeny(&pool, 2).await), (150, 850));
+        assert_eq!(stalls.stall(vendor).unwrap().items[0].quantity, 2);
+        assert_eq!(
+            vendor_rx.try_recv().unwrap(),
+            build_nfy_stall_sale(0, 3, 150, 2)
+        );
+        assert!(vendor_rx.try_recv().is_err());
+
+        // Too far away
+        zone.get_mut(buyer).unwrap().position.x = 1000.0;
+        let far = stalls
+            .buy(
+                &pool,
+                &mut zone,
+                &broadcaster,
+                bob,
+                &mut inventory,
+                &mut vendor_inventory,
+                &purchase(4, vendor, 2, 50),
+                100,
+            )
+            .await
+            .unwrap();
+        assert_eq!(far.0, BuyResult::TooFar);
+
+        // The last of it closes the stall
+        zone.get_mut(buyer).unwrap().position.x = 100.0;
+        let (result, slots, vendor_slots) = stalls
+            .buy(
+                &pool,
+                &mut zone,
+                &broadcaster,
+                bob,
+                &mut inventory,
+                &mut vendor_inventory,
+                &purchase(5, vendor, 2, 50),
+                100,
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            (result, slots, vendor_slots),
+            (BuyResult::Done, vec![0], vec![0])
+        );
+        assert!(stalls.stall(vendor).is_none());
+        assert_eq!(zone.get(vendor).unwrap().state & STATE_VENDING, 0);
+        assert!(VendingQueries::stalls(&pool).await.unwrap().is_empty());
+    }
+}
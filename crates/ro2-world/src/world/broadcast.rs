@@ -0,0 +1,376 @@
+//! Sending each tick's snapshot to the clients in a zone
+//!
+//! Entities flagged [`STATE_INVISIBLE`] are only sent to GM clients; to
+//! everyone else they leave view as the flag is set. A GM client can also
+//! spectate a player: it then sees what that player sees and gets copies
+//! of the messages sent to them alone.
+
+use super::{ClientView, EntityId, MovementSync, Position, STATE_INVISIBLE, Snapshot, Zone};
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tracing::debug;
+
+/// Default view distance, in world units
+pub const DEFAULT_VIEW_DISTANCE: f32 = 2000.0;
+
+/// Counts from one [`Broadcaster::broadcast`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BroadcastStats {
+    /// Messages queued, at most one per client
+    pub messages: usize,
+    /// Entity entries across those messages
+    pub entities: usize,
+    /// Clients whose outbox was full; they get a larger delta next tick
+    pub skipped: usize,
+}
+
+struct Subscriber {
+    /// The client's own entity; it sees what's around it
+    entity: EntityId,
+    /// The player being spectated, seen through instead of `entity`
+    watching: Option<EntityId>,
+    /// Sees invisible entities
+    gm: bool,
+    view: ClientView,
+    outbox: mpsc::Sender<Vec<u8>>,
+}
+
+/// Sends every client one aggregated delta per tick
+pub struct Broadcaster {
+    view_distance: f32,
+    /// Send movement every this many ticks
+    ticks_per_movement: u32,
+    /// Movement sync settings for new subscribers
+    move_sync: Option<Vec<u8>>,
+    subscribers: HashMap<u64, Subscriber>,
+}
+
+impl Broadcaster {
+    pub fn new(view_distance: f32) -> Self {
+        Self {
+            view_distance,
+            ticks_per_movement: 1,
+            move_sync: None,
+            subscribers: HashMap::new(),
+        }
+    }
+
+    /// Send movement at the rate `sync` asks for, given the zone ticks
+    /// every `tick_interval`, and tell each new subscriber the settings
+    pub fn with_movement_sync(mut self, sync: &MovementSync, tick_interval: Duration) -> Self {
+        self.ticks_per_movement = sync.ticks_per_update(tick_interval);
+        self.move_sync = Some(sync.encode());
+        self
+    }
+
+    /// Start sending session `session_id`, which controls `entity`, what
+    /// its entity can see. Messages go to the connection's outbox.
+    pub fn subscribe(&mut self, session_id: u64, entity: EntityId, outbox: mpsc::Sender<Vec<u8>>) {
+        if let Some(move_sync) = &self.move_sync {
+            let _ = outbox.try_send(move_sync.clone());
+        }
+        self.subscribers.insert(
+            session_id,
+            Subscriber {
+                entity,
+                watching: None,
+                gm: false,
+                view: ClientView::new(),
+                outbox,
+            },
+        );
+    }
+
+    pub fn unsubscribe(&mut self, session_id: u64) {
+        self.subscribers.remove(&session_id);
+    }
+
+    /// Let session `session_id` see invisible entities, or stop it
+    pub fn set_gm(&mut self, session_id: u64, gm: bool) {
+        if let Some(subscriber) = self.subscribers.get_mut(&session_id) {
+            subscriber.gm = gm;
+            if !gm {
+                subscriber.watching = None;
+            }
+        }
+    }
+
+    /// Show GM session `session_id` what `target` sees, or its own view
+    /// again with `None`; whether it's spectating now
+    pub fn spectate(&mut self, session_id: u64, target: Option<EntityId>) -> bool {
+        match self.subscribers.get_mut(&session_id) {
+            Some(subscriber) if subscriber.gm || target.is_none() => {
+                subscriber.watching = target;
+                target.is_some()
+            }
+            _ => false,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.subscribers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.subscribers.is_empty()
+    }
+
+    /// Queue each client's delta for `snapshot`
+    ///
+    /// Never waits: a client whose outbox is full is skipped this tick and
+    /// its view is left as it was, so the next delta covers both ticks.
+    /// Clients whose connection has closed are dropped.
+    pub fn broadcast(&mut self, snapshot: &Snapshot) -> BroadcastStats {
+        let max_distance = self.view_distance * self.view_distance;
+        let hold_movement = !snapshot.tick.is_multiple_of(self.ticks_per_movement);
+        let mut stats = BroadcastStats::default();
+
+        self.subscribers.retain(|session_id, subscriber| {
+            // Not spawned (yet), nothing to see from
+            let eyes = subscriber.watching.unwrap_or(subscriber.entity);
+            let Some(viewer) = snapshot.get(eyes) else {
+                return true;
+            };
+            let mut delta = subscriber.view.diff(snapshot, |e| {
+                e.id != subscriber.entity
+                    && (subscriber.gm || e.state & STATE_INVISIBLE == 0)
+                    && e.position.distance_squared(&viewer.position) <= max_distance
+            });
+            if hold_movement {
+                delta.hold_movement();
+            }
+            if delta.is_empty() {
+                return true;
+            }
+
+            match subscriber.outbox.try_send(delta.encode()) {
+                Ok(()) => {
+                    subscriber.view.commit(&delta);
+                    stats.messages += 1;
+                    stats.entities += delta.len();
+                    true
+                }
+                Err(TrySendError::Full(_)) => {
+                    stats.skipped += 1;
+                    true
+                }
+                Err(TrySendError::Closed(_)) => {
+                    debug!("Session {} closed, unsubscribing", session_id);
+                    false
+                }
+            }
+        });
+
+        stats
+    }
+
+    /// Queue `message` for every client whose entity is within view
+    /// distance of `around`, for events the snapshot doesn't carry;
+    /// returns how many it was queued for
+    pub fn send_near(&self, zone: &Zone, around: Position, message: &[u8]) -> usize {
+        let max_distance = self.view_distance * self.view_distance;
+        self.subscribers
+            .values()
+            .filter(|subscriber| {
+                zone.get(subscriber.entity)
+                    .is_some_and(|viewer| viewer.position.distance_squared(&around) <= max_distance)
+            })
+            .filter(|subscriber| subscriber.outbox.try_send(message.to_vec()).is_ok())
+            .count()
+    }
+
+    /// Queue `message` for the client controlling `entity`, for what only
+    /// its own client is told, and for GMs spectating it; whether it was
+    /// queued for the client controlling it
+    pub fn send_to(&self, entity: EntityId, message: Vec<u8>) -> bool {
+        let mut sent = false;
+        for subscriber in self.subscribers.values() {
+            if subscriber.entity == entity {
+                sent |= subscriber.outbox.try_send(message.clone()).is_ok();
+            } else if subscriber.watching == Some(entity) {
+                let _ = subscriber.outbox.try_send(message.clone());
+            }
+        }
+        sent
+    }
+}
+
+impl Default for Broadcaster {
+    fn default() -> Self {
+        Self::new(DEFAULT_VIEW_DISTANCE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::{Delta, EntityKind, Position, Zone};
+
+    #[test]
+    fn test_one_message_per_client_per_tick() {
+        let mut zone = Zone::new();
+        let mut broadcaster = Broadcaster::new(100.0);
+
+        let mut inboxes = Vec::new();
+        for i in 0..3 {
+            let player = zone.spawn(EntityKind::Player, Position::new(i as f32, 0.0, 0.0), 100);
+            let (tx, rx) = mpsc::channel(8);
+            broadcaster.subscribe(i, player, tx);
+            inboxes.push(rx);
+        }
+        let monsters: Vec<_> = (0..50)
+            .map(|i| zone.spawn(EntityKind::Monster, Position::new(i as f32, 5.0, 0.0), 10))
+            .collect();
+        zone.spawn(EntityKind::Monster, Position::new(1000.0, 0.0, 0.0), 10);
+
+        // 50 monsters and 2 other players each, in a single message
+        let stats = broadcaster.broadcast(&zone.snapshot());
+        assert_eq!(stats.messages, 3);
+        assert_eq!(stats.entities, 3 * 52);
+        for inbox in &mut inboxes {
+            let delta = Delta::decode(&inbox.try_recv().unwrap()).unwrap();
+            assert_eq!(delta.entered.len(), 52);
+            assert!(inbox.try_recv().is_err());
+        }
+
+        // Only the changed monsters are sent next tick
+        for id in &monsters[..5] {
+            zone.get_mut(*id).unwrap().position.y = 6.0;
+        }
+        let stats = broadcaster.broadcast(&zone.snapshot());
+        assert_eq!(
+            stats,
+            BroadcastStats {
+                messages: 3,
+                entities: 15,
+                skipped: 0
+            }
+        );
+
+        // A quiet tick sends nothing
+        assert_eq!(broadcaster.broadcast(&zone.snapshot()).messages, 0);
+    }
+
+    #[test]
+    fn test_movement_sent_at_update_rate() {
+        let mut zone = Zone::new();
+        let sync = MovementSync {
+            update_hz: 5,
+            ..MovementSync::default()
+        };
+        let mut broadcaster =
+            Broadcaster::default().with_movement_sync(&sync, Duration::from_millis(100));
+        let player = zone.spawn(EntityKind::Player, Position::default(), 100);
+        let monster = zone.spawn(EntityKind::Monster, Position::default(), 10);
+
+        let (tx, mut rx) = mpsc::channel(8);
+        broadcaster.subscribe(1, player, tx);
+        assert_eq!(rx.try_recv().unwrap(), sync.encode());
+        broadcaster.broadcast(&zone.snapshot());
+        rx.try_recv().unwrap();
+
+        // Tick 2 sends movement; tick 3 holds it but not the HP change
+        zone.get_mut(monster).unwrap().position.x = 1.0;
+        broadcaster.broadcast(&zone.snapshot());
+        let delta = Delta::decode(&rx.try_recv().unwrap()).unwrap();
+        assert_eq!(
+            delta.updated[0].position,
+            Some(Position::new(1.0, 0.0, 0.0))
+        );
+
+        zone.get_mut(monster).unwrap().position.x = 2.0;
+        *zone.get_mut(monster).unwrap().hp = 5;
+        broadcaster.broadcast(&zone.snapshot());
+        let delta = Delta::decode(&rx.try_recv().unwrap()).unwrap();
+        assert_eq!(delta.updated[0].position, None);
+        assert_eq!(delta.updated[0].hp, Some(5));
+
+        // The held position goes out on tick 4
+        broadcaster.broadcast(&zone.snapshot());
+        let delta = Delta::decode(&rx.try_recv().unwrap()).unwrap();
+        assert_eq!(
+            delta.updated[0].position,
+            Some(Position::new(2.0, 0.0, 0.0))
+        );
+        assert_eq!(delta.updated[0].hp, None);
+    }
+
+    #[test]
+    fn test_invisible_and_spectating() {
+        let mut zone = Zone::new();
+        let mut broadcaster = Broadcaster::new(100.0);
+        let gm = zone.spawn(EntityKind::Player, Position::default(), 100);
+        let player = zone.spawn(EntityKind::Player, Position::new(10.0, 0.0, 0.0), 100);
+        let other_gm = zone.spawn(EntityKind::Player, Position::new(20.0, 0.0, 0.0), 100);
+        let far = zone.spawn(EntityKind::Monster, Position::new(300.0, 0.0, 0.0), 10);
+        let mut inboxes = Vec::new();
+        for (session, entity) in [(1, gm), (2, player), (3, other_gm)] {
+            let (tx, rx) = mpsc::channel(8);
+            broadcaster.subscribe(session, entity, tx);
+            inboxes.push(rx);
+        }
+        broadcaster.set_gm(1, true);
+        broadcaster.set_gm(3, true);
+        broadcaster.broadcast(&zone.snapshot());
+        for inbox in &mut inboxes {
+            inbox.try_recv().unwrap();
+        }
+
+        // Going invisible leaves the player's view but not the other GM's
+        *zone.get_mut(gm).unwrap().state |= STATE_INVISIBLE;
+        broadcaster.broadcast(&zone.snapshot());
+        let delta = Delta::decode(&inboxes[1].try_recv().unwrap()).unwrap();
+        assert_eq!(delta.left, [gm]);
+        let delta = Delta::decode(&inboxes[2].try_recv().unwrap()).unwrap();
+        assert_eq!(delta.updated[0].state, Some(STATE_INVISIBLE));
+
+        // Spectating, the GM sees from the player and gets their messages
+        assert!(!broadcaster.spectate(2, Some(gm)));
+        assert!(broadcaster.spectate(1, Some(player)));
+        zone.get_mut(player).unwrap().position.x = 250.0;
+        broadcaster.broadcast(&zone.snapshot());
+        let delta = Delta::decode(&inboxes[0].try_recv().unwrap()).unwrap();
+        assert_eq!(delta.entered[0].id, far);
+        inboxes[1].try_recv().unwrap();
+        inboxes[2].try_recv().unwrap();
+        assert!(broadcaster.send_to(player, vec![1, 2, 3]));
+        assert_eq!(inboxes[1].try_recv().unwrap(), [1, 2, 3]);
+        assert_eq!(inboxes[0].try_recv().unwrap(), [1, 2, 3]);
+
+        assert!(!broadcaster.spectate(1, None));
+        broadcaster.set_gm(3, false);
+        broadcaster.broadcast(&zone.snapshot());
+        let delta = Delta::decode(&inboxes[0].try_recv().unwrap()).unwrap();
+        assert!(delta.left.contains(&far));
+        let delta = Delta::decode(&inboxes[2].try_recv().unwrap()).unwrap();
+        assert!(delta.left.contains(&gm));
+    }
+
+    #[test]
+    fn test_full_and_closed_outboxes() {
+        let mut zone = Zone::new();
+        let mut broadcaster = Broadcaster::default();
+        let player = zone.spawn(EntityKind::Player, Position::default(), 100);
+        let monster = zone.spawn(EntityKind::Monster, Position::default(), 10);
+
+        let (tx, mut rx) = mpsc::channel(1);
+        broadcaster.subscribe(1, player, tx);
+        assert_eq!(broadcaster.broadcast(&zone.snapshot()).messages, 1);
+
+        // The client hasn't drained its outbox: the update waits
+        *zone.get_mut(monster).unwrap().hp = 5;
+        assert_eq!(broadcaster.broadcast(&zone.snapshot()).skipped, 1);
+        *zone.get_mut(monster).unwrap().state = 1;
+        rx.try_recv().unwrap();
+        broadcaster.broadcast(&zone.snapshot());
+        let delta = Delta::decode(&rx.try_recv().unwrap()).unwrap();
+        assert_eq!(delta.updated[0].hp, Some(5));
+        assert_eq!(delta.updated[0].state, Some(1));
+
+        drop(rx);
+        *zone.get_mut(monster).unwrap().hp = 1;
+        broadcaster.broadcast(&zone.snapshot());
+        assert!(broadcaster.is_empty());
+    }
+}
@@ -0,0 +1,467 @@
+//! Live zone state over HTTP, for development
+//!
+//! With `debug_http` set in the `[dev]` section of `config/world.toml`, the
+//! world server answers plain HTTP there with what its zones hold right
+//! now, so spawn tables and view ranges can be checked without a client:
+//!
+//! ```toml
+//! [dev]
+//! debug_http = "127.0.0.1:7499"
+//! ```
+//!
+//! - `/` lists the zones, linking to the rest
+//! - `/zones` is each zone's entity counts, as JSON
+//! - `/zones/<id>` is every entity in the zone with its position, grouped
+//!   by kind, as JSON
+//! - `/zones/<id>/map` draws the zone from above (x across, z down) and
+//!   reloads every second; the dashed circle around each player is how far
+//!   it sees
+//!
+//! There's no authentication and it's meant for a development machine:
+//! keep it on a loopback address. There are no ground items in the world
+//! yet, so none are shown.
+
+use super::{DEFAULT_VIEW_DISTANCE, Entity, EntityKind, STATE_INVISIBLE, World, ZoneId, ZoneState};
+use anyhow::{Context, Result};
+use ro2_common::net::{HTTP_REQUEST_TIMEOUT, read_http_request};
+use serde::Serialize;
+use std::fmt::Write as _;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpListener;
+use tracing::{error, info, warn};
+
+/// Width of the map drawing, in pixels
+const MAP_WIDTH: u32 = 800;
+
+/// A zone's entity counts
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct ZoneSummary {
+    pub zone: u32,
+    pub tick: u32,
+    pub players: usize,
+    pub monsters: usize,
+    pub npcs: usize,
+    pub nodes: usize,
+}
+
+/// Everything in a zone, grouped by kind
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ZoneView {
+    pub zone: u32,
+    pub tick: u32,
+    /// How far players see, in world units
+    pub view_distance: f32,
+    pub players: Vec<Entity>,
+    pub monsters: Vec<Entity>,
+    pub npcs: Vec<Entity>,
+    pub nodes: Vec<Entity>,
+}
+
+impl ZoneView {
+    pub fn new(state: ZoneState) -> Self {
+        let mut view = Self {
+            zone: state.zone,
+            tick: state.tick,
+            view_distance: DEFAULT_VIEW_DISTANCE,
+            players: Vec::new(),
+            monsters: Vec::new(),
+            npcs: Vec::new(),
+            nodes: Vec::new(),
+        };
+        for entity in state.entities {
+            match entity.kind {
+                EntityKind::Player => view.players.push(entity),
+                EntityKind::Monster => view.monsters.push(entity),
+                EntityKind::Npc => view.npcs.push(entity),
+                EntityKind::Node => view.nodes.push(entity),
+            }
+        }
+        view
+    }
+
+    pub fn summary(&self) -> ZoneSummary {
+        ZoneSummary {
+            zone: self.zone,
+            tick: self.tick,
+            players: self.players.len(),
+            monsters: self.monsters.len(),
+            npcs: self.npcs.len(),
+            nodes: self.nodes.len(),
+        }
+    }
+
+    fn entities(&self) -> impl Iterator<Item = &Entity> {
+        self.players
+            .iter()
+            .chain(&self.monsters)
+            .chain(&self.npcs)
+            .chain(&self.nodes)
+    }
+
+    /// The zone from above as SVG, framing its entities and the players'
+    /// view
+    pub fn svg(&self) -> String {
+        let (mut min_x, mut min_z, mut max_x, mut max_z) = (f32::MAX, f32::MAX, f32::MIN, f32::MIN);
+        for entity in self.entities() {
+            let reach = match entity.kind {
+                EntityKind::Player => self.view_distance,
+                _ => 0.0,
+            };
+            min_x = min_x.min(entity.position.x - reach);
+            min_z = min_z.min(entity.position.z - reach);
+            max_x = max_x.max(entity.position.x + reach);
+            max_z = max_z.max(entity.position.z + reach);
+        }
+        if min_x > max_x {
+            (min_x, min_z, max_x, max_z) = (0.0, 0.0, 0.0, 0.0);
+        }
+        let margin = ((max_x - min_x).max(max_z - min_z) * 0.05).max(100.0);
+        let (left, top) = (min_x - margin, min_z - margin);
+        let (width, height) = (max_x - min_x + 2.0 * margin, max_z - min_z + 2.0 * margin);
+        let dot = width.max(height) / 200.0;
+
+        let mut svg = String::new();
+        let _ = write!(
+            svg,
+            r##"<svg xmlns="http://www.w3.org/2000/svg" width="{}" height="{}" viewBox="{} {} {} {}"><rect x="{}" y="{}" width="{}" height="{}" fill="#f8f8f4"/>"##,
+            MAP_WIDTH,
+            (MAP_WIDTH as f32 * height / width).round(),
+            left,
+            top,
+            width,
+            height,
+            left,
+            top,
+            width,
+            height
+        );
+        for player in &self.players {
+            let _ = write!(
+                svg,
+                r##"<circle cx="{}" cy="{}" r="{}" fill="none" stroke="#3060c0" stroke-dasharray="4 4" vector-effect="non-scaling-stroke"/>"##,
+                player.position.x, player.position.z, self.view_distance
+            );
+        }
+        for entity in self.entities() {
+            let (color, kind) = match entity.kind {
+                EntityKind::Player => ("#3060c0", "player"),
+                EntityKind::Monster => ("#c03030", "monster"),
+                EntityKind::Npc => ("#30a040", "npc"),
+                EntityKind::Node => ("#a07020", "node"),
+            };
+            let opacity = if entity.state & STATE_INVISIBLE != 0 {
+                0.3
+            } else {
+                1.0
+            };
+            let _ = write!(
+                svg,
+                r#"<circle cx="{}" cy="{}" r="{}" fill="{}" fill-opacity="{}"><title>{} {} ({}, {}, {}) hp {}/{}</title></circle>"#,
+                entity.position.x,
+                entity.position.z,
+                dot,
+                color,
+                opacity,
+                kind,
+                entity.id.0,
+                entity.position.x,
+                entity.position.y,
+                entity.position.z,
+                entity.hp,
+                entity.max_hp
+            );
+        }
+        svg.push_str("</svg>");
+        svg
+    }
+}
+
+/// What to send back for one request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Response {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: String,
+}
+
+impl Response {
+    fn ok(content_type: &'static str, body: String) -> Self {
+        Self {
+            status: 200,
+            content_type,
+            body,
+        }
+    }
+
+    fn json(value: &impl Serialize) -> Result<Self> {
+        Ok(Self::ok("application/json", serde_json::to_string(value)?))
+    }
+
+    fn not_found() -> Self {
+        Self {
+            status: 404,
+            content_type: "text/plain; charset=utf-8",
+            body: "not found\n".to_string(),
+        }
+    }
+
+    fn reason(&self) -> &'static str {
+        match self.status {
+            200 => "OK",
+            400 => "Bad Request",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            _ => "Internal Server Error",
+        }
+    }
+}
+
+/// Every running zone's contents, sorted by zone
+async fn zone_views(world: &World) -> Result<Vec<ZoneView>> {
+    let mut views = Vec::new();
+    for handle in world.zones() {
+        views.push(ZoneView::new(handle.save().await?));
+    }
+    views.sort_by_key(|view| view.zone);
+    Ok(views)
+}
+
+/// Answer a GET for `path`
+pub async fn respond(world: &World, path: &str) -> Result<Response> {
+    let path = path.split_once('?').map_or(path, |(path, _)| path);
+    let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+    let zone = |id: &str| {
+        id.parse()
+            .ok()
+            .and_then(|id| world.zone(ZoneId(id)))
+            .cloned()
+    };
+    match segments[..] {
+        [] => {
+            let mut html = String::from(
+                "<!DOCTYPE html><html><head><title>World zones</title></head><body><h1>Zones</h1><ul>",
+            );
+            for view in zone_views(world).await? {
+                let summary = view.summary();
+                let _ = write!(
+                    html,
+                    r#"<li>Zone {0}: {1} players, {2} monsters, {3} NPCs, {4} nodes (<a href="/zones/{0}">JSON</a>, <a href="/zones/{0}/map">map</a>)</li>"#,
+                    summary.zone, summary.players, summary.monsters, summary.npcs, summary.nodes
+                );
+            }
+            html.push_str("</ul></body></html>");
+            Ok(Response::ok("text/html; charset=utf-8", html))
+        }
+        ["zones"] => {
+            let summaries: Vec<ZoneSummary> = zone_views(world)
+                .await?
+                .iter()
+                .map(ZoneView::summary)
+                .collect();
+            Response::json(&summaries)
+        }
+        ["zones", id] => match zone(id) {
+            Some(handle) => Response::json(&ZoneView::new(handle.save().await?)),
+            None => Ok(Response::not_found()),
+        },
+        ["zones", id, "map"] => match zone(id) {
+            Some(handle) => {
+                let view = ZoneView::new(handle.save().await?);
+                let summary = view.summary();
+                Ok(Response::ok(
+                    "text/html; charset=utf-8",
+                    format!(
+                        r#"<!DOCTYPE html><html><head><title>Zone {0}</title><meta http-equiv="refresh" content="1"></head><body><p><a href="/">Zones</a> | Zone {0}, tick {1}: {2} players, {3} monsters, {4} NPCs, {5} nodes</p>{6}</body></html>"#,
+                        summary.zone,
+                        summary.tick,
+                        summary.players,
+                        summary.monsters,
+                        summary.npcs,
+                        summary.nodes,
+                        view.svg()
+                    ),
+                ))
+            }
+            None => Ok(Response::not_found()),
+        },
+        _ => Ok(Response::not_found()),
+    }
+}
+
+/// Answer debug requests on `addr`, each connection in its own task
+pub async fn start(addr: SocketAddr, world: Arc<World>) -> Result<()> {
+    let listener = TcpListener::bind(addr)
+        .await
+        .with_context(|| format!("binding debug HTTP to {}", addr))?;
+    if !addr.ip().is_loopback() {
+        warn!("Debug HTTP on {} is open to other machines", addr);
+    }
+    info!("Debug HTTP listening on {}", addr);
+    tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, peer)) => {
+                    let world = Arc::clone(&world);
+                    tokio::spawn(async move {
+                        if let Err(e) = serve(stream, &world).await {
+                            warn!("Debug HTTP client {} failed: {}", peer, e);
+                        }
+                    });
+                }
+                Err(e) => error!("Debug HTTP accept failed: {}", e),
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Answer one request, then close the connection
+pub async fn serve<S: AsyncRead + AsyncWrite + Unpin>(stream: S, world: &World) -> Result<()> {
+    let (reader, mut writer) = tokio::io::split(stream);
+    let mut reader = BufReader::new(reader);
+    let response = match read_http_request(&mut reader, HTTP_REQUEST_TIMEOUT).await {
+        Ok(request) if request.method == "GET" => match respond(world, &request.target).await {
+            Ok(response) => response,
+            Err(e) => Response {
+                status: 500,
+                content_type: "text/plain; charset=utf-8",
+                body: format!("{:#}\n", e),
+            },
+        },
+        Ok(_) => Response {
+            status: 405,
+            content_type: "text/plain; charset=utf-8",
+            body: "only GET\n".to_string(),
+        },
+        Err(e) => {
+            writer
+                .write_all(b"HTTP/1.1 400 Bad Request\r\nConnection: close\r\n\r\n")
+                .await?;
+            return Err(e);
+        }
+    };
+    writer
+        .write_all(
+            format!(
+                "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n",
+                response.status,
+                response.reason(),
+                response.content_type,
+                response.body.len()
+            )
+            .as_bytes(),
+        )
+        .await?;
+    writer.write_all(response.body.as_bytes()).await?;
+    writer.shutdown().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::{Position, Zone};
+    use std::time::Duration;
+    use tokio::io::AsyncReadExt;
+
+    async fn world() -> World {
+        let mut world = World::new(Duration::from_millis(5));
+        let town = world.start_zone(ZoneId(1), Zone::new());
+        world.start_zone(ZoneId(2), Zone::new());
+        town.spawn(EntityKind::Player, Position::new(100.0, 0.0, 200.0), 100)
+            .await
+            .unwrap();
+        town.spawn(EntityKind::Monster, Position::new(500.0, 0.0, 200.0), 50)
+            .await
+            .unwrap();
+        town.spawn(EntityKind::Monster, Position::new(-300.0, 0.0, 50.0), 50)
+            .await
+            .unwrap();
+        world
+    }
+
+    #[tokio::test]
+    async fn test_zone_json() {
+        let world = world().await;
+
+        let zones = respond(&world, "/zones").await.unwrap();
+        let zones: serde_json::Value = serde_json::from_str(&zones.body).unwrap();
+        assert_eq!(zones[0]["zone"], 1);
+        assert_eq!(zones[0]["players"], 1);
+        assert_eq!(zones[0]["monsters"], 2);
+        assert_eq!(zones[1]["zone"], 2);
+        assert_eq!(zones[1]["monsters"], 0);
+
+        let zone = respond(&world, "/zones/1?pretty").await.unwrap();
+        assert_eq!(zone.content_type, "application/json");
+        let zone: serde_json::Value = serde_json::from_str(&zone.body).unwrap();
+        assert_eq!(
+            zone["players"][0]["position"],
+            serde_json::json!([100.0, 0.0, 200.0])
+        );
+        assert_eq!(zone["monsters"].as_array().unwrap().len(), 2);
+        assert_eq!(zone["view_distance"], DEFAULT_VIEW_DISTANCE as f64);
+
+        for path in ["/zones/3", "/zones/town", "/zones/1/entities", "/admin"] {
+            assert_eq!(respond(&world, path).await.unwrap().status, 404, "{}", path);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_map() {
+        let world = world().await;
+        let map = respond(&world, "/zones/1/map").await.unwrap();
+        assert_eq!(map.status, 200);
+        // The player's view, then one dot per entity
+        assert_eq!(map.body.matches("<circle").count(), 4);
+        assert!(
+            map.body
+                .contains(&format!(r#"r="{}""#, DEFAULT_VIEW_DISTANCE))
+        );
+        assert!(map.body.contains("monster 3 (-300, 0, 50) hp 50/50"));
+
+        // An empty zone still draws
+        let empty = respond(&world, "/zones/2/map").await.unwrap();
+        assert!(empty.body.contains("<svg"));
+        assert!(!empty.body.contains("<circle"));
+
+        let index = respond(&world, "/").await.unwrap();
+        assert!(index.body.contains(r#"<a href="/zones/2/map">"#));
+    }
+
+    #[tokio::test]
+    async fn test_serve() {
+        let world = world().await;
+        let (mut client, server) = tokio::io::duplex(64 * 1024);
+        client
+            .write_all(b"GET /zones HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+        serve(server, &world).await.unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("Content-Type: application/json\r\n"));
+        assert!(response.ends_with("]"));
+
+        let (mut client, server) = tokio::io::duplex(4096);
+        client
+            .write_all(b"POST /zones HTTP/1.1\r\n\r\n")
+            .await
+            .unwrap();
+        serve(server, &world).await.unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 405 Method Not Allowed\r\n"));
+
+        let (mut client, server) = tokio::io::duplex(64 * 1024);
+        let endless = format!("GET /{} HTTP/1.1\r\n", "a".repeat(20 * 1024));
+        client.write_all(endless.as_bytes()).await.unwrap();
+        assert!(serve(server, &world).await.is_err());
+        let mut response = String::new();
+        client.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+    }
+}
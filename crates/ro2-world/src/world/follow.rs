@@ -0,0 +1,87 @@
+//! Entities following others around a zone
+//!
+//! GMs follow players they're watching (see [`crate::gm`]). Each tick a
+//! follower is put where its target is, facing the same way; a follower
+//! whose target has left the zone stops following.
+
+use super::{EntityId, Zone};
+use std::collections::HashMap;
+
+/// Who follows whom in one zone
+#[derive(Debug, Default)]
+pub struct Followers {
+    /// Follower to target
+    following: HashMap<EntityId, EntityId>,
+}
+
+impl Followers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Have `follower` follow `target`, or stop following with `None`
+    pub fn follow(&mut self, follower: EntityId, target: Option<EntityId>) {
+        match target {
+            Some(target) if target != follower => {
+                self.following.insert(follower, target);
+            }
+            _ => {
+                self.following.remove(&follower);
+            }
+        }
+    }
+
+    pub fn target(&self, follower: EntityId) -> Option<EntityId> {
+        self.following.get(&follower).copied()
+    }
+
+    /// Forget `entity`, as follower or target
+    pub fn remove(&mut self, entity: EntityId) {
+        self.following
+            .retain(|&follower, &mut target| follower != entity && target != entity);
+    }
+
+    /// Move every follower to its target
+    pub fn tick(&mut self, zone: &mut Zone) {
+        self.following.retain(|&follower, &mut target| {
+            let Some(target) = zone.get(target) else {
+                return false;
+            };
+            let Some(follower) = zone.get_mut(follower) else {
+                return false;
+            };
+            *follower.position = target.position;
+            *follower.direction = target.direction;
+            true
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::{EntityKind, Position};
+
+    #[test]
+    fn test_follow() {
+        let mut zone = Zone::new();
+        let gm = zone.spawn(EntityKind::Player, Position::default(), 100);
+        let player = zone.spawn(EntityKind::Player, Position::new(5.0, 0.0, 0.0), 100);
+        let mut followers = Followers::new();
+        followers.follow(gm, Some(gm));
+        assert_eq!(followers.target(gm), None);
+
+        followers.follow(gm, Some(player));
+        *zone.get_mut(player).unwrap().position = Position::new(40.0, 2.0, 0.0);
+        *zone.get_mut(player).unwrap().direction = 0x4000;
+        followers.tick(&mut zone);
+        let moved = zone.get(gm).unwrap();
+        assert_eq!(moved.position, Position::new(40.0, 2.0, 0.0));
+        assert_eq!(moved.direction, 0x4000);
+
+        // The player leaves the zone, and the GM stays put
+        zone.despawn(player);
+        followers.tick(&mut zone);
+        assert_eq!(followers.target(gm), None);
+    }
+}
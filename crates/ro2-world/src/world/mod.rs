@@ -0,0 +1,203 @@
+//! World simulation
+//!
+//! A [`Zone`] holds the entities of one map in an [`EntityStore`], one
+//! column per component. Once per tick the zone takes a [`Snapshot`] of
+//! them and the [`Broadcaster`] sends every client a single delta message
+//! covering everything in its view: entities that came into range, the
+//! fields that changed on the ones it already knows, and the ones that
+//! left. A crowded zone costs one message per client per tick rather than
+//! one per entity change.
+
+mod broadcast;
+pub mod debug;
+mod follow;
+mod movement;
+mod overload;
+mod regen;
+mod runner;
+mod savestate;
+mod snapshot;
+mod storage;
+
+pub use broadcast::{BroadcastStats, Broadcaster, DEFAULT_VIEW_DISTANCE};
+pub use follow::Followers;
+pub use movement::{MAX_UPDATE_HZ, MovementSync, NFY_MOVE_SYNC};
+pub use overload::{Load, OverloadConfig, OverloadDetector};
+pub use regen::{NFY_VITALS, Regen, RegenConfig, RegenRates, Vitals};
+pub use runner::{DEFAULT_TICK_INTERVAL, TickStats, World, ZoneHandle};
+pub use savestate::{DevConfig, SaveState, ZoneState};
+pub use snapshot::{ClientView, Delta, EntityUpdate, NFY_WORLD_DELTA, Snapshot};
+pub use storage::{Columns, EntityMut, EntityStore};
+
+use serde::{Deserialize, Serialize};
+
+/// Identifies a zone
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ZoneId(pub u32);
+
+/// Identifies an entity within its zone
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct EntityId(pub u32);
+
+/// What an entity is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[repr(u8)]
+pub enum EntityKind {
+    Player = 1,
+    Monster = 2,
+    Npc = 3,
+    /// A gatherable resource node (see [`crate::professions`])
+    Node = 4,
+}
+
+impl EntityKind {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            1 => Some(Self::Player),
+            2 => Some(Self::Monster),
+            3 => Some(Self::Npc),
+            4 => Some(Self::Node),
+            _ => None,
+        }
+    }
+}
+
+/// World coordinates; `[x, y, z]` in config files
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(from = "[f32; 3]", into = "[f32; 3]")]
+pub struct Position {
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+}
+
+impl Position {
+    pub fn new(x: f32, y: f32, z: f32) -> Self {
+        Self { x, y, z }
+    }
+
+    pub fn distance_squared(&self, other: &Position) -> f32 {
+        let (dx, dy, dz) = (self.x - other.x, self.y - other.y, self.z - other.z);
+        dx * dx + dy * dy + dz * dz
+    }
+}
+
+impl From<[f32; 3]> for Position {
+    fn from([x, y, z]: [f32; 3]) -> Self {
+        Self { x, y, z }
+    }
+}
+
+impl From<Position> for [f32; 3] {
+    fn from(position: Position) -> Self {
+        [position.x, position.y, position.z]
+    }
+}
+
+/// [`Entity::state`] flag of a player sitting down (see [`crate::social`]);
+/// tentative
+pub const STATE_SITTING: u8 = 0x02;
+
+/// [`Entity::state`] flag of a player riding a mount (see [`crate::mount`]);
+/// tentative, like the other flags' meaning
+pub const STATE_MOUNTED: u8 = 0x10;
+
+/// [`Entity::state`] flag of a GM hidden from everyone but other GMs (see
+/// [`crate::gm`]); tentative
+pub const STATE_INVISIBLE: u8 = 0x20;
+
+/// [`Entity::state`] flag of a player with a vending stall open (see
+/// [`crate::vending`]); tentative
+pub const STATE_VENDING: u8 = 0x40;
+
+/// The replicated state of an entity
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Entity {
+    pub id: EntityId,
+    pub kind: EntityKind,
+    pub position: Position,
+    /// Facing, in 1/65536ths of a full turn
+    pub direction: u16,
+    pub hp: u32,
+    pub max_hp: u32,
+    /// State flags (moving, sitting, dead, ...)
+    pub state: u8,
+}
+
+/// The entities of one map
+#[derive(Debug, Default)]
+pub struct Zone {
+    entities: EntityStore,
+    next_id: u32,
+    tick: u32,
+}
+
+impl Zone {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add an entity at full health
+    pub fn spawn(&mut self, kind: EntityKind, position: Position, max_hp: u32) -> EntityId {
+        self.next_id += 1;
+        let id = EntityId(self.next_id);
+        self.entities.insert(Entity {
+            id,
+            kind,
+            position,
+            direction: 0,
+            hp: max_hp,
+            max_hp,
+            state: 0,
+        });
+        id
+    }
+
+    /// Take in an entity from another zone, giving it an ID in this one
+    pub fn adopt(&mut self, entity: Entity) -> EntityId {
+        self.next_id += 1;
+        let id = EntityId(self.next_id);
+        self.entities.insert(Entity { id, ..entity });
+        id
+    }
+
+    /// Put back an entity despawned from this zone, under its old ID
+    pub fn restore(&mut self, entity: Entity) {
+        self.entities.insert(entity);
+    }
+
+    pub fn despawn(&mut self, id: EntityId) -> Option<Entity> {
+        self.entities.remove(id)
+    }
+
+    pub fn get(&self, id: EntityId) -> Option<Entity> {
+        self.entities.get(id)
+    }
+
+    pub fn get_mut(&mut self, id: EntityId) -> Option<EntityMut<'_>> {
+        self.entities.get_mut(id)
+    }
+
+    /// The zone's entity columns, for tick systems
+    pub fn entities(&self) -> &EntityStore {
+        &self.entities
+    }
+
+    pub fn entities_mut(&mut self) -> &mut EntityStore {
+        &mut self.entities
+    }
+
+    pub fn len(&self) -> usize {
+        self.entities.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entities.is_empty()
+    }
+
+    /// Advance the tick counter and capture every entity's state
+    pub fn snapshot(&mut self) -> Snapshot {
+        self.tick = self.tick.wrapping_add(1);
+        Snapshot::new(self.tick, self.entities.iter())
+    }
+}
@@ -0,0 +1,136 @@
+//! Movement sync tuning
+//!
+//! How often clients are sent other entities' movement, and how their
+//! position followers smooth it, is a trade between bandwidth and how
+//! smooth movement looks. Operators set it in the `[movement]` section of
+//! `config/world.toml`:
+//!
+//! ```toml
+//! [movement]
+//! update_hz = 10              # Movement updates sent per second
+//! interpolation_ms = 200      # Time clients take to glide to a new position
+//! max_extrapolation_ms = 500  # How long clients keep an entity moving unheard
+//! ```
+//!
+//! Zones tick at their own rate; on ticks between movement updates the
+//! [`Broadcaster`](super::Broadcaster) holds back position and direction
+//! changes (see [`Delta::hold_movement`](super::Delta::hold_movement)).
+//! The two client-side values are sent to each client as [`NFY_MOVE_SYNC`]
+//! when it subscribes to a zone, a placeholder opcode like those in
+//! `MessageType`.
+
+use anyhow::{Result, anyhow};
+use ro2_common::config::{self, Validate};
+use ro2_common::wire::WireWriter;
+use serde::Deserialize;
+use std::path::Path;
+use std::time::Duration;
+
+pub use ro2_common::protocol::opcodes::NFY_MOVE_SYNC;
+
+/// Most movement updates per second
+pub const MAX_UPDATE_HZ: u32 = 60;
+
+/// Movement sync settings
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct MovementSync {
+    pub update_hz: u32,
+    pub interpolation_ms: u32,
+    pub max_extrapolation_ms: u32,
+}
+
+impl Default for MovementSync {
+    fn default() -> Self {
+        Self {
+            update_hz: 10,
+            interpolation_ms: 200,
+            max_extrapolation_ms: 500,
+        }
+    }
+}
+
+impl MovementSync {
+    /// Read the `[movement]` section of `path`; missing keys keep their
+    /// defaults
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        config::section(path, "movement")
+    }
+
+    /// Parse the `[movement]` section of TOML text
+    pub fn from_toml(text: &str) -> Result<Self> {
+        config::section_from_toml(text, "movement")
+    }
+
+    /// Time between movement updates
+    pub fn update_interval(&self) -> Duration {
+        Duration::from_millis(1000 / self.update_hz as u64)
+    }
+
+    /// Zone ticks per movement update for zones ticking every
+    /// `tick_interval`, at least one
+    pub fn ticks_per_update(&self, tick_interval: Duration) -> u32 {
+        let ticks = self.update_interval().as_secs_f64() / tick_interval.as_secs_f64();
+        (ticks.round() as u32).max(1)
+    }
+
+    /// Encode as [`NFY_MOVE_SYNC`]: u16 update interval, interpolation
+    /// window and max extrapolation, all in milliseconds
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = WireWriter::message(NFY_MOVE_SYNC);
+        out.u16(self.update_interval().as_millis() as u16)
+            .u16(self.interpolation_ms as u16)
+            .u16(self.max_extrapolation_ms as u16);
+        out.into_bytes()
+    }
+}
+
+impl Validate for MovementSync {
+    fn validate(&self) -> Result<()> {
+        if !(1..=MAX_UPDATE_HZ).contains(&self.update_hz) {
+            return Err(anyhow!("movement update_hz must be 1 to {}", MAX_UPDATE_HZ));
+        }
+        // Shorter than the gap between updates and followers stop and
+        // start again between every update
+        if self.interpolation_ms < self.update_interval().as_millis() as u32 {
+            return Err(anyhow!(
+                "movement interpolation_ms is shorter than the {} ms between updates",
+                self.update_interval().as_millis()
+            ));
+        }
+        if self.max_extrapolation_ms > u16::MAX as u32 || self.interpolation_ms > u16::MAX as u32 {
+            return Err(anyhow!("movement windows must be under {} ms", u16::MAX));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load() {
+        assert_eq!(
+            MovementSync::load("does/not/exist.toml").unwrap(),
+            MovementSync::default()
+        );
+
+        let sync =
+            MovementSync::from_toml("bind = \"0.0.0.0:7401\"\n[movement]\nupdate_hz = 5").unwrap();
+        assert_eq!(sync.update_hz, 5);
+        assert_eq!(sync.interpolation_ms, 200);
+        assert_eq!(sync.ticks_per_update(Duration::from_millis(100)), 2);
+        assert_eq!(sync.ticks_per_update(Duration::from_millis(500)), 1);
+        assert_eq!(sync.encode(), [0x01, 0x3F, 200, 0, 200, 0, 0xF4, 0x01]);
+
+        for bad in [
+            "[movement]\nupdate_hz = 0",
+            "[movement]\nupdate_hz = 61",
+            "[movement]\nupdate_hz = 2\ninterpolation_ms = 200",
+            "[movement]\nmax_extrapolation_ms = 70000",
+        ] {
+            assert!(MovementSync::from_toml(bad).is_err(), "{}", bad);
+        }
+    }
+}
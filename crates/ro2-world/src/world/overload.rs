@@ -0,0 +1,269 @@
+//! Load shedding
+//!
+//! A zone whose ticks keep running over budget, or whose command queue
+//! backs up, is overloaded. Rather than let it fall further and further
+//! behind, the world sheds load until every zone has recovered:
+//!
+//! - zones with no clients watching run their simulation (following,
+//!   regen, monster AI) only every few ticks;
+//! - background tasks that can wait, like publishing populations or
+//!   purging rentals, skip their runs (see [`Load::is_overloaded`]);
+//! - players asking to enter the channel are refused with a "channel
+//!   full" error (see [`Load::admit`]) instead of adding to the load.
+//!
+//! Set in the `[overload]` section of `config/world.toml`:
+//!
+//! ```toml
+//! [overload]
+//! window_ticks = 50           # Recent ticks looked at
+//! overrun_percent = 20        # Overloaded once this share of them overran...
+//! queue_depth = 128           # ...or this many commands wait on the zone
+//! recover_percent = 5         # Recovered at or below this share, once the
+//!                             # queue is under half of queue_depth
+//! empty_zone_divisor = 10     # Unwatched zones simulate every this many
+//!                             # ticks while overloaded
+//! ```
+
+use super::ZoneId;
+use anyhow::{Result, anyhow};
+use ro2_common::config::{self, Validate};
+use ro2_common::protocol::{ClientError, ErrorCode};
+use serde::Deserialize;
+use std::collections::{HashSet, VecDeque};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+/// Overload thresholds
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct OverloadConfig {
+    pub window_ticks: u32,
+    pub overrun_percent: u32,
+    pub queue_depth: usize,
+    pub recover_percent: u32,
+    pub empty_zone_divisor: u32,
+}
+
+impl Default for OverloadConfig {
+    fn default() -> Self {
+        Self {
+            window_ticks: 50,
+            overrun_percent: 20,
+            queue_depth: 128,
+            recover_percent: 5,
+            empty_zone_divisor: 10,
+        }
+    }
+}
+
+impl OverloadConfig {
+    /// Read the `[overload]` section of `path`; missing keys keep their
+    /// defaults
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        config::section(path, "overload")
+    }
+
+    /// Parse the `[overload]` section of TOML text
+    pub fn from_toml(text: &str) -> Result<Self> {
+        config::section_from_toml(text, "overload")
+    }
+
+    /// Whether a zone with `clients` watching runs its simulation on tick
+    /// `tick`, while the world is `overloaded` or not
+    pub fn simulates(&self, overloaded: bool, clients: usize, tick: u64) -> bool {
+        !overloaded || clients > 0 || tick.is_multiple_of(self.empty_zone_divisor as u64)
+    }
+}
+
+impl Validate for OverloadConfig {
+    fn validate(&self) -> Result<()> {
+        if self.window_ticks == 0 || self.queue_depth == 0 || self.empty_zone_divisor == 0 {
+            return Err(anyhow!(
+                "overload window_ticks, queue_depth and empty_zone_divisor must be above 0"
+            ));
+        }
+        if self.overrun_percent > 100 || self.recover_percent >= self.overrun_percent {
+            return Err(anyhow!(
+                "overload recover_percent must be under overrun_percent, which is at most 100"
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Which zones are overloaded, shared by the world and its background
+/// tasks
+#[derive(Debug, Clone, Default)]
+pub struct Load {
+    overloaded: Arc<Mutex<HashSet<ZoneId>>>,
+}
+
+impl Load {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether any zone is overloaded, so the world is shedding load
+    pub fn is_overloaded(&self) -> bool {
+        !self.overloaded.lock().unwrap().is_empty()
+    }
+
+    /// The overloaded zones, in order
+    pub fn zones(&self) -> Vec<ZoneId> {
+        let mut zones: Vec<_> = self.overloaded.lock().unwrap().iter().copied().collect();
+        zones.sort_unstable();
+        zones
+    }
+
+    /// Let a player into the channel, unless it's shedding load; the
+    /// error is a [`ClientError`] telling them the channel is full
+    pub fn admit(&self) -> Result<()> {
+        if self.is_overloaded() {
+            return Err(ClientError::new(ErrorCode::Busy, "overload.channel_full").into());
+        }
+        Ok(())
+    }
+
+    pub(super) fn set(&self, zone: ZoneId, overloaded: bool) {
+        let mut zones = self.overloaded.lock().unwrap();
+        if overloaded {
+            zones.insert(zone);
+        } else {
+            zones.remove(&zone);
+        }
+    }
+}
+
+/// Decides from its recent ticks whether one zone is overloaded
+#[derive(Debug)]
+pub struct OverloadDetector {
+    config: OverloadConfig,
+    /// Whether each recent tick overran, oldest first
+    window: VecDeque<bool>,
+    overruns: usize,
+    overloaded: bool,
+}
+
+impl OverloadDetector {
+    pub fn new(config: OverloadConfig) -> Self {
+        Self {
+            config,
+            window: VecDeque::with_capacity(config.window_ticks as usize),
+            overruns: 0,
+            overloaded: false,
+        }
+    }
+
+    pub fn config(&self) -> &OverloadConfig {
+        &self.config
+    }
+
+    pub fn is_overloaded(&self) -> bool {
+        self.overloaded
+    }
+
+    /// Record a tick that did or didn't overrun, with `queue_depth`
+    /// commands waiting after it; returns the new state if it changed
+    pub fn record(&mut self, overran: bool, queue_depth: usize) -> Option<bool> {
+        if self.window.len() == self.config.window_ticks as usize
+            && self.window.pop_front() == Some(true)
+        {
+            self.overruns -= 1;
+        }
+        self.window.push_back(overran);
+        self.overruns += overran as usize;
+
+        // Percentages of the whole window, so a zone that just started
+        // needs as many overruns as one that's been running a while
+        let percent = self.overruns * 100 / self.config.window_ticks as usize;
+        let overloaded = if self.overloaded {
+            percent > self.config.recover_percent as usize
+                || queue_depth * 2 >= self.config.queue_depth
+        } else {
+            percent >= self.config.overrun_percent as usize
+                || queue_depth >= self.config.queue_depth
+        };
+        if overloaded == self.overloaded {
+            return None;
+        }
+        self.overloaded = overloaded;
+        Some(overloaded)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> OverloadConfig {
+        OverloadConfig {
+            window_ticks: 10,
+            overrun_percent: 30,
+            queue_depth: 8,
+            recover_percent: 10,
+            empty_zone_divisor: 4,
+        }
+    }
+
+    #[test]
+    fn test_overruns_and_recovery() {
+        let mut detector = OverloadDetector::new(config());
+        assert_eq!(detector.record(true, 0), None);
+        assert_eq!(detector.record(true, 0), None);
+        assert_eq!(detector.record(true, 0), Some(true));
+
+        // Two overruns left in the window is still too many
+        for _ in 0..8 {
+            assert_eq!(detector.record(false, 0), None);
+        }
+        assert!(detector.is_overloaded());
+        assert_eq!(detector.record(false, 0), Some(false));
+    }
+
+    #[test]
+    fn test_queue_depth() {
+        let mut detector = OverloadDetector::new(config());
+        assert_eq!(detector.record(false, 7), None);
+        assert_eq!(detector.record(false, 8), Some(true));
+        // Recovers only once the queue has drained to under half
+        assert_eq!(detector.record(false, 4), None);
+        assert_eq!(detector.record(false, 3), Some(false));
+    }
+
+    #[test]
+    fn test_load() {
+        let load = Load::new();
+        assert!(load.admit().is_ok());
+        load.set(ZoneId(2), true);
+        load.set(ZoneId(1), true);
+        assert_eq!(load.zones(), [ZoneId(1), ZoneId(2)]);
+
+        let error = load.admit().unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<ClientError>().unwrap().code,
+            ErrorCode::Busy
+        );
+        load.set(ZoneId(1), false);
+        load.set(ZoneId(2), false);
+        assert!(!load.is_overloaded());
+
+        let config = config();
+        assert!(config.simulates(false, 0, 1));
+        assert!(config.simulates(true, 1, 1));
+        assert!(!config.simulates(true, 0, 1));
+        assert!(config.simulates(true, 0, 4));
+    }
+
+    #[test]
+    fn test_config() {
+        assert_eq!(
+            OverloadConfig::load("does/not/exist.toml").unwrap(),
+            OverloadConfig::default()
+        );
+        let config = OverloadConfig::from_toml("[overload]\nqueue_depth = 64").unwrap();
+        assert_eq!(config.queue_depth, 64);
+        assert_eq!(config.window_ticks, 50);
+        assert!(OverloadConfig::from_toml("[overload]\nrecover_percent = 20").is_err());
+        assert!(OverloadConfig::from_toml("[overload]\nempty_zone_divisor = 0").is_err());
+    }
+}
@@ -0,0 +1,377 @@
+//! HP and MP regeneration
+//!
+//! Characters out of combat get some HP and MP back every regen interval,
+//! more while sitting. How much comes from their stats (see
+//! [`RegenRates::from_stats`]); how often, and how long after a hit they
+//! count as in combat, is set in the `[regen]` section of
+//! `config/world.toml`:
+//!
+//! ```toml
+//! [regen]
+//! interval_ms = 3000          # Time between regen ticks
+//! hp_percent = 1              # Max HP recovered per regen tick, before VIT
+//! mp_percent = 1              # Max MP recovered per regen tick, before INT
+//! sitting_multiplier = 2      # Sitting recovers this many times faster
+//! combat_timeout_ms = 5000    # No regen until this long after a hit
+//! ```
+//!
+//! The zone applies it on its own ticks. Other clients see HP change in
+//! the zone's delta; each character's own client gets one [`NFY_VITALS`]
+//! per regen tick with its HP and MP, and only when they changed, so idle
+//! players see their bars fill without a message per point. Like the
+//! other `0x3Fxx` opcodes it's a placeholder.
+
+use super::{EntityId, STATE_SITTING, Zone};
+use crate::stats::{StatKind, Stats};
+use anyhow::{Result, anyhow};
+use ro2_common::config::{self, Validate};
+use ro2_common::wire::WireWriter;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::Duration;
+
+pub use ro2_common::protocol::opcodes::NFY_VITALS;
+
+/// Regeneration settings
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct RegenConfig {
+    pub interval_ms: u32,
+    pub hp_percent: u32,
+    pub mp_percent: u32,
+    pub sitting_multiplier: u32,
+    pub combat_timeout_ms: u32,
+}
+
+impl Default for RegenConfig {
+    fn default() -> Self {
+        Self {
+            interval_ms: 3000,
+            hp_percent: 1,
+            mp_percent: 1,
+            sitting_multiplier: 2,
+            combat_timeout_ms: 5000,
+        }
+    }
+}
+
+impl RegenConfig {
+    /// Read the `[regen]` section of `path`; missing keys keep their
+    /// defaults
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        config::section(path, "regen")
+    }
+
+    /// Parse the `[regen]` section of TOML text
+    pub fn from_toml(text: &str) -> Result<Self> {
+        config::section_from_toml(text, "regen")
+    }
+
+    /// Zone ticks per regen tick for zones ticking every `tick_interval`,
+    /// at least one
+    pub fn ticks_per_regen(&self, tick_interval: Duration) -> u32 {
+        ticks(self.interval_ms, tick_interval).max(1)
+    }
+
+    /// Zone ticks a hit keeps a character in combat for
+    pub fn combat_ticks(&self, tick_interval: Duration) -> u32 {
+        ticks(self.combat_timeout_ms, tick_interval)
+    }
+}
+
+impl Validate for RegenConfig {
+    fn validate(&self) -> Result<()> {
+        if self.interval_ms == 0 {
+            return Err(anyhow!("regen interval_ms must be above 0"));
+        }
+        if self.hp_percent > 100 || self.mp_percent > 100 {
+            return Err(anyhow!("regen percentages must be 0 to 100"));
+        }
+        if self.sitting_multiplier == 0 {
+            return Err(anyhow!("regen sitting_multiplier must be at least 1"));
+        }
+        Ok(())
+    }
+}
+
+fn ticks(ms: u32, tick_interval: Duration) -> u32 {
+    (Duration::from_millis(ms as u64).as_secs_f64() / tick_interval.as_secs_f64()).round() as u32
+}
+
+/// HP and MP one character recovers per regen tick, standing
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RegenRates {
+    pub hp: u32,
+    pub mp: u32,
+}
+
+impl RegenRates {
+    /// A share of max HP and MP, plus a fifth of VIT in HP and a sixth of
+    /// INT in MP; always at least one point of each
+    pub fn from_stats(stats: &Stats, config: &RegenConfig) -> Self {
+        let share = |max: i32, percent: u32, attribute: i32, divisor: i32| {
+            let points = max.max(0) as i64 * percent as i64 / 100 + (attribute / divisor) as i64;
+            points.clamp(1, u32::MAX as i64) as u32
+        };
+        Self {
+            hp: share(
+                stats.get(StatKind::MaxHp),
+                config.hp_percent,
+                stats.get(StatKind::Vit),
+                5,
+            ),
+            mp: share(
+                stats.get(StatKind::MaxMp),
+                config.mp_percent,
+                stats.get(StatKind::Int),
+                6,
+            ),
+        }
+    }
+}
+
+/// What a zone keeps for each regenerating character besides its entity
+///
+/// Travels with the entity when it moves to another zone.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Vitals {
+    pub mp: u32,
+    pub max_mp: u32,
+    pub rates: RegenRates,
+    /// Regen ticks left until the character is out of combat
+    combat_ticks: u32,
+    /// HP and MP as last sent to the character's client
+    sent: Option<(u32, u32)>,
+}
+
+impl Vitals {
+    pub fn new(mp: u32, max_mp: u32, rates: RegenRates) -> Self {
+        Self {
+            mp: mp.min(max_mp),
+            max_mp,
+            rates,
+            combat_ticks: 0,
+            sent: None,
+        }
+    }
+
+    pub fn in_combat(&self) -> bool {
+        self.combat_ticks > 0
+    }
+}
+
+/// Regenerates the tracked entities of one zone
+#[derive(Debug)]
+pub struct Regen {
+    config: RegenConfig,
+    ticks_per_regen: u32,
+    combat_ticks: u32,
+    tick: u32,
+    vitals: HashMap<EntityId, Vitals>,
+}
+
+impl Regen {
+    pub fn new(config: RegenConfig, tick_interval: Duration) -> Self {
+        Self {
+            config,
+            ticks_per_regen: config.ticks_per_regen(tick_interval),
+            combat_ticks: config.combat_ticks(tick_interval),
+            tick: 0,
+            vitals: HashMap::new(),
+        }
+    }
+
+    /// Start regenerating entity `id`, or replace its vitals
+    pub fn track(&mut self, id: EntityId, vitals: Vitals) {
+        self.vitals.insert(id, vitals);
+    }
+
+    /// Stop regenerating entity `id`, returning its vitals
+    pub fn untrack(&mut self, id: EntityId) -> Option<Vitals> {
+        self.vitals.remove(&id)
+    }
+
+    pub fn vitals(&self, id: EntityId) -> Option<&Vitals> {
+        self.vitals.get(&id)
+    }
+
+    /// Entity `id` hit or was hit; it doesn't regenerate until the combat
+    /// timeout has passed
+    pub fn enter_combat(&mut self, id: EntityId) {
+        let combat_ticks = self.combat_ticks;
+        if let Some(vitals) = self.vitals.get_mut(&id) {
+            vitals.combat_ticks = combat_ticks;
+        }
+    }
+
+    /// Advance one zone tick; on regen ticks, heal every tracked entity
+    /// that's alive and out of combat. Returns the [`NFY_VITALS`] to send
+    /// each entity whose HP or MP changed since its client was last told.
+    pub fn tick(&mut self, zone: &mut Zone) -> Vec<(EntityId, Vec<u8>)> {
+        for vitals in self.vitals.values_mut() {
+            vitals.combat_ticks = vitals.combat_ticks.saturating_sub(1);
+        }
+        self.tick = self.tick.wrapping_add(1);
+        if !self.tick.is_multiple_of(self.ticks_per_regen) {
+            return Vec::new();
+        }
+
+        // Entities that left without being untracked are forgotten
+        self.vitals.retain(|id, _| zone.get(*id).is_some());
+
+        let mut messages = Vec::new();
+        for (&id, vitals) in &mut self.vitals {
+            let Some(entity) = zone.get_mut(id) else {
+                continue;
+            };
+            if *entity.hp > 0 && !vitals.in_combat() {
+                let multiplier = if *entity.state & STATE_SITTING != 0 {
+                    self.config.sitting_multiplier
+                } else {
+                    1
+                };
+                let hp = vitals.rates.hp.saturating_mul(multiplier);
+                let mp = vitals.rates.mp.saturating_mul(multiplier);
+                *entity.hp = entity.hp.saturating_add(hp).min(*entity.max_hp);
+                vitals.mp = vitals.mp.saturating_add(mp).min(vitals.max_mp);
+            }
+
+            let now = (*entity.hp, vitals.mp);
+            if vitals.sent != Some(now) {
+                vitals.sent = Some(now);
+                messages.push((id, encode_vitals(*entity.hp, *entity.max_hp, vitals)));
+            }
+        }
+        messages
+    }
+}
+
+/// Encode [`NFY_VITALS`]: u32 HP, max HP, MP and max MP
+fn encode_vitals(hp: u32, max_hp: u32, vitals: &Vitals) -> Vec<u8> {
+    let mut out = WireWriter::message(NFY_VITALS);
+    out.u32(hp).u32(max_hp).u32(vitals.mp).u32(vitals.max_mp);
+    out.into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stats::{BaseStats, JobGrowth};
+    use crate::world::{EntityKind, Position};
+
+    const TICK: Duration = Duration::from_millis(100);
+
+    fn regen() -> Regen {
+        let config = RegenConfig {
+            interval_ms: 200,
+            combat_timeout_ms: 300,
+            ..RegenConfig::default()
+        };
+        Regen::new(config, TICK)
+    }
+
+    fn rates(hp: u32, mp: u32) -> RegenRates {
+        RegenRates { hp, mp }
+    }
+
+    #[test]
+    fn test_load() {
+        assert_eq!(
+            RegenConfig::load("does/not/exist.toml").unwrap(),
+            RegenConfig::default()
+        );
+        let config = RegenConfig::from_toml("[regen]\ninterval_ms = 1000").unwrap();
+        assert_eq!(config.ticks_per_regen(TICK), 10);
+        assert_eq!(config.combat_ticks(TICK), 50);
+        for bad in [
+            "[regen]\ninterval_ms = 0",
+            "[regen]\nhp_percent = 101",
+            "[regen]\nsitting_multiplier = 0",
+        ] {
+            assert!(RegenConfig::from_toml(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_rates_from_stats() {
+        let stats = Stats::new(BaseStats {
+            level: 11,
+            str: 10,
+            dex: 10,
+            int: 12,
+            vit: 20,
+            luk: 3,
+            job: JobGrowth::default(),
+        });
+        // 360 max HP and 168 max MP
+        assert_eq!(
+            RegenRates::from_stats(&stats, &RegenConfig::default()),
+            rates(3 + 4, 1 + 2)
+        );
+    }
+
+    #[test]
+    fn test_regen_ticks() {
+        let mut zone = Zone::new();
+        let mut regen = regen();
+        let player = zone.spawn(EntityKind::Player, Position::default(), 100);
+        *zone.get_mut(player).unwrap().hp = 50;
+        regen.track(player, Vitals::new(10, 40, rates(5, 3)));
+
+        // Nothing between regen ticks
+        assert!(regen.tick(&mut zone).is_empty());
+        let messages = regen.tick(&mut zone);
+        assert_eq!(zone.get(player).unwrap().hp, 55);
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].0, player);
+        assert_eq!(&messages[0].1[..2], &NFY_VITALS.to_le_bytes());
+        assert_eq!(&messages[0].1[2..], {
+            let mut payload = Vec::new();
+            for value in [55u32, 100, 13, 40] {
+                payload.extend_from_slice(&value.to_le_bytes());
+            }
+            payload
+        });
+
+        // Sitting doubles it
+        *zone.get_mut(player).unwrap().state |= STATE_SITTING;
+        regen.tick(&mut zone);
+        regen.tick(&mut zone);
+        assert_eq!(zone.get(player).unwrap().hp, 65);
+        assert_eq!(regen.vitals(player).unwrap().mp, 19);
+
+        // A hit stops it until the timeout has passed
+        regen.enter_combat(player);
+        regen.tick(&mut zone);
+        assert!(regen.tick(&mut zone).is_empty());
+        assert_eq!(zone.get(player).unwrap().hp, 65);
+        regen.tick(&mut zone);
+        regen.tick(&mut zone);
+        assert_eq!(zone.get(player).unwrap().hp, 75);
+    }
+
+    #[test]
+    fn test_full_and_dead_stay_quiet() {
+        let mut zone = Zone::new();
+        let mut regen = regen();
+        let full = zone.spawn(EntityKind::Player, Position::default(), 100);
+        let dead = zone.spawn(EntityKind::Player, Position::default(), 100);
+        *zone.get_mut(dead).unwrap().hp = 0;
+        regen.track(full, Vitals::new(40, 40, rates(5, 3)));
+        regen.track(dead, Vitals::new(0, 40, rates(5, 3)));
+
+        // Told once, then nothing changes
+        regen.tick(&mut zone);
+        assert_eq!(regen.tick(&mut zone).len(), 2);
+        regen.tick(&mut zone);
+        assert!(regen.tick(&mut zone).is_empty());
+        assert_eq!(zone.get(dead).unwrap().hp, 0);
+
+        // Despawned entities are forgotten
+        zone.despawn(full);
+        regen.tick(&mut zone);
+        regen.tick(&mut zone);
+        assert!(regen.vitals(full).is_none());
+    }
+}
@@ -0,0 +1,771 @@
+//! Running each zone on its own task
+//!
+//! A zone's state is owned by the task that ticks it; everything else talks
+//! to it through a [`ZoneHandle`], whose commands are applied between ticks.
+//! Zones never wait on each other: moving an entity between zones goes
+//! through the caller, which takes it out of one zone and hands it to the
+//! other. On the multi-threaded runtime a crowded zone only ties up the
+//! worker running it, and every zone keeps [`TickStats`] so hotspots show
+//! up in [`World::tick_stats`]. Zones that fall behind anyway make the
+//! world shed load (see [`OverloadConfig`]).
+
+use super::{
+    Broadcaster, Entity, EntityId, EntityKind, Followers, Load, MovementSync, OverloadConfig,
+    OverloadDetector, Position, Regen, RegenConfig, STATE_INVISIBLE, Vitals, Zone, ZoneId,
+    ZoneState,
+};
+use anyhow::{Result, anyhow};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::MissedTickBehavior;
+use tracing::{debug, info, warn};
+
+/// Default time between zone ticks
+pub const DEFAULT_TICK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Tick timings of one zone
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TickStats {
+    pub ticks: u64,
+    /// Ticks that took longer than the tick interval
+    pub overruns: u64,
+    pub last: Duration,
+    pub max: Duration,
+    pub total: Duration,
+    /// Entities in the zone at the last tick
+    pub entities: usize,
+    /// Monsters among them
+    pub monsters: usize,
+    /// Clients subscribed at the last tick
+    pub clients: usize,
+    /// Commands waiting after the last tick
+    pub queue_depth: usize,
+    /// Messages broadcast to clients since the zone started
+    pub messages: u64,
+}
+
+impl TickStats {
+    pub fn mean(&self) -> Duration {
+        match self.ticks {
+            0 => Duration::ZERO,
+            n => self.total / n as u32,
+        }
+    }
+
+    fn record(&mut self, elapsed: Duration, budget: Duration) {
+        self.ticks += 1;
+        if elapsed > budget {
+            self.overruns += 1;
+        }
+        self.last = elapsed;
+        self.max = self.max.max(elapsed);
+        self.total += elapsed;
+    }
+}
+
+enum ZoneCommand {
+    Spawn {
+        kind: EntityKind,
+        position: Position,
+        max_hp: u32,
+        reply: oneshot::Sender<EntityId>,
+    },
+    Despawn(EntityId),
+    Move {
+        id: EntityId,
+        position: Position,
+        direction: u16,
+    },
+    Get {
+        id: EntityId,
+        reply: oneshot::Sender<Option<Entity>>,
+    },
+    Subscribe {
+        session_id: u64,
+        entity: EntityId,
+        outbox: mpsc::Sender<Vec<u8>>,
+    },
+    Unsubscribe(u64),
+    /// Remove an entity that's moving to another zone
+    Leave {
+        id: EntityId,
+        reply: oneshot::Sender<Option<(Entity, Option<Vitals>)>>,
+    },
+    /// Take in an entity from another zone under a new ID
+    Arrive {
+        entity: Entity,
+        vitals: Option<Vitals>,
+        reply: oneshot::Sender<EntityId>,
+    },
+    /// Put back an entity whose transfer failed
+    Restore(Entity, Option<Vitals>),
+    TrackRegen {
+        id: EntityId,
+        vitals: Vitals,
+    },
+    EnterCombat(EntityId),
+    SetInvisible {
+        id: EntityId,
+        invisible: bool,
+    },
+    SetGm {
+        session_id: u64,
+        gm: bool,
+    },
+    Spectate {
+        session_id: u64,
+        target: Option<EntityId>,
+        reply: oneshot::Sender<bool>,
+    },
+    Follow {
+        id: EntityId,
+        target: Option<EntityId>,
+    },
+    Save {
+        reply: oneshot::Sender<ZoneState>,
+    },
+}
+
+/// Sends commands to a running zone
+#[derive(Clone)]
+pub struct ZoneHandle {
+    id: ZoneId,
+    commands: mpsc::Sender<ZoneCommand>,
+    stats: Arc<Mutex<TickStats>>,
+}
+
+impl ZoneHandle {
+    pub fn id(&self) -> ZoneId {
+        self.id
+    }
+
+    pub fn tick_stats(&self) -> TickStats {
+        *self.stats.lock().unwrap()
+    }
+
+    pub async fn spawn(
+        &self,
+        kind: EntityKind,
+        position: Position,
+        max_hp: u32,
+    ) -> Result<EntityId> {
+        self.request(|reply| ZoneCommand::Spawn {
+            kind,
+            position,
+            max_hp,
+            reply,
+        })
+        .await
+    }
+
+    pub async fn despawn(&self, id: EntityId) -> Result<()> {
+        self.send(ZoneCommand::Despawn(id)).await
+    }
+
+    pub async fn move_entity(
+        &self,
+        id: EntityId,
+        position: Position,
+        direction: u16,
+    ) -> Result<()> {
+        self.send(ZoneCommand::Move {
+            id,
+            position,
+            direction,
+        })
+        .await
+    }
+
+    pub async fn entity(&self, id: EntityId) -> Result<Option<Entity>> {
+        self.request(|reply| ZoneCommand::Get { id, reply }).await
+    }
+
+    /// Send session `session_id` this zone's updates, as seen by `entity`
+    pub async fn subscribe(
+        &self,
+        session_id: u64,
+        entity: EntityId,
+        outbox: mpsc::Sender<Vec<u8>>,
+    ) -> Result<()> {
+        self.send(ZoneCommand::Subscribe {
+            session_id,
+            entity,
+            outbox,
+        })
+        .await
+    }
+
+    pub async fn unsubscribe(&self, session_id: u64) -> Result<()> {
+        self.send(ZoneCommand::Unsubscribe(session_id)).await
+    }
+
+    /// Regenerate entity `id`'s HP and MP (see [`Regen`]); does
+    /// nothing in worlds without regen
+    pub async fn track_regen(&self, id: EntityId, vitals: Vitals) -> Result<()> {
+        self.send(ZoneCommand::TrackRegen { id, vitals }).await
+    }
+
+    /// Entity `id` hit or was hit, so it stops regenerating for a while
+    pub async fn enter_combat(&self, id: EntityId) -> Result<()> {
+        self.send(ZoneCommand::EnterCombat(id)).await
+    }
+
+    /// Hide entity `id` from every client but GMs', or show it again
+    pub async fn set_invisible(&self, id: EntityId, invisible: bool) -> Result<()> {
+        self.send(ZoneCommand::SetInvisible { id, invisible }).await
+    }
+
+    /// Let session `session_id` see invisible entities and spectate, or
+    /// stop it
+    pub async fn set_gm(&self, session_id: u64, gm: bool) -> Result<()> {
+        self.send(ZoneCommand::SetGm { session_id, gm }).await
+    }
+
+    /// Show GM session `session_id` what `target` sees and send it what
+    /// `target` is sent, or stop with `None`; whether it's spectating now
+    pub async fn spectate(&self, session_id: u64, target: Option<EntityId>) -> Result<bool> {
+        self.request(|reply| ZoneCommand::Spectate {
+            session_id,
+            target,
+            reply,
+        })
+        .await
+    }
+
+    /// Keep entity `id` where `target` is, or stop with `None`
+    pub async fn follow(&self, id: EntityId, target: Option<EntityId>) -> Result<()> {
+        self.send(ZoneCommand::Follow { id, target }).await
+    }
+
+    /// Put entity `id` where `target` in zone `to` is, moving it to that
+    /// zone if need be; returns its ID there
+    ///
+    /// `None` if either entity isn't there.
+    pub async fn teleport_to(
+        &self,
+        id: EntityId,
+        to: &ZoneHandle,
+        target: EntityId,
+    ) -> Result<Option<EntityId>> {
+        let Some(target) = to.entity(target).await? else {
+            return Ok(None);
+        };
+        let id = if to.id == self.id {
+            match self.entity(id).await? {
+                Some(_) => id,
+                None => return Ok(None),
+            }
+        } else {
+            match self.transfer(id, to).await? {
+                Some(id) => id,
+                None => return Ok(None),
+            }
+        };
+        to.move_entity(id, target.position, target.direction)
+            .await?;
+        Ok(Some(id))
+    }
+
+    /// The zone's state between ticks (see [`super::SaveState`])
+    pub async fn save(&self) -> Result<ZoneState> {
+        self.request(|reply| ZoneCommand::Save { reply }).await
+    }
+
+    /// Move entity `id` to zone `to`, returning its ID there
+    ///
+    /// `None` if there's no such entity. If `to` has stopped the entity is
+    /// put back in this zone. Its regen vitals go with it.
+    pub async fn transfer(&self, id: EntityId, to: &ZoneHandle) -> Result<Option<EntityId>> {
+        let Some((entity, vitals)) = self
+            .request(|reply| ZoneCommand::Leave { id, reply })
+            .await?
+        else {
+            return Ok(None);
+        };
+
+        match to
+            .request(|reply| ZoneCommand::Arrive {
+                entity,
+                vitals,
+                reply,
+            })
+            .await
+        {
+            Ok(new_id) => Ok(Some(new_id)),
+            Err(e) => {
+                self.send(ZoneCommand::Restore(entity, vitals)).await?;
+                Err(e)
+            }
+        }
+    }
+
+    async fn send(&self, command: ZoneCommand) -> Result<()> {
+        self.commands
+            .send(command)
+            .await
+            .map_err(|_| anyhow!("Zone {} has stopped", self.id.0))
+    }
+
+    async fn request<T>(
+        &self,
+        command: impl FnOnce(oneshot::Sender<T>) -> ZoneCommand,
+    ) -> Result<T> {
+        let (reply, response) = oneshot::channel();
+        self.send(command(reply)).await?;
+        response
+            .await
+            .map_err(|_| anyhow!("Zone {} has stopped", self.id.0))
+    }
+}
+
+/// Every running zone
+pub struct World {
+    tick_interval: Duration,
+    /// Movement every tick and no settings sent when unset
+    movement: Option<MovementSync>,
+    /// No regeneration when unset
+    regen: Option<RegenConfig>,
+    /// Never overloaded when unset
+    overload: Option<OverloadConfig>,
+    load: Load,
+    zones: HashMap<ZoneId, ZoneHandle>,
+}
+
+impl World {
+    pub fn new(tick_interval: Duration) -> Self {
+        Self {
+            tick_interval,
+            movement: None,
+            regen: None,
+            overload: None,
+            load: Load::new(),
+            zones: HashMap::new(),
+        }
+    }
+
+    /// Send movement to clients as `movement` says in zones started from
+    /// now on
+    pub fn with_movement_sync(mut self, movement: MovementSync) -> Self {
+        self.movement = Some(movement);
+        self
+    }
+
+    /// Regenerate tracked entities as `regen` says in zones started from
+    /// now on
+    pub fn with_regen(mut self, regen: RegenConfig) -> Self {
+        self.regen = Some(regen);
+        self
+    }
+
+    /// Shed load as `overload` says when zones started from now on fall
+    /// behind
+    pub fn with_overload(mut self, overload: OverloadConfig) -> Self {
+        self.overload = Some(overload);
+        self
+    }
+
+    /// Whether the world is shedding load, for background tasks to check
+    pub fn load(&self) -> &Load {
+        &self.load
+    }
+
+    /// Let a player into the channel, unless it's overloaded (see
+    /// [`Load::admit`])
+    pub fn admit(&self) -> Result<()> {
+        self.load.admit()
+    }
+
+    /// Start ticking `zone` on its own task
+    ///
+    /// Replaces any zone already running under `id`; that one stops once
+    /// its last handle is dropped.
+    pub fn start_zone(&mut self, id: ZoneId, zone: Zone) -> ZoneHandle {
+        let (commands, receiver) = mpsc::channel(256);
+        let handle = ZoneHandle {
+            id,
+            commands,
+            stats: Arc::new(Mutex::new(TickStats::default())),
+        };
+        tokio::spawn(run_zone(
+            id,
+            zone,
+            receiver,
+            self.tick_interval,
+            self.movement,
+            self.regen,
+            self.overload.map(|config| (config, self.load.clone())),
+            Arc::clone(&handle.stats),
+        ));
+        self.zones.insert(id, handle.clone());
+        handle
+    }
+
+    /// Stop running zone `id`; it stops once the last of its handles is
+    /// dropped
+    pub fn stop_zone(&mut self, id: ZoneId) -> Option<ZoneHandle> {
+        self.zones.remove(&id)
+    }
+
+    pub fn zone(&self, id: ZoneId) -> Option<&ZoneHandle> {
+        self.zones.get(&id)
+    }
+
+    /// Every running zone, in no particular order
+    pub fn zones(&self) -> impl Iterator<Item = &ZoneHandle> {
+        self.zones.values()
+    }
+
+    /// Every zone's tick timings, slowest (by mean tick) first
+    pub fn tick_stats(&self) -> Vec<(ZoneId, TickStats)> {
+        let mut stats: Vec<_> = self
+            .zones
+            .values()
+            .map(|zone| (zone.id, zone.tick_stats()))
+            .collect();
+        stats.sort_by_key(|(id, stats)| (std::cmp::Reverse(stats.mean()), *id));
+        stats
+    }
+
+    /// Log every zone's tick timings
+    pub fn log_tick_stats(&self) {
+        for (id, stats) in self.tick_stats() {
+            info!(
+                zone = id.0,
+                ticks = stats.ticks,
+                overruns = stats.overruns,
+                mean_us = stats.mean().as_micros() as u64,
+                max_us = stats.max.as_micros() as u64,
+                entities = stats.entities,
+                monsters = stats.monsters,
+                clients = stats.clients,
+                queue_depth = stats.queue_depth,
+                "Zone tick"
+            );
+        }
+    }
+}
+
+impl Default for World {
+    fn default() -> Self {
+        Self::new(DEFAULT_TICK_INTERVAL)
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn run_zone(
+    id: ZoneId,
+    mut zone: Zone,
+    mut commands: mpsc::Receiver<ZoneCommand>,
+    tick_interval: Duration,
+    movement: Option<MovementSync>,
+    regen: Option<RegenConfig>,
+    overload: Option<(OverloadConfig, Load)>,
+    stats: Arc<Mutex<TickStats>>,
+) {
+    let mut broadcaster = Broadcaster::default();
+    if let Some(movement) = movement {
+        broadcaster = broadcaster.with_movement_sync(&movement, tick_interval);
+    }
+    let mut regen = regen.map(|config| Regen::new(config, tick_interval));
+    let mut followers = Followers::new();
+    let mut detector = overload.map(|(config, load)| (OverloadDetector::new(config), load));
+    let mut tick: u64 = 0;
+    let mut interval = tokio::time::interval(tick_interval);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+
+    loop {
+        tokio::select! {
+            command = commands.recv() => match command {
+                Some(command) => apply(
+                    id,
+                    &mut zone,
+                    &mut broadcaster,
+                    regen.as_mut(),
+                    &mut followers,
+                    command,
+                ),
+                None => break,
+            },
+            _ = interval.tick() => {
+                let started = Instant::now();
+                tick += 1;
+                // Nobody sees an unwatched zone fall behind while the
+                // world sheds load
+                let simulate = detector.as_ref().is_none_or(|(detector, load)| {
+                    detector.config().simulates(load.is_overloaded(), broadcaster.len(), tick)
+                });
+                if simulate {
+                    followers.tick(&mut zone);
+                    if let Some(regen) = &mut regen {
+                        for (entity, message) in regen.tick(&mut zone) {
+                            broadcaster.send_to(entity, message);
+                        }
+                    }
+                }
+                let sent = broadcaster.broadcast(&zone.snapshot());
+                let elapsed = started.elapsed();
+
+                let queue_depth = commands.len();
+                if let Some((detector, load)) = &mut detector
+                    && let Some(overloaded) = detector.record(elapsed > tick_interval, queue_depth)
+                {
+                    load.set(id, overloaded);
+                    if overloaded {
+                        warn!(zone = id.0, queue_depth, "Zone overloaded, shedding load");
+                    } else {
+                        info!(zone = id.0, "Zone recovered from overload");
+                    }
+                }
+
+                let mut stats = stats.lock().unwrap();
+                stats.record(elapsed, tick_interval);
+                stats.entities = zone.len();
+                stats.monsters = zone
+                    .entities()
+                    .kinds()
+                    .iter()
+                    .filter(|&&kind| kind == EntityKind::Monster)
+                    .count();
+                stats.clients = broadcaster.len();
+                stats.queue_depth = queue_depth;
+                stats.messages += sent.messages as u64;
+                if elapsed > tick_interval {
+                    warn!(
+                        zone = id.0,
+                        elapsed_us = elapsed.as_micros() as u64,
+                        entities = zone.len(),
+                        messages = sent.messages,
+                        "Zone tick overran its budget"
+                    );
+                }
+            }
+        }
+    }
+
+    if let Some((_, load)) = &detector {
+        load.set(id, false);
+    }
+    debug!("Zone {} stopped", id.0);
+}
+
+fn apply(
+    id: ZoneId,
+    zone: &mut Zone,
+    broadcaster: &mut Broadcaster,
+    regen: Option<&mut Regen>,
+    followers: &mut Followers,
+    command: ZoneCommand,
+) {
+    // Replies are dropped if the requester has gone away
+    match command {
+        ZoneCommand::Save { reply } => {
+            let _ = reply.send(zone.save(id));
+        }
+        ZoneCommand::Spawn {
+            kind,
+            position,
+            max_hp,
+            reply,
+        } => {
+            let _ = reply.send(zone.spawn(kind, position, max_hp));
+        }
+        ZoneCommand::Despawn(id) => {
+            zone.despawn(id);
+            followers.remove(id);
+            if let Some(regen) = regen {
+                regen.untrack(id);
+            }
+        }
+        ZoneCommand::Move {
+            id,
+            position,
+            direction,
+        } => {
+            if let Some(entity) = zone.get_mut(id) {
+                *entity.position = position;
+                *entity.direction = direction;
+            }
+        }
+        ZoneCommand::Get { id, reply } => {
+            let _ = reply.send(zone.get(id));
+        }
+        ZoneCommand::Subscribe {
+            session_id,
+            entity,
+            outbox,
+        } => broadcaster.subscribe(session_id, entity, outbox),
+        ZoneCommand::Unsubscribe(session_id) => broadcaster.unsubscribe(session_id),
+        ZoneCommand::Leave { id, reply } => {
+            followers.remove(id);
+            let vitals = regen.and_then(|regen| regen.untrack(id));
+            let _ = reply.send(zone.despawn(id).map(|entity| (entity, vitals)));
+        }
+        ZoneCommand::Arrive {
+            entity,
+            vitals,
+            reply,
+        } => {
+            let new_id = zone.adopt(entity);
+            if let (Some(regen), Some(vitals)) = (regen, vitals) {
+                regen.track(new_id, vitals);
+            }
+            let _ = reply.send(new_id);
+        }
+        ZoneCommand::Restore(entity, vitals) => {
+            if let (Some(regen), Some(vitals)) = (regen, vitals) {
+                regen.track(entity.id, vitals);
+            }
+            zone.restore(entity);
+        }
+        ZoneCommand::TrackRegen { id, vitals } => {
+            if let Some(regen) = regen
+                && zone.get(id).is_some()
+            {
+                regen.track(id, vitals);
+            }
+        }
+        ZoneCommand::EnterCombat(id) => {
+            if let Some(regen) = regen {
+                regen.enter_combat(id);
+            }
+        }
+        ZoneCommand::SetInvisible { id, invisible } => {
+            if let Some(entity) = zone.get_mut(id) {
+                if invisible {
+                    *entity.state |= STATE_INVISIBLE;
+                } else {
+                    *entity.state &= !STATE_INVISIBLE;
+                }
+            }
+        }
+        ZoneCommand::SetGm { session_id, gm } => broadcaster.set_gm(session_id, gm),
+        ZoneCommand::Spectate {
+            session_id,
+            target,
+            reply,
+        } => {
+            let target = target.filter(|target| zone.get(*target).is_some());
+            let _ = reply.send(broadcaster.spectate(session_id, target));
+        }
+        ZoneCommand::Follow { id, target } => followers.follow(id, target),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::{Delta, NFY_VITALS, RegenRates};
+
+    #[tokio::test]
+    async fn test_zones_tick_independently() {
+        let mut world = World::new(Duration::from_millis(5));
+        let town = world.start_zone(ZoneId(1), Zone::new());
+        let field = world.start_zone(ZoneId(2), Zone::new());
+
+        let player = town
+            .spawn(EntityKind::Player, Position::default(), 100)
+            .await
+            .unwrap();
+        let poring = town
+            .spawn(EntityKind::Monster, Position::new(10.0, 0.0, 0.0), 50)
+            .await
+            .unwrap();
+        let (tx, mut rx) = mpsc::channel(8);
+        town.subscribe(1, player, tx).await.unwrap();
+
+        let delta = Delta::decode(&rx.recv().await.unwrap()).unwrap();
+        assert_eq!(delta.entered.len(), 1);
+        assert_eq!(delta.entered[0].id, poring);
+
+        // The monster wanders into the other zone
+        let moved = town.transfer(poring, &field).await.unwrap().unwrap();
+        assert_eq!(town.entity(poring).await.unwrap(), None);
+        let arrived = field.entity(moved).await.unwrap().unwrap();
+        assert_eq!(arrived.position, Position::new(10.0, 0.0, 0.0));
+        assert_eq!(arrived.max_hp, 50);
+        let delta = Delta::decode(&rx.recv().await.unwrap()).unwrap();
+        assert_eq!(delta.left, [poring]);
+        assert_eq!(town.transfer(poring, &field).await.unwrap(), None);
+
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        let stats = world.tick_stats();
+        assert_eq!(stats.len(), 2);
+        for (id, stats) in stats {
+            assert!(stats.ticks > 0);
+            assert_eq!(stats.entities, 1, "zone {}", id.0);
+        }
+        assert_eq!(town.tick_stats().clients, 1);
+        assert_eq!(town.tick_stats().monsters, 0);
+        assert_eq!(field.tick_stats().monsters, 1);
+        assert!(town.tick_stats().messages >= 2);
+    }
+
+    #[tokio::test]
+    async fn test_regen_follows_transfers() {
+        let mut world = World::new(Duration::from_millis(5)).with_regen(RegenConfig {
+            interval_ms: 5,
+            ..RegenConfig::default()
+        });
+        let town = world.start_zone(ZoneId(1), Zone::new());
+        let field = world.start_zone(ZoneId(2), Zone::new());
+
+        let player = town
+            .spawn(EntityKind::Player, Position::default(), 100)
+            .await
+            .unwrap();
+        let rates = RegenRates { hp: 0, mp: 1 };
+        town.track_regen(player, Vitals::new(0, 1000, rates))
+            .await
+            .unwrap();
+        let player = town.transfer(player, &field).await.unwrap().unwrap();
+        let (tx, mut rx) = mpsc::channel(8);
+        field.subscribe(1, player, tx).await.unwrap();
+
+        // The zone it arrived in regenerates it with the vitals it had
+        let vitals = tokio::time::timeout(Duration::from_secs(1), async {
+            loop {
+                let message = rx.recv().await.unwrap();
+                if message[..2] == NFY_VITALS.to_le_bytes() {
+                    return message;
+                }
+            }
+        })
+        .await
+        .expect("vitals didn't follow the player");
+        assert_eq!(&vitals[14..18], &1000u32.to_le_bytes());
+    }
+
+    #[tokio::test]
+    async fn test_transfer_to_stopped_zone() {
+        let mut world = World::default();
+        let town = world.start_zone(ZoneId(1), Zone::new());
+        let (commands, receiver) = mpsc::channel(1);
+        drop(receiver);
+        let stopped = ZoneHandle {
+            id: ZoneId(2),
+            commands,
+            stats: Arc::default(),
+        };
+
+        let player = town
+            .spawn(EntityKind::Player, Position::default(), 100)
+            .await
+            .unwrap();
+        assert!(town.transfer(player, &stopped).await.is_err());
+        assert!(town.entity(player).await.unwrap().is_some());
+    }
+
+    #[test]
+    fn test_tick_stats() {
+        let mut stats = TickStats::default();
+        let budget = Duration::from_millis(100);
+        stats.record(Duration::from_millis(40), budget);
+        stats.record(Duration::from_millis(150), budget);
+        assert_eq!(stats.ticks, 2);
+        assert_eq!(stats.overruns, 1);
+        assert_eq!(stats.max, Duration::from_millis(150));
+        assert_eq!(stats.mean(), Duration::from_millis(95));
+    }
+}
@@ -0,0 +1,208 @@
+//! Saving and restoring the world, for development
+//!
+//! A [`SaveState`] holds every zone's entities (players, monsters, NPCs,
+//! gathering nodes) and counters. With a save state path set in the `[dev]`
+//! section of `config/world.toml`, the world server writes one when it
+//! shuts down and starts from it next time, so working on a gameplay
+//! handler doesn't mean walking a client through login and the lobby
+//! after every rebuild:
+//!
+//! ```toml
+//! [dev]
+//! savestate = "dev/world.json"
+//! ```
+//!
+//! Save states are JSON so they can be edited by hand. Sessions aren't
+//! saved; clients reconnect as usual. There are no ground items or event
+//! timers in the world yet, so there's nothing of those to save.
+
+use super::{Entity, World, Zone, ZoneId};
+use anyhow::{Context, Result};
+use ro2_common::config::{self, Validate};
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+
+/// One zone's state
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ZoneState {
+    pub zone: u32,
+    /// Last entity ID handed out, so restored zones don't reuse IDs
+    pub next_id: u32,
+    pub tick: u32,
+    pub entities: Vec<Entity>,
+}
+
+/// Every zone's state at one moment
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SaveState {
+    /// Unix time it was taken
+    pub saved_at: i64,
+    /// Sorted by zone
+    pub zones: Vec<ZoneState>,
+}
+
+impl Zone {
+    /// Capture this zone's state
+    pub fn save(&self, id: ZoneId) -> ZoneState {
+        ZoneState {
+            zone: id.0,
+            next_id: self.next_id,
+            tick: self.tick,
+            entities: self.entities.iter().collect(),
+        }
+    }
+
+    /// A zone as `state` left it
+    pub fn from_state(state: &ZoneState) -> Self {
+        let mut zone = Zone::new();
+        for entity in &state.entities {
+            zone.restore(*entity);
+        }
+        let highest = state.entities.iter().map(|e| e.id.0).max().unwrap_or(0);
+        zone.next_id = state.next_id.max(highest);
+        zone.tick = state.tick;
+        zone
+    }
+}
+
+impl SaveState {
+    /// Capture every running zone
+    pub async fn take(world: &World, saved_at: i64) -> Result<Self> {
+        let mut zones = Vec::new();
+        for handle in world.zones() {
+            zones.push(handle.save().await?);
+        }
+        zones.sort_by_key(|zone| zone.zone);
+        Ok(Self { saved_at, zones })
+    }
+
+    /// Start every zone in the save state, replacing running ones with
+    /// the same ID
+    pub fn restore(&self, world: &mut World) {
+        for state in &self.zones {
+            world.start_zone(ZoneId(state.zone), Zone::from_state(state));
+        }
+    }
+
+    /// Entities in every zone
+    pub fn entities(&self) -> usize {
+        self.zones.iter().map(|zone| zone.entities.len()).sum()
+    }
+
+    pub fn write(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir)?;
+        }
+        // Write then rename, so a crash mid-write keeps the last good one
+        let temp = path.with_extension("tmp");
+        std::fs::write(&temp, serde_json::to_vec_pretty(self)?)
+            .with_context(|| format!("writing {}", temp.display()))?;
+        std::fs::rename(&temp, path).with_context(|| format!("writing {}", path.display()))
+    }
+
+    /// Read the save state at `path`; `None` if there isn't one
+    pub fn read(path: impl AsRef<Path>) -> Result<Option<Self>> {
+        let path = path.as_ref();
+        let data = match std::fs::read(path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).with_context(|| format!("reading {}", path.display())),
+        };
+        serde_json::from_slice(&data)
+            .map(Some)
+            .with_context(|| format!("parsing {}", path.display()))
+    }
+}
+
+/// Development settings
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct DevConfig {
+    /// Where the world is saved on shutdown and restored from on start;
+    /// off when unset
+    pub savestate: Option<PathBuf>,
+    /// Where live zone state is served over HTTP (see [`super::debug`]);
+    /// off when unset
+    pub debug_http: Option<SocketAddr>,
+}
+
+impl DevConfig {
+    /// Read the `[dev]` section of `path`
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        config::section(path, "dev")
+    }
+
+    /// Parse the `[dev]` section of TOML text
+    pub fn from_toml(text: &str) -> Result<Self> {
+        config::section_from_toml(text, "dev")
+    }
+}
+
+impl Validate for DevConfig {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::{EntityKind, Position};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_save_and_restore_world() {
+        let mut world = World::new(Duration::from_millis(5));
+        let town = world.start_zone(ZoneId(1), Zone::new());
+        world.start_zone(ZoneId(2), Zone::new());
+        let player = town
+            .spawn(EntityKind::Player, Position::new(1.0, 2.0, 3.0), 100)
+            .await
+            .unwrap();
+        let poring = town
+            .spawn(EntityKind::Monster, Position::default(), 50)
+            .await
+            .unwrap();
+        town.despawn(poring).await.unwrap();
+
+        let state = SaveState::take(&world, 1_700_000_000).await.unwrap();
+        assert_eq!(state.zones.len(), 2);
+        assert_eq!(state.entities(), 1);
+
+        let path = std::env::temp_dir().join(format!("ro2-savestate-{}.json", std::process::id()));
+        state.write(&path).unwrap();
+        let read = SaveState::read(&path).unwrap().unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(read, state);
+        assert_eq!(SaveState::read(&path).unwrap(), None);
+
+        let mut restarted = World::new(Duration::from_millis(5));
+        read.restore(&mut restarted);
+        let town = restarted.zone(ZoneId(1)).unwrap();
+        let entity = town.entity(player).await.unwrap().unwrap();
+        assert_eq!(entity.position, Position::new(1.0, 2.0, 3.0));
+        assert_eq!(entity.hp, 100);
+
+        // The despawned monster's ID isn't handed out again
+        let next = town
+            .spawn(EntityKind::Monster, Position::default(), 50)
+            .await
+            .unwrap();
+        assert!(next > poring);
+    }
+
+    #[test]
+    fn test_dev_config() {
+        assert_eq!(DevConfig::from_toml("").unwrap().savestate, None);
+        assert_eq!(
+            DevConfig::from_toml("[dev]\nsavestate = \"dev/world.json\"")
+                .unwrap()
+                .savestate,
+            Some(PathBuf::from("dev/world.json"))
+        );
+        assert_eq!(
+            DevConfig::from_toml("[dev]\ndebug_http = \"127.0.0.1:7499\"")
+                .unwrap()
+                .debug_http,
+            Some("127.0.0.1:7499".parse().unwrap())
+        );
+    }
+}
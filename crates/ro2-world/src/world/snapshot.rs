@@ -0,0 +1,460 @@
+//! Per-tick snapshots and per-client deltas
+//!
+//! Each client has a [`ClientView`]: the state of every entity as it was
+//! last sent to that client. Diffing the tick's [`Snapshot`] against it
+//! gives a [`Delta`], which is encoded as one game message:
+//!
+//! ```text
+//! [opcode: u16] [tick: u32]
+//! [entered: u16] { [id: u32] [kind: u8] [x y z: f32] [direction: u16] [hp: u32] [max_hp: u32] [state: u8] }*
+//! [updated: u16] { [id: u32] [mask: u8] [x y z: f32]? [direction: u16]? [hp: u32]? [state: u8]? }*
+//! [left: u16]    { [id: u32] }*
+//! ```
+//!
+//! `mask` says which fields of an update follow (see `UPDATE_*`). The
+//! client's own batched update message hasn't been identified yet, so the
+//! opcode is a placeholder like those in `MessageType`.
+
+use super::{Entity, EntityId, EntityKind, Position};
+use anyhow::{Result, anyhow};
+use ro2_common::wire::{WireReader, WireResult, WireWriter};
+use std::collections::BTreeMap;
+
+pub use ro2_common::protocol::opcodes::NFY_WORLD_DELTA;
+
+const UPDATE_POSITION: u8 = 0x01;
+const UPDATE_DIRECTION: u8 = 0x02;
+const UPDATE_HP: u8 = 0x04;
+const UPDATE_STATE: u8 = 0x08;
+
+/// Every entity of a zone at one tick
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    pub tick: u32,
+    /// Sorted by ID
+    entities: Vec<Entity>,
+}
+
+impl Snapshot {
+    pub fn new(tick: u32, entities: impl IntoIterator<Item = Entity>) -> Self {
+        let mut entities: Vec<Entity> = entities.into_iter().collect();
+        entities.sort_by_key(|e| e.id);
+        Self { tick, entities }
+    }
+
+    pub fn get(&self, id: EntityId) -> Option<&Entity> {
+        self.entities
+            .binary_search_by_key(&id, |e| e.id)
+            .ok()
+            .map(|i| &self.entities[i])
+    }
+
+    pub fn entities(&self) -> &[Entity] {
+        &self.entities
+    }
+}
+
+/// Changed fields of an entity the client already knows
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EntityUpdate {
+    pub id: EntityId,
+    pub position: Option<Position>,
+    pub direction: Option<u16>,
+    pub hp: Option<u32>,
+    pub state: Option<u8>,
+}
+
+impl EntityUpdate {
+    /// The fields of `current` that differ from `known`, if any
+    fn between(known: &Entity, current: &Entity) -> Option<Self> {
+        let update = Self {
+            id: current.id,
+            position: (known.position != current.position).then_some(current.position),
+            direction: (known.direction != current.direction).then_some(current.direction),
+            hp: (known.hp != current.hp).then_some(current.hp),
+            state: (known.state != current.state).then_some(current.state),
+        };
+        (update.mask() != 0).then_some(update)
+    }
+
+    fn mask(&self) -> u8 {
+        let mut mask = 0;
+        if self.position.is_some() {
+            mask |= UPDATE_POSITION;
+        }
+        if self.direction.is_some() {
+            mask |= UPDATE_DIRECTION;
+        }
+        if self.hp.is_some() {
+            mask |= UPDATE_HP;
+        }
+        if self.state.is_some() {
+            mask |= UPDATE_STATE;
+        }
+        mask
+    }
+
+    fn apply(&self, entity: &mut Entity) {
+        if let Some(position) = self.position {
+            entity.position = position;
+        }
+        if let Some(direction) = self.direction {
+            entity.direction = direction;
+        }
+        if let Some(hp) = self.hp {
+            entity.hp = hp;
+        }
+        if let Some(state) = self.state {
+            entity.state = state;
+        }
+    }
+}
+
+/// What a client needs to catch up with a snapshot
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Delta {
+    pub tick: u32,
+    /// Entities that came into view, in full
+    pub entered: Vec<Entity>,
+    pub updated: Vec<EntityUpdate>,
+    /// Entities that went out of view or despawned
+    pub left: Vec<EntityId>,
+}
+
+impl Delta {
+    pub fn is_empty(&self) -> bool {
+        self.entered.is_empty() && self.updated.is_empty() && self.left.is_empty()
+    }
+
+    /// Drop position and direction changes from the updates, leaving them
+    /// for a later delta: the client's view keeps the old values, so the
+    /// next diff picks them up again
+    pub fn hold_movement(&mut self) {
+        self.updated.retain_mut(|update| {
+            update.position = None;
+            update.direction = None;
+            update.mask() != 0
+        });
+    }
+
+    /// Number of entities the delta mentions
+    pub fn len(&self) -> usize {
+        self.entered.len() + self.updated.len() + self.left.len()
+    }
+
+    /// Encode as a game message (u16 opcode + payload)
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = WireWriter::message(NFY_WORLD_DELTA);
+        out.u32(self.tick);
+
+        out.u16(self.entered.len() as u16);
+        for entity in &self.entered {
+            out.u32(entity.id.0).u8(entity.kind as u8);
+            write_position(&mut out, &entity.position);
+            out.u16(entity.direction)
+                .u32(entity.hp)
+                .u32(entity.max_hp)
+                .u8(entity.state);
+        }
+
+        out.u16(self.updated.len() as u16);
+        for update in &self.updated {
+            out.u32(update.id.0).u8(update.mask());
+            if let Some(position) = &update.position {
+                write_position(&mut out, position);
+            }
+            if let Some(direction) = update.direction {
+                out.u16(direction);
+            }
+            if let Some(hp) = update.hp {
+                out.u32(hp);
+            }
+            if let Some(state) = update.state {
+                out.u8(state);
+            }
+        }
+
+        out.u16(self.left.len() as u16);
+        for id in &self.left {
+            out.u32(id.0);
+        }
+        out.into_bytes()
+    }
+
+    /// Decode a message built by [`Delta::encode`]
+    pub fn decode(message: &[u8]) -> Result<Self> {
+        let mut r = WireReader::new(message);
+        let opcode = r.u16("opcode")?;
+        if opcode != NFY_WORLD_DELTA {
+            return Err(anyhow!("Not a world delta: opcode 0x{:04x}", opcode));
+        }
+
+        let mut delta = Delta {
+            tick: r.u32("tick")?,
+            ..Delta::default()
+        };
+
+        for _ in 0..r.u16("entered count")? {
+            let id = EntityId(r.u32("entity ID")?);
+            let kind = r.u8("entity kind")?;
+            delta.entered.push(Entity {
+                id,
+                kind: EntityKind::from_u8(kind)
+                    .ok_or_else(|| anyhow!("Unknown entity kind {}", kind))?,
+                position: read_position(&mut r)?,
+                direction: r.u16("direction")?,
+                hp: r.u32("HP")?,
+                max_hp: r.u32("max HP")?,
+                state: r.u8("state")?,
+            });
+        }
+
+        for _ in 0..r.u16("updated count")? {
+            let id = EntityId(r.u32("entity ID")?);
+            let mask = r.u8("update mask")?;
+            let field = |bit: u8| mask & bit != 0;
+            delta.updated.push(EntityUpdate {
+                id,
+                position: field(UPDATE_POSITION)
+                    .then(|| read_position(&mut r))
+                    .transpose()?,
+                direction: field(UPDATE_DIRECTION)
+                    .then(|| r.u16("direction"))
+                    .transpose()?,
+                hp: field(UPDATE_HP).then(|| r.u32("HP")).transpose()?,
+                state: field(UPDATE_STATE).then(|| r.u8("state")).transpose()?,
+            });
+        }
+
+        for _ in 0..r.u16("left count")? {
+            delta.left.push(EntityId(r.u32("entity ID")?));
+        }
+        Ok(delta)
+    }
+}
+
+/// The entities a client has been sent, as it was sent them
+#[derive(Debug, Clone, Default)]
+pub struct ClientView {
+    known: BTreeMap<EntityId, Entity>,
+}
+
+impl ClientView {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of entities the client knows about
+    pub fn len(&self) -> usize {
+        self.known.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.known.is_empty()
+    }
+
+    /// What has changed in `snapshot` among the entities `visible` accepts
+    pub fn diff(&self, snapshot: &Snapshot, visible: impl Fn(&Entity) -> bool) -> Delta {
+        let mut delta = Delta {
+            tick: snapshot.tick,
+            ..Delta::default()
+        };
+
+        for entity in snapshot.entities().iter().filter(|e| visible(e)) {
+            match self.known.get(&entity.id) {
+                Some(known) => delta.updated.extend(EntityUpdate::between(known, entity)),
+                None => delta.entered.push(*entity),
+            }
+        }
+
+        delta.left = self
+            .known
+            .values()
+            .filter(|known| snapshot.get(known.id).is_none_or(|e| !visible(e)))
+            .map(|known| known.id)
+            .collect();
+        delta
+    }
+
+    /// Record that `delta` reached the client
+    pub fn commit(&mut self, delta: &Delta) {
+        for entity in &delta.entered {
+            self.known.insert(entity.id, *entity);
+        }
+        for update in &delta.updated {
+            if let Some(entity) = self.known.get_mut(&update.id) {
+                update.apply(entity);
+            }
+        }
+        for id in &delta.left {
+            self.known.remove(id);
+        }
+    }
+}
+
+fn write_position(out: &mut WireWriter, position: &Position) {
+    out.f32(position.x).f32(position.y).f32(position.z);
+}
+
+fn read_position(r: &mut WireReader) -> WireResult<Position> {
+    Ok(Position::new(r.f32("x")?, r.f32("y")?, r.f32("z")?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::Zone;
+    use proptest::prelude::*;
+
+    #[test]
+    fn test_delta_only_sends_changes() {
+        let mut zone = Zone::new();
+        let a = zone.spawn(EntityKind::Monster, Position::new(0.0, 0.0, 0.0), 100);
+        let b = zone.spawn(EntityKind::Npc, Position::new(10.0, 0.0, 0.0), 50);
+        let mut view = ClientView::new();
+
+        let first = view.diff(&zone.snapshot(), |_| true);
+        assert_eq!(first.entered.len(), 2);
+        view.commit(&first);
+
+        // Nothing moved: nothing to send
+        assert!(view.diff(&zone.snapshot(), |_| true).is_empty());
+
+        *zone.get_mut(a).unwrap().hp = 80;
+        zone.despawn(b);
+        let delta = view.diff(&zone.snapshot(), |_| true);
+        assert_eq!(
+            delta.updated,
+            vec![EntityUpdate {
+                id: a,
+                position: None,
+                direction: None,
+                hp: Some(80),
+                state: None,
+            }]
+        );
+        assert_eq!(delta.left, vec![b]);
+        view.commit(&delta);
+        assert_eq!(view.len(), 1);
+    }
+
+    #[test]
+    fn test_entering_and_leaving_view() {
+        let mut zone = Zone::new();
+        let far = zone.spawn(EntityKind::Monster, Position::new(500.0, 0.0, 0.0), 10);
+        let near = |e: &Entity| e.position.x < 100.0;
+        let mut view = ClientView::new();
+
+        let delta = view.diff(&zone.snapshot(), near);
+        assert!(delta.is_empty());
+
+        zone.get_mut(far).unwrap().position.x = 50.0;
+        let delta = view.diff(&zone.snapshot(), near);
+        assert_eq!(delta.entered.len(), 1);
+        view.commit(&delta);
+
+        zone.get_mut(far).unwrap().position.x = 150.0;
+        let delta = view.diff(&zone.snapshot(), near);
+        assert_eq!(delta.left, vec![far]);
+    }
+
+    #[test]
+    fn test_encode_roundtrip() {
+        let mut zone = Zone::new();
+        let a = zone.spawn(EntityKind::Player, Position::new(1.5, 2.5, -3.0), 200);
+        let mut view = ClientView::new();
+        let entered = view.diff(&zone.snapshot(), |_| true);
+        view.commit(&entered);
+
+        let message = entered.encode();
+        assert_eq!(&message[..2], &NFY_WORLD_DELTA.to_le_bytes());
+        assert_eq!(message.len(), 2 + 4 + 2 + 28 + 2 + 2);
+        assert_eq!(Delta::decode(&message).unwrap(), entered);
+
+        zone.get_mut(a).unwrap().position.y = 7.0;
+        *zone.get_mut(a).unwrap().state = 1;
+        let updated = view.diff(&zone.snapshot(), |_| true);
+        let message = updated.encode();
+        // ID, mask, position and state only
+        assert_eq!(message.len(), 2 + 4 + 2 + 2 + (4 + 1 + 12 + 1) + 2);
+        assert_eq!(Delta::decode(&message).unwrap(), updated);
+
+        assert!(Delta::decode(&message[..message.len() - 1]).is_err());
+    }
+
+    fn position() -> impl Strategy<Value = Position> {
+        let coordinate = -1.0e6f32..1.0e6f32;
+        (coordinate.clone(), coordinate.clone(), coordinate)
+            .prop_map(|(x, y, z)| Position::new(x, y, z))
+    }
+
+    fn entity() -> impl Strategy<Value = Entity> {
+        let kind = prop::sample::select(vec![
+            EntityKind::Player,
+            EntityKind::Monster,
+            EntityKind::Npc,
+            EntityKind::Node,
+        ]);
+        (
+            any::<u32>(),
+            kind,
+            position(),
+            any::<u16>(),
+            any::<u32>(),
+            any::<u32>(),
+            any::<u8>(),
+        )
+            .prop_map(
+                |(id, kind, position, direction, hp, max_hp, state)| Entity {
+                    id: EntityId(id),
+                    kind,
+                    position,
+                    direction,
+                    hp,
+                    max_hp,
+                    state,
+                },
+            )
+    }
+
+    fn update() -> impl Strategy<Value = EntityUpdate> {
+        (
+            any::<u32>(),
+            prop::option::of(position()),
+            prop::option::of(any::<u16>()),
+            prop::option::of(any::<u32>()),
+            prop::option::of(any::<u8>()),
+        )
+            .prop_map(|(id, position, direction, hp, state)| EntityUpdate {
+                id: EntityId(id),
+                position,
+                direction,
+                hp,
+                state,
+            })
+    }
+
+    proptest! {
+        #[test]
+        fn prop_delta_roundtrip(
+            tick in any::<u32>(),
+            entered in prop::collection::vec(entity(), 0..16),
+            updated in prop::collection::vec(update(), 0..16),
+            left in prop::collection::vec(any::<u32>().prop_map(EntityId), 0..16),
+        ) {
+            let delta = Delta { tick, entered, updated, left };
+            let message = delta.encode();
+            prop_assert_eq!(Delta::decode(&message).unwrap(), delta);
+            for cut in 0..message.len() {
+                prop_assert!(Delta::decode(&message[..cut]).is_err());
+            }
+        }
+
+        #[test]
+        fn prop_decode_garbage_never_panics(data in prop::collection::vec(any::<u8>(), 0..128)) {
+            let mut message = NFY_WORLD_DELTA.to_le_bytes().to_vec();
+            message.extend_from_slice(&data);
+            let _ = Delta::decode(&message);
+        }
+    }
+}
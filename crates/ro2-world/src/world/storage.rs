@@ -0,0 +1,241 @@
+//! Struct-of-arrays entity storage
+//!
+//! Each component lives in its own dense column, and an entity is the same
+//! row in every column. A tick system that only touches positions walks one
+//! contiguous `Vec<Position>` instead of chasing a pointer per entity, which
+//! keeps thousands of monsters within the tick budget (see
+//! `benches/entity_storage.rs`).
+//!
+//! Despawning swaps the last row into the hole, so rows aren't stable; go
+//! through [`EntityId`] to find an entity again later.
+
+use super::{Entity, EntityId, EntityKind, Position};
+use std::collections::HashMap;
+
+/// Every entity of a zone, one column per component
+#[derive(Debug, Default)]
+pub struct EntityStore {
+    ids: Vec<EntityId>,
+    kinds: Vec<EntityKind>,
+    positions: Vec<Position>,
+    directions: Vec<u16>,
+    hp: Vec<u32>,
+    max_hp: Vec<u32>,
+    states: Vec<u8>,
+    rows: HashMap<EntityId, usize>,
+}
+
+/// Mutable access to one entity's components
+#[derive(Debug)]
+pub struct EntityMut<'a> {
+    pub id: EntityId,
+    pub kind: EntityKind,
+    pub position: &'a mut Position,
+    pub direction: &'a mut u16,
+    pub hp: &'a mut u32,
+    pub max_hp: &'a mut u32,
+    pub state: &'a mut u8,
+}
+
+/// Every column at once, for systems that touch several components
+#[derive(Debug)]
+pub struct Columns<'a> {
+    pub ids: &'a [EntityId],
+    pub kinds: &'a [EntityKind],
+    pub positions: &'a mut [Position],
+    pub directions: &'a mut [u16],
+    pub hp: &'a mut [u32],
+    pub max_hp: &'a mut [u32],
+    pub states: &'a mut [u8],
+}
+
+impl EntityStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add `entity`, replacing any entity with the same ID
+    pub fn insert(&mut self, entity: Entity) {
+        if let Some(&row) = self.rows.get(&entity.id) {
+            self.kinds[row] = entity.kind;
+            self.positions[row] = entity.position;
+            self.directions[row] = entity.direction;
+            self.hp[row] = entity.hp;
+            self.max_hp[row] = entity.max_hp;
+            self.states[row] = entity.state;
+            return;
+        }
+
+        self.rows.insert(entity.id, self.ids.len());
+        self.ids.push(entity.id);
+        self.kinds.push(entity.kind);
+        self.positions.push(entity.position);
+        self.directions.push(entity.direction);
+        self.hp.push(entity.hp);
+        self.max_hp.push(entity.max_hp);
+        self.states.push(entity.state);
+    }
+
+    pub fn remove(&mut self, id: EntityId) -> Option<Entity> {
+        let row = self.rows.remove(&id)?;
+        let entity = self.row(row);
+
+        self.ids.swap_remove(row);
+        self.kinds.swap_remove(row);
+        self.positions.swap_remove(row);
+        self.directions.swap_remove(row);
+        self.hp.swap_remove(row);
+        self.max_hp.swap_remove(row);
+        self.states.swap_remove(row);
+        if let Some(&moved) = self.ids.get(row) {
+            self.rows.insert(moved, row);
+        }
+
+        Some(entity)
+    }
+
+    pub fn get(&self, id: EntityId) -> Option<Entity> {
+        self.rows.get(&id).map(|&row| self.row(row))
+    }
+
+    pub fn get_mut(&mut self, id: EntityId) -> Option<EntityMut<'_>> {
+        let row = *self.rows.get(&id)?;
+        Some(EntityMut {
+            id,
+            kind: self.kinds[row],
+            position: &mut self.positions[row],
+            direction: &mut self.directions[row],
+            hp: &mut self.hp[row],
+            max_hp: &mut self.max_hp[row],
+            state: &mut self.states[row],
+        })
+    }
+
+    pub fn contains(&self, id: EntityId) -> bool {
+        self.rows.contains_key(&id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+
+    /// Every entity, in row order
+    pub fn iter(&self) -> impl Iterator<Item = Entity> + '_ {
+        (0..self.len()).map(|row| self.row(row))
+    }
+
+    pub fn ids(&self) -> &[EntityId] {
+        &self.ids
+    }
+
+    pub fn kinds(&self) -> &[EntityKind] {
+        &self.kinds
+    }
+
+    pub fn positions(&self) -> &[Position] {
+        &self.positions
+    }
+
+    pub fn positions_mut(&mut self) -> &mut [Position] {
+        &mut self.positions
+    }
+
+    pub fn hp(&self) -> &[u32] {
+        &self.hp
+    }
+
+    pub fn hp_mut(&mut self) -> &mut [u32] {
+        &mut self.hp
+    }
+
+    pub fn states_mut(&mut self) -> &mut [u8] {
+        &mut self.states
+    }
+
+    /// Borrow every column, mutably where the component can change
+    pub fn columns_mut(&mut self) -> Columns<'_> {
+        Columns {
+            ids: &self.ids,
+            kinds: &self.kinds,
+            positions: &mut self.positions,
+            directions: &mut self.directions,
+            hp: &mut self.hp,
+            max_hp: &mut self.max_hp,
+            states: &mut self.states,
+        }
+    }
+
+    fn row(&self, row: usize) -> Entity {
+        Entity {
+            id: self.ids[row],
+            kind: self.kinds[row],
+            position: self.positions[row],
+            direction: self.directions[row],
+            hp: self.hp[row],
+            max_hp: self.max_hp[row],
+            state: self.states[row],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn monster(id: u32) -> Entity {
+        Entity {
+            id: EntityId(id),
+            kind: EntityKind::Monster,
+            position: Position::new(id as f32, 0.0, 0.0),
+            direction: 0,
+            hp: 10,
+            max_hp: 10,
+            state: 0,
+        }
+    }
+
+    #[test]
+    fn test_remove_keeps_rows_consistent() {
+        let mut store = EntityStore::new();
+        for id in 1..=4 {
+            store.insert(monster(id));
+        }
+
+        // The last row moves into the removed one
+        assert_eq!(store.remove(EntityId(2)), Some(monster(2)));
+        assert_eq!(store.ids(), &[EntityId(1), EntityId(4), EntityId(3)]);
+        assert_eq!(store.get(EntityId(4)), Some(monster(4)));
+        assert_eq!(store.remove(EntityId(2)), None);
+
+        *store.get_mut(EntityId(4)).unwrap().hp = 3;
+        assert_eq!(store.hp(), &[10, 3, 10]);
+
+        store.remove(EntityId(3));
+        store.remove(EntityId(1));
+        assert_eq!(
+            store.iter().collect::<Vec<_>>(),
+            [Entity {
+                hp: 3,
+                ..monster(4)
+            }]
+        );
+    }
+
+    #[test]
+    fn test_insert_replaces() {
+        let mut store = EntityStore::new();
+        store.insert(monster(1));
+        store.insert(Entity {
+            kind: EntityKind::Npc,
+            hp: 1,
+            ..monster(1)
+        });
+        assert_eq!(store.len(), 1);
+        assert_eq!(store.get(EntityId(1)).unwrap().kind, EntityKind::Npc);
+        assert_eq!(store.get(EntityId(1)).unwrap().hp, 1);
+    }
+}
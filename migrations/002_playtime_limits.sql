@@ -0,0 +1,20 @@
+-- Per-account playtime limits (parental controls)
+-- SQLite version
+
+-- Accounts without a row here are unrestricted
+CREATE TABLE IF NOT EXISTS account_playtime_limits (
+    account_id INTEGER PRIMARY KEY,
+    daily_cap_minutes INTEGER,          -- NULL = no daily cap
+    allowed_start_minute INTEGER,       -- Minutes after midnight, server local time
+    allowed_end_minute INTEGER,         -- Before start = window wraps past midnight; NULL = any hour
+    FOREIGN KEY (account_id) REFERENCES accounts(id) ON DELETE CASCADE
+);
+
+-- Time played per day by restricted accounts
+CREATE TABLE IF NOT EXISTS account_playtime (
+    account_id INTEGER NOT NULL,
+    day TEXT NOT NULL,                  -- YYYY-MM-DD, server local time
+    seconds_played INTEGER NOT NULL DEFAULT 0,
+    PRIMARY KEY (account_id, day),
+    FOREIGN KEY (account_id) REFERENCES accounts(id) ON DELETE CASCADE
+);
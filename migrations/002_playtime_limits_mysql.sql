@@ -0,0 +1,20 @@
+-- Per-account playtime limits (parental controls)
+-- MySQL version
+
+-- Accounts without a row here are unrestricted
+CREATE TABLE IF NOT EXISTS account_playtime_limits (
+    account_id INT UNSIGNED PRIMARY KEY,
+    daily_cap_minutes INT UNSIGNED,
+    allowed_start_minute SMALLINT UNSIGNED,
+    allowed_end_minute SMALLINT UNSIGNED,
+    FOREIGN KEY (account_id) REFERENCES accounts(id) ON DELETE CASCADE
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
+
+-- Time played per day by restricted accounts
+CREATE TABLE IF NOT EXISTS account_playtime (
+    account_id INT UNSIGNED NOT NULL,
+    day DATE NOT NULL,
+    seconds_played INT UNSIGNED NOT NULL DEFAULT 0,
+    PRIMARY KEY (account_id, day),
+    FOREIGN KEY (account_id) REFERENCES accounts(id) ON DELETE CASCADE
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
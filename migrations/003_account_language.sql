@@ -0,0 +1,5 @@
+-- Per-account language for server-sent text (see locale/)
+-- SQLite version
+
+-- NULL = the server's default language
+ALTER TABLE accounts ADD COLUMN language TEXT;
@@ -0,0 +1,5 @@
+-- Per-account language for server-sent text (see locale/)
+-- MySQL version
+
+-- NULL = the server's default language
+ALTER TABLE accounts ADD COLUMN language VARCHAR(16) NULL;
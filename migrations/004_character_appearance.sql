@@ -0,0 +1,12 @@
+-- Character slot, appearance and job progression for the lobby character list
+-- SQLite version
+
+ALTER TABLE characters ADD COLUMN slot_index INTEGER NOT NULL DEFAULT 0;  -- Position in the character select screen
+ALTER TABLE characters ADD COLUMN gender INTEGER NOT NULL DEFAULT 0;      -- 0 = male, 1 = female
+ALTER TABLE characters ADD COLUMN hair_style INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE characters ADD COLUMN hair_color INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE characters ADD COLUMN face INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE characters ADD COLUMN job_level INTEGER NOT NULL DEFAULT 1;
+ALTER TABLE characters ADD COLUMN job_experience BIGINT NOT NULL DEFAULT 0;
+
+CREATE INDEX IF NOT EXISTS idx_characters_account_slot ON characters(account_id, slot_index);
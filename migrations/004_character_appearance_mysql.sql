@@ -0,0 +1,12 @@
+-- Character slot, appearance and job progression for the lobby character list
+-- MySQL version
+
+ALTER TABLE characters
+    ADD COLUMN slot_index TINYINT UNSIGNED NOT NULL DEFAULT 0,
+    ADD COLUMN gender TINYINT UNSIGNED NOT NULL DEFAULT 0,
+    ADD COLUMN hair_style SMALLINT UNSIGNED NOT NULL DEFAULT 0,
+    ADD COLUMN hair_color SMALLINT UNSIGNED NOT NULL DEFAULT 0,
+    ADD COLUMN face SMALLINT UNSIGNED NOT NULL DEFAULT 0,
+    ADD COLUMN job_level INT UNSIGNED NOT NULL DEFAULT 1,
+    ADD COLUMN job_experience BIGINT UNSIGNED NOT NULL DEFAULT 0,
+    ADD INDEX idx_account_slot (account_id, slot_index);
@@ -0,0 +1,10 @@
+-- Skills learned by each character
+-- SQLite version
+
+CREATE TABLE IF NOT EXISTS character_skills (
+    character_id INTEGER NOT NULL,
+    skill_id INTEGER NOT NULL,          -- Skill template ID
+    level INTEGER NOT NULL DEFAULT 1,
+    PRIMARY KEY (character_id, skill_id),
+    FOREIGN KEY (character_id) REFERENCES characters(id) ON DELETE CASCADE
+);
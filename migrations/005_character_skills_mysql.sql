@@ -0,0 +1,10 @@
+-- Skills learned by each character
+-- MySQL version
+
+CREATE TABLE IF NOT EXISTS character_skills (
+    character_id INT UNSIGNED NOT NULL,
+    skill_id INT UNSIGNED NOT NULL,
+    level SMALLINT UNSIGNED NOT NULL DEFAULT 1,
+    PRIMARY KEY (character_id, skill_id),
+    FOREIGN KEY (character_id) REFERENCES characters(id) ON DELETE CASCADE
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
@@ -0,0 +1,11 @@
+-- Life skill (profession) levels per character
+-- SQLite version
+
+CREATE TABLE IF NOT EXISTS character_professions (
+    character_id INTEGER NOT NULL,
+    profession_id INTEGER NOT NULL,     -- 1 = mining, 2 = herbalism, 3 = logging, 4 = cooking, 5 = crafting
+    level INTEGER NOT NULL DEFAULT 1,
+    experience INTEGER NOT NULL DEFAULT 0,  -- Towards the next level
+    PRIMARY KEY (character_id, profession_id),
+    FOREIGN KEY (character_id) REFERENCES characters(id) ON DELETE CASCADE
+);
@@ -0,0 +1,11 @@
+-- Life skill (profession) levels per character
+-- MySQL version
+
+CREATE TABLE IF NOT EXISTS character_professions (
+    character_id INT UNSIGNED NOT NULL,
+    profession_id TINYINT UNSIGNED NOT NULL,
+    level SMALLINT UNSIGNED NOT NULL DEFAULT 1,
+    experience INT UNSIGNED NOT NULL DEFAULT 0,
+    PRIMARY KEY (character_id, profession_id),
+    FOREIGN KEY (character_id) REFERENCES characters(id) ON DELETE CASCADE
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
@@ -0,0 +1,20 @@
+-- Khara challenge progress and the titles they unlock
+-- SQLite version
+
+CREATE TABLE IF NOT EXISTS character_khara (
+    character_id INTEGER NOT NULL,
+    challenge_id INTEGER NOT NULL,
+    progress INTEGER NOT NULL DEFAULT 0,    -- Kills, items or level so far
+    PRIMARY KEY (character_id, challenge_id),
+    FOREIGN KEY (character_id) REFERENCES characters(id) ON DELETE CASCADE
+);
+
+CREATE TABLE IF NOT EXISTS character_titles (
+    character_id INTEGER NOT NULL,
+    title_id INTEGER NOT NULL,
+    unlocked_at INTEGER NOT NULL,           -- Unix timestamp
+    PRIMARY KEY (character_id, title_id),
+    FOREIGN KEY (character_id) REFERENCES characters(id) ON DELETE CASCADE
+);
+
+ALTER TABLE characters ADD COLUMN title_id INTEGER;  -- Equipped title; NULL = none
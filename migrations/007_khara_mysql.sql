@@ -0,0 +1,21 @@
+-- Khara challenge progress and the titles they unlock
+-- MySQL version
+
+CREATE TABLE IF NOT EXISTS character_khara (
+    character_id INT UNSIGNED NOT NULL,
+    challenge_id INT UNSIGNED NOT NULL,
+    progress INT UNSIGNED NOT NULL DEFAULT 0,
+    PRIMARY KEY (character_id, challenge_id),
+    FOREIGN KEY (character_id) REFERENCES characters(id) ON DELETE CASCADE
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
+
+CREATE TABLE IF NOT EXISTS character_titles (
+    character_id INT UNSIGNED NOT NULL,
+    title_id INT UNSIGNED NOT NULL,
+    unlocked_at BIGINT UNSIGNED NOT NULL,
+    PRIMARY KEY (character_id, title_id),
+    FOREIGN KEY (character_id) REFERENCES characters(id) ON DELETE CASCADE
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
+
+ALTER TABLE characters
+    ADD COLUMN title_id INT UNSIGNED NULL;
@@ -0,0 +1,18 @@
+-- Paid renames and appearance changes, with the names characters had before
+-- SQLite version
+
+CREATE TABLE IF NOT EXISTS character_name_history (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    character_id INTEGER NOT NULL,
+    old_name TEXT NOT NULL COLLATE NOCASE,
+    new_name TEXT NOT NULL COLLATE NOCASE,
+    changed_at INTEGER NOT NULL,            -- Unix timestamp
+    changed_by TEXT,                        -- Admin who renamed it; NULL = the player
+    FOREIGN KEY (character_id) REFERENCES characters(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_name_history_character ON character_name_history(character_id);
+CREATE INDEX IF NOT EXISTS idx_name_history_old_name ON character_name_history(old_name);
+
+ALTER TABLE characters ADD COLUMN renamed_at INTEGER;             -- Last paid rename, for the cooldown
+ALTER TABLE characters ADD COLUMN appearance_changed_at INTEGER;  -- Last paid appearance change
@@ -0,0 +1,18 @@
+-- Paid renames and appearance changes, with the names characters had before
+-- MySQL version
+
+CREATE TABLE IF NOT EXISTS character_name_history (
+    id BIGINT UNSIGNED AUTO_INCREMENT PRIMARY KEY,
+    character_id INT UNSIGNED NOT NULL,
+    old_name VARCHAR(32) NOT NULL,
+    new_name VARCHAR(32) NOT NULL,
+    changed_at BIGINT UNSIGNED NOT NULL,
+    changed_by VARCHAR(32) NULL,
+    INDEX idx_character (character_id),
+    INDEX idx_old_name (old_name),
+    FOREIGN KEY (character_id) REFERENCES characters(id) ON DELETE CASCADE
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
+
+ALTER TABLE characters
+    ADD COLUMN renamed_at BIGINT UNSIGNED NULL,
+    ADD COLUMN appearance_changed_at BIGINT UNSIGNED NULL;
@@ -0,0 +1,17 @@
+-- Account deactivation, restoring and anonymization
+-- SQLite version
+
+ALTER TABLE accounts ADD COLUMN deactivated_at INTEGER;  -- Unix timestamp, NULL = active
+ALTER TABLE accounts ADD COLUMN anonymized_at INTEGER;   -- Unix timestamp, NULL = personal data kept
+
+-- Who deactivated, restored or anonymized an account. Not cascaded, so the
+-- record outlives the account's personal data.
+CREATE TABLE IF NOT EXISTS account_audit_log (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    account_id INTEGER NOT NULL,
+    action TEXT NOT NULL,                   -- 'deactivate', 'restore' or 'anonymize'
+    actor TEXT NOT NULL,                    -- Admin name, or 'player' / 'system'
+    at INTEGER NOT NULL                     -- Unix timestamp
+);
+
+CREATE INDEX IF NOT EXISTS idx_account_audit_account ON account_audit_log(account_id);
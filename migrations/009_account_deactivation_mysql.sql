@@ -0,0 +1,15 @@
+-- Account deactivation, restoring and anonymization
+-- MySQL version
+
+ALTER TABLE accounts
+    ADD COLUMN deactivated_at BIGINT UNSIGNED NULL,
+    ADD COLUMN anonymized_at BIGINT UNSIGNED NULL;
+
+CREATE TABLE IF NOT EXISTS account_audit_log (
+    id BIGINT UNSIGNED AUTO_INCREMENT PRIMARY KEY,
+    account_id INT UNSIGNED NOT NULL,
+    action VARCHAR(16) NOT NULL,
+    actor VARCHAR(32) NOT NULL,
+    at BIGINT UNSIGNED NOT NULL,
+    INDEX idx_account (account_id)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
@@ -0,0 +1,11 @@
+-- Idempotency keys of item and zeny transactions (trades, mail claims,
+-- auction settlements, shop purchases)
+-- SQLite version
+
+-- A key is recorded in the same database transaction as the items it
+-- moved, so a request replayed or sent twice finds it and does nothing.
+CREATE TABLE IF NOT EXISTS item_transactions (
+    idempotency_key TEXT PRIMARY KEY,
+    kind TEXT NOT NULL,                     -- 'trade', 'mail_claim', 'auction_settle' or 'shop_purchase'
+    committed_at INTEGER NOT NULL           -- Unix timestamp
+);
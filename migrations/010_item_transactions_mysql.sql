@@ -0,0 +1,9 @@
+-- Idempotency keys of item and zeny transactions (trades, mail claims,
+-- auction settlements, shop purchases)
+-- MySQL version
+
+CREATE TABLE IF NOT EXISTS item_transactions (
+    idempotency_key VARCHAR(64) NOT NULL PRIMARY KEY,
+    kind VARCHAR(16) NOT NULL,
+    committed_at BIGINT UNSIGNED NOT NULL
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
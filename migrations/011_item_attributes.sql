@@ -0,0 +1,9 @@
+-- Bound and rental items
+-- SQLite version
+
+-- A bound item can't be traded, mailed or auctioned; it still sells to
+-- shops. Items bind when equipped or picked up, as their template says.
+ALTER TABLE inventory ADD COLUMN is_bound INTEGER DEFAULT 0;    -- Boolean
+ALTER TABLE inventory ADD COLUMN expires_at INTEGER;            -- Unix timestamp rentals vanish at; NULL = never
+
+CREATE INDEX IF NOT EXISTS idx_inventory_expires_at ON inventory(expires_at);
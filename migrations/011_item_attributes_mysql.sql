@@ -0,0 +1,7 @@
+-- Bound and rental items
+-- MySQL version
+
+ALTER TABLE inventory
+    ADD COLUMN is_bound TINYINT(1) DEFAULT 0,
+    ADD COLUMN expires_at BIGINT UNSIGNED NULL,
+    ADD INDEX idx_expires_at (expires_at);
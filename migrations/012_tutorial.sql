@@ -0,0 +1,9 @@
+-- First-login tutorial progress
+-- SQLite version
+
+CREATE TABLE IF NOT EXISTS character_tutorial (
+    character_id INTEGER PRIMARY KEY,
+    step INTEGER NOT NULL DEFAULT 0,        -- Index of the current step
+    completed_at INTEGER,                   -- Unix timestamp; NULL = still in it
+    FOREIGN KEY (character_id) REFERENCES characters(id) ON DELETE CASCADE
+);
@@ -0,0 +1,9 @@
+-- First-login tutorial progress
+-- MySQL version
+
+CREATE TABLE IF NOT EXISTS character_tutorial (
+    character_id INT UNSIGNED NOT NULL PRIMARY KEY,
+    step INT UNSIGNED NOT NULL DEFAULT 0,
+    completed_at BIGINT UNSIGNED NULL,
+    FOREIGN KEY (character_id) REFERENCES characters(id) ON DELETE CASCADE
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
@@ -0,0 +1,13 @@
+-- Extra character slots bought or granted per account
+-- SQLite version
+
+CREATE TABLE IF NOT EXISTS account_slot_grants (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    account_id INTEGER NOT NULL,
+    slots INTEGER NOT NULL,                 -- Extra slots this grant adds
+    granted_at INTEGER NOT NULL,            -- Unix timestamp
+    granted_by TEXT NOT NULL,               -- Admin name, or what sold it (e.g. the cash shop)
+    FOREIGN KEY (account_id) REFERENCES accounts(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_account_slot_grants_account ON account_slot_grants(account_id);
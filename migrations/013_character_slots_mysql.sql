@@ -0,0 +1,12 @@
+-- Extra character slots bought or granted per account
+-- MySQL version
+
+CREATE TABLE IF NOT EXISTS account_slot_grants (
+    id INT UNSIGNED AUTO_INCREMENT PRIMARY KEY,
+    account_id INT UNSIGNED NOT NULL,
+    slots INT UNSIGNED NOT NULL,
+    granted_at BIGINT UNSIGNED NOT NULL,
+    granted_by VARCHAR(64) NOT NULL,
+    INDEX idx_account_slot_grants_account (account_id),
+    FOREIGN KEY (account_id) REFERENCES accounts(id) ON DELETE CASCADE
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
@@ -0,0 +1,17 @@
+-- Server-wide messages: megaphones and GM announcements
+-- SQLite version
+--
+-- World servers relay every new row to their players, so this is both how
+-- a message reaches every channel and the record of who sent what.
+
+CREATE TABLE IF NOT EXISTS global_messages (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    kind TEXT NOT NULL,                     -- 'megaphone' or 'announcement'
+    sender TEXT NOT NULL,                   -- Character name, or the GM's
+    character_id INTEGER,                   -- Who used the megaphone; NULL for announcements
+    item_id INTEGER,                        -- The megaphone item used
+    text TEXT NOT NULL,
+    sent_at INTEGER NOT NULL                -- Unix timestamp
+);
+
+CREATE INDEX IF NOT EXISTS idx_global_messages_character ON global_messages(character_id, sent_at);
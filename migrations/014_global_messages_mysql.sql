@@ -0,0 +1,13 @@
+-- Server-wide messages: megaphones and GM announcements
+-- MySQL version
+
+CREATE TABLE IF NOT EXISTS global_messages (
+    id BIGINT UNSIGNED AUTO_INCREMENT PRIMARY KEY,
+    kind VARCHAR(16) NOT NULL,
+    sender VARCHAR(64) NOT NULL,
+    character_id INT UNSIGNED NULL,
+    item_id INT UNSIGNED NULL,
+    text VARCHAR(255) NOT NULL,
+    sent_at BIGINT UNSIGNED NOT NULL,
+    INDEX idx_global_messages_character (character_id, sent_at)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
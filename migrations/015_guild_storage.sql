@@ -0,0 +1,58 @@
+-- Guilds and their shared storage
+-- SQLite version
+
+CREATE TABLE IF NOT EXISTS guilds (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    name TEXT UNIQUE NOT NULL COLLATE NOCASE,
+    zeny BIGINT NOT NULL DEFAULT 0,         -- In the guild storage
+    created_at INTEGER NOT NULL             -- Unix timestamp
+);
+
+-- What each rank may do with the storage. Rank 0 is the guild master;
+-- a rank without a row may do nothing.
+CREATE TABLE IF NOT EXISTS guild_ranks (
+    guild_id INTEGER NOT NULL,
+    rank INTEGER NOT NULL,
+    name TEXT NOT NULL,
+    can_view INTEGER NOT NULL DEFAULT 0,    -- Boolean
+    can_deposit INTEGER NOT NULL DEFAULT 0, -- Boolean
+    items_per_day INTEGER DEFAULT 0,        -- Items a member may withdraw a day; NULL = no limit
+    zeny_per_day BIGINT DEFAULT 0,          -- Zeny a member may withdraw a day; NULL = no limit
+    PRIMARY KEY (guild_id, rank),
+    FOREIGN KEY (guild_id) REFERENCES guilds(id) ON DELETE CASCADE
+);
+
+CREATE TABLE IF NOT EXISTS guild_members (
+    character_id INTEGER PRIMARY KEY,       -- One guild per character
+    guild_id INTEGER NOT NULL,
+    rank INTEGER NOT NULL,
+    joined_at INTEGER NOT NULL,             -- Unix timestamp
+    FOREIGN KEY (guild_id) REFERENCES guilds(id) ON DELETE CASCADE,
+    FOREIGN KEY (character_id) REFERENCES characters(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_guild_members_guild ON guild_members(guild_id);
+
+CREATE TABLE IF NOT EXISTS guild_storage (
+    guild_id INTEGER NOT NULL,
+    slot_index INTEGER NOT NULL,
+    item_id INTEGER NOT NULL,
+    quantity INTEGER NOT NULL,
+    PRIMARY KEY (guild_id, slot_index),
+    FOREIGN KEY (guild_id) REFERENCES guilds(id) ON DELETE CASCADE
+);
+
+-- Every deposit and withdrawal, written in the same transaction as the
+-- move; the daily withdrawal limits are counted from it
+CREATE TABLE IF NOT EXISTS guild_storage_log (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    guild_id INTEGER NOT NULL,
+    character_id INTEGER NOT NULL,
+    action TEXT NOT NULL,                   -- 'deposit_item', 'withdraw_item', 'deposit_zeny' or 'withdraw_zeny'
+    item_id INTEGER,                        -- NULL for zeny
+    amount BIGINT NOT NULL,                 -- Items or zeny
+    at INTEGER NOT NULL,                    -- Unix timestamp
+    FOREIGN KEY (guild_id) REFERENCES guilds(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_guild_storage_log_member ON guild_storage_log(guild_id, character_id, action, at);
@@ -0,0 +1,52 @@
+-- Guilds and their shared storage
+-- MySQL version
+
+CREATE TABLE IF NOT EXISTS guilds (
+    id INT UNSIGNED AUTO_INCREMENT PRIMARY KEY,
+    name VARCHAR(32) UNIQUE NOT NULL,
+    zeny BIGINT UNSIGNED NOT NULL DEFAULT 0,
+    created_at BIGINT UNSIGNED NOT NULL
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
+
+CREATE TABLE IF NOT EXISTS guild_ranks (
+    guild_id INT UNSIGNED NOT NULL,
+    `rank` INT UNSIGNED NOT NULL,
+    name VARCHAR(32) NOT NULL,
+    can_view TINYINT(1) NOT NULL DEFAULT 0,
+    can_deposit TINYINT(1) NOT NULL DEFAULT 0,
+    items_per_day INT UNSIGNED NULL DEFAULT 0,
+    zeny_per_day BIGINT UNSIGNED NULL DEFAULT 0,
+    PRIMARY KEY (guild_id, `rank`),
+    FOREIGN KEY (guild_id) REFERENCES guilds(id) ON DELETE CASCADE
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
+
+CREATE TABLE IF NOT EXISTS guild_members (
+    character_id INT UNSIGNED NOT NULL PRIMARY KEY,
+    guild_id INT UNSIGNED NOT NULL,
+    `rank` INT UNSIGNED NOT NULL,
+    joined_at BIGINT UNSIGNED NOT NULL,
+    INDEX idx_guild_id (guild_id),
+    FOREIGN KEY (guild_id) REFERENCES guilds(id) ON DELETE CASCADE,
+    FOREIGN KEY (character_id) REFERENCES characters(id) ON DELETE CASCADE
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
+
+CREATE TABLE IF NOT EXISTS guild_storage (
+    guild_id INT UNSIGNED NOT NULL,
+    slot_index INT UNSIGNED NOT NULL,
+    item_id INT UNSIGNED NOT NULL,
+    quantity INT UNSIGNED NOT NULL,
+    PRIMARY KEY (guild_id, slot_index),
+    FOREIGN KEY (guild_id) REFERENCES guilds(id) ON DELETE CASCADE
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
+
+CREATE TABLE IF NOT EXISTS guild_storage_log (
+    id BIGINT UNSIGNED AUTO_INCREMENT PRIMARY KEY,
+    guild_id INT UNSIGNED NOT NULL,
+    character_id INT UNSIGNED NOT NULL,
+    action VARCHAR(16) NOT NULL,
+    item_id INT UNSIGNED NULL,
+    amount BIGINT UNSIGNED NOT NULL,
+    at BIGINT UNSIGNED NOT NULL,
+    INDEX idx_member (guild_id, character_id, action, at),
+    FOREIGN KEY (guild_id) REFERENCES guilds(id) ON DELETE CASCADE
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
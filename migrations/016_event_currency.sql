@@ -0,0 +1,15 @@
+-- Currencies earned during time-limited events
+-- SQLite version
+
+-- One balance per character and currency; a balance past `expires_at` is
+-- worth nothing and is purged
+CREATE TABLE IF NOT EXISTS event_currency (
+    character_id INTEGER NOT NULL,
+    currency_id INTEGER NOT NULL,           -- From config/events.toml
+    amount BIGINT NOT NULL DEFAULT 0,
+    expires_at INTEGER NOT NULL,            -- Unix timestamp
+    PRIMARY KEY (character_id, currency_id),
+    FOREIGN KEY (character_id) REFERENCES characters(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_event_currency_expires ON event_currency(expires_at);
@@ -0,0 +1,12 @@
+-- Currencies earned during time-limited events
+-- MySQL version
+
+CREATE TABLE IF NOT EXISTS event_currency (
+    character_id INT UNSIGNED NOT NULL,
+    currency_id INT UNSIGNED NOT NULL,
+    amount BIGINT UNSIGNED NOT NULL DEFAULT 0,
+    expires_at BIGINT UNSIGNED NOT NULL,
+    PRIMARY KEY (character_id, currency_id),
+    INDEX idx_event_currency_expires (expires_at),
+    FOREIGN KEY (character_id) REFERENCES characters(id) ON DELETE CASCADE
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
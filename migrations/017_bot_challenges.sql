@@ -0,0 +1,16 @@
+-- Anti-bot challenges and how they went, for GMs to review
+-- SQLite version
+
+CREATE TABLE IF NOT EXISTS bot_challenges (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    account_id INTEGER,                     -- NULL if the session had none
+    character_id INTEGER NOT NULL,
+    reason TEXT NOT NULL,                   -- What set it off, e.g. 'cooldown' or 'gm:alice'
+    kind TEXT NOT NULL,                     -- Which challenge was asked
+    result TEXT NOT NULL,                   -- 'passed', 'wrong', 'timed_out' or 'left'
+    kicked INTEGER NOT NULL DEFAULT 0,      -- Boolean; the last failure allowed
+    issued_at INTEGER NOT NULL,             -- Unix timestamp
+    resolved_at INTEGER NOT NULL            -- Unix timestamp
+);
+
+CREATE INDEX IF NOT EXISTS idx_bot_challenges_account ON bot_challenges(account_id, issued_at);
@@ -0,0 +1,15 @@
+-- Anti-bot challenges and how they went, for GMs to review
+-- MySQL version
+
+CREATE TABLE IF NOT EXISTS bot_challenges (
+    id BIGINT UNSIGNED AUTO_INCREMENT PRIMARY KEY,
+    account_id INT UNSIGNED NULL,
+    character_id INT UNSIGNED NOT NULL,
+    reason VARCHAR(64) NOT NULL,
+    kind VARCHAR(32) NOT NULL,
+    result VARCHAR(16) NOT NULL,
+    kicked TINYINT(1) NOT NULL DEFAULT 0,
+    issued_at BIGINT UNSIGNED NOT NULL,
+    resolved_at BIGINT UNSIGNED NOT NULL,
+    INDEX idx_bot_challenges_account (account_id, issued_at)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
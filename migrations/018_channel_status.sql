@@ -0,0 +1,14 @@
+-- Each channel's latest population report
+-- SQLite version
+--
+-- Every world server (channel) writes its player count here on a timer;
+-- the lobby reads it to mark channels crowded or full and recommend one.
+-- A row whose report is old belongs to a channel that's down.
+
+CREATE TABLE IF NOT EXISTS channel_status (
+    id INTEGER PRIMARY KEY,                 -- Channel ID, from the world server's [channel] section
+    name TEXT NOT NULL,
+    address TEXT NOT NULL,                  -- Where players connect to it
+    players INTEGER NOT NULL DEFAULT 0,
+    reported_at INTEGER NOT NULL            -- Unix timestamp
+);
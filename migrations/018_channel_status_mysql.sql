@@ -0,0 +1,10 @@
+-- Each channel's latest population report
+-- MySQL version
+
+CREATE TABLE IF NOT EXISTS channel_status (
+    id INT UNSIGNED PRIMARY KEY,
+    name VARCHAR(64) NOT NULL,
+    address VARCHAR(255) NOT NULL,
+    players INT UNSIGNED NOT NULL DEFAULT 0,
+    reported_at BIGINT UNSIGNED NOT NULL
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
@@ -0,0 +1,32 @@
+-- Player vending stalls
+-- SQLite version
+--
+-- A stall is saved while its owner vends and deleted when they stop. Its
+-- items stay in the owner's inventory; `vending_items` says which slot
+-- each one is sold from and at what price, and a purchase takes from the
+-- listing in the same transaction as the items and zeny it moves.
+
+CREATE TABLE IF NOT EXISTS vending_stalls (
+    character_id INTEGER PRIMARY KEY,
+    channel_id INTEGER NOT NULL,            -- The world server it's open on
+    title TEXT NOT NULL,
+    map_id INTEGER NOT NULL,
+    position_x REAL NOT NULL,
+    position_y REAL NOT NULL,
+    position_z REAL NOT NULL,
+    opened_at INTEGER NOT NULL,             -- Unix timestamp
+    FOREIGN KEY (character_id) REFERENCES characters(id) ON DELETE CASCADE
+);
+
+CREATE TABLE IF NOT EXISTS vending_items (
+    character_id INTEGER NOT NULL,
+    stall_slot INTEGER NOT NULL,            -- Position in the stall, from 0
+    inventory_slot INTEGER NOT NULL,        -- Where the owner holds the items
+    item_id INTEGER NOT NULL,
+    quantity INTEGER NOT NULL,              -- Left for sale
+    price BIGINT NOT NULL,                  -- Zeny each
+    PRIMARY KEY (character_id, stall_slot),
+    FOREIGN KEY (character_id) REFERENCES vending_stalls(character_id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_vending_stalls_channel ON vending_stalls(channel_id);
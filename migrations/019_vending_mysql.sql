@@ -0,0 +1,26 @@
+-- Player vending stalls
+-- MySQL version
+
+CREATE TABLE IF NOT EXISTS vending_stalls (
+    character_id INT UNSIGNED PRIMARY KEY,
+    channel_id INT UNSIGNED NOT NULL,
+    title VARCHAR(128) NOT NULL,
+    map_id INT NOT NULL,
+    position_x FLOAT NOT NULL,
+    position_y FLOAT NOT NULL,
+    position_z FLOAT NOT NULL,
+    opened_at BIGINT UNSIGNED NOT NULL,
+    FOREIGN KEY (character_id) REFERENCES characters(id) ON DELETE CASCADE,
+    INDEX idx_vending_stalls_channel (channel_id)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
+
+CREATE TABLE IF NOT EXISTS vending_items (
+    character_id INT UNSIGNED NOT NULL,
+    stall_slot SMALLINT UNSIGNED NOT NULL,
+    inventory_slot SMALLINT UNSIGNED NOT NULL,
+    item_id INT NOT NULL,
+    quantity INT NOT NULL,
+    price BIGINT NOT NULL,
+    PRIMARY KEY (character_id, stall_slot),
+    FOREIGN KEY (character_id) REFERENCES vending_stalls(character_id) ON DELETE CASCADE
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
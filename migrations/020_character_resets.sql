@@ -0,0 +1,19 @@
+-- Stat and skill resets, and the skill points they give back
+-- SQLite version
+
+ALTER TABLE character_stats ADD COLUMN skill_points INTEGER NOT NULL DEFAULT 0;  -- Unspent skill points
+
+-- Every reset a character has had; how many of a kind sets the next one's
+-- cost. Each one is also in account_audit_log.
+CREATE TABLE IF NOT EXISTS character_resets (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    character_id INTEGER NOT NULL,
+    kind TEXT NOT NULL,                     -- 'stats' or 'skills'
+    cost INTEGER NOT NULL,                  -- Zeny paid; 0 with an item
+    item_id INTEGER,                        -- Reset item used up, NULL at an NPC
+    refunded INTEGER NOT NULL,              -- Points given back
+    reset_at INTEGER NOT NULL,              -- Unix timestamp
+    FOREIGN KEY (character_id) REFERENCES characters(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_character_resets_character ON character_resets(character_id, kind);
@@ -0,0 +1,16 @@
+-- Stat and skill resets, and the skill points they give back
+-- MySQL version
+
+ALTER TABLE character_stats ADD COLUMN skill_points INT NOT NULL DEFAULT 0;
+
+CREATE TABLE IF NOT EXISTS character_resets (
+    id BIGINT UNSIGNED AUTO_INCREMENT PRIMARY KEY,
+    character_id INT UNSIGNED NOT NULL,
+    kind VARCHAR(8) NOT NULL,
+    cost BIGINT NOT NULL,
+    item_id INT NULL,
+    refunded INT NOT NULL,
+    reset_at BIGINT UNSIGNED NOT NULL,
+    FOREIGN KEY (character_id) REFERENCES characters(id) ON DELETE CASCADE,
+    INDEX idx_character_resets_character (character_id, kind)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
@@ -0,0 +1,24 @@
+-- Namespaced key-value flags kept by scripts and game systems
+-- SQLite version
+
+-- Values are JSON text; a namespace is the system or script that owns them
+-- (e.g. 'quest', 'event.summer').
+CREATE TABLE IF NOT EXISTS character_profile_values (
+    character_id INTEGER NOT NULL,
+    namespace TEXT NOT NULL,
+    key TEXT NOT NULL,
+    value TEXT NOT NULL,                    -- JSON
+    updated_at INTEGER NOT NULL,            -- Unix timestamp
+    PRIMARY KEY (character_id, namespace, key),
+    FOREIGN KEY (character_id) REFERENCES characters(id) ON DELETE CASCADE
+);
+
+CREATE TABLE IF NOT EXISTS account_profile_values (
+    account_id INTEGER NOT NULL,
+    namespace TEXT NOT NULL,
+    key TEXT NOT NULL,
+    value TEXT NOT NULL,                    -- JSON
+    updated_at INTEGER NOT NULL,            -- Unix timestamp
+    PRIMARY KEY (account_id, namespace, key),
+    FOREIGN KEY (account_id) REFERENCES accounts(id) ON DELETE CASCADE
+);
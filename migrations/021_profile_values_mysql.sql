@@ -0,0 +1,24 @@
+-- Namespaced key-value flags kept by scripts and game systems
+-- MySQL version
+
+-- Values are JSON text; a namespace is the system or script that owns them
+-- (e.g. 'quest', 'event.summer').
+CREATE TABLE IF NOT EXISTS character_profile_values (
+    character_id INT UNSIGNED NOT NULL,
+    namespace VARCHAR(32) NOT NULL,
+    `key` VARCHAR(64) NOT NULL,
+    value TEXT NOT NULL,
+    updated_at BIGINT UNSIGNED NOT NULL,
+    PRIMARY KEY (character_id, namespace, `key`),
+    FOREIGN KEY (character_id) REFERENCES characters(id) ON DELETE CASCADE
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
+
+CREATE TABLE IF NOT EXISTS account_profile_values (
+    account_id INT UNSIGNED NOT NULL,
+    namespace VARCHAR(32) NOT NULL,
+    `key` VARCHAR(64) NOT NULL,
+    value TEXT NOT NULL,
+    updated_at BIGINT UNSIGNED NOT NULL,
+    PRIMARY KEY (account_id, namespace, `key`),
+    FOREIGN KEY (account_id) REFERENCES accounts(id) ON DELETE CASCADE
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
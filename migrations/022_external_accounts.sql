@@ -0,0 +1,11 @@
+-- Local accounts of players who log in through an external account API
+-- SQLite version
+
+-- The login server's HTTP auth backend creates the local account the first
+-- time an external account logs in, and finds it here after that.
+CREATE TABLE IF NOT EXISTS external_accounts (
+    external_id INTEGER PRIMARY KEY,        -- account_id from the account API
+    account_id INTEGER UNIQUE NOT NULL,
+    created_at INTEGER NOT NULL,            -- Unix timestamp
+    FOREIGN KEY (account_id) REFERENCES accounts(id) ON DELETE CASCADE
+);
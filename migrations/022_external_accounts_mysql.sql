@@ -0,0 +1,11 @@
+-- Local accounts of players who log in through an external account API
+-- MySQL version
+
+-- The login server's HTTP auth backend creates the local account the first
+-- time an external account logs in, and finds it here after that.
+CREATE TABLE IF NOT EXISTS external_accounts (
+    external_id BIGINT PRIMARY KEY,
+    account_id INT UNSIGNED UNIQUE NOT NULL,
+    created_at BIGINT UNSIGNED NOT NULL,
+    FOREIGN KEY (account_id) REFERENCES accounts(id) ON DELETE CASCADE
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;